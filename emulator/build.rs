@@ -1,9 +1,39 @@
+use std::env;
+
+/// Kernel sources compiled into the crate, from its own copy in `kernel/`
+/// so the crate builds outside this repository too. `make vendor` in
+/// `../kernel` refreshes the copy.
+const KERNEL_SOURCES: [&str; 4] = ["cpu.c", "bus.c", "opcodes.c", "state.c"];
+
 fn main() {
-    cc::Build::new()
-        .file("../kernel/cpu.c")
-        .file("../kernel/bus.c")
-        .file("../kernel/opcodes.c")
-        .compile("rvm8_kernel");
-
-    println!("cargo:rerun-if-changed=../kernel/cpu.h");
-}
\ No newline at end of file
+    println!("cargo:rerun-if-changed=kernel");
+    println!("cargo:rustc-check-cfg=cfg(c_kernel, rust_core)");
+
+    // `c_kernel` links the C kernel and `rust_core` compiles the Rust one
+    // alongside. With no C kernel the Rust core provides the whole kernel
+    // API, so there is nothing to compile or link.
+    let feature = |name: &str| env::var_os(format!("CARGO_FEATURE_{name}")).is_some();
+    let c_kernel = feature("C_KERNEL") && !feature("PURE_RUST");
+    if feature("RUST_CORE") || !c_kernel {
+        println!("cargo:rustc-cfg=rust_core");
+    }
+    if !c_kernel {
+        return;
+    }
+    println!("cargo:rustc-cfg=c_kernel");
+
+    // Emscripten ships a C compiler; bare wasm32 does not.
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap();
+    let os = env::var("CARGO_CFG_TARGET_OS").unwrap();
+    if arch == "wasm32" && os != "emscripten" {
+        panic!(
+            "the C kernel needs emscripten to target wasm32; enable the `pure-rust` or `wasm` feature instead"
+        );
+    }
+
+    let mut build = cc::Build::new();
+    for source in KERNEL_SOURCES {
+        build.file(format!("kernel/{source}"));
+    }
+    build.compile("rvm8_kernel");
+}
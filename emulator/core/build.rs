@@ -0,0 +1,79 @@
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    println!("cargo:rerun-if-changed=kernel/opcodes.def");
+    println!("cargo:rerun-if-changed=kernel/undocumented.def");
+    generate_opcode_table(
+        "opcodes",
+        "INSTRUCTION_TABLE",
+        "OPCODES",
+        "Every opcode byte's description, `None` where it is undefined.",
+    );
+    generate_opcode_table(
+        "undocumented",
+        "UNDOCUMENTED_TABLE",
+        "UNDOCUMENTED",
+        "The undocumented opcodes executed under `ILLEGAL_UNDOCUMENTED`, `None` where they trap.",
+    );
+}
+
+/// Translates `kernel/{name}.def`, the crate's copy of the C kernel's
+/// table, into an `opcode_table!` invocation in `$OUT_DIR/{name}.rs`.
+/// `cpu::opcodes` defines the macro to build both the dispatch table `table`
+/// and the public descriptions `descriptions`, with `doc` as their
+/// documentation, from it, so Rust never keeps its own copy of an opcode
+/// list.
+fn generate_opcode_table(name: &str, table: &str, descriptions: &str, doc: &str) {
+    let def = fs::read_to_string(format!("kernel/{name}.def"))
+        .unwrap_or_else(|err| panic!("read kernel/{name}.def: {err}"));
+    let mut out = format!(
+        "// @generated by build.rs from kernel/{name}.def\nopcode_table! {{\n    {table}, #[doc = \"{doc}\"] {descriptions};\n"
+    );
+
+    for (lineno, line) in def.lines().enumerate() {
+        let Some(args) = line.trim().strip_prefix("OPCODE(") else {
+            continue;
+        };
+        let fields: Vec<&str> = args
+            .trim_end_matches(')')
+            .split(',')
+            .map(str::trim)
+            .collect();
+        let [code, name, handler, mode, cycles] = fields[..] else {
+            panic!("{name}.def:{}: expected 5 fields", lineno + 1);
+        };
+        writeln!(
+            out,
+            "    ({code}, \"{name}\", {handler}, {}, {cycles}),",
+            rust_mode(mode)
+        )
+        .unwrap();
+    }
+
+    out.push_str("}\n");
+    let dest = Path::new(&env::var("OUT_DIR").unwrap()).join(format!("{name}.rs"));
+    fs::write(dest, out).unwrap_or_else(|err| panic!("write {name}.rs: {err}"));
+}
+
+/// Maps a C `AddressingMode` constant to its Rust `AddressingMode` variant.
+fn rust_mode(mode: &str) -> &'static str {
+    match mode {
+        "MODE_IMMEDIATE" => "Immediate",
+        "MODE_ZEROPAGE" => "ZeroPage",
+        "MODE_ABSOLUTE" => "Absolute",
+        "MODE_ZEROPAGE_X" => "ZeroPageX",
+        "MODE_ZEROPAGE_Y" => "ZeroPageY",
+        "MODE_ABSOLUTE_X" => "AbsoluteX",
+        "MODE_ABSOLUTE_Y" => "AbsoluteY",
+        "MODE_INDIRECT" => "Indirect",
+        "MODE_INDIRECT_X" => "IndirectX",
+        "MODE_INDIRECT_Y" => "IndirectY",
+        "MODE_IMPLIED" => "Implied",
+        "MODE_ACCUMULATOR" => "Accumulator",
+        "MODE_RELATIVE" => "Relative",
+        other => panic!("unknown addressing mode {other}"),
+    }
+}
@@ -0,0 +1,82 @@
+//! Memory bus (`kernel/bus.c`).
+
+use crate::{BusAccess, Cpu};
+
+/// Replaces the page of `addr` with its `page_map` entry.
+pub(super) fn decode(cpu: &Cpu, addr: u16) -> u16 {
+    (u16::from(cpu.page_map[addr as usize >> 8]) << 8) | (addr & 0xFF)
+}
+
+/// Decodes `addr` and charges the wait states of the page it lands on.
+fn access(cpu: &mut Cpu, addr: u16) -> u16 {
+    let addr = decode(cpu, addr);
+    let wait = cpu.wait_pages[addr as usize >> 8];
+    cpu.cycles = cpu.cycles.wrapping_add(u32::from(wait));
+    addr
+}
+
+/// Reads a byte from memory, letting the bus hook supply it if its page is
+/// selected.
+pub(super) fn read(cpu: &mut Cpu, addr: u16) -> u8 {
+    cpu.stats.reads += 1;
+    let addr = access(cpu, addr);
+    // SAFETY: `cpu_init` requires `memory` to span `RVM_MEM_SIZE` bytes, so
+    // every 16-bit address is in bounds.
+    let mut val = unsafe { *cpu.memory.add(addr as usize) };
+    hook(cpu, BusAccess::Read, addr, &mut val);
+    val
+}
+
+/// Writes a byte to memory unless the bus hook claims it or the page is ROM.
+pub(super) fn write(cpu: &mut Cpu, addr: u16, mut val: u8) {
+    cpu.stats.writes += 1;
+    let addr = access(cpu, addr);
+    if !hook(cpu, BusAccess::Write, addr, &mut val) && cpu.rom_pages[addr as usize >> 8] == 0 {
+        // SAFETY: see `read`.
+        unsafe { *cpu.memory.add(addr as usize) = val };
+        cpu.dirty_pages[addr as usize >> 8] = 1;
+    }
+}
+
+/// Passes an access to the bus hook if its page is selected, returning
+/// whether the hook claimed it and noting a claim in `bus_claimed`.
+fn hook(cpu: &mut Cpu, kind: BusAccess, addr: u16, val: &mut u8) -> bool {
+    let claimed = match cpu.bus_hook {
+        Some(hook) if cpu.hook_pages[addr as usize >> 8] != 0 => {
+            // SAFETY: whoever installed the hook vouches for `bus_ctx`.
+            unsafe { hook(cpu.bus_ctx, kind, addr, val) != 0 }
+        }
+        _ => false,
+    };
+    if claimed {
+        cpu.bus_claimed = 1;
+    }
+    claimed
+}
+
+/// Decodes an address through the page map.
+///
+/// # Safety
+///
+/// `cpu` must point to a CPU initialized with [`cpu_init`](super::cpu_init).
+pub unsafe fn mem_decode(cpu: *const Cpu, addr: u16) -> u16 {
+    decode(unsafe { &*cpu }, addr)
+}
+
+/// Reads a byte from memory.
+///
+/// # Safety
+///
+/// `cpu` must point to a CPU initialized with [`cpu_init`](super::cpu_init).
+pub unsafe fn mem_read(cpu: *mut Cpu, addr: u16) -> u8 {
+    read(unsafe { &mut *cpu }, addr)
+}
+
+/// Writes a byte to memory.
+///
+/// # Safety
+///
+/// `cpu` must point to a CPU initialized with [`cpu_init`](super::cpu_init).
+pub unsafe fn mem_write(cpu: *mut Cpu, addr: u16, val: u8) {
+    write(unsafe { &mut *cpu }, addr, val)
+}
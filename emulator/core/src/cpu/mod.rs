@@ -0,0 +1,214 @@
+//! Rust reimplementation of the kernel CPU (`kernel/cpu.c`).
+//!
+//! The entry points keep the raw-pointer signatures of their C counterparts
+//! so the emulator's `ffi` module can re-export them unchanged in place of
+//! the C kernel; everything below them is safe Rust operating on `&mut Cpu`.
+//! [`Machine`](crate::Machine) wraps them in a safe API.
+
+mod bus;
+pub(crate) mod opcodes;
+mod state;
+
+use core::ffi::c_int;
+
+pub use self::bus::{mem_decode, mem_read, mem_write};
+pub use self::state::{rvm8_export_state, rvm8_import_state};
+
+use self::bus::{decode, read, write};
+use crate::{
+    Cpu, FLAG_B, FLAG_I, ILLEGAL_NOP, ILLEGAL_UNDOCUMENTED, INT_IRQ, INT_IRQ_MASKED, INT_NMI,
+    INT_NMI_LEVEL, IRQ_CYCLES, IRQ_VECTOR, NMI_VECTOR, RVM_ILLEGAL_OPCODE, RVM_OK,
+};
+
+/// Loads the 16-bit reset vector stored at 0xFFFC-0xFFFD, decoded but
+/// bypassing the bus hook like the C kernel does.
+fn reset_vector(cpu: &Cpu) -> u16 {
+    let [lo, hi] = [0xFFFC, 0xFFFD].map(|addr| decode(cpu, addr) as usize);
+    // SAFETY: both addresses are inside the `RVM_MEM_SIZE` backing store.
+    unsafe { u16::from_le_bytes([*cpu.memory.add(lo), *cpu.memory.add(hi)]) }
+}
+
+/// Initializes the CPU state, attaching `memory` and loading the PC from the
+/// reset vector.
+///
+/// # Safety
+///
+/// `cpu` must be valid for writes and `memory` must point to at least
+/// `RVM_MEM_SIZE` bytes that outlive every later call using `cpu`.
+pub unsafe fn cpu_init(cpu: *mut Cpu, memory: *mut u8) {
+    let cpu = unsafe {
+        cpu.write(Cpu {
+            sp: 0xFD,
+            flags: FLAG_I,
+            memory,
+            page_map: core::array::from_fn(|page| page as u8),
+            ..Cpu::default()
+        });
+        &mut *cpu
+    };
+    cpu.pc = reset_vector(cpu);
+}
+
+/// Resets the CPU to its power-on state, reloading the PC from the reset
+/// vector.
+///
+/// # Safety
+///
+/// `cpu` must point to a CPU initialized with [`cpu_init`].
+pub unsafe fn cpu_reset(cpu: *mut Cpu) {
+    let cpu = unsafe { &mut *cpu };
+    cpu.pc = reset_vector(cpu);
+    cpu.flags = FLAG_I;
+    cpu.sp = 0xFD;
+    cpu.a = 0;
+    cpu.x = 0;
+    cpu.y = 0;
+    cpu.cycles = 0;
+    // Latched interrupts are lost, and an NMI line held through the reset
+    // is no edge.
+    cpu.int_state = if cpu.nmi != 0 { INT_NMI_LEVEL } else { 0 };
+}
+
+/// Pushes a byte onto the page-one stack.
+fn push(cpu: &mut Cpu, val: u8) {
+    write(cpu, 0x0100 | (cpu.sp & 0xFF), val);
+    cpu.sp = cpu.sp.wrapping_sub(1) & 0xFF;
+}
+
+/// Enters the interrupt handler at `vector`, saving the PC and flags.
+fn enter_interrupt(cpu: &mut Cpu, vector: u16) {
+    let [lo, hi] = cpu.pc.to_le_bytes();
+    push(cpu, hi);
+    push(cpu, lo);
+    push(cpu, cpu.flags & !FLAG_B);
+    cpu.flags |= FLAG_I;
+    cpu.pc = u16::from_le_bytes([read(cpu, vector), read(cpu, vector + 1)]);
+    cpu.cycles = cpu.cycles.wrapping_add(IRQ_CYCLES);
+}
+
+/// Samples the interrupt lines at the end of a step, with `flags` as the
+/// I flag is seen by the poll. A latched NMI stays latched until it is
+/// taken; the IRQ line is level-sensitive and re-sampled every time.
+fn poll_interrupts(cpu: &mut Cpu, flags: u8) {
+    let mut state = cpu.int_state & INT_NMI;
+    if cpu.nmi != 0 {
+        if cpu.int_state & INT_NMI_LEVEL == 0 {
+            state |= INT_NMI;
+        }
+        state |= INT_NMI_LEVEL;
+    }
+    if flags & FLAG_I != 0 {
+        state |= INT_IRQ_MASKED;
+    } else if cpu.irq != 0 {
+        state |= INT_IRQ;
+    }
+    cpu.int_state = state;
+}
+
+/// Repeats the IRQ sample of the last interrupt poll with the `irq` line as
+/// it is now, for a host whose devices changed the line while the last
+/// instruction executed.
+///
+/// # Safety
+///
+/// `cpu` must point to a CPU initialized with [`cpu_init`].
+pub unsafe fn cpu_poll_irq(cpu: *mut Cpu) {
+    let cpu = unsafe { &mut *cpu };
+    cpu.int_state &= !INT_IRQ;
+    if cpu.irq != 0 && cpu.int_state & INT_IRQ_MASKED == 0 {
+        cpu.int_state |= INT_IRQ;
+    }
+}
+
+/// Executes a single instruction, returning `RVM_OK` or
+/// `RVM_ILLEGAL_OPCODE` for an opcode without a handler. Opcodes enabled in
+/// `ext_opcodes` go to `ext_handler` when it is set, and `illegal_mode` may
+/// execute the others anyway. An interrupt latched by the previous step's
+/// poll is entered in place of the instruction.
+///
+/// # Safety
+///
+/// `cpu` must point to a CPU initialized with [`cpu_init`].
+pub unsafe fn cpu_step(cpu: *mut Cpu) -> c_int {
+    step(unsafe { &mut *cpu })
+}
+
+/// Executes instructions until at least `budget` cycles have elapsed,
+/// returning early with the status of an illegal opcode or after an
+/// instruction in which the bus hook claimed an access.
+///
+/// # Safety
+///
+/// `cpu` must point to a CPU initialized with [`cpu_init`].
+pub unsafe fn cpu_run(cpu: *mut Cpu, budget: u32) -> c_int {
+    let cpu = unsafe { &mut *cpu };
+    let start = cpu.cycles;
+    cpu.bus_claimed = 0;
+    while cpu.cycles.wrapping_sub(start) < budget {
+        let status = step(cpu);
+        if status != RVM_OK {
+            return status;
+        }
+        if cpu.bus_claimed != 0 {
+            cpu.bus_claimed = 0;
+            break;
+        }
+    }
+    RVM_OK
+}
+
+pub(crate) fn step(cpu: &mut Cpu) -> c_int {
+    let start = cpu.cycles;
+    let mut flags = cpu.flags;
+    let status = if cpu.int_state & (INT_NMI | INT_IRQ) != 0 {
+        let vector = if cpu.int_state & INT_NMI != 0 {
+            NMI_VECTOR
+        } else {
+            IRQ_VECTOR
+        };
+        cpu.int_state &= INT_NMI_LEVEL;
+        enter_interrupt(cpu, vector);
+        cpu.stats.irqs += 1;
+        // The poll at the end of the entry sees the I flag it just set.
+        flags = cpu.flags;
+        RVM_OK
+    } else {
+        execute(cpu)
+    };
+    poll_interrupts(cpu, flags);
+    cpu.stats.cycles += u64::from(cpu.cycles.wrapping_sub(start));
+    status
+}
+
+/// What an opcode without a handler executes under `illegal_mode`; the
+/// handler is `None` where it traps.
+fn illegal_instruction(cpu: &Cpu, opcode: u8) -> opcodes::Instruction {
+    match cpu.illegal_mode {
+        ILLEGAL_NOP => opcodes::ILLEGAL_NOP,
+        ILLEGAL_UNDOCUMENTED => opcodes::UNDOCUMENTED_TABLE[opcode as usize],
+        _ => opcodes::INSTRUCTION_TABLE[opcode as usize],
+    }
+}
+
+fn execute(cpu: &mut Cpu) -> c_int {
+    let opcode = read(cpu, cpu.pc);
+    cpu.pc = cpu.pc.wrapping_add(1);
+    let mut instr = opcodes::INSTRUCTION_TABLE[opcode as usize];
+    let ext = cpu
+        .ext_handler
+        .filter(|_| cpu.ext_opcodes[opcode as usize] != 0);
+    if instr.handler.is_none() && ext.is_none() {
+        instr = illegal_instruction(cpu, opcode);
+    }
+
+    let cycles = match (instr.handler, ext) {
+        (Some(handler), _) => handler(cpu, instr.mode),
+        // SAFETY: the host installing `ext_handler` vouches for it and its
+        // context; the handler gets the CPU it was installed on.
+        (None, Some(ext)) => unsafe { ext(cpu.ext_ctx, cpu, opcode) },
+        (None, None) => return RVM_ILLEGAL_OPCODE,
+    };
+    cpu.cycles = cpu.cycles.wrapping_add(cycles as u32);
+    cpu.stats.opcodes[opcode as usize] += 1;
+    RVM_OK
+}
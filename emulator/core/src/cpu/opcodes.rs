@@ -0,0 +1,437 @@
+//! Instruction handlers and the opcode table (`kernel/opcodes.c`).
+//!
+//! Handlers mirror their C counterparts exactly, including cycle counts, so
+//! both cores stay interchangeable. The table itself is generated from
+//! `kernel/opcodes.def`, the same list the C kernel is built from, along
+//! with [`OPCODES`], which describes it for disassemblers and assemblers;
+//! the undocumented opcodes come from `kernel/undocumented.def` the same
+//! way, described by [`UNDOCUMENTED`].
+
+use super::bus::{read, write};
+use crate::{AddressingMode, Cpu, FLAG_C, FLAG_D, FLAG_I, FLAG_N, FLAG_V, FLAG_Z};
+
+/// Executes one instruction and returns the cycles it consumed.
+pub(super) type Handler = fn(&mut Cpu, AddressingMode) -> u8;
+
+/// One entry of the opcode table.
+#[derive(Clone, Copy)]
+pub(super) struct Instruction {
+    pub handler: Option<Handler>,
+    pub mode: AddressingMode,
+}
+
+const ILLEGAL: Instruction = Instruction {
+    handler: None,
+    mode: AddressingMode::Implied,
+};
+
+const fn op(handler: Handler, mode: AddressingMode) -> Instruction {
+    Instruction {
+        handler: Some(handler),
+        mode,
+    }
+}
+
+/// Static description of one opcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Opcode {
+    pub mnemonic: &'static str,
+    pub mode: AddressingMode,
+    /// Base cycle count from the opcode table.
+    pub cycles: u8,
+}
+
+macro_rules! opcode_table {
+    (
+        $table:ident, #[$doc:meta] $descriptions:ident;
+        $(($code:literal, $name:literal, $handler:ident, $mode:ident, $cycles:literal)),* $(,)?
+    ) => {
+        pub(super) static $table: [Instruction; 256] = {
+            let mut t = [ILLEGAL; 256];
+            $(t[$code] = op($handler, AddressingMode::$mode);)*
+            t
+        };
+
+        #[$doc]
+        pub static $descriptions: [Option<Opcode>; 256] = {
+            let mut t = [None; 256];
+            $(t[$code] = Some(Opcode {
+                mnemonic: $name,
+                mode: AddressingMode::$mode,
+                cycles: $cycles,
+            });)*
+            t
+        };
+    };
+}
+
+// Generated from `kernel/opcodes.def` and `kernel/undocumented.def` by
+// build.rs.
+include!(concat!(env!("OUT_DIR"), "/opcodes.rs"));
+include!(concat!(env!("OUT_DIR"), "/undocumented.rs"));
+
+/// What illegal opcodes execute under `ILLEGAL_NOP`.
+pub(super) const ILLEGAL_NOP: Instruction = op(handler_nop, AddressingMode::Implied);
+
+fn fetch(cpu: &mut Cpu) -> u8 {
+    let value = read(cpu, cpu.pc);
+    cpu.pc = cpu.pc.wrapping_add(1);
+    value
+}
+
+fn addr_immediate(cpu: &mut Cpu) -> u16 {
+    let addr = cpu.pc;
+    cpu.pc = cpu.pc.wrapping_add(1);
+    addr
+}
+
+fn addr_zeropage(cpu: &mut Cpu) -> u16 {
+    fetch(cpu) as u16
+}
+
+fn addr_absolute(cpu: &mut Cpu) -> u16 {
+    let lo = fetch(cpu);
+    let hi = fetch(cpu);
+    u16::from_le_bytes([lo, hi])
+}
+
+fn addr_zeropage_indexed(cpu: &mut Cpu, index: u8) -> u16 {
+    fetch(cpu).wrapping_add(index) as u16
+}
+
+/// Resolves `base + index`, returning the address and the one-cycle penalty
+/// for crossing a page boundary.
+fn addr_absolute_indexed(cpu: &mut Cpu, index: u8) -> (u16, u8) {
+    let base = addr_absolute(cpu);
+    let addr = base.wrapping_add(index as u16);
+    (addr, u8::from(base & 0xFF00 != addr & 0xFF00))
+}
+
+/// Reads a little-endian pointer from the zero page, wrapping within it.
+fn zeropage_pointer(cpu: &mut Cpu, ptr: u8) -> u16 {
+    u16::from_le_bytes([read(cpu, ptr as u16), read(cpu, ptr.wrapping_add(1) as u16)])
+}
+
+fn set_flag(cpu: &mut Cpu, flag: u8, on: bool) {
+    if on {
+        cpu.flags |= flag;
+    } else {
+        cpu.flags &= !flag;
+    }
+}
+
+fn lsr_op(cpu: &mut Cpu, val: u8) -> u8 {
+    set_flag(cpu, FLAG_C, val & 0x01 != 0);
+    let val = val >> 1;
+    set_flag(cpu, FLAG_Z, val == 0);
+    cpu.flags &= !FLAG_N;
+    val
+}
+
+fn handler_lda(cpu: &mut Cpu, mode: AddressingMode) -> u8 {
+    let (value, cycles_used) = match mode {
+        AddressingMode::Immediate => {
+            let addr = addr_immediate(cpu);
+            (read(cpu, addr), 2)
+        }
+        AddressingMode::ZeroPage => {
+            let addr = addr_zeropage(cpu);
+            (read(cpu, addr), 3)
+        }
+        AddressingMode::Absolute => {
+            let addr = addr_absolute(cpu);
+            (read(cpu, addr), 4)
+        }
+        AddressingMode::ZeroPageX => {
+            let addr = addr_zeropage_indexed(cpu, cpu.x);
+            (read(cpu, addr), 4)
+        }
+        AddressingMode::AbsoluteX => {
+            let (addr, penalty) = addr_absolute_indexed(cpu, cpu.x);
+            (read(cpu, addr), 4 + penalty)
+        }
+        AddressingMode::AbsoluteY => {
+            let (addr, penalty) = addr_absolute_indexed(cpu, cpu.y);
+            (read(cpu, addr), 4 + penalty)
+        }
+        AddressingMode::IndirectX => {
+            let ptr = fetch(cpu).wrapping_add(cpu.x);
+            let addr = zeropage_pointer(cpu, ptr);
+            (read(cpu, addr), 6)
+        }
+        AddressingMode::IndirectY => {
+            let ptr = fetch(cpu);
+            let base = zeropage_pointer(cpu, ptr);
+            let addr = base.wrapping_add(cpu.y as u16);
+            (
+                read(cpu, addr),
+                5 + u8::from(base & 0xFF00 != addr & 0xFF00),
+            )
+        }
+        _ => (0, 0),
+    };
+
+    cpu.a = value;
+    set_flag(cpu, FLAG_Z, cpu.a == 0);
+    set_flag(cpu, FLAG_N, cpu.a & 0x80 != 0);
+    cycles_used
+}
+
+fn handler_ldx(cpu: &mut Cpu, mode: AddressingMode) -> u8 {
+    let (value, cycles_used) = match mode {
+        AddressingMode::Immediate => {
+            let addr = addr_immediate(cpu);
+            (read(cpu, addr), 2)
+        }
+        AddressingMode::ZeroPage => {
+            let addr = addr_zeropage(cpu);
+            (read(cpu, addr), 3)
+        }
+        AddressingMode::Absolute => {
+            let addr = addr_absolute(cpu);
+            (read(cpu, addr), 3)
+        }
+        AddressingMode::ZeroPageY => {
+            let addr = addr_zeropage_indexed(cpu, cpu.y);
+            (read(cpu, addr), 4)
+        }
+        AddressingMode::AbsoluteY => {
+            let (addr, penalty) = addr_absolute_indexed(cpu, cpu.y);
+            (read(cpu, addr), 4 + penalty)
+        }
+        _ => {
+            // Unreachable from the opcode table; the C kernel logs it to stdout.
+            return 0;
+        }
+    };
+
+    cpu.x = value;
+    cycles_used
+}
+
+fn handler_ldy(cpu: &mut Cpu, mode: AddressingMode) -> u8 {
+    let (value, cycles_used) = match mode {
+        AddressingMode::Immediate => {
+            let addr = addr_immediate(cpu);
+            (read(cpu, addr), 2)
+        }
+        AddressingMode::ZeroPage => {
+            let addr = addr_zeropage(cpu);
+            (read(cpu, addr), 3)
+        }
+        AddressingMode::Absolute => {
+            let addr = addr_absolute(cpu);
+            (read(cpu, addr), 3)
+        }
+        AddressingMode::ZeroPageX => {
+            let addr = addr_zeropage_indexed(cpu, cpu.x);
+            (read(cpu, addr), 4)
+        }
+        AddressingMode::AbsoluteX => {
+            let (addr, penalty) = addr_absolute_indexed(cpu, cpu.x);
+            (read(cpu, addr), 4 + penalty)
+        }
+        _ => {
+            // Unreachable from the opcode table; the C kernel logs it to stdout.
+            return 0;
+        }
+    };
+
+    cpu.y = value;
+    cycles_used
+}
+
+/// Adds `value` and the carry to A, in packed BCD while `FLAG_D` is set.
+/// Decimal mode keeps the NMOS 6502's flags: Z from the binary sum, N and V
+/// from the sum once the low digit is adjusted.
+fn adc_op(cpu: &mut Cpu, value: u8) {
+    let carry_in = u16::from(cpu.flags & FLAG_C != 0);
+    let result_16 = cpu.a as u16 + value as u16 + carry_in;
+    let final_result = result_16 as u8;
+
+    set_flag(cpu, FLAG_Z, final_result == 0);
+    if cpu.flags & FLAG_D == 0 {
+        set_flag(cpu, FLAG_N, final_result & 0x80 != 0);
+        set_flag(
+            cpu,
+            FLAG_V,
+            !(cpu.a ^ value) & (cpu.a ^ final_result) & 0x80 != 0,
+        );
+        set_flag(cpu, FLAG_C, result_16 & 0x100 != 0);
+        cpu.a = final_result;
+        return;
+    }
+
+    let mut low = (cpu.a & 0x0F) as i32 + (value & 0x0F) as i32 + carry_in as i32;
+    if low >= 0x0A {
+        low = ((low + 0x06) & 0x0F) + 0x10;
+    }
+    let mut sum = (cpu.a & 0xF0) as i32 + (value & 0xF0) as i32 + low;
+    let signed_sum = (cpu.a & 0xF0) as i8 as i32 + (value & 0xF0) as i8 as i32 + low;
+
+    set_flag(cpu, FLAG_N, sum & 0x80 != 0);
+    set_flag(cpu, FLAG_V, !(-128..=127).contains(&signed_sum));
+    if sum >= 0xA0 {
+        sum += 0x60;
+    }
+    set_flag(cpu, FLAG_C, sum >= 0x100);
+    cpu.a = sum as u8;
+}
+
+/// Subtracts `value` and the borrow from A, in packed BCD while `FLAG_D` is
+/// set. The flags always describe the binary difference.
+fn sbc_op(cpu: &mut Cpu, value: u8) {
+    let carry_in = u16::from(cpu.flags & FLAG_C != 0);
+    let result_16 = cpu.a as u16 + (value ^ 0xFF) as u16 + carry_in;
+    let mut final_result = result_16 as u8;
+
+    set_flag(cpu, FLAG_Z, final_result == 0);
+    set_flag(cpu, FLAG_N, final_result & 0x80 != 0);
+    set_flag(
+        cpu,
+        FLAG_V,
+        (cpu.a ^ value) & (cpu.a ^ final_result) & 0x80 != 0,
+    );
+    set_flag(cpu, FLAG_C, result_16 & 0x100 != 0);
+
+    if cpu.flags & FLAG_D != 0 {
+        let mut low = (cpu.a & 0x0F) as i32 - (value & 0x0F) as i32 + carry_in as i32 - 1;
+        if low < 0 {
+            low = ((low - 0x06) & 0x0F) - 0x10;
+        }
+        let mut difference = (cpu.a & 0xF0) as i32 - (value & 0xF0) as i32 + low;
+        if difference < 0 {
+            difference -= 0x60;
+        }
+        final_result = difference as u8;
+    }
+    cpu.a = final_result;
+}
+
+fn handler_adc(cpu: &mut Cpu, mode: AddressingMode) -> u8 {
+    let addr = match mode {
+        AddressingMode::Immediate => addr_immediate(cpu),
+        AddressingMode::ZeroPage => addr_zeropage(cpu),
+        AddressingMode::Absolute => addr_absolute(cpu),
+        _ => {
+            // Unreachable from the opcode table; the C kernel logs it to stdout.
+            return 0;
+        }
+    };
+
+    let value = read(cpu, addr);
+    adc_op(cpu, value);
+    2
+}
+
+fn handler_sbc(cpu: &mut Cpu, mode: AddressingMode) -> u8 {
+    let (addr, cycles_used) = match mode {
+        AddressingMode::Immediate => (addr_immediate(cpu), 2),
+        AddressingMode::ZeroPage => (addr_zeropage(cpu), 3),
+        AddressingMode::Absolute => (addr_absolute(cpu), 4),
+        _ => {
+            // Unreachable from the opcode table; the C kernel logs it to stdout.
+            return 0;
+        }
+    };
+
+    let value = read(cpu, addr);
+    sbc_op(cpu, value);
+    cycles_used
+}
+
+fn handler_sed(cpu: &mut Cpu, _mode: AddressingMode) -> u8 {
+    cpu.flags |= FLAG_D;
+    2
+}
+
+fn handler_cld(cpu: &mut Cpu, _mode: AddressingMode) -> u8 {
+    cpu.flags &= !FLAG_D;
+    2
+}
+
+fn handler_cli(cpu: &mut Cpu, _mode: AddressingMode) -> u8 {
+    cpu.flags &= !FLAG_I;
+    2
+}
+
+fn handler_sei(cpu: &mut Cpu, _mode: AddressingMode) -> u8 {
+    cpu.flags |= FLAG_I;
+    2
+}
+
+fn handler_lsr(cpu: &mut Cpu, mode: AddressingMode) -> u8 {
+    let (addr, cycles_used) = match mode {
+        AddressingMode::Accumulator => {
+            cpu.a = lsr_op(cpu, cpu.a);
+            return 2;
+        }
+        AddressingMode::ZeroPage => (addr_zeropage(cpu), 5),
+        AddressingMode::ZeroPageX => (addr_zeropage_indexed(cpu, cpu.x), 6),
+        AddressingMode::Absolute => (addr_absolute(cpu), 6),
+        AddressingMode::AbsoluteX => {
+            let (addr, penalty) = addr_absolute_indexed(cpu, cpu.x);
+            (addr, 7 + penalty)
+        }
+        _ => {
+            // Unreachable from the opcode table; the C kernel logs it to stdout.
+            return 0;
+        }
+    };
+
+    let value = read(cpu, addr);
+    let value = lsr_op(cpu, value);
+    write(cpu, addr, value);
+    cycles_used
+}
+
+/// Reads and drops the operand, as the 6502's undocumented NOPs do.
+fn handler_nop(cpu: &mut Cpu, mode: AddressingMode) -> u8 {
+    match mode {
+        AddressingMode::Immediate => {
+            let addr = addr_immediate(cpu);
+            read(cpu, addr);
+            2
+        }
+        AddressingMode::ZeroPage => {
+            let addr = addr_zeropage(cpu);
+            read(cpu, addr);
+            3
+        }
+        AddressingMode::ZeroPageX => {
+            let addr = addr_zeropage_indexed(cpu, cpu.x);
+            read(cpu, addr);
+            4
+        }
+        AddressingMode::Absolute => {
+            let addr = addr_absolute(cpu);
+            read(cpu, addr);
+            4
+        }
+        AddressingMode::AbsoluteX => {
+            let (addr, penalty) = addr_absolute_indexed(cpu, cpu.x);
+            read(cpu, addr);
+            4 + penalty
+        }
+        _ => 2,
+    }
+}
+
+/// Loads A and X at once, timed like the load with the same mode.
+fn handler_lax(cpu: &mut Cpu, mode: AddressingMode) -> u8 {
+    let cycles_used = match mode {
+        AddressingMode::ZeroPageY | AddressingMode::AbsoluteY => {
+            let cycles_used = handler_ldx(cpu, mode);
+            cpu.a = cpu.x;
+            cycles_used
+        }
+        _ => {
+            let cycles_used = handler_lda(cpu, mode);
+            cpu.x = cpu.a;
+            cycles_used
+        }
+    };
+    set_flag(cpu, FLAG_Z, cpu.a == 0);
+    set_flag(cpu, FLAG_N, cpu.a & 0x80 != 0);
+    cycles_used
+}
@@ -0,0 +1,149 @@
+//! Portable CPU state (`kernel/state.c`).
+//!
+//! The bytes are the C kernel's, field for field, so a state exported by
+//! either core imports into the other.
+
+use core::ffi::c_int;
+
+use crate::{
+    Cpu, ILLEGAL_UNDOCUMENTED, RVM_BAD_STATE, RVM_MEM_SIZE, RVM_OK, RVM_STATE_SIZE,
+    RVM_STATE_VERSION,
+};
+
+const MAGIC: [u8; 4] = *b"R8KS";
+
+/// Appends fields to an export buffer.
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl Writer<'_> {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.buf[self.pos..self.pos + bytes.len()].copy_from_slice(bytes);
+        self.pos += bytes.len();
+    }
+}
+
+/// Takes fields off an imported state.
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&mut self) -> [u8; N] {
+        let (head, rest) = self.buf.split_at(N);
+        self.buf = rest;
+        head.try_into().unwrap()
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.bytes::<1>()[0]
+    }
+
+    fn u64(&mut self) -> u64 {
+        u64::from_le_bytes(self.bytes())
+    }
+}
+
+fn export(cpu: &Cpu, memory: &[u8], buf: &mut [u8]) {
+    let mut w = Writer { buf, pos: 0 };
+    w.bytes(&MAGIC);
+    w.bytes(&[RVM_STATE_VERSION, cpu.a, cpu.x, cpu.y]);
+    w.bytes(&cpu.pc.to_le_bytes());
+    w.bytes(&cpu.sp.to_le_bytes());
+    w.bytes(&[cpu.flags]);
+    w.bytes(&cpu.cycles.to_le_bytes());
+    w.bytes(&[cpu.irq, cpu.nmi, cpu.int_state, cpu.illegal_mode]);
+    for table in [
+        &cpu.rom_pages,
+        &cpu.page_map,
+        &cpu.wait_pages,
+        &cpu.dirty_pages,
+    ] {
+        w.bytes(table);
+    }
+    let stats = &cpu.stats;
+    let counters = [stats.cycles, stats.irqs, stats.reads, stats.writes];
+    for count in stats.opcodes.iter().chain(&counters) {
+        w.bytes(&count.to_le_bytes());
+    }
+    w.bytes(memory);
+}
+
+fn import(cpu: &mut Cpu, memory: &mut [u8], buf: &[u8]) {
+    let mut r = Reader { buf };
+    r.bytes::<5>();
+    cpu.a = r.u8();
+    cpu.x = r.u8();
+    cpu.y = r.u8();
+    cpu.pc = u16::from_le_bytes(r.bytes());
+    cpu.sp = u16::from_le_bytes(r.bytes());
+    cpu.flags = r.u8();
+    cpu.cycles = u32::from_le_bytes(r.bytes());
+    cpu.irq = r.u8();
+    cpu.nmi = r.u8();
+    cpu.int_state = r.u8();
+    cpu.illegal_mode = r.u8();
+    cpu.rom_pages = r.bytes();
+    cpu.page_map = r.bytes();
+    cpu.wait_pages = r.bytes();
+    cpu.dirty_pages = r.bytes();
+    for count in &mut cpu.stats.opcodes {
+        *count = r.u64();
+    }
+    cpu.stats.cycles = r.u64();
+    cpu.stats.irqs = r.u64();
+    cpu.stats.reads = r.u64();
+    cpu.stats.writes = r.u64();
+    memory.copy_from_slice(r.buf);
+    cpu.bus_claimed = 0;
+}
+
+/// Writes the CPU state, memory included, in the layout the C kernel uses
+/// into `buf` if it holds `RVM_STATE_SIZE` bytes, and returns
+/// `RVM_STATE_SIZE` either way. The callbacks, `hook_pages` and
+/// `ext_opcodes` belong to the host and are left out.
+///
+/// # Safety
+///
+/// `cpu` must point to a CPU initialized with
+/// [`cpu_init`](super::cpu_init), and `buf`, unless null, must be valid for
+/// `len` bytes of writes.
+pub unsafe fn rvm8_export_state(cpu: *const Cpu, buf: *mut u8, len: usize) -> usize {
+    if buf.is_null() || len < RVM_STATE_SIZE {
+        return RVM_STATE_SIZE;
+    }
+    let cpu = unsafe { &*cpu };
+    let (memory, buf) = unsafe {
+        (
+            core::slice::from_raw_parts(cpu.memory, RVM_MEM_SIZE),
+            core::slice::from_raw_parts_mut(buf, RVM_STATE_SIZE),
+        )
+    };
+    export(cpu, memory, buf);
+    RVM_STATE_SIZE
+}
+
+/// Replaces the CPU state and memory with a state either core exported,
+/// returning `RVM_BAD_STATE` and changing nothing if `buf` holds none of
+/// this version.
+///
+/// # Safety
+///
+/// `cpu` must point to a CPU initialized with
+/// [`cpu_init`](super::cpu_init), and `buf`, unless null, must be valid for
+/// `len` bytes of reads.
+pub unsafe fn rvm8_import_state(cpu: *mut Cpu, buf: *const u8, len: usize) -> c_int {
+    if buf.is_null() || len != RVM_STATE_SIZE {
+        return RVM_BAD_STATE;
+    }
+    let buf = unsafe { core::slice::from_raw_parts(buf, len) };
+    if buf[..4] != MAGIC || buf[4] != RVM_STATE_VERSION || buf[20] > ILLEGAL_UNDOCUMENTED {
+        return RVM_BAD_STATE;
+    }
+    let cpu = unsafe { &mut *cpu };
+    let memory = unsafe { core::slice::from_raw_parts_mut(cpu.memory, RVM_MEM_SIZE) };
+    import(cpu, memory, buf);
+    RVM_OK
+}
@@ -0,0 +1,245 @@
+//! The rvm-8 CPU and kernel bus in `no_std` Rust.
+//!
+//! This is the part of the emulator a microcontroller needs: the CPU state
+//! laid out like the C kernel's `CPU` struct, the Rust reimplementation of
+//! the kernel in [`cpu`], and [`Machine`], a safe wrapper that runs it over
+//! borrowed memory with host [`Peripherals`] behind selected pages. It uses
+//! neither `std` nor an allocator, so the 64 KiB of memory can be a
+//! `static` buffer.
+//!
+//! The `emulator` crate builds everything else on top: devices, the
+//! debugger, save states, file formats and frontends, all of which need
+//! `std`.
+
+#![no_std]
+
+pub mod cpu;
+mod machine;
+
+use core::ffi::{c_int, c_void};
+
+pub use cpu::opcodes::{OPCODES, Opcode, UNDOCUMENTED};
+pub use machine::{IllegalOpcode, Machine, Peripherals};
+
+/// Size of the CPU address space in bytes (`RVM_MEM_SIZE`).
+pub const RVM_MEM_SIZE: usize = 65536;
+
+/// `cpu_step` completed the instruction.
+pub const RVM_OK: c_int = 0;
+/// `cpu_step` fetched an opcode without a handler and skipped it.
+pub const RVM_ILLEGAL_OPCODE: c_int = 1;
+/// `rvm8_import_state` was given bytes no kernel exported.
+pub const RVM_BAD_STATE: c_int = 2;
+
+/// Version of the layout `rvm8_export_state` writes (`RVM_STATE_VERSION`).
+pub const RVM_STATE_VERSION: u8 = 1;
+/// Bytes in an exported state (`RVM_STATE_SIZE`): the header and
+/// registers, four page tables, the execution counters and the memory.
+pub const RVM_STATE_SIZE: usize = 21 + 4 * 256 + 260 * 8 + RVM_MEM_SIZE;
+
+/// [`Cpu::illegal_mode`]: an illegal opcode is skipped and reported as
+/// `RVM_ILLEGAL_OPCODE`.
+pub const ILLEGAL_TRAP: u8 = 0;
+/// [`Cpu::illegal_mode`]: an illegal opcode is a one-byte NOP taking 2
+/// cycles.
+pub const ILLEGAL_NOP: u8 = 1;
+/// [`Cpu::illegal_mode`]: an illegal opcode does what it does on the NMOS
+/// 6502 where [`UNDOCUMENTED`] lists it, and traps otherwise.
+pub const ILLEGAL_UNDOCUMENTED: u8 = 2;
+
+/// Address of the 16-bit NMI vector (`NMI_VECTOR`).
+pub const NMI_VECTOR: u16 = 0xFFFA;
+/// Address of the 16-bit IRQ vector (`IRQ_VECTOR`).
+pub const IRQ_VECTOR: u16 = 0xFFFE;
+/// Cycles taken to enter an interrupt handler, IRQ or NMI (`IRQ_CYCLES`).
+pub const IRQ_CYCLES: u32 = 7;
+
+/// [`Cpu::int_state`]: the last interrupt poll latched an IRQ.
+pub const INT_IRQ: u8 = 1 << 0;
+/// [`Cpu::int_state`]: the last interrupt poll latched an NMI, or one
+/// latched earlier has not been taken yet.
+pub const INT_NMI: u8 = 1 << 1;
+/// [`Cpu::int_state`]: the NMI line as the last poll found it, for edge
+/// detection.
+pub const INT_NMI_LEVEL: u8 = 1 << 2;
+/// [`Cpu::int_state`]: `FLAG_I` was set as the last poll saw it.
+pub const INT_IRQ_MASKED: u8 = 1 << 3;
+
+/// Carry flag.
+pub const FLAG_C: u8 = 1 << 0;
+/// Zero flag.
+pub const FLAG_Z: u8 = 1 << 1;
+/// Interrupt disable flag.
+pub const FLAG_I: u8 = 1 << 2;
+/// Decimal mode flag.
+pub const FLAG_D: u8 = 1 << 3;
+/// Break flag.
+pub const FLAG_B: u8 = 1 << 4;
+/// Overflow flag.
+pub const FLAG_V: u8 = 1 << 6;
+/// Negative flag.
+pub const FLAG_N: u8 = 1 << 7;
+
+/// Kind of memory access reported to a [`BusHook`] (`BusAccess`).
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusAccess {
+    Read,
+    Write,
+}
+
+/// Callback invoked by `mem_read`/`mem_write` for pages enabled in
+/// [`Cpu::hook_pages`]. Returns non-zero to claim the access: a claimed read
+/// yields the byte left in `*val`, a claimed write skips RAM.
+pub type BusHook =
+    unsafe extern "C" fn(ctx: *mut c_void, kind: BusAccess, addr: u16, val: *mut u8) -> c_int;
+
+/// Handler for opcodes the instruction table leaves undefined, invoked by
+/// `cpu_step` for those enabled in [`Cpu::ext_opcodes`] with the PC just
+/// past the opcode byte. Returns the cycles the instruction took.
+pub type ExtHandler = unsafe extern "C" fn(ctx: *mut c_void, cpu: *mut Cpu, opcode: u8) -> u8;
+
+/// Execution counters kept by the CPU (`CpuStats`). `cpu_init` zeroes them
+/// and `cpu_reset` leaves them alone.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuStats {
+    /// Executions of each opcode; illegal opcodes are not counted.
+    pub opcodes: [u64; 256],
+    /// Cycles spent in `cpu_step`, wait states and interrupt entry included.
+    pub cycles: u64,
+    /// Interrupt handlers entered.
+    pub irqs: u64,
+    /// Accesses made through `mem_read`.
+    pub reads: u64,
+    /// Accesses made through `mem_write`.
+    pub writes: u64,
+}
+
+impl Default for CpuStats {
+    fn default() -> Self {
+        Self {
+            opcodes: [0; 256],
+            cycles: 0,
+            irqs: 0,
+            reads: 0,
+            writes: 0,
+        }
+    }
+}
+
+/// Core CPU state, laid out exactly like the C `CPU` struct.
+#[repr(C)]
+#[derive(Debug)]
+pub struct Cpu {
+    /// Accumulator.
+    pub a: u8,
+    /// X index register.
+    pub x: u8,
+    /// Y index register.
+    pub y: u8,
+    /// Program counter.
+    pub pc: u16,
+    /// Stack pointer; the stack grows downward through page one.
+    pub sp: u16,
+    /// Processor status flags (`FLAG_*`).
+    pub flags: u8,
+    /// Backing store of `RVM_MEM_SIZE` bytes.
+    pub memory: *mut u8,
+    /// Total cycles executed since init/reset.
+    pub cycles: u32,
+    /// Optional bus callback.
+    pub bus_hook: Option<BusHook>,
+    /// Opaque pointer passed back to `bus_hook`.
+    pub bus_ctx: *mut c_void,
+    /// Non-zero entries select the 256-byte pages that invoke `bus_hook`.
+    pub hook_pages: [u8; 256],
+    /// Non-zero entries mark the 256-byte pages whose writes are dropped.
+    pub rom_pages: [u8; 256],
+    /// Address decoding: accesses to page `p` go to page `page_map[p]`,
+    /// which `cpu_init` sets to `p`.
+    pub page_map: [u8; 256],
+    /// Wait states: each access decoding to page `p` costs `wait_pages[p]`
+    /// extra cycles.
+    pub wait_pages: [u8; 256],
+    /// IRQ input line, level-sensitive: sampled by the interrupt poll at
+    /// the end of every instruction.
+    pub irq: u8,
+    /// NMI input line, edge-triggered: the poll latches an NMI when it
+    /// finds the line non-zero after having last found it zero.
+    pub nmi: u8,
+    /// Interrupt poll state: [`INT_IRQ`] and [`INT_NMI`] for the interrupts
+    /// taken before the next instruction, [`INT_NMI_LEVEL`] and
+    /// [`INT_IRQ_MASKED`].
+    pub int_state: u8,
+    /// Set when `bus_hook` claims an access; `cpu_run` clears it and stops
+    /// after the instruction that set it.
+    pub bus_claimed: u8,
+    /// Execution counters.
+    pub stats: CpuStats,
+    /// Optional handler for extension opcodes.
+    pub ext_handler: Option<ExtHandler>,
+    /// Opaque pointer passed back to `ext_handler`.
+    pub ext_ctx: *mut c_void,
+    /// Non-zero entries select the opcodes without a table handler that go
+    /// to `ext_handler` instead of being illegal.
+    pub ext_opcodes: [u8; 256],
+    /// What opcodes with no handler of either kind do (`ILLEGAL_*`);
+    /// `cpu_init` sets `ILLEGAL_TRAP`.
+    pub illegal_mode: u8,
+    /// Non-zero entries mark the pages stored to in RAM; the host clears
+    /// them.
+    pub dirty_pages: [u8; 256],
+}
+
+impl Default for Cpu {
+    /// Zeroed registers and no memory attached, like a freshly `memset` C
+    /// struct waiting for `cpu_init`.
+    fn default() -> Self {
+        Self {
+            a: 0,
+            x: 0,
+            y: 0,
+            pc: 0,
+            sp: 0,
+            flags: 0,
+            memory: core::ptr::null_mut(),
+            cycles: 0,
+            bus_hook: None,
+            bus_ctx: core::ptr::null_mut(),
+            hook_pages: [0; 256],
+            rom_pages: [0; 256],
+            page_map: [0; 256],
+            wait_pages: [0; 256],
+            irq: 0,
+            nmi: 0,
+            int_state: 0,
+            bus_claimed: 0,
+            stats: CpuStats::default(),
+            ext_handler: None,
+            ext_ctx: core::ptr::null_mut(),
+            ext_opcodes: [0; 256],
+            illegal_mode: ILLEGAL_TRAP,
+            dirty_pages: [0; 256],
+        }
+    }
+}
+
+/// Addressing modes used by the instruction table (`AddressingMode`).
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressingMode {
+    Immediate,
+    ZeroPage,
+    Absolute,
+    ZeroPageX,
+    ZeroPageY,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+    Implied,
+    Accumulator,
+    Relative,
+}
@@ -0,0 +1,250 @@
+//! A safe machine over borrowed memory.
+
+use core::ffi::{c_int, c_void};
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::RangeInclusive;
+
+use crate::cpu;
+use crate::{BusAccess, Cpu, CpuStats, RVM_ILLEGAL_OPCODE, RVM_MEM_SIZE};
+
+/// The host side of the bus: whatever sits behind the pages passed to
+/// [`Machine::map`]. Accesses to any other page go straight to RAM.
+pub trait Peripherals {
+    /// Supplies the byte read at `addr`, or `None` to read RAM instead.
+    fn read(&mut self, addr: u16) -> Option<u8> {
+        let _ = addr;
+        None
+    }
+
+    /// Takes a write of `val` to `addr`, returning `true` to keep it from
+    /// reaching RAM.
+    fn write(&mut self, addr: u16, val: u8) -> bool {
+        let _ = (addr, val);
+        false
+    }
+
+    /// Advances by the cycles the instruction just executed took.
+    fn tick(&mut self, cycles: u32) {
+        let _ = cycles;
+    }
+
+    /// Whether the IRQ line is held, checked before every instruction and
+    /// again after the tick, so the instruction's interrupt poll sees a
+    /// line raised while it executed.
+    fn irq(&self) -> bool {
+        false
+    }
+}
+
+/// No peripherals: every page is RAM.
+impl Peripherals for () {}
+
+/// The CPU fetched an opcode with no handler. `pc` is the address of the
+/// opcode byte; the CPU has already moved past it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IllegalOpcode {
+    pub pc: u16,
+    pub opcode: u8,
+}
+
+impl fmt::Display for IllegalOpcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "illegal opcode 0x{:02X} at PC 0x{:04X}",
+            self.opcode, self.pc
+        )
+    }
+}
+
+impl core::error::Error for IllegalOpcode {}
+
+/// The CPU running over `'m`-borrowed memory, with `P` serving the pages
+/// it maps.
+///
+/// ```
+/// use rvm8_core::{Machine, RVM_MEM_SIZE};
+///
+/// let mut memory = [0; RVM_MEM_SIZE];
+/// // LDA #$2A at 0x0000, where the zeroed reset vector points.
+/// memory[..2].copy_from_slice(&[0xA9, 0x2A]);
+/// let mut machine = Machine::new(&mut memory, ());
+/// assert_eq!(machine.step(), Ok(2));
+/// assert_eq!(machine.cpu().a, 0x2A);
+/// ```
+pub struct Machine<'m, P> {
+    cpu: Cpu,
+    peripherals: P,
+    memory: PhantomData<&'m mut [u8; RVM_MEM_SIZE]>,
+}
+
+impl<'m, P: Peripherals> Machine<'m, P> {
+    /// Attaches the CPU to `memory` and loads the PC from the reset vector
+    /// in it. No pages are mapped yet.
+    pub fn new(memory: &'m mut [u8; RVM_MEM_SIZE], peripherals: P) -> Self {
+        let mut cpu = Cpu::default();
+        // SAFETY: `memory` spans RVM_MEM_SIZE bytes and stays borrowed for
+        // `'m`, as long as the machine exists.
+        unsafe { cpu::cpu_init(&mut cpu, memory.as_mut_ptr()) };
+        cpu.bus_hook = Some(trampoline::<P>);
+        Self {
+            cpu,
+            peripherals,
+            memory: PhantomData,
+        }
+    }
+
+    /// Resets the registers and reloads the PC from the reset vector.
+    pub fn reset(&mut self) {
+        // SAFETY: `self.cpu` was initialized by `cpu_init` in `new`.
+        unsafe { cpu::cpu_reset(&mut self.cpu) };
+    }
+
+    /// Executes one instruction, or enters the interrupt handler if the
+    /// previous one's interrupt poll found the IRQ line held and unmasked,
+    /// then ticks the peripherals. Returns the cycles taken.
+    pub fn step(&mut self) -> Result<u32, IllegalOpcode> {
+        self.cpu.irq = u8::from(self.peripherals.irq());
+        // The hook only runs during `cpu::step`, while `self` is borrowed.
+        self.cpu.bus_ctx = (&raw mut self.peripherals).cast();
+        let start = self.cpu.cycles;
+        let status = cpu::step(&mut self.cpu);
+        let cycles = self.cpu.cycles.wrapping_sub(start);
+        self.peripherals.tick(cycles);
+        let irq = u8::from(self.peripherals.irq());
+        if irq != self.cpu.irq {
+            self.cpu.irq = irq;
+            // SAFETY: `self.cpu` was initialized by `cpu_init` in `new`.
+            unsafe { cpu::cpu_poll_irq(&mut self.cpu) };
+        }
+        if status == RVM_ILLEGAL_OPCODE {
+            let pc = self.cpu.pc.wrapping_sub(1);
+            return Err(IllegalOpcode {
+                pc,
+                opcode: self.memory()[pc as usize],
+            });
+        }
+        Ok(cycles)
+    }
+
+    /// Steps until at least `budget` cycles have elapsed, returning how
+    /// many did.
+    pub fn run(&mut self, budget: u32) -> Result<u32, IllegalOpcode> {
+        let mut elapsed = 0;
+        while elapsed < budget {
+            elapsed += self.step()?;
+        }
+        Ok(elapsed)
+    }
+
+    /// The registers and the rest of the CPU state.
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    /// Zeroes the [execution counters](CpuStats), which a reset keeps.
+    pub fn reset_stats(&mut self) {
+        self.cpu.stats = CpuStats::default();
+    }
+
+    /// Jumps to `pc` before the next instruction.
+    pub fn set_pc(&mut self, pc: u16) {
+        self.cpu.pc = pc;
+    }
+
+    /// Replaces the status flags, for example to clear `FLAG_I`, which
+    /// reset sets, and let IRQs in.
+    pub fn set_flags(&mut self, flags: u8) {
+        self.cpu.flags = flags;
+    }
+
+    /// The whole address space, as RAM holds it.
+    pub fn memory(&self) -> &[u8] {
+        // SAFETY: `cpu.memory` is the `'m` borrow taken in `new`.
+        unsafe { core::slice::from_raw_parts(self.cpu.memory, RVM_MEM_SIZE) }
+    }
+
+    /// The whole address space, writable.
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        // SAFETY: as in `memory`, and `&mut self` makes the borrow unique.
+        unsafe { core::slice::from_raw_parts_mut(self.cpu.memory, RVM_MEM_SIZE) }
+    }
+
+    pub fn peripherals(&self) -> &P {
+        &self.peripherals
+    }
+
+    pub fn peripherals_mut(&mut self) -> &mut P {
+        &mut self.peripherals
+    }
+
+    /// Routes every access to the 256-byte pages covering `range` through
+    /// the peripherals.
+    pub fn map(&mut self, range: RangeInclusive<u16>) {
+        self.cpu.hook_pages[pages(range)].fill(1);
+    }
+
+    /// Drops CPU writes to the 256-byte pages covering `range` that the
+    /// peripherals do not take.
+    pub fn protect(&mut self, range: RangeInclusive<u16>) {
+        self.cpu.rom_pages[pages(range)].fill(1);
+    }
+
+    /// Makes every access to the 256-byte pages covering `range` take
+    /// `cycles` extra cycles, as slow memory would.
+    pub fn set_wait_states(&mut self, range: RangeInclusive<u16>, cycles: u8) {
+        self.cpu.wait_pages[pages(range)].fill(cycles);
+    }
+
+    /// Makes the 256-byte pages covering `range` decode to the pages from
+    /// `target` on, so both read and write the same bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mirrored pages would run past the end of the address
+    /// space.
+    pub fn mirror(&mut self, range: RangeInclusive<u16>, target: u16) {
+        let pages = pages(range);
+        let first = target as usize >> 8;
+        assert!(
+            first + (pages.end() - pages.start()) < 256,
+            "mirror target runs past 0xFFFF"
+        );
+        for (offset, page) in pages.enumerate() {
+            self.cpu.page_map[page] = (first + offset) as u8;
+        }
+    }
+}
+
+impl<P: fmt::Debug> fmt::Debug for Machine<'_, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Machine")
+            .field("pc", &self.cpu.pc)
+            .field("cycles", &self.cpu.cycles)
+            .field("peripherals", &self.peripherals)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Indices of the pages covering `range`.
+fn pages(range: RangeInclusive<u16>) -> RangeInclusive<usize> {
+    (*range.start() as usize >> 8)..=(*range.end() as usize >> 8)
+}
+
+/// The [`BusHook`](crate::BusHook) installed by [`Machine::new`].
+unsafe extern "C" fn trampoline<P: Peripherals>(
+    ctx: *mut c_void,
+    kind: BusAccess,
+    addr: u16,
+    val: *mut u8,
+) -> c_int {
+    // SAFETY: `Machine::step` points `ctx` at its peripherals for the call,
+    // and the core passes a pointer to its own local byte.
+    let (peripherals, val) = unsafe { (&mut *ctx.cast::<P>(), &mut *val) };
+    let claimed = match kind {
+        BusAccess::Read => peripherals.read(addr).map(|byte| *val = byte).is_some(),
+        BusAccess::Write => peripherals.write(addr, *val),
+    };
+    c_int::from(claimed)
+}
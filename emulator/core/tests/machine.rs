@@ -0,0 +1,166 @@
+use rvm8_core::{FLAG_I, IRQ_VECTOR, IllegalOpcode, Machine, Peripherals, RVM_MEM_SIZE};
+
+/// A latch at 0xD000 that reads back the last byte written, and an IRQ
+/// line raised once `fire_at` cycles have passed.
+#[derive(Debug, Default)]
+struct Latch {
+    value: u8,
+    cycles: u32,
+    fire_at: Option<u32>,
+}
+
+impl Peripherals for Latch {
+    fn read(&mut self, addr: u16) -> Option<u8> {
+        (addr == 0xD000).then_some(self.value)
+    }
+
+    fn write(&mut self, addr: u16, val: u8) -> bool {
+        if addr == 0xD000 {
+            self.value = val;
+        }
+        addr == 0xD000
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.cycles += cycles;
+    }
+
+    fn irq(&self) -> bool {
+        self.fire_at.is_some_and(|at| self.cycles >= at)
+    }
+}
+
+fn memory_with(program: &[u8]) -> [u8; RVM_MEM_SIZE] {
+    let mut memory = [0; RVM_MEM_SIZE];
+    memory[0x8000..0x8000 + program.len()].copy_from_slice(program);
+    memory[0xFFFC..0xFFFE].copy_from_slice(&[0x00, 0x80]);
+    memory
+}
+
+#[test]
+fn runs_over_a_static_buffer() {
+    static mut MEMORY: [u8; RVM_MEM_SIZE] = [0; RVM_MEM_SIZE];
+    // SAFETY: no other test touches `MEMORY`.
+    let memory = unsafe { &mut *core::ptr::addr_of_mut!(MEMORY) };
+    memory[..4].copy_from_slice(&[0xA9, 0x07, 0x46, 0x10]); // LDA #$07; LSR $10
+    memory[0x10] = 0x0E;
+    let mut machine = Machine::new(memory, ());
+    assert_eq!(machine.cpu().pc, 0x0000);
+    assert_eq!(machine.run(7), Ok(7));
+    assert_eq!(machine.cpu().a, 0x07);
+    assert_eq!(machine.memory()[0x10], 0x07);
+}
+
+#[test]
+fn mapped_pages_go_through_the_peripherals() {
+    // LDA $D000; LSR $D000; LSR $D001
+    let mut memory = memory_with(&[0xAD, 0x00, 0xD0, 0x4E, 0x00, 0xD0, 0x4E, 0x01, 0xD0]);
+    memory[0xD001] = 0x08;
+    let latch = Latch {
+        value: 0x42,
+        ..Latch::default()
+    };
+    let mut machine = Machine::new(&mut memory, latch);
+    machine.map(0xD000..=0xD0FF);
+    assert_eq!(machine.run(16), Ok(16));
+    assert_eq!(machine.cpu().a, 0x42);
+    assert_eq!(machine.peripherals().value, 0x21);
+    assert_eq!(machine.peripherals().cycles, 16);
+
+    let memory = machine.memory();
+    assert_eq!(memory[0xD000], 0x00, "a claimed write skips RAM");
+    assert_eq!(memory[0xD001], 0x04, "an unclaimed write reaches RAM");
+}
+
+#[test]
+fn protected_pages_drop_writes() {
+    // LSR $9000
+    let mut memory = memory_with(&[0x4E, 0x00, 0x90]);
+    memory[0x9000] = 0x22;
+    let mut machine = Machine::new(&mut memory, ());
+    machine.protect(0x8000..=0xFFFF);
+    machine.step().unwrap();
+    assert_eq!(machine.memory()[0x9000], 0x22);
+
+    machine.memory_mut()[0x9000] = 0x44;
+    assert_eq!(machine.memory()[0x9000], 0x44);
+}
+
+#[test]
+fn peripherals_raise_irqs() {
+    // LDA #$A9 forever, with the handler at 0x9000.
+    let mut memory = memory_with(&[0xA9; 16]);
+    memory[IRQ_VECTOR as usize..][..2].copy_from_slice(&[0x00, 0x90]);
+    let latch = Latch {
+        fire_at: Some(4),
+        ..Latch::default()
+    };
+    let mut machine = Machine::new(&mut memory, latch);
+    assert_ne!(machine.cpu().flags & FLAG_I, 0);
+    machine.set_flags(0);
+    assert_eq!(machine.step(), Ok(2));
+    assert_eq!(machine.step(), Ok(2));
+    assert_eq!(machine.step(), Ok(7));
+    assert_eq!(machine.cpu().pc, 0x9000);
+    assert_ne!(machine.cpu().flags & FLAG_I, 0);
+}
+
+#[test]
+fn illegal_opcodes_are_errors() {
+    // LDA #$01; .byte $02
+    let mut memory = memory_with(&[0xA9, 0x01, 0x02]);
+    let mut machine = Machine::new(&mut memory, ());
+    let err = IllegalOpcode {
+        pc: 0x8002,
+        opcode: 0x02,
+    };
+    assert_eq!(machine.run(100), Err(err));
+    assert_eq!(err.to_string(), "illegal opcode 0x02 at PC 0x8002");
+
+    machine.reset();
+    machine.set_pc(0x8000);
+    assert_eq!(machine.step(), Ok(2));
+    assert_eq!(machine.cpu().pc, 0x8002);
+}
+
+#[test]
+fn mirrors_decode_to_their_target() {
+    // LDA $0810; LSR $0811
+    let mut memory = memory_with(&[0xAD, 0x10, 0x08, 0x4E, 0x11, 0x08]);
+    memory[0x10..0x12].copy_from_slice(&[0x42, 0x80]);
+    let mut machine = Machine::new(&mut memory, ());
+    machine.mirror(0x0800..=0x0FFF, 0x0000);
+    machine.run(10).unwrap();
+    assert_eq!(machine.cpu().a, 0x42);
+    assert_eq!(machine.memory()[0x11], 0x40);
+    assert_eq!(machine.memory()[0x0811], 0x00);
+}
+
+#[test]
+fn wait_states_slow_accesses() {
+    // LDA $9000, fetched from page $80 and reading page $90.
+    let mut memory = memory_with(&[0xAD, 0x00, 0x90]);
+    let mut machine = Machine::new(&mut memory, ());
+    machine.set_wait_states(0x8000..=0x80FF, 1);
+    machine.set_wait_states(0x9000..=0x9000, 2);
+    assert_eq!(machine.step(), Ok(4 + 3 + 2));
+}
+
+#[test]
+fn the_cpu_counts_what_it_executes() {
+    // LDA #$01; LSR $D000; LDA #$01
+    let mut memory = memory_with(&[0xA9, 0x01, 0x4E, 0x00, 0xD0, 0xA9, 0x01]);
+    let mut machine = Machine::new(&mut memory, Latch::default());
+    machine.map(0xD000..=0xD000);
+    machine.run(10).unwrap();
+    let stats = machine.cpu().stats;
+    assert_eq!(stats.opcodes[0xA9], 2);
+    assert_eq!(stats.opcodes[0x4E], 1);
+    assert_eq!(stats.cycles, 10);
+    assert_eq!((stats.reads, stats.writes), (2 + 4 + 2, 1));
+
+    machine.reset();
+    assert_eq!(machine.cpu().stats, stats);
+    machine.reset_stats();
+    assert_eq!(machine.cpu().stats.opcodes[0xA9], 0);
+}
@@ -0,0 +1,33 @@
+//! Runs arbitrary bytes on the C kernel and the Rust core in lockstep;
+//! `DiffTest::run` panics, and so fails the input, where they disagree.
+
+#![no_main]
+
+use emulator::difftest::DiffTest;
+use emulator::ffi::RVM_MEM_SIZE;
+use emulator::fuzz::ORIGIN;
+use emulator::{CpuConfig, IllegalOpcodes, Registers, Vm};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&mode, program)) = data.split_first() else {
+        return;
+    };
+    let illegal_opcodes = match mode % 3 {
+        0 => IllegalOpcodes::Trap,
+        1 => IllegalOpcodes::Nop,
+        _ => IllegalOpcodes::Undocumented,
+    };
+    let mut vm = Vm::new();
+    vm.set_cpu_config(CpuConfig {
+        illegal_opcodes,
+        ..CpuConfig::default()
+    });
+    let fits = program.len().min(RVM_MEM_SIZE - ORIGIN as usize);
+    vm.load(ORIGIN, &program[..fits]).unwrap();
+    vm.set_registers(Registers {
+        pc: ORIGIN,
+        ..vm.registers()
+    });
+    DiffTest::from_vm(&vm).run(10_000);
+});
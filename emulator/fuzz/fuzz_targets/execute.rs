@@ -0,0 +1,24 @@
+//! Runs arbitrary bytes as a program. The first byte picks what illegal
+//! opcodes do, so the undocumented table gets fuzzed too.
+
+#![no_main]
+
+use emulator::{CpuConfig, IllegalOpcodes, Vm};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&mode, program)) = data.split_first() else {
+        return;
+    };
+    let illegal_opcodes = match mode % 3 {
+        0 => IllegalOpcodes::Trap,
+        1 => IllegalOpcodes::Nop,
+        _ => IllegalOpcodes::Undocumented,
+    };
+    let mut vm = Vm::new();
+    vm.set_cpu_config(CpuConfig {
+        illegal_opcodes,
+        ..CpuConfig::default()
+    });
+    let _ = vm.execute_raw(program, 100_000);
+});
@@ -0,0 +1,22 @@
+//! Feeds arbitrary bytes to every file format the emulator reads, and
+//! loads whatever parses into a machine.
+
+#![no_main]
+
+use emulator::elf::Elf;
+use emulator::hexfile::Image;
+use emulator::{Rom, Snapshot, Vm};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(rom) = Rom::from_bytes(data) {
+        Vm::new().load_rom(&rom).unwrap();
+    }
+    if let Ok(elf) = Elf::parse(data) {
+        Vm::new().load_elf(&elf);
+    }
+    if let Ok(Ok(image)) = std::str::from_utf8(data).map(Image::parse) {
+        Vm::new().load_image(&image);
+    }
+    let _ = Snapshot::from_bytes(data);
+});
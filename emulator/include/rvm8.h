@@ -0,0 +1,418 @@
+/* rvm-8 C embedding API, exported with the emulator's `capi` feature. */
+
+#ifndef RVM8_H
+#define RVM8_H
+
+/* Generated by cbindgen from src/capi.rs; do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Framebuffer width in pixels.
+ */
+#define RVM8_WIDTH 160
+
+/**
+ * Framebuffer height in pixels.
+ */
+#define RVM8_HEIGHT 144
+
+/**
+ * Bytes of memory behind [`rvm8_memory`].
+ */
+#define RVM8_MEMORY_SIZE 65536
+
+/**
+ * Button bits for [`rvm8_set_buttons`], as [`Button::mask`](crate::Button::mask).
+ */
+#define RVM8_BUTTON_A (1 << 0)
+
+#define RVM8_BUTTON_B (1 << 1)
+
+#define RVM8_BUTTON_SELECT (1 << 2)
+
+#define RVM8_BUTTON_START (1 << 3)
+
+#define RVM8_BUTTON_UP (1 << 4)
+
+#define RVM8_BUTTON_DOWN (1 << 5)
+
+#define RVM8_BUTTON_LEFT (1 << 6)
+
+#define RVM8_BUTTON_RIGHT (1 << 7)
+
+/**
+ * Outcome of a call, mirroring [`VmError`].
+ */
+typedef enum Rvm8Status {
+  RVM8_STATUS_OK = 0,
+  /**
+   * The CPU fetched an opcode with no handler.
+   */
+  RVM8_STATUS_ILLEGAL_OPCODE = 1,
+  /**
+   * A block does not fit in the address space.
+   */
+  RVM8_STATUS_OUT_OF_BOUNDS = 2,
+  /**
+   * A save state is malformed or does not fit this machine.
+   */
+  RVM8_STATUS_INVALID_STATE = 3,
+  /**
+   * A device range is already taken.
+   */
+  RVM8_STATUS_MAP_CONFLICT = 4,
+  /**
+   * A ROM image failed to parse.
+   */
+  RVM8_STATUS_INVALID_ROM = 5,
+  /**
+   * The CPU trapped on an access to unmapped memory.
+   */
+  RVM8_STATUS_BUS_FAULT = 6,
+  /**
+   * A device panicked while serving a CPU access.
+   */
+  RVM8_STATUS_DEVICE_PANIC = 7,
+  /**
+   * An opcode the kernel implements cannot be an extension.
+   */
+  RVM8_STATUS_OPCODE_TAKEN = 8,
+  /**
+   * An extension opcode's handler failed.
+   */
+  RVM8_STATUS_OPCODE_FAILED = 9,
+  /**
+   * The memory protection unit trapped an access.
+   */
+  RVM8_STATUS_MPU_FAULT = 10,
+  /**
+   * A push or pop left the configured stack bounds.
+   */
+  RVM8_STATUS_STACK_FAULT = 11,
+  /**
+   * The CPU wrote to ROM and the bus traps such writes.
+   */
+  RVM8_STATUS_ROM_WRITE = 12,
+} Rvm8Status;
+
+/**
+ * What an [`Rvm8Event`] reports, as [`Event`].
+ */
+typedef enum Rvm8EventKind {
+  /**
+   * A frame ended; `frame` is its number.
+   */
+  RVM8_EVENT_KIND_VBLANK = 0,
+  /**
+   * A frame's audio: `len` mono samples at `data`.
+   */
+  RVM8_EVENT_KIND_AUDIO = 1,
+  /**
+   * A UART at `port` sent `len` bytes, at `data`.
+   */
+  RVM8_EVENT_KIND_SERIAL = 2,
+  /**
+   * Running a frame failed with `status`.
+   */
+  RVM8_EVENT_KIND_FAULT = 3,
+} Rvm8EventKind;
+
+/**
+ * A machine, opaque to C.
+ */
+typedef struct Rvm8 Rvm8;
+
+/**
+ * The CPU registers.
+ */
+typedef struct Rvm8Registers {
+  uint8_t a;
+  uint8_t x;
+  uint8_t y;
+  uint8_t flags;
+  uint16_t pc;
+  uint16_t sp;
+} Rvm8Registers;
+
+/**
+ * An event taken by [`rvm8_next_event`]. Fields its kind does not use
+ * are zero, and `data` stays valid until the next call on the handle.
+ */
+typedef struct Rvm8Event {
+  Rvm8EventKind kind;
+  Rvm8Status status;
+  uint16_t port;
+  uint64_t frame;
+  const void *data;
+  size_t len;
+} Rvm8Event;
+
+/**
+ * Receives each frame's audio: the context given to
+ * [`rvm8_set_audio_callback`], then `len` mono samples.
+ */
+typedef void (*Rvm8AudioCallback)(void *ctx, const int16_t *samples, size_t len);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * A powered-on machine with empty memory. Free it with [`rvm8_destroy`].
+ */
+Rvm8 *rvm8_create(void);
+
+/**
+ * Frees a machine. Null is ignored.
+ *
+ * # Safety
+ *
+ * `vm` must be null or a handle from [`rvm8_create`] not yet destroyed.
+ */
+void rvm8_destroy(Rvm8 *vm);
+
+/**
+ * Loads a ROM image in the `.rvm` format and resets the CPU to its entry
+ * point. A banked image fails with [`Rvm8Status::MapConflict`] if another
+ * device is mapped where its bank registers go.
+ *
+ * # Safety
+ *
+ * `vm` must be a live handle and `data` must point to `len` readable bytes.
+ */
+Rvm8Status rvm8_load_rom(Rvm8 *vm, const uint8_t *data, size_t len);
+
+/**
+ * Copies `len` bytes into memory at `addr`, as [`Vm::load`].
+ *
+ * # Safety
+ *
+ * `vm` must be a live handle and `data` must point to `len` readable bytes.
+ */
+Rvm8Status rvm8_load(Rvm8 *vm, uint16_t addr, const uint8_t *data, size_t len);
+
+/**
+ * Resets the CPU, keeping memory.
+ *
+ * # Safety
+ *
+ * `vm` must be a live handle.
+ */
+void rvm8_reset(Rvm8 *vm);
+
+/**
+ * Executes one instruction, as [`Vm::step`].
+ *
+ * # Safety
+ *
+ * `vm` must be a live handle.
+ */
+Rvm8Status rvm8_step(Rvm8 *vm);
+
+/**
+ * Runs for at least `cycles` cycles, as [`Vm::run_cycles`].
+ *
+ * # Safety
+ *
+ * `vm` must be a live handle.
+ */
+Rvm8Status rvm8_run_cycles(Rvm8 *vm, uint32_t cycles);
+
+/**
+ * Runs to the end of the frame and renders it, as [`Vm::run_frame`].
+ *
+ * # Safety
+ *
+ * `vm` must be a live handle.
+ */
+Rvm8Status rvm8_run_frame(Rvm8 *vm);
+
+/**
+ * Frames completed since construction or the last reset.
+ *
+ * # Safety
+ *
+ * `vm` must be a live handle.
+ */
+uint64_t rvm8_frame(const Rvm8 *vm);
+
+/**
+ * The cycle counter, wrapping at 2^32.
+ *
+ * # Safety
+ *
+ * `vm` must be a live handle.
+ */
+uint32_t rvm8_cycles(const Rvm8 *vm);
+
+/**
+ * Copies the registers into `*regs`.
+ *
+ * # Safety
+ *
+ * `vm` must be a live handle and `regs` must point to a writable
+ * `Rvm8Registers`.
+ */
+void rvm8_registers(const Rvm8 *vm, Rvm8Registers *regs);
+
+/**
+ * Overwrites the registers with `*regs`.
+ *
+ * # Safety
+ *
+ * `vm` must be a live handle and `regs` must point to a valid
+ * `Rvm8Registers`.
+ */
+void rvm8_set_registers(Rvm8 *vm, const Rvm8Registers *regs);
+
+/**
+ * Reads memory directly, bypassing devices.
+ *
+ * # Safety
+ *
+ * `vm` must be a live handle.
+ */
+uint8_t rvm8_read(const Rvm8 *vm, uint16_t addr);
+
+/**
+ * Writes memory directly, bypassing devices.
+ *
+ * # Safety
+ *
+ * `vm` must be a live handle.
+ */
+void rvm8_write(Rvm8 *vm, uint16_t addr, uint8_t val);
+
+/**
+ * The [`RVM8_MEMORY_SIZE`] bytes of memory, valid until the machine is
+ * destroyed.
+ *
+ * # Safety
+ *
+ * `vm` must be a live handle.
+ */
+uint8_t *rvm8_memory(Rvm8 *vm);
+
+/**
+ * The last rendered frame as [`RVM8_WIDTH`] × [`RVM8_HEIGHT`] RGBA
+ * pixels, row by row, valid until the machine is destroyed.
+ *
+ * # Safety
+ *
+ * `vm` must be a live handle.
+ */
+const uint8_t *rvm8_framebuffer(const Rvm8 *vm);
+
+/**
+ * Replaces every held button at once with a union of `RVM8_BUTTON_*`
+ * bits.
+ *
+ * # Safety
+ *
+ * `vm` must be a live handle.
+ */
+void rvm8_set_buttons(Rvm8 *vm, uint8_t mask);
+
+/**
+ * Raises interrupt line `line`, as [`Vm::raise_irq`].
+ *
+ * # Safety
+ *
+ * `vm` must be a live handle.
+ */
+void rvm8_raise_irq(Rvm8 *vm, uint8_t line);
+
+/**
+ * Lowers interrupt line `line`, as [`Vm::ack_irq`].
+ *
+ * # Safety
+ *
+ * `vm` must be a live handle.
+ */
+void rvm8_ack_irq(Rvm8 *vm, uint8_t line);
+
+/**
+ * Asserts or releases the NMI input, as [`Vm::set_nmi`].
+ *
+ * # Safety
+ *
+ * `vm` must be a live handle.
+ */
+void rvm8_set_nmi(Rvm8 *vm, bool asserted);
+
+/**
+ * Calls `callback` with `ctx` and each frame's samples at the end of
+ * every frame; null removes the callback.
+ *
+ * # Safety
+ *
+ * `vm` must be a live handle, and `callback` must be safe to call with
+ * `ctx` for as long as it stays set.
+ */
+void rvm8_set_audio_callback(Rvm8 *vm, Rvm8AudioCallback callback, void *ctx);
+
+/**
+ * Sets the audio output rate in Hz, as [`Vm::set_sample_rate`].
+ *
+ * # Safety
+ *
+ * `vm` must be a live handle.
+ */
+void rvm8_set_sample_rate(Rvm8 *vm, uint32_t rate);
+
+/**
+ * Starts or stops queueing [events](crate::events) for
+ * [`rvm8_next_event`], as [`Vm::enable_events`]; stopping drops those
+ * not taken yet. Polling the queue after each frame saves calling back
+ * into C in the middle of one.
+ *
+ * # Safety
+ *
+ * `vm` must be a live handle.
+ */
+void rvm8_enable_events(Rvm8 *vm, bool enabled);
+
+/**
+ * Takes the oldest queued event into `event` and returns true, or returns
+ * false if there is none.
+ *
+ * # Safety
+ *
+ * `vm` must be a live handle and `event` must point to a writable
+ * [`Rvm8Event`].
+ */
+bool rvm8_next_event(Rvm8 *vm, Rvm8Event *event);
+
+/**
+ * Writes a save state into `buf` if it holds `len` bytes or more, and
+ * returns the state's size either way, so a call with a null `buf` and
+ * `len` 0 asks for the size. The state is the start state of an
+ * [input recording](crate::replay), controller included.
+ *
+ * # Safety
+ *
+ * `vm` must be a live handle and `buf` must be null or point to `len`
+ * writable bytes.
+ */
+size_t rvm8_save_state(const Rvm8 *vm, uint8_t *buf, size_t len);
+
+/**
+ * Restores a state written by [`rvm8_save_state`].
+ *
+ * # Safety
+ *
+ * `vm` must be a live handle and `data` must point to `len` readable
+ * bytes.
+ */
+Rvm8Status rvm8_load_state(Rvm8 *vm, const uint8_t *data, size_t len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* RVM8_H */
@@ -0,0 +1,197 @@
+/*
+ * rvm-8/kernel/cpu.c
+ *
+ * Partial implementation of the rvm-8 emulator CPU.
+ *
+ * Copyright (c) 2025 foxomax
+ * SPDX-License-Identifier: MIT
+ *
+ * Notes:
+ * - This file contains flag definitions and CPU state-related
+ *   functionality. Implementation will be extended with instructions
+ *   and more logic over time.
+ */
+
+#include "cpu.h"
+#include <string.h>
+
+/**
+ * @brief Initializes the CPU state.
+ *
+ * Sets up the CPU registers and memory pointer.
+ *
+ * @param cpu Pointer to the CPU structure to initialize.
+ * @param memory Pointer to the memory buffer.
+ */
+void cpu_init(CPU *cpu, uint8_t *memory) {
+  uint8_t *mem = memory;
+
+  memset(cpu, 0, sizeof(CPU));
+  cpu->memory = mem;
+  for (int page = 0; page < 256; page++)
+    cpu->page_map[page] = (uint8_t)page;
+  cpu->a = 0;
+  cpu->x = 0;
+  cpu->y = 0;
+  cpu->sp = 0xFD;
+  cpu->flags = FLAG_I;
+
+  uint16_t lo = cpu->memory[mem_decode(cpu, 0xFFFC)];
+  uint16_t hi = cpu->memory[mem_decode(cpu, 0xFFFD)];
+
+  cpu->pc = (hi << 8) | lo;
+  cpu->cycles = 0;
+}
+
+/**
+ * @brief Resets the CPU to its initial state.
+ *
+ * Reloads the PC from the reset vector (0xFFFC-0xFFFD) and resets
+ * flags/registers.
+ *
+ * @param cpu Pointer to the CPU instance.
+ */
+void cpu_reset(CPU *cpu) {
+  uint16_t lo = cpu->memory[mem_decode(cpu, 0xFFFC)];
+  uint16_t hi = cpu->memory[mem_decode(cpu, 0xFFFD)];
+
+  cpu->pc = (hi << 8) | lo;
+  cpu->flags = FLAG_I;
+  cpu->sp = 0xFD;
+  cpu->a = 0;
+  cpu->x = 0;
+  cpu->y = 0;
+  cpu->cycles = 0;
+  // Latched interrupts are lost, and an NMI line held through the reset
+  // is no edge.
+  cpu->int_state = cpu->nmi ? INT_NMI_LEVEL : 0;
+}
+
+/**
+ * @brief Pushes a byte onto the page-one stack.
+ */
+static void push(CPU *cpu, uint8_t val) {
+  mem_write(cpu, 0x0100 | (cpu->sp & 0xFF), val);
+  cpu->sp = (cpu->sp - 1) & 0xFF;
+}
+
+/**
+ * @brief Enters the interrupt handler at `vector`, saving the PC and flags.
+ */
+static void enter_interrupt(CPU *cpu, uint16_t vector) {
+  push(cpu, cpu->pc >> 8);
+  push(cpu, lo8(cpu->pc));
+  push(cpu, cpu->flags & ~FLAG_B);
+  cpu->flags |= FLAG_I;
+
+  uint16_t lo = mem_read(cpu, vector);
+  uint16_t hi = mem_read(cpu, vector + 1);
+
+  cpu->pc = (hi << 8) | lo;
+  cpu->cycles += IRQ_CYCLES;
+}
+
+/**
+ * @brief Samples the interrupt lines at the end of a step, with `flags` as
+ * the I flag is seen by the poll.
+ *
+ * A latched NMI stays latched until it is taken; the IRQ is re-sampled
+ * every time because its line is level-sensitive.
+ */
+static void poll_interrupts(CPU *cpu, uint8_t flags) {
+  uint8_t state = cpu->int_state & INT_NMI;
+
+  if (cpu->nmi) {
+    if (!(cpu->int_state & INT_NMI_LEVEL))
+      state |= INT_NMI;
+    state |= INT_NMI_LEVEL;
+  }
+  if (flags & FLAG_I)
+    state |= INT_IRQ_MASKED;
+  else if (cpu->irq)
+    state |= INT_IRQ;
+  cpu->int_state = state;
+}
+
+void cpu_poll_irq(CPU *cpu) {
+  cpu->int_state &= ~INT_IRQ;
+  if (cpu->irq && !(cpu->int_state & INT_IRQ_MASKED))
+    cpu->int_state |= INT_IRQ;
+}
+
+/**
+ * @brief Picks what an opcode without a handler executes under the CPU's
+ * illegal_mode; the returned handler is NULL where it traps.
+ */
+static Instruction illegal_instruction(const CPU *cpu, uint8_t opcode) {
+  switch (cpu->illegal_mode) {
+  case ILLEGAL_NOP:
+    return illegal_nop;
+  case ILLEGAL_UNDOCUMENTED:
+    return undocumented_table[opcode];
+  default:
+    return instruction_table[opcode];
+  }
+}
+
+/**
+ * @brief Executes a single CPU instruction.
+ *
+ * This function fetches an opcode from memory, looks up the corresponding
+ * instruction, and executes its handler, or the host's ext_handler for an
+ * extension opcode. Illegal opcodes are reported to the caller instead of
+ * being executed, unless illegal_mode says otherwise. An interrupt latched
+ * by the previous step is entered in place of the instruction.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @return RVM_OK, or RVM_ILLEGAL_OPCODE if the opcode has no handler.
+ */
+int cpu_step(CPU *cpu) {
+  uint32_t start = cpu->cycles;
+  uint8_t flags = cpu->flags;
+  int status = RVM_OK;
+
+  if (cpu->int_state & (INT_NMI | INT_IRQ)) {
+    uint16_t vector = cpu->int_state & INT_NMI ? NMI_VECTOR : IRQ_VECTOR;
+    cpu->int_state &= INT_NMI_LEVEL;
+    enter_interrupt(cpu, vector);
+    cpu->stats.irqs++;
+    // The poll at the end of the entry sees the I flag it just set.
+    flags = cpu->flags;
+  } else {
+    uint8_t opcode = mem_read(cpu, cpu->pc++);
+    Instruction instr = instruction_table[opcode];
+    int extension = cpu->ext_handler != NULL && cpu->ext_opcodes[opcode];
+
+    if (instr.handler == NULL && !extension)
+      instr = illegal_instruction(cpu, opcode);
+    if (instr.handler != NULL) {
+      cpu->cycles += instr.handler(cpu, instr.mode);
+      cpu->stats.opcodes[opcode]++;
+    } else if (extension) {
+      cpu->cycles += cpu->ext_handler(cpu->ext_ctx, cpu, opcode);
+      cpu->stats.opcodes[opcode]++;
+    } else {
+      status = RVM_ILLEGAL_OPCODE;
+    }
+  }
+  poll_interrupts(cpu, flags);
+  cpu->stats.cycles += (uint32_t)(cpu->cycles - start);
+  return status;
+}
+
+int cpu_run(CPU *cpu, uint32_t budget) {
+  uint32_t start = cpu->cycles;
+
+  cpu->bus_claimed = 0;
+  while ((uint32_t)(cpu->cycles - start) < budget) {
+    int status = cpu_step(cpu);
+    if (status != RVM_OK)
+      return status;
+    if (cpu->bus_claimed) {
+      cpu->bus_claimed = 0;
+      break;
+    }
+  }
+  return RVM_OK;
+}
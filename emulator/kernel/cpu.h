@@ -0,0 +1,446 @@
+/**
+ * rvm-8/kernel/cpu.h
+ *
+ * CPU abstraction for the rvm-8 emulator.
+ *
+ * Copyright (c) 2025 foxomax
+ * SPDX-License-Identifier: MIT
+ *
+ * This header declares the main `CPU` structure and the public
+ * functions used to initialize, reset and step the CPU, as well
+ * as memory access helpers.
+ *
+ * The kernel keeps no mutable global state: a CPU's registers, memory
+ * pointer and hooks all live in its CPU structure, and the instruction
+ * tables are constant. CPUs on different threads therefore run
+ * independently, as long as each is used by one thread at a time.
+ */
+
+#ifndef RVM_CPU_H
+#define RVM_CPU_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define RVM_MEM_SIZE 65536
+
+/**
+ * CPU register conventions and notes (inspired by the MOS 6502):
+ *
+ * - PC (Program Counter): 16-bit. Addresses the 64 KiB memory map
+ *   (range 0x0000..0xFFFF). The PC must be wide enough to reference
+ *   any address in the 16-bit address space.
+ *
+ * - X, Y (Index registers): 8-bit. Used as loop counters and for
+ *   indexed addressing modes. X and Y behave similarly but are used
+ *   by different addressing modes.
+ *
+ * - A (Accumulator): 8-bit. The primary register for arithmetic and
+ *   logic; most operations read from or write to the accumulator.
+ *
+ * - SP (Stack Pointer): 16-bit. The stack grows downward: a push
+ *   typically decrements SP and a pop increments it. As on the 6502 the
+ *   stack lives in page one, at 0x0100 plus the low byte of SP.
+ *
+ * - Flags: 8-bit processor status containing condition flags that
+ *   reflect the result of the most recent operations.
+ *
+ * - memory: pointer to the CPU's RAM backing store (byte array of
+ *   size RVM_MEM_SIZE).
+ */
+/**
+ * @brief Kind of memory access reported to a bus hook.
+ */
+typedef enum { BUS_READ, BUS_WRITE } BusAccess;
+
+/**
+ * @brief Host callback for memory accesses made through mem_read/mem_write.
+ *
+ * Receives the opaque context registered alongside it, the access kind,
+ * the address and a pointer to the byte being read or written. Returns
+ * non-zero when the host claims the access for a device of its own: a
+ * claimed read returns whatever the hook left in *val, and a claimed
+ * write does not reach RAM. Returning 0 only observes the access.
+ */
+typedef int (*BusHook)(void *ctx, BusAccess kind, uint16_t addr,
+                       uint8_t *val);
+
+typedef struct CPU CPU;
+
+/**
+ * @brief Host handler for opcodes the instruction table leaves undefined.
+ *
+ * Called by cpu_step for an opcode enabled in ext_opcodes, with the opaque
+ * context registered alongside it, the CPU (PC just past the opcode byte)
+ * and the opcode. The handler fetches its own operands and updates the
+ * registers as it likes; it returns the cycles the instruction took.
+ */
+typedef uint8_t (*ExtHandler)(void *ctx, CPU *cpu, uint8_t opcode);
+
+/**
+ * @brief What cpu_step does with an opcode the instruction table and
+ * ext_handler leave undefined.
+ */
+typedef enum {
+  /** Skip the opcode byte and return RVM_ILLEGAL_OPCODE */
+  ILLEGAL_TRAP,
+  /** Execute it as a one-byte NOP taking 2 cycles */
+  ILLEGAL_NOP,
+  /** Execute what the NMOS 6502 does where undocumented_table has it, and
+   *  trap otherwise */
+  ILLEGAL_UNDOCUMENTED,
+} IllegalMode;
+
+/**
+ * @brief Execution counters kept by the CPU.
+ *
+ * cpu_init zeroes them and cpu_reset leaves them alone; the host clears
+ * them when it wants a fresh count.
+ */
+typedef struct {
+  /** Executions of each opcode; illegal opcodes are not counted */
+  uint64_t opcodes[256];
+  /** Cycles spent in cpu_step, wait states and interrupt entry included */
+  uint64_t cycles;
+  /** Interrupt handlers entered */
+  uint64_t irqs;
+  /** Accesses made through mem_read */
+  uint64_t reads;
+  /** Accesses made through mem_write */
+  uint64_t writes;
+} CpuStats;
+
+/**
+ * @brief Core CPU state for the rvm-8 emulator.
+ *
+ * This struct contains the minimal set of registers and a pointer to
+ * the backing memory required to emulate a simple 8-bit CPU with a
+ * 16-bit address space.
+ */
+struct CPU {
+  /** Accumulator (8-bit) */
+  uint8_t a;
+  /** X index register (8-bit) */
+  uint8_t x;
+  /** Y index register (8-bit) */
+  uint8_t y;
+  /** Program counter (16-bit) */
+  uint16_t pc;
+  /** Stack pointer (16-bit) — stack grows downward */
+  uint16_t sp;
+  /** Processor status / flags (8-bit) */
+  uint8_t flags;
+  /** Pointer to the RAM backing store (RVM_MEM_SIZE bytes) */
+  uint8_t *memory;
+  /** Total Cycles*/
+  uint32_t cycles;
+  /** Optional bus callback (NULL when unused) */
+  BusHook bus_hook;
+  /** Opaque pointer passed back to bus_hook */
+  void *bus_ctx;
+  /** Non-zero entries select the 256-byte pages that invoke bus_hook */
+  uint8_t hook_pages[256];
+  /** Non-zero entries mark 256-byte pages as ROM: writes to them are dropped */
+  uint8_t rom_pages[256];
+  /** Address decoding: accesses to page p go to page page_map[p], which
+   *  cpu_init sets to p. Mirrors point several pages at the same one */
+  uint8_t page_map[256];
+  /** Wait states: every access decoding to page p costs wait_pages[p]
+   *  extra cycles, on top of the instruction's own */
+  uint8_t wait_pages[256];
+  /** IRQ input line, level-sensitive: sampled by the interrupt poll at the
+   *  end of every instruction */
+  uint8_t irq;
+  /** NMI input line, edge-triggered: the poll latches an NMI when it finds
+   *  the line non-zero after having last found it zero */
+  uint8_t nmi;
+  /** Interrupt poll state: INT_IRQ and INT_NMI for the interrupts the last
+   *  poll latched, taken before the next instruction, INT_NMI_LEVEL for
+   *  the nmi line as it last polled it and INT_IRQ_MASKED for FLAG_I as
+   *  it saw it */
+  uint8_t int_state;
+  /** Set by mem_read/mem_write when bus_hook claims an access; cpu_run
+   *  clears it and stops after the instruction that set it */
+  uint8_t bus_claimed;
+  /** Execution counters */
+  CpuStats stats;
+  /** Optional handler for extension opcodes (NULL when unused) */
+  ExtHandler ext_handler;
+  /** Opaque pointer passed back to ext_handler */
+  void *ext_ctx;
+  /** Non-zero entries select the opcodes without an instruction table
+   *  handler that go to ext_handler instead of being illegal */
+  uint8_t ext_opcodes[256];
+  /** IllegalMode for the remaining opcodes; cpu_init sets ILLEGAL_TRAP */
+  uint8_t illegal_mode;
+  /** Non-zero entries mark the pages mem_write has stored to in RAM; the
+   *  host clears them */
+  uint8_t dirty_pages[256];
+};
+
+/**
+ * @brief Defines the addressing modes for CPU instructions.
+ *
+ * Addressing modes determine how the CPU fetches operands for instructions.
+ * Each mode has different performance characteristics and use cases.
+ */
+typedef enum {
+  MODE_IMMEDIATE,   // LDA #10
+  MODE_ZEROPAGE,    // LDA $00
+  MODE_ABSOLUTE,    // LDA $1234
+  MODE_ZEROPAGE_X,  // LDA $10,X
+  MODE_ZEROPAGE_Y,  // LDA $10,X
+  MODE_ABSOLUTE_X,  // LDA $2000,X
+  MODE_ABSOLUTE_Y,  // LDA $2000,Y
+  MODE_INDIRECT,    // JMP ($1234)
+  MODE_INDIRECT_X,  // LDA ($10,X)
+  MODE_INDIRECT_Y,  // LDA ($10),Y
+  MODE_IMPLIED,     // CLC, NOP, INX
+  MODE_ACCUMULATOR, // ASL A
+  MODE_RELATIVE     // BNE, BEQ
+} AddressingMode;
+
+/**
+ * @brief Function pointer for an instruction handler.
+ *
+ * Each instruction in the CPU is implemented by a handler function that
+ * takes a pointer to the CPU state and an addressing mode, and returns
+ * the number of cycles it consumed.
+ */
+typedef uint8_t (*InstructionHandler)(CPU *cpu, AddressingMode mode);
+
+/**
+ * @brief Represents a single CPU instruction.
+ *
+ * This struct defines an instruction's mnemonic name, its handler function,
+ * the addressing mode it uses, and its base cycle count.
+ */
+typedef struct {
+  const char *name;
+  InstructionHandler handler;
+  AddressingMode mode;
+  uint8_t cycles;
+} Instruction;
+
+extern const Instruction instruction_table[256];
+
+/** Undocumented opcodes, executed under ILLEGAL_UNDOCUMENTED */
+extern const Instruction undocumented_table[256];
+
+/** The instruction illegal opcodes execute under ILLEGAL_NOP */
+extern const Instruction illegal_nop;
+
+/*
+ *
+ *  Flags ------------
+ *  Carry        = 0000001
+ *  Zero         = 0000010
+ *  Interruption = 0000100
+ *  Decimal      = 0001000
+ *  Break        = 0010000
+ *  Overflow     = 0100000
+ *  Negative     = 1000000
+ *  ------------------
+ */
+enum FLAGS_BITS {
+  FLAG_C = 1 << 0,
+  FLAG_Z = 1 << 1,
+  FLAG_I = 1 << 2,
+  FLAG_D = 1 << 3,
+  FLAG_B = 1 << 4,
+  FLAG_V = 1 << 6,
+  FLAG_N = 1 << 7,
+};
+
+/**
+ * @brief Bits of CPU::int_state.
+ */
+enum INT_STATE_BITS {
+  INT_IRQ = 1 << 0,
+  INT_NMI = 1 << 1,
+  INT_NMI_LEVEL = 1 << 2,
+  INT_IRQ_MASKED = 1 << 3,
+};
+
+/**
+ * @brief Status codes returned by cpu_step.
+ */
+enum RVM_STATUS {
+  RVM_OK = 0,
+  RVM_ILLEGAL_OPCODE = 1,
+  /** rvm8_import_state was given bytes no kernel exported */
+  RVM_BAD_STATE = 2,
+};
+
+/** Version of the layout rvm8_export_state writes. */
+#define RVM_STATE_VERSION 1
+
+/**
+ * Bytes in an exported state: the header and registers, four page tables,
+ * the execution counters and the memory.
+ */
+#define RVM_STATE_SIZE (21 + 4 * 256 + 260 * 8 + RVM_MEM_SIZE)
+
+/**
+ * @brief Initialize a CPU instance.
+ *
+ * Sets up register defaults and attaches the provided memory buffer.
+ *
+ * @param cpu Pointer to an allocated CPU structure to initialize.
+ * @param memory Pointer to a byte buffer of at least RVM_MEM_SIZE bytes.
+ */
+void cpu_init(CPU *cpu, uint8_t *memory);
+
+/**
+ * @brief Reset CPU registers to their power-on defaults.
+ *
+ * This does not change the memory pointer; use cpu_init to attach
+ * memory if needed.
+ *
+ * @param cpu Pointer to the CPU instance to reset.
+ */
+void cpu_reset(CPU *cpu);
+
+/** Address of the 16-bit NMI vector. */
+#define NMI_VECTOR 0xFFFA
+
+/** Address of the 16-bit IRQ vector. */
+#define IRQ_VECTOR 0xFFFE
+
+/** Cycles taken to enter an interrupt handler, IRQ or NMI. */
+#define IRQ_CYCLES 7
+
+/**
+ * @brief Execute one CPU instruction (single step).
+ *
+ * Advances the PC and updates registers/flags according to the
+ * semantics of the executed opcode. An undefined opcode is skipped
+ * (PC moves past it) and reported through the return value, unless it is
+ * enabled in ext_opcodes and an ext_handler is set: the handler then
+ * executes it like any other instruction. Otherwise illegal_mode may have
+ * it executed as a NOP or an undocumented instruction instead.
+ *
+ * As on the 6502, interrupts are polled during the last cycle of each
+ * instruction and taken before the next one: the poll latches an IRQ if
+ * the irq line is asserted and FLAG_I was clear before the instruction
+ * (so CLI and SEI take effect one instruction late), and an NMI if the nmi
+ * line rose since the previous poll, whatever FLAG_I says. A line the host
+ * changes between two steps is therefore first seen one instruction later,
+ * and a latched IRQ is taken even if the line has dropped since. When the
+ * previous step latched an interrupt, this step enters its handler instead
+ * of executing an instruction, NMI first: the PC (high byte first) and the
+ * flags are pushed, FLAG_I is set and the PC is loaded from NMI_VECTOR or
+ * IRQ_VECTOR. The handler's first instruction always executes before
+ * another IRQ is taken.
+ *
+ * @param cpu Pointer to the CPU instance to step.
+ * @return RVM_OK, or RVM_ILLEGAL_OPCODE if the opcode has no handler.
+ */
+int cpu_step(CPU *cpu);
+
+/**
+ * @brief Execute instructions until a cycle budget is spent (batched step).
+ *
+ * Calls cpu_step repeatedly until at least `budget` cycles have elapsed,
+ * an opcode has no handler, or an instruction made an access that bus_hook
+ * claimed. The last case lets the host react to device accesses between
+ * instructions, as it would when stepping one at a time. The irq line is
+ * only sampled, never changed, so a host driving it from device state
+ * should bound the budget by the next device event.
+ *
+ * A budget of 0 executes nothing.
+ *
+ * @param cpu Pointer to the CPU instance to run.
+ * @param budget Minimum number of cycles to run for.
+ * @return RVM_OK, or the first status other than RVM_OK cpu_step returned.
+ */
+int cpu_run(CPU *cpu, uint32_t budget);
+
+/**
+ * @brief Repeat the IRQ sample of the last interrupt poll with the irq
+ * line as it is now.
+ *
+ * For a host whose devices drive the irq line from state that advances
+ * with the cycles an instruction took: updating the line after the step
+ * and calling this lets the poll of that instruction see an interrupt a
+ * device raised, or dropped, while the instruction executed. FLAG_I is
+ * taken as the poll saw it.
+ *
+ * @param cpu Pointer to the CPU instance.
+ */
+void cpu_poll_irq(CPU *cpu);
+
+/**
+ * @brief Decode an address through page_map.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @param addr 16-bit address as the CPU issues it.
+ * @return The address it selects in memory and for bus_hook.
+ */
+uint16_t mem_decode(const CPU *cpu, uint16_t addr);
+
+/**
+ * @brief Read a byte from the CPU memory.
+ *
+ * This helper centralizes memory reads. The address is decoded with
+ * mem_decode first; reads from pages enabled in hook_pages are then passed
+ * to bus_hook, which may supply the value.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @param addr 16-bit memory address to read from.
+ * @return The byte read from memory.
+ */
+uint8_t mem_read(CPU *cpu, uint16_t addr);
+
+/**
+ * @brief Write a byte to the CPU memory.
+ *
+ * This helper centralizes memory writes. After mem_decode, writes to pages
+ * enabled in hook_pages are passed to bus_hook first and skip RAM if it
+ * claims them.
+ * Writes that reach a page marked in rom_pages are discarded.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @param addr 16-bit memory address to write to.
+ * @param val Byte value to write.
+ */
+void mem_write(CPU *cpu, uint16_t addr, uint8_t val);
+
+/**
+ * @brief Export the CPU state in a portable layout.
+ *
+ * Writes the registers, cycle count, interrupt lines and poll state,
+ * illegal_mode, the rom_pages, page_map, wait_pages and dirty_pages tables,
+ * the execution counters and the whole memory, in a fixed little-endian
+ * layout that any implementation of this API reads back with
+ * rvm8_import_state. A host uses it to move a running machine from one
+ * kernel to another. The callbacks, hook_pages and ext_opcodes are the
+ * host's and are not exported.
+ *
+ * If buf is NULL or len is too small, nothing is written.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @param buf Buffer of at least RVM_STATE_SIZE bytes.
+ * @param len Size of buf.
+ * @return RVM_STATE_SIZE, the bytes the state takes.
+ */
+size_t rvm8_export_state(const CPU *cpu, uint8_t *buf, size_t len);
+
+/**
+ * @brief Replace the CPU state with one rvm8_export_state wrote.
+ *
+ * Everything rvm8_export_state writes is overwritten, memory included, and
+ * bus_claimed is cleared; the callbacks, hook_pages and ext_opcodes are left
+ * alone. A state of the wrong size or version is refused and the CPU is
+ * left untouched.
+ *
+ * @param cpu Pointer to a CPU initialized with cpu_init.
+ * @param buf The exported state.
+ * @param len Size of buf, which must be RVM_STATE_SIZE.
+ * @return RVM_OK, or RVM_BAD_STATE if buf holds no state of this version.
+ */
+int rvm8_import_state(CPU *cpu, const uint8_t *buf, size_t len);
+
+static inline uint8_t lo8(int value) { return value & 0xFF; }
+
+#endif
@@ -0,0 +1,593 @@
+/*
+ * rvm-8/kernel/opcodes.c
+ *
+ * Implementation of opcode tables/handlers for rvm-8 (currently a stub).
+ *
+ * Copyright (c) 2025 foxomax
+ * SPDX-License-Identifier: MIT
+ */
+#include "cpu.h"
+#include <stdio.h>
+#include <string.h>
+
+/**
+ * @brief Reads the address for the immediate addressing mode.
+ *
+ * In immediate addressing, the operand is the byte immediately following the
+ * opcode. This function returns the current program counter and increments it.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @return The address of the immediate value.
+ */
+uint16_t addr_immediate(CPU *cpu) { return cpu->pc++; }
+
+/**
+ * @brief Reads the address for the zero-page addressing mode.
+ *
+ * In zero-page addressing, the operand is an 8-bit address in the first
+ * 256 bytes of memory (0x0000-0x00FF). This function reads the address
+ * from the current program counter.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @return The 8-bit zero-page address.
+ */
+uint16_t addr_zeropage(CPU *cpu) { return mem_read(cpu, cpu->pc++); }
+
+/**
+ * @brief Reads the address for the absolute addressing mode.
+ *
+ * In absolute addressing, the operand is a full 16-bit address. This
+ * function reads the two bytes following the opcode to form the address.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @return The 16-bit absolute address.
+ */
+uint16_t addr_absolute(CPU *cpu) {
+  uint16_t lo = mem_read(cpu, cpu->pc++);
+  uint16_t hi = mem_read(cpu, cpu->pc++);
+  return (hi << 8) | lo;
+}
+
+uint8_t lsr_op(CPU *cpu, uint8_t val) {
+  cpu->flags = val & 0x01 ? (cpu->flags | FLAG_C) : (cpu->flags & ~FLAG_C);
+  val >>= 1;
+  cpu->flags = val == 0 ? (cpu->flags | FLAG_Z) : (cpu->flags & ~FLAG_Z);
+  cpu->flags &= ~(FLAG_N);
+  return val;
+}
+
+/**
+ * @brief Handles the LDA (Load Accumulator) instruction.
+ *
+ * This function loads a byte from memory into the accumulator, updating the
+ * Zero (Z) and Negative (N) flags based on the value loaded.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @param mode The addressing mode used by the instruction.
+ * @return The number of cycles consumed by the instruction.
+ */
+uint8_t handler_lda(CPU *cpu, AddressingMode mode) {
+  uint16_t addr;
+  uint8_t value = 0;
+  uint8_t cycles_used = 0;
+
+  switch (mode) {
+  case MODE_IMMEDIATE:
+    addr = addr_immediate(cpu);
+    value = mem_read(cpu, addr);
+    cycles_used += 2;
+    break;
+  case MODE_ZEROPAGE:
+    addr = addr_zeropage(cpu);
+    value = mem_read(cpu, addr);
+    cycles_used += 3;
+    break;
+  case MODE_ABSOLUTE:
+    addr = addr_absolute(cpu);
+    value = mem_read(cpu, addr);
+    cycles_used += 4;
+    break;
+  case MODE_ZEROPAGE_X:
+    addr = (addr_zeropage(cpu) + cpu->x) & 0xFF;
+    value = mem_read(cpu, addr);
+    cycles_used += 4;
+    break;
+  case MODE_ABSOLUTE_X: {
+    uint16_t base_addr = addr_absolute(cpu);
+    addr = base_addr + cpu->x;
+    if ((base_addr & 0xFF00) != (addr & 0xFF00)) {
+      cycles_used += 1;
+    }
+    value = mem_read(cpu, addr);
+    cycles_used += 4;
+    break;
+  }
+  case MODE_ABSOLUTE_Y: {
+    uint16_t base_addr = addr_absolute(cpu);
+    addr = base_addr + cpu->y;
+    if ((base_addr & 0xFF00) != (addr & 0xFF00)) {
+      cycles_used += 1;
+    }
+    value = mem_read(cpu, addr);
+    cycles_used += 4;
+    break;
+  }
+  case MODE_INDIRECT_X: {
+    uint8_t ptr_addr = (addr_zeropage(cpu) + cpu->x) & 0xFF;
+    uint8_t low_byte = mem_read(cpu, ptr_addr);
+    uint8_t high_byte = mem_read(cpu, (ptr_addr + 1) & 0xFF);
+    addr = (high_byte << 8) | low_byte;
+    value = mem_read(cpu, addr);
+    cycles_used = 6;
+    break;
+  }
+  case MODE_INDIRECT_Y: {
+    uint8_t zp_ptr_addr = addr_zeropage(cpu);
+
+    uint8_t lo = mem_read(cpu, zp_ptr_addr);
+    uint8_t hi = mem_read(cpu, (zp_ptr_addr + 1) & 0xFF);
+
+    uint16_t base_addr = (hi << 8) | lo;
+
+    addr = base_addr + cpu->y;
+
+    if ((addr & 0xFF00) != (base_addr & 0xFF00)) {
+      cycles_used += 1;
+    }
+
+    value = mem_read(cpu, addr);
+    cycles_used += 5;
+    break;
+  }
+  default:
+    break;
+  }
+
+  cpu->a = lo8(value);
+
+  cpu->flags &= ~(FLAG_Z | FLAG_N);
+  if (cpu->a == 0)
+    cpu->flags |= FLAG_Z;
+  if (cpu->a & 0x80)
+    cpu->flags |= FLAG_N;
+
+  return cycles_used;
+}
+
+/**
+ * @brief Handles the LDX (Load X Register) instruction.
+ *
+ * This function loads a byte from memory into the X register, updating the
+ * Zero (Z) and Negative (N) flags based on the value loaded.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @param mode The addressing mode used by the instruction.
+ * @return The number of cycles consumed by the instruction.
+ */
+uint8_t handler_ldx(CPU *cpu, AddressingMode mode) {
+  uint16_t addr;
+  uint8_t value;
+  uint8_t cycles_used = 0;
+
+  switch (mode) {
+  case MODE_IMMEDIATE:
+    addr = addr_immediate(cpu);
+    value = mem_read(cpu, addr);
+      cycles_used += 2;
+    break;
+  case MODE_ZEROPAGE:
+    addr = addr_zeropage(cpu);
+    value = mem_read(cpu, addr);
+      cycles_used += 3;
+    break;
+  case MODE_ABSOLUTE:
+    addr = addr_absolute(cpu);
+    value = mem_read(cpu, addr);
+    cycles_used += 3;
+    break;
+  case MODE_ZEROPAGE_Y:
+      addr = (addr_zeropage(cpu) + cpu->y) & 0xFF;
+      value = mem_read(cpu, addr);
+      cycles_used += 4;
+      break;
+  case MODE_ABSOLUTE_Y: {
+      uint16_t base_addr = addr_absolute(cpu);
+      addr = base_addr + cpu->y;
+      if ((base_addr & 0xFF00) != (addr & 0xFF00)) {
+        cycles_used += 1;
+      }
+      value = mem_read(cpu, addr);
+      cycles_used += 4;
+      break;
+  }
+  default:
+    printf("Unimplemented addressing mode\n");
+    return 0;
+  }
+
+  cpu->x = lo8(value);
+  return cycles_used;
+}
+
+uint8_t handler_ldy(CPU *cpu, AddressingMode mode) {
+  uint16_t addr;
+  uint8_t value;
+  uint8_t cycles_used = 0;
+  switch (mode) {
+    case MODE_IMMEDIATE:
+      addr = addr_immediate(cpu);
+      value = mem_read(cpu, addr);
+      cycles_used += 2;
+      break;
+    case MODE_ZEROPAGE:
+      addr = addr_zeropage(cpu);
+      value = mem_read(cpu, addr);
+      cycles_used += 3;
+      break;
+    case MODE_ABSOLUTE:
+      addr = addr_absolute(cpu);
+      value = mem_read(cpu, addr);
+      cycles_used += 3;
+      break;
+    case MODE_ZEROPAGE_X:
+      addr = (addr_zeropage(cpu) + cpu->x) & 0xFF;
+      value = mem_read(cpu, addr);
+      cycles_used += 4;
+      break;
+    case MODE_ABSOLUTE_X: {
+      uint16_t base_addr = addr_absolute(cpu);
+      addr = base_addr + cpu->x;
+      if ((base_addr & 0xFF00) != (addr & 0xFF00)) {
+        cycles_used += 1;
+      }
+      value = mem_read(cpu, addr);
+      cycles_used += 4;
+      break;
+    }
+    default:
+      printf("Unimplemented addressing mode\n");
+      return 0;
+  }
+
+  cpu->y = lo8(value);
+  return cycles_used;
+}
+
+static void set_flag(CPU *cpu, uint8_t flag, int on) {
+  if (on)
+    cpu->flags |= flag;
+  else
+    cpu->flags &= ~flag;
+}
+
+/**
+ * @brief Adds a value and the carry flag to the accumulator.
+ *
+ * In binary mode Z, N, V and C describe the sum. While FLAG_D is set the
+ * operands are packed BCD and the result and C are those of the decimal
+ * sum. The other flags follow the NMOS 6502: Z still reflects the binary
+ * sum, and N and V the intermediate result once the low digit has been
+ * adjusted, so they are only meaningful for valid BCD operands.
+ */
+static void adc_op(CPU *cpu, uint8_t value) {
+  uint8_t carry_in = (cpu->flags & FLAG_C) ? 1 : 0;
+  uint16_t result_16 = (uint16_t)cpu->a + (uint16_t)value + (uint16_t)carry_in;
+  uint8_t final_result = lo8(result_16);
+
+  set_flag(cpu, FLAG_Z, final_result == 0);
+  if (!(cpu->flags & FLAG_D)) {
+    set_flag(cpu, FLAG_N, final_result & 0x80);
+    set_flag(cpu, FLAG_V, ~(cpu->a ^ value) & (cpu->a ^ final_result) & 0x80);
+    set_flag(cpu, FLAG_C, result_16 & 0x100);
+    cpu->a = final_result;
+    return;
+  }
+
+  int low = (cpu->a & 0x0F) + (value & 0x0F) + carry_in;
+  if (low >= 0x0A)
+    low = ((low + 0x06) & 0x0F) + 0x10;
+  int sum = (cpu->a & 0xF0) + (value & 0xF0) + low;
+  int signed_sum = (int8_t)(cpu->a & 0xF0) + (int8_t)(value & 0xF0) + low;
+
+  set_flag(cpu, FLAG_N, sum & 0x80);
+  set_flag(cpu, FLAG_V, signed_sum < -128 || signed_sum > 127);
+  if (sum >= 0xA0)
+    sum += 0x60;
+  set_flag(cpu, FLAG_C, sum >= 0x100);
+  cpu->a = lo8(sum);
+}
+
+/**
+ * @brief Subtracts a value and the borrow (inverted carry) from the
+ * accumulator.
+ *
+ * Z, N, V and C always describe the binary difference, as on the NMOS
+ * 6502; while FLAG_D is set the result is the packed BCD difference.
+ */
+static void sbc_op(CPU *cpu, uint8_t value) {
+  uint8_t carry_in = (cpu->flags & FLAG_C) ? 1 : 0;
+  uint16_t result_16 =
+      (uint16_t)cpu->a + (uint16_t)(value ^ 0xFF) + (uint16_t)carry_in;
+  uint8_t final_result = lo8(result_16);
+
+  set_flag(cpu, FLAG_Z, final_result == 0);
+  set_flag(cpu, FLAG_N, final_result & 0x80);
+  set_flag(cpu, FLAG_V, (cpu->a ^ value) & (cpu->a ^ final_result) & 0x80);
+  set_flag(cpu, FLAG_C, result_16 & 0x100);
+
+  if (cpu->flags & FLAG_D) {
+    int low = (cpu->a & 0x0F) - (value & 0x0F) + carry_in - 1;
+    if (low < 0)
+      low = ((low - 0x06) & 0x0F) - 0x10;
+    int difference = (cpu->a & 0xF0) - (value & 0xF0) + low;
+    if (difference < 0)
+      difference -= 0x60;
+    final_result = lo8(difference);
+  }
+  cpu->a = final_result;
+}
+
+/**
+ * @brief Handles the ADC (Add with Carry) instruction.
+ *
+ * This function adds a value from memory and the carry flag to the
+ * accumulator, in binary or, while FLAG_D is set, decimal. It updates the
+ * Zero (Z), Negative (N), Overflow (V), and Carry (C) flags; see adc_op.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @param mode The addressing mode used by the instruction.
+ * @return The number of cycles consumed by the instruction.
+ */
+uint8_t handler_adc(CPU *cpu, AddressingMode mode) {
+  uint16_t addr;
+
+  switch (mode) {
+  case MODE_IMMEDIATE:
+    addr = addr_immediate(cpu);
+    break;
+  case MODE_ZEROPAGE:
+    addr = addr_zeropage(cpu);
+    break;
+  case MODE_ABSOLUTE:
+    addr = addr_absolute(cpu);
+    break;
+  default:
+    printf("Unimplemented addressing mode\n");
+    return 0;
+  }
+
+  adc_op(cpu, mem_read(cpu, addr));
+  return 2;
+}
+
+/**
+ * @brief Handles the SBC (Subtract with Carry) instruction.
+ *
+ * This function subtracts a value from memory and the inverted carry flag
+ * from the accumulator, in binary or, while FLAG_D is set, decimal. It
+ * updates the Zero (Z), Negative (N), Overflow (V), and Carry (C) flags;
+ * see sbc_op.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @param mode The addressing mode used by the instruction.
+ * @return The number of cycles consumed by the instruction.
+ */
+uint8_t handler_sbc(CPU *cpu, AddressingMode mode) {
+  uint16_t addr;
+  uint8_t cycles_used;
+
+  switch (mode) {
+  case MODE_IMMEDIATE:
+    addr = addr_immediate(cpu);
+    cycles_used = 2;
+    break;
+  case MODE_ZEROPAGE:
+    addr = addr_zeropage(cpu);
+    cycles_used = 3;
+    break;
+  case MODE_ABSOLUTE:
+    addr = addr_absolute(cpu);
+    cycles_used = 4;
+    break;
+  default:
+    printf("Unimplemented addressing mode\n");
+    return 0;
+  }
+
+  sbc_op(cpu, mem_read(cpu, addr));
+  return cycles_used;
+}
+
+/**
+ * @brief Handles the SED (Set Decimal) instruction, which turns on decimal
+ * arithmetic for ADC and SBC.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @param mode The addressing mode used by the instruction (implied).
+ * @return The number of cycles consumed by the instruction.
+ */
+uint8_t handler_sed(CPU *cpu, AddressingMode mode) {
+  (void)mode;
+  cpu->flags |= FLAG_D;
+  return 2;
+}
+
+/**
+ * @brief Handles the CLD (Clear Decimal) instruction, which returns ADC
+ * and SBC to binary arithmetic.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @param mode The addressing mode used by the instruction (implied).
+ * @return The number of cycles consumed by the instruction.
+ */
+uint8_t handler_cld(CPU *cpu, AddressingMode mode) {
+  (void)mode;
+  cpu->flags &= ~FLAG_D;
+  return 2;
+}
+
+/**
+ * @brief Handles the CLI (Clear Interrupt Disable) instruction, which
+ * unmasks IRQs. The poll at its own end still sees them masked, so an IRQ
+ * is taken after the next instruction at the earliest.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @param mode The addressing mode used by the instruction (implied).
+ * @return The number of cycles consumed by the instruction.
+ */
+uint8_t handler_cli(CPU *cpu, AddressingMode mode) {
+  (void)mode;
+  cpu->flags &= ~FLAG_I;
+  return 2;
+}
+
+/**
+ * @brief Handles the SEI (Set Interrupt Disable) instruction, which masks
+ * IRQs; one the poll at its end still saw unmasked is taken right after it.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @param mode The addressing mode used by the instruction (implied).
+ * @return The number of cycles consumed by the instruction.
+ */
+uint8_t handler_sei(CPU *cpu, AddressingMode mode) {
+  (void)mode;
+  cpu->flags |= FLAG_I;
+  return 2;
+}
+
+uint8_t handler_lsr(CPU *cpu, AddressingMode mode) {
+  uint16_t addr;
+  uint8_t value;
+  uint8_t cycles_used = 0;
+  switch (mode) {
+    case MODE_ACCUMULATOR:
+      cpu->a = lsr_op(cpu, cpu->a);
+      cycles_used = 2;
+      break;
+    case MODE_ZEROPAGE:
+      addr = addr_zeropage(cpu);
+      value = mem_read(cpu, addr);
+      value = lsr_op(cpu, value);
+      mem_write(cpu, addr, value);
+      cycles_used = 5;
+      break;
+    case MODE_ZEROPAGE_X:
+      addr = (addr_zeropage(cpu) + cpu->x) & 0xFF;
+      value = mem_read(cpu, addr);
+      value = lsr_op(cpu, value);
+      mem_write(cpu, addr, value);
+      cycles_used = 6;
+      break;
+    case MODE_ABSOLUTE:
+      addr = addr_absolute(cpu);
+      value = mem_read(cpu, addr);
+      value = lsr_op(cpu, value);
+      mem_write(cpu, addr, value);
+      cycles_used = 6;
+      break;
+    case MODE_ABSOLUTE_X: {
+      uint16_t base_addr = addr_absolute(cpu);
+      addr = base_addr + cpu->x;
+      if ((base_addr & 0xFF00) != (addr & 0xFF00)) {
+        cycles_used += 1;
+      }
+      value = mem_read(cpu, addr);
+      value = lsr_op(cpu, value);
+      mem_write(cpu, addr, value);
+      cycles_used += 7;
+      break;
+    }
+    default:
+      printf("Unimplemented addressing mode\n");
+      return 0;
+  }
+  return cycles_used;
+}
+
+/**
+ * @brief Handles the undocumented NOPs, and illegal opcodes under
+ * ILLEGAL_NOP.
+ *
+ * Operands are fetched and, outside immediate mode, the address they form
+ * is read and the value dropped, as on the 6502.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @param mode The addressing mode used by the instruction.
+ * @return The number of cycles consumed by the instruction.
+ */
+uint8_t handler_nop(CPU *cpu, AddressingMode mode) {
+  switch (mode) {
+  case MODE_IMMEDIATE:
+    mem_read(cpu, addr_immediate(cpu));
+    return 2;
+  case MODE_ZEROPAGE:
+    mem_read(cpu, addr_zeropage(cpu));
+    return 3;
+  case MODE_ZEROPAGE_X:
+    mem_read(cpu, (addr_zeropage(cpu) + cpu->x) & 0xFF);
+    return 4;
+  case MODE_ABSOLUTE:
+    mem_read(cpu, addr_absolute(cpu));
+    return 4;
+  case MODE_ABSOLUTE_X: {
+    uint16_t base_addr = addr_absolute(cpu);
+    uint16_t addr = base_addr + cpu->x;
+    mem_read(cpu, addr);
+    return (base_addr & 0xFF00) != (addr & 0xFF00) ? 5 : 4;
+  }
+  default:
+    return 2;
+  }
+}
+
+/**
+ * @brief Handles the undocumented LAX instruction.
+ *
+ * Loads a byte into both A and X, updating Z and N. It is LDA and LDX at
+ * once and takes the cycles of the load that has its addressing mode.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @param mode The addressing mode used by the instruction.
+ * @return The number of cycles consumed by the instruction.
+ */
+uint8_t handler_lax(CPU *cpu, AddressingMode mode) {
+  uint8_t cycles_used;
+
+  if (mode == MODE_ZEROPAGE_Y || mode == MODE_ABSOLUTE_Y) {
+    cycles_used = handler_ldx(cpu, mode);
+    cpu->a = cpu->x;
+  } else {
+    cycles_used = handler_lda(cpu, mode);
+    cpu->x = cpu->a;
+  }
+
+  cpu->flags &= ~(FLAG_Z | FLAG_N);
+  if (cpu->a == 0)
+    cpu->flags |= FLAG_Z;
+  if (cpu->a & 0x80)
+    cpu->flags |= FLAG_N;
+
+  return cycles_used;
+}
+
+const Instruction illegal_nop = {"NOP", handler_nop, MODE_IMPLIED, 2};
+
+/**
+ * The instruction tables, built at compile time from opcodes.def and
+ * undocumented.def. Opcodes the lists leave out have no handler. Being
+ * constant, the tables are safe to share between CPUs on any number of
+ * threads.
+ */
+const Instruction instruction_table[256] = {
+#define OPCODE(code, name, handler, mode, cycles)                              \
+  [code] = {#name, handler, mode, cycles},
+#include "opcodes.def"
+#undef OPCODE
+};
+
+const Instruction undocumented_table[256] = {
+#define OPCODE(code, name, handler, mode, cycles)                              \
+  [code] = {#name, handler, mode, cycles},
+#include "undocumented.def"
+#undef OPCODE
+};
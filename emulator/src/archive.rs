@@ -0,0 +1,444 @@
+//! Compressed and archived ROMs.
+//!
+//! Only compiled with the `compression` feature.
+//! [`Rom::from_bytes`](crate::Rom::from_bytes), and so
+//! [`Rom::from_file`](crate::Rom::from_file) and every loader built on
+//! them, unpacks a ROM that comes gzipped (`.rvm.gz`) or inside a zip
+//! archive first, so ROM collections can be loaded as they are
+//! distributed. Data that is neither is taken to be a plain ROM image,
+//! which can never be mistaken for an archive since it starts with
+//! [`MAGIC`](crate::rom::MAGIC).
+//!
+//! From a zip archive the loader takes the first entry whose name ends in
+//! `.rvm`, or the only file if there is just one. Entries must be stored or
+//! deflated, the two methods every zip tool writes, and their checksums are
+//! verified like the gzip trailer's. Nothing unpacks to more than
+//! [`MAX_UNPACKED`] bytes, which is far more than any ROM, so a malicious
+//! archive cannot exhaust memory.
+
+use std::borrow::Cow;
+use std::fmt;
+
+use crate::rom::{BANK_SIZE, HEADER_SIZE, crc32};
+
+/// Most bytes an archive may unpack to.
+pub const MAX_UNPACKED: usize = HEADER_SIZE + 256 * BANK_SIZE;
+
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+const ZIP_MAGIC: [u8; 4] = *b"PK\x03\x04";
+const ZIP_DIRECTORY: [u8; 4] = *b"PK\x01\x02";
+const ZIP_END: [u8; 4] = *b"PK\x05\x06";
+/// Fixed part of a zip end-of-central-directory record.
+const ZIP_END_SIZE: usize = 22;
+
+/// Why an archive could not be unpacked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArchiveError {
+    /// The archive ends early.
+    Truncated,
+    /// The archive or its compressed data is malformed.
+    Corrupt,
+    /// A zip entry uses a compression method other than stored or deflate.
+    UnsupportedMethod(u16),
+    /// The unpacked data does not match the archive's checksum.
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// A zip archive with several files, none of them named `.rvm`, or
+    /// none at all.
+    NoRom,
+    /// The data unpacks to more than [`MAX_UNPACKED`] bytes.
+    TooLarge,
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated => write!(f, "archive is truncated"),
+            Self::Corrupt => write!(f, "archive is corrupt"),
+            Self::UnsupportedMethod(method) => {
+                write!(f, "unsupported zip compression method {method}")
+            }
+            Self::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "unpacked checksum 0x{actual:08X} does not match archive 0x{expected:08X}"
+            ),
+            Self::NoRom => write!(f, "archive holds no single ROM"),
+            Self::TooLarge => write!(f, "archive unpacks to more than {MAX_UNPACKED} bytes"),
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {}
+
+/// The ROM image in `bytes`: unpacked if it is a gzip file or zip archive,
+/// and `bytes` itself otherwise.
+pub fn unpack(bytes: &[u8]) -> Result<Cow<'_, [u8]>, ArchiveError> {
+    if bytes.starts_with(&GZIP_MAGIC) {
+        gunzip(bytes).map(Cow::Owned)
+    } else if bytes.starts_with(&ZIP_MAGIC) {
+        unzip(bytes).map(Cow::Owned)
+    } else {
+        Ok(Cow::Borrowed(bytes))
+    }
+}
+
+fn le16(bytes: &[u8], at: usize) -> Result<u16, ArchiveError> {
+    let field = bytes.get(at..at + 2).ok_or(ArchiveError::Truncated)?;
+    Ok(u16::from_le_bytes([field[0], field[1]]))
+}
+
+fn le32(bytes: &[u8], at: usize) -> Result<u32, ArchiveError> {
+    let field = bytes.get(at..at + 4).ok_or(ArchiveError::Truncated)?;
+    Ok(u32::from_le_bytes([field[0], field[1], field[2], field[3]]))
+}
+
+fn check(data: &[u8], expected: u32) -> Result<(), ArchiveError> {
+    let actual = crc32(data);
+    if actual != expected {
+        return Err(ArchiveError::ChecksumMismatch { expected, actual });
+    }
+    Ok(())
+}
+
+/// Unpacks a gzip member (RFC 1952).
+fn gunzip(bytes: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+    const FHCRC: u8 = 1 << 1;
+    const FEXTRA: u8 = 1 << 2;
+    const FNAME: u8 = 1 << 3;
+    const FCOMMENT: u8 = 1 << 4;
+
+    let header = bytes.get(..10).ok_or(ArchiveError::Truncated)?;
+    if header[2] != 8 {
+        return Err(ArchiveError::Corrupt);
+    }
+    let flags = header[3];
+    let mut at = 10;
+    if flags & FEXTRA != 0 {
+        at += 2 + usize::from(le16(bytes, at)?);
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let rest = bytes.get(at..).ok_or(ArchiveError::Truncated)?;
+            let len = rest
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or(ArchiveError::Truncated)?;
+            at += len + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        at += 2;
+    }
+    let (data, used) = inflate(bytes.get(at..).ok_or(ArchiveError::Truncated)?)?;
+    let trailer = at + used;
+    check(&data, le32(bytes, trailer)?)?;
+    if le32(bytes, trailer + 4)? != data.len() as u32 {
+        return Err(ArchiveError::Corrupt);
+    }
+    Ok(data)
+}
+
+/// Unpacks the ROM entry of a zip archive.
+fn unzip(bytes: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+    // The end record sits at the very end, unless a comment follows it.
+    let end = (0..=bytes.len().saturating_sub(ZIP_END_SIZE))
+        .rev()
+        .find(|&at| bytes[at..].starts_with(&ZIP_END))
+        .ok_or(ArchiveError::Truncated)?;
+    let entries = le16(bytes, end + 10)?;
+    let mut at = le32(bytes, end + 16)? as usize;
+
+    // (name, method, crc, compressed size, size, local header offset)
+    let mut files = Vec::new();
+    for _ in 0..entries {
+        if bytes.get(at..at + 4) != Some(&ZIP_DIRECTORY[..]) {
+            return Err(ArchiveError::Corrupt);
+        }
+        let name_len = usize::from(le16(bytes, at + 28)?);
+        let extra_len = usize::from(le16(bytes, at + 30)?);
+        let comment_len = usize::from(le16(bytes, at + 32)?);
+        let name = bytes
+            .get(at + 46..at + 46 + name_len)
+            .ok_or(ArchiveError::Truncated)?;
+        if !name.ends_with(b"/") {
+            files.push((
+                name,
+                le16(bytes, at + 10)?,
+                le32(bytes, at + 16)?,
+                le32(bytes, at + 20)? as usize,
+                le32(bytes, at + 24)? as usize,
+                le32(bytes, at + 42)? as usize,
+            ));
+        }
+        at += 46 + name_len + extra_len + comment_len;
+    }
+
+    let is_rom = |name: &[u8]| name.to_ascii_lowercase().ends_with(b".rvm");
+    let &(_, method, crc, packed, size, local) = match files.iter().find(|f| is_rom(f.0)) {
+        Some(file) => file,
+        None if files.len() == 1 => &files[0],
+        None => return Err(ArchiveError::NoRom),
+    };
+    if size > MAX_UNPACKED {
+        return Err(ArchiveError::TooLarge);
+    }
+    if bytes.get(local..local + 4) != Some(&ZIP_MAGIC[..]) {
+        return Err(ArchiveError::Corrupt);
+    }
+    let start =
+        local + 30 + usize::from(le16(bytes, local + 26)?) + usize::from(le16(bytes, local + 28)?);
+    let packed = bytes
+        .get(start..start + packed)
+        .ok_or(ArchiveError::Truncated)?;
+    let data = match method {
+        0 => packed.to_vec(),
+        8 => inflate(packed)?.0,
+        method => return Err(ArchiveError::UnsupportedMethod(method)),
+    };
+    if data.len() != size {
+        return Err(ArchiveError::Corrupt);
+    }
+    check(&data, crc)?;
+    Ok(data)
+}
+
+/// Reads a deflate stream LSB first.
+struct Bits<'a> {
+    data: &'a [u8],
+    /// Next byte to load.
+    at: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl Bits<'_> {
+    fn bits(&mut self, n: u32) -> Result<u32, ArchiveError> {
+        while self.count < n {
+            let byte = *self.data.get(self.at).ok_or(ArchiveError::Truncated)?;
+            self.at += 1;
+            self.buffer |= u32::from(byte) << self.count;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1 << n) - 1);
+        self.buffer >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    /// Drops the bits left in the current byte.
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+const MAX_BITS: usize = 15;
+
+/// A canonical Huffman code, as counts of codes per length and the
+/// symbols in code order.
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// The code giving symbol `i` a code of `lengths[i]` bits, 0 for none.
+    fn new(lengths: &[u8]) -> Result<Self, ArchiveError> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &len in lengths {
+            counts[usize::from(len)] += 1;
+        }
+        counts[0] = 0;
+        // Refuse codes with more codes of a length than there is room for.
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = left * 2 - i32::from(count);
+            if left < 0 {
+                return Err(ArchiveError::Corrupt);
+            }
+        }
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for len in 1..=MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; usize::from(offsets[MAX_BITS + 1])];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                let slot = &mut offsets[usize::from(len)];
+                symbols[usize::from(*slot)] = symbol as u16;
+                *slot += 1;
+            }
+        }
+        Ok(Self { counts, symbols })
+    }
+
+    fn decode(&self, bits: &mut Bits<'_>) -> Result<u16, ArchiveError> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= bits.bits(1)? as i32;
+            let count = i32::from(count);
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(ArchiveError::Corrupt)
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// The order code length code lengths are sent in.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Decompresses a raw deflate stream (RFC 1951), returning the data and
+/// the bytes of `data` the stream took.
+fn inflate(data: &[u8]) -> Result<(Vec<u8>, usize), ArchiveError> {
+    let mut bits = Bits {
+        data,
+        at: 0,
+        buffer: 0,
+        count: 0,
+    };
+    let mut out = Vec::new();
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => stored(&mut bits, &mut out)?,
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                let literals = Huffman::new(&lengths)?;
+                let distances = Huffman::new(&[5; 30])?;
+                codes(&mut bits, &mut out, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic(&mut bits)?;
+                codes(&mut bits, &mut out, &literals, &distances)?;
+            }
+            _ => return Err(ArchiveError::Corrupt),
+        }
+        if last {
+            // Whole bytes left in the buffer belong to what follows.
+            return Ok((out, bits.at - (bits.count / 8) as usize));
+        }
+    }
+}
+
+fn stored(bits: &mut Bits<'_>, out: &mut Vec<u8>) -> Result<(), ArchiveError> {
+    bits.align();
+    let at = bits.at;
+    let len = le16(bits.data, at)?;
+    if le16(bits.data, at + 2)? != !len {
+        return Err(ArchiveError::Corrupt);
+    }
+    let block = bits
+        .data
+        .get(at + 4..at + 4 + usize::from(len))
+        .ok_or(ArchiveError::Truncated)?;
+    if out.len() + block.len() > MAX_UNPACKED {
+        return Err(ArchiveError::TooLarge);
+    }
+    out.extend_from_slice(block);
+    bits.at = at + 4 + usize::from(len);
+    Ok(())
+}
+
+/// Reads the codes of a dynamic block.
+fn dynamic(bits: &mut Bits<'_>) -> Result<(Huffman, Huffman), ArchiveError> {
+    let literals = bits.bits(5)? as usize + 257;
+    let distances = bits.bits(5)? as usize + 1;
+    let code_lengths = bits.bits(4)? as usize + 4;
+    if literals > 286 || distances > 30 {
+        return Err(ArchiveError::Corrupt);
+    }
+    let mut lengths = [0u8; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..code_lengths] {
+        lengths[symbol] = bits.bits(3)? as u8;
+    }
+    let code = Huffman::new(&lengths)?;
+
+    let mut lengths = vec![0u8; literals + distances];
+    let mut i = 0;
+    while i < lengths.len() {
+        let (len, repeat) = match code.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 if i > 0 => (lengths[i - 1], 3 + bits.bits(2)?),
+            17 => (0, 3 + bits.bits(3)?),
+            18 => (0, 11 + bits.bits(7)?),
+            _ => return Err(ArchiveError::Corrupt),
+        };
+        let run = lengths
+            .get_mut(i..i + repeat as usize)
+            .ok_or(ArchiveError::Corrupt)?;
+        run.fill(len);
+        i += repeat as usize;
+    }
+    if lengths[256] == 0 {
+        return Err(ArchiveError::Corrupt);
+    }
+    Ok((
+        Huffman::new(&lengths[..literals])?,
+        Huffman::new(&lengths[literals..])?,
+    ))
+}
+
+/// Decodes a compressed block's literals and back-references.
+fn codes(
+    bits: &mut Bits<'_>,
+    out: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), ArchiveError> {
+    loop {
+        let symbol = usize::from(literals.decode(bits)?);
+        if symbol < 256 {
+            out.push(symbol as u8);
+        } else if symbol == 256 {
+            return Ok(());
+        } else {
+            let symbol = symbol - 257;
+            let (&base, &extra) = LENGTH_BASE
+                .get(symbol)
+                .zip(LENGTH_EXTRA.get(symbol))
+                .ok_or(ArchiveError::Corrupt)?;
+            let len = usize::from(base) + bits.bits(u32::from(extra))? as usize;
+            let symbol = usize::from(distances.decode(bits)?);
+            let (&base, &extra) = DISTANCE_BASE
+                .get(symbol)
+                .zip(DISTANCE_EXTRA.get(symbol))
+                .ok_or(ArchiveError::Corrupt)?;
+            let distance = usize::from(base) + bits.bits(u32::from(extra))? as usize;
+            if distance > out.len() {
+                return Err(ArchiveError::Corrupt);
+            }
+            // Copies byte by byte, since the source may overlap the copy.
+            let from = out.len() - distance;
+            for k in 0..len {
+                out.push(out[from + k]);
+            }
+        }
+        if out.len() > MAX_UNPACKED {
+            return Err(ArchiveError::TooLarge);
+        }
+    }
+}
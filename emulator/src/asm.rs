@@ -0,0 +1,707 @@
+//! Two-pass assembler for rvm-8 assembly.
+//!
+//! The syntax follows the usual 6502 conventions:
+//!
+//! ```text
+//! ; comments run to the end of the line
+//! COUNT = 3               ; constants
+//!         .org $8000      ; set the assembly address
+//! start:  LDX #COUNT      ; labels end with ':'
+//!         LDA table,X
+//!         LSR A
+//! table:  .byte 1, 2, $03, %100, "text"
+//!         .word start
+//! ```
+//!
+//! Numbers are decimal, `$`/`0x` hex, `%` binary or `'c'` characters;
+//! expressions combine them with symbols, `*` (the current address), `+`,
+//! `-`, and the `<`/`>` low/high byte operators. The first pass sizes every
+//! line and assigns label addresses; the second emits bytes with all symbols
+//! known. An operand that is still undefined during the first pass is sized
+//! as absolute, so zero-page forms are only chosen for values defined before
+//! their use.
+//!
+//! Opcodes come from [`disasm`]'s table, so anything the kernel can execute
+//! can be assembled.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::disasm::{self, instruction_size};
+use crate::error::VmError;
+use crate::ffi::AddressingMode;
+use crate::vm::Vm;
+
+/// A contiguous run of assembled bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub origin: u16,
+    pub bytes: Vec<u8>,
+}
+
+/// The output of [`assemble`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Assembly {
+    /// Segments in source order; each `.org` starts a new one.
+    pub segments: Vec<Segment>,
+    /// Every label and constant with its value.
+    pub symbols: BTreeMap<String, u16>,
+    /// The address of each source line holding an instruction, by 1-based
+    /// line number, for debuggers to map lines to code and back.
+    pub lines: BTreeMap<usize, u16>,
+}
+
+impl Assembly {
+    /// Flattens all segments into one image starting at the lowest origin,
+    /// zero-filling any gaps between them.
+    pub fn image(&self) -> (u16, Vec<u8>) {
+        let Some(start) = self.segments.iter().map(|s| s.origin).min() else {
+            return (0, Vec::new());
+        };
+        let end = self
+            .segments
+            .iter()
+            .map(|s| s.origin as usize + s.bytes.len())
+            .max()
+            .unwrap_or(start as usize);
+
+        let mut image = vec![0; end - start as usize];
+        for seg in &self.segments {
+            let offset = (seg.origin - start) as usize;
+            image[offset..offset + seg.bytes.len()].copy_from_slice(&seg.bytes);
+        }
+        (start, image)
+    }
+}
+
+/// An assembly failure on a given source line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    /// 1-based source line.
+    pub line: usize,
+    pub kind: AsmErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmErrorKind {
+    /// The line could not be parsed.
+    Syntax(String),
+    UnknownMnemonic(String),
+    /// The mnemonic exists, but not with the written addressing mode.
+    InvalidMode(String),
+    UnknownDirective(String),
+    UndefinedSymbol(String),
+    DuplicateSymbol(String),
+    /// A value does not fit the field it is assembled into.
+    OutOfRange(i64),
+    /// Output ran past the end of the address space.
+    Overflow,
+    /// This segment overlaps bytes already emitted at `addr`.
+    Overlap {
+        addr: u16,
+    },
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            AsmErrorKind::Syntax(msg) => write!(f, "syntax error: {msg}"),
+            AsmErrorKind::UnknownMnemonic(m) => write!(f, "unknown mnemonic `{m}`"),
+            AsmErrorKind::InvalidMode(m) => {
+                write!(f, "`{m}` does not support this addressing mode")
+            }
+            AsmErrorKind::UnknownDirective(d) => write!(f, "unknown directive `{d}`"),
+            AsmErrorKind::UndefinedSymbol(s) => write!(f, "undefined symbol `{s}`"),
+            AsmErrorKind::DuplicateSymbol(s) => write!(f, "symbol `{s}` is already defined"),
+            AsmErrorKind::OutOfRange(v) => write!(f, "value {v} is out of range"),
+            AsmErrorKind::Overflow => write!(f, "output runs past 0xFFFF"),
+            AsmErrorKind::Overlap { addr } => write!(f, "segment overlaps code at 0x{addr:04X}"),
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Assembles `source` into segments and a symbol table.
+pub fn assemble(source: &str) -> Result<Assembly, AsmError> {
+    let mut asm = Assembler::default();
+    asm.first_pass(source)?;
+    asm.resolve_constants()?;
+    asm.second_pass()
+}
+
+impl Vm {
+    /// Copies every segment of `program` into memory.
+    pub fn load_assembly(&mut self, program: &Assembly) -> Result<(), VmError> {
+        for seg in &program.segments {
+            self.load(seg.origin, &seg.bytes)?;
+        }
+        Ok(())
+    }
+}
+
+/// A directive or instruction after the first pass, with its address and
+/// size already fixed.
+#[derive(Debug)]
+enum Stmt<'a> {
+    Org(u16),
+    Bytes(Vec<&'a str>),
+    Words(Vec<&'a str>),
+    Instr {
+        opcode: u8,
+        mode: AddressingMode,
+        operand: &'a str,
+    },
+}
+
+#[derive(Debug)]
+struct Line<'a> {
+    number: usize,
+    addr: u16,
+    stmt: Stmt<'a>,
+}
+
+#[derive(Default)]
+struct Assembler<'a> {
+    symbols: HashMap<&'a str, u16>,
+    /// Constants whose expressions referenced symbols not yet defined.
+    pending: Vec<(usize, &'a str, &'a str, u16)>,
+    lines: Vec<Line<'a>>,
+}
+
+impl<'a> Assembler<'a> {
+    fn first_pass(&mut self, source: &'a str) -> Result<(), AsmError> {
+        let mut pc: u32 = 0;
+
+        for (index, raw) in source.lines().enumerate() {
+            let number = index + 1;
+            let err = |kind| AsmError { line: number, kind };
+            let mut text = strip_comment(raw).trim();
+
+            if let Some((label, rest)) = split_label(text) {
+                let addr = u16::try_from(pc).map_err(|_| err(AsmErrorKind::Overflow))?;
+                self.define(label, addr).map_err(err)?;
+                text = rest.trim();
+            }
+            if text.is_empty() {
+                continue;
+            }
+
+            if let Some((name, expr)) = split_constant(text) {
+                match eval(expr, &self.symbols, pc as u16) {
+                    Ok(value) => {
+                        let value = to_u16(value).map_err(err)?;
+                        self.define(name, value).map_err(err)?;
+                    }
+                    Err(AsmErrorKind::UndefinedSymbol(_)) => {
+                        self.pending.push((number, name, expr, pc as u16))
+                    }
+                    Err(kind) => return Err(err(kind)),
+                }
+                continue;
+            }
+
+            let (word, rest) = split_word(text);
+            let stmt = if word.starts_with('.') {
+                self.directive(word, rest, pc as u16).map_err(err)?
+            } else {
+                let known = |expr: &str| eval(expr, &self.symbols, pc as u16).ok();
+                let (opcode, mode, operand) = select_opcode(word, rest, known).map_err(err)?;
+                Stmt::Instr {
+                    opcode,
+                    mode,
+                    operand,
+                }
+            };
+
+            let addr = pc as u16;
+            pc = match stmt {
+                Stmt::Org(origin) => origin as u32,
+                _ => pc + stmt_size(&stmt),
+            };
+            if pc > 0x10000 {
+                return Err(err(AsmErrorKind::Overflow));
+            }
+            self.lines.push(Line { number, addr, stmt });
+        }
+        Ok(())
+    }
+
+    fn directive(&self, name: &str, args: &'a str, pc: u16) -> Result<Stmt<'a>, AsmErrorKind> {
+        match name.to_ascii_lowercase().as_str() {
+            ".org" => Ok(Stmt::Org(to_u16(eval(args, &self.symbols, pc)?)?)),
+            ".byte" | ".db" => Ok(Stmt::Bytes(split_args(args)?)),
+            ".word" | ".dw" => Ok(Stmt::Words(split_args(args)?)),
+            _ => Err(AsmErrorKind::UnknownDirective(name.to_string())),
+        }
+    }
+
+    fn define(&mut self, name: &'a str, value: u16) -> Result<(), AsmErrorKind> {
+        if self.symbols.insert(name, value).is_some() {
+            return Err(AsmErrorKind::DuplicateSymbol(name.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Evaluates constants that forward-referenced labels, repeating while
+    /// progress is made so chains of constants resolve in any order.
+    fn resolve_constants(&mut self) -> Result<(), AsmError> {
+        loop {
+            let pending = std::mem::take(&mut self.pending);
+            let count = pending.len();
+            for (number, name, expr, pc) in pending {
+                let err = |kind| AsmError { line: number, kind };
+                match eval(expr, &self.symbols, pc) {
+                    Ok(value) => {
+                        let value = to_u16(value).map_err(err)?;
+                        self.define(name, value).map_err(err)?;
+                    }
+                    Err(AsmErrorKind::UndefinedSymbol(_)) => {
+                        self.pending.push((number, name, expr, pc))
+                    }
+                    Err(kind) => return Err(err(kind)),
+                }
+            }
+
+            match self.pending.first() {
+                None => return Ok(()),
+                Some(&(number, _, expr, pc)) if self.pending.len() == count => {
+                    let kind = eval(expr, &self.symbols, pc).unwrap_err();
+                    return Err(AsmError { line: number, kind });
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    fn second_pass(self) -> Result<Assembly, AsmError> {
+        let mut segments: Vec<(usize, Segment)> = Vec::new();
+
+        for line in &self.lines {
+            let err = |kind| AsmError {
+                line: line.number,
+                kind,
+            };
+            if let Stmt::Org(origin) = line.stmt {
+                match segments.last_mut() {
+                    Some((_, seg)) if seg.bytes.is_empty() => seg.origin = origin,
+                    _ => segments.push((
+                        line.number,
+                        Segment {
+                            origin,
+                            bytes: Vec::new(),
+                        },
+                    )),
+                }
+                continue;
+            }
+
+            if segments.is_empty() {
+                segments.push((
+                    line.number,
+                    Segment {
+                        origin: 0,
+                        bytes: Vec::new(),
+                    },
+                ));
+            }
+            let out = &mut segments.last_mut().unwrap().1.bytes;
+            self.emit(line, out).map_err(err)?;
+        }
+
+        check_overlaps(&segments)?;
+        let lines = self.lines.iter().filter_map(|line| {
+            matches!(line.stmt, Stmt::Instr { .. }).then_some((line.number, line.addr))
+        });
+        Ok(Assembly {
+            lines: lines.collect(),
+            segments: segments.into_iter().map(|(_, seg)| seg).collect(),
+            symbols: self
+                .symbols
+                .iter()
+                .map(|(&name, &value)| (name.to_string(), value))
+                .collect(),
+        })
+    }
+
+    fn emit(&self, line: &Line<'a>, out: &mut Vec<u8>) -> Result<(), AsmErrorKind> {
+        let value = |expr: &str| eval(expr, &self.symbols, line.addr);
+
+        match &line.stmt {
+            Stmt::Org(_) => {}
+            Stmt::Bytes(items) => {
+                for item in items {
+                    match string_literal(item) {
+                        Some(text) => out.extend_from_slice(text.as_bytes()),
+                        None => out.push(to_byte(value(item)?)?),
+                    }
+                }
+            }
+            Stmt::Words(items) => {
+                for item in items {
+                    let v = value(item)?;
+                    if !(-0x8000..=0xFFFF).contains(&v) {
+                        return Err(AsmErrorKind::OutOfRange(v));
+                    }
+                    out.extend_from_slice(&(v as u16).to_le_bytes());
+                }
+            }
+            &Stmt::Instr {
+                opcode,
+                mode,
+                operand,
+            } => {
+                out.push(opcode);
+                match instruction_size(mode) {
+                    1 => {}
+                    _ if mode == AddressingMode::Relative => {
+                        let offset = value(operand)? - (line.addr as i64 + 2);
+                        let offset =
+                            i8::try_from(offset).map_err(|_| AsmErrorKind::OutOfRange(offset))?;
+                        out.push(offset as u8);
+                    }
+                    2 if mode == AddressingMode::Immediate => out.push(to_byte(value(operand)?)?),
+                    2 => {
+                        let v = value(operand)?;
+                        out.push(u8::try_from(v).map_err(|_| AsmErrorKind::OutOfRange(v))?);
+                    }
+                    _ => out.extend_from_slice(&to_u16(value(operand)?)?.to_le_bytes()),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn stmt_size(stmt: &Stmt<'_>) -> u32 {
+    match stmt {
+        Stmt::Org(_) => 0,
+        Stmt::Bytes(items) => items
+            .iter()
+            .map(|item| string_literal(item).map_or(1, |s| s.len() as u32))
+            .sum(),
+        Stmt::Words(items) => 2 * items.len() as u32,
+        Stmt::Instr { mode, .. } => instruction_size(*mode) as u32,
+    }
+}
+
+fn check_overlaps(segments: &[(usize, Segment)]) -> Result<(), AsmError> {
+    let mut sorted: Vec<_> = segments
+        .iter()
+        .filter(|(_, s)| !s.bytes.is_empty())
+        .collect();
+    sorted.sort_by_key(|(_, seg)| seg.origin);
+    for pair in sorted.windows(2) {
+        let (_, first) = pair[0];
+        let (line, second) = pair[1];
+        if first.origin as usize + first.bytes.len() > second.origin as usize {
+            return Err(AsmError {
+                line: *line,
+                kind: AsmErrorKind::Overlap {
+                    addr: second.origin,
+                },
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Picks the opcode for `mnemonic` with the operand as written. `known`
+/// evaluates an expression if all its symbols are already defined, which
+/// decides between zero-page and absolute forms.
+fn select_opcode<'a>(
+    mnemonic: &str,
+    operand: &'a str,
+    known: impl Fn(&str) -> Option<i64>,
+) -> Result<(u8, AddressingMode, &'a str), AsmErrorKind> {
+    use AddressingMode::*;
+
+    let mnemonic = mnemonic.to_ascii_uppercase();
+    let find = |mode| {
+        disasm::opcodes()
+            .find(|(_, op)| op.mnemonic == mnemonic && op.mode == mode)
+            .map(|(code, _)| code)
+    };
+    if !disasm::opcodes().any(|(_, op)| op.mnemonic == mnemonic) {
+        return Err(AsmErrorKind::UnknownMnemonic(mnemonic));
+    }
+
+    let zero_page = |expr: &str| known(expr).is_some_and(|v| (0..=0xFF).contains(&v));
+    let (expr, candidates): (&str, &[AddressingMode]) = match parse_operand(operand)? {
+        Syntax::None => ("", &[Implied, Accumulator]),
+        Syntax::Accumulator => ("", &[Accumulator]),
+        Syntax::Immediate(e) => (e, &[Immediate]),
+        Syntax::Indirect(e) => (e, &[Indirect]),
+        Syntax::IndirectX(e) => (e, &[IndirectX]),
+        Syntax::IndirectY(e) => (e, &[IndirectY]),
+        Syntax::Direct(e) if zero_page(e) => (e, &[Relative, ZeroPage, Absolute]),
+        Syntax::Direct(e) => (e, &[Relative, Absolute]),
+        Syntax::IndexedX(e) if zero_page(e) => (e, &[ZeroPageX, AbsoluteX]),
+        Syntax::IndexedX(e) => (e, &[AbsoluteX]),
+        Syntax::IndexedY(e) if zero_page(e) => (e, &[ZeroPageY, AbsoluteY]),
+        Syntax::IndexedY(e) => (e, &[AbsoluteY]),
+    };
+
+    candidates
+        .iter()
+        .find_map(|&mode| find(mode).map(|code| (code, mode, expr)))
+        .ok_or(AsmErrorKind::InvalidMode(mnemonic))
+}
+
+/// An operand as written, before its addressing mode is resolved.
+enum Syntax<'a> {
+    None,
+    Accumulator,
+    Immediate(&'a str),
+    Indirect(&'a str),
+    IndirectX(&'a str),
+    IndirectY(&'a str),
+    Direct(&'a str),
+    IndexedX(&'a str),
+    IndexedY(&'a str),
+}
+
+fn parse_operand(text: &str) -> Result<Syntax<'_>, AsmErrorKind> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(Syntax::None);
+    }
+    if text.eq_ignore_ascii_case("A") {
+        return Ok(Syntax::Accumulator);
+    }
+    if let Some(expr) = text.strip_prefix('#') {
+        return Ok(Syntax::Immediate(expr.trim()));
+    }
+    if let Some(inner) = text.strip_prefix('(') {
+        if let Some(expr) = strip_index(inner, ",X)") {
+            return Ok(Syntax::IndirectX(expr));
+        }
+        if let Some(expr) = strip_index(inner, "),Y") {
+            return Ok(Syntax::IndirectY(expr));
+        }
+        if let Some(expr) = inner.strip_suffix(')') {
+            return Ok(Syntax::Indirect(expr.trim()));
+        }
+        return Err(AsmErrorKind::Syntax(format!(
+            "unbalanced parenthesis in `{text}`"
+        )));
+    }
+    if let Some(expr) = strip_index(text, ",X") {
+        return Ok(Syntax::IndexedX(expr));
+    }
+    if let Some(expr) = strip_index(text, ",Y") {
+        return Ok(Syntax::IndexedY(expr));
+    }
+    Ok(Syntax::Direct(text))
+}
+
+/// Strips an index suffix such as `,X)` ignoring case and whitespace.
+fn strip_index<'a>(text: &'a str, suffix: &str) -> Option<&'a str> {
+    let mut rest = text.trim_end();
+    for expected in suffix.chars().rev() {
+        let c = rest.chars().next_back()?;
+        if !c.eq_ignore_ascii_case(&expected) {
+            return None;
+        }
+        rest = rest[..rest.len() - c.len_utf8()].trim_end();
+    }
+    Some(rest.trim())
+}
+
+/// Removes a `;` comment, ignoring semicolons inside quotes.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, ';') => return &line[..i],
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            _ => {}
+        }
+    }
+    line
+}
+
+fn is_symbol_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn symbol_len(text: &str) -> usize {
+    match text.chars().next() {
+        Some(c) if is_symbol_start(c) => text.find(|c| !is_symbol_char(c)).unwrap_or(text.len()),
+        _ => 0,
+    }
+}
+
+/// Splits `label: rest`.
+fn split_label(text: &str) -> Option<(&str, &str)> {
+    let len = symbol_len(text);
+    let rest = text[len..].strip_prefix(':')?;
+    (len > 0).then(|| (&text[..len], rest))
+}
+
+/// Splits `NAME = expr`.
+fn split_constant(text: &str) -> Option<(&str, &str)> {
+    let len = symbol_len(text);
+    let rest = text[len..].trim_start().strip_prefix('=')?;
+    (len > 0).then(|| (&text[..len], rest.trim()))
+}
+
+/// Splits off the first whitespace-delimited word.
+fn split_word(text: &str) -> (&str, &str) {
+    match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], text[i..].trim()),
+        None => (text, ""),
+    }
+}
+
+/// Splits directive arguments on commas outside quotes.
+fn split_args(text: &str) -> Result<Vec<&str>, AsmErrorKind> {
+    let mut args = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (None, ',') => {
+                args.push(text[start..i].trim());
+                start = i + 1;
+            }
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            _ => {}
+        }
+    }
+    args.push(text[start..].trim());
+    if args.iter().any(|a| a.is_empty()) {
+        return Err(AsmErrorKind::Syntax("empty directive argument".to_string()));
+    }
+    Ok(args)
+}
+
+fn string_literal(item: &str) -> Option<&str> {
+    item.strip_prefix('"')?.strip_suffix('"')
+}
+
+fn to_byte(value: i64) -> Result<u8, AsmErrorKind> {
+    if (-0x80..=0xFF).contains(&value) {
+        Ok(value as u8)
+    } else {
+        Err(AsmErrorKind::OutOfRange(value))
+    }
+}
+
+fn to_u16(value: i64) -> Result<u16, AsmErrorKind> {
+    u16::try_from(value).map_err(|_| AsmErrorKind::OutOfRange(value))
+}
+
+/// Evaluates an expression; `pc` is the value of `*`.
+fn eval(text: &str, symbols: &HashMap<&str, u16>, pc: u16) -> Result<i64, AsmErrorKind> {
+    let mut parser = ExprParser {
+        rest: text.trim(),
+        symbols,
+        pc,
+    };
+    let value = parser.sum()?;
+    if !parser.rest.is_empty() {
+        return Err(AsmErrorKind::Syntax(format!(
+            "unexpected `{}`",
+            parser.rest
+        )));
+    }
+    Ok(value)
+}
+
+struct ExprParser<'s, 'a> {
+    rest: &'s str,
+    symbols: &'s HashMap<&'a str, u16>,
+    pc: u16,
+}
+
+impl ExprParser<'_, '_> {
+    fn sum(&mut self) -> Result<i64, AsmErrorKind> {
+        let mut value = self.unary()?;
+        loop {
+            self.rest = self.rest.trim_start();
+            if let Some(rest) = self.rest.strip_prefix('+') {
+                self.rest = rest;
+                value += self.unary()?;
+            } else if let Some(rest) = self.rest.strip_prefix('-') {
+                self.rest = rest;
+                value -= self.unary()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<i64, AsmErrorKind> {
+        self.rest = self.rest.trim_start();
+        let Some(c) = self.rest.chars().next() else {
+            return Err(AsmErrorKind::Syntax("missing value".to_string()));
+        };
+        match c {
+            '<' | '>' | '-' => {
+                self.rest = &self.rest[1..];
+                let v = self.unary()?;
+                Ok(match c {
+                    '<' => v & 0xFF,
+                    '>' => (v >> 8) & 0xFF,
+                    _ => -v,
+                })
+            }
+            _ => self.atom(),
+        }
+    }
+
+    fn atom(&mut self) -> Result<i64, AsmErrorKind> {
+        let text = self.rest;
+        let syntax = || AsmErrorKind::Syntax(format!("invalid value `{text}`"));
+
+        if let Some(rest) = text.strip_prefix('*') {
+            self.rest = rest;
+            return Ok(self.pc as i64);
+        }
+        if let Some(rest) = text.strip_prefix('\'') {
+            let mut chars = rest.chars();
+            let (Some(c), Some('\'')) = (chars.next(), chars.next()) else {
+                return Err(syntax());
+            };
+            self.rest = chars.as_str();
+            return Ok(c as i64);
+        }
+
+        let (radix, digits) = if let Some(rest) = text.strip_prefix('$') {
+            (16, rest)
+        } else if let Some(rest) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+            (16, rest)
+        } else if let Some(rest) = text.strip_prefix('%') {
+            (2, rest)
+        } else if text.starts_with(|c: char| c.is_ascii_digit()) {
+            (10, text)
+        } else {
+            let len = symbol_len(text);
+            if len == 0 {
+                return Err(syntax());
+            }
+            let name = &text[..len];
+            self.rest = &text[len..];
+            return self
+                .symbols
+                .get(name)
+                .map(|&v| v as i64)
+                .ok_or_else(|| AsmErrorKind::UndefinedSymbol(name.to_string()));
+        };
+
+        let len = digits
+            .find(|c: char| !c.is_digit(radix))
+            .unwrap_or(digits.len());
+        let value = i64::from_str_radix(&digits[..len], radix).map_err(|_| syntax())?;
+        self.rest = &digits[len..];
+        Ok(value)
+    }
+}
@@ -0,0 +1,326 @@
+//! Programmable sound generator.
+//!
+//! Like the display, the sound chip keeps its registers in ordinary memory.
+//! It has two square channels and a noise channel. At the end of every frame
+//! [`Vm::run_frame`] synthesizes that frame's audio straight at the host
+//! sample rate, from the register values at that point, and hands it to the
+//! audio callback as signed 16-bit mono samples, and to the
+//! [capture](Vm::start_audio_capture) if one is running. Nothing is
+//! synthesized while neither is. The callback's samples are resampled to
+//! the host speed a [`Throttle`](crate::throttle::Throttle) sets.
+//!
+//! | Address         | Contents                                             |
+//! | --------------- | ---------------------------------------------------- |
+//! | 0x2600–0x2601   | square 1 period, little-endian                       |
+//! | 0x2602          | square 1 control: bits 0-3 volume, bits 4-5 duty     |
+//! | 0x2603–0x2605   | square 2, laid out like square 1                     |
+//! | 0x2606          | noise period                                         |
+//! | 0x2607          | noise control: bits 0-3 volume                       |
+//! | 0x2608          | channel enable: bit 0 square 1, 1 square 2, 2 noise  |
+//!
+//! A square channel with period `p` repeats every `16 * (p + 1)` CPU cycles,
+//! high for 12.5%, 25%, 50% or 75% of it depending on the duty setting. The
+//! noise channel clocks a 15-bit LFSR every `16 * (p + 1)` cycles.
+//!
+//! The host can mute channels or turn them down on the [`Mixer`] to hear
+//! one in isolation. It is applied as samples are mixed, after the enable
+//! register, so the callback, the capture and audio events all hear it, and
+//! the program cannot see it:
+//!
+//! ```
+//! # use emulator::{audio::Channel, Vm};
+//! let mut vm = Vm::new();
+//! vm.audio_mixer_mut().solo(Channel::Noise);
+//! vm.audio_mixer_mut().set_volume(Channel::Noise, 0.5);
+//! assert!(vm.audio_mixer().is_muted(Channel::Square1));
+//! ```
+
+use crate::events::Event;
+use crate::throttle::Playback;
+use crate::vm::{FRAME_RATE, Vm};
+use crate::wav::AudioCapture;
+
+/// Square 1 period, low byte.
+pub const SQUARE1_PERIOD: u16 = 0x2600;
+/// Square 1 control register.
+pub const SQUARE1_CTRL: u16 = 0x2602;
+/// Square 2 period, low byte.
+pub const SQUARE2_PERIOD: u16 = 0x2603;
+/// Square 2 control register.
+pub const SQUARE2_CTRL: u16 = 0x2605;
+/// Noise period register.
+pub const NOISE_PERIOD: u16 = 0x2606;
+/// Noise control register.
+pub const NOISE_CTRL: u16 = 0x2607;
+/// Channel enable register.
+pub const AUDIO_ENABLE: u16 = 0x2608;
+
+/// `AUDIO_ENABLE` bit for square 1.
+pub const ENABLE_SQUARE1: u8 = 1 << 0;
+/// `AUDIO_ENABLE` bit for square 2.
+pub const ENABLE_SQUARE2: u8 = 1 << 1;
+/// `AUDIO_ENABLE` bit for the noise channel.
+pub const ENABLE_NOISE: u8 = 1 << 2;
+
+/// Host sample rate used until [`Vm::set_sample_rate`] is called.
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+/// Output amplitude of one volume step on one channel; three channels at
+/// full volume stay inside `i16`.
+const VOLUME_STEP: i16 = 682;
+/// High part of a square period for each duty setting, in eighths.
+const DUTY_EIGHTHS: [u64; 4] = [1, 2, 4, 6];
+
+/// A sound channel, as the [`Mixer`] names it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Channel {
+    Square1,
+    Square2,
+    Noise,
+}
+
+impl Channel {
+    /// Every channel, in register order.
+    pub const ALL: [Channel; 3] = [Channel::Square1, Channel::Square2, Channel::Noise];
+}
+
+/// Host-side mute and volume controls for each channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mixer {
+    muted: [bool; 3],
+    volume: [f32; 3],
+}
+
+impl Default for Mixer {
+    /// Every channel playing at full volume.
+    fn default() -> Self {
+        Self {
+            muted: [false; 3],
+            volume: [1.0; 3],
+        }
+    }
+}
+
+impl Mixer {
+    pub fn is_muted(&self, channel: Channel) -> bool {
+        self.muted[channel as usize]
+    }
+
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.muted[channel as usize] = muted;
+    }
+
+    /// Mutes every channel but `channel`, and unmutes that one.
+    pub fn solo(&mut self, channel: Channel) {
+        for other in Channel::ALL {
+            self.set_muted(other, other != channel);
+        }
+    }
+
+    /// The factor a channel's level is scaled by, from 0 to 1.
+    pub fn volume(&self, channel: Channel) -> f32 {
+        self.volume[channel as usize]
+    }
+
+    /// Scales a channel's level by `volume`, clamped to 0 to 1 so the mix
+    /// stays inside `i16`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `volume` is NaN.
+    pub fn set_volume(&mut self, channel: Channel, volume: f32) {
+        assert!(!volume.is_nan(), "volume must be a number");
+        self.volume[channel as usize] = volume.clamp(0.0, 1.0);
+    }
+
+    /// `level` as `channel` plays it.
+    fn apply(&self, channel: Channel, level: i16) -> i16 {
+        if self.is_muted(channel) {
+            0
+        } else {
+            (f32::from(level) * self.volume(channel)).round() as i16
+        }
+    }
+}
+
+/// Called with each frame's samples.
+pub(crate) type AudioCallback = Box<dyn FnMut(&[i16]) + Send>;
+
+/// Host-side synthesis state.
+pub(crate) struct Audio {
+    sample_rate: u32,
+    pub callback: Option<AudioCallback>,
+    pub capture: Option<AudioCapture>,
+    pub playback: Playback,
+    pub mixer: Mixer,
+    /// Synthesize each frame for [`Vm::frame_hash`] even with nothing
+    /// taking the samples.
+    pub hashing: bool,
+    /// Cycle the sample numbering starts from.
+    base: u64,
+    /// Index of the next sample, counted from `base`.
+    next: u64,
+    /// Cycle of the previous sample.
+    last: u64,
+    lfsr: u16,
+    /// Cycles since the LFSR was last clocked.
+    noise_clock: u64,
+    samples: Vec<i16>,
+}
+
+impl Default for Audio {
+    fn default() -> Self {
+        Self {
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            callback: None,
+            capture: None,
+            playback: Playback::default(),
+            mixer: Mixer::default(),
+            hashing: false,
+            base: 0,
+            next: 0,
+            last: 0,
+            lfsr: 1,
+            noise_clock: 0,
+            samples: Vec::new(),
+        }
+    }
+}
+
+impl Audio {
+    /// The samples synthesized for the last frame, before resampling.
+    pub(crate) fn samples(&self) -> &[i16] {
+        &self.samples
+    }
+
+    pub(crate) fn rebase(&mut self, cycle: u64) {
+        self.base = cycle;
+        self.next = 0;
+        self.last = cycle;
+    }
+
+    /// Cycle at which sample `next` is taken on a `clock_hz` clock.
+    fn sample_cycle(&self, clock_hz: u32) -> u64 {
+        self.base + self.next * clock_hz as u64 / self.sample_rate as u64
+    }
+
+    /// Synthesizes every sample due before cycle `end` into `self.samples`.
+    fn synthesize(&mut self, memory: &[u8], end: u64, clock_hz: u32) {
+        self.samples.clear();
+        let frame = (clock_hz / FRAME_RATE) as u64;
+        if end < self.base || end - self.base > 2 * frame {
+            // The clock jumped (reset, rewind, callback installed late):
+            // start over from the previous frame boundary.
+            self.rebase(end.saturating_sub(frame));
+        }
+
+        let enable = memory[AUDIO_ENABLE as usize];
+        let noise_period = 16 * (memory[NOISE_PERIOD as usize] as u64 + 1);
+        while self.sample_cycle(clock_hz) < end {
+            let t = self.sample_cycle(clock_hz);
+            self.noise_clock += t - self.last;
+            self.last = t;
+            while self.noise_clock >= noise_period {
+                self.noise_clock -= noise_period;
+                let feedback = (self.lfsr ^ self.lfsr >> 1) & 1;
+                self.lfsr = self.lfsr >> 1 | feedback << 14;
+            }
+
+            let mut mix = 0;
+            if enable & ENABLE_SQUARE1 != 0 {
+                let level = square(memory, SQUARE1_PERIOD, SQUARE1_CTRL, t);
+                mix += self.mixer.apply(Channel::Square1, level);
+            }
+            if enable & ENABLE_SQUARE2 != 0 {
+                let level = square(memory, SQUARE2_PERIOD, SQUARE2_CTRL, t);
+                mix += self.mixer.apply(Channel::Square2, level);
+            }
+            if enable & ENABLE_NOISE != 0 {
+                let level = volume(memory[NOISE_CTRL as usize]);
+                let level = if self.lfsr & 1 == 0 { level } else { -level };
+                mix += self.mixer.apply(Channel::Noise, level);
+            }
+            self.samples.push(mix);
+            self.next += 1;
+        }
+    }
+}
+
+fn volume(ctrl: u8) -> i16 {
+    (ctrl & 0x0F) as i16 * VOLUME_STEP
+}
+
+/// Level of a square channel at cycle `t`.
+fn square(memory: &[u8], period: u16, ctrl: u16, t: u64) -> i16 {
+    let at = period as usize;
+    let period = 16 * (u16::from_le_bytes([memory[at], memory[at + 1]]) as u64 + 1);
+    let ctrl = memory[ctrl as usize];
+    let high = period * DUTY_EIGHTHS[(ctrl >> 4 & 0b11) as usize] / 8;
+    let level = volume(ctrl);
+    if t % period < high { level } else { -level }
+}
+
+impl Vm {
+    /// Calls `callback` with each frame's audio, as mono samples at the rate
+    /// set by [`Vm::set_sample_rate`].
+    pub fn set_audio_callback(&mut self, callback: impl FnMut(&[i16]) + Send + 'static) {
+        self.audio.callback = Some(Box::new(callback));
+    }
+
+    /// Removes the audio callback.
+    pub fn clear_audio_callback(&mut self) {
+        self.audio.callback = None;
+    }
+
+    /// The channel controls applied to the audio delivered.
+    pub fn audio_mixer(&self) -> &Mixer {
+        &self.audio.mixer
+    }
+
+    pub fn audio_mixer_mut(&mut self) -> &mut Mixer {
+        &mut self.audio.mixer
+    }
+
+    /// Host sample rate audio is delivered at.
+    pub fn sample_rate(&self) -> u32 {
+        self.audio.sample_rate
+    }
+
+    /// Changes the host sample rate, taking effect from the next frame.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is 0.
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        assert!(rate > 0, "sample rate must be non-zero");
+        self.audio.sample_rate = rate;
+        self.audio.rebase(self.frame_cycle);
+    }
+
+    /// Synthesizes the frame that just ended and passes it to the callback,
+    /// the capture and the event queue.
+    pub(crate) fn flush_audio(&mut self) {
+        if self.audio.callback.is_none()
+            && self.audio.capture.is_none()
+            && !self.audio.hashing
+            && !self.events_enabled()
+        {
+            self.audio.samples.clear();
+            return;
+        }
+        let mut audio = std::mem::take(&mut self.audio);
+        audio.synthesize(self.memory(), self.frame_cycle, self.clock_hz);
+        if let Some(samples) = audio.playback.resample(&audio.samples) {
+            if let Some(callback) = &mut audio.callback {
+                callback(samples);
+            }
+            if self.events_enabled() {
+                self.queue_event(Event::Audio(samples.to_vec()));
+            }
+        }
+        if let Some(capture) = &mut audio.capture {
+            capture.write(&audio.samples);
+        }
+        self.audio = audio;
+    }
+}
@@ -0,0 +1,172 @@
+//! Kernel state export and switching backends mid-run.
+//!
+//! The CPU runs on one of two kernels: the C kernel, or the Rust core in
+//! [`rvm8_core`]. Both export their complete state, registers,
+//! counters, page tables and memory, in one portable layout through
+//! `rvm8_export_state` and read it back through `rvm8_import_state`, so a
+//! machine can move from one to the other while it runs. [`Vm::set_backend`]
+//! does that, which helps chase a bug down to one core: run to just before
+//! it on the fast one, then switch and step through the other.
+//!
+//! Which backends a build has depends on its features. The default build
+//! only has [`Backend::C`] and `pure-rust` only [`Backend::Rust`]; with
+//! `rust-core`, which `difftest` implies, it links both and a machine can
+//! switch back and forth:
+//!
+//! ```no_run
+//! # use emulator::{backend::Backend, Vm};
+//! let mut vm = Vm::new();
+//! vm.run_cycles(1_000_000).unwrap();
+//! vm.set_backend(Backend::Rust).unwrap();
+//! vm.step().unwrap();
+//! vm.set_backend(Backend::C).unwrap();
+//! ```
+//!
+//! [`Vm::export_kernel_state`] hands the bytes to the host, to move them to
+//! another process or another build. Only the kernel's state is in them:
+//! devices, the frame counter and everything else the host keeps are not,
+//! so a whole machine travels in a [`Snapshot`](crate::Snapshot) instead.
+
+use std::fmt;
+
+use crate::ffi::{RVM_OK, RVM_STATE_SIZE};
+use crate::vm::Vm;
+
+/// A kernel the CPU can run on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Backend {
+    /// The C kernel compiled by `build.rs`.
+    C,
+    /// The Rust core in [`rvm8_core`].
+    Rust,
+}
+
+impl Backend {
+    /// The backend a new [`Vm`] runs on: the C kernel when the build has
+    /// it.
+    pub const DEFAULT: Self = if cfg!(c_kernel) { Self::C } else { Self::Rust };
+
+    /// Whether this build has the backend.
+    pub const fn is_available(self) -> bool {
+        match self {
+            Self::C => cfg!(c_kernel),
+            Self::Rust => cfg!(rust_core),
+        }
+    }
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::C => "C kernel",
+            Self::Rust => "Rust core",
+        })
+    }
+}
+
+/// Why a kernel state could not be taken on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendError {
+    /// The build does not have the backend.
+    Unavailable(Backend),
+    /// The kernel refused the bytes: they are not an exported state, or one
+    /// of another layout version.
+    BadState,
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unavailable(backend) => write!(f, "this build has no {backend}"),
+            Self::BadState => write!(f, "not a kernel state this build can import"),
+        }
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+/// Calls the kernel function `$name` of the backend `$backend`. Builds
+/// with a single backend always call [`ffi`](crate::ffi), which is it.
+macro_rules! kernel {
+    ($backend:expr, $name:ident($($arg:expr),* $(,)?)) => {
+        match $backend {
+            #[cfg(all(c_kernel, rust_core))]
+            $crate::backend::Backend::Rust => $crate::cpu::$name($($arg),*),
+            _ => $crate::ffi::$name($($arg),*),
+        }
+    };
+}
+pub(crate) use kernel;
+
+impl Vm {
+    /// The backend the CPU is running on.
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// Moves the CPU to `backend`, exporting its state from the current one
+    /// and importing it into the other between two instructions. Execution
+    /// carries on exactly where it was; hooks, devices and debugger state
+    /// are the host's and stay in place.
+    pub fn set_backend(&mut self, backend: Backend) -> Result<(), BackendError> {
+        if !backend.is_available() {
+            return Err(BackendError::Unavailable(backend));
+        }
+        if backend == self.backend {
+            return Ok(());
+        }
+        let state = self.export_kernel_state();
+        let previous = std::mem::replace(&mut self.backend, backend);
+        self.load_kernel_state(&state)
+            .inspect_err(|_| self.backend = previous)
+    }
+
+    /// The kernel's state in the portable layout, [`RVM_STATE_SIZE`]
+    /// bytes: registers, cycle and execution counters, interrupt state,
+    /// the ROM, mirror, wait-state and dirty page tables, and the memory.
+    pub fn export_kernel_state(&self) -> Vec<u8> {
+        let mut state = vec![0; RVM_STATE_SIZE];
+        // SAFETY: `self.cpu` was initialized by `cpu_init` in `Vm::new` and
+        // `state` holds the bytes the call writes.
+        unsafe {
+            kernel!(
+                self.backend,
+                rvm8_export_state(&*self.cpu, state.as_mut_ptr(), state.len())
+            )
+        };
+        state
+    }
+
+    /// Replaces the kernel's state with one some backend exported, leaving
+    /// the host's alone: devices, hooks and the frame counter carry on as
+    /// they were. The next [paged snapshot](crate::paged) copies every page
+    /// afresh, and the [clock](Vm::clock) carries on from where it was.
+    pub fn import_kernel_state(&mut self, state: &[u8]) -> Result<(), BackendError> {
+        let clock = self.clock();
+        self.load_kernel_state(state)?;
+        self.scheduler.rebase(clock, self.cpu.cycles);
+        self.cpu.dirty_pages = [1; 256];
+        Ok(())
+    }
+
+    fn load_kernel_state(&mut self, state: &[u8]) -> Result<(), BackendError> {
+        // SAFETY: see `Vm::export_kernel_state`; the kernel reads at most
+        // `state.len()` bytes.
+        let status = unsafe {
+            kernel!(
+                self.backend,
+                rvm8_import_state(&mut *self.cpu, state.as_ptr(), state.len())
+            )
+        };
+        if status != RVM_OK {
+            return Err(BackendError::BadState);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,408 @@
+//! Interactive debugger for RVM-8 ROMs.
+//!
+//! ```text
+//! rvm8-dbg <rom> [--symbols <map file>] [--plain]
+//! ```
+//!
+//! Loads the ROM, resets the machine and shows the registers, the code
+//! around the PC, a memory hexdump and the breakpoints, redrawn after every
+//! command. Commands are read a line at a time; an empty line repeats the
+//! previous one. `h` lists them.
+//!
+//! Built with the `tui` feature, on a terminal and without `--plain`, the
+//! same views are panes of a full-screen interface driven by keys; `:`
+//! still takes any of the commands.
+
+#[cfg(feature = "tui")]
+mod tui;
+
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::process::ExitCode;
+
+use emulator::{
+    Condition, ResetKind, Rom, StopReason, SymbolTable, Vm, VmError, WatchKind, memory,
+};
+
+const HELP: &str = "\
+  s [n]         step one or n instructions
+  c             continue to a breakpoint, watchpoint or error
+  f             run to the end of the frame
+  b <addr>      toggle a breakpoint
+  b <addr> if <condition>
+                break only when the condition holds, e.g. A == $3F && [$2000] != 0
+  w <addr>      toggle a write watchpoint
+  m <addr>      show memory from addr
+  l <addr>[-<end>] <name>
+                label an address or a region, shown wherever it is named
+  r             reset
+  q             quit
+addresses are hex ($C000, 0xC000 or C000) or symbol names";
+
+/// Instructions listed from the PC.
+const CODE_LINES: usize = 10;
+/// Hexdump rows of 16 bytes.
+const MEMORY_ROWS: u16 = 8;
+
+struct Debugger {
+    vm: Vm,
+    symbols: SymbolTable,
+    /// First address of the hexdump.
+    memory: u16,
+    /// Result of the last command.
+    status: String,
+    /// Clear the screen and highlight, not when output is piped.
+    ansi: bool,
+}
+
+/// A line of the code listing.
+struct CodeLine {
+    /// The instruction's address, `None` for a label.
+    addr: Option<u16>,
+    text: String,
+}
+
+/// What the command loop should do next.
+enum Flow {
+    Continue,
+    Quit,
+}
+
+impl Debugger {
+    fn address(&self, arg: Option<&str>) -> Result<u16, String> {
+        let arg = arg.ok_or("missing address")?;
+        if let Some(addr) = self.symbols.address(arg) {
+            return Ok(addr);
+        }
+        let digits = arg
+            .strip_prefix('$')
+            .or_else(|| arg.strip_prefix("0x"))
+            .unwrap_or(arg);
+        u16::from_str_radix(digits, 16).map_err(|_| format!("bad address `{arg}`"))
+    }
+
+    /// Adds `name` for an address or an `addr-end` region.
+    fn label(&mut self, range: &str, name: &str) -> String {
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (self.address(Some(start)), self.address(Some(end))),
+            None => (self.address(Some(range)), self.address(Some(range))),
+        };
+        let (start, end) = match (start, end) {
+            (Ok(start), Ok(end)) => (start, end),
+            (Err(err), _) | (_, Err(err)) => return err,
+        };
+        let (inserted, what) = match end.checked_sub(start) {
+            None => return format!("bad region `{range}`"),
+            Some(0) => (self.symbols.insert(name, start), format!("${start:04X}")),
+            Some(_) => (
+                self.symbols.insert_region(name, start..=end),
+                format!("${start:04X}-${end:04X}"),
+            ),
+        };
+        if inserted {
+            format!("labelled {what} as {name}")
+        } else {
+            format!("`{name}` is already a symbol")
+        }
+    }
+
+    fn stopped(&mut self, result: Result<Option<StopReason>, VmError>) {
+        self.status = match result {
+            Ok(Some(reason)) => reason.with_symbols(&self.symbols).to_string(),
+            Ok(None) => String::new(),
+            Err(err) => err.to_string(),
+        };
+    }
+
+    fn command(&mut self, line: &str) -> Flow {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return Flow::Continue;
+        };
+        let arg = words.next();
+        self.status.clear();
+        match command {
+            "s" => {
+                let count = match arg.map(str::parse::<u32>) {
+                    None => Ok(1),
+                    Some(Ok(count)) => Ok(count),
+                    Some(Err(_)) => Err(format!("bad count `{}`", arg.unwrap_or_default())),
+                };
+                match count {
+                    Ok(count) => {
+                        let result = (0..count).try_for_each(|_| self.vm.step());
+                        self.stopped(result.map(|()| None));
+                    }
+                    Err(err) => self.status = err,
+                }
+            }
+            "c" => {
+                let result = self.vm.run_until_break().map(Some);
+                self.stopped(result);
+            }
+            "f" => {
+                let result = self.vm.run_frame().map(|()| None);
+                self.stopped(result);
+                if self.status.is_empty() {
+                    self.status = format!("frame {}", self.vm.frame());
+                }
+            }
+            "b" => match self.address(arg) {
+                Ok(addr) => {
+                    let addr_name = self.symbols.describe(addr);
+                    self.status = match line.split_once(" if ") {
+                        Some((_, condition)) => {
+                            match Condition::parse_with_symbols(condition.trim(), &self.symbols) {
+                                Ok(condition) => {
+                                    let status =
+                                        format!("breakpoint set at {addr_name} if {condition}");
+                                    self.vm.add_conditional_breakpoint(addr, condition);
+                                    status
+                                }
+                                Err(err) => format!("bad condition: {err}"),
+                            }
+                        }
+                        None if self.vm.add_breakpoint(addr) => {
+                            format!("breakpoint set at {addr_name}")
+                        }
+                        None => {
+                            self.vm.remove_breakpoint(addr);
+                            format!("breakpoint cleared at {addr_name}")
+                        }
+                    };
+                }
+                Err(err) => self.status = err,
+            },
+            "w" => match self.address(arg) {
+                Ok(addr) => {
+                    let addr_name = self.symbols.describe(addr);
+                    self.status = if self.vm.add_watchpoint(addr..=addr, WatchKind::Write) {
+                        format!("watching writes to {addr_name}")
+                    } else {
+                        self.vm.remove_watchpoint(addr..=addr, WatchKind::Write);
+                        format!("stopped watching {addr_name}")
+                    };
+                }
+                Err(err) => self.status = err,
+            },
+            "m" => match self.address(arg) {
+                Ok(addr) => self.memory = addr & !0xF,
+                Err(err) => self.status = err,
+            },
+            "l" => match (arg, words.next()) {
+                (Some(range), Some(name)) => self.status = self.label(range, name),
+                _ => self.status = "usage: l <addr>[-<end>] <name>".into(),
+            },
+            "r" => {
+                self.vm.reset(ResetKind::Soft);
+                self.status = "reset".into();
+            }
+            "h" | "?" => self.status = HELP.into(),
+            "q" => return Flow::Quit,
+            _ => self.status = format!("unknown command `{command}`; h for help"),
+        }
+        Flow::Continue
+    }
+
+    fn registers(&self) -> String {
+        let regs = self.vm.registers();
+        format!(
+            "A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PC:{:04X}  CYC:{}  frame {}",
+            regs.a,
+            regs.x,
+            regs.y,
+            regs.flags,
+            regs.sp,
+            regs.pc,
+            self.vm.cycles(),
+            self.vm.frame()
+        )
+    }
+
+    /// `count` instructions from the PC, each after its label if it has one.
+    fn code(&self, count: usize) -> Vec<CodeLine> {
+        let pc = self.vm.registers().pc;
+        let mut lines = Vec::new();
+        let mut addr = pc;
+        for _ in 0..count {
+            if let Some(name) = self.symbols.name(addr) {
+                lines.push(CodeLine {
+                    addr: None,
+                    text: format!("            {name}:"),
+                });
+            }
+            let instr = self.vm.disassemble(addr);
+            let hex: Vec<String> = (0..u16::from(instr.size))
+                .map(|i| format!("{:02X}", self.vm.read(addr.wrapping_add(i))))
+                .collect();
+            let at_pc = if addr == pc { '>' } else { ' ' };
+            let bp = if self.vm.breakpoints().contains(addr) {
+                '*'
+            } else {
+                ' '
+            };
+            lines.push(CodeLine {
+                addr: Some(addr),
+                text: format!(
+                    "{at_pc}{bp} {addr:04X}  {:<8}  {}",
+                    hex.join(" "),
+                    instr.with_symbols(&self.symbols)
+                ),
+            });
+            addr = addr.wrapping_add(u16::from(instr.size));
+        }
+        lines
+    }
+
+    /// `rows` rows of hexdump from the first address shown.
+    fn hexdump(&self, rows: u16) -> String {
+        let bytes: Vec<u8> = (0..rows * 16)
+            .map(|i| self.vm.read(self.memory.wrapping_add(i)))
+            .collect();
+        memory::hexdump(self.memory, &bytes, Some(&self.symbols))
+    }
+
+    /// The breakpoints and watchpoints, a line each.
+    fn breakpoints(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for addr in self.vm.breakpoints().iter() {
+            let mut line = format!("{addr:04X}  {}", self.symbols.describe(addr));
+            if let Some(condition) = self.vm.breakpoints().condition(addr) {
+                line += &format!(" if {condition}");
+            }
+            lines.push(line);
+        }
+        for watch in self.vm.watchpoints() {
+            let addr = *watch.range.start();
+            lines.push(format!(
+                "{addr:04X}  {} (write)",
+                self.symbols.describe(addr)
+            ));
+        }
+        lines
+    }
+
+    fn draw(&self, out: &mut impl Write) -> io::Result<()> {
+        if self.ansi {
+            write!(out, "\x1b[2J\x1b[H")?;
+        }
+        writeln!(out, "{}", self.registers())?;
+
+        writeln!(out, "\n-- code")?;
+        let pc = self.vm.registers().pc;
+        for line in self.code(CODE_LINES) {
+            if self.ansi && line.addr == Some(pc) {
+                writeln!(out, "\x1b[7m{}\x1b[0m", line.text)?;
+            } else {
+                writeln!(out, "{}", line.text)?;
+            }
+        }
+
+        writeln!(out, "\n-- memory")?;
+        writeln!(out, "{}", self.hexdump(MEMORY_ROWS))?;
+
+        writeln!(out, "\n-- breakpoints")?;
+        let breakpoints = self.breakpoints();
+        if breakpoints.is_empty() {
+            writeln!(out, "(none)")?;
+        }
+        for line in breakpoints {
+            writeln!(out, "{line}")?;
+        }
+
+        if !self.status.is_empty() {
+            writeln!(out, "\n{}", self.status)?;
+        }
+        write!(out, "rvm8> ")?;
+        out.flush()
+    }
+}
+
+fn usage() -> ExitCode {
+    eprintln!("usage: rvm8-dbg <rom> [--symbols <map file>] [--plain]");
+    ExitCode::FAILURE
+}
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let (mut rom, mut symbols, mut plain) = (None, None, false);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--plain" => plain = true,
+            "--symbols" => match args.next() {
+                Some(path) => symbols = Some(path),
+                None => return usage(),
+            },
+            _ if rom.is_none() => rom = Some(arg),
+            _ => return usage(),
+        }
+    }
+    let Some(rom) = rom else {
+        return usage();
+    };
+    let rom = match Rom::from_file(&rom) {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            eprintln!("rvm8-dbg: {rom}: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let symbols = match symbols {
+        None => SymbolTable::new(),
+        Some(path) => match std::fs::read_to_string(&path) {
+            Ok(text) => match SymbolTable::parse(&text) {
+                Ok(table) => table,
+                Err(err) => {
+                    eprintln!("rvm8-dbg: {path}: {err}");
+                    return ExitCode::FAILURE;
+                }
+            },
+            Err(err) => {
+                eprintln!("rvm8-dbg: {path}: {err}");
+                return ExitCode::FAILURE;
+            }
+        },
+    };
+
+    let mut vm = Vm::new();
+    vm.load_rom(&rom)
+        .expect("a new machine has nothing on the mapper ports");
+    let mut debugger = Debugger {
+        vm,
+        symbols,
+        memory: 0,
+        status: "h for help".into(),
+        ansi: io::stdout().is_terminal(),
+    };
+    #[cfg(feature = "tui")]
+    if debugger.ansi && !plain {
+        return match tui::run(&mut debugger) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("rvm8-dbg: {err}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+    #[cfg(not(feature = "tui"))]
+    let _ = plain;
+    let mut out = io::stdout().lock();
+    let mut input = io::stdin().lock();
+    let (mut line, mut last) = (String::new(), String::new());
+    loop {
+        if debugger.draw(&mut out).is_err() {
+            return ExitCode::FAILURE;
+        }
+        line.clear();
+        match input.read_line(&mut line) {
+            Ok(0) => return ExitCode::SUCCESS,
+            Ok(_) => {}
+            Err(_) => return ExitCode::FAILURE,
+        }
+        if !line.trim().is_empty() {
+            last.clone_from(&line);
+        }
+        if let Flow::Quit = debugger.command(&last) {
+            return ExitCode::SUCCESS;
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! The full-screen frontend, on ratatui and crossterm.
+//!
+//! The registers sit along the top, the code from the PC on the left, the
+//! memory hexdump and the breakpoints on the right and the status, or the
+//! command being typed, at the bottom. Keys run the commands of the line
+//! interface:
+//!
+//! | Key             | Does                                              |
+//! | --------------- | ------------------------------------------------- |
+//! | `s`, F10        | step one instruction                              |
+//! | `c`, F5         | continue to a breakpoint, watchpoint or error     |
+//! | `f`             | run to the end of the frame                       |
+//! | Up, Down        | move the code cursor                              |
+//! | `b`, F9         | toggle a breakpoint at the cursor                 |
+//! | PgUp, PgDn      | scroll the memory pane                            |
+//! | `r`             | reset                                             |
+//! | `:`             | type a command, as at the line interface          |
+//! | `?`             | list the keys                                     |
+//! | `q`             | quit                                              |
+
+use std::io;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::{Debugger, Flow};
+
+const KEYS: &str = "\
+s/F10 step  c/F5 continue  f frame  Up/Down cursor  b/F9 breakpoint
+PgUp/PgDn memory  r reset  : command  ? keys  q quit";
+
+/// Status lines shown at most, with `h` output in mind.
+const STATUS_LINES: u16 = 16;
+
+struct Tui<'a> {
+    debugger: &'a mut Debugger,
+    /// Index of the selected instruction in the code pane.
+    cursor: usize,
+    /// The PC the cursor was placed for; it returns to the top once the
+    /// PC moves.
+    cursor_pc: u16,
+    /// The command being typed after `:`.
+    input: Option<String>,
+    /// Instructions the code pane fits, from the last draw.
+    code_lines: usize,
+}
+
+/// Runs the debugger full screen until `q`.
+pub fn run(debugger: &mut Debugger) -> io::Result<()> {
+    debugger.status = "? for keys".into();
+    let mut terminal = ratatui::init();
+    let mut tui = Tui {
+        debugger,
+        cursor: 0,
+        cursor_pc: 0,
+        input: None,
+        code_lines: 0,
+    };
+    let result = tui.run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+impl Tui<'_> {
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            if let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+                && let Flow::Quit = self.key(key)
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    fn key(&mut self, key: KeyEvent) -> Flow {
+        if let Some(input) = &mut self.input {
+            match key.code {
+                KeyCode::Char(c) => input.push(c),
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Esc => self.input = None,
+                KeyCode::Enter => {
+                    let line = self.input.take().unwrap_or_default();
+                    return self.debugger.command(&line);
+                }
+                _ => {}
+            }
+            return Flow::Continue;
+        }
+        match key.code {
+            KeyCode::Char('s') | KeyCode::F(10) => return self.debugger.command("s"),
+            KeyCode::Char('c') | KeyCode::F(5) => return self.debugger.command("c"),
+            KeyCode::Char('f') => return self.debugger.command("f"),
+            KeyCode::Char('r') => return self.debugger.command("r"),
+            KeyCode::Char('b') | KeyCode::F(9) => {
+                if let Some(addr) = self.selected() {
+                    return self.debugger.command(&format!("b {addr:04X}"));
+                }
+            }
+            KeyCode::Up => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Down => self.cursor = (self.cursor + 1).min(self.code_lines.saturating_sub(1)),
+            KeyCode::PageUp => self.debugger.memory = self.debugger.memory.wrapping_sub(0x80),
+            KeyCode::PageDown => self.debugger.memory = self.debugger.memory.wrapping_add(0x80),
+            KeyCode::Char(':') => self.input = Some(String::new()),
+            KeyCode::Char('?') => self.debugger.status = KEYS.into(),
+            KeyCode::Char('q') => return Flow::Quit,
+            _ => {}
+        }
+        Flow::Continue
+    }
+
+    /// The address of the instruction under the cursor.
+    fn selected(&self) -> Option<u16> {
+        self.debugger
+            .code(self.cursor + 1)
+            .iter()
+            .filter_map(|line| line.addr)
+            .nth(self.cursor)
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let status_lines = match &self.input {
+            Some(_) => 1,
+            None => (self.debugger.status.lines().count() as u16).clamp(1, STATUS_LINES),
+        };
+        let [registers, main, status] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(0),
+            Constraint::Length(status_lines + 2),
+        ])
+        .areas(frame.area());
+        let [code, right] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(main);
+        let [memory, breakpoints] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(8)]).areas(right);
+
+        frame.render_widget(
+            Paragraph::new(self.debugger.registers()).block(Block::bordered().title("registers")),
+            registers,
+        );
+        self.draw_code(frame, code);
+        let rows = memory.height.saturating_sub(2);
+        frame.render_widget(
+            Paragraph::new(self.debugger.hexdump(rows)).block(Block::bordered().title("memory")),
+            memory,
+        );
+        let mut lines = self.debugger.breakpoints();
+        if lines.is_empty() {
+            lines.push("(none)".into());
+        }
+        frame.render_widget(
+            Paragraph::new(lines.join("\n")).block(Block::bordered().title("breakpoints")),
+            breakpoints,
+        );
+        let text = match &self.input {
+            Some(input) => format!(":{input}"),
+            None => self.debugger.status.clone(),
+        };
+        frame.render_widget(Paragraph::new(text).block(Block::bordered()), status);
+    }
+
+    fn draw_code(&mut self, frame: &mut Frame, area: Rect) {
+        let pc = self.debugger.vm.registers().pc;
+        if pc != self.cursor_pc {
+            self.cursor_pc = pc;
+            self.cursor = 0;
+        }
+        // Labels take lines too, so this may list a few more than fit.
+        self.code_lines = usize::from(area.height.saturating_sub(2));
+        self.cursor = self.cursor.min(self.code_lines.saturating_sub(1));
+        let selected = self.selected();
+        let lines: Vec<Line> = self
+            .debugger
+            .code(self.code_lines)
+            .into_iter()
+            .map(|line| {
+                let mut style = Style::new();
+                if line.addr == Some(pc) {
+                    style = style.add_modifier(Modifier::REVERSED);
+                }
+                if line.addr.is_some() && line.addr == selected {
+                    style = style.add_modifier(Modifier::BOLD | Modifier::UNDERLINED);
+                }
+                Line::styled(line.text, style)
+            })
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title("code")),
+            area,
+        );
+    }
+}
@@ -0,0 +1,619 @@
+//! Headless ROM runner and machine monitor.
+//!
+//! ```text
+//! rvm8 run <rom> [--cycles <n>] [--trace <file>|-] [--trace-format <format>]
+//!                [--symbols <map file>] [--exit-on-halt] [--exit-port <addr>] [--patch <file>]...
+//!                [--machine <file>] [--manifest <file>] [--files <dir>|<image>]
+//!                [--control <addr>] [--script <file>]
+//! rvm8 monitor <rom> [--symbols <map file>] [--patch <file>]...
+//!                [--machine <file>] [--manifest <file>] [--files <dir>|<image>]
+//!                [--listen <addr>]
+//! rvm8 trace dump <trace> [--pc <addr>[-<addr>]] [--mnemonic <name>] [--symbols <map file>]
+//! rvm8 trace filter <trace> <output>|- [--pc <addr>[-<addr>]] [--mnemonic <name>]
+//! ```
+//!
+//! Runs the ROM with no display or audio until it exits, so test ROMs can
+//! drive CI. The exit status says how the run ended:
+//!
+//! * the value the program wrote to the exit port (default `$27FF`), as
+//!   soon as it writes one;
+//! * 0 when the program halts on an illegal opcode and `--exit-on-halt` is
+//!   given; without it the first one is reported and execution carries
+//!   on past it;
+//! * 1 when the program traps on unmapped memory, which only a `--machine`
+//!   description can set up;
+//! * 124 when the cycle limit runs out first;
+//! * 2 for a bad command line or a ROM that fails to load, or that
+//!   `--manifest` does not list with its digest.
+//!
+//! Each `--patch` is an IPS file or an `addr = value` list (see
+//! [`emulator::patches`]) applied to the ROM before it loads. `--machine`
+//! runs it on the board a machine description declares (see
+//! [`emulator::config`]) instead of the stock one. `--manifest` checks the
+//! ROM against a `sha256sum` list of known builds (see
+//! [`emulator::manifest`]) before any patch applies. `--files` maps a
+//! [file service](emulator::files) at its conventional slot, serving the
+//! files in a directory or else a disk image. With `--trace`, the
+//! ROM's checksums go to stderr first, to tell which build a trace is of.
+//! `--trace-format binary` writes the trace as [`BinarySink`] records
+//! instead of text lines.
+//!
+//! `rvm8 trace dump` prints a binary trace as text lines, and
+//! `rvm8 trace filter` writes the records it keeps to another binary
+//! trace. Both keep only the instructions at PCs in the `--pc` range, or
+//! at the one address given, and with the `--mnemonic` given, when given.
+//!
+//! The ROM can also be an ELF executable, as llvm-mos and similar
+//! toolchains link (see [`emulator::elf`]). It starts at its entry point
+//! rather than the reset vector, and its symbols name addresses in the
+//! trace unless `--symbols` is given. `--patch` and `--manifest` do not
+//! apply to it.
+//!
+//! So can an Intel HEX or S-record file, named `.hex`, `.ihx`, `.srec`,
+//! `.s19`, `.s28`, `.s37` or `.mot` (see [`emulator::hexfile`]). Its
+//! records are stored over empty memory, and it starts at the start address
+//! it gives or else through the reset vector.
+//!
+//! `--control` serves the
+#![cfg_attr(feature = "remote", doc = "[remote control](emulator::remote)")]
+#![cfg_attr(not(feature = "remote"), doc = "remote control")]
+//! protocol while the ROM runs, on a TCP `host:port` or, on Unix, a socket
+//! path. It needs the `remote` feature. A client's `pause` holds the run,
+//! cycle limit included, until it resumes.
+//!
+//! `--script` loads a
+#![cfg_attr(feature = "script", doc = "[Rhai script](emulator::script)")]
+#![cfg_attr(not(feature = "script"), doc = "Rhai script")]
+//! before the ROM starts, whose callbacks then run as the ROM does. It
+//! needs the `script` feature. The run stops with status 2 on the first
+//! error a callback raises.
+//!
+//! `rvm8 monitor` loads the ROM the same way and hands the machine to the
+//! [machine monitor](emulator::monitor) on stdin and stdout, or with
+//! `--listen` on the first TCP connection to `host:port`, for a terminal
+//! behind a serial bridge. It exits with 0 once the monitor quits.
+//!
+//! The exit port is an ordinary write hook, so it can sit anywhere,
+//! including over ROM, where the write itself is still dropped.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use emulator::elf::Elf;
+use emulator::files::{FILE_PORTS, FileService};
+use emulator::hexfile::Image;
+use emulator::manifest::Manifest;
+use emulator::patches::{self, Ips};
+#[cfg(feature = "remote")]
+use emulator::remote::RemoteControl;
+use emulator::trace::{BinarySink, TextSink, TraceReader};
+use emulator::{MachineConfig, ResetKind, Rom, SymbolTable, TraceConfig, TraceSink, Vm, VmError};
+
+const USAGE: &str = "\
+usage: rvm8 run <rom, ELF, HEX or S-record file> [options]
+  --cycles <n>       stop with status 124 after n cycles
+  --trace <file>     write an instruction trace to file, or stdout for -
+  --trace-format <f> write the trace as text (the default) or binary
+  --symbols <file>   name addresses in the trace from a symbol map
+  --exit-on-halt     stop with status 0 on an illegal opcode
+  --exit-port <addr> exit with the value written here (default $27FF)
+  --patch <file>     patch the ROM with an IPS file or addr = value list
+  --machine <file>   run on the board this machine description declares
+  --manifest <file>  refuse a ROM this sha256sum list does not match
+  --files <path>     serve the files in a directory, or a disk image
+  --control <addr>   serve remote control on host:port or a socket path
+  --script <file>    run the callbacks a Rhai script registers
+usage: rvm8 monitor <rom, ELF, HEX or S-record file> [options]
+  --symbols, --patch, --machine, --manifest and --files as for run
+  --listen <addr>    serve the monitor on host:port instead of stdin
+usage: rvm8 trace dump <binary trace> [options]
+       rvm8 trace filter <binary trace> <output file, or - for stdout> [options]
+  --pc <a>[-<b>]     keep instructions at PCs a to b, or at a
+  --mnemonic <name>  keep instructions with this mnemonic
+  --symbols <file>   name addresses in dumped lines from a symbol map";
+
+const DEFAULT_EXIT_PORT: u16 = 0x27FF;
+/// Status for a run stopped by `--cycles`, as `timeout` uses.
+const TIMED_OUT: u8 = 124;
+const BUS_FAULT: u8 = 1;
+const USAGE_ERROR: u8 = 2;
+/// Instructions run between polls of the remote control.
+#[cfg(feature = "remote")]
+const CONTROL_POLL: u32 = 4096;
+
+#[derive(PartialEq, Eq)]
+enum Mode {
+    Run,
+    Monitor,
+}
+
+struct Options {
+    mode: Mode,
+    rom: String,
+    cycles: Option<u64>,
+    trace: Option<String>,
+    binary_trace: bool,
+    symbols: Option<String>,
+    exit_on_halt: bool,
+    exit_port: u16,
+    patches: Vec<String>,
+    machine: Option<String>,
+    manifest: Option<String>,
+    files: Option<String>,
+    control: Option<String>,
+    script: Option<String>,
+    listen: Option<String>,
+}
+
+fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix('$').or_else(|| text.strip_prefix("0x")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mode = match args.next().as_deref() {
+        Some("run") => Mode::Run,
+        Some("monitor") => Mode::Monitor,
+        _ => return Err("expected the `run`, `monitor` or `trace` command".into()),
+    };
+    let mut options = Options {
+        mode,
+        rom: String::new(),
+        cycles: None,
+        trace: None,
+        binary_trace: false,
+        symbols: None,
+        exit_on_halt: false,
+        exit_port: DEFAULT_EXIT_PORT,
+        patches: Vec::new(),
+        machine: None,
+        manifest: None,
+        files: None,
+        control: None,
+        script: None,
+        listen: None,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--cycles" | "--trace" | "--trace-format" | "--exit-on-halt" | "--exit-port"
+            | "--control" | "--script"
+                if options.mode == Mode::Monitor =>
+            {
+                return Err(format!("`{arg}` is not a monitor option"));
+            }
+            "--listen" if options.mode == Mode::Monitor => options.listen = Some(value()?),
+            "--cycles" => {
+                let value = value()?;
+                options.cycles =
+                    Some(parse_number(&value).ok_or(format!("bad cycle count `{value}`"))?);
+            }
+            "--trace" => options.trace = Some(value()?),
+            "--trace-format" => {
+                options.binary_trace = match value()?.as_str() {
+                    "text" => false,
+                    "binary" => true,
+                    format => return Err(format!("unknown trace format `{format}`")),
+                };
+            }
+            "--symbols" => options.symbols = Some(value()?),
+            "--exit-on-halt" => options.exit_on_halt = true,
+            "--patch" => options.patches.push(value()?),
+            "--machine" => options.machine = Some(value()?),
+            "--manifest" => options.manifest = Some(value()?),
+            "--files" => options.files = Some(value()?),
+            "--control" if cfg!(feature = "remote") => options.control = Some(value()?),
+            "--control" => return Err("--control needs the `remote` feature".into()),
+            "--script" if cfg!(feature = "script") => options.script = Some(value()?),
+            "--script" => return Err("--script needs the `script` feature".into()),
+            "--exit-port" => options.exit_port = parse_address(&value()?)?,
+            _ if arg.starts_with("--") => return Err(format!("unknown option `{arg}`")),
+            _ if options.rom.is_empty() => options.rom = arg,
+            _ => return Err(format!("unexpected argument `{arg}`")),
+        }
+    }
+    if options.rom.is_empty() {
+        return Err("missing ROM".into());
+    }
+    Ok(options)
+}
+
+/// What `rvm8 trace` was given.
+struct TraceTool {
+    /// Writes records to this binary trace rather than dumping them.
+    filter: Option<String>,
+    input: String,
+    pcs: RangeInclusive<u16>,
+    mnemonic: Option<String>,
+    symbols: Option<String>,
+}
+
+fn parse_address(text: &str) -> Result<u16, String> {
+    parse_number(text)
+        .and_then(|addr| u16::try_from(addr).ok())
+        .ok_or(format!("bad address `{text}`"))
+}
+
+fn parse_trace_args(mut args: impl Iterator<Item = String>) -> Result<TraceTool, String> {
+    let filter = match args.next().as_deref() {
+        Some("dump") => false,
+        Some("filter") => true,
+        _ => return Err("expected `trace dump` or `trace filter`".into()),
+    };
+    let mut tool = TraceTool {
+        filter: None,
+        input: String::new(),
+        pcs: 0x0000..=0xFFFF,
+        mnemonic: None,
+        symbols: None,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--pc" => {
+                let value = value()?;
+                tool.pcs = match value.split_once('-') {
+                    Some((start, end)) => parse_address(start)?..=parse_address(end)?,
+                    None => parse_address(&value)?..=parse_address(&value)?,
+                };
+            }
+            "--mnemonic" => tool.mnemonic = Some(value()?.to_ascii_uppercase()),
+            "--symbols" if !filter => tool.symbols = Some(value()?),
+            _ if arg.starts_with("--") => return Err(format!("unknown option `{arg}`")),
+            _ if tool.input.is_empty() => tool.input = arg,
+            _ if filter && tool.filter.is_none() => tool.filter = Some(arg),
+            _ => return Err(format!("unexpected argument `{arg}`")),
+        }
+    }
+    if tool.input.is_empty() {
+        return Err("missing trace".into());
+    }
+    if filter && tool.filter.is_none() {
+        return Err("missing output".into());
+    }
+    Ok(tool)
+}
+
+fn output(path: &str) -> Result<Box<dyn Write + Send>, String> {
+    if path == "-" {
+        return Ok(Box::new(io::stdout()));
+    }
+    let file = File::create(path).map_err(|err| format!("{path}: {err}"))?;
+    Ok(Box::new(BufWriter::new(file)))
+}
+
+fn trace_tool(tool: &TraceTool) -> Result<u8, String> {
+    let path = &tool.input;
+    let file = File::open(path).map_err(|err| format!("{path}: {err}"))?;
+    let reader = TraceReader::new(BufReader::new(file)).map_err(|err| format!("{path}: {err}"))?;
+    let symbols = match &tool.symbols {
+        Some(path) => {
+            let text = std::fs::read_to_string(path).map_err(|err| format!("{path}: {err}"))?;
+            Some(SymbolTable::parse(&text).map_err(|err| format!("{path}: {err}"))?)
+        }
+        None => None,
+    };
+    let mut sink: Box<dyn TraceSink> = match &tool.filter {
+        Some(out) => Box::new(BinarySink::new(output(out)?)),
+        None => {
+            let text = TextSink::new(BufWriter::new(io::stdout()));
+            Box::new(match symbols {
+                Some(symbols) => text.with_symbols(symbols),
+                None => text,
+            })
+        }
+    };
+    let written = |result: io::Result<()>| match result {
+        // `rvm8 trace dump ... | head` stops reading early.
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(false),
+        Err(err) => Err(format!(
+            "{}: {err}",
+            tool.filter.as_deref().unwrap_or("stdout")
+        )),
+        Ok(()) => Ok(true),
+    };
+    for record in reader {
+        let record = record.map_err(|err| format!("{path}: {err}"))?;
+        let kept = tool.pcs.contains(&record.pc)
+            && (tool.mnemonic.as_deref()).is_none_or(|name| record.instruction.mnemonic == name);
+        if kept && !written(sink.record(&record))? {
+            return Ok(0);
+        }
+    }
+    written(sink.finish())?;
+    Ok(0)
+}
+
+fn trace(options: &Options) -> Result<Option<TraceConfig>, String> {
+    let Some(path) = &options.trace else {
+        return Ok(None);
+    };
+    let writer = output(path)?;
+    let config = if options.binary_trace {
+        TraceConfig::sink(BinarySink::new(writer))
+    } else {
+        TraceConfig::writer(writer)
+    };
+    let Some(path) = &options.symbols else {
+        return Ok(Some(config));
+    };
+    let text = std::fs::read_to_string(path).map_err(|err| format!("{path}: {err}"))?;
+    let symbols = SymbolTable::parse(&text).map_err(|err| format!("{path}: {err}"))?;
+    Ok(Some(config.with_symbols(symbols)))
+}
+
+fn patch(rom: &mut Rom, path: &str) -> Result<(), String> {
+    let bytes = std::fs::read(path).map_err(|err| format!("{path}: {err}"))?;
+    let result = if bytes.starts_with(b"PATCH") {
+        Ips::parse(&bytes).and_then(|ips| rom.apply_ips(&ips))
+    } else {
+        let text = String::from_utf8_lossy(&bytes);
+        patches::parse_list(&text).and_then(|list| rom.patch(&list))
+    };
+    result.map_err(|err| format!("{path}: {err}"))
+}
+
+#[cfg(feature = "remote")]
+fn control(options: &Options) -> Result<Option<RemoteControl>, String> {
+    let Some(addr) = &options.control else {
+        return Ok(None);
+    };
+    let remote = if addr
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| port.parse::<u16>().is_ok())
+    {
+        RemoteControl::bind_tcp(addr.as_str())
+    } else {
+        #[cfg(unix)]
+        {
+            RemoteControl::bind_unix(addr)
+        }
+        #[cfg(not(unix))]
+        {
+            return Err(format!("{addr}: not a host:port address"));
+        }
+    };
+    remote.map(Some).map_err(|err| format!("{addr}: {err}"))
+}
+
+/// What `rvm8 run` was given.
+enum Program {
+    Rom(Rom),
+    Elf(Elf),
+    Image(Image),
+}
+
+/// Extensions of HEX and S-record files.
+const IMAGE_EXTENSIONS: [&str; 7] = ["hex", "ihx", "srec", "s19", "s28", "s37", "mot"];
+
+/// Fails if ROM options were given for a program of the `kind` named.
+fn no_rom_options(options: &Options, kind: &str) -> Result<(), String> {
+    if !options.patches.is_empty() || options.manifest.is_some() {
+        return Err(format!(
+            "{}: --patch and --manifest take a ROM, not {kind}",
+            options.rom
+        ));
+    }
+    Ok(())
+}
+
+fn program(options: &Options) -> Result<Program, String> {
+    let path = Path::new(&options.rom);
+    let read_error = |err| format!("{}: {err}", options.rom);
+    let is_image = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()));
+    if is_image {
+        no_rom_options(options, "a HEX or S-record file")?;
+        let text = std::fs::read_to_string(path).map_err(read_error)?;
+        let image = Image::parse(&text).map_err(|err| format!("{}: {err}", options.rom))?;
+        return Ok(Program::Image(image));
+    }
+    let bytes = std::fs::read(path).map_err(read_error)?;
+    if Elf::is_elf(&bytes) {
+        no_rom_options(options, "an ELF file")?;
+        let elf = Elf::parse(&bytes).map_err(|err| format!("{}: {err}", options.rom))?;
+        return Ok(Program::Elf(elf));
+    }
+    rom(options, &bytes).map(Program::Rom)
+}
+
+fn rom(options: &Options, bytes: &[u8]) -> Result<Rom, String> {
+    let mut rom = Rom::from_bytes(bytes).map_err(|err| format!("{}: {err}", options.rom))?;
+    if let Some(path) = &options.manifest {
+        let text = std::fs::read_to_string(path).map_err(|err| format!("{path}: {err}"))?;
+        let manifest = Manifest::parse(&text).map_err(|err| format!("{path}: {err}"))?;
+        manifest
+            .verify(&options.rom, &rom)
+            .map_err(|err| format!("{}: {err}", options.rom))?;
+    }
+    for path in &options.patches {
+        patch(&mut rom, path)?;
+    }
+    Ok(rom)
+}
+
+/// The machine `options` describe, with `program` loaded.
+fn machine(options: &Options, program: &Program) -> Result<Vm, String> {
+    let mut vm = match &options.machine {
+        Some(path) => {
+            let text = std::fs::read_to_string(path).map_err(|err| format!("{path}: {err}"))?;
+            let config = MachineConfig::from_toml(&text).map_err(|err| format!("{path}: {err}"))?;
+            Vm::with_config(&config).map_err(|err| format!("{path}: {err}"))?
+        }
+        None => Vm::new(),
+    };
+    if let Some(path) = &options.files {
+        let files = if Path::new(path).is_dir() {
+            FileService::directory(path)
+        } else {
+            FileService::image(path)
+        };
+        vm.bus_mut()
+            .map(FILE_PORTS, files)
+            .map_err(|err| format!("{path}: {err}"))?;
+    }
+    match program {
+        Program::Rom(rom) => vm
+            .load_rom(rom)
+            .map_err(|err| format!("{}: {err}", options.rom))?,
+        Program::Elf(elf) => vm.load_elf(elf),
+        Program::Image(image) => {
+            vm.load_image(image);
+            if image.entry().is_none() {
+                vm.reset(ResetKind::Soft);
+            }
+        }
+    }
+    Ok(vm)
+}
+
+fn monitor(options: &Options) -> Result<u8, String> {
+    let mut vm = machine(options, &program(options)?)?;
+    if let Some(path) = &options.symbols {
+        let text = std::fs::read_to_string(path).map_err(|err| format!("{path}: {err}"))?;
+        let map = SymbolTable::parse(&text).map_err(|err| format!("{path}: {err}"))?;
+        let mut symbols = vm.take_symbols().unwrap_or_default();
+        for (name, addr) in map.iter() {
+            symbols.insert(name, addr);
+        }
+        vm.set_symbols(symbols);
+    }
+    let result = match &options.listen {
+        Some(addr) => vm
+            .serve_monitor(addr.as_str())
+            .map_err(|err| format!("{addr}: {err}")),
+        None => vm
+            .run_monitor(io::stdin().lock(), io::stdout().lock())
+            .map_err(|err| format!("monitor: {err}")),
+    };
+    result.map(|()| 0)
+}
+
+fn run(options: &Options) -> Result<u8, String> {
+    let program = program(options)?;
+    let mut vm = machine(options, &program)?;
+    if let Some(mut config) = trace(options)? {
+        match &program {
+            Program::Rom(rom) => eprintln!("rvm8: {}: {}", options.rom, rom.info()),
+            Program::Elf(elf) if options.symbols.is_none() => {
+                config = config.with_symbols(elf.symbols().clone());
+            }
+            Program::Elf(_) | Program::Image(_) => {}
+        }
+        vm.set_trace(config);
+    }
+    let exit = Arc::new(Mutex::new(None));
+    let port = Arc::clone(&exit);
+    vm.on_write(options.exit_port..=options.exit_port, move |_, val| {
+        *port.lock().unwrap_or_else(PoisonError::into_inner) = Some(val);
+        val
+    });
+
+    #[cfg(feature = "script")]
+    let script = match &options.script {
+        Some(path) => {
+            let source = std::fs::read_to_string(path).map_err(|err| format!("{path}: {err}"))?;
+            let script = vm
+                .load_script(&source)
+                .map_err(|err| format!("{path}: {err}"))?;
+            Some((path, script))
+        }
+        None => None,
+    };
+    #[cfg(feature = "remote")]
+    let (mut remote, mut polled) = (control(options)?, 0);
+
+    let (mut elapsed, mut reported) = (0, false);
+    let status = loop {
+        #[cfg(feature = "remote")]
+        if let Some(remote) = &mut remote {
+            polled += 1;
+            if polled >= CONTROL_POLL || remote.is_paused() {
+                polled = 0;
+                remote
+                    .poll(&mut vm)
+                    .map_err(|err| format!("control: {err}"))?;
+            }
+            if remote.is_paused() {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                continue;
+            }
+        }
+        if options.cycles.is_some_and(|limit| elapsed >= limit) {
+            eprintln!("rvm8: cycle limit reached at PC ${:04X}", vm.registers().pc);
+            break TIMED_OUT;
+        }
+        let before = vm.cycles();
+        let result = vm.step();
+        // The 32-bit counter wraps on long runs. Illegal opcodes take no
+        // cycles, so each step counts as at least one to keep a program
+        // running through zeroed memory within the limit.
+        elapsed += u64::from(vm.cycles().wrapping_sub(before).max(1));
+        #[cfg(feature = "script")]
+        if let Some((path, script)) = &script
+            && let Some(err) = script.take_errors().into_iter().next()
+        {
+            return Err(format!("{path}: {err}"));
+        }
+        if let Some(code) = *exit.lock().unwrap_or_else(PoisonError::into_inner) {
+            break code;
+        }
+        match result {
+            Ok(()) => {}
+            Err(err @ VmError::IllegalOpcode { .. }) => {
+                if options.exit_on_halt {
+                    eprintln!("rvm8: halted: {err}");
+                    break 0;
+                }
+                if !reported {
+                    eprintln!("rvm8: {err}; continuing, further ones not reported");
+                    reported = true;
+                }
+            }
+            Err(err @ VmError::BusFault { .. }) => {
+                eprintln!("rvm8: {err} at PC ${:04X}", vm.registers().pc);
+                break BUS_FAULT;
+            }
+            Err(err) => return Err(err.to_string()),
+        }
+    };
+    vm.clear_trace();
+    if let Some(err) = vm.take_trace_error() {
+        eprintln!("rvm8: trace: {err}");
+    }
+    Ok(status)
+}
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1).peekable();
+    let result = if args.next_if_eq("trace").is_some() {
+        parse_trace_args(args).map(|tool| trace_tool(&tool))
+    } else {
+        parse_args(args).map(|options| match options.mode {
+            Mode::Run => run(&options),
+            Mode::Monitor => monitor(&options),
+        })
+    };
+    let result = match result {
+        Ok(result) => result,
+        Err(err) => {
+            eprintln!("rvm8: {err}\n{USAGE}");
+            return ExitCode::from(USAGE_ERROR);
+        }
+    };
+    match result {
+        Ok(status) => ExitCode::from(status),
+        Err(err) => {
+            eprintln!("rvm8: {err}");
+            ExitCode::from(USAGE_ERROR)
+        }
+    }
+}
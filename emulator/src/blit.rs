@@ -0,0 +1,195 @@
+//! Turning rows of shades into framebuffer pixels.
+//!
+//! Converting and scaling the picture touches every byte of the
+//! framebuffer each frame, which on a slow host costs more than emulating
+//! the frame did. Both steps are table lookups, a byte shuffle each, so on
+//! x86-64 they run 16 bytes at a time with SSSE3 when the host has it,
+//! checked at runtime. Other hosts, and the ends of rows, take the plain
+//! loops, which give the same bytes. A [`Blitter`] works out the table and
+//! the shuffle masks for one [`DisplayConfig`] up front, so drawing a row
+//! only reads them.
+
+use crate::display::{DisplayConfig, PixelFormat};
+
+/// What turning shades into pixels takes for one display configuration.
+pub(crate) struct Blitter {
+    config: DisplayConfig,
+    /// The bytes of each shade's pixel, one after the other.
+    table: [u8; 16],
+    #[cfg(target_arch = "x86_64")]
+    simd: Option<x86::Masks>,
+}
+
+impl Blitter {
+    pub(crate) fn new(config: DisplayConfig) -> Self {
+        let table = table(config.format);
+        Self {
+            config,
+            table,
+            #[cfg(target_arch = "x86_64")]
+            simd: is_x86_feature_detected!("ssse3").then(|| {
+                x86::Masks::new(&table, config.scale.into(), config.format.bytes_per_pixel())
+            }),
+        }
+    }
+
+    pub(crate) fn config(&self) -> DisplayConfig {
+        self.config
+    }
+
+    /// Repeats each shade of `shades` `scale` times into `out`, which is
+    /// `scale` times as long.
+    pub(crate) fn widen(&self, shades: &[u8], out: &mut [u8]) {
+        let scale = self.config.scale.into();
+        #[cfg(target_arch = "x86_64")]
+        if let Some(masks) = &self.simd {
+            // SAFETY: the masks are only built when the host has SSSE3.
+            return unsafe { x86::widen(masks, scale, shades, out) };
+        }
+        widen_scalar(shades, scale, out);
+    }
+
+    /// Writes each shade of `shades` into `out` as a pixel in the format.
+    pub(crate) fn convert(&self, shades: &[u8], out: &mut [u8]) {
+        let format = self.config.format;
+        if format == PixelFormat::Indexed {
+            return out.copy_from_slice(shades);
+        }
+        #[cfg(target_arch = "x86_64")]
+        if let Some(masks) = &self.simd {
+            // SAFETY: the masks are only built when the host has SSSE3.
+            return unsafe {
+                x86::convert(masks, &self.table, format.bytes_per_pixel(), shades, out)
+            };
+        }
+        convert_scalar(&self.table, format.bytes_per_pixel(), shades, out);
+    }
+}
+
+fn widen_scalar(shades: &[u8], scale: usize, out: &mut [u8]) {
+    for (pixels, &shade) in out.chunks_exact_mut(scale).zip(shades) {
+        pixels.fill(shade);
+    }
+}
+
+/// The bytes of each shade's pixel in `format`, one after the other.
+fn table(format: PixelFormat) -> [u8; 16] {
+    let bytes = format.bytes_per_pixel();
+    let mut table = [0; 16];
+    for (shade, pixel) in table.chunks_exact_mut(bytes).take(4).enumerate() {
+        format.encode(shade as u8, pixel);
+    }
+    table
+}
+
+fn convert_scalar(table: &[u8; 16], bytes: usize, shades: &[u8], out: &mut [u8]) {
+    for (pixel, &shade) in out.chunks_exact_mut(bytes).zip(shades) {
+        pixel.copy_from_slice(&table[usize::from(shade & 3) * bytes..][..bytes]);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::{
+        __m128i, _mm_add_epi8, _mm_and_si128, _mm_loadu_si128, _mm_set1_epi8, _mm_shuffle_epi8,
+        _mm_slli_epi16, _mm_storeu_si128,
+    };
+
+    pub(super) struct Masks {
+        /// Vector `v` takes shade `(16 * v + i) / scale` of a 16-shade
+        /// input block into byte `i` of the widened output.
+        widen: Vec<__m128i>,
+        /// Vector `v` copies into byte `i` the shade of the pixel that byte
+        /// belongs to, among the pixels of the `v`th output vector.
+        spreads: Vec<__m128i>,
+        /// Which byte of its pixel each byte is.
+        lanes: __m128i,
+        /// The pixel table.
+        lookup: __m128i,
+    }
+
+    impl Masks {
+        pub(super) fn new(table: &[u8; 16], scale: usize, bytes: usize) -> Self {
+            let per_vector = 16 / bytes;
+            Self {
+                widen: (0..scale)
+                    .map(|v| vector(|i| ((16 * v + i) / scale) as u8))
+                    .collect(),
+                spreads: (0..bytes)
+                    .map(|v| vector(|i| (v * per_vector + i / bytes) as u8))
+                    .collect(),
+                lanes: vector(|i| (i % bytes) as u8),
+                lookup: load(table),
+            }
+        }
+    }
+
+    /// Each output vector shuffles 16 bytes of one 16-shade input block.
+    #[target_feature(enable = "ssse3")]
+    pub(super) fn widen(masks: &Masks, scale: usize, shades: &[u8], out: &mut [u8]) {
+        let blocks = shades.len() / 16;
+        for (input, output) in shades
+            .chunks_exact(16)
+            .zip(out.chunks_exact_mut(16 * scale))
+        {
+            let input = load(input);
+            for (mask, output) in masks.widen.iter().zip(output.chunks_exact_mut(16)) {
+                store(output, _mm_shuffle_epi8(input, *mask));
+            }
+        }
+        let done = blocks * 16;
+        super::widen_scalar(&shades[done..], scale, &mut out[done * scale..]);
+    }
+
+    /// Each output vector holds `16 / bytes` pixels: the shades are spread
+    /// to one byte per pixel byte, turned into offsets into the table and
+    /// looked up there.
+    #[target_feature(enable = "ssse3")]
+    pub(super) fn convert(
+        masks: &Masks,
+        table: &[u8; 16],
+        bytes: usize,
+        shades: &[u8],
+        out: &mut [u8],
+    ) {
+        let three = _mm_set1_epi8(3);
+        let blocks = shades.len() / 16;
+        for (input, output) in shades
+            .chunks_exact(16)
+            .zip(out.chunks_exact_mut(16 * bytes))
+        {
+            let input = _mm_and_si128(load(input), three);
+            for (spread, output) in masks.spreads.iter().zip(output.chunks_exact_mut(16)) {
+                // Shades and offsets stay below 16, so no bit crosses into
+                // the next byte.
+                let shades = _mm_shuffle_epi8(input, *spread);
+                let offsets = match bytes {
+                    2 => _mm_slli_epi16::<1>(shades),
+                    _ => _mm_slli_epi16::<2>(shades),
+                };
+                let offsets = _mm_add_epi8(offsets, masks.lanes);
+                store(output, _mm_shuffle_epi8(masks.lookup, offsets));
+            }
+        }
+        let done = blocks * 16;
+        super::convert_scalar(table, bytes, &shades[done..], &mut out[done * bytes..]);
+    }
+
+    fn vector(byte: impl Fn(usize) -> u8) -> __m128i {
+        let bytes: [u8; 16] = std::array::from_fn(byte);
+        load(&bytes)
+    }
+
+    /// The first 16 bytes of `bytes`.
+    fn load(bytes: &[u8]) -> __m128i {
+        assert!(bytes.len() >= 16);
+        // SAFETY: there are 16 readable bytes, and the load is unaligned.
+        unsafe { _mm_loadu_si128(bytes.as_ptr().cast()) }
+    }
+
+    fn store(bytes: &mut [u8], vector: __m128i) {
+        assert!(bytes.len() >= 16);
+        // SAFETY: there are 16 writable bytes, and the store is unaligned.
+        unsafe { _mm_storeu_si128(bytes.as_mut_ptr().cast(), vector) }
+    }
+}
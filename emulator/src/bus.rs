@@ -0,0 +1,950 @@
+//! Memory-mapped devices and the host side of the kernel bus hook.
+//!
+//! Every `Vm` installs the same `extern "C"` `trampoline` on its CPU. The
+//! kernel only calls it for pages enabled in `Cpu::hook_pages`, and it
+//! forwards those accesses to the [`Bus`] owned by that `Vm`, which hands
+//! them to the [`BusDevice`] mapped at the address and checks watchpoints.
+//! Everything else is plain RAM and never leaves the kernel, unless
+//! [`TimingMode::CycleAccurate`] asks to see every access.
+//!
+//! All 64 KiB are RAM by default. [`Bus::unmap_ram`] takes pages out, and
+//! CPU accesses there that no device claims are handled as [`BusConfig`]
+//! says: like open bus on hardware, as zero, or as a [`VmError::BusFault`]
+//! that stops the machine on the offending instruction.
+
+use std::any::Any;
+use std::ffi::{c_int, c_void};
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::chrome::DeviceAccess;
+use crate::debugger::Watchpoint;
+use crate::error::VmError;
+use crate::extension::OpcodeHandler;
+use crate::ffi::BusAccess;
+use crate::heatmap::Heatmap;
+use crate::hooks::HookId;
+use crate::mpu::{MPU_PORTS, Mpu};
+use crate::reset::ResetKind;
+use crate::scheduler::Scheduler;
+use crate::snapshot::DeviceState;
+use crate::snoop::Snoop;
+
+/// Page size used by the kernel's hook filter.
+pub(crate) const PAGE_SIZE: usize = 256;
+
+/// A peripheral attached to the address space with [`Bus::map`].
+///
+/// Offsets are relative to the start of the mapped range, so the same
+/// device works wherever it is mapped.
+///
+/// CPU accesses reach the device from inside the kernel. A panic there
+/// cannot unwind through it, so it is caught and the step that made the
+/// access returns [`VmError::DevicePanic`] instead.
+pub trait BusDevice: Any + Send {
+    /// Returns the byte at `offset`. Takes `&mut self` because reading a
+    /// device register may have side effects, such as clearing a status bit.
+    fn read8(&mut self, offset: u16) -> u8;
+
+    /// Stores `val` at `offset`.
+    fn write8(&mut self, offset: u16, val: u8);
+
+    /// Advances the device by `cycles` CPU cycles. See [`TimingMode`] for
+    /// when it is called.
+    fn tick(&mut self, cycles: u32) {
+        let _ = cycles;
+    }
+
+    /// Interrupt lines the device currently holds active, one bit per line;
+    /// see [`crate::irq`]. Checked before every instruction.
+    fn irq_lines(&self) -> u8 {
+        0
+    }
+
+    /// Lets the device master the bus after every instruction, for DMA.
+    /// Returns the cycles it kept the CPU off the bus, which are added to
+    /// the clock.
+    fn dma(&mut self, bus: &mut DmaBus<'_>) -> u32 {
+        let _ = bus;
+        0
+    }
+
+    /// Cycles the device can go without being ticked or polled while the
+    /// CPU leaves it alone, with its interrupt lines and DMA unaffected by
+    /// being ticked late.
+    /// [`Vm::run_cycles`](crate::Vm::run_cycles) runs the kernel that long
+    /// in one batch and ticks the device once at the end. Registers need
+    /// not wait: the bus catches every device up before an access reaches
+    /// one, and the access ends the batch after that instruction.
+    ///
+    /// The default of 0 keeps batches off altogether, so a device that does
+    /// not know is ticked after every instruction as usual.
+    fn batch_cycles(&self) -> u32 {
+        0
+    }
+
+    /// Cycles until ticking alone would have the device raise an interrupt
+    /// line, as a [`Timer`](crate::timer::Timer) does when it overflows, or
+    /// `None` if it would not. The machine schedules an
+    /// [event](crate::scheduler) there, which ends batches on time, and asks
+    /// again after anything else reaches the device: an access, the host
+    /// going through [`Bus::device_mut`], a reset or a restored snapshot.
+    /// The default of `None` leaves the device to
+    /// [`BusDevice::batch_cycles`].
+    fn next_irq(&self) -> Option<u32> {
+        None
+    }
+
+    /// Returns the device's registers to their state after a reset of
+    /// `kind`; see [`crate::reset`] for what each kind keeps. The default
+    /// keeps everything, as for a device without a reset line.
+    fn reset(&mut self, kind: ResetKind) {
+        let _ = kind;
+    }
+
+    /// Appends whatever [`BusDevice::load_state`] needs to pick up where the
+    /// device is now, for a [snapshot](crate::snapshot). Rewind and netplay
+    /// rollback restore snapshots, so a device whose state changes as the
+    /// machine runs must save it to stay in step with them. The default
+    /// saves nothing, which leaves the device out of snapshots.
+    fn save_state(&self, state: &mut Vec<u8>) {
+        let _ = state;
+    }
+
+    /// Returns the device to a non-empty `state` that
+    /// [`BusDevice::save_state`] wrote. It may come from a file, so a device
+    /// should not panic on state it cannot make sense of. The default does
+    /// nothing.
+    fn load_state(&mut self, state: &[u8]) {
+        let _ = state;
+    }
+}
+
+/// The bus as seen by a device mastering it in [`BusDevice::dma`].
+///
+/// Reaches RAM and every other mapped device; the mastering device itself
+/// reads as the RAM under it. Addresses are decoded through
+/// [mirrors](crate::Vm::mirror) and writes to ROM pages are dropped, as for
+/// the CPU. Watchpoints and access hooks do not see these accesses.
+///
+/// The CPU and the device share the bus, so an access to a page with
+/// [wait states](crate::Vm::set_wait_states) keeps the CPU off it for the
+/// extra cycles as well, on top of what [`BusDevice::dma`] returns.
+pub struct DmaBus<'a> {
+    before: &'a mut [Mapping],
+    after: &'a mut [Mapping],
+    memory: &'a mut [u8],
+    page_map: &'a [u8; 256],
+    rom_pages: &'a [u8; 256],
+    dirty_pages: &'a mut [u8; 256],
+    wait_pages: &'a [u8; 256],
+    /// Wait states the accesses so far took.
+    waited: u32,
+}
+
+impl DmaBus<'_> {
+    fn device(&mut self, addr: u16) -> Option<(&mut Mapping, u16)> {
+        let mapping = self
+            .before
+            .iter_mut()
+            .chain(self.after.iter_mut())
+            .find(|m| m.range.contains(&addr))?;
+        let offset = addr - mapping.range.start();
+        Some((mapping, offset))
+    }
+
+    pub fn read(&mut self, addr: u16) -> u8 {
+        let addr = decode(self.page_map, addr);
+        self.waited += u32::from(self.wait_pages[addr as usize / PAGE_SIZE]);
+        match self.device(addr) {
+            Some((mapping, offset)) => mapping.device.read8(offset),
+            None => self.memory[addr as usize],
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, val: u8) {
+        let addr = decode(self.page_map, addr);
+        self.waited += u32::from(self.wait_pages[addr as usize / PAGE_SIZE]);
+        if let Some((mapping, offset)) = self.device(addr) {
+            mapping.device.write8(offset, val);
+        } else if self.rom_pages[addr as usize / PAGE_SIZE] == 0 {
+            self.memory[addr as usize] = val;
+            self.dirty_pages[addr as usize / PAGE_SIZE] = 1;
+        }
+    }
+}
+
+/// Replaces the page of `addr` with its `page_map` entry, as the kernel's
+/// `mem_decode` does.
+pub(crate) fn decode(page_map: &[u8; 256], addr: u16) -> u16 {
+    u16::from(page_map[addr as usize / PAGE_SIZE]) << 8 | addr & 0xFF
+}
+
+/// Replaces the low or high byte of a little-endian device register.
+pub(crate) fn set_word_byte(word: &mut u16, high: bool, val: u8) {
+    let mut bytes = word.to_le_bytes();
+    bytes[usize::from(high)] = val;
+    *word = u16::from_le_bytes(bytes);
+}
+
+/// How often mapped devices are ticked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimingMode {
+    /// Once after every instruction, with all the cycles it took, and just
+    /// before an access reaches a device with the cycles run since. Devices
+    /// see the clock as it was when the instruction started, plus the wait
+    /// states its accesses so far have spent.
+    #[default]
+    Instruction,
+    /// One cycle just before every bus access, plus any cycles an instruction
+    /// spends off the bus once it completes, so a device read or written
+    /// mid-instruction sees the clock at that access. Every page is then
+    /// routed through the host, which makes execution markedly slower.
+    CycleAccurate,
+}
+
+/// What a CPU read of an unmapped address returns.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum UnmappedRead {
+    /// The byte left floating on the data bus, which for an rvm-8 is the
+    /// high byte of the address: the last operand byte fetched before an
+    /// absolute read.
+    #[default]
+    OpenBus,
+    /// Zero.
+    Zero,
+    /// Reads as open bus and fails the instruction with
+    /// [`VmError::BusFault`].
+    Trap,
+}
+
+/// What happens to a CPU write to an unmapped address.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum UnmappedWrite {
+    /// The write is dropped.
+    #[default]
+    Ignore,
+    /// The write is dropped and the instruction fails with
+    /// [`VmError::BusFault`].
+    Trap,
+}
+
+/// What happens to a CPU write to a ROM page that no device claims.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum RomWrite {
+    /// The write is dropped, as the kernel does on its own.
+    #[default]
+    Ignore,
+    /// The write is dropped and the instruction fails with
+    /// [`VmError::RomWrite`]. ROM pages are then routed through the host,
+    /// which slows down code running from them.
+    Trap,
+}
+
+/// How the bus treats CPU accesses to pages taken out of RAM with
+/// [`Bus::unmap_ram`] that no device claims, and CPU writes to ROM.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BusConfig {
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub unmapped_read: UnmappedRead,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub unmapped_write: UnmappedWrite,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub rom_write: RomWrite,
+}
+
+/// A memory access that matched a watchpoint or access hook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct WatchHit {
+    pub addr: u16,
+    pub kind: BusAccess,
+    pub val: u8,
+}
+
+struct Mapping {
+    range: RangeInclusive<u16>,
+    device: Box<dyn BusDevice>,
+    /// The device's type name, without its path.
+    name: &'static str,
+}
+
+/// A read or write hook; see [`Vm::on_read`](crate::Vm::on_read).
+pub(crate) struct ValueHook {
+    pub id: HookId,
+    pub range: RangeInclusive<u16>,
+    pub kind: BusAccess,
+    pub callback: Box<dyn FnMut(u16, u8) -> u8 + Send>,
+}
+
+impl ValueHook {
+    /// Passes `val` through the hook if it covers the access.
+    fn apply(&mut self, kind: BusAccess, addr: u16, val: &mut u8) {
+        if self.kind == kind && self.range.contains(&addr) {
+            *val = (self.callback)(addr, *val);
+        }
+    }
+}
+
+/// Everything between the CPU and RAM: mapped devices, watchpoints and the
+/// handlers of extension opcodes.
+///
+/// Obtained from [`Vm::bus`](crate::Vm::bus) and
+/// [`Vm::bus_mut`](crate::Vm::bus_mut).
+pub struct Bus {
+    mappings: Vec<Mapping>,
+    pub(crate) watchpoints: Vec<Watchpoint>,
+    /// First watchpoint hit since the start of the current step.
+    pub(crate) watch_hit: Option<WatchHit>,
+    /// Ranges watched by access hooks.
+    pub(crate) access_hooks: Vec<Watchpoint>,
+    /// Accesses matching `access_hooks` during the current step.
+    pub(crate) accesses: Vec<WatchHit>,
+    /// Device accesses during the current step, kept while a
+    /// [Chrome trace](crate::TraceConfig::chrome) is set.
+    pub(crate) device_log: Option<Vec<DeviceAccess>>,
+    /// Every access during the current step, kept while a
+    /// [VCD trace](crate::TraceConfig::vcd) is set.
+    pub(crate) access_log: Option<Vec<WatchHit>>,
+    /// Read and write hooks, in registration order.
+    pub(crate) value_hooks: Vec<ValueHook>,
+    /// Set when the kernel's `hook_pages` no longer cover every mapping and
+    /// watched range.
+    pub(crate) pages_dirty: bool,
+    timing: TimingMode,
+    /// Cycles ticked by bus accesses during the current instruction.
+    access_ticks: u32,
+    /// The CPU's cycle counter, read to catch devices up before an access.
+    pub(crate) clock: *const u32,
+    /// Cycles ticked to catch devices up during the current instruction or
+    /// batch, which began with the counter at `start`.
+    caught_up: u32,
+    start: u32,
+    config: BusConfig,
+    /// Pages with no RAM behind them.
+    unmapped: [bool; 256],
+    /// First trapped unmapped access or device panic since the last check.
+    pub(crate) fault: Option<VmError>,
+    /// ROM pages whose writes trap, kept in step with the kernel's
+    /// `rom_pages` when the hook pages are synced.
+    pub(crate) rom_trap: [bool; 256],
+    /// Address and value of the first trapped ROM write since the last
+    /// check.
+    pub(crate) rom_fault: Option<(u16, u8)>,
+    /// Handlers of [extension opcodes](crate::extension), by opcode.
+    pub(crate) opcodes: Box<[Option<Box<OpcodeHandler>>; 256]>,
+    /// The [memory protection unit](crate::mpu), if installed.
+    pub(crate) mpu: Option<Mpu>,
+    /// Access counts, kept while a [heatmap](crate::heatmap) is enabled.
+    pub(crate) heatmap: Option<Heatmap>,
+    /// Every access, kept while [snooping](crate::snoop) is on.
+    pub(crate) snoop: Option<Snoop>,
+    /// Set when a device may have moved its [`BusDevice::next_irq`] since
+    /// the devices were last asked.
+    pub(crate) devices_stale: bool,
+    /// Address and final value of the last read in the current instruction
+    /// or batch, which for an illegal opcode is its fetch.
+    pub(crate) last_read: Option<(u16, u8)>,
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Self {
+            mappings: Vec::new(),
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            access_hooks: Vec::new(),
+            accesses: Vec::new(),
+            device_log: None,
+            access_log: None,
+            value_hooks: Vec::new(),
+            pages_dirty: false,
+            timing: TimingMode::default(),
+            access_ticks: 0,
+            clock: std::ptr::null(),
+            caught_up: 0,
+            start: 0,
+            config: BusConfig::default(),
+            unmapped: [false; 256],
+            rom_trap: [false; 256],
+            rom_fault: None,
+            fault: None,
+            opcodes: Box::new(std::array::from_fn(|_| None)),
+            mpu: None,
+            heatmap: None,
+            snoop: None,
+            devices_stale: false,
+            last_read: None,
+        }
+    }
+}
+
+impl Bus {
+    /// Attaches `device` to every address in `range`.
+    ///
+    /// Fails with [`VmError::MapConflict`] if `range` overlaps a device that
+    /// is already mapped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is empty.
+    pub fn map(
+        &mut self,
+        range: RangeInclusive<u16>,
+        device: impl BusDevice,
+    ) -> Result<(), VmError> {
+        assert!(!range.is_empty(), "cannot map an empty range");
+        if let Some(other) = self
+            .mappings
+            .iter()
+            .find(|m| m.range.start() <= range.end() && range.start() <= m.range.end())
+        {
+            return Err(VmError::MapConflict {
+                start: *other.range.start(),
+                end: *other.range.end(),
+            });
+        }
+        let name = std::any::type_name_of_val(&device);
+        let name = name.split('<').next().unwrap_or(name);
+        self.mappings.push(Mapping {
+            range,
+            device: Box::new(device),
+            name: name.rsplit("::").next().unwrap_or(name),
+        });
+        self.pages_dirty = true;
+        self.devices_stale = true;
+        Ok(())
+    }
+
+    /// Detaches the device mapped at `addr` and returns it. The range reverts
+    /// to RAM.
+    pub fn unmap(&mut self, addr: u16) -> Option<Box<dyn BusDevice>> {
+        let index = self.mappings.iter().position(|m| m.range.contains(&addr))?;
+        self.pages_dirty = true;
+        self.devices_stale = true;
+        Some(self.mappings.remove(index).device)
+    }
+
+    /// Address ranges that currently have a device mapped, in mapping order.
+    pub fn mappings(&self) -> impl Iterator<Item = RangeInclusive<u16>> + '_ {
+        self.mappings.iter().map(|m| m.range.clone())
+    }
+
+    /// Type name of the device mapped at `addr`.
+    pub(crate) fn device_name(&self, addr: u16) -> Option<&'static str> {
+        let mapping = self.mappings.iter().find(|m| m.range.contains(&addr))?;
+        Some(mapping.name)
+    }
+
+    /// The device mapped at `addr`, if it is a `T`.
+    pub fn device<T: BusDevice>(&self, addr: u16) -> Option<&T> {
+        let mapping = self.mappings.iter().find(|m| m.range.contains(&addr))?;
+        (&*mapping.device as &dyn Any).downcast_ref()
+    }
+
+    /// The device mapped at `addr`, mutably, if it is a `T`.
+    pub fn device_mut<T: BusDevice>(&mut self, addr: u16) -> Option<&mut T> {
+        let mapping = self.mappings.iter_mut().find(|m| m.range.contains(&addr))?;
+        self.devices_stale = true;
+        (&mut *mapping.device as &mut dyn Any).downcast_mut()
+    }
+
+    /// Every mapped device that is a `T`, mutably, with its range.
+    pub fn devices_mut<T: BusDevice>(
+        &mut self,
+    ) -> impl Iterator<Item = (RangeInclusive<u16>, &mut T)> + '_ {
+        self.devices_stale = true;
+        self.mappings.iter_mut().filter_map(|m| {
+            let device = (&mut *m.device as &mut dyn Any).downcast_mut()?;
+            Some((m.range.clone(), device))
+        })
+    }
+
+    /// When mapped devices are ticked.
+    pub fn timing_mode(&self) -> TimingMode {
+        self.timing
+    }
+
+    /// Switches between instruction and cycle granularity.
+    pub fn set_timing_mode(&mut self, mode: TimingMode) {
+        self.timing = mode;
+        self.pages_dirty = true;
+    }
+
+    /// How unmapped accesses are handled.
+    pub fn config(&self) -> BusConfig {
+        self.config
+    }
+
+    pub fn set_config(&mut self, config: BusConfig) {
+        self.config = config;
+        self.pages_dirty = true;
+    }
+
+    /// Takes the 256-byte pages covering `range` out of RAM. Devices mapped
+    /// there keep working; every other CPU access to them is unmapped. The
+    /// bytes stay in [`Vm::memory`](crate::Vm::memory), which like DMA and
+    /// [`Vm::write`](crate::Vm::write) still reaches them.
+    pub fn unmap_ram(&mut self, range: RangeInclusive<u16>) {
+        self.unmapped[page_range(&range)].fill(true);
+        self.pages_dirty = true;
+    }
+
+    /// Puts RAM back behind the pages covering `range`.
+    pub fn map_ram(&mut self, range: RangeInclusive<u16>) {
+        self.unmapped[page_range(&range)].fill(false);
+        self.pages_dirty = true;
+    }
+
+    /// Whether RAM backs `addr`.
+    pub fn is_ram(&self, addr: u16) -> bool {
+        !self.unmapped[addr as usize / PAGE_SIZE]
+    }
+
+    /// Serves a host access to `addr` as the kernel and [`Bus::access`]
+    /// would serve the CPU's, decoding it, then going to the MPU, the read
+    /// and write hooks, the device mapped there, unmapped handling, ROM or
+    /// `memory`. Nothing is latched: an access the MPU refuses or a
+    /// trapping unmapped page would fault is only refused, reading as open
+    /// bus. Watchpoints, access hooks and the access logs do not see it.
+    pub(crate) fn host_access(
+        &mut self,
+        memory: &mut [u8],
+        page_map: &[u8; 256],
+        rom_pages: &[u8; 256],
+        kind: BusAccess,
+        addr: u16,
+        val: &mut u8,
+    ) {
+        let addr = decode(page_map, addr);
+        let page = addr as usize / PAGE_SIZE;
+        if self
+            .mpu
+            .as_ref()
+            .is_some_and(|mpu| mpu.refuses(kind, addr).is_some())
+        {
+            if kind == BusAccess::Read {
+                *val = (addr >> 8) as u8;
+            }
+            return;
+        }
+        if kind == BusAccess::Write {
+            for hook in &mut self.value_hooks {
+                hook.apply(kind, addr, val);
+            }
+        }
+        let mapping = self.mappings.iter_mut().find(|m| m.range.contains(&addr));
+        match (self.mpu.as_mut(), mapping) {
+            (Some(mpu), _) if MPU_PORTS.contains(&addr) => {
+                mpu.register(kind, addr - MPU_PORTS.start(), val);
+            }
+            (_, Some(mapping)) => {
+                self.devices_stale = true;
+                let offset = addr - mapping.range.start();
+                match kind {
+                    BusAccess::Read => *val = mapping.device.read8(offset),
+                    BusAccess::Write => mapping.device.write8(offset, *val),
+                }
+            }
+            (_, None) if self.unmapped[page] => {
+                if kind == BusAccess::Read {
+                    *val = self.unmapped_value(addr);
+                }
+            }
+            (_, None) => match kind {
+                BusAccess::Read => *val = memory[addr as usize],
+                BusAccess::Write if rom_pages[page] == 0 => memory[addr as usize] = *val,
+                BusAccess::Write => {}
+            },
+        }
+        if kind == BusAccess::Read {
+            for hook in &mut self.value_hooks {
+                hook.apply(kind, addr, val);
+            }
+        }
+    }
+
+    /// Ticks every mapped device.
+    fn tick(&mut self, cycles: u32) {
+        for mapping in &mut self.mappings {
+            mapping.device.tick(cycles);
+        }
+    }
+
+    /// The state of the MPU, if installed, then of every mapped device that
+    /// saves any, in the order they were mapped.
+    pub(crate) fn save_devices(&self) -> Vec<DeviceState> {
+        let mpu = self.mpu.iter().map(|mpu| {
+            let mut state = Vec::new();
+            mpu.save_state(&mut state);
+            DeviceState {
+                start: *MPU_PORTS.start(),
+                state,
+            }
+        });
+        let devices = self.mappings.iter().filter_map(|mapping| {
+            let mut state = Vec::new();
+            mapping.device.save_state(&mut state);
+            (!state.is_empty()).then(|| DeviceState {
+                start: *mapping.range.start(),
+                state,
+            })
+        });
+        mpu.chain(devices).collect()
+    }
+
+    /// Whether a device's range starts at `start`, or an MPU is installed
+    /// there.
+    pub(crate) fn maps_device_at(&self, start: u16) -> bool {
+        (self.mpu.is_some() && start == *MPU_PORTS.start())
+            || self.mappings.iter().any(|m| *m.range.start() == start)
+    }
+
+    /// Hands each state to the device whose range starts where it was saved
+    /// from, skipping those no device starts at. An installed MPU takes the
+    /// first state saved from [`MPU_PORTS`], as it saves first.
+    pub(crate) fn load_devices(&mut self, states: &[DeviceState]) {
+        self.devices_stale = true;
+        let mut mpu = self.mpu.as_mut();
+        for saved in states {
+            if saved.start == *MPU_PORTS.start()
+                && let Some(mpu) = mpu.take()
+            {
+                mpu.load_state(&saved.state);
+                continue;
+            }
+            if let Some(mapping) = self
+                .mappings
+                .iter_mut()
+                .find(|m| *m.range.start() == saved.start)
+            {
+                mapping.device.load_state(&saved.state);
+            }
+        }
+    }
+
+    pub(crate) fn reset_devices(&mut self, kind: ResetKind) {
+        self.devices_stale = true;
+        for mapping in &mut self.mappings {
+            mapping.device.reset(kind);
+        }
+    }
+
+    /// The shortest [`BusDevice::batch_cycles`] of any mapped device, or 0
+    /// in [`TimingMode::CycleAccurate`], which ticks devices as the CPU
+    /// runs.
+    pub(crate) fn batch_cycles(&self) -> u32 {
+        if self.timing == TimingMode::CycleAccurate {
+            return 0;
+        }
+        self.mappings
+            .iter()
+            .map(|m| m.device.batch_cycles())
+            .min()
+            .unwrap_or(u32::MAX)
+    }
+
+    /// Schedules an event at each device's [`BusDevice::next_irq`], counting
+    /// from the clock, `now`.
+    pub(crate) fn schedule_irqs(&mut self, now: u64, scheduler: &mut Scheduler) {
+        self.devices_stale = false;
+        for mapping in &self.mappings {
+            if let Some(cycles) = mapping.device.next_irq() {
+                scheduler.schedule_device(now + u64::from(cycles));
+            }
+        }
+    }
+
+    /// Interrupt lines held by any mapped device.
+    pub(crate) fn irq_lines(&self) -> u8 {
+        let mpu = self.mpu.as_ref().map_or(0, Mpu::irq_lines);
+        self.mappings
+            .iter()
+            .fold(mpu, |lines, m| lines | m.device.irq_lines())
+    }
+
+    /// Gives every device its [`BusDevice::dma`] turn, returning the cycles
+    /// they stole from the CPU. Pages they store to are marked in
+    /// `dirty_pages`.
+    pub(crate) fn run_dma(
+        &mut self,
+        memory: &mut [u8],
+        page_map: &[u8; 256],
+        rom_pages: &[u8; 256],
+        dirty_pages: &mut [u8; 256],
+        wait_pages: &[u8; 256],
+    ) -> u32 {
+        let mut stolen = 0;
+        for index in 0..self.mappings.len() {
+            let (before, rest) = self.mappings.split_at_mut(index);
+            let (current, after) = rest.split_first_mut().expect("index is in bounds");
+            let mut bus = DmaBus {
+                before,
+                after,
+                memory: &mut *memory,
+                page_map,
+                rom_pages,
+                dirty_pages: &mut *dirty_pages,
+                wait_pages,
+                waited: 0,
+            };
+            stolen += current.device.dma(&mut bus) + bus.waited;
+        }
+        // Mastering the bus may have reached any device.
+        self.devices_stale |= stolen > 0;
+        stolen
+    }
+
+    /// Notes that an instruction or batch starts with the cycle counter at
+    /// `cycles`.
+    pub(crate) fn begin_instruction(&mut self, cycles: u32) {
+        self.start = cycles;
+        self.last_read = None;
+    }
+
+    /// Ticks whatever part of an instruction's `cycles` its bus accesses did
+    /// not already account for.
+    pub(crate) fn end_instruction(&mut self, cycles: u32) {
+        let rest = cycles.saturating_sub(self.access_ticks + self.caught_up);
+        self.access_ticks = 0;
+        self.caught_up = 0;
+        if rest > 0 {
+            self.tick(rest);
+        }
+    }
+
+    /// Dispatches one kernel access, returning whether a device claimed it.
+    ///
+    /// An access the [MPU](crate::mpu) blocks goes no further. Write hooks
+    /// rewrite the value before it reaches a device or RAM, and read hooks
+    /// rewrite whatever the device or RAM supplied; watchpoints and access
+    /// hooks see the final value.
+    fn access(&mut self, kind: BusAccess, addr: u16, val: &mut u8) -> bool {
+        if self.timing == TimingMode::CycleAccurate {
+            self.tick(1);
+            self.access_ticks += 1;
+        } else {
+            self.catch_up();
+        }
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.count(kind, addr);
+        }
+        if let Some(mpu) = &mut self.mpu
+            && mpu.check(kind, addr)
+        {
+            if kind == BusAccess::Read {
+                *val = (addr >> 8) as u8;
+                self.last_read = Some((addr, *val));
+            }
+            return true;
+        }
+        if kind == BusAccess::Write {
+            for hook in &mut self.value_hooks {
+                hook.apply(kind, addr, val);
+            }
+        }
+        let mapping = self.mappings.iter_mut().find(|m| m.range.contains(&addr));
+        let claimed = match (self.mpu.as_mut(), mapping) {
+            (Some(mpu), _) if MPU_PORTS.contains(&addr) => {
+                mpu.register(kind, addr - MPU_PORTS.start(), val);
+                true
+            }
+            (_, Some(mapping)) => {
+                self.devices_stale = true;
+                let offset = addr - mapping.range.start();
+                match kind {
+                    BusAccess::Read => *val = mapping.device.read8(offset),
+                    BusAccess::Write => mapping.device.write8(offset, *val),
+                }
+                if let Some(log) = &mut self.device_log {
+                    log.push(DeviceAccess {
+                        kind,
+                        addr,
+                        val: *val,
+                        start: *mapping.range.start(),
+                    });
+                }
+                true
+            }
+            (_, None) if self.unmapped[addr as usize / PAGE_SIZE] => {
+                self.unmapped_access(kind, addr, val);
+                true
+            }
+            (_, None) if kind == BusAccess::Write && self.rom_trap[addr as usize / PAGE_SIZE] => {
+                self.rom_fault.get_or_insert((addr, *val));
+                true
+            }
+            (_, None) => false,
+        };
+        if kind == BusAccess::Read {
+            for hook in &mut self.value_hooks {
+                hook.apply(kind, addr, val);
+            }
+            self.last_read = Some((addr, *val));
+        }
+        let hit = WatchHit {
+            addr,
+            kind,
+            val: *val,
+        };
+        if self.watch_hit.is_none() && self.watchpoints.iter().any(|w| w.matches(kind, addr)) {
+            self.watch_hit = Some(hit);
+        }
+        if self.access_hooks.iter().any(|w| w.matches(kind, addr)) {
+            self.accesses.push(hit);
+        }
+        if let Some(log) = &mut self.access_log {
+            log.push(hit);
+        }
+        if let Some(snoop) = &mut self.snoop {
+            snoop.record(hit, self.access_ticks.saturating_sub(1));
+        }
+        claimed
+    }
+
+    /// Ticks devices through the cycles run since the current instruction or
+    /// batch began, so a register read mid-batch reads as it would between
+    /// steps.
+    fn catch_up(&mut self) {
+        if self.clock.is_null() {
+            return;
+        }
+        // SAFETY: `clock` points into the CPU of the `Vm` owning this bus,
+        // which is executing and outlives it.
+        let now = unsafe { *self.clock };
+        let behind = now.wrapping_sub(self.start).saturating_sub(self.caught_up);
+        if behind > 0 {
+            self.tick(behind);
+            self.caught_up += behind;
+        }
+    }
+
+    /// Applies [`BusConfig`] to an access no device or RAM serves.
+    fn unmapped_access(&mut self, kind: BusAccess, addr: u16, val: &mut u8) {
+        let trap = match kind {
+            BusAccess::Read => {
+                *val = self.unmapped_value(addr);
+                self.config.unmapped_read == UnmappedRead::Trap
+            }
+            BusAccess::Write => self.config.unmapped_write == UnmappedWrite::Trap,
+        };
+        if trap && self.fault.is_none() {
+            self.fault = Some(VmError::BusFault { addr, kind });
+        }
+    }
+
+    /// What a read of the unmapped `addr` returns.
+    fn unmapped_value(&self, addr: u16) -> u8 {
+        match self.config.unmapped_read {
+            UnmappedRead::Zero => 0,
+            UnmappedRead::OpenBus | UnmappedRead::Trap => (addr >> 8) as u8,
+        }
+    }
+
+    /// The kernel `hook_pages` table covering every mapping, watchpoint,
+    /// hook, unmapped page, trapped ROM page and MPU guard, or every page in
+    /// cycle-accurate mode, while every access is logged or snooped or while
+    /// a heatmap counts them.
+    pub(crate) fn hook_pages(&self) -> [u8; 256] {
+        if self.timing == TimingMode::CycleAccurate
+            || self.access_log.is_some()
+            || self.heatmap.is_some()
+            || self.snoop.is_some()
+        {
+            return [1; 256];
+        }
+        let mut pages = [0; 256];
+        let watched = self.watchpoints.iter().chain(&self.access_hooks);
+        let ranges = self.mappings.iter().map(|m| &m.range);
+        let hooked = self.value_hooks.iter().map(|h| &h.range);
+        let guarded = self.mpu.iter().flat_map(Mpu::hooked_ranges);
+        let ranges = ranges.chain(guarded);
+        for range in ranges.chain(watched.map(|w| &w.range)).chain(hooked) {
+            pages[page_range(range)].fill(1);
+        }
+        for ((page, &unmapped), &rom) in pages.iter_mut().zip(&self.unmapped).zip(&self.rom_trap) {
+            *page |= u8::from(unmapped || rom);
+        }
+        pages
+    }
+}
+
+/// Indices of the pages covering `range`.
+fn page_range(range: &RangeInclusive<u16>) -> RangeInclusive<usize> {
+    *range.start() as usize / PAGE_SIZE..=*range.end() as usize / PAGE_SIZE
+}
+
+impl fmt::Debug for Bus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bus")
+            .field("mappings", &self.mappings().collect::<Vec<_>>())
+            .field("watchpoints", &self.watchpoints)
+            .field("timing", &self.timing)
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+// SAFETY: `clock` is only read while the owning `Vm` runs the kernel, which
+// borrows the `Vm` and with it the bus mutably, so no other thread holds it
+// then.
+unsafe impl Send for Bus {}
+
+/// The [`BusHook`](crate::ffi::BusHook) installed on every `Vm`'s CPU.
+///
+/// A panic must not unwind into the kernel, which would abort the process,
+/// so one raised by a device or hook is caught here and kept as a
+/// [`VmError::DevicePanic`] for the `Vm` to report after the instruction.
+/// The access counts as claimed, so the kernel leaves RAM alone.
+pub(crate) unsafe extern "C" fn trampoline(
+    ctx: *mut c_void,
+    kind: BusAccess,
+    addr: u16,
+    val: *mut u8,
+) -> c_int {
+    // SAFETY: `ctx` is the `Bus` allocation owned by the `Vm` whose CPU is
+    // executing, no Rust reference to it is live during a kernel call, and
+    // the kernel passes a pointer to its own local byte.
+    let (bus, val) = unsafe { (&mut *ctx.cast::<Bus>(), &mut *val) };
+    match panic::catch_unwind(AssertUnwindSafe(|| bus.access(kind, addr, &mut *val))) {
+        Ok(claimed) => c_int::from(claimed),
+        Err(payload) => {
+            *val = 0;
+            if bus.fault.is_none() {
+                bus.fault = Some(VmError::DevicePanic {
+                    addr,
+                    kind,
+                    message: panic_message(payload),
+                });
+            }
+            1
+        }
+    }
+}
+
+/// The message a caught panic was raised with.
+pub(crate) fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic payload".to_string(),
+        },
+    }
+}
@@ -0,0 +1,599 @@
+//! C embedding API.
+//!
+//! Only compiled with the `capi` feature. The crate's `cdylib` then exports
+//! these functions, declared for C in `include/rvm8.h`, so applications in
+//! any language with a C FFI can embed the emulator:
+//!
+//! ```c
+//! #include "rvm8.h"
+//!
+//! Rvm8 *vm = rvm8_create();
+//! if (rvm8_load_rom(vm, rom, rom_len) != RVM8_STATUS_OK) { ... }
+//! while (running) {
+//!   rvm8_set_buttons(vm, RVM8_BUTTON_A | RVM8_BUTTON_START);
+//!   rvm8_run_frame(vm);
+//!   draw(rvm8_framebuffer(vm), RVM8_WIDTH, RVM8_HEIGHT);
+//! }
+//! rvm8_destroy(vm);
+//! ```
+//!
+//! The header is generated from this module with
+//! `cbindgen --config cbindgen.toml --output include/rvm8.h`; regenerate it
+//! whenever an export changes.
+//!
+//! A machine is an opaque [`Rvm8`] handle, owned by the caller from
+//! [`rvm8_create`] to [`rvm8_destroy`] and used from one thread at a time.
+//! Every function taking a handle requires a live one. Failures come back
+//! as an [`Rvm8Status`] rather than unwinding into the caller.
+
+use std::ffi::{c_uint, c_void};
+
+use crate::error::VmError;
+use crate::events::Event;
+use crate::input::{CONTROLLER, Controller};
+use crate::replay::Recording;
+use crate::reset::ResetKind;
+use crate::rom::Rom;
+use crate::vm::{Registers, Vm};
+
+// Literal values so cbindgen can emit them as `#define`s.
+
+/// Framebuffer width in pixels.
+pub const RVM8_WIDTH: c_uint = 160;
+/// Framebuffer height in pixels.
+pub const RVM8_HEIGHT: c_uint = 144;
+/// Bytes of memory behind [`rvm8_memory`].
+pub const RVM8_MEMORY_SIZE: usize = 65536;
+
+/// Button bits for [`rvm8_set_buttons`], as [`Button::mask`](crate::Button::mask).
+pub const RVM8_BUTTON_A: u8 = 1 << 0;
+pub const RVM8_BUTTON_B: u8 = 1 << 1;
+pub const RVM8_BUTTON_SELECT: u8 = 1 << 2;
+pub const RVM8_BUTTON_START: u8 = 1 << 3;
+pub const RVM8_BUTTON_UP: u8 = 1 << 4;
+pub const RVM8_BUTTON_DOWN: u8 = 1 << 5;
+pub const RVM8_BUTTON_LEFT: u8 = 1 << 6;
+pub const RVM8_BUTTON_RIGHT: u8 = 1 << 7;
+
+/// A machine, opaque to C.
+pub struct Rvm8 {
+    vm: Vm,
+    /// The event [`rvm8_next_event`] returned last, which its data points
+    /// into.
+    event: Option<Event>,
+}
+
+/// Outcome of a call, mirroring [`VmError`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rvm8Status {
+    Ok = 0,
+    /// The CPU fetched an opcode with no handler.
+    IllegalOpcode = 1,
+    /// A block does not fit in the address space.
+    OutOfBounds = 2,
+    /// A save state is malformed or does not fit this machine.
+    InvalidState = 3,
+    /// A device range is already taken.
+    MapConflict = 4,
+    /// A ROM image failed to parse.
+    InvalidRom = 5,
+    /// The CPU trapped on an access to unmapped memory.
+    BusFault = 6,
+    /// A device panicked while serving a CPU access.
+    DevicePanic = 7,
+    /// An opcode the kernel implements cannot be an extension.
+    OpcodeTaken = 8,
+    /// An extension opcode's handler failed.
+    OpcodeFailed = 9,
+    /// The memory protection unit trapped an access.
+    MpuFault = 10,
+    /// A push or pop left the configured stack bounds.
+    StackFault = 11,
+    /// The CPU wrote to ROM and the bus traps such writes.
+    RomWrite = 12,
+}
+
+impl From<Result<(), VmError>> for Rvm8Status {
+    fn from(result: Result<(), VmError>) -> Self {
+        match result {
+            Ok(()) => Self::Ok,
+            Err(VmError::IllegalOpcode { .. }) => Self::IllegalOpcode,
+            Err(VmError::OutOfBounds { .. }) => Self::OutOfBounds,
+            Err(VmError::InvalidSnapshot(_)) => Self::InvalidState,
+            Err(VmError::MapConflict { .. }) => Self::MapConflict,
+            Err(VmError::BusFault { .. }) => Self::BusFault,
+            Err(VmError::DevicePanic { .. }) => Self::DevicePanic,
+            Err(VmError::OpcodeTaken { .. }) => Self::OpcodeTaken,
+            Err(VmError::OpcodeFailed { .. }) => Self::OpcodeFailed,
+            Err(VmError::MpuFault { .. }) => Self::MpuFault,
+            Err(VmError::StackOverflow { .. } | VmError::StackUnderflow { .. }) => Self::StackFault,
+            Err(VmError::RomWrite { .. }) => Self::RomWrite,
+        }
+    }
+}
+
+/// The CPU registers.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rvm8Registers {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub flags: u8,
+    pub pc: u16,
+    pub sp: u16,
+}
+
+impl From<Registers> for Rvm8Registers {
+    fn from(regs: Registers) -> Self {
+        Self {
+            a: regs.a,
+            x: regs.x,
+            y: regs.y,
+            flags: regs.flags,
+            pc: regs.pc,
+            sp: regs.sp,
+        }
+    }
+}
+
+impl From<Rvm8Registers> for Registers {
+    fn from(regs: Rvm8Registers) -> Self {
+        Self {
+            a: regs.a,
+            x: regs.x,
+            y: regs.y,
+            pc: regs.pc,
+            sp: regs.sp,
+            flags: regs.flags,
+        }
+    }
+}
+
+/// What an [`Rvm8Event`] reports, as [`Event`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rvm8EventKind {
+    /// A frame ended; `frame` is its number.
+    Vblank = 0,
+    /// A frame's audio: `len` mono samples at `data`.
+    Audio = 1,
+    /// A UART at `port` sent `len` bytes, at `data`.
+    Serial = 2,
+    /// Running a frame failed with `status`.
+    Fault = 3,
+}
+
+/// An event taken by [`rvm8_next_event`]. Fields its kind does not use
+/// are zero, and `data` stays valid until the next call on the handle.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Rvm8Event {
+    pub kind: Rvm8EventKind,
+    pub status: Rvm8Status,
+    pub port: u16,
+    pub frame: u64,
+    pub data: *const c_void,
+    pub len: usize,
+}
+
+impl Rvm8Event {
+    fn new(event: &Event) -> Self {
+        let empty = Self {
+            kind: Rvm8EventKind::Vblank,
+            status: Rvm8Status::Ok,
+            port: 0,
+            frame: 0,
+            data: std::ptr::null(),
+            len: 0,
+        };
+        match event {
+            &Event::Vblank { frame } => Self { frame, ..empty },
+            Event::Audio(samples) => Self {
+                kind: Rvm8EventKind::Audio,
+                data: samples.as_ptr().cast(),
+                len: samples.len(),
+                ..empty
+            },
+            Event::Serial { port, bytes } => Self {
+                kind: Rvm8EventKind::Serial,
+                port: *port,
+                data: bytes.as_ptr().cast(),
+                len: bytes.len(),
+                ..empty
+            },
+            Event::Fault(err) => Self {
+                kind: Rvm8EventKind::Fault,
+                status: Err(err.clone()).into(),
+                ..empty
+            },
+        }
+    }
+}
+
+/// Receives each frame's audio: the context given to
+/// [`rvm8_set_audio_callback`], then `len` mono samples.
+pub type Rvm8AudioCallback =
+    unsafe extern "C" fn(ctx: *mut c_void, samples: *const i16, len: usize);
+
+/// A powered-on machine with empty memory. Free it with [`rvm8_destroy`].
+#[unsafe(no_mangle)]
+pub extern "C" fn rvm8_create() -> *mut Rvm8 {
+    Box::into_raw(Box::new(Rvm8 {
+        vm: Vm::new(),
+        event: None,
+    }))
+}
+
+/// Frees a machine. Null is ignored.
+///
+/// # Safety
+///
+/// `vm` must be null or a handle from [`rvm8_create`] not yet destroyed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rvm8_destroy(vm: *mut Rvm8) {
+    if !vm.is_null() {
+        // SAFETY: the caller passes a live handle, which is given up here.
+        drop(unsafe { Box::from_raw(vm) });
+    }
+}
+
+/// Loads a ROM image in the `.rvm` format and resets the CPU to its entry
+/// point. A banked image fails with [`Rvm8Status::MapConflict`] if another
+/// device is mapped where its bank registers go.
+///
+/// # Safety
+///
+/// `vm` must be a live handle and `data` must point to `len` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rvm8_load_rom(vm: *mut Rvm8, data: *const u8, len: usize) -> Rvm8Status {
+    // SAFETY: the caller passes a live handle and `len` readable bytes.
+    let (vm, bytes) = unsafe { (&mut (*vm).vm, std::slice::from_raw_parts(data, len)) };
+    match Rom::from_bytes(bytes) {
+        Ok(rom) => match vm.load_rom(&rom) {
+            Ok(()) => Rvm8Status::Ok,
+            Err(_) => Rvm8Status::MapConflict,
+        },
+        Err(_) => Rvm8Status::InvalidRom,
+    }
+}
+
+/// Copies `len` bytes into memory at `addr`, as [`Vm::load`].
+///
+/// # Safety
+///
+/// `vm` must be a live handle and `data` must point to `len` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rvm8_load(
+    vm: *mut Rvm8,
+    addr: u16,
+    data: *const u8,
+    len: usize,
+) -> Rvm8Status {
+    // SAFETY: the caller passes a live handle and `len` readable bytes.
+    let (vm, bytes) = unsafe { (&mut (*vm).vm, std::slice::from_raw_parts(data, len)) };
+    vm.load(addr, bytes).into()
+}
+
+/// Resets the CPU, keeping memory.
+///
+/// # Safety
+///
+/// `vm` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rvm8_reset(vm: *mut Rvm8) {
+    // SAFETY: the caller passes a live handle.
+    unsafe { (*vm).vm.reset(ResetKind::Soft) };
+}
+
+/// Executes one instruction, as [`Vm::step`].
+///
+/// # Safety
+///
+/// `vm` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rvm8_step(vm: *mut Rvm8) -> Rvm8Status {
+    // SAFETY: the caller passes a live handle.
+    unsafe { (*vm).vm.step() }.into()
+}
+
+/// Runs for at least `cycles` cycles, as [`Vm::run_cycles`].
+///
+/// # Safety
+///
+/// `vm` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rvm8_run_cycles(vm: *mut Rvm8, cycles: u32) -> Rvm8Status {
+    // SAFETY: the caller passes a live handle.
+    unsafe { (*vm).vm.run_cycles(cycles) }.into()
+}
+
+/// Runs to the end of the frame and renders it, as [`Vm::run_frame`].
+///
+/// # Safety
+///
+/// `vm` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rvm8_run_frame(vm: *mut Rvm8) -> Rvm8Status {
+    // SAFETY: the caller passes a live handle.
+    unsafe { (*vm).vm.run_frame() }.into()
+}
+
+/// Frames completed since construction or the last reset.
+///
+/// # Safety
+///
+/// `vm` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rvm8_frame(vm: *const Rvm8) -> u64 {
+    // SAFETY: the caller passes a live handle.
+    unsafe { (*vm).vm.frame() }
+}
+
+/// The cycle counter, wrapping at 2^32.
+///
+/// # Safety
+///
+/// `vm` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rvm8_cycles(vm: *const Rvm8) -> u32 {
+    // SAFETY: the caller passes a live handle.
+    unsafe { (*vm).vm.cycles() }
+}
+
+/// Copies the registers into `*regs`.
+///
+/// # Safety
+///
+/// `vm` must be a live handle and `regs` must point to a writable
+/// `Rvm8Registers`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rvm8_registers(vm: *const Rvm8, regs: *mut Rvm8Registers) {
+    // SAFETY: the caller passes a live handle and a writable pointer.
+    unsafe { regs.write((*vm).vm.registers().into()) };
+}
+
+/// Overwrites the registers with `*regs`.
+///
+/// # Safety
+///
+/// `vm` must be a live handle and `regs` must point to a valid
+/// `Rvm8Registers`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rvm8_set_registers(vm: *mut Rvm8, regs: *const Rvm8Registers) {
+    // SAFETY: the caller passes a live handle and a readable pointer.
+    unsafe { (*vm).vm.set_registers((*regs).into()) };
+}
+
+/// Reads memory directly, bypassing devices.
+///
+/// # Safety
+///
+/// `vm` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rvm8_read(vm: *const Rvm8, addr: u16) -> u8 {
+    // SAFETY: the caller passes a live handle.
+    unsafe { (*vm).vm.read(addr) }
+}
+
+/// Writes memory directly, bypassing devices.
+///
+/// # Safety
+///
+/// `vm` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rvm8_write(vm: *mut Rvm8, addr: u16, val: u8) {
+    // SAFETY: the caller passes a live handle.
+    unsafe { (*vm).vm.write(addr, val) };
+}
+
+/// The [`RVM8_MEMORY_SIZE`] bytes of memory, valid until the machine is
+/// destroyed.
+///
+/// # Safety
+///
+/// `vm` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rvm8_memory(vm: *mut Rvm8) -> *mut u8 {
+    // SAFETY: the caller passes a live handle.
+    unsafe { (*vm).vm.memory_ptr() }
+}
+
+/// The last rendered frame as [`RVM8_WIDTH`] × [`RVM8_HEIGHT`] RGBA
+/// pixels, row by row, valid until the machine is destroyed.
+///
+/// # Safety
+///
+/// `vm` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rvm8_framebuffer(vm: *const Rvm8) -> *const u8 {
+    // SAFETY: the caller passes a live handle.
+    unsafe { (*vm).vm.framebuffer().as_ptr() }
+}
+
+/// Replaces every held button at once with a union of `RVM8_BUTTON_*`
+/// bits.
+///
+/// # Safety
+///
+/// `vm` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rvm8_set_buttons(vm: *mut Rvm8, mask: u8) {
+    // SAFETY: the caller passes a live handle.
+    unsafe { (*vm).vm.set_buttons(mask) };
+}
+
+/// Raises interrupt line `line`, as [`Vm::raise_irq`].
+///
+/// # Safety
+///
+/// `vm` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rvm8_raise_irq(vm: *mut Rvm8, line: u8) {
+    // SAFETY: the caller passes a live handle.
+    unsafe { (*vm).vm.raise_irq(line) };
+}
+
+/// Lowers interrupt line `line`, as [`Vm::ack_irq`].
+///
+/// # Safety
+///
+/// `vm` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rvm8_ack_irq(vm: *mut Rvm8, line: u8) {
+    // SAFETY: the caller passes a live handle.
+    unsafe { (*vm).vm.ack_irq(line) };
+}
+
+/// Asserts or releases the NMI input, as [`Vm::set_nmi`].
+///
+/// # Safety
+///
+/// `vm` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rvm8_set_nmi(vm: *mut Rvm8, asserted: bool) {
+    // SAFETY: the caller passes a live handle.
+    unsafe { (*vm).vm.set_nmi(asserted) };
+}
+
+/// A C callback's context pointer, which the caller vouches for on every
+/// thread the handle moves to.
+struct Context(*mut c_void);
+
+// SAFETY: the setters taking a context require it to be usable from any
+// thread the handle is used on.
+unsafe impl Send for Context {}
+
+impl Context {
+    /// The pointer. A method, so closures capture the whole `Context`.
+    fn get(&self) -> *mut c_void {
+        self.0
+    }
+}
+
+/// Calls `callback` with `ctx` and each frame's samples at the end of
+/// every frame; null removes the callback.
+///
+/// # Safety
+///
+/// `vm` must be a live handle, and `callback` must be safe to call with
+/// `ctx`, from any thread the handle is used on, for as long as it stays
+/// set.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rvm8_set_audio_callback(
+    vm: *mut Rvm8,
+    callback: Option<Rvm8AudioCallback>,
+    ctx: *mut c_void,
+) {
+    // SAFETY: the caller passes a live handle.
+    let vm = unsafe { &mut (*vm).vm };
+    let ctx = Context(ctx);
+    match callback {
+        // SAFETY: the caller keeps `callback` callable with `ctx`.
+        Some(callback) => vm.set_audio_callback(move |samples| unsafe {
+            callback(ctx.get(), samples.as_ptr(), samples.len())
+        }),
+        None => vm.clear_audio_callback(),
+    }
+}
+
+/// Sets the audio output rate in Hz, as [`Vm::set_sample_rate`].
+///
+/// # Safety
+///
+/// `vm` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rvm8_set_sample_rate(vm: *mut Rvm8, rate: u32) {
+    // SAFETY: the caller passes a live handle.
+    unsafe { (*vm).vm.set_sample_rate(rate) };
+}
+
+/// Starts or stops queueing [events](crate::events) for
+/// [`rvm8_next_event`], as [`Vm::enable_events`]; stopping drops those
+/// not taken yet. Polling the queue after each frame saves calling back
+/// into C in the middle of one.
+///
+/// # Safety
+///
+/// `vm` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rvm8_enable_events(vm: *mut Rvm8, enabled: bool) {
+    // SAFETY: the caller passes a live handle.
+    let vm = unsafe { &mut (*vm).vm };
+    if enabled {
+        vm.enable_events();
+    } else {
+        vm.disable_events();
+    }
+}
+
+/// Takes the oldest queued event into `event` and returns true, or returns
+/// false if there is none.
+///
+/// # Safety
+///
+/// `vm` must be a live handle and `event` must point to a writable
+/// [`Rvm8Event`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rvm8_next_event(vm: *mut Rvm8, event: *mut Rvm8Event) -> bool {
+    // SAFETY: the caller passes a live handle.
+    let handle = unsafe { &mut *vm };
+    handle.event = handle.vm.next_event();
+    let Some(taken) = &handle.event else {
+        return false;
+    };
+    // SAFETY: the caller passes a writable event.
+    unsafe { event.write(Rvm8Event::new(taken)) };
+    true
+}
+
+/// Writes a save state into `buf` if it holds `len` bytes or more, and
+/// returns the state's size either way, so a call with a null `buf` and
+/// `len` 0 asks for the size. The state is the start state of an
+/// [input recording](crate::replay), controller included.
+///
+/// # Safety
+///
+/// `vm` must be a live handle and `buf` must be null or point to `len`
+/// writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rvm8_save_state(vm: *const Rvm8, buf: *mut u8, len: usize) -> usize {
+    // SAFETY: the caller passes a live handle.
+    let vm = unsafe { &(*vm).vm };
+    let state = Recording {
+        start: vm.save_state(),
+        controller: vm
+            .bus()
+            .device::<Controller>(CONTROLLER)
+            .cloned()
+            .unwrap_or_default(),
+        events: Vec::new(),
+    }
+    .to_bytes();
+    if !buf.is_null() && len >= state.len() {
+        // SAFETY: the caller passes `len` writable bytes, at least
+        // `state.len()`.
+        unsafe { std::ptr::copy_nonoverlapping(state.as_ptr(), buf, state.len()) };
+    }
+    state.len()
+}
+
+/// Restores a state written by [`rvm8_save_state`].
+///
+/// # Safety
+///
+/// `vm` must be a live handle and `data` must point to `len` readable
+/// bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rvm8_load_state(vm: *mut Rvm8, data: *const u8, len: usize) -> Rvm8Status {
+    // SAFETY: the caller passes a live handle and `len` readable bytes.
+    let (vm, bytes) = unsafe { (&mut (*vm).vm, std::slice::from_raw_parts(data, len)) };
+    match Recording::from_bytes(bytes) {
+        Ok(recording) => {
+            vm.replay_recording(recording);
+            Rvm8Status::Ok
+        }
+        Err(_) => Rvm8Status::InvalidState,
+    }
+}
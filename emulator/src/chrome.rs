@@ -0,0 +1,274 @@
+//! Chrome trace event export.
+//!
+//! [`TraceConfig::chrome`] makes a trace write the timeline of a run as
+//! Chrome `trace_event` JSON, which Perfetto (<https://ui.perfetto.dev>)
+//! and `chrome://tracing` open. Instead of one record per instruction it
+//! writes what happened when, on one track each:
+//!
+//! * `frames`: every [`Vm::run_frame`] as a slice from its first cycle to
+//!   its last.
+//! * `cpu`: an instant where the CPU entered an interrupt handler, with
+//!   the line it served, and a counter of the pending interrupt lines.
+//! * `dma`: a slice for the cycles each device's DMA held the CPU off the
+//!   bus.
+//! * one track per mapped device, named after its type, with an instant
+//!   for every read and write the CPU made to it.
+//!
+//! Timestamps are emulated time: cycles converted to microseconds at the
+//! [clock rate](Vm::clock_hz), so a 1 MHz machine puts one cycle per
+//! microsecond. They never go backwards; a reset or restored state just
+//! continues the timeline.
+//!
+//! Events are streamed as a JSON array. [`Vm::clear_trace`] or replacing the
+//! trace closes it; a trace cut short without that is still readable.
+
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+use crate::ffi::BusAccess;
+use crate::trace::TraceConfig;
+use crate::vm::Vm;
+
+const PID: u32 = 1;
+const FRAMES: u32 = 1;
+const CPU: u32 = 2;
+const DMA: u32 = 3;
+/// Device tracks are numbered from here plus their first address.
+const DEVICES: u32 = 0x10;
+
+/// A claimed device access, kept for the trace until the instruction ends.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DeviceAccess {
+    pub kind: BusAccess,
+    pub addr: u16,
+    pub val: u8,
+    /// First address of the device's mapping.
+    pub start: u16,
+}
+
+/// A JSON array of trace events being written.
+pub(crate) struct ChromeTrace {
+    writer: Box<dyn Write + Send>,
+    /// Events written so far.
+    events: u64,
+    /// Cycles elapsed on the trace's timeline.
+    time: u64,
+    /// Cycle counter when `time` was last advanced.
+    last: u32,
+    /// Devices whose track has been named.
+    named: Vec<u16>,
+    /// Where the frame being run started.
+    frame_start: u64,
+    pending_irqs: u8,
+}
+
+impl ChromeTrace {
+    pub(crate) fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            writer,
+            events: 0,
+            time: 0,
+            last: 0,
+            named: Vec::new(),
+            frame_start: 0,
+            pending_irqs: 0,
+        }
+    }
+
+    /// Moves the timeline to the cycle counter `cycles`, ignoring jumps
+    /// backwards.
+    fn advance(&mut self, cycles: u32) -> u64 {
+        let elapsed = cycles.wrapping_sub(self.last);
+        if (elapsed as i32) > 0 {
+            self.time += u64::from(elapsed);
+        }
+        self.last = cycles;
+        self.time
+    }
+
+    /// Writes one event object, given its fields after `"pid"`.
+    fn event(&mut self, fields: &str) -> io::Result<()> {
+        let separator = if self.events == 0 { "[\n" } else { ",\n" };
+        self.events += 1;
+        write!(self.writer, "{separator}{{\"pid\":{PID},{fields}}}")
+    }
+
+    fn thread_name(&mut self, tid: u32, name: &str) -> io::Result<()> {
+        self.event(&format!(
+            "\"tid\":{tid},\"ph\":\"M\",\"name\":\"thread_name\",\"args\":{{\"name\":\"{name}\"}}"
+        ))
+    }
+
+    /// Names the process and the fixed tracks.
+    fn start(&mut self) -> io::Result<()> {
+        self.event(
+            "\"tid\":0,\"ph\":\"M\",\"name\":\"process_name\",\"args\":{\"name\":\"rvm-8\"}",
+        )?;
+        self.thread_name(FRAMES, "frames")?;
+        self.thread_name(CPU, "cpu")?;
+        self.thread_name(DMA, "dma")
+    }
+
+    /// Closes the array.
+    pub(crate) fn finish(&mut self) -> io::Result<()> {
+        if self.events == 0 {
+            self.writer.write_all(b"[")?;
+        }
+        self.writer.write_all(b"\n]\n")?;
+        self.writer.flush()
+    }
+}
+
+/// Microseconds at `clock_hz` for `cycles`, as a JSON number.
+fn micros(cycles: u64, clock_hz: u32) -> String {
+    let mut out = String::new();
+    let _ = write!(out, "{:.3}", cycles as f64 * 1e6 / f64::from(clock_hz));
+    out
+}
+
+impl TraceConfig {
+    /// Writes frame, interrupt, DMA and device events as Chrome trace event
+    /// JSON instead of instruction records; see [`crate::chrome`].
+    pub fn chrome(writer: impl Write + Send + 'static) -> Self {
+        let mut config = Self::empty();
+        config.chrome = Some(Box::new(ChromeTrace::new(Box::new(writer))));
+        config
+    }
+}
+
+impl Vm {
+    /// Runs `write` on the Chrome trace, if the trace has one, and stops
+    /// the trace on the first write error.
+    fn chrome_event(&mut self, write: impl FnOnce(&mut ChromeTrace, u32) -> io::Result<()>) {
+        let clock_hz = self.clock_hz;
+        let Some(TraceConfig {
+            chrome: Some(chrome),
+            ..
+        }) = &mut self.tracer.config
+        else {
+            return;
+        };
+        let result = match chrome.events {
+            0 => chrome.start(),
+            _ => Ok(()),
+        };
+        if let Err(err) = result.and_then(|()| write(chrome, clock_hz)) {
+            self.fail_trace(err);
+        }
+    }
+
+    /// Whether a Chrome trace is being written.
+    pub(crate) fn chrome_tracing(&self) -> bool {
+        matches!(
+            self.tracer.config,
+            Some(TraceConfig {
+                chrome: Some(_),
+                ..
+            })
+        )
+    }
+
+    /// Notes where the frame about to run starts.
+    pub(crate) fn chrome_frame_start(&mut self) {
+        let cycles = self.cpu.cycles;
+        self.chrome_event(|chrome, _| {
+            chrome.frame_start = chrome.advance(cycles);
+            Ok(())
+        });
+    }
+
+    /// Writes the slice of the frame that just ended.
+    pub(crate) fn chrome_frame_end(&mut self) {
+        let (cycles, frame) = (self.cpu.cycles, self.frame);
+        self.chrome_event(|chrome, clock_hz| {
+            let end = chrome.advance(cycles);
+            let (ts, dur) = (chrome.frame_start, end - chrome.frame_start);
+            chrome.event(&format!(
+                "\"tid\":{FRAMES},\"ph\":\"X\",\"cat\":\"frame\",\"name\":\"frame {}\",\"ts\":{},\"dur\":{}",
+                frame - 1,
+                micros(ts, clock_hz),
+                micros(dur, clock_hz)
+            ))
+        });
+    }
+
+    /// Writes a counter sample when the pending interrupt lines change.
+    pub(crate) fn chrome_irq_lines(&mut self) {
+        let (cycles, pending) = (self.cpu.cycles, self.pending_irqs());
+        self.chrome_event(|chrome, clock_hz| {
+            if pending == chrome.pending_irqs {
+                return Ok(());
+            }
+            chrome.pending_irqs = pending;
+            let ts = micros(chrome.advance(cycles), clock_hz);
+            chrome.event(&format!(
+                "\"tid\":{CPU},\"ph\":\"C\",\"name\":\"pending irqs\",\"ts\":{ts},\"args\":{{\"lines\":{pending}}}"
+            ))
+        });
+    }
+
+    /// Writes an instant `name`d for the interrupt, `irq` or `nmi`, whose
+    /// handler is entered at `cycles` to serve `line`.
+    pub(crate) fn chrome_irq_entry(&mut self, cycles: u32, name: &str, line: Option<u8>) {
+        self.chrome_event(|chrome, clock_hz| {
+            let ts = micros(chrome.advance(cycles), clock_hz);
+            let line = line.map_or("null".to_string(), |line| line.to_string());
+            chrome.event(&format!(
+                "\"tid\":{CPU},\"ph\":\"i\",\"s\":\"t\",\"cat\":\"irq\",\"name\":\"{name}\",\"ts\":{ts},\"args\":{{\"line\":{line}}}"
+            ))
+        });
+    }
+
+    /// Writes a slice for `stolen` cycles of DMA ending now.
+    pub(crate) fn chrome_dma(&mut self, stolen: u32) {
+        let cycles = self.cpu.cycles;
+        self.chrome_event(|chrome, clock_hz| {
+            let end = chrome.advance(cycles);
+            let start = end.saturating_sub(u64::from(stolen));
+            chrome.event(&format!(
+                "\"tid\":{DMA},\"ph\":\"X\",\"cat\":\"dma\",\"name\":\"dma\",\"ts\":{},\"dur\":{}",
+                micros(start, clock_hz),
+                micros(end - start, clock_hz)
+            ))
+        });
+    }
+
+    /// Writes the device accesses the last instruction made, stamped with
+    /// the cycle it started at.
+    pub(crate) fn chrome_device_accesses(&mut self, cycles: u32) {
+        let Some(log) = self.bus_mut().device_log.as_mut() else {
+            return;
+        };
+        if log.is_empty() {
+            return;
+        }
+        let accesses = std::mem::take(log);
+        let names: Vec<_> = accesses
+            .iter()
+            .map(|access| self.bus().device_name(access.start).unwrap_or("device"))
+            .collect();
+        self.chrome_event(|chrome, clock_hz| {
+            let ts = micros(chrome.advance(cycles), clock_hz);
+            for (access, name) in accesses.iter().zip(names) {
+                let tid = DEVICES + u32::from(access.start);
+                if !chrome.named.contains(&access.start) {
+                    chrome.named.push(access.start);
+                    chrome.thread_name(tid, &format!("{name} @ ${:04X}", access.start))?;
+                }
+                let kind = match access.kind {
+                    BusAccess::Read => "read",
+                    BusAccess::Write => "write",
+                };
+                chrome.event(&format!(
+                    "\"tid\":{tid},\"ph\":\"i\",\"s\":\"t\",\"cat\":\"device\",\"name\":\"{kind}\",\"ts\":{ts},\"args\":{{\"addr\":\"${:04X}\",\"value\":\"${:02X}\"}}",
+                    access.addr, access.val
+                ))?;
+            }
+            Ok(())
+        });
+        if let Some(log) = self.bus_mut().device_log.as_mut() {
+            *log = accesses;
+            log.clear();
+        }
+    }
+}
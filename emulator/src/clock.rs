@@ -0,0 +1,64 @@
+//! CPU clock speed and memory wait states.
+//!
+//! A frame is always 1/[`FRAME_RATE`] of a second, so the clock rate sets
+//! how many cycles [`Vm::run_frame`] runs: raising it overclocks the CPU
+//! relative to the display. The sound chip is clocked by the CPU, so its
+//! pitch follows.
+//!
+//! Wait states make memory slow: every CPU access to a page with wait
+//! states set takes that many extra cycles, as slow ROM or external RAM on a
+//! real board would. The kernel charges them as it performs the access, so
+//! they hold in batches as well as when stepping. A device mastering the
+//! bus for DMA pays them too, and since the CPU cannot use the bus until
+//! the device lets go, the CPU loses those cycles with it.
+
+use std::ops::RangeInclusive;
+
+use crate::bus::PAGE_SIZE;
+use crate::vm::{FRAME_RATE, Vm};
+
+impl Vm {
+    /// CPU clock rate in cycles per second, [`CLOCK_HZ`](crate::vm::CLOCK_HZ)
+    /// until changed.
+    pub fn clock_hz(&self) -> u32 {
+        self.clock_hz
+    }
+
+    /// Changes the CPU clock rate, taking effect from the next frame.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `hz` is below [`FRAME_RATE`], which would leave frames with
+    /// no cycles.
+    pub fn set_clock_hz(&mut self, hz: u32) {
+        assert!(
+            hz >= FRAME_RATE,
+            "clock must run at least one cycle per frame"
+        );
+        self.clock_hz = hz;
+        self.audio.rebase(self.frame_cycle);
+        self.schedule_vblank();
+    }
+
+    /// CPU cycles in one frame at the current clock rate.
+    pub fn cycles_per_frame(&self) -> u32 {
+        self.clock_hz / FRAME_RATE
+    }
+
+    /// Makes every CPU access to the 256-byte pages covering `range` take
+    /// `cycles` extra cycles; 0 makes them full speed again.
+    ///
+    /// Pages are charged after [mirror](Vm::mirror) decoding, so a mirror
+    /// is as slow as its target whatever is set for its own pages. Host
+    /// accesses are not charged. [DMA](crate::dma) is, on the pages as
+    /// addressed, and the CPU waits for the bus meanwhile.
+    pub fn set_wait_states(&mut self, range: RangeInclusive<u16>, cycles: u8) {
+        let pages = *range.start() as usize / PAGE_SIZE..=*range.end() as usize / PAGE_SIZE;
+        self.cpu.wait_pages[pages].fill(cycles);
+    }
+
+    /// Extra cycles set for the page holding `addr`.
+    pub fn wait_states(&self, addr: u16) -> u8 {
+        self.cpu.wait_pages[addr as usize / PAGE_SIZE]
+    }
+}
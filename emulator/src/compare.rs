@@ -0,0 +1,184 @@
+//! Running two machines in lockstep to find where they part.
+//!
+//! Checking an optimization of emulated code, or a change of
+//! [`MachineConfig`](crate::MachineConfig), comes down to running the old
+//! and the new side by side and seeing whether anything a player could
+//! notice changes. [`frames`] runs two [`Vm`]s a frame at a time and
+//! [`steps`] an instruction at a time, comparing them after each as
+//! [`Checks`] say, and stop at the first [`Divergence`]:
+//!
+//! ```no_run
+//! # use emulator::{compare::{self, Checks}, Rom, Vm};
+//! let (mut old, mut new) = (Vm::new(), Vm::new());
+//! old.load_rom(&Rom::from_file("game.rvm").unwrap()).unwrap();
+//! new.load_rom(&Rom::from_file("game-optimized.rvm").unwrap()).unwrap();
+//! // The optimized code takes other paths, so only what is drawn counts.
+//! let checks = Checks {
+//!     registers: false,
+//!     memory: None,
+//!     ..Checks::default()
+//! };
+//! if let Err(divergence) = compare::frames(&mut old, &mut new, 600, &checks) {
+//!     println!("{divergence}");
+//! }
+//! ```
+//!
+//! Memory is compared as [`Vm::memory`] sees it, so device registers are
+//! not read. Whether a step or frame failed is always compared, and both
+//! machines stop at the first error, shared or not.
+
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::error::VmError;
+use crate::vm::{Registers, Vm};
+
+/// What [`frames`] and [`steps`] compare.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checks {
+    pub registers: bool,
+    /// The addresses whose bytes must agree, if any.
+    pub memory: Option<RangeInclusive<u16>>,
+    /// Whether the framebuffers must agree, compared after every frame
+    /// either machine finishes.
+    pub framebuffer: bool,
+}
+
+/// Everything.
+impl Default for Checks {
+    fn default() -> Self {
+        Self {
+            registers: true,
+            memory: Some(0x0000..=0xFFFF),
+            framebuffer: true,
+        }
+    }
+}
+
+/// The first difference between the two machines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Frames or steps, as run, both machines completed alike before the
+    /// one that differed.
+    pub after: u64,
+    pub mismatch: Mismatch,
+}
+
+/// What differed; each holds the left machine's side first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    /// One machine failed and the other did not, or they failed
+    /// differently.
+    Result(Option<VmError>, Option<VmError>),
+    Registers(Registers, Registers),
+    /// The lowest checked address holding different bytes.
+    Memory {
+        addr: u16,
+        left: u8,
+        right: u8,
+    },
+    /// The first framebuffer byte that differs; framebuffers of different
+    /// lengths differ at the end of the shorter one.
+    Framebuffer {
+        offset: usize,
+    },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "machines diverged after {}: ", self.after)?;
+        match &self.mismatch {
+            Mismatch::Result(left, right) => {
+                let describe = |err: &Option<VmError>| match err {
+                    Some(err) => err.to_string(),
+                    None => "ok".into(),
+                };
+                write!(f, "left {}, right {}", describe(left), describe(right))
+            }
+            Mismatch::Registers(left, right) => {
+                write!(f, "registers {left:?} and {right:?}")
+            }
+            Mismatch::Memory { addr, left, right } => {
+                write!(f, "memory 0x{addr:04X}: 0x{left:02X} and 0x{right:02X}")
+            }
+            Mismatch::Framebuffer { offset } => {
+                write!(f, "framebuffers differ at byte {offset}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Divergence {}
+
+/// Runs `left` and `right` for up to `count` frames with
+/// [`Vm::run_frame`], comparing them after each.
+pub fn frames(
+    left: &mut Vm,
+    right: &mut Vm,
+    count: u64,
+    checks: &Checks,
+) -> Result<(), Divergence> {
+    lockstep(left, right, count, checks, Vm::run_frame)
+}
+
+/// Runs `left` and `right` for up to `count` instructions with
+/// [`Vm::step`], comparing them after each.
+pub fn steps(left: &mut Vm, right: &mut Vm, count: u64, checks: &Checks) -> Result<(), Divergence> {
+    lockstep(left, right, count, checks, Vm::step)
+}
+
+fn lockstep(
+    left: &mut Vm,
+    right: &mut Vm,
+    count: u64,
+    checks: &Checks,
+    mut advance: impl FnMut(&mut Vm) -> Result<(), VmError>,
+) -> Result<(), Divergence> {
+    for after in 0..count {
+        let frames = (left.frame(), right.frame());
+        let results = (advance(left).err(), advance(right).err());
+        let frame_ended = frames != (left.frame(), right.frame());
+        let failed = results.0.is_some() || results.1.is_some();
+        let mismatch = if results.0 != results.1 {
+            Some(Mismatch::Result(results.0, results.1))
+        } else {
+            difference(left, right, checks, frame_ended)
+        };
+        if let Some(mismatch) = mismatch {
+            return Err(Divergence { after, mismatch });
+        }
+        if failed {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn difference(left: &Vm, right: &Vm, checks: &Checks, frame_ended: bool) -> Option<Mismatch> {
+    if checks.registers && left.registers() != right.registers() {
+        return Some(Mismatch::Registers(left.registers(), right.registers()));
+    }
+    if let Some(range) = &checks.memory {
+        let range = usize::from(*range.start())..=usize::from(*range.end());
+        let (l, r) = (
+            &left.memory()[range.clone()],
+            &right.memory()[range.clone()],
+        );
+        if let Some(i) = l.iter().zip(r).position(|(l, r)| l != r) {
+            return Some(Mismatch::Memory {
+                addr: (range.start() + i) as u16,
+                left: l[i],
+                right: r[i],
+            });
+        }
+    }
+    if checks.framebuffer && frame_ended {
+        let (l, r) = (left.framebuffer(), right.framebuffer());
+        if l != r {
+            let offset = l.iter().zip(r).position(|(l, r)| l != r);
+            let offset = offset.unwrap_or(l.len().min(r.len()));
+            return Some(Mismatch::Framebuffer { offset });
+        }
+    }
+    None
+}
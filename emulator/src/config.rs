@@ -0,0 +1,676 @@
+//! Machine descriptions.
+//!
+//! A [`MachineConfig`] describes an rvm-8 board variant: how fast the CPU
+//! runs, which pages hold RAM and ROM and how slow they are, which mirror
+//! other pages, which devices sit where, and how the bus treats everything
+//! else. [`Vm::with_config`] builds a machine from
+//! one; [`MachineConfig::stock`] is the board [`Vm::new`] builds, with RAM
+//! everywhere and the controller at [`INPUT_PORTS`].
+//!
+//! [`MachineConfig::from_toml`] reads a description like this one:
+//!
+//! ```toml
+//! # 16 KiB of RAM seen twice, a slow ROM window and two devices.
+//! [cpu]
+//! clock_hz = 2_000_000
+//! illegal_opcodes = "nop"   # "trap" or "undocumented"
+//!
+//! [bus]
+//! unmapped_read = "trap"    # "open-bus", "zero" or "trap"
+//! unmapped_write = "ignore" # or "trap"
+//! rom_write = "ignore"      # or "trap"
+//!
+//! [display]
+//! format = "rgb565"         # "rgba8888" or "indexed"
+//! scale = 2
+//!
+//! [[ram]]
+//! start = 0x0000
+//! end = 0x3FFF
+//!
+//! [[mirror]]
+//! start = 0x4000
+//! end = 0x7FFF
+//! target = 0x0000
+//!
+//! [[rom]]
+//! start = 0x8000
+//! end = 0xFFFF
+//! wait = 1
+//!
+//! [[device]]
+//! kind = "controller"
+//!
+//! [[device]]
+//! kind = "timer"
+//! start = 0x2718
+//! line = 3
+//! ```
+//!
+//! The `[cpu]` table is the [`CpuConfig`]: `clock_hz` defaults to
+//! [`CLOCK_HZ`](crate::vm::CLOCK_HZ), see [`Vm::set_clock_hz`], and
+//! illegal opcodes trap unless `illegal_opcodes` says otherwise. Pages
+//! outside every `ram` and `rom` region are unmapped, see [`BusConfig`].
+//! The `[display]` table picks the framebuffer's [`DisplayConfig`]; either
+//! key may be left out for its default. A region's `wait` is its [wait
+//! states](Vm::set_wait_states), 0 unless given. Regions cover each
+//! 256-byte page they touch; mirrors must start and end on page boundaries,
+//! and make the CPU's accesses to their pages decode to the pages from
+//! `target` on, devices included. ROM regions are write-protected until
+//! [`Vm::load_rom`] protects its own banks instead.
+//!
+//! A device is a `controller`, `timer` (`line`, default [`TIMER_IRQ`]),
+//! `uart` (`stdio = true` connects it to the process's stdin and stdout),
+//! `dma`, `rtc` (`host = true` follows the host's clock, otherwise it
+//! counts cycles from [`RTC_EPOCH`]), `keyboard` or `rng` (`seed`, default
+//! [`RNG_SEED`]), at `start` or else its conventional slot.
+//!
+//! Only the TOML the example uses is understood: tables, arrays of tables,
+//! integers (decimal, `0x` hex or `0b` binary, with `_` separators),
+//! strings without escapes, booleans and `#` comments. With the `serde`
+//! feature the config types are serializable, so full TOML, RON or any other
+//! serde format can be read through its own crate, with the same tables,
+//! keys and defaults.
+
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::bus::{BusConfig, PAGE_SIZE, RomWrite, UnmappedRead, UnmappedWrite};
+use crate::display::{DisplayConfig, PixelFormat};
+use crate::dma::{DMA_PORTS, Dma};
+use crate::error::VmError;
+use crate::input::{Controller, INPUT_PORTS};
+use crate::irq::IRQ_LINES;
+use crate::keyboard::{KEYBOARD_PORTS, Keyboard};
+use crate::rng::{RNG_PORTS, RNG_SEED, Rng};
+use crate::rtc::{RTC_EPOCH, RTC_PORTS, Rtc, RtcMode};
+use crate::timer::{TIMER_IRQ, Timer, timer_ports};
+use crate::uart::{UART_PORTS, Uart};
+use crate::vm::{CpuConfig, FRAME_RATE, IllegalOpcodes, Vm};
+
+/// A range of RAM or ROM, each access to which takes `wait` extra cycles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Region {
+    pub start: u16,
+    pub end: u16,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub wait: u8,
+}
+
+impl Region {
+    pub fn range(&self) -> RangeInclusive<u16> {
+        self.start..=self.end
+    }
+}
+
+/// Pages `start..=end` decoding to the same number of pages from `target`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mirror {
+    pub start: u16,
+    pub end: u16,
+    pub target: u16,
+}
+
+/// A device to map, at `start` or its conventional slot when that is
+/// `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "kind", rename_all = "lowercase")
+)]
+pub enum DeviceConfig {
+    Controller {
+        start: Option<u16>,
+    },
+    Timer {
+        start: Option<u16>,
+        #[cfg_attr(feature = "serde", serde(default = "timer_irq"))]
+        line: u8,
+    },
+    Uart {
+        start: Option<u16>,
+        #[cfg_attr(feature = "serde", serde(default))]
+        stdio: bool,
+    },
+    Dma {
+        start: Option<u16>,
+    },
+    Rtc {
+        start: Option<u16>,
+        #[cfg_attr(feature = "serde", serde(default))]
+        host: bool,
+    },
+    Keyboard {
+        start: Option<u16>,
+    },
+    Rng {
+        start: Option<u16>,
+        #[cfg_attr(feature = "serde", serde(default = "rng_seed"))]
+        seed: u32,
+    },
+}
+
+#[cfg(feature = "serde")]
+fn timer_irq() -> u8 {
+    TIMER_IRQ
+}
+
+#[cfg(feature = "serde")]
+fn rng_seed() -> u32 {
+    RNG_SEED
+}
+
+impl DeviceConfig {
+    /// The addresses the device takes.
+    pub fn range(&self) -> Result<RangeInclusive<u16>, VmError> {
+        let (start, conventional) = match *self {
+            Self::Controller { start } => (start, INPUT_PORTS),
+            Self::Timer { start, .. } => (start, timer_ports(0)),
+            Self::Uart { start, .. } => (start, UART_PORTS),
+            Self::Dma { start } => (start, DMA_PORTS),
+            Self::Rtc { start, .. } => (start, RTC_PORTS),
+            Self::Keyboard { start } => (start, KEYBOARD_PORTS),
+            Self::Rng { start, .. } => (start, RNG_PORTS),
+        };
+        let Some(start) = start else {
+            return Ok(conventional);
+        };
+        let len = conventional.len();
+        match start.checked_add(len as u16 - 1) {
+            Some(end) => Ok(start..=end),
+            None => Err(VmError::OutOfBounds { addr: start, len }),
+        }
+    }
+
+    fn map(&self, vm: &mut Vm) -> Result<(), VmError> {
+        let range = self.range()?;
+        let bus = vm.bus_mut();
+        match *self {
+            Self::Controller { .. } => bus.map(range, Controller::default()),
+            Self::Timer { line, .. } => bus.map(range, Timer::new(line)),
+            Self::Uart { stdio: true, .. } => bus.map(range, Uart::stdio()),
+            Self::Uart { stdio: false, .. } => bus.map(range, Uart::new()),
+            Self::Dma { .. } => bus.map(range, Dma::default()),
+            Self::Rtc { host: true, .. } => bus.map(range, Rtc::new(RtcMode::Host)),
+            Self::Rtc { host: false, .. } => {
+                let clock_hz = vm.clock_hz();
+                let mode = RtcMode::Cycles {
+                    start: RTC_EPOCH,
+                    clock_hz,
+                };
+                vm.bus_mut().map(range, Rtc::new(mode))
+            }
+            Self::Keyboard { .. } => bus.map(range, Keyboard::new()),
+            Self::Rng { seed, .. } => bus.map(range, Rng::new(seed)),
+        }
+    }
+}
+
+/// A board variant, see the [module documentation](self).
+///
+/// The default is a board with nothing on it: every page unmapped.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MachineConfig {
+    /// The CPU, whose `clock_hz` is [`CLOCK_HZ`](crate::vm::CLOCK_HZ) when
+    /// `None`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub cpu: CpuConfig,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub bus: BusConfig,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub display: DisplayConfig,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub ram: Vec<Region>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub rom: Vec<Region>,
+    #[cfg_attr(feature = "serde", serde(default, rename = "mirror"))]
+    pub mirrors: Vec<Mirror>,
+    #[cfg_attr(feature = "serde", serde(default, rename = "device"))]
+    pub devices: Vec<DeviceConfig>,
+}
+
+/// A machine description line that could not be used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    /// 1-based line.
+    pub line: usize,
+    pub kind: ConfigErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigErrorKind {
+    /// The line is neither a table header nor `key = value`.
+    Syntax,
+    UnknownTable(String),
+    /// `[cpu]`, `[bus]` or `[display]` appears more than once.
+    DuplicateTable(String),
+    /// The key does not belong in its table, or comes before any table.
+    UnknownKey(String),
+    DuplicateKey(String),
+    /// The table starting on this line lacks a required key.
+    MissingKey(&'static str),
+    /// The value has the wrong type or does not fit its key.
+    BadValue(String),
+    UnknownDevice(String),
+    /// A region or mirror ends before it starts, or a mirror's target runs
+    /// past the end of the address space.
+    BadRange,
+    /// A mirror does not start and end on page boundaries.
+    Misaligned,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            ConfigErrorKind::Syntax => write!(f, "expected `[table]` or `key = value`"),
+            ConfigErrorKind::UnknownTable(name) => write!(f, "unknown table `{name}`"),
+            ConfigErrorKind::DuplicateTable(name) => write!(f, "table `{name}` is already defined"),
+            ConfigErrorKind::UnknownKey(key) => write!(f, "unknown key `{key}`"),
+            ConfigErrorKind::DuplicateKey(key) => write!(f, "key `{key}` is already set"),
+            ConfigErrorKind::MissingKey(key) => write!(f, "missing key `{key}`"),
+            ConfigErrorKind::BadValue(key) => write!(f, "bad value for `{key}`"),
+            ConfigErrorKind::UnknownDevice(kind) => write!(f, "unknown device `{kind}`"),
+            ConfigErrorKind::BadRange => write!(f, "range does not fit the address space"),
+            ConfigErrorKind::Misaligned => write!(f, "mirror is not whole pages"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn error(line: usize, kind: ConfigErrorKind) -> ConfigError {
+    ConfigError { line, kind }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Int(u64),
+    Str(String),
+    Bool(bool),
+}
+
+fn parse_value(text: &str) -> Option<Value> {
+    if let Some(inner) = text.strip_prefix('"') {
+        let inner = inner.strip_suffix('"')?;
+        return (!inner.contains(['"', '\\'])).then(|| Value::Str(inner.into()));
+    }
+    match text {
+        "true" => return Some(Value::Bool(true)),
+        "false" => return Some(Value::Bool(false)),
+        _ => {}
+    }
+    let (digits, radix) = if let Some(hex) = text.strip_prefix("0x") {
+        (hex, 16)
+    } else if let Some(bin) = text.strip_prefix("0b") {
+        (bin, 2)
+    } else {
+        (text, 10)
+    };
+    if digits.starts_with('_') || digits.ends_with('_') {
+        return None;
+    }
+    u64::from_str_radix(&digits.replace('_', ""), radix)
+        .ok()
+        .map(Value::Int)
+}
+
+/// Cuts a `#` comment off a line, leaving any inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// One `[table]` or `[[table]]` and the keys set in it.
+struct Table {
+    name: String,
+    /// Line of the header.
+    line: usize,
+    entries: Vec<(String, Value, usize)>,
+}
+
+impl Table {
+    fn take(&mut self, key: &str) -> Option<(Value, usize)> {
+        let index = self.entries.iter().position(|(k, ..)| k == key)?;
+        let (_, value, line) = self.entries.remove(index);
+        Some((value, line))
+    }
+
+    fn int<T: TryFrom<u64>>(&mut self, key: &'static str) -> Result<Option<T>, ConfigError> {
+        match self.take(key) {
+            None => Ok(None),
+            Some((Value::Int(n), line)) => T::try_from(n)
+                .map(Some)
+                .map_err(|_| error(line, ConfigErrorKind::BadValue(key.into()))),
+            Some((_, line)) => Err(error(line, ConfigErrorKind::BadValue(key.into()))),
+        }
+    }
+
+    fn required<T: TryFrom<u64>>(&mut self, key: &'static str) -> Result<T, ConfigError> {
+        self.int(key)?
+            .ok_or(error(self.line, ConfigErrorKind::MissingKey(key)))
+    }
+
+    fn string(&mut self, key: &'static str) -> Result<Option<(String, usize)>, ConfigError> {
+        match self.take(key) {
+            None => Ok(None),
+            Some((Value::Str(s), line)) => Ok(Some((s, line))),
+            Some((_, line)) => Err(error(line, ConfigErrorKind::BadValue(key.into()))),
+        }
+    }
+
+    fn bool(&mut self, key: &'static str) -> Result<Option<bool>, ConfigError> {
+        match self.take(key) {
+            None => Ok(None),
+            Some((Value::Bool(b), _)) => Ok(Some(b)),
+            Some((_, line)) => Err(error(line, ConfigErrorKind::BadValue(key.into()))),
+        }
+    }
+
+    /// Fails on the first key nothing took.
+    fn finish(self) -> Result<(), ConfigError> {
+        match self.entries.into_iter().next() {
+            Some((key, _, line)) => Err(error(line, ConfigErrorKind::UnknownKey(key))),
+            None => Ok(()),
+        }
+    }
+
+    /// `start` and `end`, in order.
+    fn span(&mut self) -> Result<(u16, u16), ConfigError> {
+        let (start, end) = (self.required("start")?, self.required("end")?);
+        if end < start {
+            return Err(error(self.line, ConfigErrorKind::BadRange));
+        }
+        Ok((start, end))
+    }
+
+    fn region(&mut self) -> Result<Region, ConfigError> {
+        let (start, end) = self.span()?;
+        let wait = self.int("wait")?.unwrap_or(0);
+        Ok(Region { start, end, wait })
+    }
+
+    fn mirror(&mut self) -> Result<Mirror, ConfigError> {
+        let (start, end) = self.span()?;
+        let target: u16 = self.required("target")?;
+        let page = PAGE_SIZE as u16;
+        if !start.is_multiple_of(page) || end % page != page - 1 || !target.is_multiple_of(page) {
+            return Err(error(self.line, ConfigErrorKind::Misaligned));
+        }
+        if target.checked_add(end - start).is_none() {
+            return Err(error(self.line, ConfigErrorKind::BadRange));
+        }
+        Ok(Mirror { start, end, target })
+    }
+
+    fn device(&mut self) -> Result<DeviceConfig, ConfigError> {
+        let (kind, line) = self
+            .string("kind")?
+            .ok_or(error(self.line, ConfigErrorKind::MissingKey("kind")))?;
+        let start = self.int("start")?;
+        let device = match kind.as_str() {
+            "controller" => DeviceConfig::Controller { start },
+            "timer" => {
+                let irq = self.int("line")?.unwrap_or(TIMER_IRQ);
+                if irq >= IRQ_LINES {
+                    return Err(error(self.line, ConfigErrorKind::BadValue("line".into())));
+                }
+                DeviceConfig::Timer { start, line: irq }
+            }
+            "uart" => DeviceConfig::Uart {
+                start,
+                stdio: self.bool("stdio")?.unwrap_or(false),
+            },
+            "dma" => DeviceConfig::Dma { start },
+            "rtc" => DeviceConfig::Rtc {
+                start,
+                host: self.bool("host")?.unwrap_or(false),
+            },
+            "keyboard" => DeviceConfig::Keyboard { start },
+            "rng" => DeviceConfig::Rng {
+                start,
+                seed: self.int("seed")?.unwrap_or(RNG_SEED),
+            },
+            _ => return Err(error(line, ConfigErrorKind::UnknownDevice(kind))),
+        };
+        if device.range().is_err() {
+            return Err(error(self.line, ConfigErrorKind::BadRange));
+        }
+        Ok(device)
+    }
+}
+
+impl MachineConfig {
+    /// The board [`Vm::new`] builds: RAM everywhere and the controller at
+    /// [`INPUT_PORTS`].
+    pub fn stock() -> Self {
+        Self {
+            ram: vec![Region {
+                start: 0x0000,
+                end: 0xFFFF,
+                wait: 0,
+            }],
+            devices: vec![DeviceConfig::Controller { start: None }],
+            ..Self::default()
+        }
+    }
+
+    /// Parses a description in the TOML subset the
+    /// [module documentation](self) shows.
+    pub fn from_toml(text: &str) -> Result<Self, ConfigError> {
+        let mut config = Self::default();
+        let mut seen = Vec::new();
+        let mut table: Option<Table> = None;
+        for (index, line) in text.lines().enumerate() {
+            let line_no = index + 1;
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let header = line
+                .strip_prefix("[[")
+                .and_then(|rest| rest.strip_suffix("]]"))
+                .map(|name| (name, true))
+                .or_else(|| {
+                    line.strip_prefix('[')
+                        .and_then(|rest| rest.strip_suffix(']'))
+                        .map(|name| (name, false))
+                });
+            if let Some((name, array)) = header {
+                if let Some(done) = table.take() {
+                    config.add(done)?;
+                }
+                let name = name.trim();
+                match (name, array) {
+                    ("cpu" | "bus" | "display", false) if seen.contains(&name) => {
+                        return Err(error(line_no, ConfigErrorKind::DuplicateTable(name.into())));
+                    }
+                    ("cpu" | "bus" | "display", false) => seen.push(name),
+                    ("ram" | "rom" | "mirror" | "device", true) => {}
+                    _ => return Err(error(line_no, ConfigErrorKind::UnknownTable(name.into()))),
+                }
+                table = Some(Table {
+                    name: name.into(),
+                    line: line_no,
+                    entries: Vec::new(),
+                });
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or(error(line_no, ConfigErrorKind::Syntax))?;
+            let key = key.trim();
+            let Some(table) = &mut table else {
+                return Err(error(line_no, ConfigErrorKind::UnknownKey(key.into())));
+            };
+            if table.entries.iter().any(|(k, ..)| k == key) {
+                return Err(error(line_no, ConfigErrorKind::DuplicateKey(key.into())));
+            }
+            let value = parse_value(value.trim())
+                .ok_or(error(line_no, ConfigErrorKind::BadValue(key.into())))?;
+            table.entries.push((key.into(), value, line_no));
+        }
+        if let Some(done) = table {
+            config.add(done)?;
+        }
+        Ok(config)
+    }
+
+    /// Adds what a finished table describes.
+    fn add(&mut self, mut table: Table) -> Result<(), ConfigError> {
+        match table.name.as_str() {
+            "cpu" => {
+                self.cpu.clock_hz = table.int("clock_hz")?;
+                if self.cpu.clock_hz.is_some_and(|hz| hz < FRAME_RATE) {
+                    return Err(error(
+                        table.line,
+                        ConfigErrorKind::BadValue("clock_hz".into()),
+                    ));
+                }
+                if let Some((illegal, line)) = table.string("illegal_opcodes")? {
+                    self.cpu.illegal_opcodes = match illegal.as_str() {
+                        "trap" => IllegalOpcodes::Trap,
+                        "nop" => IllegalOpcodes::Nop,
+                        "undocumented" => IllegalOpcodes::Undocumented,
+                        _ => {
+                            return Err(error(
+                                line,
+                                ConfigErrorKind::BadValue("illegal_opcodes".into()),
+                            ));
+                        }
+                    };
+                }
+            }
+            "bus" => {
+                if let Some((read, line)) = table.string("unmapped_read")? {
+                    self.bus.unmapped_read = match read.as_str() {
+                        "open-bus" => UnmappedRead::OpenBus,
+                        "zero" => UnmappedRead::Zero,
+                        "trap" => UnmappedRead::Trap,
+                        _ => {
+                            return Err(error(
+                                line,
+                                ConfigErrorKind::BadValue("unmapped_read".into()),
+                            ));
+                        }
+                    };
+                }
+                if let Some((write, line)) = table.string("unmapped_write")? {
+                    self.bus.unmapped_write = match write.as_str() {
+                        "ignore" => UnmappedWrite::Ignore,
+                        "trap" => UnmappedWrite::Trap,
+                        _ => {
+                            return Err(error(
+                                line,
+                                ConfigErrorKind::BadValue("unmapped_write".into()),
+                            ));
+                        }
+                    };
+                }
+                if let Some((write, line)) = table.string("rom_write")? {
+                    self.bus.rom_write = match write.as_str() {
+                        "ignore" => RomWrite::Ignore,
+                        "trap" => RomWrite::Trap,
+                        _ => {
+                            return Err(error(line, ConfigErrorKind::BadValue("rom_write".into())));
+                        }
+                    };
+                }
+            }
+            "display" => {
+                if let Some((format, line)) = table.string("format")? {
+                    self.display.format = PixelFormat::from_name(&format)
+                        .ok_or(error(line, ConfigErrorKind::BadValue("format".into())))?;
+                }
+                if let Some(scale) = table.int("scale")? {
+                    if scale == 0 {
+                        return Err(error(table.line, ConfigErrorKind::BadValue("scale".into())));
+                    }
+                    self.display.scale = scale;
+                }
+            }
+            "ram" => self.ram.push(table.region()?),
+            "rom" => self.rom.push(table.region()?),
+            "mirror" => self.mirrors.push(table.mirror()?),
+            _ => self.devices.push(table.device()?),
+        }
+        table.finish()
+    }
+}
+
+impl Vm {
+    /// Builds the board `config` describes, with zeroed memory and the
+    /// reset vector pointing at 0x0000 as for [`Vm::new`].
+    ///
+    /// Fails with [`VmError::MapConflict`] if two devices overlap, or
+    /// [`VmError::OutOfBounds`] if a device or mirror runs past 0xFFFF.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `clock_hz` is below [`FRAME_RATE`] or the display scale is
+    /// 0, both of which [`MachineConfig::from_toml`] rejects.
+    pub fn with_config(config: &MachineConfig) -> Result<Self, VmError> {
+        let mut vm = Self::new();
+        vm.set_cpu_config(config.cpu);
+        vm.set_display_config(config.display);
+        vm.bus_mut().unmap(*INPUT_PORTS.start());
+        let bus = vm.bus_mut();
+        bus.set_config(config.bus);
+        bus.unmap_ram(0x0000..=0xFFFF);
+        for region in config.ram.iter().chain(&config.rom) {
+            bus.map_ram(region.range());
+        }
+        for region in &config.rom {
+            let pages = region.start as usize / PAGE_SIZE..=region.end as usize / PAGE_SIZE;
+            vm.cpu.rom_pages[pages].fill(1);
+        }
+        vm.bus_mut().pages_dirty = true;
+        for region in config.ram.iter().chain(&config.rom) {
+            if region.wait > 0 {
+                vm.set_wait_states(region.range(), region.wait);
+            }
+        }
+        for mirror in &config.mirrors {
+            vm.mirror(mirror.start..=mirror.end, mirror.target)?;
+        }
+        for device in &config.devices {
+            device.map(&mut vm)?;
+        }
+        Ok(vm)
+    }
+
+    /// Makes the CPU's accesses to the 256-byte pages covering `range`
+    /// decode to the pages from `target` on, so both reach the same RAM,
+    /// ROM or device. Watchpoints and hooks see the decoded address, and
+    /// DMA and [`Vm::read_mem`] decode as the CPU does. [`Vm::read`] and
+    /// [`Vm::memory`] see the backing store, undecoded.
+    ///
+    /// Fails with [`VmError::OutOfBounds`], changing nothing, if the
+    /// target pages would run past 0xFFFF.
+    pub fn mirror(&mut self, range: RangeInclusive<u16>, target: u16) -> Result<(), VmError> {
+        let first = *range.start() as usize / PAGE_SIZE;
+        let last = *range.end() as usize / PAGE_SIZE;
+        let target_page = target as usize / PAGE_SIZE;
+        if target_page + (last - first) >= 256 {
+            return Err(VmError::OutOfBounds {
+                addr: target,
+                len: (last - first + 1) * PAGE_SIZE,
+            });
+        }
+        for (offset, page) in (first..=last).enumerate() {
+            self.cpu.page_map[page] = (target_page + offset) as u8;
+        }
+        Ok(())
+    }
+}
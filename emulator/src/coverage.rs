@@ -0,0 +1,237 @@
+//! Code coverage of emulated programs.
+//!
+//! While coverage is enabled, [`Vm::step`] marks the address of every
+//! instruction it executes, and also notes which of them start a basic
+//! block: an instruction reached other than by falling through from the
+//! previous one, as after reset, an interrupt or the host moving the PC.
+//! The collected [`Coverage`] accumulates across resets, can be merged with
+//! that of other machines, and dumps as a raw address bitmap or as a
+//! disassembly listing with the executed instructions marked:
+//!
+//! ```text
+//! * C000  A9 42     LDA #$42
+//!   C002  4E 10 C0  LSR $C010
+//! ; 1 of 2 instructions executed (50.0%)
+//! ```
+//!
+//! Interrupt entry executes no instruction and is not marked, and neither is
+//! an illegal opcode.
+
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use crate::disasm::{instruction_size, lookup};
+use crate::vm::Vm;
+
+const BITMAP_BYTES: usize = 0x10000 / 8;
+
+/// One bit per address.
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) struct Bitmap(Box<[u8; BITMAP_BYTES]>);
+
+impl Bitmap {
+    pub(crate) fn new() -> Self {
+        Self(Box::new([0; BITMAP_BYTES]))
+    }
+
+    pub(crate) fn get(&self, addr: u16) -> bool {
+        self.0[addr as usize / 8] & 1 << (addr % 8) != 0
+    }
+
+    pub(crate) fn set(&mut self, addr: u16) {
+        self.0[addr as usize / 8] |= 1 << (addr % 8);
+    }
+
+    fn union(&mut self, other: &Bitmap) {
+        for (a, b) in self.0.iter_mut().zip(other.0.iter()) {
+            *a |= b;
+        }
+    }
+}
+
+/// Instructions executed so far.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Coverage {
+    executed: Bitmap,
+    leaders: Bitmap,
+    /// Where the last executed instruction falls through to.
+    next: Option<u16>,
+}
+
+impl Coverage {
+    /// Nothing executed yet.
+    pub fn new() -> Self {
+        Self {
+            executed: Bitmap::new(),
+            leaders: Bitmap::new(),
+            next: None,
+        }
+    }
+
+    /// Whether an instruction starting at `addr` has executed.
+    pub fn is_executed(&self, addr: u16) -> bool {
+        self.executed.get(addr)
+    }
+
+    /// Addresses of the executed instructions, ascending.
+    pub fn executed(&self) -> impl Iterator<Item = u16> + '_ {
+        (0..=u16::MAX).filter(|&addr| self.executed.get(addr))
+    }
+
+    /// Number of distinct instruction addresses executed.
+    pub fn len(&self) -> usize {
+        self.executed
+            .0
+            .iter()
+            .map(|b| b.count_ones() as usize)
+            .sum()
+    }
+
+    /// Whether nothing has executed.
+    pub fn is_empty(&self) -> bool {
+        self.executed.0.iter().all(|&b| b == 0)
+    }
+
+    /// Executed basic blocks in address order, each the range from its
+    /// first instruction's address to its last one's.
+    ///
+    /// A block ends at the last executed instruction before the next block
+    /// start or the first instruction that never ran.
+    pub fn blocks(&self, vm: &Vm) -> Vec<RangeInclusive<u16>> {
+        let mut blocks = Vec::new();
+        for start in (0..=u16::MAX).filter(|&addr| self.leaders.get(addr)) {
+            let mut end = start;
+            loop {
+                let next = end.wrapping_add(u16::from(vm.disassemble(end).size));
+                if next <= end || !self.executed.get(next) || self.leaders.get(next) {
+                    break;
+                }
+                end = next;
+            }
+            blocks.push(start..=end);
+        }
+        blocks
+    }
+
+    /// Adds everything `other` has seen, e.g. to combine the runs of a test
+    /// suite.
+    pub fn merge(&mut self, other: &Coverage) {
+        self.executed.union(&other.executed);
+        self.leaders.union(&other.leaders);
+    }
+
+    /// The raw bitmap: bit `addr % 8` of byte `addr / 8` is set if the
+    /// instruction at `addr` executed.
+    pub fn bitmap(&self) -> &[u8] {
+        &self.executed.0[..]
+    }
+
+    /// Writes [`Coverage::bitmap`] to `writer`.
+    pub fn write_bitmap(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(self.bitmap())
+    }
+
+    /// Disassembles `range` from `vm`'s memory, marking executed
+    /// instructions with `*`, and ends with a summary line.
+    ///
+    /// Decoding follows the executed addresses, so code is listed at the
+    /// offsets it actually ran from; bytes that would hide an executed
+    /// instruction inside an operand are listed as data.
+    pub fn listing(&self, vm: &Vm, range: RangeInclusive<u16>) -> String {
+        let mut out = String::new();
+        let (mut executed, mut total) = (0, 0);
+        let mut addr = u32::from(*range.start());
+        let end = u32::from(*range.end());
+        while addr <= end {
+            let pc = addr as u16;
+            let instr = vm.disassemble(pc);
+            let size = u32::from(instr.size);
+            let hides_code = (1..size).any(|i| self.executed.get(pc.wrapping_add(i as u16)));
+            if !self.executed.get(pc) && hides_code {
+                let byte = vm.read(pc);
+                writeln!(out, "  {pc:04X}  {byte:02X}        .byte ${byte:02X}").unwrap();
+                addr += 1;
+                continue;
+            }
+            let hex: Vec<String> = (0..size)
+                .map(|i| format!("{:02X}", vm.read(pc.wrapping_add(i as u16))))
+                .collect();
+            let mark = if self.executed.get(pc) {
+                executed += 1;
+                '*'
+            } else {
+                ' '
+            };
+            total += 1;
+            writeln!(out, "{mark} {pc:04X}  {:<8}  {instr}", hex.join(" ")).unwrap();
+            addr += size;
+        }
+        let percent = if total == 0 {
+            0.0
+        } else {
+            100.0 * f64::from(executed) / f64::from(total)
+        };
+        writeln!(
+            out,
+            "; {executed} of {total} instructions executed ({percent:.1}%)"
+        )
+        .unwrap();
+        out
+    }
+
+    /// Marks the instruction at `pc`, `opcode` being its first byte.
+    fn record(&mut self, pc: u16, opcode: u8) {
+        if self.next != Some(pc) {
+            self.leaders.set(pc);
+        }
+        self.executed.set(pc);
+        let size = lookup(opcode).map_or(1, |op| instruction_size(op.mode));
+        self.next = Some(pc.wrapping_add(u16::from(size)));
+    }
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for Coverage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Coverage")
+            .field("executed", &self.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Vm {
+    /// Starts collecting coverage, keeping what was collected so far.
+    pub fn enable_coverage(&mut self) {
+        self.coverage.get_or_insert_with(Coverage::new);
+    }
+
+    /// Stops collecting coverage and hands back what was collected.
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
+    /// Coverage collected so far, if enabled.
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Called by [`Vm::step`] with the address of the instruction it
+    /// executed, or `None` if it entered an interrupt or hit an illegal
+    /// opcode instead.
+    pub(crate) fn record_coverage(&mut self, pc: Option<u16>) {
+        let opcode = pc.map(|pc| self.read(pc));
+        let Some(coverage) = &mut self.coverage else {
+            return;
+        };
+        match pc.zip(opcode) {
+            Some((pc, opcode)) => coverage.record(pc, opcode),
+            None => coverage.next = None,
+        }
+    }
+}
@@ -0,0 +1,888 @@
+//! Debug Adapter Protocol server.
+//!
+//! [`Vm::serve_dap`] waits for an editor on a TCP socket and acts as its
+//! debug adapter, so VS Code and other DAP clients can debug an rvm-8
+//! program with their own UI. VS Code connects to a running adapter through
+//! `debugServer` in `launch.json`:
+//!
+//! ```json
+//! {
+//!     "type": "rvm8",
+//!     "request": "launch",
+//!     "name": "Debug demo",
+//!     "program": "${workspaceFolder}/demo.asm",
+//!     "stopOnEntry": true,
+//!     "debugServer": 4711
+//! }
+//! ```
+//!
+//! `launch` loads `program` and resets into it: a `.rvm` ROM, or assembly
+//! source (`.asm` or `.s`), which is assembled and then debugged at the
+//! source level, with its labels as symbols. `attach`, or `launch` without
+//! a program, debugs whatever the machine already runs. Either way the
+//! program starts once the client has sent its breakpoints, or stays stopped
+//! on entry with `stopOnEntry`.
+//!
+//! The adapter offers:
+//!
+//! * source breakpoints in the launched assembly, function breakpoints on
+//!   symbols or addresses, and instruction breakpoints from the disassembly
+//!   view, all of them optionally [conditional](crate::debugger::Condition);
+//! * continue, pause, and stepping, where every step request executes one
+//!   instruction;
+//! * a `Registers` and a `Flags` scope, both writable;
+//! * `evaluate` for the condition expression language, so hovers and the
+//!   watch view show registers, symbols and `[addr]` memory reads;
+//! * memory reads and writes, which go through [`Vm::read`] and
+//!   [`Vm::write`] like the GDB stub's, and disassembly.
+//!
+//! VS Code only starts sessions for debug types an extension contributes,
+//! so `rvm8` needs a minimal one declaring it; with `debugServer` set, the
+//! extension needs no adapter of its own.
+//!
+//! The machine has a single thread, with id 1, and a single stack frame.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::Path;
+
+use crate::asm;
+use crate::debugger::Condition;
+use crate::ffi::{FLAG_B, FLAG_C, FLAG_D, FLAG_I, FLAG_N, FLAG_V, FLAG_Z};
+use crate::json::{Json, object};
+use crate::reset::ResetKind;
+use crate::rom::Rom;
+use crate::symbols::{SymbolTable, parse_value};
+use crate::vm::Vm;
+
+/// Instructions executed between checks for requests from the client.
+const POLL_INTERVAL: u32 = 4096;
+/// The machine's only thread.
+const THREAD_ID: i64 = 1;
+const REGISTERS_SCOPE: i64 = 1;
+const FLAGS_SCOPE: i64 = 2;
+const FLAGS: [(&str, u8); 7] = [
+    ("N", FLAG_N),
+    ("V", FLAG_V),
+    ("B", FLAG_B),
+    ("D", FLAG_D),
+    ("I", FLAG_I),
+    ("Z", FLAG_Z),
+    ("C", FLAG_C),
+];
+
+/// Why the program stopped, as reported to the client.
+#[derive(Debug, Clone)]
+enum Stop {
+    Entry,
+    Step,
+    Breakpoint,
+    /// A watchpoint set by the host fired.
+    Data,
+    Pause,
+    /// An instruction failed.
+    Exception(String),
+}
+
+/// Where the breakpoints the client set came from. Each kind is replaced
+/// wholesale by its own request.
+#[derive(Debug, Clone, Copy)]
+enum Origin {
+    Source,
+    Function,
+    Instruction,
+}
+
+/// Assembly source the session launched.
+struct Source {
+    path: String,
+    /// Address of each instruction line.
+    lines: BTreeMap<usize, u16>,
+}
+
+struct Session<'a> {
+    vm: &'a mut Vm,
+    stream: TcpStream,
+    /// Bytes received but not yet parsed into a message.
+    input: Vec<u8>,
+    seq: i64,
+    source: Option<Source>,
+    breakpoints: [BTreeMap<u16, Option<Condition>>; 3],
+    /// Breakpoints the session set on the machine.
+    installed: BTreeSet<u16>,
+    stop_on_entry: bool,
+    /// Whether `launch` or `attach`, and `configurationDone`, arrived.
+    launched: bool,
+    configured: bool,
+    running: bool,
+    done: bool,
+}
+
+impl Vm {
+    /// Listens on `addr`, waits for one client to connect and serves it
+    /// with [`Vm::run_dap_session`].
+    pub fn serve_dap(&mut self, addr: impl ToSocketAddrs) -> io::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, _) = listener.accept()?;
+        self.run_dap_session(stream)
+    }
+
+    /// Serves a connected client until it disconnects or hangs up. The
+    /// machine is left wherever it stopped, and breakpoints the client set
+    /// are cleared.
+    pub fn run_dap_session(&mut self, stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        let mut session = Session {
+            vm: self,
+            stream,
+            input: Vec::new(),
+            seq: 0,
+            source: None,
+            breakpoints: Default::default(),
+            installed: BTreeSet::new(),
+            stop_on_entry: false,
+            launched: false,
+            configured: false,
+            running: false,
+            done: false,
+        };
+        let result = session.run();
+        session.breakpoints = Default::default();
+        session.sync_breakpoints();
+        result
+    }
+}
+
+impl Session<'_> {
+    fn run(&mut self) -> io::Result<()> {
+        while !self.done {
+            if self.running {
+                self.run_slice()?;
+                continue;
+            }
+            match self.read_message()? {
+                Some(message) => self.handle(&message)?,
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs up to [`POLL_INTERVAL`] instructions, then handles whatever the
+    /// client sent meanwhile.
+    fn run_slice(&mut self) -> io::Result<()> {
+        for _ in 0..POLL_INTERVAL {
+            if let Some(stop) = self.step() {
+                self.running = false;
+                return self.stopped(stop);
+            }
+        }
+        if !self.poll()? {
+            self.done = true;
+            return Ok(());
+        }
+        while self.running {
+            let Some(message) = self.take_message() else {
+                break;
+            };
+            self.handle(&message)?;
+        }
+        Ok(())
+    }
+
+    /// Executes one instruction, returning why the program should stop
+    /// after it, if it should.
+    fn step(&mut self) -> Option<Stop> {
+        if let Err(err) = self.vm.step() {
+            return Some(Stop::Exception(err.to_string()));
+        }
+        if self.vm.bus_mut().watch_hit.take().is_some() {
+            return Some(Stop::Data);
+        }
+        self.vm
+            .breakpoint_hit(self.vm.registers().pc)
+            .then_some(Stop::Breakpoint)
+    }
+
+    /// Parses the first complete message out of the input. Messages whose
+    /// content is not JSON are dropped.
+    fn take_message(&mut self) -> Option<Json> {
+        loop {
+            let end = self.input.windows(4).position(|w| w == b"\r\n\r\n")?;
+            let header = String::from_utf8_lossy(&self.input[..end]);
+            let length = header.lines().find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                let name = name.trim().eq_ignore_ascii_case("Content-Length");
+                name.then(|| value.trim().parse::<usize>().ok()).flatten()
+            });
+            let start = end + 4;
+            let Some(length) = length else {
+                self.input.drain(..start);
+                continue;
+            };
+            if self.input.len() < start + length {
+                return None;
+            }
+            let content: Vec<u8> = self.input.drain(..start + length).skip(start).collect();
+            if let Some(message) = std::str::from_utf8(&content).ok().and_then(Json::parse) {
+                return Some(message);
+            }
+        }
+    }
+
+    /// Waits for the next message. Returns `None` when the client hangs up.
+    fn read_message(&mut self) -> io::Result<Option<Json>> {
+        let mut buffer = [0; 4096];
+        loop {
+            if let Some(message) = self.take_message() {
+                return Ok(Some(message));
+            }
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Ok(None),
+                Ok(n) => self.input.extend_from_slice(&buffer[..n]),
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Reads whatever the client sent without waiting, returning `false` if
+    /// it hung up.
+    fn poll(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut buffer = [0; 4096];
+        let result = loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => break Ok(false),
+                Ok(n) => self.input.extend_from_slice(&buffer[..n]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break Ok(true),
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => break Err(err),
+            }
+        };
+        self.stream.set_nonblocking(false)?;
+        result
+    }
+
+    fn send(&mut self, kind: &str, mut fields: Vec<(String, Json)>) -> io::Result<()> {
+        self.seq += 1;
+        fields.splice(
+            0..0,
+            [
+                ("seq".into(), self.seq.into()),
+                ("type".into(), kind.into()),
+            ],
+        );
+        let content = Json::Object(fields).to_string();
+        write!(
+            self.stream,
+            "Content-Length: {}\r\n\r\n{content}",
+            content.len()
+        )?;
+        self.stream.flush()
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> io::Result<()> {
+        let fields = vec![("event".into(), event.into()), ("body".into(), body)];
+        self.send("event", fields)
+    }
+
+    fn stopped(&mut self, stop: Stop) -> io::Result<()> {
+        let (reason, text) = match stop {
+            Stop::Entry => ("entry", None),
+            Stop::Step => ("step", None),
+            Stop::Breakpoint => ("breakpoint", None),
+            Stop::Data => ("data breakpoint", None),
+            Stop::Pause => ("pause", None),
+            Stop::Exception(text) => ("exception", Some(text)),
+        };
+        let body = object([
+            ("reason", reason.into()),
+            ("threadId", THREAD_ID.into()),
+            ("allThreadsStopped", true.into()),
+            ("text", text.into()),
+        ]);
+        self.event("stopped", body)
+    }
+
+    fn handle(&mut self, request: &Json) -> io::Result<()> {
+        if request.get("type").as_str() != Some("request") {
+            return Ok(());
+        }
+        let command = request.get("command").as_str().unwrap_or_default();
+        let args = request.get("arguments");
+        let result = match command {
+            "initialize" => Ok(capabilities()),
+            "launch" => self.launch(args),
+            "attach" => Ok(self.attach(args)),
+            "configurationDone" => Ok(Json::Null),
+            "setBreakpoints" => Ok(self.set_source_breakpoints(args)),
+            "setFunctionBreakpoints" => Ok(self.set_function_breakpoints(args)),
+            "setInstructionBreakpoints" => Ok(self.set_instruction_breakpoints(args)),
+            "setExceptionBreakpoints" => Ok(object([("breakpoints", Json::Array(Vec::new()))])),
+            "threads" => Ok(object([(
+                "threads",
+                vec![object([("id", THREAD_ID.into()), ("name", "cpu".into())])].into(),
+            )])),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(scopes()),
+            "variables" => Ok(self.variables(args)),
+            "setVariable" => self.set_variable(args),
+            "evaluate" => self.evaluate(args),
+            "readMemory" => self.read_memory(args),
+            "writeMemory" => self.write_memory(args),
+            "disassemble" => self.disassemble(args),
+            "continue" => Ok(object([("allThreadsContinued", true.into())])),
+            "next" | "stepIn" | "stepOut" | "pause" | "disconnect" | "terminate" => Ok(Json::Null),
+            _ => Err(format!("unsupported request `{command}`")),
+        };
+
+        let mut fields = vec![
+            ("request_seq".into(), request.get("seq").clone()),
+            ("success".into(), result.is_ok().into()),
+            ("command".into(), command.into()),
+        ];
+        match result {
+            Ok(body) => fields.push(("body".into(), body)),
+            Err(message) => {
+                fields.push(("message".into(), message.into()));
+                return self.send("response", fields);
+            }
+        }
+        self.send("response", fields)?;
+
+        match command {
+            "initialize" => self.event("initialized", Json::Null),
+            "launch" | "attach" => {
+                self.launched = true;
+                self.start()
+            }
+            "configurationDone" => {
+                self.configured = true;
+                self.start()
+            }
+            "continue" => {
+                self.running = true;
+                Ok(())
+            }
+            "next" | "stepIn" | "stepOut" => {
+                let stop = match self.step() {
+                    Some(Stop::Exception(text)) => Stop::Exception(text),
+                    _ => Stop::Step,
+                };
+                self.stopped(stop)
+            }
+            "pause" => {
+                self.running = false;
+                self.stopped(Stop::Pause)
+            }
+            "disconnect" => {
+                self.done = true;
+                Ok(())
+            }
+            "terminate" => {
+                self.done = true;
+                self.event("terminated", Json::Null)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Starts the program once the client is both launched and configured.
+    fn start(&mut self) -> io::Result<()> {
+        if !(self.launched && self.configured) {
+            return Ok(());
+        }
+        if self.stop_on_entry {
+            self.stopped(Stop::Entry)
+        } else {
+            self.running = true;
+            Ok(())
+        }
+    }
+
+    fn launch(&mut self, args: &Json) -> Result<Json, String> {
+        if let Some(path) = args.get("program").as_str() {
+            let extension = Path::new(path).extension().and_then(|e| e.to_str());
+            let is_source = extension
+                .is_some_and(|e| e.eq_ignore_ascii_case("asm") || e.eq_ignore_ascii_case("s"));
+            if is_source {
+                let text = std::fs::read_to_string(path).map_err(|err| format!("{path}: {err}"))?;
+                let program = asm::assemble(&text).map_err(|err| format!("{path}: {err}"))?;
+                self.vm
+                    .load_assembly(&program)
+                    .map_err(|err| format!("{path}: {err}"))?;
+                self.vm.set_symbols(SymbolTable::from(&program));
+                self.source = Some(Source {
+                    path: path.into(),
+                    lines: program.lines,
+                });
+            } else {
+                let rom = Rom::from_file(path).map_err(|err| format!("{path}: {err}"))?;
+                self.vm
+                    .load_rom(&rom)
+                    .map_err(|err| format!("{path}: {err}"))?;
+            }
+            self.vm.reset(ResetKind::Soft);
+        }
+        Ok(self.attach(args))
+    }
+
+    fn attach(&mut self, args: &Json) -> Json {
+        self.stop_on_entry = args.get("stopOnEntry").as_bool().unwrap_or(false);
+        Json::Null
+    }
+
+    fn symbols(&self) -> SymbolTable {
+        self.vm.symbols().cloned().unwrap_or_default()
+    }
+
+    /// Parses a breakpoint's optional `condition`.
+    fn condition(&self, breakpoint: &Json) -> Result<Option<Condition>, String> {
+        match breakpoint.get("condition").as_str() {
+            Some(text) if !text.trim().is_empty() => {
+                let condition = Condition::parse_with_symbols(text, &self.symbols());
+                condition.map(Some).map_err(|err| err.to_string())
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Replaces the breakpoints from `origin` with the ones `requested`
+    /// resolves to, answering with a `breakpoints` body.
+    fn set_breakpoints(
+        &mut self,
+        origin: Origin,
+        requested: &[Json],
+        mut resolve: impl FnMut(&Self, &Json) -> Result<(u16, Json), String>,
+    ) -> Json {
+        let mut set = BTreeMap::new();
+        let mut results = Vec::new();
+        for breakpoint in requested {
+            let resolved = resolve(self, breakpoint)
+                .and_then(|(addr, fields)| Ok((addr, fields, self.condition(breakpoint)?)));
+            results.push(match resolved {
+                Ok((addr, fields, condition)) => {
+                    set.insert(addr, condition);
+                    let mut fields = match fields {
+                        Json::Object(fields) => fields,
+                        _ => Vec::new(),
+                    };
+                    fields.insert(0, ("verified".into(), true.into()));
+                    fields.push(("instructionReference".into(), reference(addr).into()));
+                    Json::Object(fields)
+                }
+                Err(message) => object([("verified", false.into()), ("message", message.into())]),
+            });
+        }
+        self.breakpoints[origin as usize] = set;
+        self.sync_breakpoints();
+        object([("breakpoints", results.into())])
+    }
+
+    /// Makes the machine's breakpoints those the client set, leaving alone
+    /// those the host set on other addresses. Where the client set several
+    /// on one address, an unconditional one wins.
+    fn sync_breakpoints(&mut self) {
+        for addr in std::mem::take(&mut self.installed) {
+            self.vm.remove_breakpoint(addr);
+        }
+        let mut wanted: BTreeMap<u16, Option<&Condition>> = BTreeMap::new();
+        for (&addr, condition) in self.breakpoints.iter().flatten() {
+            let entry = wanted.entry(addr).or_insert(condition.as_ref());
+            if condition.is_none() {
+                *entry = None;
+            }
+        }
+        for (addr, condition) in wanted {
+            match condition {
+                Some(condition) => self.vm.add_conditional_breakpoint(addr, condition.clone()),
+                None => self.vm.add_breakpoint(addr),
+            };
+            self.installed.insert(addr);
+        }
+    }
+
+    fn set_source_breakpoints(&mut self, args: &Json) -> Json {
+        let path = args.get("source").get("path").as_str().unwrap_or_default();
+        let known = self
+            .source
+            .as_ref()
+            .is_some_and(|source| same_file(&source.path, path));
+        self.set_breakpoints(
+            Origin::Source,
+            args.get("breakpoints").as_array(),
+            |session, breakpoint| {
+                let source = session.source.as_ref().filter(|_| known);
+                let source = source.ok_or("not the launched program")?;
+                let line = breakpoint.get("line").as_i64().unwrap_or(0).max(0) as usize;
+                let (&line, &addr) = source
+                    .lines
+                    .range(line..)
+                    .next()
+                    .ok_or("no code at or after this line")?;
+                Ok((addr, object([("line", (line as i64).into())])))
+            },
+        )
+    }
+
+    fn set_function_breakpoints(&mut self, args: &Json) -> Json {
+        self.set_breakpoints(
+            Origin::Function,
+            args.get("breakpoints").as_array(),
+            |session, breakpoint| {
+                let name = breakpoint.get("name").as_str().unwrap_or_default().trim();
+                let addr = session
+                    .vm
+                    .symbols()
+                    .and_then(|symbols| symbols.address(name));
+                let addr = addr.or_else(|| parse_value(name));
+                let addr = addr.ok_or_else(|| format!("`{name}` is not a symbol or address"))?;
+                Ok((addr, Json::Null))
+            },
+        )
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Json) -> Json {
+        self.set_breakpoints(
+            Origin::Instruction,
+            args.get("breakpoints").as_array(),
+            |_, breakpoint| {
+                let addr = address(breakpoint, "instructionReference", "offset")
+                    .ok_or("not an address")?;
+                Ok((addr, Json::Null))
+            },
+        )
+    }
+
+    /// The source line of the instruction at `addr`, if the launched
+    /// assembly has it.
+    fn line(&self, addr: u16) -> Option<(&Source, usize)> {
+        let source = self.source.as_ref()?;
+        let (&line, _) = source.lines.iter().find(|&(_, &at)| at == addr)?;
+        Some((source, line))
+    }
+
+    fn stack_trace(&self) -> Json {
+        let pc = self.vm.registers().pc;
+        let mut frame = vec![
+            ("id".into(), 0.into()),
+            ("name".into(), self.symbols().describe(pc).into()),
+            ("instructionPointerReference".into(), reference(pc).into()),
+        ];
+        let (line, source) = match self.line(pc) {
+            Some((source, line)) => (line as i64, Some(source_json(&source.path))),
+            None => (0, None),
+        };
+        frame.push(("line".into(), line.into()));
+        frame.push(("column".into(), i64::from(line != 0).into()));
+        if let Some(source) = source {
+            frame.push(("source".into(), source));
+        }
+        object([
+            ("stackFrames", vec![Json::Object(frame)].into()),
+            ("totalFrames", 1.into()),
+        ])
+    }
+
+    fn variables(&self, args: &Json) -> Json {
+        let regs = self.vm.registers();
+        let variable = |name: &str, value: String, memory: Option<u16>| {
+            let mut fields = vec![
+                ("name".into(), name.into()),
+                ("value".into(), value.into()),
+                ("variablesReference".into(), 0.into()),
+            ];
+            if let Some(addr) = memory {
+                fields.push(("memoryReference".into(), reference(addr).into()));
+            }
+            Json::Object(fields)
+        };
+        let variables = match args.get("variablesReference").as_i64() {
+            Some(REGISTERS_SCOPE) => vec![
+                variable("A", format!("${:02X}", regs.a), None),
+                variable("X", format!("${:02X}", regs.x), None),
+                variable("Y", format!("${:02X}", regs.y), None),
+                variable("P", format!("${:02X}", regs.flags), None),
+                variable("SP", format!("${:04X}", regs.sp), Some(regs.sp)),
+                variable("PC", format!("${:04X}", regs.pc), Some(regs.pc)),
+            ],
+            Some(FLAGS_SCOPE) => FLAGS
+                .iter()
+                .map(|&(name, bit)| {
+                    variable(name, u8::from(regs.flags & bit != 0).to_string(), None)
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        object([("variables", variables.into())])
+    }
+
+    fn set_variable(&mut self, args: &Json) -> Result<Json, String> {
+        let name = args.get("name").as_str().unwrap_or_default();
+        let text = args.get("value").as_str().unwrap_or_default().trim();
+        let value = parse_value(text).ok_or_else(|| format!("`{text}` is not a 16-bit value"))?;
+        let byte = || u8::try_from(value).map_err(|_| format!("`{text}` does not fit in {name}"));
+        let mut regs = self.vm.registers();
+        let shown = match (args.get("variablesReference").as_i64(), name) {
+            (Some(REGISTERS_SCOPE), "A") => {
+                regs.a = byte()?;
+                format!("${:02X}", regs.a)
+            }
+            (Some(REGISTERS_SCOPE), "X") => {
+                regs.x = byte()?;
+                format!("${:02X}", regs.x)
+            }
+            (Some(REGISTERS_SCOPE), "Y") => {
+                regs.y = byte()?;
+                format!("${:02X}", regs.y)
+            }
+            (Some(REGISTERS_SCOPE), "P") => {
+                regs.flags = byte()?;
+                format!("${:02X}", regs.flags)
+            }
+            (Some(REGISTERS_SCOPE), "SP") => {
+                regs.sp = value;
+                format!("${:04X}", regs.sp)
+            }
+            (Some(REGISTERS_SCOPE), "PC") => {
+                regs.pc = value;
+                format!("${:04X}", regs.pc)
+            }
+            (Some(FLAGS_SCOPE), name) => {
+                let &(_, bit) = FLAGS
+                    .iter()
+                    .find(|(flag, _)| *flag == name)
+                    .ok_or_else(|| format!("no flag `{name}`"))?;
+                match value {
+                    0 => regs.flags &= !bit,
+                    1 => regs.flags |= bit,
+                    _ => return Err(format!("flag {name} is 0 or 1")),
+                }
+                value.to_string()
+            }
+            _ => return Err(format!("no variable `{name}`")),
+        };
+        self.vm.set_registers(regs);
+        Ok(object([("value", shown.into())]))
+    }
+
+    fn evaluate(&self, args: &Json) -> Result<Json, String> {
+        let text = args.get("expression").as_str().unwrap_or_default();
+        let condition =
+            Condition::parse_with_symbols(text, &self.symbols()).map_err(|err| err.to_string())?;
+        let value = condition.eval(self.vm);
+        let result = match u16::try_from(value) {
+            Ok(value) => format!("{value} (${value:X})"),
+            Err(_) => value.to_string(),
+        };
+        Ok(object([
+            ("result", result.into()),
+            ("variablesReference", 0.into()),
+        ]))
+    }
+
+    fn read_memory(&self, args: &Json) -> Result<Json, String> {
+        let start = offset_address(args, "memoryReference", "offset").ok_or("not an address")?;
+        let count = args.get("count").as_i64().unwrap_or(0).max(0);
+        let readable = count.min(0x10000 - i64::from(start)) as usize;
+        let bytes: Vec<u8> = (0..readable)
+            .map(|i| self.vm.read(start + i as u16))
+            .collect();
+        Ok(object([
+            ("address", reference(start).into()),
+            ("data", base64(&bytes).into()),
+            ("unreadableBytes", (count - readable as i64).into()),
+        ]))
+    }
+
+    fn write_memory(&mut self, args: &Json) -> Result<Json, String> {
+        let start = offset_address(args, "memoryReference", "offset").ok_or("not an address")?;
+        let data = args.get("data").as_str().unwrap_or_default();
+        let bytes = unbase64(data).ok_or("data is not base64")?;
+        let writable = bytes.len().min(0x10000 - usize::from(start));
+        for (i, &byte) in bytes[..writable].iter().enumerate() {
+            self.vm.write(start + i as u16, byte);
+        }
+        Ok(object([("bytesWritten", (writable as i64).into())]))
+    }
+
+    fn disassemble(&self, args: &Json) -> Result<Json, String> {
+        let start = address(args, "memoryReference", "offset").ok_or("not an address")?;
+        let skip = args.get("instructionOffset").as_i64().unwrap_or(0);
+        let count = args.get("instructionCount").as_i64().unwrap_or(0).max(0) as usize;
+
+        // Instructions before `start` come from decoding forward from far
+        // enough back; any that cannot be found are padded as invalid.
+        let mut addrs: Vec<Option<u16>> = Vec::with_capacity(count);
+        let mut next = Some(start);
+        if skip < 0 {
+            let before = skip.unsigned_abs() as usize;
+            let mut earlier = Vec::new();
+            let mut addr = usize::from(start).saturating_sub(3 * before);
+            while addr < usize::from(start) {
+                earlier.push(Some(addr as u16));
+                addr += usize::from(self.vm.disassemble(addr as u16).size);
+            }
+            let found = earlier.len().min(before);
+            addrs.resize(before - found, None);
+            addrs.extend_from_slice(&earlier[earlier.len() - found..]);
+        } else {
+            for _ in 0..skip {
+                next = next.and_then(|addr| self.after(addr));
+            }
+        }
+        while addrs.len() < count {
+            addrs.push(next);
+            next = next.and_then(|addr| self.after(addr));
+        }
+        addrs.truncate(count);
+
+        let symbols = self.symbols();
+        let instructions = addrs
+            .into_iter()
+            .map(|addr| match addr {
+                Some(addr) => self.instruction(addr, &symbols),
+                None => object([
+                    ("address", reference(start).into()),
+                    ("instruction", "".into()),
+                    ("presentationHint", "invalid".into()),
+                ]),
+            })
+            .collect::<Vec<_>>();
+        Ok(object([("instructions", instructions.into())]))
+    }
+
+    /// The address after the instruction at `addr`, unless it leaves the
+    /// address space.
+    fn after(&self, addr: u16) -> Option<u16> {
+        addr.checked_add(u16::from(self.vm.disassemble(addr).size))
+    }
+
+    fn instruction(&self, addr: u16, symbols: &SymbolTable) -> Json {
+        let instr = self.vm.disassemble(addr);
+        let bytes = (0..instr.size)
+            .map(|i| format!("{:02X}", self.vm.read(addr.wrapping_add(u16::from(i)))))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mut fields = vec![
+            ("address".into(), reference(addr).into()),
+            ("instructionBytes".into(), bytes.into()),
+            (
+                "instruction".into(),
+                instr.with_symbols(symbols).to_string().into(),
+            ),
+        ];
+        if let Some(name) = symbols.name(addr) {
+            fields.push(("symbol".into(), name.into()));
+        }
+        if let Some((source, line)) = self.line(addr) {
+            fields.push(("location".into(), source_json(&source.path)));
+            fields.push(("line".into(), (line as i64).into()));
+        }
+        Json::Object(fields)
+    }
+}
+
+fn capabilities() -> Json {
+    object([
+        ("supportsConfigurationDoneRequest", true.into()),
+        ("supportsFunctionBreakpoints", true.into()),
+        ("supportsConditionalBreakpoints", true.into()),
+        ("supportsInstructionBreakpoints", true.into()),
+        ("supportsEvaluateForHovers", true.into()),
+        ("supportsSetVariable", true.into()),
+        ("supportsReadMemoryRequest", true.into()),
+        ("supportsWriteMemoryRequest", true.into()),
+        ("supportsDisassembleRequest", true.into()),
+        ("supportsSteppingGranularity", true.into()),
+        ("supportsTerminateRequest", true.into()),
+    ])
+}
+
+fn scopes() -> Json {
+    let scope = |name: &str, reference: i64| {
+        object([
+            ("name", name.into()),
+            ("variablesReference", reference.into()),
+            ("expensive", false.into()),
+        ])
+    };
+    object([(
+        "scopes",
+        vec![
+            scope("Registers", REGISTERS_SCOPE),
+            scope("Flags", FLAGS_SCOPE),
+        ]
+        .into(),
+    )])
+}
+
+fn source_json(path: &str) -> Json {
+    let name = Path::new(path).file_name().and_then(|name| name.to_str());
+    object([("name", name.unwrap_or(path).into()), ("path", path.into())])
+}
+
+fn same_file(a: &str, b: &str) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// An address as a memory or instruction reference.
+fn reference(addr: u16) -> String {
+    format!("0x{addr:04X}")
+}
+
+/// The reference in `args[field]` moved by `args[offset]` bytes, if it
+/// stays in the address space.
+fn offset_address(args: &Json, field: &str, offset: &str) -> Option<u16> {
+    let base = parse_value(args.get(field).as_str()?.trim())?;
+    let addr = i64::from(base) + args.get(offset).as_i64().unwrap_or(0);
+    u16::try_from(addr).ok()
+}
+
+/// As [`offset_address`], but wrapping around the address space.
+fn address(args: &Json, field: &str, offset: &str) -> Option<u16> {
+    let base = parse_value(args.get(field).as_str()?.trim())?;
+    let offset = args.get(offset).as_i64().unwrap_or(0);
+    Some((i64::from(base) + offset).rem_euclid(0x10000) as u16)
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let word = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |word, (i, &b)| word | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(word >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn unbase64(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut word, mut bits) = (0u32, 0);
+    for c in text.bytes() {
+        let digit = BASE64.iter().position(|&d| d == c)? as u32;
+        word = word << 6 | digit;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((word >> bits) as u8);
+        }
+    }
+    Some(out)
+}
@@ -0,0 +1,648 @@
+//! Breakpoints, watchpoints and run control for debugger frontends.
+//!
+//! A breakpoint can carry a [`Condition`], an expression over the registers
+//! and memory such as `A == 0x3F && [0x2000] != 0`, and then only stops the
+//! program when the expression is nonzero.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use crate::error::VmError;
+use crate::ffi::BusAccess;
+use crate::symbols::SymbolTable;
+use crate::vm::{CYCLES_PER_FRAME, Vm};
+
+/// Why [`Vm::run_until_break`] returned control to the caller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The PC reached a breakpoint. The instruction at `pc` has not executed.
+    Breakpoint { pc: u16 },
+    /// The instruction at `pc` made an access matching a watchpoint. It ran
+    /// to completion; only the first matching access is reported.
+    Watchpoint {
+        pc: u16,
+        addr: u16,
+        access: BusAccess,
+        value: u8,
+    },
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.with_symbols(&SymbolTable::new()).fmt(f)
+    }
+}
+
+/// Which accesses trigger a watchpoint.
+///
+/// Instruction fetches are bus reads, so a `Read` watchpoint over code fires
+/// when that code executes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    /// Either reads or writes.
+    Access,
+}
+
+impl WatchKind {
+    fn matches(self, access: BusAccess) -> bool {
+        matches!(
+            (self, access),
+            (Self::Access, _) | (Self::Read, BusAccess::Read) | (Self::Write, BusAccess::Write)
+        )
+    }
+}
+
+/// A watched address range.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub range: RangeInclusive<u16>,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    pub(crate) fn matches(&self, access: BusAccess, addr: u16) -> bool {
+        self.kind.matches(access) && self.range.contains(&addr)
+    }
+}
+
+/// A set of PC breakpoints, some with a [`Condition`].
+///
+/// Stored as a bitmap over the whole address space so the run loop can test
+/// the current PC with a single load.
+#[derive(Debug, Clone)]
+pub struct Breakpoints {
+    bits: Box<[u64]>,
+    len: usize,
+    conditions: BTreeMap<u16, Condition>,
+}
+
+impl Breakpoints {
+    /// An empty set.
+    pub fn new() -> Self {
+        Self {
+            bits: vec![0; 65536 / 64].into_boxed_slice(),
+            len: 0,
+            conditions: BTreeMap::new(),
+        }
+    }
+
+    /// Adds `addr`, returning `false` if it was already present. Any
+    /// condition it had is kept.
+    pub fn insert(&mut self, addr: u16) -> bool {
+        let (word, bit) = Self::slot(addr);
+        let added = self.bits[word] & bit == 0;
+        self.bits[word] |= bit;
+        self.len += usize::from(added);
+        added
+    }
+
+    /// Adds `addr` with a condition, replacing any condition it had, and
+    /// returns `false` if it was already present.
+    pub fn insert_conditional(&mut self, addr: u16, condition: Condition) -> bool {
+        self.conditions.insert(addr, condition);
+        self.insert(addr)
+    }
+
+    /// Removes `addr` and its condition, returning `false` if it was not
+    /// present.
+    pub fn remove(&mut self, addr: u16) -> bool {
+        let (word, bit) = Self::slot(addr);
+        let removed = self.bits[word] & bit != 0;
+        self.bits[word] &= !bit;
+        self.len -= usize::from(removed);
+        self.conditions.remove(&addr);
+        removed
+    }
+
+    pub fn contains(&self, addr: u16) -> bool {
+        let (word, bit) = Self::slot(addr);
+        self.bits[word] & bit != 0
+    }
+
+    /// The condition of the breakpoint at `addr`, if it has one.
+    pub fn condition(&self, addr: u16) -> Option<&Condition> {
+        self.conditions.get(&addr)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.bits.fill(0);
+        self.len = 0;
+        self.conditions.clear();
+    }
+
+    /// Breakpoint addresses in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        (0..=u16::MAX).filter(|&addr| self.contains(addr))
+    }
+
+    fn slot(addr: u16) -> (usize, u64) {
+        (addr as usize / 64, 1 << (addr % 64))
+    }
+}
+
+impl Default for Breakpoints {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A breakpoint condition, or the expression of a
+/// [watch](crate::watches).
+///
+/// The syntax is a small subset of C expressions over signed 64-bit
+/// integers:
+///
+/// * numbers in decimal, hex (`0x3F` or `$3F`) or binary (`%101`);
+/// * the registers `A`, `X`, `Y`, `P`, `SP` and `PC`, in either case;
+/// * `[addr]`, the byte of RAM at `addr` as [`Vm::read`] sees it, so
+///   evaluating a condition never touches a device;
+/// * symbol names, standing for their address, when parsed with
+///   [`Condition::parse_with_symbols`]; register names win over symbols;
+/// * unary `!`, `~` and `-`, then `+ -`, `& ^ |`, the comparisons
+///   `== != < <= > >=`, `&&` and `||` from tightest to loosest, and
+///   parentheses. Comparisons and logical operators yield 0 or 1.
+///
+/// Flags are tested through `P`, as in `P & 0x02` for the zero flag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    source: String,
+    expr: Expr,
+}
+
+/// Why a [`Condition`] failed to parse.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConditionError {
+    /// 1-based character position of the offending token.
+    pub column: usize,
+    pub kind: ConditionErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConditionErrorKind {
+    /// A character that starts no token.
+    UnexpectedChar(char),
+    /// A token where something else was required.
+    Expected(&'static str),
+    /// The text ended in the middle of an expression.
+    UnexpectedEnd,
+    BadNumber(String),
+    /// Neither a register nor a known symbol.
+    UnknownName(String),
+}
+
+impl fmt::Display for ConditionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: ", self.column)?;
+        match &self.kind {
+            ConditionErrorKind::UnexpectedChar(c) => write!(f, "unexpected `{c}`"),
+            ConditionErrorKind::Expected(what) => write!(f, "expected {what}"),
+            ConditionErrorKind::UnexpectedEnd => write!(f, "unexpected end of condition"),
+            ConditionErrorKind::BadNumber(text) => write!(f, "`{text}` is not a number"),
+            ConditionErrorKind::UnknownName(name) => {
+                write!(f, "`{name}` is not a register or symbol")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConditionError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Register {
+    A,
+    X,
+    Y,
+    P,
+    Sp,
+    Pc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UnaryOp {
+    Not,
+    Complement,
+    Negate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Add,
+    Sub,
+    And,
+    Xor,
+    Or,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    LogicalAnd,
+    LogicalOr,
+}
+
+impl BinaryOp {
+    /// Binding strength; higher binds tighter.
+    fn precedence(self) -> u8 {
+        match self {
+            Self::LogicalOr => 1,
+            Self::LogicalAnd => 2,
+            Self::Eq | Self::Ne | Self::Lt | Self::Le | Self::Gt | Self::Ge => 3,
+            Self::Or => 4,
+            Self::Xor => 5,
+            Self::And => 6,
+            Self::Add | Self::Sub => 7,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Number(i64),
+    Register(Register),
+    Memory(Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, vm: &Vm) -> i64 {
+        match self {
+            Self::Number(n) => *n,
+            Self::Register(reg) => {
+                let regs = vm.registers();
+                i64::from(match reg {
+                    Register::A => u16::from(regs.a),
+                    Register::X => u16::from(regs.x),
+                    Register::Y => u16::from(regs.y),
+                    Register::P => u16::from(regs.flags),
+                    Register::Sp => regs.sp,
+                    Register::Pc => regs.pc,
+                })
+            }
+            Self::Memory(addr) => i64::from(vm.read(addr.eval(vm) as u16)),
+            Self::Unary(op, operand) => {
+                let value = operand.eval(vm);
+                match op {
+                    UnaryOp::Not => i64::from(value == 0),
+                    UnaryOp::Complement => !value,
+                    UnaryOp::Negate => value.wrapping_neg(),
+                }
+            }
+            Self::Binary(BinaryOp::LogicalAnd, lhs, rhs) => {
+                i64::from(lhs.eval(vm) != 0 && rhs.eval(vm) != 0)
+            }
+            Self::Binary(BinaryOp::LogicalOr, lhs, rhs) => {
+                i64::from(lhs.eval(vm) != 0 || rhs.eval(vm) != 0)
+            }
+            Self::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.eval(vm), rhs.eval(vm));
+                match op {
+                    BinaryOp::Add => lhs.wrapping_add(rhs),
+                    BinaryOp::Sub => lhs.wrapping_sub(rhs),
+                    BinaryOp::And => lhs & rhs,
+                    BinaryOp::Xor => lhs ^ rhs,
+                    BinaryOp::Or => lhs | rhs,
+                    BinaryOp::Eq => i64::from(lhs == rhs),
+                    BinaryOp::Ne => i64::from(lhs != rhs),
+                    BinaryOp::Lt => i64::from(lhs < rhs),
+                    BinaryOp::Le => i64::from(lhs <= rhs),
+                    BinaryOp::Gt => i64::from(lhs > rhs),
+                    BinaryOp::Ge => i64::from(lhs >= rhs),
+                    BinaryOp::LogicalAnd | BinaryOp::LogicalOr => unreachable!(),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Unary(UnaryOp),
+    Binary(BinaryOp),
+    Open(char),
+    Close(char),
+}
+
+/// Splits `text` into tokens, each with its 1-based column.
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, ConditionError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let column = i + 1;
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if c.is_ascii_alphanumeric() || c == '_' || c == '$' || c == '%' {
+            let start = i;
+            i += 1;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            let token = if c.is_ascii_digit() || c == '$' || c == '%' {
+                let (digits, radix) = if let Some(hex) = word.strip_prefix('$') {
+                    (hex, 16)
+                } else if let Some(hex) = word.strip_prefix("0x") {
+                    (hex, 16)
+                } else if let Some(bin) = word.strip_prefix('%') {
+                    (bin, 2)
+                } else {
+                    (word.as_str(), 10)
+                };
+                let number = i64::from_str_radix(digits, radix).map_err(|_| ConditionError {
+                    column,
+                    kind: ConditionErrorKind::BadNumber(word.clone()),
+                })?;
+                Token::Number(number)
+            } else {
+                Token::Name(word)
+            };
+            tokens.push((column, token));
+            continue;
+        }
+        let (token, len) = match (c, next) {
+            ('=', Some('=')) => (Token::Binary(BinaryOp::Eq), 2),
+            ('!', Some('=')) => (Token::Binary(BinaryOp::Ne), 2),
+            ('<', Some('=')) => (Token::Binary(BinaryOp::Le), 2),
+            ('>', Some('=')) => (Token::Binary(BinaryOp::Ge), 2),
+            ('&', Some('&')) => (Token::Binary(BinaryOp::LogicalAnd), 2),
+            ('|', Some('|')) => (Token::Binary(BinaryOp::LogicalOr), 2),
+            ('<', _) => (Token::Binary(BinaryOp::Lt), 1),
+            ('>', _) => (Token::Binary(BinaryOp::Gt), 1),
+            ('&', _) => (Token::Binary(BinaryOp::And), 1),
+            ('|', _) => (Token::Binary(BinaryOp::Or), 1),
+            ('^', _) => (Token::Binary(BinaryOp::Xor), 1),
+            ('+', _) => (Token::Binary(BinaryOp::Add), 1),
+            // Negation when it starts an operand; the parser decides.
+            ('-', _) => (Token::Binary(BinaryOp::Sub), 1),
+            ('!', _) => (Token::Unary(UnaryOp::Not), 1),
+            ('~', _) => (Token::Unary(UnaryOp::Complement), 1),
+            ('(' | '[', _) => (Token::Open(c), 1),
+            (')' | ']', _) => (Token::Close(c), 1),
+            _ => {
+                return Err(ConditionError {
+                    column,
+                    kind: ConditionErrorKind::UnexpectedChar(c),
+                });
+            }
+        };
+        tokens.push((column, token));
+        i += len;
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    /// Column just past the end, for errors at the end of the text.
+    end: usize,
+    symbols: &'a SymbolTable,
+}
+
+impl Parser<'_> {
+    fn error(&self, kind: ConditionErrorKind) -> ConditionError {
+        let column = self
+            .tokens
+            .get(self.pos)
+            .map_or(self.end, |&(column, _)| column);
+        ConditionError { column, kind }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    /// Parses operands joined by operators binding at least as tightly as
+    /// `min`.
+    fn expr(&mut self, min: u8) -> Result<Expr, ConditionError> {
+        let mut lhs = self.operand()?;
+        while let Some(&Token::Binary(op)) = self.peek() {
+            if op.precedence() < min {
+                break;
+            }
+            self.pos += 1;
+            let rhs = self.expr(op.precedence() + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn operand(&mut self) -> Result<Expr, ConditionError> {
+        let Some(token) = self.peek().cloned() else {
+            return Err(self.error(ConditionErrorKind::UnexpectedEnd));
+        };
+        let unary = match token {
+            Token::Unary(op) => Some(op),
+            Token::Binary(BinaryOp::Sub) => Some(UnaryOp::Negate),
+            _ => None,
+        };
+        if let Some(op) = unary {
+            self.pos += 1;
+            return Ok(Expr::Unary(op, Box::new(self.operand()?)));
+        }
+        let expr = match token {
+            Token::Number(n) => Expr::Number(n),
+            Token::Name(name) => match name.to_ascii_uppercase().as_str() {
+                "A" => Expr::Register(Register::A),
+                "X" => Expr::Register(Register::X),
+                "Y" => Expr::Register(Register::Y),
+                "P" => Expr::Register(Register::P),
+                "SP" => Expr::Register(Register::Sp),
+                "PC" => Expr::Register(Register::Pc),
+                _ => match self.symbols.address(&name) {
+                    Some(addr) => Expr::Number(i64::from(addr)),
+                    None => return Err(self.error(ConditionErrorKind::UnknownName(name))),
+                },
+            },
+            Token::Open(open) => {
+                self.pos += 1;
+                let inner = self.expr(0)?;
+                let close = if open == '(' { ')' } else { ']' };
+                if self.peek() != Some(&Token::Close(close)) {
+                    let what = if open == '(' { "`)`" } else { "`]`" };
+                    return Err(self.error(match self.peek() {
+                        None => ConditionErrorKind::UnexpectedEnd,
+                        Some(_) => ConditionErrorKind::Expected(what),
+                    }));
+                }
+                if open == '(' {
+                    inner
+                } else {
+                    Expr::Memory(Box::new(inner))
+                }
+            }
+            _ => return Err(self.error(ConditionErrorKind::Expected("a value"))),
+        };
+        self.pos += 1;
+        Ok(expr)
+    }
+}
+
+impl Condition {
+    /// Parses a condition with no symbols.
+    pub fn parse(text: &str) -> Result<Self, ConditionError> {
+        Self::parse_with_symbols(text, &SymbolTable::new())
+    }
+
+    /// Parses a condition, resolving names that are not registers from
+    /// `symbols`.
+    pub fn parse_with_symbols(text: &str, symbols: &SymbolTable) -> Result<Self, ConditionError> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            pos: 0,
+            end: text.chars().count() + 1,
+            symbols,
+        };
+        let expr = parser.expr(0)?;
+        if parser.peek().is_some() {
+            return Err(parser.error(ConditionErrorKind::Expected("an operator")));
+        }
+        Ok(Self {
+            source: text.trim().into(),
+            expr,
+        })
+    }
+
+    /// Evaluates the condition against the current state of `vm`.
+    pub fn eval(&self, vm: &Vm) -> i64 {
+        self.expr.eval(vm)
+    }
+
+    /// Whether the condition holds, that is evaluates to nonzero.
+    pub fn holds(&self, vm: &Vm) -> bool {
+        self.eval(vm) != 0
+    }
+}
+
+impl FromStr for Condition {
+    type Err = ConditionError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Self::parse(text)
+    }
+}
+
+/// The text the condition was parsed from.
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Vm {
+    /// Sets a breakpoint at `addr`, returning `false` if one already existed.
+    pub fn add_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.insert(addr)
+    }
+
+    /// Sets a breakpoint at `addr` that only stops when `condition` holds,
+    /// replacing the condition of an existing one, and returns `false` if
+    /// one already existed.
+    pub fn add_conditional_breakpoint(&mut self, addr: u16, condition: Condition) -> bool {
+        self.breakpoints.insert_conditional(addr, condition)
+    }
+
+    /// Clears the breakpoint at `addr`, returning `false` if there was none.
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(addr)
+    }
+
+    pub fn breakpoints(&self) -> &Breakpoints {
+        &self.breakpoints
+    }
+
+    /// Whether a breakpoint at `pc` stops the program now: there is one and
+    /// it has no condition or its condition holds.
+    pub(crate) fn breakpoint_hit(&self, pc: u16) -> bool {
+        self.breakpoints.contains(pc)
+            && self
+                .breakpoints
+                .condition(pc)
+                .is_none_or(|condition| condition.holds(self))
+    }
+
+    /// Stops execution when the program accesses `range` in a way matching
+    /// `kind`, returning `false` if the same watchpoint already existed.
+    pub fn add_watchpoint(&mut self, range: RangeInclusive<u16>, kind: WatchKind) -> bool {
+        let wp = Watchpoint { range, kind };
+        if self.bus().watchpoints.contains(&wp) {
+            return false;
+        }
+        self.bus_mut().watchpoints.push(wp);
+        self.sync_hook_pages();
+        true
+    }
+
+    /// Removes a watchpoint, returning `false` if there was none.
+    pub fn remove_watchpoint(&mut self, range: RangeInclusive<u16>, kind: WatchKind) -> bool {
+        let wp = Watchpoint { range, kind };
+        let watchpoints = &mut self.bus_mut().watchpoints;
+        let before = watchpoints.len();
+        watchpoints.retain(|w| *w != wp);
+        let removed = watchpoints.len() != before;
+        self.sync_hook_pages();
+        removed
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.bus().watchpoints
+    }
+
+    /// Runs until the PC lands on a breakpoint whose condition holds, a
+    /// watchpoint fires or an instruction fails.
+    ///
+    /// At least one instruction is always executed, so calling this again
+    /// while stopped on a breakpoint continues past it. With nothing to stop
+    /// on it runs in batches, as [`Vm::run_cycles`] does, until an error
+    /// ends it. However it stops, the [watches](crate::watches) are updated.
+    pub fn run_until_break(&mut self) -> Result<StopReason, VmError> {
+        let stop = self.run_to_stop();
+        self.update_watches();
+        stop
+    }
+
+    fn run_to_stop(&mut self) -> Result<StopReason, VmError> {
+        if self.breakpoints.is_empty() && self.bus().watchpoints.is_empty() {
+            loop {
+                self.run_cycles(CYCLES_PER_FRAME)?;
+            }
+        }
+
+        loop {
+            let pc = self.cpu.pc;
+            self.step()?;
+            if let Some(hit) = self.bus_mut().watch_hit.take() {
+                return Ok(StopReason::Watchpoint {
+                    pc,
+                    addr: hit.addr,
+                    access: hit.kind,
+                    value: hit.val,
+                });
+            }
+            let pc = self.cpu.pc;
+            if self.breakpoint_hit(pc) {
+                return Ok(StopReason::Breakpoint { pc });
+            }
+        }
+    }
+}
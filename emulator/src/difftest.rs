@@ -0,0 +1,319 @@
+//! Differential testing of the Rust core against the C kernel.
+//!
+//! Only compiled with the `difftest` feature, which links the C kernel and
+//! builds the Rust core in [`cpu`] next to it. A [`DiffTest`] runs the two
+//! in lockstep from the same state, each on its own copy of memory, and
+//! after every instruction compares the status, registers, cycle count, the
+//! bus accesses made and the bytes they wrote. The first mismatch is
+//! reported as a [`Divergence`] listing both sides:
+//!
+//! ```no_run
+//! # use emulator::{difftest::DiffTest, Rom};
+//! let rom = Rom::from_file("game.rvm").unwrap();
+//! let executed = DiffTest::from_rom(&rom).run(1_000_000);
+//! println!("cores agreed on {executed} instructions");
+//! ```
+//!
+//! The cores run bare: mapped devices are not attached, so device
+//! registers read and write as plain RAM on both sides, and
+//! [extension opcodes](crate::extension) are illegal.
+
+use std::ffi::{c_int, c_void};
+use std::fmt;
+
+use crate::cpu;
+use crate::ffi::{self, BusAccess, Cpu, CpuStats, RVM_ILLEGAL_OPCODE, RVM_OK};
+use crate::hooks::Access;
+use crate::rom::Rom;
+use crate::vm::{Registers, Vm};
+
+/// One core and the memory and access log it owns, boxed so that the
+/// pointers the CPU holds into it stay put.
+struct Core {
+    cpu: Cpu,
+    /// Kept alive for `cpu.memory`.
+    memory: Box<[u8]>,
+    /// Written by [`record`] through `cpu.bus_ctx`.
+    accesses: Vec<Access>,
+}
+
+/// Bus hook logging every access without claiming it.
+unsafe extern "C" fn record(ctx: *mut c_void, kind: BusAccess, addr: u16, val: *mut u8) -> c_int {
+    // SAFETY: `ctx` is the `Core::accesses` of a boxed `Core`, installed in
+    // `Core::new` and not otherwise borrowed while a step runs.
+    let accesses = unsafe { &mut *ctx.cast::<Vec<Access>>() };
+    accesses.push(Access {
+        addr,
+        kind,
+        // SAFETY: the kernel passes a valid byte.
+        value: unsafe { *val },
+    });
+    0
+}
+
+impl Core {
+    fn new(vm: &Vm) -> Box<Self> {
+        let mut memory: Box<[u8]> = vm.memory().into();
+        let Registers {
+            a,
+            x,
+            y,
+            pc,
+            sp,
+            flags,
+        } = vm.registers();
+        let cpu = Cpu {
+            a,
+            x,
+            y,
+            pc,
+            sp,
+            flags,
+            memory: memory.as_mut_ptr(),
+            cycles: vm.cycles(),
+            bus_hook: Some(record),
+            bus_ctx: std::ptr::null_mut(),
+            hook_pages: [1; 256],
+            rom_pages: vm.cpu.rom_pages,
+            page_map: vm.cpu.page_map,
+            wait_pages: vm.cpu.wait_pages,
+            irq: 0,
+            nmi: 0,
+            int_state: 0,
+            bus_claimed: 0,
+            stats: CpuStats::default(),
+            ext_handler: None,
+            ext_ctx: std::ptr::null_mut(),
+            ext_opcodes: [0; 256],
+            illegal_mode: vm.cpu.illegal_mode,
+            dirty_pages: [0; 256],
+        };
+        let mut core = Box::new(Self {
+            cpu,
+            memory,
+            accesses: Vec::new(),
+        });
+        core.cpu.bus_ctx = (&raw mut core.accesses).cast();
+        core
+    }
+
+    fn outcome(&mut self, status: c_int) -> Outcome {
+        let cpu = &self.cpu;
+        Outcome {
+            status,
+            registers: Registers {
+                a: cpu.a,
+                x: cpu.x,
+                y: cpu.y,
+                pc: cpu.pc,
+                sp: cpu.sp,
+                flags: cpu.flags,
+            },
+            cycles: cpu.cycles,
+            accesses: std::mem::take(&mut self.accesses),
+        }
+    }
+}
+
+/// What one core did in one instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    /// `RVM_OK` or `RVM_ILLEGAL_OPCODE`.
+    pub status: c_int,
+    pub registers: Registers,
+    pub cycles: u32,
+    /// Every bus access in order, instruction fetches included.
+    pub accesses: Vec<Access>,
+}
+
+/// The first instruction on which the cores disagreed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Instructions both cores completed identically before this one.
+    pub step: u64,
+    /// The state both cores started the instruction from.
+    pub before: Registers,
+    pub c: Outcome,
+    pub rust: Outcome,
+    /// Written addresses whose contents differ afterwards, with the C and
+    /// Rust bytes.
+    pub memory: Vec<(u16, u8, u8)>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (c, rust) = (&self.c, &self.rust);
+        writeln!(
+            f,
+            "cores diverged on instruction {} at PC 0x{:04X}",
+            self.step, self.before.pc
+        )?;
+        writeln!(f, "  {:<8}{:>10}{:>10}", "", "C", "Rust")?;
+        let fields = [
+            ("status", c.status as u32, rust.status as u32, 1),
+            ("A", c.registers.a.into(), rust.registers.a.into(), 2),
+            ("X", c.registers.x.into(), rust.registers.x.into(), 2),
+            ("Y", c.registers.y.into(), rust.registers.y.into(), 2),
+            (
+                "P",
+                c.registers.flags.into(),
+                rust.registers.flags.into(),
+                2,
+            ),
+            ("SP", c.registers.sp.into(), rust.registers.sp.into(), 4),
+            ("PC", c.registers.pc.into(), rust.registers.pc.into(), 4),
+            ("cycles", c.cycles, rust.cycles, 8),
+        ];
+        for (name, c, rust, width) in fields {
+            let mark = if c == rust { "" } else { "  <--" };
+            let (c, rust) = (format!("{c:0width$X}"), format!("{rust:0width$X}"));
+            writeln!(f, "  {name:<8}{c:>10}{rust:>10}{mark}")?;
+        }
+        if c.accesses != rust.accesses {
+            writeln!(f, "  bus accesses differ:")?;
+            writeln!(f, "    C:    {}", Accesses(&c.accesses))?;
+            writeln!(f, "    Rust: {}", Accesses(&rust.accesses))?;
+        }
+        for &(addr, c, rust) in &self.memory {
+            writeln!(f, "  memory 0x{addr:04X}: C 0x{c:02X}, Rust 0x{rust:02X}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Divergence {}
+
+struct Accesses<'a>(&'a [Access]);
+
+impl fmt::Display for Accesses<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, access) in self.0.iter().enumerate() {
+            let kind = match access.kind {
+                BusAccess::Read => 'R',
+                BusAccess::Write => 'W',
+            };
+            let sep = if i == 0 { "" } else { " " };
+            write!(f, "{sep}{kind}{:04X}={:02X}", access.addr, access.value)?;
+        }
+        Ok(())
+    }
+}
+
+/// The C kernel and the Rust core running side by side.
+pub struct DiffTest {
+    c: Box<Core>,
+    rust: Box<Core>,
+    steps: u64,
+}
+
+impl DiffTest {
+    /// Starts both cores from `vm`'s registers, cycle count, memory and ROM
+    /// pages.
+    pub fn from_vm(vm: &Vm) -> Self {
+        Self {
+            c: Core::new(vm),
+            rust: Core::new(vm),
+            steps: 0,
+        }
+    }
+
+    /// Starts both cores from reset with `rom` loaded as by
+    /// [`Vm::load_rom`].
+    pub fn from_rom(rom: &Rom) -> Self {
+        let mut vm = Vm::new();
+        vm.load_rom(rom)
+            .expect("a new machine has nothing on the mapper ports");
+        Self::from_vm(&vm)
+    }
+
+    /// Instructions executed identically so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// The registers both cores currently agree on.
+    pub fn registers(&self) -> Registers {
+        let cpu = &self.c.cpu;
+        Registers {
+            a: cpu.a,
+            x: cpu.x,
+            y: cpu.y,
+            pc: cpu.pc,
+            sp: cpu.sp,
+            flags: cpu.flags,
+        }
+    }
+
+    /// Drives both cores' IRQ input.
+    pub fn set_irq(&mut self, active: bool) {
+        self.c.cpu.irq = active.into();
+        self.rust.cpu.irq = active.into();
+    }
+
+    /// Drives both cores' NMI input.
+    pub fn set_nmi(&mut self, active: bool) {
+        self.c.cpu.nmi = active.into();
+        self.rust.cpu.nmi = active.into();
+    }
+
+    /// Executes one instruction on both cores and compares the results.
+    /// After a divergence the cores are out of step and should be dropped.
+    ///
+    /// Returns the status both cores agreed on.
+    pub fn step(&mut self) -> Result<c_int, Box<Divergence>> {
+        let before = self.registers();
+        // SAFETY: each CPU's memory and hook context are owned by its
+        // `Core` and outlive the call.
+        let c = unsafe { ffi::cpu_step(&raw mut self.c.cpu) };
+        // SAFETY: as above.
+        let rust = unsafe { cpu::cpu_step(&raw mut self.rust.cpu) };
+        let c = self.c.outcome(c);
+        let rust = self.rust.outcome(rust);
+
+        let memory: Vec<_> = c
+            .accesses
+            .iter()
+            .chain(&rust.accesses)
+            .filter(|access| access.kind == BusAccess::Write)
+            .map(|access| access.addr)
+            .filter_map(|addr| {
+                let (c, rust) = (
+                    self.c.memory[addr as usize],
+                    self.rust.memory[addr as usize],
+                );
+                (c != rust).then_some((addr, c, rust))
+            })
+            .collect();
+        if c != rust || !memory.is_empty() {
+            return Err(Box::new(Divergence {
+                step: self.steps,
+                before,
+                c,
+                rust,
+                memory,
+            }));
+        }
+        self.steps += 1;
+        Ok(c.status)
+    }
+
+    /// Runs up to `max` instructions, stopping early after an illegal opcode
+    /// both cores agree on, and returns how many were executed, that opcode
+    /// included.
+    ///
+    /// # Panics
+    ///
+    /// Panics with the full [`Divergence`] as soon as the cores disagree.
+    pub fn run(&mut self, max: u64) -> u64 {
+        let start = self.steps;
+        while self.steps - start < max {
+            match self.step() {
+                Ok(RVM_OK) => {}
+                Ok(RVM_ILLEGAL_OPCODE) => break,
+                Ok(status) => unreachable!("unknown kernel status {status}"),
+                Err(divergence) => panic!("{divergence}"),
+            }
+        }
+        self.steps - start
+    }
+}
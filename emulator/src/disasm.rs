@@ -0,0 +1,180 @@
+//! Disassembler for rvm-8 machine code.
+//!
+//! The opcode table is [`rvm8_core::OPCODES`], generated from
+//! `kernel/opcodes.def`, the list the C kernel builds its `instruction_table`
+//! from, so decoding can never drift from what the CPU actually executes.
+
+use std::fmt;
+
+use crate::ffi::AddressingMode;
+use crate::vm::Vm;
+
+pub use rvm8_core::Opcode;
+
+/// Looks up an opcode byte, returning `None` if it is undefined.
+pub fn lookup(opcode: u8) -> Option<&'static Opcode> {
+    rvm8_core::OPCODES[opcode as usize].as_ref()
+}
+
+/// Iterates over every defined opcode byte and its description.
+pub fn opcodes() -> impl Iterator<Item = (u8, &'static Opcode)> {
+    (0..=u8::MAX).filter_map(|code| lookup(code).map(|op| (code, op)))
+}
+
+/// Number of bytes (opcode included) an instruction in `mode` occupies.
+pub fn instruction_size(mode: AddressingMode) -> u8 {
+    match mode {
+        AddressingMode::Implied | AddressingMode::Accumulator => 1,
+        AddressingMode::Immediate
+        | AddressingMode::ZeroPage
+        | AddressingMode::ZeroPageX
+        | AddressingMode::ZeroPageY
+        | AddressingMode::IndirectX
+        | AddressingMode::IndirectY
+        | AddressingMode::Relative => 2,
+        AddressingMode::Absolute
+        | AddressingMode::AbsoluteX
+        | AddressingMode::AbsoluteY
+        | AddressingMode::Indirect => 3,
+    }
+}
+
+/// A decoded operand, in assembler notation terms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    /// No operand (implied, or an undefined opcode).
+    None,
+    /// `A`
+    Accumulator,
+    /// `#$nn`
+    Immediate(u8),
+    /// `$nn`
+    ZeroPage(u8),
+    /// `$nn,X`
+    ZeroPageX(u8),
+    /// `$nn,Y`
+    ZeroPageY(u8),
+    /// `$nnnn`
+    Absolute(u16),
+    /// `$nnnn,X`
+    AbsoluteX(u16),
+    /// `$nnnn,Y`
+    AbsoluteY(u16),
+    /// `($nnnn)`
+    Indirect(u16),
+    /// `($nn,X)`
+    IndirectX(u8),
+    /// `($nn),Y`
+    IndirectY(u8),
+    /// Signed branch offset relative to the next instruction.
+    Relative(i8),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::None => Ok(()),
+            Self::Accumulator => write!(f, "A"),
+            Self::Immediate(v) => write!(f, "#${v:02X}"),
+            Self::ZeroPage(a) => write!(f, "${a:02X}"),
+            Self::ZeroPageX(a) => write!(f, "${a:02X},X"),
+            Self::ZeroPageY(a) => write!(f, "${a:02X},Y"),
+            Self::Absolute(a) => write!(f, "${a:04X}"),
+            Self::AbsoluteX(a) => write!(f, "${a:04X},X"),
+            Self::AbsoluteY(a) => write!(f, "${a:04X},Y"),
+            Self::Indirect(a) => write!(f, "(${a:04X})"),
+            Self::IndirectX(a) => write!(f, "(${a:02X},X)"),
+            Self::IndirectY(a) => write!(f, "(${a:02X}),Y"),
+            Self::Relative(off) => write!(f, "*{off:+}"),
+        }
+    }
+}
+
+/// One decoded instruction.
+///
+/// Undefined opcodes decode as a one-byte `???` with no operand, matching
+/// the placeholder entries of the kernel's table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    pub operands: Operand,
+    /// Encoded length in bytes.
+    pub size: u8,
+    /// Base cycle count; page crossings may add more at run time.
+    pub cycles: u8,
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.operands {
+            Operand::None => f.write_str(self.mnemonic),
+            operand => write!(f, "{} {operand}", self.mnemonic),
+        }
+    }
+}
+
+/// Decodes the instruction at the start of `bytes`.
+///
+/// Returns `None` if `bytes` is empty or ends inside the instruction.
+pub fn decode(bytes: &[u8]) -> Option<Instruction> {
+    let &opcode = bytes.first()?;
+    let Some(op) = lookup(opcode) else {
+        return Some(Instruction {
+            opcode,
+            mnemonic: "???",
+            operands: Operand::None,
+            size: 1,
+            cycles: 0,
+        });
+    };
+
+    let size = instruction_size(op.mode);
+    let args = bytes.get(1..size as usize)?;
+    let byte = || args[0];
+    let word = || u16::from_le_bytes([args[0], args[1]]);
+    let operands = match op.mode {
+        AddressingMode::Implied => Operand::None,
+        AddressingMode::Accumulator => Operand::Accumulator,
+        AddressingMode::Immediate => Operand::Immediate(byte()),
+        AddressingMode::ZeroPage => Operand::ZeroPage(byte()),
+        AddressingMode::ZeroPageX => Operand::ZeroPageX(byte()),
+        AddressingMode::ZeroPageY => Operand::ZeroPageY(byte()),
+        AddressingMode::Absolute => Operand::Absolute(word()),
+        AddressingMode::AbsoluteX => Operand::AbsoluteX(word()),
+        AddressingMode::AbsoluteY => Operand::AbsoluteY(word()),
+        AddressingMode::Indirect => Operand::Indirect(word()),
+        AddressingMode::IndirectX => Operand::IndirectX(byte()),
+        AddressingMode::IndirectY => Operand::IndirectY(byte()),
+        AddressingMode::Relative => Operand::Relative(byte() as i8),
+    };
+
+    Some(Instruction {
+        opcode,
+        mnemonic: op.mnemonic,
+        operands,
+        size,
+        cycles: op.cycles,
+    })
+}
+
+/// Linearly disassembles `bytes` as if loaded at `origin`, yielding each
+/// instruction with its address. Stops at a truncated trailing instruction.
+pub fn disassemble(bytes: &[u8], origin: u16) -> impl Iterator<Item = (u16, Instruction)> + '_ {
+    let mut offset = 0usize;
+    std::iter::from_fn(move || {
+        let instr = decode(bytes.get(offset..)?)?;
+        let addr = origin.wrapping_add(offset as u16);
+        offset += instr.size as usize;
+        Some((addr, instr))
+    })
+}
+
+impl Vm {
+    /// Decodes the instruction at `addr` in live memory, wrapping around the
+    /// end of the address space.
+    pub fn disassemble(&self, addr: u16) -> Instruction {
+        let bytes = [0, 1, 2].map(|i| self.read(addr.wrapping_add(i)));
+        decode(&bytes).expect("three bytes hold any instruction")
+    }
+}
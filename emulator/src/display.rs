@@ -0,0 +1,425 @@
+//! Display device (PPU).
+//!
+//! The PPU has no memory of its own: video RAM and its registers live in the
+//! address space at the locations given by the spec, so programs drive it
+//! with ordinary loads and stores and save states capture it for free.
+//!
+//! The screen is drawn a line at a time, one per
+//! [scanline](crate::stepping::SCANLINES): each row is rendered from memory
+//! as it stands when the CPU's clock reaches the end of that scanline, so a
+//! write to the registers, the tilemap or the sprites mid-frame shows from
+//! the line being drawn on, and raster effects such as split scrolling
+//! work. At the end of every frame [`Vm::run_frame`] hands the finished
+//! picture to the framebuffer, raises [`STATUS_VBLANK`] and calls the
+//! vblank callback.
+//!
+//! After drawing the line `LINE_COMPARE` names, the PPU sets [`STATUS_LINE`]
+//! and, while that stays set and [`CTRL_LINE_IRQ`] is on, holds
+//! [`DISPLAY_IRQ`] active: the handler writes the registers for the lines
+//! below and clears the flag. While the line is held the machine runs an
+//! instruction at a time, so the handler's acknowledgement is seen at once.
+//!
+//! | Address         | Contents                                           |
+//! | --------------- | -------------------------------------------------- |
+//! | 0x2000–0x21FF   | 32 tiles, 16 bytes each                            |
+//! | 0x2200–0x2367   | 20×18 background tilemap, one tile index per byte  |
+//! | 0x23C0–0x23FF   | 16 sprites: Y, X, tile, attributes                 |
+//! | 0x2400          | `CTRL`: bit 0 background, bit 1 sprites, bit 2 line IRQ |
+//! | 0x2401          | `STATUS`: bit 7 vblank, bit 0 line; cleared by the CPU |
+//! | 0x2402–0x2403   | background scroll X, Y                             |
+//! | 0x2404–0x2405   | background and sprite palettes                     |
+//! | 0x2406          | `LINE_COMPARE`: the line the line IRQ follows      |
+//!
+//! Tiles are 8×8 pixels at 2 bits per pixel: each row is a low-bit byte
+//! followed by a high-bit byte, bit 7 being the leftmost pixel. A palette
+//! maps the four pixel values to shades, two bits each with pixel value 0 in
+//! the low bits; shade 0 is white and 3 is black. Sprite pixel value 0 is
+//! transparent, sprite attribute bit 0 flips horizontally and bit 1
+//! vertically, and lower-numbered sprites are drawn on top.
+//!
+//! The framebuffer is RGBA unless a [`DisplayConfig`] asks for another
+//! [`PixelFormat`] or scales the picture up, so a frontend can take frames
+//! as its screen wants them without converting each one.
+
+use crate::blit::Blitter;
+use crate::events::Event;
+use crate::stepping::SCANLINES;
+use crate::vm::Vm;
+
+/// Screen width in pixels.
+pub const WIDTH: usize = 160;
+/// Screen height in pixels.
+pub const HEIGHT: usize = 144;
+/// Bytes per framebuffer pixel in the default RGBA format.
+pub const BYTES_PER_PIXEL: usize = 4;
+
+/// Start of tile data.
+pub const TILE_DATA: u16 = 0x2000;
+/// Start of the background tilemap.
+pub const TILEMAP: u16 = 0x2200;
+/// Start of sprite attribute memory.
+pub const SPRITES: u16 = 0x23C0;
+/// Control register.
+pub const CTRL: u16 = 0x2400;
+/// Status register.
+pub const STATUS: u16 = 0x2401;
+/// Background scroll X register.
+pub const SCROLL_X: u16 = 0x2402;
+/// Background scroll Y register.
+pub const SCROLL_Y: u16 = 0x2403;
+/// Background palette register.
+pub const BG_PALETTE: u16 = 0x2404;
+/// Sprite palette register.
+pub const SPRITE_PALETTE: u16 = 0x2405;
+/// Line compare register.
+pub const LINE_COMPARE: u16 = 0x2406;
+
+/// `CTRL` bit enabling the background layer.
+pub const CTRL_BACKGROUND: u8 = 1 << 0;
+/// `CTRL` bit enabling sprites.
+pub const CTRL_SPRITES: u8 = 1 << 1;
+/// `CTRL` bit enabling the line interrupt.
+pub const CTRL_LINE_IRQ: u8 = 1 << 2;
+/// `STATUS` bit raised at the end of every frame.
+pub const STATUS_VBLANK: u8 = 1 << 7;
+/// `STATUS` bit raised after drawing the `LINE_COMPARE` line.
+pub const STATUS_LINE: u8 = 1 << 0;
+/// Interrupt line of the line interrupt.
+pub const DISPLAY_IRQ: u8 = 6;
+
+/// Tiles in tile data.
+pub const TILE_COUNT: usize = 32;
+const TILE_BYTES: usize = 16;
+/// Background tilemap width in tiles.
+pub const MAP_WIDTH: usize = WIDTH / 8;
+/// Background tilemap height in tiles.
+pub const MAP_HEIGHT: usize = HEIGHT / 8;
+/// Sprites in sprite attribute memory.
+pub const SPRITE_COUNT: usize = 16;
+
+/// RGBA value of each shade, which [`PixelFormat::Indexed`] pixels index.
+pub const PALETTE: [[u8; 4]; 4] = [
+    [0xFF, 0xFF, 0xFF, 0xFF],
+    [0xAA, 0xAA, 0xAA, 0xFF],
+    [0x55, 0x55, 0x55, 0xFF],
+    [0x00, 0x00, 0x00, 0xFF],
+];
+
+/// How framebuffer pixels are stored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum PixelFormat {
+    /// Four bytes: red, green, blue and alpha.
+    #[default]
+    Rgba8888,
+    /// Two bytes, little-endian: red in the top 5 bits, green in the
+    /// middle 6 and blue in the low 5.
+    Rgb565,
+    /// One byte: the shade, an index into [`PALETTE`].
+    Indexed,
+}
+
+impl PixelFormat {
+    /// The format called `name`: `rgba8888`, `rgb565` or `indexed`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "rgba8888" => Some(Self::Rgba8888),
+            "rgb565" => Some(Self::Rgb565),
+            "indexed" => Some(Self::Indexed),
+            _ => None,
+        }
+    }
+
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            Self::Rgba8888 => 4,
+            Self::Rgb565 => 2,
+            Self::Indexed => 1,
+        }
+    }
+
+    /// Writes `shade` into `pixel` in this format.
+    pub(crate) fn encode(self, shade: u8, pixel: &mut [u8]) {
+        let [r, g, b, _] = PALETTE[shade as usize];
+        match self {
+            Self::Rgba8888 => pixel.copy_from_slice(&PALETTE[shade as usize]),
+            Self::Rgb565 => {
+                let rgb = u16::from(r >> 3) << 11 | u16::from(g >> 2) << 5 | u16::from(b >> 3);
+                pixel.copy_from_slice(&rgb.to_le_bytes());
+            }
+            Self::Indexed => pixel[0] = shade,
+        }
+    }
+}
+
+/// The framebuffer's layout; see [`Vm::set_display_config`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct DisplayConfig {
+    pub format: PixelFormat,
+    /// Each screen pixel becomes a `scale`×`scale` block of framebuffer
+    /// pixels, nearest-neighbor. At least 1.
+    pub scale: u8,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        Self {
+            format: PixelFormat::default(),
+            scale: 1,
+        }
+    }
+}
+
+impl DisplayConfig {
+    /// Framebuffer width in pixels.
+    pub fn width(&self) -> usize {
+        WIDTH * self.scale as usize
+    }
+
+    /// Framebuffer height in pixels.
+    pub fn height(&self) -> usize {
+        HEIGHT * self.scale as usize
+    }
+
+    /// Framebuffer length in bytes.
+    pub fn frame_len(&self) -> usize {
+        self.width() * self.height() * self.format.bytes_per_pixel()
+    }
+}
+
+/// Called with the framebuffer at every vblank.
+pub(crate) type VblankCallback = Box<dyn FnMut(&[u8]) + Send>;
+
+/// Host-side display state: the picture being drawn, the last finished
+/// frame and the vblank callback.
+pub(crate) struct Display {
+    pub config: DisplayConfig,
+    pub framebuffer: Vec<u8>,
+    pub vblank: Option<VblankCallback>,
+    /// One shade per pixel, the lines above `line` from the current frame.
+    shades: Vec<u8>,
+    /// The next line to draw.
+    line: u32,
+    /// Built for the last config the framebuffer was encoded in.
+    blitter: Blitter,
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        let config = DisplayConfig::default();
+        Self {
+            config,
+            framebuffer: vec![0; config.frame_len()],
+            vblank: None,
+            shades: vec![0; WIDTH * HEIGHT],
+            line: 0,
+            blitter: Blitter::new(config),
+        }
+    }
+}
+
+/// Value (0-3) of pixel `x`, `y` of `tile`.
+pub(crate) fn tile_pixel(memory: &[u8], tile: u8, x: usize, y: usize) -> u8 {
+    let row = TILE_DATA as usize + (tile as usize % TILE_COUNT) * TILE_BYTES + y * 2;
+    let bit = 7 - x;
+    (memory[row] >> bit & 1) | (memory[row + 1] >> bit & 1) << 1
+}
+
+pub(crate) fn shade(palette: u8, value: u8) -> u8 {
+    palette >> (value * 2) & 0b11
+}
+
+/// Renders line `y` from `memory` into `row`, one shade per pixel.
+fn render_line(memory: &[u8], y: usize, row: &mut [u8]) {
+    let ctrl = memory[CTRL as usize];
+    row.fill(0);
+
+    if ctrl & CTRL_BACKGROUND != 0 {
+        let scroll_x = memory[SCROLL_X as usize] as usize;
+        let scroll_y = memory[SCROLL_Y as usize] as usize;
+        let palette = memory[BG_PALETTE as usize];
+        let map_y = (y + scroll_y) % HEIGHT;
+        for (x, shade_out) in row.iter_mut().enumerate() {
+            let map_x = (x + scroll_x) % WIDTH;
+            let tile = memory[TILEMAP as usize + map_y / 8 * MAP_WIDTH + map_x / 8];
+            let value = tile_pixel(memory, tile, map_x % 8, map_y % 8);
+            *shade_out = shade(palette, value);
+        }
+    }
+
+    if ctrl & CTRL_SPRITES != 0 {
+        let palette = memory[SPRITE_PALETTE as usize];
+        for sprite in memory[SPRITES as usize..][..SPRITE_COUNT * 4]
+            .chunks_exact(4)
+            .rev()
+        {
+            let &[top, left, tile, attr] = sprite else {
+                unreachable!()
+            };
+            let Some(row_in) = y.checked_sub(top as usize).filter(|&row| row < 8) else {
+                continue;
+            };
+            let ty = if attr & 0b10 != 0 { 7 - row_in } else { row_in };
+            for col in 0..8 {
+                let x = left as usize + col;
+                if x >= WIDTH {
+                    break;
+                }
+                let tx = if attr & 0b01 != 0 { 7 - col } else { col };
+                let value = tile_pixel(memory, tile, tx, ty);
+                if value != 0 {
+                    row[x] = shade(palette, value);
+                }
+            }
+        }
+    }
+}
+
+impl Vm {
+    /// The last rendered frame, row by row, laid out as the
+    /// [`DisplayConfig`] says: by default `WIDTH * HEIGHT` RGBA pixels.
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.display.framebuffer
+    }
+
+    pub fn display_config(&self) -> DisplayConfig {
+        self.display.config
+    }
+
+    /// Changes the framebuffer's pixel format and scale, re-rendering the
+    /// current frame into the new layout.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `config.scale` is 0.
+    pub fn set_display_config(&mut self, config: DisplayConfig) {
+        assert!(config.scale > 0, "the display scale must be at least 1");
+        self.display.config = config;
+        self.display.framebuffer = vec![0; config.frame_len()];
+        self.render_display();
+    }
+
+    /// Calls `callback` with the new framebuffer at the end of every frame.
+    pub fn set_vblank_callback(&mut self, callback: impl FnMut(&[u8]) + Send + 'static) {
+        self.display.vblank = Some(Box::new(callback));
+    }
+
+    /// Removes the vblank callback.
+    pub fn clear_vblank_callback(&mut self) {
+        self.display.vblank = None;
+    }
+
+    /// Re-renders the whole picture from the current contents of memory.
+    pub(crate) fn render_display(&mut self) {
+        let mut shades = std::mem::take(&mut self.display.shades);
+        for (y, row) in shades.chunks_exact_mut(WIDTH).enumerate() {
+            render_line(self.memory(), y, row);
+        }
+        self.display.shades = shades;
+        self.encode_framebuffer();
+    }
+
+    /// Draws the lines whose scanlines have ended since the last call, and
+    /// raises the line interrupt after the line it names.
+    pub(crate) fn draw_lines(&mut self) {
+        let ended = self.scanlines_ended();
+        // The clock went back, on a reset or a restore.
+        self.display.line = self.display.line.min(ended);
+        self.draw_lines_to(ended);
+    }
+
+    fn draw_lines_to(&mut self, end: u32) {
+        while self.display.line < end {
+            let y = self.display.line;
+            let mut shades = std::mem::take(&mut self.display.shades);
+            render_line(
+                self.memory(),
+                y as usize,
+                &mut shades[y as usize * WIDTH..][..WIDTH],
+            );
+            self.display.shades = shades;
+            self.display.line += 1;
+            if u32::from(self.read(LINE_COMPARE)) == y {
+                self.write(STATUS, self.read(STATUS) | STATUS_LINE);
+            }
+        }
+    }
+
+    /// Cycles until the next line is due to be drawn, so a batch stops there.
+    pub(crate) fn display_batch_cycles(&self) -> u32 {
+        if self.display.line >= SCANLINES {
+            return u32::MAX;
+        }
+        let end = self.scanline_end(self.display.line);
+        match end.wrapping_sub(self.cpu.cycles) {
+            cycles if cycles as i32 > 0 => cycles,
+            _ => 0,
+        }
+    }
+
+    /// The line interrupt, as a bit of [`DISPLAY_IRQ`] if it is held.
+    pub(crate) fn display_irq_lines(&self) -> u8 {
+        let memory = self.memory();
+        let held = memory[STATUS as usize] & STATUS_LINE != 0
+            && memory[CTRL as usize] & CTRL_LINE_IRQ != 0;
+        u8::from(held) << DISPLAY_IRQ
+    }
+
+    /// Encodes the shades of the picture into the framebuffer's layout.
+    fn encode_framebuffer(&mut self) {
+        let config = self.display.config;
+        if self.display.blitter.config() != config {
+            self.display.blitter = Blitter::new(config);
+        }
+        let DisplayConfig { format, scale } = config;
+        let scale = scale as usize;
+        let row_len = WIDTH * scale * format.bytes_per_pixel();
+        let mut wide = vec![0; if scale > 1 { WIDTH * scale } else { 0 }];
+        let Display {
+            shades,
+            framebuffer,
+            blitter,
+            ..
+        } = &mut self.display;
+        for (line, rows) in shades
+            .chunks_exact(WIDTH)
+            .zip(framebuffer.chunks_exact_mut(row_len * scale))
+        {
+            let (row, copies) = rows.split_at_mut(row_len);
+            match (scale, format) {
+                (1, _) => blitter.convert(line, row),
+                (_, PixelFormat::Indexed) => blitter.widen(line, row),
+                _ => {
+                    blitter.widen(line, &mut wide);
+                    blitter.convert(&wide, row);
+                }
+            }
+            for copy in copies.chunks_exact_mut(row_len) {
+                copy.copy_from_slice(row);
+            }
+        }
+    }
+
+    /// Draws the rest of the frame, raises vblank and hands the frame to the
+    /// callback.
+    pub(crate) fn vblank(&mut self) {
+        self.draw_lines_to(SCANLINES);
+        self.display.line = 0;
+        self.encode_framebuffer();
+        self.write(STATUS, self.read(STATUS) | STATUS_VBLANK);
+        if let Some(callback) = &mut self.display.vblank {
+            callback(&self.display.framebuffer);
+        }
+        self.queue_event(Event::Vblank { frame: self.frame });
+        self.dump_frame();
+    }
+}
@@ -0,0 +1,167 @@
+//! DMA controller.
+//!
+//! [`Dma`] copies blocks of bytes between any two places on the bus, RAM or
+//! device registers, without the CPU. It is not mapped by default; map it
+//! over [`DMA_PORTS`]:
+//!
+//! ```
+//! # use emulator::{dma::{Dma, DMA_PORTS}, Vm};
+//! let mut vm = Vm::new();
+//! vm.bus_mut().map(DMA_PORTS, Dma::default()).unwrap();
+//! ```
+//!
+//! | Offset | Register                                            |
+//! | ------ | --------------------------------------------------- |
+//! | 0–1    | source address, little-endian                       |
+//! | 2–3    | destination address, little-endian                  |
+//! | 4–5    | length in bytes, little-endian                      |
+//! | 6      | `CTRL`: writing sets the mode bits and bit 0 starts |
+//!
+//! `CTRL` bits are [`CTRL_START`], [`CTRL_STEAL`], [`CTRL_FIXED_SRC`],
+//! [`CTRL_FIXED_DST`] and [`CTRL_IRQ`]; reading it reports [`CTRL_START`]
+//! while a transfer is in progress. Fixed addresses let a transfer drain or
+//! fill a device FIFO register.
+//!
+//! Every byte costs [`CYCLES_PER_BYTE`] cycles of bus time taken from the
+//! CPU, plus the [wait states](crate::Vm::set_wait_states) of the two
+//! addresses it moves between. In the default burst mode the whole transfer
+//! runs as soon as the instruction that started it finishes, stalling the
+//! CPU for its full length; in cycle-steal mode one byte moves after every
+//! instruction. When [`CTRL_IRQ`] is set, completion holds interrupt line
+//! [`DMA_IRQ`] until `CTRL` is next written.
+
+use std::ops::RangeInclusive;
+
+use crate::bus::{BusDevice, DmaBus, set_word_byte};
+use crate::reset::ResetKind;
+
+/// Suggested place for the DMA registers.
+pub const DMA_PORTS: RangeInclusive<u16> = 0x2700..=0x270F;
+/// Interrupt line raised on completion.
+pub const DMA_IRQ: u8 = 1;
+/// Bus cycles to move one byte: a read and a write.
+pub const CYCLES_PER_BYTE: u32 = 2;
+
+/// `CTRL` bit starting a transfer, and reading as busy.
+pub const CTRL_START: u8 = 1 << 0;
+/// `CTRL` bit selecting cycle-steal mode instead of burst.
+pub const CTRL_STEAL: u8 = 1 << 1;
+/// `CTRL` bit keeping the source address fixed.
+pub const CTRL_FIXED_SRC: u8 = 1 << 2;
+/// `CTRL` bit keeping the destination address fixed.
+pub const CTRL_FIXED_DST: u8 = 1 << 3;
+/// `CTRL` bit raising [`DMA_IRQ`] on completion.
+pub const CTRL_IRQ: u8 = 1 << 4;
+
+const CTRL: u16 = 6;
+
+/// The DMA controller device.
+#[derive(Debug, Clone, Default)]
+pub struct Dma {
+    src: u16,
+    dst: u16,
+    len: u16,
+    ctrl: u8,
+    /// Bytes left in the running transfer.
+    remaining: u16,
+    irq: bool,
+}
+
+impl Dma {
+    /// Whether a transfer is in progress.
+    pub fn busy(&self) -> bool {
+        self.remaining > 0
+    }
+
+    fn transfer_byte(&mut self, bus: &mut DmaBus<'_>) {
+        let val = bus.read(self.src);
+        bus.write(self.dst, val);
+        if self.ctrl & CTRL_FIXED_SRC == 0 {
+            self.src = self.src.wrapping_add(1);
+        }
+        if self.ctrl & CTRL_FIXED_DST == 0 {
+            self.dst = self.dst.wrapping_add(1);
+        }
+        self.remaining -= 1;
+        if self.remaining == 0 {
+            self.irq = self.ctrl & CTRL_IRQ != 0;
+        }
+    }
+}
+
+impl BusDevice for Dma {
+    fn read8(&mut self, offset: u16) -> u8 {
+        let byte = |word: u16| word.to_le_bytes()[usize::from(offset & 1 == 1)];
+        match offset {
+            0..=1 => byte(self.src),
+            2..=3 => byte(self.dst),
+            4..=5 => byte(self.len),
+            CTRL => self.ctrl & !CTRL_START | if self.busy() { CTRL_START } else { 0 },
+            _ => 0,
+        }
+    }
+
+    fn write8(&mut self, offset: u16, val: u8) {
+        let high = offset & 1 == 1;
+        match offset {
+            0..=1 => set_word_byte(&mut self.src, high, val),
+            2..=3 => set_word_byte(&mut self.dst, high, val),
+            4..=5 => set_word_byte(&mut self.len, high, val),
+            CTRL => {
+                self.ctrl = val;
+                self.irq = false;
+                if val & CTRL_START != 0 {
+                    self.remaining = self.len;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn irq_lines(&self) -> u8 {
+        u8::from(self.irq) << DMA_IRQ
+    }
+
+    fn dma(&mut self, bus: &mut DmaBus<'_>) -> u32 {
+        if !self.busy() {
+            return 0;
+        }
+        let bytes = if self.ctrl & CTRL_STEAL != 0 {
+            1
+        } else {
+            self.remaining
+        };
+        for _ in 0..bytes {
+            self.transfer_byte(bus);
+        }
+        u32::from(bytes) * CYCLES_PER_BYTE
+    }
+
+    fn batch_cycles(&self) -> u32 {
+        if self.busy() { 0 } else { u32::MAX }
+    }
+
+    /// Abandons any transfer and clears every register and the interrupt.
+    fn reset(&mut self, _: ResetKind) {
+        *self = Self::default();
+    }
+
+    fn save_state(&self, state: &mut Vec<u8>) {
+        for word in [self.src, self.dst, self.len, self.remaining] {
+            state.extend_from_slice(&word.to_le_bytes());
+        }
+        state.extend_from_slice(&[self.ctrl, self.irq.into()]);
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        let &[s0, s1, d0, d1, l0, l1, r0, r1, ctrl, irq] = state else {
+            return;
+        };
+        self.src = u16::from_le_bytes([s0, s1]);
+        self.dst = u16::from_le_bytes([d0, d1]);
+        self.len = u16::from_le_bytes([l0, l1]);
+        self.remaining = u16::from_le_bytes([r0, r1]);
+        self.ctrl = ctrl;
+        self.irq = irq != 0;
+    }
+}
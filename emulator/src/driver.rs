@@ -0,0 +1,253 @@
+//! Async frame driver.
+//!
+//! Only compiled with the `async` feature. [`Vm::run_async`] runs frames
+//! inside an async task without blocking the executor: it runs one frame
+//! per poll and then yields, so other tasks on the same thread get their
+//! turn at every frame boundary. Each frame's picture and sound go out
+//! through an [`EventReceiver`] another task, or another thread, can await.
+//!
+//! Nothing here depends on a particular runtime. A `Vm` is not `Send`, so
+//! under tokio the driver runs on a `LocalSet` or a current-thread runtime:
+//!
+//! ```text
+//! let (events, mut frames) = emulator::driver::channel(4);
+//! let stop = StopToken::new();
+//! local.spawn_local({
+//!     let stop = stop.clone();
+//!     async move { vm.run_async(&stop, &events).await }
+//! });
+//! while let Some(frame) = frames.recv().await {
+//!     send_to_client(&frame.pixels, &frame.samples).await;
+//! }
+//! ```
+//!
+//! The channel holds a fixed number of frames. When it is full the driver
+//! waits for the receiver instead of running ahead, so a slow consumer
+//! slows the emulation down rather than filling memory.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+
+use crate::error::VmError;
+use crate::vm::Vm;
+
+/// What one frame produced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameEvent {
+    /// Frames completed, counting this one, as [`Vm::frame`] reports.
+    pub frame: u64,
+    /// The framebuffer it rendered, as [`Vm::framebuffer`].
+    pub pixels: Box<[u8]>,
+    /// Its audio at the [sample rate](Vm::set_sample_rate) set.
+    pub samples: Vec<i16>,
+}
+
+/// Tells [`Vm::run_async`] to return, from any thread.
+#[derive(Debug, Clone, Default)]
+pub struct StopToken(Arc<StopState>);
+
+#[derive(Debug, Default)]
+struct StopState {
+    stopped: AtomicBool,
+    /// The driver waiting for room in its channel, if any.
+    waker: Mutex<Option<Waker>>,
+}
+
+impl StopToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes the driver using this token return before its next frame.
+    pub fn stop(&self) {
+        self.0.stopped.store(true, Ordering::SeqCst);
+        if let Some(waker) = lock(&self.0.waker).take() {
+            waker.wake();
+        }
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.0.stopped.load(Ordering::SeqCst)
+    }
+}
+
+struct Channel {
+    queue: VecDeque<FrameEvent>,
+    capacity: usize,
+    /// Set when either end is dropped.
+    closed: bool,
+    receiver: Option<Waker>,
+    sender: Option<Waker>,
+}
+
+/// The driver's end of a [`channel`].
+pub struct EventSender(Arc<Mutex<Channel>>);
+
+/// The consumer's end of a [`channel`].
+pub struct EventReceiver(Arc<Mutex<Channel>>);
+
+/// A channel buffering up to `frames` frames of events.
+///
+/// # Panics
+///
+/// Panics if `frames` is 0.
+pub fn channel(frames: usize) -> (EventSender, EventReceiver) {
+    assert!(frames > 0, "the channel must hold at least one frame");
+    let channel = Arc::new(Mutex::new(Channel {
+        queue: VecDeque::with_capacity(frames),
+        capacity: frames,
+        closed: false,
+        receiver: None,
+        sender: None,
+    }));
+    (EventSender(channel.clone()), EventReceiver(channel))
+}
+
+/// Locks `mutex`, ignoring poisoning: nothing here panics while holding
+/// one.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl EventReceiver {
+    /// Waits for the next frame, or `None` once the sender is dropped and
+    /// every frame sent has been received.
+    pub fn recv(&mut self) -> impl Future<Output = Option<FrameEvent>> + '_ {
+        std::future::poll_fn(|cx| {
+            let mut channel = lock(&self.0);
+            if let Some(event) = channel.queue.pop_front() {
+                if let Some(sender) = channel.sender.take() {
+                    sender.wake();
+                }
+                return Poll::Ready(Some(event));
+            }
+            if channel.closed {
+                return Poll::Ready(None);
+            }
+            channel.receiver = Some(cx.waker().clone());
+            Poll::Pending
+        })
+    }
+
+    /// The next frame if one is waiting.
+    pub fn try_recv(&mut self) -> Option<FrameEvent> {
+        let mut channel = lock(&self.0);
+        let event = channel.queue.pop_front();
+        if event.is_some()
+            && let Some(sender) = channel.sender.take()
+        {
+            sender.wake();
+        }
+        event
+    }
+}
+
+impl Drop for EventReceiver {
+    fn drop(&mut self) {
+        let mut channel = lock(&self.0);
+        channel.closed = true;
+        if let Some(sender) = channel.sender.take() {
+            sender.wake();
+        }
+    }
+}
+
+impl Drop for EventSender {
+    fn drop(&mut self) {
+        let mut channel = lock(&self.0);
+        channel.closed = true;
+        if let Some(receiver) = channel.receiver.take() {
+            receiver.wake();
+        }
+    }
+}
+
+/// Future returned by [`Vm::run_async`].
+struct RunAsync<'a> {
+    vm: &'a mut Vm,
+    stop: &'a StopToken,
+    events: &'a EventSender,
+    frames: u64,
+}
+
+impl Future for RunAsync<'_> {
+    type Output = Result<u64, VmError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        *lock(&this.stop.0.waker) = Some(cx.waker().clone());
+        if this.stop.is_stopped() {
+            return Poll::Ready(Ok(this.frames));
+        }
+        {
+            let mut channel = lock(&this.events.0);
+            if channel.closed {
+                return Poll::Ready(Ok(this.frames));
+            }
+            if channel.queue.len() >= channel.capacity {
+                channel.sender = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+        }
+        let event = this.vm.run_frame_for_event()?;
+        this.frames += 1;
+        let mut channel = lock(&this.events.0);
+        channel.queue.push_back(event);
+        if let Some(receiver) = channel.receiver.take() {
+            receiver.wake();
+        }
+        drop(channel);
+        // Yield, so the frame boundary is a point where other tasks run.
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+impl Vm {
+    /// Runs frames until `stop` is stopped or the receiving end of `events`
+    /// is dropped, sending a [`FrameEvent`] for each and yielding to the
+    /// executor after every one. Returns the frames run.
+    ///
+    /// The vblank and audio callbacks, if set, still see every frame. On
+    /// error the failing frame is left unfinished, as [`Vm::run_frame`]
+    /// leaves it, and sends nothing. Dropping the future stops it at the
+    /// last frame boundary.
+    pub fn run_async<'a>(
+        &'a mut self,
+        stop: &'a StopToken,
+        events: &'a EventSender,
+    ) -> impl Future<Output = Result<u64, VmError>> + 'a {
+        RunAsync {
+            vm: self,
+            stop,
+            events,
+            frames: 0,
+        }
+    }
+
+    /// Runs one frame, collecting its audio alongside any audio callback.
+    fn run_frame_for_event(&mut self) -> Result<FrameEvent, VmError> {
+        let callback = self.audio.callback.take();
+        let samples = Arc::new(Mutex::new(Vec::new()));
+        let sink = samples.clone();
+        self.set_audio_callback(move |frame| lock(&sink).extend_from_slice(frame));
+        let result = self.run_frame();
+        self.audio.callback = callback;
+        result?;
+        let samples = std::mem::take(&mut *lock(&samples));
+        if let Some(callback) = &mut self.audio.callback {
+            callback(&samples);
+        }
+        Ok(FrameEvent {
+            frame: self.frame(),
+            pixels: self.framebuffer().into(),
+            samples,
+        })
+    }
+}
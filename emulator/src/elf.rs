@@ -0,0 +1,294 @@
+//! ELF executables.
+//!
+//! Toolchains such as llvm-mos link 8-bit programs into 32-bit
+//! little-endian ELF files. [`Elf::parse`] reads one, and [`Vm::load_elf`]
+//! runs it without converting it to a raw binary first:
+//!
+//! * every `PT_LOAD` segment is copied to its load address (LMA, the
+//!   physical address), with the bytes past its file contents cleared as
+//!   for `.bss`; a file with no program headers has its allocated sections
+//!   copied to their addresses instead;
+//! * the PC is set to the entry point;
+//! * named function, object and untyped symbols from `.symtab` become the
+//!   machine's [symbol table](crate::symbols), for the debugger and traces.
+//!
+//! Anything that does not fit the 16-bit address space is rejected with
+//! [`ElfError::OutOfRange`] rather than truncated. The machine type in the
+//! header is not checked, since toolchains disagree on it.
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use crate::ffi::RVM_MEM_SIZE;
+use crate::symbols::SymbolTable;
+use crate::vm::{Registers, Vm};
+
+/// File magic.
+pub const MAGIC: [u8; 4] = *b"\x7FELF";
+
+const HEADER_SIZE: usize = 52;
+const CLASS_32: u8 = 1;
+const DATA_LITTLE_ENDIAN: u8 = 1;
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_NOBITS: u32 = 8;
+const SHF_ALLOC: u32 = 0x2;
+const SHN_UNDEF: u16 = 0;
+const SHN_ABS: u16 = 0xFFF1;
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+/// Why an ELF file could not be loaded.
+#[derive(Debug)]
+pub enum ElfError {
+    /// The file could not be read.
+    Io(io::Error),
+    /// A header or table runs past the end of the data.
+    Truncated,
+    /// The data does not start with [`MAGIC`].
+    BadMagic,
+    /// The file is valid ELF of a kind this loader does not take, such as
+    /// 64-bit or big-endian.
+    Unsupported(&'static str),
+    /// `len` bytes at `addr`, or the entry point when `len` is 0, do not
+    /// fit the address space.
+    OutOfRange { addr: u64, len: u64 },
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "cannot read ELF file: {err}"),
+            Self::Truncated => write!(f, "ELF file is truncated"),
+            Self::BadMagic => write!(f, "not an ELF file"),
+            Self::Unsupported(what) => write!(f, "unsupported ELF file: {what}"),
+            Self::OutOfRange { addr, len: 0 } => {
+                write!(f, "entry point 0x{addr:X} is outside the address space")
+            }
+            Self::OutOfRange { addr, len } => {
+                write!(f, "{len} bytes at 0x{addr:X} do not fit the address space")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ElfError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ElfError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// A block of memory an [`Elf`] loads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    /// Load address.
+    pub addr: u16,
+    /// Contents, cleared `.bss` included.
+    pub data: Vec<u8>,
+}
+
+/// A parsed ELF executable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Elf {
+    entry: u16,
+    segments: Vec<Segment>,
+    symbols: SymbolTable,
+}
+
+/// Little-endian fields of the file, bounds-checked.
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn bytes(&self, at: u64, len: u64) -> Result<&[u8], ElfError> {
+        let start = usize::try_from(at).map_err(|_| ElfError::Truncated)?;
+        let len = usize::try_from(len).map_err(|_| ElfError::Truncated)?;
+        let end = start.checked_add(len).ok_or(ElfError::Truncated)?;
+        self.0.get(start..end).ok_or(ElfError::Truncated)
+    }
+
+    fn u8(&self, at: u64) -> Result<u8, ElfError> {
+        Ok(self.bytes(at, 1)?[0])
+    }
+
+    fn u16(&self, at: u64) -> Result<u16, ElfError> {
+        let bytes = self.bytes(at, 2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&self, at: u64) -> Result<u32, ElfError> {
+        let bytes = self.bytes(at, 4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().expect("4 bytes")))
+    }
+}
+
+/// `len` bytes at `addr` as a [`Segment`] start, if they fit.
+fn load_address(addr: u32, len: u32) -> Result<u16, ElfError> {
+    if u64::from(addr) + u64::from(len) > RVM_MEM_SIZE as u64 {
+        return Err(ElfError::OutOfRange {
+            addr: addr.into(),
+            len: len.into(),
+        });
+    }
+    Ok(addr as u16)
+}
+
+impl Elf {
+    /// Parses an ELF file's contents.
+    pub fn parse(bytes: &[u8]) -> Result<Self, ElfError> {
+        let file = Reader(bytes);
+        if file.bytes(0, 4).map_err(|_| ElfError::BadMagic)? != MAGIC {
+            return Err(ElfError::BadMagic);
+        }
+        if bytes.len() < HEADER_SIZE {
+            return Err(ElfError::Truncated);
+        }
+        if file.u8(4)? != CLASS_32 {
+            return Err(ElfError::Unsupported("not a 32-bit file"));
+        }
+        if file.u8(5)? != DATA_LITTLE_ENDIAN {
+            return Err(ElfError::Unsupported("not little-endian"));
+        }
+        let entry = file.u32(24)?;
+        if entry >= RVM_MEM_SIZE as u32 {
+            return Err(ElfError::OutOfRange {
+                addr: entry.into(),
+                len: 0,
+            });
+        }
+        let (phoff, shoff) = (file.u32(28)?, file.u32(32)?);
+        let (phentsize, phnum) = (file.u16(42)?, file.u16(44)?);
+        let (shentsize, shnum) = (file.u16(46)?, file.u16(48)?);
+
+        let header = |offset: u32, size: u16, index: u16| {
+            u64::from(offset) + u64::from(size) * u64::from(index)
+        };
+        let mut segments = Vec::new();
+        for index in 0..phnum {
+            let at = header(phoff, phentsize, index);
+            if file.u32(at)? != PT_LOAD {
+                continue;
+            }
+            let (offset, paddr) = (file.u32(at + 4)?, file.u32(at + 12)?);
+            let (filesz, memsz) = (file.u32(at + 16)?, file.u32(at + 20)?);
+            let addr = load_address(paddr, memsz.max(filesz))?;
+            let mut data = file.bytes(offset.into(), filesz.into())?.to_vec();
+            data.resize(memsz.max(filesz) as usize, 0);
+            if !data.is_empty() {
+                segments.push(Segment { addr, data });
+            }
+        }
+
+        let mut symbols = SymbolTable::new();
+        for index in 0..shnum {
+            let at = header(shoff, shentsize, index);
+            let (kind, flags) = (file.u32(at + 4)?, file.u32(at + 8)?);
+            let (addr, offset, size) = (file.u32(at + 12)?, file.u32(at + 16)?, file.u32(at + 20)?);
+            if kind == SHT_SYMTAB {
+                let link = file.u32(at + 24)?;
+                let strtab = header(shoff, shentsize, link as u16);
+                let strings =
+                    file.bytes(file.u32(strtab + 16)?.into(), file.u32(strtab + 20)?.into())?;
+                read_symbols(&file, offset, size, strings, &mut symbols)?;
+            } else if phnum == 0 && flags & SHF_ALLOC != 0 && size > 0 {
+                let addr = load_address(addr, size)?;
+                let data = match kind {
+                    SHT_NOBITS => vec![0; size as usize],
+                    _ => file.bytes(offset.into(), size.into())?.to_vec(),
+                };
+                segments.push(Segment { addr, data });
+            }
+        }
+        Ok(Self {
+            entry: entry as u16,
+            segments,
+            symbols,
+        })
+    }
+
+    /// Reads and parses an ELF file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ElfError> {
+        Self::parse(&std::fs::read(path)?)
+    }
+
+    /// Whether `bytes` start like an ELF file.
+    pub fn is_elf(bytes: &[u8]) -> bool {
+        bytes.starts_with(&MAGIC)
+    }
+
+    /// Where execution starts.
+    pub fn entry(&self) -> u16 {
+        self.entry
+    }
+
+    /// What gets loaded, in file order.
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// The symbols the file names.
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+}
+
+/// Adds the symbols of a `.symtab` at `offset` to `table`.
+fn read_symbols(
+    file: &Reader<'_>,
+    offset: u32,
+    size: u32,
+    strings: &[u8],
+    table: &mut SymbolTable,
+) -> Result<(), ElfError> {
+    const ENTRY_SIZE: u32 = 16;
+    for index in 0..size / ENTRY_SIZE {
+        let at = u64::from(offset + index * ENTRY_SIZE);
+        let (name, value) = (file.u32(at)?, file.u32(at + 4)?);
+        let (info, shndx) = (file.u8(at + 12)?, file.u16(at + 14)?);
+        let typed = matches!(info & 0xF, STT_NOTYPE | STT_OBJECT | STT_FUNC);
+        let defined = shndx != SHN_UNDEF && (shndx < 0xFF00 || shndx == SHN_ABS);
+        if !typed || !defined || value >= RVM_MEM_SIZE as u32 {
+            continue;
+        }
+        let name = strings.get(name as usize..).unwrap_or_default();
+        let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+        if let Ok(name) = std::str::from_utf8(name)
+            && !name.is_empty()
+        {
+            table.insert(name, value as u16);
+        }
+    }
+    Ok(())
+}
+
+impl Vm {
+    /// Loads `elf`'s segments, points the PC at its entry and makes its
+    /// symbols the machine's, keeping any symbols set before that it does
+    /// not redefine. The rest of the machine is left as it is; see the
+    /// [module docs](crate::elf).
+    pub fn load_elf(&mut self, elf: &Elf) {
+        for segment in &elf.segments {
+            self.load(segment.addr, &segment.data)
+                .expect("segments fit the address space");
+        }
+        let mut symbols = self.take_symbols().unwrap_or_default();
+        for (name, addr) in elf.symbols.iter() {
+            symbols.insert(name, addr);
+        }
+        self.set_symbols(symbols);
+        self.set_registers(Registers {
+            pc: elf.entry,
+            ..self.registers()
+        });
+    }
+}
@@ -0,0 +1,133 @@
+//! Errors reported by the safe [`Vm`](crate::Vm) API.
+
+use std::fmt;
+
+use crate::ffi::BusAccess;
+use crate::mpu::Violation;
+
+/// A failed VM operation.
+///
+/// An error says where the machine stopped; [`Vm::history`](crate::Vm::history)
+/// keeps the instructions that led there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmError {
+    /// The CPU fetched an opcode with no handler. `pc` is the address of the
+    /// opcode byte; the CPU has already moved past it.
+    IllegalOpcode { pc: u16, opcode: u8 },
+    /// A block of `len` bytes starting at `addr` does not fit in the 64 KiB
+    /// address space.
+    OutOfBounds { addr: u16, len: usize },
+    /// A snapshot cannot be restored into this machine.
+    InvalidSnapshot(&'static str),
+    /// A device cannot be mapped because `start..=end` already has one.
+    MapConflict { start: u16, end: u16 },
+    /// The CPU accessed `addr`, which has no RAM or device behind it, and
+    /// the [`BusConfig`](crate::bus::BusConfig) traps such accesses. The
+    /// instruction has completed.
+    BusFault { addr: u16, kind: BusAccess },
+    /// The device mapped at `addr` panicked while serving a CPU access,
+    /// with `message` as its panic message. The panic was caught at the
+    /// kernel boundary and the instruction completed with the access
+    /// claimed: a read saw 0 and a write went nowhere. The device may be in
+    /// an inconsistent state.
+    DevicePanic {
+        addr: u16,
+        kind: BusAccess,
+        message: String,
+    },
+    /// [`Vm::register_opcode`](crate::Vm::register_opcode) was given an
+    /// opcode the kernel implements.
+    OpcodeTaken { opcode: u8 },
+    /// The handler registered for the extension opcode at `pc` returned an
+    /// error or panicked, with `message` saying why. The instruction
+    /// completed with whatever the handler had done to the CPU and memory.
+    OpcodeFailed {
+        pc: u16,
+        opcode: u8,
+        message: String,
+    },
+    /// The instruction at `pc` broke the [MPU](crate::mpu)'s protection of
+    /// `addr`. The offending access was blocked; a fetch from no-execute
+    /// memory left the instruction unexecuted, and any other violation let
+    /// it complete.
+    MpuFault {
+        pc: u16,
+        addr: u16,
+        violation: Violation,
+    },
+    /// The instruction at `pc` wrote `val` to `addr`, which is ROM, and the
+    /// [`BusConfig`](crate::bus::BusConfig) traps such writes. The write was
+    /// dropped and the instruction completed.
+    RomWrite { pc: u16, addr: u16, val: u8 },
+    /// The instruction at `pc` pushed the stack pointer below the
+    /// [stack bounds](crate::stack), leaving it at `sp`. The instruction
+    /// completed.
+    StackOverflow { pc: u16, sp: u16 },
+    /// The instruction at `pc` popped the stack pointer above the
+    /// [stack bounds](crate::stack), leaving it at `sp`. The instruction
+    /// completed.
+    StackUnderflow { pc: u16, sp: u16 },
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IllegalOpcode { pc, opcode } => {
+                write!(f, "illegal opcode 0x{opcode:02X} at PC 0x{pc:04X}")
+            }
+            Self::OutOfBounds { addr, len } => {
+                write!(f, "{len} bytes at 0x{addr:04X} overrun the address space")
+            }
+            Self::InvalidSnapshot(reason) => write!(f, "invalid snapshot: {reason}"),
+            Self::MapConflict { start, end } => {
+                write!(f, "0x{start:04X}..=0x{end:04X} already has a device mapped")
+            }
+            Self::BusFault { addr, kind } => {
+                let kind = match kind {
+                    BusAccess::Read => "read",
+                    BusAccess::Write => "write",
+                };
+                write!(f, "unmapped {kind} at 0x{addr:04X}")
+            }
+            Self::DevicePanic {
+                addr,
+                kind,
+                message,
+            } => {
+                let kind = match kind {
+                    BusAccess::Read => "read",
+                    BusAccess::Write => "write",
+                };
+                write!(f, "device panicked on {kind} at 0x{addr:04X}: {message}")
+            }
+            Self::OpcodeTaken { opcode } => {
+                write!(f, "opcode 0x{opcode:02X} is already an instruction")
+            }
+            Self::OpcodeFailed {
+                pc,
+                opcode,
+                message,
+            } => write!(
+                f,
+                "extension opcode 0x{opcode:02X} at PC 0x{pc:04X} failed: {message}"
+            ),
+            Self::MpuFault {
+                pc,
+                addr,
+                violation,
+            } => write!(f, "MPU fault at PC 0x{pc:04X}: {violation} at 0x{addr:04X}"),
+            Self::RomWrite { pc, addr, val } => write!(
+                f,
+                "write of 0x{val:02X} to ROM at 0x{addr:04X} from PC 0x{pc:04X}"
+            ),
+            Self::StackOverflow { pc, sp } => {
+                write!(f, "stack overflow at PC 0x{pc:04X}: SP is 0x{sp:02X}")
+            }
+            Self::StackUnderflow { pc, sp } => {
+                write!(f, "stack underflow at PC 0x{pc:04X}: SP is 0x{sp:02X}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
@@ -0,0 +1,115 @@
+//! Batched machine events.
+//!
+//! The [vblank](Vm::set_vblank_callback) and
+//! [audio](Vm::set_audio_callback) callbacks run in the middle of
+//! [`Vm::run_frame`], with the machine borrowed, which is awkward for a
+//! host and costly when every call crosses an FFI boundary. A host can
+//! instead [`Vm::enable_events`] and take what happened after each frame
+//! from one queue:
+//!
+//! ```
+//! # use emulator::{events::Event, ResetKind, Vm};
+//! let mut vm = Vm::new();
+//! vm.enable_events();
+//! vm.load(0x8000, &[0xA9; 0x8000]).unwrap(); // LDA #$A9 over and over
+//! vm.load(0xFFFC, &[0x00, 0x80]).unwrap();
+//! vm.reset(ResetKind::Soft);
+//! vm.run_frame().unwrap();
+//! for event in vm.drain_events() {
+//!     match event {
+//!         Event::Vblank { frame } => assert_eq!(frame, 1),
+//!         Event::Audio(samples) => assert!(!samples.is_empty()),
+//!         Event::Serial { .. } | Event::Fault(_) => unreachable!(),
+//!     }
+//! }
+//! ```
+//!
+//! A frame queues the bytes its [UARTs](crate::uart) sent without a writer
+//! connected, then its vblank and then its audio. A frame that fails
+//! queues the bytes sent up to the failure and the error. Callbacks keep
+//! working alongside the queue, which holds events until they are drained,
+//! however many frames that takes. Frames run again by [`Vm::rewind`]
+//! queue nothing.
+
+use std::collections::VecDeque;
+use std::collections::vec_deque::Drain;
+
+use crate::error::VmError;
+use crate::uart::Uart;
+use crate::vm::Vm;
+
+/// Something that happened while the machine ran.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// Frame `frame` ended and was rendered into [`Vm::framebuffer`], as
+    /// the vblank callback would be told.
+    Vblank { frame: u64 },
+    /// A frame's audio, the samples the audio callback would get.
+    Audio(Vec<i16>),
+    /// Bytes the UART mapped at `port` sent with no writer connected, taken
+    /// as [`Uart::take_tx`] would.
+    Serial { port: u16, bytes: Vec<u8> },
+    /// [`Vm::run_frame`] returned this error.
+    Fault(VmError),
+}
+
+/// Events queued and not drained yet.
+#[derive(Debug, Default)]
+pub(crate) struct EventQueue {
+    events: VecDeque<Event>,
+    enabled: bool,
+}
+
+impl Vm {
+    /// Starts queueing [events](self) for [`Vm::drain_events`].
+    pub fn enable_events(&mut self) {
+        self.events.enabled = true;
+    }
+
+    /// Stops queueing events and drops those not drained yet.
+    pub fn disable_events(&mut self) {
+        self.events = EventQueue::default();
+    }
+
+    /// Takes every queued event, oldest first.
+    pub fn drain_events(&mut self) -> Drain<'_, Event> {
+        self.events.events.drain(..)
+    }
+
+    /// Takes the oldest queued event.
+    pub fn next_event(&mut self) -> Option<Event> {
+        self.events.events.pop_front()
+    }
+
+    /// Events queued and not drained yet.
+    pub fn pending_events(&self) -> usize {
+        self.events.events.len()
+    }
+
+    pub(crate) fn events_enabled(&self) -> bool {
+        self.events.enabled
+    }
+
+    pub(crate) fn queue_event(&mut self, event: Event) {
+        if self.events.enabled {
+            self.events.events.push_back(event);
+        }
+    }
+
+    /// Queues what every UART has sent since the last time.
+    pub(crate) fn queue_serial(&mut self) {
+        if !self.events_enabled() {
+            return;
+        }
+        let sent: Vec<Event> = self
+            .bus_mut()
+            .devices_mut::<Uart>()
+            .map(|(range, uart)| (*range.start(), uart.take_tx()))
+            .filter(|(_, bytes)| !bytes.is_empty())
+            .map(|(port, bytes)| Event::Serial { port, bytes })
+            .collect();
+        for event in sent {
+            self.queue_event(event);
+        }
+    }
+}
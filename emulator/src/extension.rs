@@ -0,0 +1,174 @@
+//! Extension opcodes implemented in Rust.
+//!
+//! The kernel leaves most of the 256 opcode bytes undefined. Every `Vm`
+//! installs a trampoline as the CPU's `ext_handler`, and
+//! [`Vm::register_opcode`] claims an undefined byte for a Rust handler by
+//! enabling it in `Cpu::ext_opcodes`: the kernel then calls the handler
+//! instead of reporting an illegal opcode. That makes room for host calls,
+//! accelerated math routines or assertions in test programs:
+//!
+//! ```
+//! # use emulator::Vm;
+//! let mut vm = Vm::new();
+//! // 0x02 nn: multiply A by the operand, taking 8 cycles.
+//! vm.register_opcode(0x02, |call| {
+//!     let operand = call.fetch();
+//!     let mut regs = call.registers();
+//!     regs.a = regs.a.wrapping_mul(operand);
+//!     call.set_registers(regs);
+//!     Ok(8)
+//! })
+//! .unwrap();
+//! vm.load(0x0000, &[0xA9, 6, 0x02, 7]).unwrap();
+//! vm.step().unwrap();
+//! vm.step().unwrap();
+//! assert_eq!(vm.registers().a, 42);
+//! ```
+//!
+//! Extension opcodes count as executed instructions in the
+//! [statistics](crate::Stats), coverage and profiles, and run inside
+//! batches like built-in ones. The disassembler and assembler do not know
+//! them.
+
+use std::ffi::c_void;
+use std::panic::{self, AssertUnwindSafe};
+
+use rvm8_core::OPCODES;
+
+use crate::bus::{self, Bus};
+use crate::error::VmError;
+use crate::ffi::{self, Cpu};
+use crate::vm::{Registers, Vm};
+
+/// A handler for one extension opcode.
+pub(crate) type OpcodeHandler = dyn FnMut(&mut OpcodeCall<'_>) -> Result<u8, String> + Send;
+
+/// The CPU as an extension opcode's handler sees it, with the PC just past
+/// the opcode byte.
+///
+/// Memory accesses go through the bus like the CPU's own, so devices,
+/// watchpoints and hooks see them.
+pub struct OpcodeCall<'a> {
+    cpu: &'a mut Cpu,
+    opcode: u8,
+}
+
+impl OpcodeCall<'_> {
+    /// The opcode being executed.
+    pub fn opcode(&self) -> u8 {
+        self.opcode
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            a: self.cpu.a,
+            x: self.cpu.x,
+            y: self.cpu.y,
+            pc: self.cpu.pc,
+            sp: self.cpu.sp,
+            flags: self.cpu.flags,
+        }
+    }
+
+    /// Overwrites every register with `regs`.
+    pub fn set_registers(&mut self, regs: Registers) {
+        self.cpu.a = regs.a;
+        self.cpu.x = regs.x;
+        self.cpu.y = regs.y;
+        self.cpu.pc = regs.pc;
+        self.cpu.sp = regs.sp;
+        self.cpu.flags = regs.flags;
+    }
+
+    /// Reads a byte as the CPU would.
+    pub fn read(&mut self, addr: u16) -> u8 {
+        // SAFETY: `cpu` is the initialized CPU executing the opcode, and the
+        // trampoline holds no reference to the bus while the handler runs.
+        unsafe { ffi::mem_read(self.cpu, addr) }
+    }
+
+    /// Writes a byte as the CPU would.
+    pub fn write(&mut self, addr: u16, val: u8) {
+        // SAFETY: see `OpcodeCall::read`.
+        unsafe { ffi::mem_write(self.cpu, addr, val) }
+    }
+
+    /// Reads the operand byte at the PC and moves the PC past it.
+    pub fn fetch(&mut self) -> u8 {
+        let val = self.read(self.cpu.pc);
+        self.cpu.pc = self.cpu.pc.wrapping_add(1);
+        val
+    }
+}
+
+/// The [`ExtHandler`](crate::ffi::ExtHandler) installed on every `Vm`'s
+/// CPU.
+///
+/// The handler is taken out of the [`Bus`] while it runs, so its memory
+/// accesses can reach the bus through [`bus::trampoline`]. An error or a
+/// panic is kept as a [`VmError::OpcodeFailed`] for the `Vm` to report
+/// after the instruction, and ends a [`ffi::cpu_run`] batch like a claimed
+/// access.
+pub(crate) unsafe extern "C" fn trampoline(ctx: *mut c_void, cpu: *mut Cpu, opcode: u8) -> u8 {
+    let bus = ctx.cast::<Bus>();
+    // SAFETY: `ctx` is the `Bus` allocation owned by the `Vm` whose CPU is
+    // executing and no Rust reference to it is live during a kernel call;
+    // this one ends before the handler runs.
+    let Some(mut handler) = (unsafe { (*bus).opcodes[opcode as usize].take() }) else {
+        return 0;
+    };
+    // SAFETY: the kernel passes the CPU it is executing, which nothing else
+    // borrows until the handler returns.
+    let cpu = unsafe { &mut *cpu };
+    let pc = cpu.pc.wrapping_sub(1);
+    let mut call = OpcodeCall { cpu, opcode };
+    let result = panic::catch_unwind(AssertUnwindSafe(|| handler(&mut call)));
+    let cpu = call.cpu;
+    // SAFETY: as above; the handler's accesses are over.
+    let bus = unsafe { &mut *bus };
+    bus.opcodes[opcode as usize] = Some(handler);
+    let message = match result {
+        Ok(Ok(cycles)) => return cycles,
+        Ok(Err(message)) => message,
+        Err(payload) => bus::panic_message(payload),
+    };
+    if bus.fault.is_none() {
+        bus.fault = Some(VmError::OpcodeFailed {
+            pc,
+            opcode,
+            message,
+        });
+    }
+    cpu.bus_claimed = 1;
+    0
+}
+
+impl Vm {
+    /// Makes `opcode`, which the kernel does not implement, execute
+    /// `handler`, replacing any handler registered for it before. Fails
+    /// with [`VmError::OpcodeTaken`] for an opcode the kernel implements.
+    ///
+    /// The handler fetches its own operands with [`OpcodeCall::fetch`] and
+    /// returns the cycles the instruction took, or an error message that
+    /// stops the machine with [`VmError::OpcodeFailed`] once the
+    /// instruction completes. Registrations are host state: save states do
+    /// not carry them.
+    pub fn register_opcode(
+        &mut self,
+        opcode: u8,
+        handler: impl FnMut(&mut OpcodeCall<'_>) -> Result<u8, String> + Send + 'static,
+    ) -> Result<(), VmError> {
+        if OPCODES[opcode as usize].is_some() {
+            return Err(VmError::OpcodeTaken { opcode });
+        }
+        self.bus_mut().opcodes[opcode as usize] = Some(Box::new(handler));
+        self.cpu.ext_opcodes[opcode as usize] = 1;
+        Ok(())
+    }
+
+    /// Makes `opcode` illegal again, returning whether it had a handler.
+    pub fn unregister_opcode(&mut self, opcode: u8) -> bool {
+        self.cpu.ext_opcodes[opcode as usize] = 0;
+        self.bus_mut().opcodes[opcode as usize].take().is_some()
+    }
+}
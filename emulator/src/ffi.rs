@@ -0,0 +1,54 @@
+//! Raw bindings to the rvm-8 kernel.
+//!
+//! Everything here mirrors `kernel/cpu.h` one-to-one. The types and
+//! constants come from [`rvm8_core`], which lays them out like the C
+//! headers. By default the functions are resolved against the C kernel
+//! compiled by `build.rs`; in a build without it, under `pure-rust` or
+//! without the `c-kernel` feature, they are re-exported from `crate::cpu`
+//! instead, with identical signatures, so callers never need to know which
+//! core they are talking to.
+
+#[cfg(c_kernel)]
+use std::ffi::c_int;
+
+pub use rvm8_core::{
+    AddressingMode, BusAccess, BusHook, Cpu, CpuStats, ExtHandler, FLAG_B, FLAG_C, FLAG_D, FLAG_I,
+    FLAG_N, FLAG_V, FLAG_Z, ILLEGAL_NOP, ILLEGAL_TRAP, ILLEGAL_UNDOCUMENTED, INT_IRQ,
+    INT_IRQ_MASKED, INT_NMI, INT_NMI_LEVEL, IRQ_CYCLES, IRQ_VECTOR, NMI_VECTOR, RVM_BAD_STATE,
+    RVM_ILLEGAL_OPCODE, RVM_MEM_SIZE, RVM_OK, RVM_STATE_SIZE, RVM_STATE_VERSION,
+};
+
+#[cfg(c_kernel)]
+unsafe extern "C" {
+    /// Initializes `cpu`, attaches `memory` and loads PC from the reset vector.
+    pub fn cpu_init(cpu: *mut Cpu, memory: *mut u8);
+    /// Resets registers to their power-on defaults, keeping the memory.
+    pub fn cpu_reset(cpu: *mut Cpu);
+    /// Executes a single instruction, returning `RVM_OK` or an error status.
+    pub fn cpu_step(cpu: *mut Cpu) -> c_int;
+    /// Executes instructions until at least `budget` cycles have elapsed, an
+    /// illegal opcode is hit or the bus hook claims an access, returning the
+    /// first status other than `RVM_OK`.
+    pub fn cpu_run(cpu: *mut Cpu, budget: u32) -> c_int;
+    /// Repeats the IRQ sample of the last interrupt poll with the current
+    /// `irq` line.
+    pub fn cpu_poll_irq(cpu: *mut Cpu);
+    /// Decodes an address through the CPU's `page_map`.
+    pub fn mem_decode(cpu: *const Cpu, addr: u16) -> u16;
+    /// Reads a byte through the CPU's memory helpers.
+    pub fn mem_read(cpu: *mut Cpu, addr: u16) -> u8;
+    /// Writes a byte through the CPU's memory helpers.
+    pub fn mem_write(cpu: *mut Cpu, addr: u16, val: u8);
+    /// Writes the CPU state and memory in the portable layout into `buf` if
+    /// it holds `RVM_STATE_SIZE` bytes, returning `RVM_STATE_SIZE`.
+    pub fn rvm8_export_state(cpu: *const Cpu, buf: *mut u8, len: usize) -> usize;
+    /// Replaces the CPU state and memory with an exported state, returning
+    /// `RVM_OK` or `RVM_BAD_STATE`.
+    pub fn rvm8_import_state(cpu: *mut Cpu, buf: *const u8, len: usize) -> c_int;
+}
+
+#[cfg(not(c_kernel))]
+pub use crate::cpu::{
+    cpu_init, cpu_poll_irq, cpu_reset, cpu_run, cpu_step, mem_decode, mem_read, mem_write,
+    rvm8_export_state, rvm8_import_state,
+};
@@ -0,0 +1,312 @@
+//! File service.
+//!
+//! A [`FileService`] lets a program open, read, write and seek files the
+//! host keeps, so levels, graphics and saves need not all fit in the ROM.
+//! It serves either the files of one host directory or a single disk image,
+//! which a program then reads and writes as a block of bytes. It is not
+//! mapped by default; [`FILE_PORTS`] is its conventional slot:
+//!
+//! ```no_run
+//! # use emulator::{files::{FileService, FILE_PORTS}, Vm};
+//! let mut vm = Vm::new();
+//! vm.bus_mut().map(FILE_PORTS, FileService::directory("assets")).unwrap();
+//! ```
+//!
+//! | Offset | Register                                                   |
+//! | ------ | ---------------------------------------------------------- |
+//! | 0      | `CMD`: writing runs a command; reading gives the status    |
+//! | 1      | `NAME`: each byte written adds to the name the next command opens |
+//! | 2      | `DATA`: reads or writes the byte at the position, moving on |
+//! | 3–6    | `POS`, little-endian: reads the position, written for [`CMD_SEEK`] |
+//!
+//! The commands are [`CMD_OPEN`], [`CMD_CREATE`], [`CMD_CLOSE`],
+//! [`CMD_SEEK`] and [`CMD_END`]; each leaves [`STATUS_OK`] or an error
+//! status, and so does every `DATA` access. One file is open at a time, and
+//! opening another closes it. A program reads a file's size by moving to
+//! its end and reading `POS`.
+//!
+//! Names are single file names within the directory: no paths, no `.` or
+//! `..`. A disk image opens whatever the name, and cannot be created or
+//! grown. An open file is held in memory, and what the program wrote goes
+//! back to the host when it is closed, by [`CMD_CLOSE`], another open, a
+//! reset or dropping the device. A [`Snapshot`](crate::Snapshot) keeps the
+//! registers but not the file, so a restored machine finds it closed.
+
+use std::fs;
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use crate::bus::BusDevice;
+use crate::reset::ResetKind;
+
+/// Suggested place for the file service registers.
+pub const FILE_PORTS: RangeInclusive<u16> = 0x2790..=0x2796;
+
+/// Opens the named file for reading.
+pub const CMD_OPEN: u8 = 1;
+/// Creates the named file, or empties it, for reading and writing.
+pub const CMD_CREATE: u8 = 2;
+/// Closes the open file, writing back what changed.
+pub const CMD_CLOSE: u8 = 3;
+/// Moves to the position written to `POS`, which may be past the end.
+pub const CMD_SEEK: u8 = 4;
+/// Moves to the end of the open file.
+pub const CMD_END: u8 = 5;
+/// Size a file can grow to.
+pub const FILE_MAX: u32 = 16 << 20;
+
+pub const STATUS_OK: u8 = 0;
+/// A `DATA` read past the end of the file, which reads as 0.
+pub const STATUS_EOF: u8 = 1;
+/// The named file does not exist.
+pub const STATUS_NOT_FOUND: u8 = 2;
+/// A bad name or command, no file open, a write to a file opened for
+/// reading, past the end of a disk image or past [`FILE_MAX`], or a host
+/// I/O error.
+pub const STATUS_ERROR: u8 = 3;
+
+const CMD: u16 = 0;
+const NAME: u16 = 1;
+const DATA: u16 = 2;
+const POS: u16 = 3;
+const POS_LAST: u16 = 6;
+/// Longest name kept; longer ones fail to open.
+const NAME_MAX: usize = 255;
+
+#[derive(Debug)]
+enum Backing {
+    Directory(PathBuf),
+    Image(PathBuf),
+}
+
+#[derive(Debug)]
+struct OpenFile {
+    path: PathBuf,
+    data: Vec<u8>,
+    writable: bool,
+    dirty: bool,
+}
+
+impl OpenFile {
+    fn write_back(&mut self) -> io::Result<()> {
+        if self.dirty {
+            fs::write(&self.path, &self.data)?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+/// The file service device.
+#[derive(Debug)]
+pub struct FileService {
+    backing: Backing,
+    file: Option<OpenFile>,
+    name: Vec<u8>,
+    pos: u32,
+    /// `POS` as last written, for [`CMD_SEEK`].
+    seek: [u8; 4],
+    status: u8,
+}
+
+impl FileService {
+    /// A service for the files in `root`.
+    pub fn directory(root: impl Into<PathBuf>) -> Self {
+        Self::new(Backing::Directory(root.into()))
+    }
+
+    /// A service for the disk image at `path`, written back in place.
+    pub fn image(path: impl Into<PathBuf>) -> Self {
+        Self::new(Backing::Image(path.into()))
+    }
+
+    fn new(backing: Backing) -> Self {
+        Self {
+            backing,
+            file: None,
+            name: Vec::new(),
+            pos: 0,
+            seek: [0; 4],
+            status: STATUS_OK,
+        }
+    }
+
+    /// Where the files come from: the directory or the disk image.
+    pub fn root(&self) -> &Path {
+        match &self.backing {
+            Backing::Directory(root) | Backing::Image(root) => root,
+        }
+    }
+
+    /// Closes the open file, if any, writing back what changed.
+    pub fn close(&mut self) -> io::Result<()> {
+        self.pos = 0;
+        match self.file.take() {
+            Some(mut file) => file.write_back(),
+            None => Ok(()),
+        }
+    }
+
+    fn run(&mut self, cmd: u8) -> u8 {
+        match cmd {
+            CMD_OPEN | CMD_CREATE => {
+                let name = std::mem::take(&mut self.name);
+                if self.close().is_err() {
+                    return STATUS_ERROR;
+                }
+                match self.open(&name, cmd == CMD_CREATE) {
+                    Ok(file) => {
+                        self.file = Some(file);
+                        STATUS_OK
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => STATUS_NOT_FOUND,
+                    Err(_) => STATUS_ERROR,
+                }
+            }
+            CMD_CLOSE => match self.close() {
+                Ok(()) => STATUS_OK,
+                Err(_) => STATUS_ERROR,
+            },
+            CMD_SEEK | CMD_END => {
+                let Some(file) = &self.file else {
+                    return STATUS_ERROR;
+                };
+                self.pos = match cmd {
+                    CMD_SEEK => u32::from_le_bytes(self.seek),
+                    _ => file.data.len() as u32,
+                };
+                STATUS_OK
+            }
+            _ => STATUS_ERROR,
+        }
+    }
+
+    fn open(&self, name: &[u8], create: bool) -> io::Result<OpenFile> {
+        let path = match &self.backing {
+            Backing::Image(_) if create => return Err(io::ErrorKind::Unsupported.into()),
+            Backing::Image(path) => path.clone(),
+            Backing::Directory(root) => root.join(file_name(name)?),
+        };
+        let data = if create {
+            fs::write(&path, [])?;
+            Vec::new()
+        } else {
+            fs::read(&path)?
+        };
+        Ok(OpenFile {
+            path,
+            data,
+            writable: create || matches!(self.backing, Backing::Image(_)),
+            dirty: false,
+        })
+    }
+
+    fn read_data(&mut self) -> (u8, u8) {
+        let Some(file) = &self.file else {
+            return (0, STATUS_ERROR);
+        };
+        match file.data.get(self.pos as usize) {
+            Some(&byte) => {
+                self.pos += 1;
+                (byte, STATUS_OK)
+            }
+            None => (0, STATUS_EOF),
+        }
+    }
+
+    fn write_data(&mut self, val: u8) -> u8 {
+        let image = matches!(self.backing, Backing::Image(_));
+        let Some(file) = &mut self.file else {
+            return STATUS_ERROR;
+        };
+        let pos = self.pos as usize;
+        let limit = if image {
+            file.data.len()
+        } else {
+            FILE_MAX as usize
+        };
+        if !file.writable || pos >= limit {
+            return STATUS_ERROR;
+        }
+        if pos >= file.data.len() {
+            file.data.resize(pos + 1, 0);
+        }
+        file.data[pos] = val;
+        file.dirty = true;
+        self.pos += 1;
+        STATUS_OK
+    }
+}
+
+/// `name` as a name of a file directly in the directory.
+fn file_name(name: &[u8]) -> io::Result<&str> {
+    let invalid = || io::Error::from(io::ErrorKind::InvalidInput);
+    let name = std::str::from_utf8(name).map_err(|_| invalid())?;
+    let bad = name.is_empty()
+        || name.len() > NAME_MAX
+        || name == "."
+        || name == ".."
+        || name.contains(['/', '\\', '\0']);
+    if bad { Err(invalid()) } else { Ok(name) }
+}
+
+impl BusDevice for FileService {
+    fn read8(&mut self, offset: u16) -> u8 {
+        match offset {
+            CMD => self.status,
+            DATA => {
+                let (byte, status) = self.read_data();
+                self.status = status;
+                byte
+            }
+            POS..=POS_LAST => self.pos.to_le_bytes()[usize::from(offset - POS)],
+            _ => 0,
+        }
+    }
+
+    fn write8(&mut self, offset: u16, val: u8) {
+        match offset {
+            CMD => self.status = self.run(val),
+            NAME if self.name.len() <= NAME_MAX => self.name.push(val),
+            NAME => {}
+            DATA => self.status = self.write_data(val),
+            POS..=POS_LAST => self.seek[usize::from(offset - POS)] = val,
+            _ => {}
+        }
+    }
+
+    /// Closes the file, writing it back, and clears the registers.
+    fn reset(&mut self, _: ResetKind) {
+        // There is no program to report a failure to once it restarts.
+        let _ = self.close();
+        self.name.clear();
+        self.seek = [0; 4];
+        self.status = STATUS_OK;
+    }
+
+    /// The status and `POS` as written; the file stays on the host.
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.push(self.status);
+        state.extend_from_slice(&self.seek);
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        let Some((&status, seek)) = state.split_first() else {
+            return;
+        };
+        let Ok(seek) = seek.try_into() else {
+            return;
+        };
+        let _ = self.close();
+        self.name.clear();
+        self.status = status;
+        self.seek = seek;
+    }
+}
+
+impl Drop for FileService {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
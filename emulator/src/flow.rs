@@ -0,0 +1,395 @@
+//! Static control-flow analysis and Graphviz export.
+//!
+//! [`Vm::flow_graph`] follows the code in memory from the reset, NMI and
+//! IRQ vectors and splits it into basic blocks: straight runs of
+//! instructions entered only at the top. A block ends where a branch, jump,
+//! call or return leaves it, at an undefined opcode, which stops the path,
+//! or just before another block's first instruction:
+//!
+//! ```
+//! # use emulator::Vm;
+//! let mut vm = Vm::new();
+//! vm.load(0xC000, &[0xA9, 0x01, 0xA2, 0x02, 0x02]).unwrap();
+//! vm.load(0xFFFA, &[0x02, 0xC0, 0x00, 0xC0, 0x00, 0x00]).unwrap();
+//! let graph = vm.flow_graph();
+//! // The NMI handler starts at $C002, inside the reset code.
+//! let starts: Vec<u16> = graph.blocks().map(|block| block.start).collect();
+//! assert_eq!(starts, [0x0000, 0xC000, 0xC002]);
+//! let dot = graph.to_dot(&vm, None);
+//! assert!(dot.contains("b0000 [label=\"irq:\\l$0000  ???\\l\"];"));
+//! ```
+//!
+//! Control flow is recognised by mnemonic, the 6502's branches, `JMP`,
+//! `JSR`, `RTS`, `RTI` and `BRK`, so the analysis follows whichever of them
+//! the opcode table defines. Today's table has none, which leaves blocks
+//! ending at undefined opcodes and other blocks' starts. An indirect `JMP`
+//! is followed to the address its pointer holds when analysed. Code only
+//! reached through computed addresses is missed; pass its addresses to
+//! [`FlowGraph::analyze`] as extra entries.
+//!
+//! [`FlowGraph::to_dot`] writes the blocks as a control-flow graph and
+//! [`FlowGraph::call_graph_dot`] the subroutines (entry points and call
+//! targets) as a call graph. Given a [`Profile`], both carry how often each
+//! node ran, and code that never ran is drawn dashed.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::disasm::{Instruction, Operand};
+use crate::profile::Profile;
+use crate::vm::Vm;
+
+/// How control leaves a basic block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    /// Runs on into the block at this address.
+    Fall(u16),
+    /// A conditional branch to `taken`, or on to `next`.
+    Branch {
+        taken: u16,
+        next: u16,
+    },
+    Jump(u16),
+    /// `JMP (pointer)`, which held `target` when analysed.
+    IndirectJump {
+        pointer: u16,
+        target: u16,
+    },
+    /// A subroutine call returning to `next`.
+    Call {
+        target: u16,
+        next: u16,
+    },
+    /// `RTS` or `RTI`.
+    Return,
+    /// The last instruction is undefined or `BRK`.
+    Halt,
+}
+
+/// A basic block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Block {
+    /// Address of the first instruction.
+    pub start: u16,
+    /// Address of the last instruction.
+    pub end: u16,
+    /// Instructions in the block.
+    pub len: usize,
+    pub exit: Exit,
+}
+
+impl Block {
+    /// The blocks control can go to next, in address order.
+    pub fn successors(&self) -> Vec<u16> {
+        let mut next = match self.exit {
+            Exit::Fall(next) | Exit::Jump(next) => vec![next],
+            Exit::IndirectJump { target, .. } => vec![target],
+            Exit::Branch { taken, next } => vec![taken, next],
+            Exit::Call { target, next } => vec![target, next],
+            Exit::Return | Exit::Halt => Vec::new(),
+        };
+        next.sort_unstable();
+        next.dedup();
+        next
+    }
+}
+
+/// Where an instruction may send control, besides falling through.
+enum Flow {
+    Next,
+    Branch(u16),
+    Jump(u16),
+    IndirectJump(u16),
+    Call(u16),
+    Return,
+    Halt,
+}
+
+fn flow(addr: u16, instr: &Instruction) -> Flow {
+    let next = addr.wrapping_add(u16::from(instr.size));
+    match (instr.mnemonic, instr.operands) {
+        ("???" | "BRK", _) => Flow::Halt,
+        ("RTS" | "RTI", _) => Flow::Return,
+        ("JSR", Operand::Absolute(target)) => Flow::Call(target),
+        ("JMP", Operand::Absolute(target)) => Flow::Jump(target),
+        ("JMP", Operand::Indirect(pointer)) => Flow::IndirectJump(pointer),
+        (_, Operand::Relative(offset)) => Flow::Branch(next.wrapping_add(offset as u16)),
+        _ => Flow::Next,
+    }
+}
+
+/// The address an indirect `JMP` through `pointer` goes to, with the
+/// 6502's wrap within the pointer's page.
+fn read_pointer(vm: &Vm, pointer: u16) -> u16 {
+    let hi = (pointer & 0xFF00) | (pointer.wrapping_add(1) & 0x00FF);
+    u16::from_le_bytes([vm.read(pointer), vm.read(hi)])
+}
+
+/// The basic blocks reachable from a set of entry points.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlowGraph {
+    blocks: BTreeMap<u16, Block>,
+    /// Entry points with the name they were given, if any.
+    entries: BTreeMap<u16, Option<String>>,
+}
+
+impl Vm {
+    /// Analyses the code reachable from the reset, NMI and IRQ vectors.
+    pub fn flow_graph(&self) -> FlowGraph {
+        let vector = |addr: u16| u16::from_le_bytes([self.read(addr), self.read(addr + 1)]);
+        let mut graph = FlowGraph::analyze(self, []);
+        for (name, addr) in [("reset", 0xFFFC), ("nmi", 0xFFFA), ("irq", 0xFFFE)] {
+            // Vectors sharing a handler give it both names.
+            match graph.entries.entry(vector(addr)).or_default() {
+                Some(names) => *names = format!("{names}/{name}"),
+                entry => *entry = Some(name.into()),
+            }
+        }
+        graph.discover(self);
+        graph
+    }
+}
+
+impl FlowGraph {
+    /// Analyses the code reachable from `entries` in `vm`'s memory.
+    pub fn analyze(vm: &Vm, entries: impl IntoIterator<Item = u16>) -> Self {
+        let mut graph = Self {
+            blocks: BTreeMap::new(),
+            entries: entries.into_iter().map(|addr| (addr, None)).collect(),
+        };
+        graph.discover(vm);
+        graph
+    }
+
+    /// Finds every block from the entry points.
+    fn discover(&mut self, vm: &Vm) {
+        // First every address a path can start at, then the blocks between
+        // them.
+        let mut leaders: BTreeSet<u16> = self.entries.keys().copied().collect();
+        let mut seen = vec![false; 0x10000];
+        let mut work: Vec<u16> = leaders.iter().copied().collect();
+        while let Some(mut addr) = work.pop() {
+            // Walks until the path leaves or joins one already walked,
+            // which then needs a block starting where they meet.
+            let targets = loop {
+                if seen[addr as usize] {
+                    break vec![addr];
+                }
+                seen[addr as usize] = true;
+                let instr = vm.disassemble(addr);
+                let next = addr.wrapping_add(u16::from(instr.size));
+                match flow(addr, &instr) {
+                    Flow::Next => addr = next,
+                    Flow::Branch(taken) => break vec![taken, next],
+                    Flow::Jump(to) => break vec![to],
+                    Flow::IndirectJump(pointer) => break vec![read_pointer(vm, pointer)],
+                    Flow::Call(to) => break vec![to, next],
+                    Flow::Return | Flow::Halt => break Vec::new(),
+                }
+            };
+            for target in targets {
+                if leaders.insert(target) && !seen[target as usize] {
+                    work.push(target);
+                }
+            }
+        }
+
+        self.blocks.clear();
+        for &start in &leaders {
+            let (mut addr, mut len) = (start, 0);
+            let exit = loop {
+                let instr = vm.disassemble(addr);
+                let next = addr.wrapping_add(u16::from(instr.size));
+                len += 1;
+                match flow(addr, &instr) {
+                    Flow::Next if leaders.contains(&next) => break Exit::Fall(next),
+                    Flow::Next => addr = next,
+                    Flow::Branch(taken) => break Exit::Branch { taken, next },
+                    Flow::Jump(to) => break Exit::Jump(to),
+                    Flow::IndirectJump(pointer) => {
+                        let target = read_pointer(vm, pointer);
+                        break Exit::IndirectJump { pointer, target };
+                    }
+                    Flow::Call(target) => break Exit::Call { target, next },
+                    Flow::Return => break Exit::Return,
+                    Flow::Halt => break Exit::Halt,
+                }
+            };
+            self.blocks.insert(
+                start,
+                Block {
+                    start,
+                    end: addr,
+                    len,
+                    exit,
+                },
+            );
+        }
+    }
+
+    /// The blocks in address order.
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> {
+        self.blocks.values()
+    }
+
+    /// The block starting at `addr`.
+    pub fn block(&self, addr: u16) -> Option<&Block> {
+        self.blocks.get(&addr)
+    }
+
+    /// The entry points analysed from.
+    pub fn entries(&self) -> impl Iterator<Item = u16> + '_ {
+        self.entries.keys().copied()
+    }
+
+    /// Entry points and call targets, in address order.
+    pub fn subroutines(&self) -> Vec<u16> {
+        let calls = self.blocks().filter_map(|block| match block.exit {
+            Exit::Call { target, .. } => Some(target),
+            _ => None,
+        });
+        let all: BTreeSet<u16> = self.entries().chain(calls).collect();
+        all.into_iter().collect()
+    }
+
+    /// The blocks of the subroutine at `start`: those reachable from it
+    /// without following calls, in address order.
+    pub fn body(&self, start: u16) -> Vec<&Block> {
+        let mut found = BTreeSet::new();
+        let mut work = vec![start];
+        while let Some(addr) = work.pop() {
+            let Some(block) = self.block(addr) else {
+                continue;
+            };
+            if !found.insert(addr) {
+                continue;
+            }
+            match block.exit {
+                Exit::Call { next, .. } => work.push(next),
+                _ => work.extend(block.successors()),
+            }
+        }
+        found.iter().map(|addr| &self.blocks[addr]).collect()
+    }
+
+    /// Names `addr` by entry point, then symbol, then address.
+    fn name(&self, vm: &Vm, addr: u16) -> String {
+        let entry = self.entries.get(&addr).and_then(Option::as_deref);
+        let symbol = vm.symbols().and_then(|symbols| symbols.name(addr));
+        match (entry, symbol) {
+            (Some(entry), Some(symbol)) if entry != symbol => format!("{entry}: {symbol}"),
+            (Some(name), _) | (_, Some(name)) => name.to_string(),
+            (None, None) => format!("${addr:04X}"),
+        }
+    }
+
+    /// The control-flow graph as Graphviz DOT, one node per block listing
+    /// its instructions. With `profile`, each node also shows how often the
+    /// block ran and its cycles.
+    pub fn to_dot(&self, vm: &Vm, profile: Option<&Profile>) -> String {
+        let mut dot = String::from("digraph cfg {\n");
+        dot.push_str("  node [shape=box, fontname=\"monospace\"];\n");
+        for block in self.blocks() {
+            let mut label = String::new();
+            let named = self.entries.contains_key(&block.start)
+                || vm.symbols().and_then(|s| s.name(block.start)).is_some();
+            if named {
+                let _ = write!(label, "{}:\\l", escape(&self.name(vm, block.start)));
+            }
+            let mut addr = block.start;
+            let mut cycles = 0;
+            for _ in 0..block.len {
+                let instr = vm.disassemble(addr);
+                let text = match vm.symbols() {
+                    Some(symbols) => instr.with_symbols(symbols).to_string(),
+                    None => instr.to_string(),
+                };
+                let _ = write!(label, "${addr:04X}  {}\\l", escape(&text));
+                if let Some(profile) = profile {
+                    cycles += profile.cost(addr).cycles;
+                }
+                addr = addr.wrapping_add(u16::from(instr.size));
+            }
+            let _ = write!(dot, "  b{:04X} [label=\"{label}", block.start);
+            if let Some(profile) = profile {
+                let count = profile.cost(block.start).count;
+                let _ = write!(dot, "ran {count}x, {cycles} cycles\\l\"");
+                if count == 0 {
+                    dot.push_str(", style=dashed");
+                }
+            } else {
+                dot.push('"');
+            }
+            dot.push_str("];\n");
+        }
+        for block in self.blocks() {
+            let edges: Vec<(u16, &str)> = match block.exit {
+                Exit::Fall(next) => vec![(next, "")],
+                Exit::Branch { taken, next } => vec![(taken, "taken"), (next, "")],
+                Exit::Jump(target) => vec![(target, "")],
+                Exit::IndirectJump { target, .. } => vec![(target, "indirect")],
+                Exit::Call { target, next } => vec![(target, "call"), (next, "return")],
+                Exit::Return | Exit::Halt => Vec::new(),
+            };
+            for (to, label) in edges {
+                let _ = write!(dot, "  b{:04X} -> b{to:04X}", block.start);
+                match label {
+                    "" => dot.push_str(";\n"),
+                    "call" => dot.push_str(" [label=\"call\", style=dashed];\n"),
+                    label => {
+                        let _ = writeln!(dot, " [label=\"{label}\"];");
+                    }
+                }
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// The call graph as Graphviz DOT, one node per
+    /// [subroutine](FlowGraph::subroutines) with an edge to each one it
+    /// calls. With `profile`, each node also shows how often the
+    /// subroutine was entered.
+    pub fn call_graph_dot(&self, vm: &Vm, profile: Option<&Profile>) -> String {
+        let mut dot = String::from("digraph calls {\n");
+        dot.push_str("  node [shape=box, fontname=\"monospace\"];\n");
+        let subroutines = self.subroutines();
+        for &start in &subroutines {
+            let _ = write!(
+                dot,
+                "  s{start:04X} [label=\"{}",
+                escape(&self.name(vm, start))
+            );
+            if let Some(profile) = profile {
+                let count = profile.cost(start).count;
+                let _ = write!(dot, "\\nran {count}x\"");
+                if count == 0 {
+                    dot.push_str(", style=dashed");
+                }
+            } else {
+                dot.push('"');
+            }
+            dot.push_str("];\n");
+        }
+        for &start in &subroutines {
+            let callees: BTreeSet<u16> = self
+                .body(start)
+                .iter()
+                .filter_map(|block| match block.exit {
+                    Exit::Call { target, .. } => Some(target),
+                    _ => None,
+                })
+                .collect();
+            for callee in callees {
+                let _ = writeln!(dot, "  s{start:04X} -> s{callee:04X};");
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Escapes `text` for a quoted DOT label.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
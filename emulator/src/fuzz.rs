@@ -0,0 +1,64 @@
+//! Entry points for fuzzing the CPU core.
+//!
+//! [`Vm::execute_raw`] runs whatever bytes a fuzzer hands it as a program
+//! for a bounded number of cycles. Every outcome an arbitrary program can
+//! reach comes back as a [`VmError`] or a finished run: every instruction
+//! in the kernel's tables takes cycles, so a run always ends, and device
+//! panics are caught at the bus. [Extensions](crate::extension) are the
+//! host's own code and must take cycles too. The cargo-fuzz targets in
+//! `fuzz/` drive it, the
+#![cfg_attr(feature = "difftest", doc = "[`difftest`](crate::difftest)")]
+#![cfg_attr(not(feature = "difftest"), doc = "`difftest`")]
+//! cores and the file loaders:
+//!
+//! ```
+//! # use emulator::{fuzz::ORIGIN, Vm, VmError};
+//! let mut vm = Vm::new();
+//! // LDA ($B1),Y over and over.
+//! assert_eq!(vm.execute_raw(&[0xB1; 0x1000], 10_000), Ok(()));
+//! assert!(vm.cycles() >= 10_000);
+//! // Past a short program, zeroed memory holds illegal opcodes.
+//! let mut vm = Vm::new();
+//! assert!(matches!(
+//!     vm.execute_raw(&[0xB1; 16], 10_000),
+//!     Err(VmError::IllegalOpcode { pc: 0x0210, .. })
+//! ));
+//! assert_eq!(vm.read(ORIGIN), 0xB1);
+//! ```
+//!
+//! Run the targets from `emulator/` with a nightly toolchain and
+//! `cargo install cargo-fuzz`:
+//!
+//! ```text
+//! cargo +nightly fuzz run execute
+//! cargo +nightly fuzz run difftest
+//! cargo +nightly fuzz run loaders
+//! ```
+
+use crate::error::VmError;
+use crate::ffi::RVM_MEM_SIZE;
+use crate::vm::Vm;
+
+/// Where [`Vm::execute_raw`] loads its program, past the zero page and the
+/// stack.
+pub const ORIGIN: u16 = 0x0200;
+
+impl Vm {
+    /// Loads `program` at [`ORIGIN`], dropping whatever does not fit below
+    /// the end of the address space, points the PC at it and runs for at
+    /// least `budget` cycles as [`Vm::run_cycles`] does.
+    ///
+    /// The rest of the machine is left as the caller set it up: other
+    /// registers, memory outside the program, devices and the
+    /// [CPU configuration](Vm::set_cpu_config). The run stops at the first
+    /// error, which is returned; none of them leaves the machine unusable.
+    pub fn execute_raw(&mut self, program: &[u8], budget: u32) -> Result<(), VmError> {
+        let fits = program.len().min(RVM_MEM_SIZE - ORIGIN as usize);
+        self.load(ORIGIN, &program[..fits])?;
+        self.set_registers(crate::Registers {
+            pc: ORIGIN,
+            ..self.registers()
+        });
+        self.run_cycles(budget)
+    }
+}
@@ -0,0 +1,454 @@
+//! GDB remote serial protocol server.
+//!
+//! [`Vm::serve_gdb`] waits for a debugger on a TCP socket and lets it drive
+//! the machine with the usual `target remote host:port` workflow: reading and
+//! writing registers and memory, software and hardware breakpoints,
+//! watchpoints, continue, single-step and Ctrl-C. Memory accesses from the
+//! debugger go through [`Vm::read`] and [`Vm::write`], so they never touch
+//! devices and may patch ROM.
+//!
+//! GDB has no built-in rvm-8 architecture, so the stub describes its register
+//! file in a target description (`target.xml`), in `g` packet order:
+//!
+//! | Number | Register | Size     |
+//! | ------ | -------- | -------- |
+//! | 0      | `a`      | 1 byte   |
+//! | 1      | `x`      | 1 byte   |
+//! | 2      | `y`      | 1 byte   |
+//! | 3      | `flags`  | 1 byte   |
+//! | 4      | `sp`     | 2 bytes  |
+//! | 5      | `pc`     | 2 bytes  |
+//!
+//! Multi-byte registers are little-endian.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use crate::debugger::WatchKind;
+use crate::vm::{Registers, Vm};
+
+/// Instructions executed between checks for a Ctrl-C from the debugger.
+const INTERRUPT_POLL: u32 = 4096;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.rvm8.cpu">
+    <reg name="a" bitsize="8" type="uint8"/>
+    <reg name="x" bitsize="8" type="uint8"/>
+    <reg name="y" bitsize="8" type="uint8"/>
+    <reg name="flags" bitsize="8" type="uint8"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+/// Why the target stopped, as reported to the debugger.
+#[derive(Debug, Clone, Copy)]
+enum Stop {
+    /// Stepped, hit a breakpoint, or nothing has run yet.
+    Trap,
+    /// A watchpoint fired at `addr`.
+    Watch(WatchKind, u16),
+    /// The debugger sent Ctrl-C.
+    Interrupt,
+    /// The CPU fetched an illegal opcode.
+    Illegal,
+}
+
+impl Stop {
+    fn reply(self) -> String {
+        match self {
+            Self::Trap => "S05".into(),
+            Self::Watch(kind, addr) => {
+                let name = match kind {
+                    WatchKind::Read => "rwatch",
+                    WatchKind::Write => "watch",
+                    WatchKind::Access => "awatch",
+                };
+                format!("T05{name}:{addr:04x};")
+            }
+            Self::Interrupt => "S02".into(),
+            Self::Illegal => "S04".into(),
+        }
+    }
+}
+
+/// What the session should do after a packet.
+enum Next {
+    Reply(String),
+    /// Reply, then end the session.
+    Close(Option<String>),
+}
+
+struct Session<'a> {
+    vm: &'a mut Vm,
+    stream: TcpStream,
+    last_stop: Stop,
+}
+
+impl Vm {
+    /// Listens on `addr`, waits for one debugger to connect and serves it
+    /// with [`Vm::run_gdb_session`].
+    pub fn serve_gdb(&mut self, addr: impl ToSocketAddrs) -> io::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, _) = listener.accept()?;
+        self.run_gdb_session(stream)
+    }
+
+    /// Serves a connected debugger until it detaches, kills the target or
+    /// hangs up. The machine is left wherever the debugger stopped it.
+    pub fn run_gdb_session(&mut self, stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        let mut session = Session {
+            vm: self,
+            stream,
+            last_stop: Stop::Trap,
+        };
+        session.run()
+    }
+}
+
+impl Session<'_> {
+    fn run(&mut self) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            match self.handle(&packet) {
+                Next::Reply(reply) => self.send(&reply)?,
+                Next::Close(reply) => {
+                    if let Some(reply) = reply {
+                        self.send(&reply)?;
+                    }
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        match self.stream.read(&mut byte) {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(byte[0])),
+            Err(err) if err.kind() == ErrorKind::Interrupted => self.read_byte(),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Reads the next well-formed packet, acknowledging it. Returns `None`
+    /// when the debugger hangs up.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // Skip acks and anything else outside a packet, including a
+            // Ctrl-C that arrives while the target is already stopped.
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'$') => {}
+                Some(_) => continue,
+            }
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                }
+            }
+            let mut sum = [0; 2];
+            for digit in &mut sum {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(byte) => *digit = byte,
+                }
+            }
+            let expected = std::str::from_utf8(&sum)
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok());
+            if expected != Some(checksum(&data)) {
+                self.stream.write_all(b"-")?;
+                continue;
+            }
+            self.stream.write_all(b"+")?;
+            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+
+    /// Sends `data` as a packet and waits for the debugger's ack, resending
+    /// on a nack.
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        for &byte in data.as_bytes() {
+            if matches!(byte, b'$' | b'#' | b'}' | b'*') {
+                packet.extend([b'}', byte ^ 0x20]);
+            } else {
+                packet.push(byte);
+            }
+        }
+        let sum = checksum(&packet[1..]);
+        packet.extend(format!("#{sum:02x}").bytes());
+        loop {
+            self.stream.write_all(&packet)?;
+            match self.read_byte()? {
+                Some(b'-') => continue,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn handle(&mut self, packet: &str) -> Next {
+        let (cmd, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match cmd {
+            "?" => self.last_stop.reply(),
+            "g" => self.read_registers(),
+            "G" => self.write_registers(args),
+            "p" => self.read_register(args),
+            "P" => self.write_register(args),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "c" | "s" => self.resume(args, cmd == "s"),
+            "Z" | "z" => self.breakpoint(args, cmd == "Z"),
+            "H" => "OK".into(),
+            "q" => self.query(args),
+            "D" => return Next::Close(Some("OK".into())),
+            "k" => return Next::Close(None),
+            _ => String::new(),
+        };
+        Next::Reply(reply)
+    }
+
+    fn query(&self, args: &str) -> String {
+        if args.starts_with("Supported") {
+            return "PacketSize=1000;qXfer:features:read+;swbreak+;hwbreak+".into();
+        }
+        if let Some(range) = args.strip_prefix("Xfer:features:read:target.xml:") {
+            let Some((offset, len)) = parse_pair(range, ',') else {
+                return "E01".into();
+            };
+            let offset = (offset as usize).min(TARGET_XML.len());
+            let end = offset.saturating_add(len as usize).min(TARGET_XML.len());
+            let marker = if end == TARGET_XML.len() { 'l' } else { 'm' };
+            return format!("{marker}{}", &TARGET_XML[offset..end]);
+        }
+        match args {
+            "Attached" => "1".into(),
+            "C" => "QC1".into(),
+            "fThreadInfo" => "m1".into(),
+            "sThreadInfo" => "l".into(),
+            _ => String::new(),
+        }
+    }
+
+    fn read_registers(&self) -> String {
+        let regs = self.vm.registers();
+        let mut bytes = vec![regs.a, regs.x, regs.y, regs.flags];
+        bytes.extend(regs.sp.to_le_bytes());
+        bytes.extend(regs.pc.to_le_bytes());
+        to_hex(&bytes)
+    }
+
+    fn write_registers(&mut self, args: &str) -> String {
+        match from_hex(args).as_deref() {
+            Some(&[a, x, y, flags, sp0, sp1, pc0, pc1]) => {
+                self.vm.set_registers(Registers {
+                    a,
+                    x,
+                    y,
+                    flags,
+                    sp: u16::from_le_bytes([sp0, sp1]),
+                    pc: u16::from_le_bytes([pc0, pc1]),
+                });
+                "OK".into()
+            }
+            _ => "E01".into(),
+        }
+    }
+
+    fn read_register(&self, args: &str) -> String {
+        let regs = self.vm.registers();
+        match u8::from_str_radix(args, 16) {
+            Ok(0) => to_hex(&[regs.a]),
+            Ok(1) => to_hex(&[regs.x]),
+            Ok(2) => to_hex(&[regs.y]),
+            Ok(3) => to_hex(&[regs.flags]),
+            Ok(4) => to_hex(&regs.sp.to_le_bytes()),
+            Ok(5) => to_hex(&regs.pc.to_le_bytes()),
+            _ => "E01".into(),
+        }
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let Some((num, value)) = args.split_once('=') else {
+            return "E01".into();
+        };
+        let mut regs = self.vm.registers();
+        match (u8::from_str_radix(num, 16), from_hex(value).as_deref()) {
+            (Ok(0), Some(&[v])) => regs.a = v,
+            (Ok(1), Some(&[v])) => regs.x = v,
+            (Ok(2), Some(&[v])) => regs.y = v,
+            (Ok(3), Some(&[v])) => regs.flags = v,
+            (Ok(4), Some(&[lo, hi])) => regs.sp = u16::from_le_bytes([lo, hi]),
+            (Ok(5), Some(&[lo, hi])) => regs.pc = u16::from_le_bytes([lo, hi]),
+            _ => return "E01".into(),
+        }
+        self.vm.set_registers(regs);
+        "OK".into()
+    }
+
+    fn read_memory(&self, args: &str) -> String {
+        match parse_range(args) {
+            Some((addr, len)) => {
+                let bytes: Vec<u8> = (0..len).map(|i| self.vm.read(addr + i as u16)).collect();
+                to_hex(&bytes)
+            }
+            None => "E01".into(),
+        }
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let Some((range, data)) = args.split_once(':') else {
+            return "E01".into();
+        };
+        match (parse_range(range), from_hex(data)) {
+            (Some((addr, len)), Some(bytes)) if bytes.len() == len => {
+                for (i, byte) in bytes.into_iter().enumerate() {
+                    self.vm.write(addr + i as u16, byte);
+                }
+                "OK".into()
+            }
+            _ => "E01".into(),
+        }
+    }
+
+    /// Handles `Z`/`z`: type 0 and 1 are PC breakpoints, 2 to 4 are write,
+    /// read and access watchpoints.
+    fn breakpoint(&mut self, args: &str, insert: bool) -> String {
+        let Some((kind, Some((addr, len)))) = args
+            .split_once(',')
+            .map(|(kind, range)| (kind, parse_range(range)))
+        else {
+            return "E01".into();
+        };
+        let watch = match kind {
+            "0" | "1" => {
+                if insert {
+                    self.vm.add_breakpoint(addr);
+                } else {
+                    self.vm.remove_breakpoint(addr);
+                }
+                return "OK".into();
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return String::new(),
+        };
+        let Ok(last) = u16::try_from(addr as usize + len.max(1) - 1) else {
+            return "E01".into();
+        };
+        let range = addr..=last;
+        if insert {
+            self.vm.add_watchpoint(range, watch);
+        } else {
+            self.vm.remove_watchpoint(range, watch);
+        }
+        "OK".into()
+    }
+
+    /// Runs from the current PC, or from the address in `args`, until
+    /// something stops the target, then reports why.
+    fn resume(&mut self, args: &str, single: bool) -> String {
+        if !args.is_empty() {
+            let Ok(pc) = u16::from_str_radix(args, 16) else {
+                return "E01".into();
+            };
+            self.vm.set_registers(Registers {
+                pc,
+                ..self.vm.registers()
+            });
+        }
+        self.last_stop = self.run_until_stop(single);
+        self.last_stop.reply()
+    }
+
+    fn run_until_stop(&mut self, single: bool) -> Stop {
+        let mut polled = 0;
+        loop {
+            if self.vm.step().is_err() {
+                return Stop::Illegal;
+            }
+            if let Some(hit) = self.vm.bus_mut().watch_hit.take() {
+                // Only a matching watchpoint records a hit.
+                let watchpoint = self.vm.watchpoints().iter();
+                let kind = watchpoint
+                    .filter(|w| w.matches(hit.kind, hit.addr))
+                    .map(|w| w.kind)
+                    .next()
+                    .unwrap_or(WatchKind::Access);
+                return Stop::Watch(kind, hit.addr);
+            }
+            if single || self.vm.breakpoint_hit(self.vm.registers().pc) {
+                return Stop::Trap;
+            }
+            polled += 1;
+            if polled == INTERRUPT_POLL {
+                polled = 0;
+                if self.interrupted() {
+                    return Stop::Interrupt;
+                }
+            }
+        }
+    }
+
+    /// Whether the debugger has sent Ctrl-C (or hung up) since the target
+    /// started running.
+    fn interrupted(&mut self) -> bool {
+        if self.stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let mut byte = [0];
+        let read = self.stream.read(&mut byte);
+        let _ = self.stream.set_nonblocking(false);
+        match read {
+            Ok(0) => true,
+            Ok(_) => byte[0] == 0x03,
+            Err(_) => false,
+        }
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_pair(args: &str, sep: char) -> Option<(u32, u32)> {
+    let (a, b) = args.split_once(sep)?;
+    Some((
+        u32::from_str_radix(a, 16).ok()?,
+        u32::from_str_radix(b, 16).ok()?,
+    ))
+}
+
+/// Parses `addr,len`, rejecting ranges that leave the address space.
+fn parse_range(args: &str) -> Option<(u16, usize)> {
+    let (addr, len) = parse_pair(args, ',')?;
+    if addr as usize + len as usize > 0x10000 {
+        return None;
+    }
+    Some((addr as u16, len as usize))
+}
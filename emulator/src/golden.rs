@@ -0,0 +1,160 @@
+//! Frame hashes and golden runs.
+//!
+//! [`Vm::frame_hash`] boils the last frame down to 64 bits: its picture
+//! and its sound. A golden file lists the hash of every frame of a run, so
+//! a CI job can [`record`] one from a known-good build and [`verify`]
+//! later builds against it, catching any change in what the core does over
+//! a whole ROM without storing a single picture:
+//!
+//! ```no_run
+//! # use emulator::{golden, Rom, Vm};
+//! let mut vm = Vm::new();
+//! vm.load_rom(&Rom::from_file("game.rvm").unwrap()).unwrap();
+//! golden::verify(&mut vm, "tests/game.golden").unwrap();
+//! ```
+//!
+//! A golden file is text, one frame per line: the frame number, counted
+//! from where the run started, and its hash in hex. Lines starting with
+//! `#` are comments. The hash is FNV-1a over the framebuffer bytes and then
+//! the frame's samples, little-endian, so it only changes with the crate
+//! when the pictures or sounds do.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::error::VmError;
+use crate::vm::Vm;
+
+const FNV_OFFSET: u64 = 0xCBF2_9CE4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01B3;
+
+/// Why a golden run did not match.
+#[derive(Debug)]
+pub enum GoldenError {
+    /// The golden file could not be read or written.
+    Io(io::Error),
+    /// Line `line`, counted from 1, is neither a frame nor a comment.
+    BadLine { line: usize },
+    /// A frame failed to run.
+    Vm { frame: u64, err: VmError },
+    /// Frame `frame` hashed to `actual` instead of `expected`.
+    Mismatch {
+        frame: u64,
+        expected: u64,
+        actual: u64,
+    },
+}
+
+impl fmt::Display for GoldenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "cannot access golden file: {err}"),
+            Self::BadLine { line } => write!(f, "golden file line {line} is not a frame hash"),
+            Self::Vm { frame, err } => write!(f, "frame {frame} failed: {err}"),
+            Self::Mismatch {
+                frame,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "frame {frame} hashed to {actual:016x}, expected {expected:016x}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GoldenError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Vm { err, .. } => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for GoldenError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl Vm {
+    /// A hash of the framebuffer and of the samples synthesized for the
+    /// last frame.
+    ///
+    /// Samples are only synthesized while something takes them: an
+    /// [audio callback](Vm::set_audio_callback), a capture, the
+    /// [event queue](crate::events) or a golden run. Otherwise the hash
+    /// covers the picture alone.
+    pub fn frame_hash(&self) -> u64 {
+        let samples = self.audio.samples().iter().flat_map(|s| s.to_le_bytes());
+        self.framebuffer()
+            .iter()
+            .copied()
+            .chain(samples)
+            .fold(FNV_OFFSET, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+            })
+    }
+
+    /// Runs a frame with its samples synthesized, and hashes it.
+    fn hash_frame(&mut self, frame: u64) -> Result<u64, GoldenError> {
+        self.audio.hashing = true;
+        let result = self.run_frame();
+        self.audio.hashing = false;
+        result.map_err(|err| GoldenError::Vm { frame, err })?;
+        Ok(self.frame_hash())
+    }
+}
+
+/// Runs `frames` frames of `vm` and writes their hashes to the golden file
+/// at `path`, replacing it.
+pub fn record(vm: &mut Vm, frames: u64, path: impl AsRef<Path>) -> Result<(), GoldenError> {
+    let mut text = String::from("# rvm8 golden run: frame, hash\n");
+    for frame in 0..frames {
+        let hash = vm.hash_frame(frame)?;
+        text.push_str(&format!("{frame} {hash:016x}\n"));
+    }
+    fs::write(path, text)?;
+    Ok(())
+}
+
+/// Runs `vm` for as many frames as the golden file at `path` lists,
+/// stopping at the first whose hash differs.
+pub fn verify(vm: &mut Vm, path: impl AsRef<Path>) -> Result<(), GoldenError> {
+    let expected = parse(&fs::read_to_string(path)?)?;
+    for (frame, expected) in expected.into_iter().enumerate() {
+        let frame = frame as u64;
+        let actual = vm.hash_frame(frame)?;
+        if actual != expected {
+            return Err(GoldenError::Mismatch {
+                frame,
+                expected,
+                actual,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The hashes in a golden file, indexed by frame.
+fn parse(text: &str) -> Result<Vec<u64>, GoldenError> {
+    let mut hashes = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let bad = || GoldenError::BadLine { line: i + 1 };
+        let (frame, hash) = line.split_once(char::is_whitespace).ok_or_else(bad)?;
+        if frame.parse() != Ok(hashes.len()) {
+            return Err(bad());
+        }
+        let hash = u64::from_str_radix(hash.trim(), 16).map_err(|_| bad())?;
+        hashes.push(hash);
+    }
+    Ok(hashes)
+}
@@ -0,0 +1,189 @@
+//! Per-address memory access counts.
+//!
+//! While a heatmap is enabled, every CPU read and write counts against the
+//! address it touched, and [`Vm::step`] counts the address of every
+//! instruction it executes. The result shows which memory a program really
+//! uses:
+//!
+//! ```
+//! # use emulator::Vm;
+//! let mut vm = Vm::new();
+//! vm.load(0x0000, &[0xAD, 0x00, 0x02]).unwrap(); // LDA $0200
+//! vm.enable_heatmap();
+//! vm.step().unwrap();
+//! let heatmap = vm.heatmap().unwrap();
+//! assert_eq!(heatmap.reads()[0x0200], 1);
+//! assert_eq!(heatmap.executes()[0x0000], 1);
+//! ```
+//!
+//! Reads include instruction fetches, so code shows up as read as well as
+//! executed. Only the CPU's accesses count: device DMA and the host's
+//! [`Vm::read`] and [`Vm::write`] do not. Counts accumulate across resets
+//! until the heatmap is taken, and heatmaps from several runs can be merged.
+//!
+//! [`Heatmap::write_png`] renders the counts as a 256 by 256 image, one
+//! pixel per address with a row per page, writes in red, reads in green
+//! and executes in blue. Each channel is scaled logarithmically to its own
+//! highest count, so rarely touched memory still shows.
+//!
+//! Counting needs every access to reach the host, so a machine with a
+//! heatmap enabled runs instruction by instruction.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::ffi::BusAccess;
+use crate::screenshot;
+use crate::vm::Vm;
+
+const ADDRESSES: usize = 0x10000;
+
+/// Reads, writes and executes counted at one address.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AccessCounts {
+    pub reads: u64,
+    pub writes: u64,
+    pub executes: u64,
+}
+
+/// Access counts for every address.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Heatmap {
+    reads: Box<[u64]>,
+    writes: Box<[u64]>,
+    executes: Box<[u64]>,
+}
+
+impl Heatmap {
+    /// Nothing counted yet.
+    pub fn new() -> Self {
+        let zeros = || vec![0; ADDRESSES].into_boxed_slice();
+        Self {
+            reads: zeros(),
+            writes: zeros(),
+            executes: zeros(),
+        }
+    }
+
+    /// Reads of each address, indexed by address.
+    pub fn reads(&self) -> &[u64] {
+        &self.reads
+    }
+
+    /// Writes to each address, indexed by address.
+    pub fn writes(&self) -> &[u64] {
+        &self.writes
+    }
+
+    /// Instructions executed at each address, indexed by address.
+    pub fn executes(&self) -> &[u64] {
+        &self.executes
+    }
+
+    /// Everything counted at `addr`.
+    pub fn get(&self, addr: u16) -> AccessCounts {
+        let addr = addr as usize;
+        AccessCounts {
+            reads: self.reads[addr],
+            writes: self.writes[addr],
+            executes: self.executes[addr],
+        }
+    }
+
+    /// Adds the counts of `other`.
+    pub fn merge(&mut self, other: &Heatmap) {
+        let pairs = [
+            (&mut self.reads, &other.reads),
+            (&mut self.writes, &other.writes),
+            (&mut self.executes, &other.executes),
+        ];
+        for (mine, theirs) in pairs {
+            for (a, b) in mine.iter_mut().zip(theirs.iter()) {
+                *a += b;
+            }
+        }
+    }
+
+    pub(crate) fn count(&mut self, kind: BusAccess, addr: u16) {
+        let counts = match kind {
+            BusAccess::Read => &mut self.reads,
+            BusAccess::Write => &mut self.writes,
+        };
+        counts[addr as usize] += 1;
+    }
+
+    pub(crate) fn count_execute(&mut self, addr: u16) {
+        self.executes[addr as usize] += 1;
+    }
+
+    /// Writes the heatmap as an RGB PNG; see the [module docs](self).
+    pub fn write_png(&self, mut writer: impl Write) -> io::Result<()> {
+        let channels = [&self.writes, &self.reads, &self.executes].map(|counts| {
+            let max = counts.iter().copied().max().unwrap_or(0);
+            let scale = 255.0 / (max as f64).ln_1p();
+            counts.iter().map(move |&count| match count {
+                0 => 0,
+                count => ((count as f64).ln_1p() * scale).round() as u8,
+            })
+        });
+        let [red, green, blue] = channels;
+        let pixels = red.zip(green).zip(blue).map(|((r, g), b)| [r, g, b]);
+        let mut image = Vec::with_capacity(256 * (1 + 256 * 3));
+        for (i, pixel) in pixels.enumerate() {
+            if i % 256 == 0 {
+                // Filter type 0: the row as it is.
+                image.push(0);
+            }
+            image.extend(pixel);
+        }
+        screenshot::write_png(&mut writer, (256, 256), 2, None, &image)
+    }
+
+    /// Writes the PNG to a file at `path`.
+    pub fn save_png(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        self.write_png(&mut file)?;
+        file.flush()
+    }
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for Heatmap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let touched = |counts: &[u64]| counts.iter().filter(|&&count| count > 0).count();
+        f.debug_struct("Heatmap")
+            .field("read", &touched(&self.reads))
+            .field("written", &touched(&self.writes))
+            .field("executed", &touched(&self.executes))
+            .finish()
+    }
+}
+
+impl Vm {
+    /// Starts counting accesses, keeping what was counted so far.
+    pub fn enable_heatmap(&mut self) {
+        let bus = self.bus_mut();
+        if bus.heatmap.is_none() {
+            bus.heatmap = Some(Heatmap::new());
+            bus.pages_dirty = true;
+        }
+    }
+
+    /// Stops counting accesses and hands back what was counted.
+    pub fn take_heatmap(&mut self) -> Option<Heatmap> {
+        let bus = self.bus_mut();
+        bus.pages_dirty = true;
+        bus.heatmap.take()
+    }
+
+    /// Access counts so far, if enabled.
+    pub fn heatmap(&self) -> Option<&Heatmap> {
+        self.bus().heatmap.as_ref()
+    }
+}
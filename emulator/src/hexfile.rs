@@ -0,0 +1,399 @@
+//! Intel HEX and Motorola S-record files.
+//!
+//! Most 8-bit assemblers and EPROM programmers write their output as one
+//! of these text formats rather than as a raw binary. [`Image::parse`]
+//! reads either, telling them apart by the first record, and
+//! [`Vm::load_image`] stores it:
+//!
+//! ```
+//! # use emulator::{hexfile::Image, Vm};
+//! let image = Image::parse(":03C00000A9014E45\n:00000001FF\n").unwrap();
+//! let mut vm = Vm::new();
+//! vm.load_image(&image);
+//! assert_eq!(vm.read_mem_raw(0xC000..=0xC002), [0xA9, 0x01, 0x4E]);
+//! ```
+//!
+//! Files are usually sparse, code in one place and vectors in another, so
+//! an image is a list of [`Region`]s, runs of bytes the records cover, and
+//! loading one leaves the memory between them as it was. Two records that
+//! cover the same byte are an error rather than the later one winning, as
+//! that is almost always two sections linked over each other.
+//!
+//! Intel HEX data (type 00) is placed with the extended segment (02) and
+//! extended linear (04) address records; S1, S2 and S3 records carry 16,
+//! 24 and 32-bit addresses. Every byte must land inside the 64 KiB address
+//! space. A start address record (Intel HEX 03 or 05, S7, S8 or S9) gives
+//! the image's [entry point](Image::entry), and an S5 or S6 count is
+//! checked against the data records. The end record may be left out, but
+//! nothing may follow it. Checksums are always checked.
+
+use std::fmt;
+
+use crate::ffi::RVM_MEM_SIZE;
+use crate::vm::{Registers, Vm};
+
+/// A run of bytes an [`Image`] covers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub addr: u16,
+    pub data: Vec<u8>,
+}
+
+/// The contents of a HEX or S-record file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Image {
+    /// In address order, none touching another.
+    regions: Vec<Region>,
+    entry: Option<u16>,
+}
+
+/// A record that could not be parsed or placed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HexError {
+    /// 1-based line.
+    pub line: usize,
+    pub kind: HexErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HexErrorKind {
+    /// The line is not a record of the file's format, or the file is
+    /// neither format.
+    Syntax,
+    /// The record's length byte disagrees with its size.
+    BadLength,
+    /// The record's bytes do not add up to its checksum byte.
+    BadChecksum { expected: u8, found: u8 },
+    /// A record type the format does not define.
+    UnknownRecord(u8),
+    /// `len` bytes at `addr`, or the entry point when `len` is 0, are
+    /// outside the address space.
+    OutOfRange { addr: u32, len: usize },
+    /// The byte at `addr` was already given by this earlier line.
+    Overlap { addr: u16, line: usize },
+    /// An S5 or S6 record counts this many data records, not the number
+    /// before it.
+    CountMismatch { count: u32, records: u32 },
+    /// A record follows the end-of-file record.
+    AfterEnd,
+}
+
+impl fmt::Display for HexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            HexErrorKind::Syntax => write!(f, "not an Intel HEX or S-record line"),
+            HexErrorKind::BadLength => write!(f, "record length does not match its data"),
+            HexErrorKind::BadChecksum { expected, found } => {
+                write!(f, "checksum is 0x{found:02X}, expected 0x{expected:02X}")
+            }
+            HexErrorKind::UnknownRecord(kind) => write!(f, "unknown record type {kind:02X}"),
+            HexErrorKind::OutOfRange { addr, len: 0 } => {
+                write!(f, "entry point 0x{addr:X} is outside the address space")
+            }
+            HexErrorKind::OutOfRange { addr, len } => {
+                write!(f, "{len} bytes at 0x{addr:X} do not fit the address space")
+            }
+            HexErrorKind::Overlap { addr, line } => {
+                write!(f, "byte at 0x{addr:04X} was already given on line {line}")
+            }
+            HexErrorKind::CountMismatch { count, records } => {
+                write!(
+                    f,
+                    "record count is {count}, but {records} data records precede it"
+                )
+            }
+            HexErrorKind::AfterEnd => write!(f, "record after the end-of-file record"),
+        }
+    }
+}
+
+impl std::error::Error for HexError {}
+
+/// A data record, before regions are merged.
+struct Chunk {
+    addr: u16,
+    data: Vec<u8>,
+    line: usize,
+}
+
+/// The image being read, and where the reader is.
+struct Builder {
+    chunks: Vec<Chunk>,
+    entry: Option<u16>,
+    ended: bool,
+    line: usize,
+}
+
+impl Builder {
+    fn error(&self, kind: HexErrorKind) -> HexError {
+        HexError {
+            line: self.line,
+            kind,
+        }
+    }
+
+    fn data(&mut self, addr: u32, data: Vec<u8>) -> Result<(), HexError> {
+        if addr as usize + data.len() > RVM_MEM_SIZE {
+            return Err(self.error(HexErrorKind::OutOfRange {
+                addr,
+                len: data.len(),
+            }));
+        }
+        if !data.is_empty() {
+            self.chunks.push(Chunk {
+                addr: addr as u16,
+                data,
+                line: self.line,
+            });
+        }
+        Ok(())
+    }
+
+    fn entry(&mut self, addr: u32) -> Result<(), HexError> {
+        let addr = u16::try_from(addr)
+            .map_err(|_| self.error(HexErrorKind::OutOfRange { addr, len: 0 }))?;
+        self.entry = Some(addr);
+        Ok(())
+    }
+
+    /// Sorts the chunks into regions, failing on the first overlap.
+    fn finish(mut self) -> Result<Image, HexError> {
+        self.chunks.sort_by_key(|chunk| chunk.addr);
+        let mut regions: Vec<Region> = Vec::new();
+        let mut last: Option<(usize, usize)> = None;
+        for chunk in self.chunks {
+            let start = chunk.addr as usize;
+            if let Some((end, line)) = last
+                && start < end
+            {
+                // Report against whichever record came later in the file.
+                let (line, earlier) = (line.max(chunk.line), line.min(chunk.line));
+                return Err(HexError {
+                    line,
+                    kind: HexErrorKind::Overlap {
+                        addr: chunk.addr,
+                        line: earlier,
+                    },
+                });
+            }
+            let end = start + chunk.data.len();
+            last = Some((end, chunk.line));
+            match regions.last_mut() {
+                Some(region) if region.addr as usize + region.data.len() == start => {
+                    region.data.extend_from_slice(&chunk.data);
+                }
+                _ => regions.push(Region {
+                    addr: chunk.addr,
+                    data: chunk.data,
+                }),
+            }
+        }
+        Ok(Image {
+            regions,
+            entry: self.entry,
+        })
+    }
+}
+
+/// The bytes of a record written as hex digit pairs.
+fn hex_bytes(digits: &str) -> Option<Vec<u8>> {
+    if !digits.len().is_multiple_of(2) || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(&digits[at..at + 2], 16).ok())
+        .collect()
+}
+
+fn be_address(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |addr, &b| addr << 8 | u32::from(b))
+}
+
+/// Checks a record's last byte against what `checksum` makes of the sum of
+/// the others.
+fn check(builder: &Builder, bytes: &[u8], checksum: impl Fn(u8) -> u8) -> Result<(), HexError> {
+    let (&found, body) = bytes.split_last().expect("records are not empty");
+    let sum = body.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+    let expected = checksum(sum);
+    if found != expected {
+        return Err(builder.error(HexErrorKind::BadChecksum { expected, found }));
+    }
+    Ok(())
+}
+
+impl Image {
+    /// Parses an Intel HEX or S-record file, whichever its first record is.
+    pub fn parse(text: &str) -> Result<Self, HexError> {
+        let first = text.lines().map(str::trim).find(|line| !line.is_empty());
+        match first.and_then(|line| line.chars().next()) {
+            Some('S') => Self::parse_srec(text),
+            Some(':') | None => Self::parse_ihex(text),
+            Some(_) => {
+                let line = text.lines().position(|line| !line.trim().is_empty());
+                Err(HexError {
+                    line: line.unwrap_or_default() + 1,
+                    kind: HexErrorKind::Syntax,
+                })
+            }
+        }
+    }
+
+    /// Parses an Intel HEX file.
+    pub fn parse_ihex(text: &str) -> Result<Self, HexError> {
+        let mut builder = Builder {
+            chunks: Vec::new(),
+            entry: None,
+            ended: false,
+            line: 0,
+        };
+        let mut base = 0u32;
+        for (index, line) in text.lines().enumerate() {
+            builder.line = index + 1;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if builder.ended {
+                return Err(builder.error(HexErrorKind::AfterEnd));
+            }
+            let bytes = line
+                .strip_prefix(':')
+                .and_then(hex_bytes)
+                .filter(|bytes| bytes.len() >= 5)
+                .ok_or_else(|| builder.error(HexErrorKind::Syntax))?;
+            if bytes[0] as usize + 5 != bytes.len() {
+                return Err(builder.error(HexErrorKind::BadLength));
+            }
+            check(&builder, &bytes, |sum| sum.wrapping_neg())?;
+            let offset = u32::from(u16::from_be_bytes([bytes[1], bytes[2]]));
+            let data = &bytes[4..bytes.len() - 1];
+            let fixed = |len: usize| {
+                if data.len() == len {
+                    Ok(be_address(data))
+                } else {
+                    Err(builder.error(HexErrorKind::BadLength))
+                }
+            };
+            match bytes[3] {
+                0x00 => builder.data(base + offset, data.to_vec())?,
+                0x01 => builder.ended = true,
+                0x02 => base = fixed(2)? << 4,
+                0x03 => {
+                    let start = fixed(4)?;
+                    builder.entry(((start >> 16) << 4) + (start & 0xFFFF))?;
+                }
+                0x04 => base = fixed(2)? << 16,
+                0x05 => {
+                    let start = fixed(4)?;
+                    builder.entry(start)?;
+                }
+                kind => return Err(builder.error(HexErrorKind::UnknownRecord(kind))),
+            }
+        }
+        builder.finish()
+    }
+
+    /// Parses a Motorola S-record file.
+    pub fn parse_srec(text: &str) -> Result<Self, HexError> {
+        let mut builder = Builder {
+            chunks: Vec::new(),
+            entry: None,
+            ended: false,
+            line: 0,
+        };
+        let mut records = 0u32;
+        for (index, line) in text.lines().enumerate() {
+            builder.line = index + 1;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if builder.ended {
+                return Err(builder.error(HexErrorKind::AfterEnd));
+            }
+            let (kind, bytes) = line
+                .strip_prefix('S')
+                .and_then(|rest| rest.split_at_checked(1))
+                .and_then(|(kind, rest)| Some((kind.parse::<u8>().ok()?, hex_bytes(rest)?)))
+                .filter(|(_, bytes)| bytes.len() >= 2)
+                .ok_or_else(|| builder.error(HexErrorKind::Syntax))?;
+            if bytes[0] as usize + 1 != bytes.len() {
+                return Err(builder.error(HexErrorKind::BadLength));
+            }
+            check(&builder, &bytes, |sum| !sum)?;
+            let width = match kind {
+                0 | 1 | 5 | 9 => 2,
+                2 | 6 | 8 => 3,
+                3 | 7 => 4,
+                _ => return Err(builder.error(HexErrorKind::UnknownRecord(kind))),
+            };
+            if bytes.len() < width + 2 {
+                return Err(builder.error(HexErrorKind::BadLength));
+            }
+            let addr = be_address(&bytes[1..1 + width]);
+            let data = &bytes[1 + width..bytes.len() - 1];
+            match kind {
+                0 => {}
+                1..=3 => {
+                    builder.data(addr, data.to_vec())?;
+                    records += 1;
+                }
+                5 | 6 if addr != records => {
+                    return Err(builder.error(HexErrorKind::CountMismatch {
+                        count: addr,
+                        records,
+                    }));
+                }
+                5 | 6 => {}
+                _ => {
+                    builder.entry(addr)?;
+                    builder.ended = true;
+                }
+            }
+        }
+        builder.finish()
+    }
+
+    /// The runs of bytes the file gives, in address order.
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    /// The start address the file gives, if any.
+    pub fn entry(&self) -> Option<u16> {
+        self.entry
+    }
+
+    /// The bytes from the lowest address the file gives to the highest,
+    /// with `fill` in the gaps, and the address they start at, as an EPROM
+    /// image. `None` for an image with no data.
+    pub fn flatten(&self, fill: u8) -> Option<(u16, Vec<u8>)> {
+        let (first, last) = (self.regions.first()?, self.regions.last()?);
+        let start = first.addr as usize;
+        let mut bytes = vec![fill; last.addr as usize + last.data.len() - start];
+        for region in &self.regions {
+            let at = region.addr as usize - start;
+            bytes[at..at + region.data.len()].copy_from_slice(&region.data);
+        }
+        Some((first.addr, bytes))
+    }
+}
+
+impl Vm {
+    /// Stores `image`'s regions, leaving the memory between them alone,
+    /// and points the PC at its entry point if it gives one.
+    pub fn load_image(&mut self, image: &Image) {
+        for region in &image.regions {
+            self.load(region.addr, &region.data)
+                .expect("regions fit the address space");
+        }
+        if let Some(pc) = image.entry {
+            self.set_registers(Registers {
+                pc,
+                ..self.registers()
+            });
+        }
+    }
+}
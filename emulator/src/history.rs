@@ -0,0 +1,122 @@
+//! The last instructions the machine executed.
+//!
+//! A fault says where the program ended up but not how it got there. With
+//! [`Vm::set_history_len`] the machine keeps a ring of the last few
+//! instructions it stepped, much cheaper than a [trace](crate::trace)
+//! since nothing is disassembled or written anywhere, and a debugger reads
+//! it back with [`Vm::history`] once an instruction fails:
+//!
+//! ```
+//! # use emulator::{ResetKind, Vm, VmError};
+//! let mut vm = Vm::new();
+//! vm.set_history_len(16);
+//! vm.load(0x8000, &[0xA9, 0x01, 0xA2, 0x02, 0x02]).unwrap(); // 0x02 is illegal
+//! vm.load(0xFFFC, &[0x00, 0x80]).unwrap();
+//! vm.reset(ResetKind::Soft);
+//! let err = (0..3).try_for_each(|_| vm.step()).unwrap_err();
+//! assert!(matches!(err, VmError::IllegalOpcode { pc: 0x8004, .. }));
+//! let pcs: Vec<u16> = vm.history().map(|entry| entry.pc).collect();
+//! assert_eq!(pcs, [0x8000, 0x8002, 0x8004]);
+//! ```
+//!
+//! The last entry is the instruction that failed. Like coverage, keeping a
+//! history makes [`Vm::run_frame`] step the instructions one at a time.
+
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::vm::{Registers, Vm};
+
+/// One instruction the machine executed, and the state it started from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub pc: u16,
+    pub opcode: u8,
+    pub registers: Registers,
+    /// Cycle counter before the instruction.
+    pub cycles: u32,
+    /// The step entered an interrupt handler instead of executing the
+    /// instruction at `pc`.
+    pub interrupt: bool,
+}
+
+/// In the layout of a [`TraceRecord`](crate::TraceRecord) line, with the
+/// opcode alone in place of the instruction.
+impl fmt::Display for HistoryEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Registers {
+            a, x, y, sp, flags, ..
+        } = self.registers;
+        let opcode = if self.interrupt {
+            "IRQ".into()
+        } else {
+            format!("{:02X}", self.opcode)
+        };
+        write!(
+            f,
+            "{:04X}  {opcode:<3}  A:{a:02X} X:{x:02X} Y:{y:02X} P:{flags:02X} SP:{sp:02X} CYC:{}",
+            self.pc, self.cycles
+        )
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct History {
+    entries: VecDeque<HistoryEntry>,
+    len: usize,
+}
+
+impl Vm {
+    /// Keeps the last `len` executed instructions for [`Vm::history`],
+    /// dropping the oldest ones if fewer than before; 0 stops keeping any
+    /// and drops them all.
+    pub fn set_history_len(&mut self, len: usize) {
+        if len == 0 {
+            self.history = None;
+            return;
+        }
+        let history = self.history.get_or_insert_with(|| History {
+            entries: VecDeque::with_capacity(len),
+            len,
+        });
+        let excess = history.entries.len().saturating_sub(len);
+        history.entries.drain(..excess);
+        history.len = len;
+    }
+
+    /// How many instructions the history keeps, 0 when it is off.
+    pub fn history_len(&self) -> usize {
+        self.history.as_ref().map_or(0, |history| history.len)
+    }
+
+    /// The kept instructions, oldest first.
+    pub fn history(&self) -> impl DoubleEndedIterator<Item = &HistoryEntry> + '_ {
+        self.history.iter().flat_map(|history| &history.entries)
+    }
+
+    /// Drops the kept instructions, keeping the history on.
+    pub fn clear_history(&mut self) {
+        if let Some(history) = &mut self.history {
+            history.entries.clear();
+        }
+    }
+
+    /// Adds the instruction about to execute at the PC.
+    pub(crate) fn record_history(&mut self, interrupt: bool) {
+        let pc = self.cpu.pc;
+        let entry = HistoryEntry {
+            pc,
+            opcode: self.read(pc),
+            registers: self.registers(),
+            cycles: self.cpu.cycles,
+            interrupt,
+        };
+        let Some(history) = &mut self.history else {
+            return;
+        };
+        if history.entries.len() == history.len {
+            history.entries.pop_front();
+        }
+        history.entries.push_back(entry);
+    }
+}
@@ -0,0 +1,409 @@
+//! Host callbacks for scripted and tool-assisted runs.
+//!
+//! Hooks fire when the PC reaches an address, when the program accesses a
+//! watched range, and at every frame boundary. Each callback gets the whole
+//! [`Vm`], so it can inspect or poke registers and memory, press buttons or
+//! register further hooks. This is the surface a scripting engine binds its
+//! own callbacks to.
+//!
+//! PC hooks run just before the instruction at their address executes, and
+//! a hook that moves the PC redirects that instruction. Access hooks run once
+//! the instruction that made the access has finished, with the value that
+//! was read or written; they observe accesses rather than altering them.
+//! Frame hooks run at the end of [`Vm::run_frame`], before the rewind buffer
+//! records the frame. Frames replayed by [`Vm::rewind`] run none of these.
+//!
+//! Read and write hooks, registered with [`Vm::on_read`] and
+//! [`Vm::on_write`], instead run in the middle of the access and decide the
+//! value it carries, so they can patch what the program reads or change
+//! what it stores. Running mid-instruction, they get only the address and
+//! value, not the `Vm`. Because they can alter execution they stay active
+//! during rewind replays. Like watchpoints they are filtered by page in the
+//! kernel, so accesses to pages without a hook never leave it.
+//!
+//! Code write hooks, registered with [`Vm::on_code_write`], catch
+//! self-modifying code: a write to a byte of an instruction the program
+//! has executed, or to a given code range.
+//! They run like access hooks, and also get the PC of the writing
+//! instruction, which is what a debugger reports and what a JIT needs to
+//! invalidate its translated blocks. Watching executed code sends every
+//! write through the host.
+
+use std::collections::BTreeSet;
+use std::mem;
+use std::ops::RangeInclusive;
+
+use crate::bus::{ValueHook, WatchHit};
+use crate::coverage::Bitmap;
+use crate::debugger::{Breakpoints, WatchKind, Watchpoint};
+use crate::disasm::{instruction_size, lookup};
+use crate::ffi::BusAccess;
+use crate::vm::Vm;
+
+/// Identifies a registered hook for [`Vm::remove_hook`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HookId(u32);
+
+/// A memory access reported to an access hook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub addr: u16,
+    pub kind: BusAccess,
+    /// The byte read or written.
+    pub value: u8,
+}
+
+/// Which writes a code write hook catches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodeWatch {
+    /// Writes to any byte of an instruction executed while a hook watches
+    /// executed code, operands included.
+    Executed,
+    /// Writes to the range, executed or not.
+    Range(RangeInclusive<u16>),
+}
+
+/// A write reported to a code write hook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodeWrite {
+    /// Address of the instruction that wrote.
+    pub pc: u16,
+    pub addr: u16,
+    /// The byte written.
+    pub value: u8,
+}
+
+type Callback = Box<dyn FnMut(&mut Vm) + Send>;
+type AccessCallback = Box<dyn FnMut(&mut Vm, Access) + Send>;
+type CodeWriteCallback = Box<dyn FnMut(&mut Vm, CodeWrite) + Send>;
+
+struct PcHook {
+    id: HookId,
+    addr: u16,
+    callback: Callback,
+}
+
+struct AccessHook {
+    id: HookId,
+    watch: Watchpoint,
+    callback: AccessCallback,
+}
+
+struct FrameHook {
+    id: HookId,
+    callback: Callback,
+}
+
+struct CodeWriteHook {
+    id: HookId,
+    watch: CodeWatch,
+    callback: CodeWriteCallback,
+}
+
+/// Registered hooks.
+///
+/// While one kind of hook is being dispatched its list is taken out of the
+/// `Vm`, so callbacks can register and remove hooks freely; `ids` is the
+/// source of truth for which hooks are still live.
+#[derive(Default)]
+pub(crate) struct Hooks {
+    next_id: u32,
+    /// Bumped whenever a hook is registered or removed.
+    generation: u32,
+    ids: BTreeSet<HookId>,
+    pc: Vec<PcHook>,
+    pc_addrs: Breakpoints,
+    access: Vec<AccessHook>,
+    frame: Vec<FrameHook>,
+    code_write: Vec<CodeWriteHook>,
+    /// Bytes of the instructions executed, kept while a code write hook
+    /// watches [`CodeWatch::Executed`].
+    executed: Option<Bitmap>,
+}
+
+impl Hooks {
+    fn allocate(&mut self) -> HookId {
+        let id = HookId(self.next_id);
+        self.next_id += 1;
+        self.generation += 1;
+        self.ids.insert(id);
+        id
+    }
+
+    /// Whether any hook at all is registered.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    fn watches_executed(&self) -> bool {
+        self.code_write
+            .iter()
+            .any(|h| h.watch == CodeWatch::Executed)
+    }
+
+    fn matches_code(&self, watch: &CodeWatch, addr: u16) -> bool {
+        match watch {
+            CodeWatch::Executed => self.executed.as_ref().is_some_and(|e| e.get(addr)),
+            CodeWatch::Range(range) => range.contains(&addr),
+        }
+    }
+
+    fn sync_pc_addrs(&mut self) {
+        self.pc_addrs.clear();
+        for hook in &self.pc {
+            self.pc_addrs.insert(hook.addr);
+        }
+    }
+}
+
+/// Puts a taken hook list back, keeping hooks registered meanwhile and
+/// dropping those removed meanwhile.
+fn restore<T>(ids: &BTreeSet<HookId>, slot: &mut Vec<T>, taken: Vec<T>, id: fn(&T) -> HookId) {
+    let added = mem::replace(slot, taken);
+    slot.extend(added);
+    slot.retain(|hook| ids.contains(&id(hook)));
+}
+
+impl Vm {
+    /// Calls `callback` every time the instruction at `addr` is about to
+    /// execute.
+    pub fn on_pc(&mut self, addr: u16, callback: impl FnMut(&mut Vm) + Send + 'static) -> HookId {
+        let id = self.hooks.allocate();
+        self.hooks.pc.push(PcHook {
+            id,
+            addr,
+            callback: Box::new(callback),
+        });
+        self.hooks.pc_addrs.insert(addr);
+        id
+    }
+
+    /// Calls `callback` for every access by the program to `range` that
+    /// matches `kind`. Instruction fetches count as reads.
+    pub fn on_access(
+        &mut self,
+        range: RangeInclusive<u16>,
+        kind: WatchKind,
+        callback: impl FnMut(&mut Vm, Access) + Send + 'static,
+    ) -> HookId {
+        let id = self.hooks.allocate();
+        self.hooks.access.push(AccessHook {
+            id,
+            watch: Watchpoint { range, kind },
+            callback: Box::new(callback),
+        });
+        self.sync_access_hooks();
+        id
+    }
+
+    /// Passes every byte the program reads from `range` through `callback`,
+    /// which gets the address and the value read and returns the value the
+    /// CPU sees. Instruction fetches count as reads.
+    pub fn on_read(
+        &mut self,
+        range: RangeInclusive<u16>,
+        callback: impl FnMut(u16, u8) -> u8 + Send + 'static,
+    ) -> HookId {
+        self.add_value_hook(range, BusAccess::Read, Box::new(callback))
+    }
+
+    /// Passes every byte the program writes to `range` through `callback`,
+    /// which gets the address and the value written and returns the value
+    /// stored. Writes to ROM are still dropped afterwards.
+    pub fn on_write(
+        &mut self,
+        range: RangeInclusive<u16>,
+        callback: impl FnMut(u16, u8) -> u8 + Send + 'static,
+    ) -> HookId {
+        self.add_value_hook(range, BusAccess::Write, Box::new(callback))
+    }
+
+    fn add_value_hook(
+        &mut self,
+        range: RangeInclusive<u16>,
+        kind: BusAccess,
+        callback: Box<dyn FnMut(u16, u8) -> u8 + Send>,
+    ) -> HookId {
+        let id = self.hooks.allocate();
+        let bus = self.bus_mut();
+        bus.value_hooks.push(ValueHook {
+            id,
+            range,
+            kind,
+            callback,
+        });
+        bus.pages_dirty = true;
+        id
+    }
+
+    /// Calls `callback` for every write by the program to code that
+    /// `watch` covers, once the writing instruction has finished.
+    pub fn on_code_write(
+        &mut self,
+        watch: CodeWatch,
+        callback: impl FnMut(&mut Vm, CodeWrite) + Send + 'static,
+    ) -> HookId {
+        let id = self.hooks.allocate();
+        if watch == CodeWatch::Executed && self.hooks.executed.is_none() {
+            self.hooks.executed = Some(Bitmap::new());
+        }
+        self.hooks.code_write.push(CodeWriteHook {
+            id,
+            watch,
+            callback: Box::new(callback),
+        });
+        self.sync_access_hooks();
+        id
+    }
+
+    /// Calls `callback` at the end of every frame run by [`Vm::run_frame`].
+    pub fn on_frame(&mut self, callback: impl FnMut(&mut Vm) + Send + 'static) -> HookId {
+        let id = self.hooks.allocate();
+        self.hooks.frame.push(FrameHook {
+            id,
+            callback: Box::new(callback),
+        });
+        id
+    }
+
+    /// Unregisters a hook, returning `false` if it was already gone. A hook
+    /// may remove itself.
+    pub fn remove_hook(&mut self, id: HookId) -> bool {
+        let hooks = &mut self.hooks;
+        if !hooks.ids.remove(&id) {
+            return false;
+        }
+        hooks.generation += 1;
+        hooks.pc.retain(|h| h.id != id);
+        hooks.sync_pc_addrs();
+        hooks.access.retain(|h| h.id != id);
+        hooks.frame.retain(|h| h.id != id);
+        hooks.code_write.retain(|h| h.id != id);
+        if !hooks.watches_executed() {
+            hooks.executed = None;
+        }
+        self.bus_mut().value_hooks.retain(|h| h.id != id);
+        self.sync_access_hooks();
+        true
+    }
+
+    /// Mirrors the access and code write hook ranges into the bus, which
+    /// records matching accesses for [`Vm::run_access_hooks`].
+    fn sync_access_hooks(&mut self) {
+        let code = self.hooks.code_write.iter().map(|h| Watchpoint {
+            range: match &h.watch {
+                CodeWatch::Executed => 0x0000..=0xFFFF,
+                CodeWatch::Range(range) => range.clone(),
+            },
+            kind: WatchKind::Write,
+        });
+        let access = self.hooks.access.iter().map(|h| h.watch.clone());
+        let watches = access.chain(code).collect();
+        self.bus_mut().access_hooks = watches;
+        self.bus_mut().pages_dirty = true;
+    }
+
+    /// Runs the PC hooks for the instruction about to execute.
+    pub(crate) fn run_pc_hooks(&mut self) {
+        let pc = self.cpu.pc;
+        if !self.hooks.pc_addrs.contains(pc) {
+            return;
+        }
+        let mut taken = mem::take(&mut self.hooks.pc);
+        for hook in taken.iter_mut().filter(|h| h.addr == pc) {
+            if self.hooks.ids.contains(&hook.id) {
+                (hook.callback)(self);
+            }
+        }
+        let hooks = &mut self.hooks;
+        restore(&hooks.ids, &mut hooks.pc, taken, |h| h.id);
+        hooks.sync_pc_addrs();
+    }
+
+    /// Marks the bytes of the instruction at `pc` as executed for
+    /// [`CodeWatch::Executed`].
+    pub(crate) fn mark_executed(&mut self, pc: u16) {
+        if self.hooks.executed.is_none() {
+            return;
+        }
+        let opcode = self.read(pc);
+        let Some(executed) = &mut self.hooks.executed else {
+            return;
+        };
+        let size = lookup(opcode).map_or(1, |op| instruction_size(op.mode));
+        for offset in 0..u16::from(size) {
+            executed.set(pc.wrapping_add(offset));
+        }
+    }
+
+    /// Runs the access and code write hooks for the accesses the bus
+    /// recorded during the instruction at `pc`.
+    pub(crate) fn run_access_hooks(&mut self, pc: u16) {
+        let mut accesses = mem::take(&mut self.bus_mut().accesses);
+        let generation = self.hooks.generation;
+        let mut taken = mem::take(&mut self.hooks.access);
+        for hit in &accesses {
+            let access = Access {
+                addr: hit.addr,
+                kind: hit.kind,
+                value: hit.val,
+            };
+            for hook in taken
+                .iter_mut()
+                .filter(|h| h.watch.matches(hit.kind, hit.addr))
+            {
+                if self.hooks.ids.contains(&hook.id) {
+                    (hook.callback)(self, access);
+                }
+            }
+        }
+        let hooks = &mut self.hooks;
+        restore(&hooks.ids, &mut hooks.access, taken, |h| h.id);
+        self.run_code_write_hooks(pc, &accesses);
+        let hooks = &mut self.hooks;
+        if hooks.generation != generation {
+            self.sync_access_hooks();
+        }
+        // Hand the allocation back for the next instruction.
+        accesses.clear();
+        self.bus_mut().accesses = accesses;
+    }
+
+    fn run_code_write_hooks(&mut self, pc: u16, accesses: &[WatchHit]) {
+        if self.hooks.code_write.is_empty() {
+            return;
+        }
+        let mut taken = mem::take(&mut self.hooks.code_write);
+        for hit in accesses.iter().filter(|hit| hit.kind == BusAccess::Write) {
+            let write = CodeWrite {
+                pc,
+                addr: hit.addr,
+                value: hit.val,
+            };
+            for hook in &mut taken {
+                if self.hooks.ids.contains(&hook.id)
+                    && self.hooks.matches_code(&hook.watch, hit.addr)
+                {
+                    (hook.callback)(self, write);
+                }
+            }
+        }
+        let hooks = &mut self.hooks;
+        restore(&hooks.ids, &mut hooks.code_write, taken, |h| h.id);
+    }
+
+    /// Runs the frame hooks.
+    pub(crate) fn run_frame_hooks(&mut self) {
+        if self.hooks.frame.is_empty() {
+            return;
+        }
+        let mut taken = mem::take(&mut self.hooks.frame);
+        for hook in &mut taken {
+            if self.hooks.ids.contains(&hook.id) {
+                (hook.callback)(self);
+            }
+        }
+        let hooks = &mut self.hooks;
+        restore(&hooks.ids, &mut hooks.frame, taken, |h| h.id);
+    }
+}
@@ -0,0 +1,195 @@
+//! Host calls from emulated code.
+//!
+//! [`Vm::register_hostcall`] makes a Rust closure callable from a ROM by
+//! number. The [`HOSTCALL`] opcode, claimed as an
+//! [extension opcode](crate::extension) when the first call is registered,
+//! takes the number as its operand byte: `$72 nn` calls closure `nn`.
+//! Arguments and results travel in registers, with the closure free to
+//! read and write memory for anything bigger, which is enough for
+//! semihosting-style printing and file I/O or for test fixtures:
+//!
+//! ```
+//! # use emulator::Vm;
+//! let mut vm = Vm::new();
+//! // Call 1: A = X + Y, carry set on overflow.
+//! vm.register_hostcall(1, |call| {
+//!     let (sum, overflow) = call.x().overflowing_add(call.y());
+//!     call.set_a(sum);
+//!     call.set_carry(overflow);
+//!     Ok(())
+//! });
+//! // LDX #$F0; LDY #$20; HOSTCALL 1
+//! vm.load(0x0000, &[0xA2, 0xF0, 0xA0, 0x20, 0x72, 0x01]).unwrap();
+//! for _ in 0..3 {
+//!     vm.step().unwrap();
+//! }
+//! assert_eq!(vm.registers().a, 0x10);
+//! assert!(vm.registers().flags & emulator::ffi::FLAG_C != 0);
+//! ```
+//!
+//! By convention A, X and Y carry the arguments, X and Y together a pointer
+//! with X the low byte, and a closure reports failure to the ROM with the
+//! carry flag. An `Err` from a closure, or calling a number nothing is
+//! registered for, stops the machine with
+//! [`VmError::OpcodeFailed`](crate::VmError::OpcodeFailed) instead. A host
+//! call takes [`HOSTCALL_CYCLES`] cycles however long the closure runs.
+
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::extension::OpcodeCall;
+use crate::ffi::FLAG_C;
+use crate::vm::{Registers, Vm};
+
+/// `HOSTCALL nn`: run the closure registered as `nn`.
+pub const HOSTCALL: u8 = 0x72;
+/// Cycles a host call takes.
+pub const HOSTCALL_CYCLES: u8 = 4;
+
+/// Longest string [`HostCall::read_string`] reads.
+const MAX_STRING: usize = 256;
+
+type Closure = dyn FnMut(&mut HostCall<'_, '_>) -> Result<(), String> + Send;
+
+/// Closures by call number, shared between the [`Vm`] and the opcode
+/// handler.
+pub(crate) type Hostcalls = Arc<Mutex<Vec<Option<Box<Closure>>>>>;
+
+/// A host call in progress, as its closure sees the machine.
+pub struct HostCall<'a, 'b> {
+    op: &'a mut OpcodeCall<'b>,
+    id: u8,
+    regs: Registers,
+}
+
+impl HostCall<'_, '_> {
+    /// The call number.
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    pub fn a(&self) -> u8 {
+        self.regs.a
+    }
+
+    pub fn x(&self) -> u8 {
+        self.regs.x
+    }
+
+    pub fn y(&self) -> u8 {
+        self.regs.y
+    }
+
+    /// X and Y as an address, X the low byte.
+    pub fn pointer(&self) -> u16 {
+        u16::from_le_bytes([self.regs.x, self.regs.y])
+    }
+
+    pub fn set_a(&mut self, val: u8) {
+        self.regs.a = val;
+    }
+
+    pub fn set_x(&mut self, val: u8) {
+        self.regs.x = val;
+    }
+
+    pub fn set_y(&mut self, val: u8) {
+        self.regs.y = val;
+    }
+
+    /// Sets X and Y to `addr`, X the low byte.
+    pub fn set_pointer(&mut self, addr: u16) {
+        [self.regs.x, self.regs.y] = addr.to_le_bytes();
+    }
+
+    /// Sets or clears the carry flag, by convention set on failure.
+    pub fn set_carry(&mut self, carry: bool) {
+        if carry {
+            self.regs.flags |= FLAG_C;
+        } else {
+            self.regs.flags &= !FLAG_C;
+        }
+    }
+
+    /// Reads a byte as the CPU would.
+    pub fn read(&mut self, addr: u16) -> u8 {
+        self.op.read(addr)
+    }
+
+    /// Writes a byte as the CPU would.
+    pub fn write(&mut self, addr: u16, val: u8) {
+        self.op.write(addr, val);
+    }
+
+    /// Reads `len` bytes from `addr` on, wrapping at the top of memory.
+    pub fn read_bytes(&mut self, addr: u16, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| self.read(addr.wrapping_add(i as u16)))
+            .collect()
+    }
+
+    /// Writes `bytes` from `addr` on, wrapping at the top of memory.
+    pub fn write_bytes(&mut self, addr: u16, bytes: &[u8]) {
+        for (i, &byte) in bytes.iter().enumerate() {
+            self.write(addr.wrapping_add(i as u16), byte);
+        }
+    }
+
+    /// Reads the 0-terminated string at `addr`, at most 256 bytes of it.
+    pub fn read_string(&mut self, addr: u16) -> String {
+        let bytes: Vec<u8> = (0..MAX_STRING as u16)
+            .map(|i| self.read(addr.wrapping_add(i)))
+            .take_while(|&byte| byte != 0)
+            .collect();
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+/// The [`HOSTCALL`] opcode's handler.
+fn dispatch(calls: &Hostcalls, op: &mut OpcodeCall<'_>) -> Result<u8, String> {
+    let id = op.fetch();
+    // Taken out while it runs, so the lock is not held across it.
+    let taken = calls.lock().unwrap_or_else(PoisonError::into_inner)[id as usize].take();
+    let Some(mut closure) = taken else {
+        return Err(format!("no host call {id} is registered"));
+    };
+    let regs = op.registers();
+    let mut call = HostCall { op, id, regs };
+    let result = closure(&mut call);
+    let regs = call.regs;
+    calls.lock().unwrap_or_else(PoisonError::into_inner)[id as usize] = Some(closure);
+    result?;
+    op.set_registers(regs);
+    Ok(HOSTCALL_CYCLES)
+}
+
+impl Vm {
+    /// Makes `HOSTCALL id` run `closure`, replacing any closure registered
+    /// as `id` before; see the [module docs](crate::hostcall). Registering
+    /// claims the [`HOSTCALL`] opcode, replacing any handler it had.
+    ///
+    /// Registrations are host state: save states do not carry them.
+    pub fn register_hostcall(
+        &mut self,
+        id: u8,
+        closure: impl FnMut(&mut HostCall<'_, '_>) -> Result<(), String> + Send + 'static,
+    ) {
+        let calls = Arc::clone(
+            self.hostcalls
+                .get_or_insert_with(|| Arc::new(Mutex::new((0..256).map(|_| None).collect()))),
+        );
+        let handler = Arc::clone(&calls);
+        self.register_opcode(HOSTCALL, move |op| dispatch(&handler, op))
+            .expect("HOSTCALL is not a kernel opcode");
+        calls.lock().unwrap_or_else(PoisonError::into_inner)[id as usize] = Some(Box::new(closure));
+    }
+
+    /// Removes the closure registered as `id`, returning whether there was
+    /// one. The opcode stays claimed, so calling `id` stops the machine.
+    pub fn unregister_hostcall(&mut self, id: u8) -> bool {
+        self.hostcalls.as_ref().is_some_and(|calls| {
+            calls.lock().unwrap_or_else(PoisonError::into_inner)[id as usize]
+                .take()
+                .is_some()
+        })
+    }
+}
@@ -0,0 +1,174 @@
+//! Controller input.
+//!
+//! Every `Vm` maps a [`Controller`] over the input registers at
+//! [`INPUT_PORTS`]. The host sets which buttons are held with
+//! [`Vm::set_button`]; the program reads them through the port at
+//! [`CONTROLLER`] with the usual strobe and shift protocol:
+//!
+//! 1. Write 1 then 0 to the port to latch the held buttons.
+//! 2. Read the port eight times. Bit 0 of each read is one button, pressed
+//!    as 1, in [`Button`] order: A, B, Select, Start, Up, Down, Left, Right.
+//!    Further reads return 1.
+//!
+//! While the strobe bit is held at 1 every read reports the A button.
+//! Because the program only ever sees the latched state, input is
+//! deterministic as long as the host changes buttons between frames.
+
+use std::ops::RangeInclusive;
+
+use crate::bus::BusDevice;
+use crate::replay::InputEvent;
+use crate::reset::ResetKind;
+use crate::vm::Vm;
+
+/// The input register block.
+pub const INPUT_PORTS: RangeInclusive<u16> = 0x2500..=0x250F;
+/// The controller port.
+pub const CONTROLLER: u16 = 0x2500;
+
+/// A controller button, in the order the port shifts them out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Button {
+    A,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Button {
+    /// Every button, in port order.
+    pub const ALL: [Button; 8] = [
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+        Button::Up,
+        Button::Down,
+        Button::Left,
+        Button::Right,
+    ];
+
+    /// This button's bit in a button mask.
+    pub fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// The controller port device.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Controller {
+    /// Held buttons, one bit per [`Button::mask`].
+    buttons: u8,
+    strobe: bool,
+    /// Latched buttons still to be shifted out.
+    shift: u8,
+    remaining: u8,
+}
+
+impl Controller {
+    /// Currently held buttons as a mask.
+    pub fn buttons(&self) -> u8 {
+        self.buttons
+    }
+
+    /// Replaces the held buttons with `mask`.
+    pub fn set_buttons(&mut self, mask: u8) {
+        self.buttons = mask;
+    }
+
+    /// Held buttons, strobe, latched bits and bits left to shift.
+    pub(crate) fn to_bytes(&self) -> [u8; 4] {
+        [self.buttons, self.strobe.into(), self.shift, self.remaining]
+    }
+
+    pub(crate) fn from_bytes([buttons, strobe, shift, remaining]: [u8; 4]) -> Self {
+        Self {
+            buttons,
+            strobe: strobe != 0,
+            shift,
+            remaining,
+        }
+    }
+}
+
+impl BusDevice for Controller {
+    fn read8(&mut self, offset: u16) -> u8 {
+        if offset != CONTROLLER - INPUT_PORTS.start() {
+            return 0;
+        }
+        if self.strobe {
+            return self.buttons & 1;
+        }
+        if self.remaining == 0 {
+            return 1;
+        }
+        let bit = self.shift & 1;
+        self.shift >>= 1;
+        self.remaining -= 1;
+        bit
+    }
+
+    fn write8(&mut self, offset: u16, val: u8) {
+        if offset == CONTROLLER - INPUT_PORTS.start() {
+            self.strobe = val & 1 != 0;
+            self.shift = self.buttons;
+            self.remaining = 8;
+        }
+    }
+
+    /// Unbounded: the controller only changes when read, written or set by
+    /// the host.
+    fn batch_cycles(&self) -> u32 {
+        u32::MAX
+    }
+
+    /// Releases the strobe with nothing left to shift out; the buttons stay
+    /// as the player holds them.
+    fn reset(&mut self, _: ResetKind) {
+        self.strobe = false;
+        self.shift = 0;
+        self.remaining = 0;
+    }
+}
+
+impl Vm {
+    /// Presses or releases `button` on the controller.
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        let buttons = self.buttons();
+        let mask = button.mask();
+        self.set_buttons(if pressed {
+            buttons | mask
+        } else {
+            buttons & !mask
+        });
+    }
+
+    /// Replaces every held button at once with a [`Button::mask`] union.
+    /// Ignored while a [replay](crate::replay) is running.
+    pub fn set_buttons(&mut self, mask: u8) {
+        if self.inputs.is_replaying() {
+            return;
+        }
+        if mask != self.buttons() {
+            self.inputs.record(InputEvent::Buttons(mask));
+        }
+        self.apply_buttons(mask);
+    }
+
+    pub(crate) fn apply_buttons(&mut self, mask: u8) {
+        if let Some(controller) = self.bus_mut().device_mut::<Controller>(CONTROLLER) {
+            controller.set_buttons(mask);
+        }
+    }
+
+    /// Held buttons as a mask, or 0 if the controller has been unmapped.
+    pub fn buttons(&self) -> u8 {
+        self.bus()
+            .device::<Controller>(CONTROLLER)
+            .map_or(0, Controller::buttons)
+    }
+}
@@ -0,0 +1,101 @@
+//! [`tracing`] spans and events, for plugging the machine into an existing
+//! observability setup.
+//!
+//! With the `instrument` feature every [`Vm`] reports to whatever
+//! [`tracing`] subscriber is in effect, under the `emulator::instrument`
+//! target:
+//!
+//! | Name        | Kind         | Level | Fields                          |
+//! | ----------- | ------------ | ----- | ------------------------------- |
+//! | `frame`     | span         | INFO  | `frame`, `cycles`, `end`        |
+//! | `interrupt` | span         | DEBUG | `nmi`, `line`, `cycles`, `end`  |
+//! | `dma`       | span         | DEBUG | `cycles`, `stolen`              |
+//! | `step`      | span         | TRACE | `pc`, `cycles`                  |
+//! | fault       | event        | ERROR | `cycles` and the error message  |
+//!
+//! `cycles` is the [cycle counter](Vm::cycles) when the span opens or the
+//! event happens, so emulated time can be recorded next to the host's, and
+//! `end` is the counter when the span closes. A `frame` span opens when
+//! [`Vm::run_frame`] or another frame-stepping call starts a frame and
+//! closes when the frame's cycles have all run, or when a reset or a
+//! restored snapshot abandons it. A frame interrupted by an error stays
+//! open, and the next [`Vm::run_frame`] carries on with it. An `interrupt` span covers the step that enters
+//! a handler, with `line` the IRQ line served, left out for an NMI or an
+//! IRQ raised directly on the CPU. A `dma` span covers the `stolen` cycles
+//! device DMA held the CPU off the bus after an instruction. A `step`
+//! span covers one [`Vm::step`], and is entered while it runs, so events
+//! a hook emits fall inside it. Every span and fault belongs to the open
+//! frame, or else to the caller's current span.
+//!
+//! While the subscriber is interested in DEBUG spans the machine steps
+//! instruction by instruction, as it does when traced, so no interrupt is
+//! taken inside a batch where it could not be seen. Builds without the
+//! feature have no calls to pay for.
+
+use tracing::field::Empty;
+use tracing::{Id, Level, Span};
+
+use crate::error::VmError;
+use crate::vm::Vm;
+
+impl Vm {
+    /// The parent of the spans and events the machine reports.
+    fn span_parent(&self) -> Option<Id> {
+        self.frame_span.id().or_else(|| Span::current().id())
+    }
+
+    /// Whether the subscriber must see every instruction.
+    pub(crate) fn instrumented(&self) -> bool {
+        tracing::enabled!(Level::DEBUG)
+    }
+
+    /// Opens the span of the frame starting now.
+    pub(crate) fn open_frame_span(&mut self) {
+        self.frame_span = tracing::info_span!(
+            "frame",
+            frame = self.frame,
+            cycles = self.cpu.cycles,
+            end = Empty,
+        );
+    }
+
+    /// Closes the span of the current frame, if open.
+    pub(crate) fn close_frame_span(&mut self) {
+        let span = std::mem::replace(&mut self.frame_span, Span::none());
+        span.record("end", self.cpu.cycles);
+    }
+
+    /// The span of a step starting now.
+    pub(crate) fn step_span(&self) -> Span {
+        tracing::trace_span!(
+            parent: self.span_parent(),
+            "step",
+            pc = self.cpu.pc,
+            cycles = self.cpu.cycles,
+        )
+    }
+
+    /// The span of a step entering an interrupt handler; `line` as for
+    /// [`Vm::active_irq`].
+    pub(crate) fn interrupt_span(&self, nmi: bool, line: Option<u8>) -> Span {
+        tracing::debug_span!(
+            parent: self.span_parent(),
+            "interrupt",
+            nmi,
+            line,
+            cycles = self.cpu.cycles,
+            end = Empty,
+        )
+    }
+
+    /// Records that `stolen` cycles of DMA just ended.
+    pub(crate) fn instrument_dma(&self, stolen: u32) {
+        let cycles = self.cpu.cycles.wrapping_sub(stolen);
+        let span = tracing::debug_span!(parent: self.span_parent(), "dma", cycles, stolen);
+        drop(span.entered());
+    }
+
+    pub(crate) fn instrument_fault(&self, err: &VmError) {
+        tracing::error!(parent: self.span_parent(), cycles = self.cpu.cycles, "{err}");
+    }
+}
@@ -0,0 +1,175 @@
+//! Interrupt controller.
+//!
+//! The CPU has a level-sensitive IRQ input. In front of it sits a
+//! controller with [`IRQ_LINES`] lines, each of which can be raised by the
+//! host with [`Vm::raise_irq`] or held by a mapped device through
+//! [`BusDevice::irq_lines`](crate::BusDevice::irq_lines) or by the
+//! display's [line interrupt](crate::display::DISPLAY_IRQ). The CPU sees an
+//! interrupt whenever a line is active and enabled in the mask.
+//!
+//! Like the 6502, the CPU polls its interrupt inputs during the last cycle
+//! of every instruction and takes what the poll found before the next one,
+//! pushing the PC and flags and jumping through the vector at
+//! [`IRQ_VECTOR`](crate::ffi::IRQ_VECTOR). The poll ignores the IRQ input
+//! while the I flag is set, as the flag stood before the instruction, so
+//! CLI and SEI take effect one instruction late. A line a device raises
+//! while an instruction executes is seen by that instruction's poll, so the
+//! interrupt is taken right after it; a line the host raises between two
+//! instructions is first polled by the next one and taken after that. Once
+//! polled, an interrupt is taken even if its line has been acknowledged
+//! since.
+//!
+//! The non-maskable interrupt is a separate, edge-triggered input driven
+//! with [`Vm::set_nmi`]. A poll that finds it asserted after one that did
+//! not takes an NMI through [`NMI_VECTOR`](crate::ffi::NMI_VECTOR),
+//! whatever the I flag says and ahead of any IRQ; holding the line
+//! asserted does not take another. The line must stay asserted until an
+//! instruction has run for the edge to be seen.
+//!
+//! A host-raised line stays active until [`Vm::ack_irq`]; a device line is
+//! active for as long as the device reports it. All lines share the vector,
+//! so a handler asks [`Vm::active_irq`] which one to serve: lower-numbered
+//! lines take priority.
+//!
+//! Raising, acknowledging and masking lines and driving the NMI are host
+//! inputs: they are recorded by [`Vm::record_inputs`] and ignored while a
+//! replay runs.
+
+use crate::backend::kernel;
+use crate::ffi::{INT_IRQ, INT_NMI};
+use crate::replay::InputEvent;
+use crate::vm::Vm;
+
+/// Number of interrupt lines.
+pub const IRQ_LINES: u8 = 8;
+
+/// Interrupt controller and CPU interrupt state captured in snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IrqState {
+    /// Lines raised by the host and not yet acknowledged, one bit per line.
+    pub raised: u8,
+    /// Enabled lines, one bit per line.
+    pub mask: u8,
+    /// Whether the host asserts the NMI input.
+    pub nmi: bool,
+    /// What the CPU's last interrupt poll found, as the [`INT_IRQ`],
+    /// [`INT_NMI`] and [`INT_NMI_LEVEL`](crate::ffi::INT_NMI_LEVEL) bits of
+    /// `Cpu::int_state`. Only snapshots fill it in; the `Vm` keeps it in
+    /// the CPU.
+    pub poll: u8,
+}
+
+impl Default for IrqState {
+    /// No line raised, every line enabled, the NMI released and nothing
+    /// polled.
+    fn default() -> Self {
+        Self {
+            raised: 0,
+            mask: 0xFF,
+            nmi: false,
+            poll: 0,
+        }
+    }
+}
+
+fn line_bit(line: u8) -> u8 {
+    assert!(line < IRQ_LINES, "IRQ line {line} out of range");
+    1 << line
+}
+
+impl Vm {
+    /// Raises interrupt `line` until it is acknowledged.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `line` is not below [`IRQ_LINES`].
+    pub fn raise_irq(&mut self, line: u8) {
+        let bit = line_bit(line);
+        if self.inputs.is_replaying() || self.irq.raised & bit != 0 {
+            return;
+        }
+        self.inputs.record(InputEvent::RaiseIrq(line));
+        self.irq.raised |= bit;
+    }
+
+    /// Acknowledges host-raised interrupt `line`. Lines held by devices stay
+    /// active until the device releases them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `line` is not below [`IRQ_LINES`].
+    pub fn ack_irq(&mut self, line: u8) {
+        let bit = line_bit(line);
+        if self.inputs.is_replaying() || self.irq.raised & bit == 0 {
+            return;
+        }
+        self.inputs.record(InputEvent::AckIrq(line));
+        self.irq.raised &= !bit;
+    }
+
+    /// Asserts the NMI input while `asserted` is set and releases it
+    /// otherwise; see the [module docs](self) for when an NMI is taken.
+    pub fn set_nmi(&mut self, asserted: bool) {
+        if self.inputs.is_replaying() || self.irq.nmi == asserted {
+            return;
+        }
+        self.inputs.record(InputEvent::Nmi(asserted));
+        self.irq.nmi = asserted;
+    }
+
+    /// Whether the host asserts the NMI input.
+    pub fn nmi(&self) -> bool {
+        self.irq.nmi
+    }
+
+    /// Active lines, masked or not, one bit per line.
+    pub fn pending_irqs(&self) -> u8 {
+        self.irq.raised | self.bus().irq_lines() | self.display_irq_lines()
+    }
+
+    /// Enabled lines, one bit per line; all are enabled initially.
+    pub fn irq_mask(&self) -> u8 {
+        self.irq.mask
+    }
+
+    /// Enables exactly the lines set in `mask`.
+    pub fn set_irq_mask(&mut self, mask: u8) {
+        if self.inputs.is_replaying() || self.irq.mask == mask {
+            return;
+        }
+        self.inputs.record(InputEvent::IrqMask(mask));
+        self.irq.mask = mask;
+    }
+
+    /// The highest-priority line that is both active and enabled.
+    pub fn active_irq(&self) -> Option<u8> {
+        let lines = self.pending_irqs() & self.irq.mask;
+        (lines != 0).then(|| lines.trailing_zeros() as u8)
+    }
+
+    /// Drives the CPU's interrupt inputs from the controller.
+    pub(crate) fn update_irq_input(&mut self) {
+        self.cpu.irq = u8::from(self.active_irq().is_some());
+        self.cpu.nmi = u8::from(self.irq.nmi);
+    }
+
+    /// Updates the IRQ input once the devices have caught up with the
+    /// instructions just executed, and lets the last one's poll see a line
+    /// that changed meanwhile.
+    pub(crate) fn repoll_irq_input(&mut self) {
+        let irq = u8::from(self.active_irq().is_some());
+        if irq != self.cpu.irq {
+            self.cpu.irq = irq;
+            // SAFETY: `self.cpu` was initialized by `cpu_init` in `Vm::new`.
+            unsafe { kernel!(self.backend, cpu_poll_irq(&mut *self.cpu)) };
+        }
+    }
+
+    /// Whether the CPU enters an interrupt handler instead of executing its
+    /// next instruction, and whether that is an NMI.
+    pub(crate) fn latched_interrupt(&self) -> Option<bool> {
+        let state = self.cpu.int_state;
+        (state & (INT_IRQ | INT_NMI) != 0).then_some(state & INT_NMI != 0)
+    }
+}
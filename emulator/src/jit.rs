@@ -0,0 +1,983 @@
+//! Block recompiler for instruction batches, on Cranelift.
+//!
+//! Only compiled with the `jit` feature. Once [`Vm::enable_jit`] is called,
+//! the batches [`Vm::run_cycles`] and [`Vm::run_frame`] hand to the kernel
+//! while nothing observes single instructions run through native code
+//! instead: straight-line blocks of documented instructions, compiled the
+//! first time the PC reaches them. Anything else, interrupt entries and the
+//! opcodes the ISA leaves to `illegal_mode` or extensions, still goes to
+//! the kernel one `cpu_step` at a time, so the machine's state after a
+//! batch is exactly the kernel's: registers, cycles, wait states, counters,
+//! interrupt polls and bus hook calls included.
+//!
+//! A block is only entered while the bytes it was compiled from are still
+//! there, decoded through the same page map and outside hooked pages. A
+//! write to a page holding compiled code ends the block after the writing
+//! instruction and drops every block on that page, so self-modifying code
+//! is recompiled before it runs again:
+//!
+//! ```no_run
+//! # use emulator::Vm;
+//! let mut vm = Vm::new();
+//! vm.enable_jit().unwrap();
+//! vm.run_frame().unwrap();
+//! let stats = vm.jit_stats().unwrap();
+//! println!("{} blocks, {} invalidated", stats.compiled, stats.invalidated);
+//! ```
+
+use std::collections::HashMap;
+use std::ffi::c_int;
+use std::fmt;
+use std::mem::offset_of;
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::types::{I8, I16, I32, I64};
+use cranelift_codegen::ir::{
+    self, AbiParam, InstBuilder, MemFlagsData, SigRef, Signature, Type, Value,
+};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{Module, default_libcall_names};
+
+use crate::backend::{Backend, kernel};
+use crate::disasm::{instruction_size, lookup};
+use crate::ffi::{
+    AddressingMode, Cpu, CpuStats, FLAG_C, FLAG_D, FLAG_I, FLAG_N, FLAG_V, FLAG_Z, INT_IRQ,
+    INT_IRQ_MASKED, INT_NMI, INT_NMI_LEVEL, RVM_OK,
+};
+use crate::vm::Vm;
+
+/// Instructions compiled into one block at most.
+const MAX_INSTRUCTIONS: usize = 32;
+
+/// Blocks compiled into one code module before it is thrown away with
+/// every block in it, bounding the memory stale blocks hold.
+const MAX_COMPILED: usize = 4096;
+
+/// Why the recompiler could not start.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JitError(String);
+
+impl fmt::Display for JitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cannot compile for this host: {}", self.0)
+    }
+}
+
+impl std::error::Error for JitError {}
+
+/// Counters kept by the recompiler since [`Vm::enable_jit`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JitStats {
+    /// Blocks compiled, recompilations included.
+    pub compiled: u64,
+    /// Blocks dropped because their code was written or changed.
+    pub invalidated: u64,
+    /// Blocks entered.
+    pub entered: u64,
+}
+
+/// A compiled block: `cpu`, the [`Runtime`], the cycle counter at the start
+/// of the batch and its budget. It runs until the end of the block or
+/// until the batch has to stop, leaving the CPU as `cpu_step` would.
+type BlockFn = unsafe extern "C" fn(*mut Cpu, *mut Runtime, u32, u32);
+
+/// What the helpers compiled code calls need.
+struct Runtime {
+    backend: Backend,
+    /// Blocks compiled from each physical page.
+    code_pages: [u16; 256],
+    /// Pages holding compiled code that were written since the last block
+    /// returned.
+    written: [bool; 256],
+}
+
+/// A compiled block and what it was compiled from.
+struct Block {
+    func: BlockFn,
+    /// Where each byte the block was compiled from lives and what it was.
+    source: Vec<(u16, u8)>,
+    /// The physical pages of `source`, once each.
+    pages: Vec<u8>,
+}
+
+impl Block {
+    /// Whether the block still stands for the code at `pc`.
+    fn matches(&self, cpu: &Cpu, pc: u16) -> bool {
+        self.source.iter().enumerate().all(|(i, &(addr, byte))| {
+            let logical = pc.wrapping_add(i as u16);
+            decode(cpu, logical) == addr
+                && cpu.hook_pages[addr as usize >> 8] == 0
+                // SAFETY: `memory` spans every 16-bit address.
+                && unsafe { *cpu.memory.add(addr as usize) } == byte
+        })
+    }
+}
+
+/// The recompiler state of a [`Vm`].
+pub(crate) struct Jit {
+    module: Option<JITModule>,
+    builder: FunctionBuilderContext,
+    /// Blocks compiled into `module`, stale ones included.
+    in_module: usize,
+    blocks: HashMap<u16, Block>,
+    runtime: Box<Runtime>,
+    stats: JitStats,
+}
+
+/// A code module for the host, set to compile quickly rather than well:
+/// blocks are short and compiled while the machine waits for them.
+fn module() -> Result<JITModule, JitError> {
+    let mut flags = settings::builder();
+    for (name, value) in [
+        ("use_colocated_libcalls", "false"),
+        ("is_pic", "false"),
+        ("enable_verifier", "false"),
+        ("regalloc_algorithm", "single_pass"),
+    ] {
+        flags.set(name, value).expect("Cranelift has the setting");
+    }
+    let isa = cranelift_native::builder().map_err(|err| JitError(err.into()))?;
+    let isa = isa
+        .finish(settings::Flags::new(flags))
+        .map_err(|err| JitError(err.to_string()))?;
+    Ok(JITModule::new(JITBuilder::with_isa(
+        isa,
+        default_libcall_names(),
+    )))
+}
+
+impl Jit {
+    fn new() -> Result<Self, JitError> {
+        Ok(Self {
+            module: Some(module()?),
+            builder: FunctionBuilderContext::new(),
+            in_module: 0,
+            blocks: HashMap::new(),
+            runtime: Box::new(Runtime {
+                backend: Backend::DEFAULT,
+                code_pages: [0; 256],
+                written: [false; 256],
+            }),
+            stats: JitStats::default(),
+        })
+    }
+
+    /// Runs like `cpu_run`, through compiled blocks where it can.
+    pub(crate) fn run(&mut self, cpu: &mut Cpu, backend: Backend, budget: u32) -> c_int {
+        let start = cpu.cycles;
+        cpu.bus_claimed = 0;
+        self.runtime.backend = backend;
+        while cpu.cycles.wrapping_sub(start) < budget {
+            let block = match cpu.int_state & (INT_NMI | INT_IRQ) {
+                0 => self.block(cpu),
+                _ => None,
+            };
+            match block {
+                Some(func) => {
+                    self.stats.entered += 1;
+                    // SAFETY: the block was compiled against this layout of
+                    // `Cpu` and only touches memory through `cpu.memory`
+                    // and the kernel's helpers.
+                    unsafe { func(cpu, &mut *self.runtime, start, budget) };
+                    self.drop_written();
+                }
+                None => {
+                    // SAFETY: `cpu` was initialized by `cpu_init`.
+                    let status = unsafe { kernel!(backend, cpu_step(cpu)) };
+                    if status != RVM_OK {
+                        return status;
+                    }
+                }
+            }
+            if cpu.bus_claimed != 0 {
+                cpu.bus_claimed = 0;
+                break;
+            }
+        }
+        RVM_OK
+    }
+
+    /// The block at the PC, compiled now if it has to be, or `None` if the
+    /// instruction there is left to the kernel.
+    fn block(&mut self, cpu: &Cpu) -> Option<BlockFn> {
+        let pc = cpu.pc;
+        match self.blocks.get(&pc) {
+            Some(block) if block.matches(cpu, pc) => return Some(block.func),
+            Some(_) => self.remove(pc),
+            None => {}
+        }
+        if self.in_module >= MAX_COMPILED {
+            self.flush();
+        }
+        let block = self.compile(cpu, pc)?;
+        let func = block.func;
+        for &page in &block.pages {
+            self.runtime.code_pages[page as usize] += 1;
+        }
+        self.blocks.insert(pc, block);
+        Some(func)
+    }
+
+    fn remove(&mut self, pc: u16) {
+        if let Some(block) = self.blocks.remove(&pc) {
+            for &page in &block.pages {
+                self.runtime.code_pages[page as usize] -= 1;
+            }
+            self.stats.invalidated += 1;
+        }
+    }
+
+    /// Drops the blocks on the pages the last block wrote to.
+    fn drop_written(&mut self) {
+        let written = std::mem::replace(&mut self.runtime.written, [false; 256]);
+        if !written.contains(&true) {
+            return;
+        }
+        let stale: Vec<u16> = self
+            .blocks
+            .iter()
+            .filter(|(_, block)| block.pages.iter().any(|&page| written[page as usize]))
+            .map(|(&pc, _)| pc)
+            .collect();
+        for pc in stale {
+            self.remove(pc);
+        }
+    }
+
+    /// Throws every block away with the module holding their code.
+    fn flush(&mut self) {
+        self.blocks.clear();
+        self.runtime.code_pages = [0; 256];
+        if let Some(module) = self.module.take() {
+            // SAFETY: no block runs, and none is left to call into the
+            // module.
+            unsafe { module.free_memory() };
+        }
+        self.module = module().ok();
+        self.in_module = 0;
+    }
+
+    /// Compiles the instructions from `pc` up to the first one the block
+    /// cannot hold.
+    fn compile(&mut self, cpu: &Cpu, pc: u16) -> Option<Block> {
+        let mut instructions = Vec::new();
+        let mut source = Vec::new();
+        let mut addr = pc;
+        while instructions.len() < MAX_INSTRUCTIONS {
+            let Some(insn) = Instruction::decode(cpu, addr) else {
+                break;
+            };
+            for i in 0..u16::from(insn.size) {
+                let phys = decode(cpu, addr.wrapping_add(i));
+                // SAFETY: `memory` spans every 16-bit address.
+                source.push((phys, unsafe { *cpu.memory.add(phys as usize) }));
+            }
+            addr = addr.wrapping_add(u16::from(insn.size));
+            instructions.push(insn);
+        }
+        if instructions.is_empty() {
+            return None;
+        }
+        let mut pages: Vec<u8> = source.iter().map(|&(addr, _)| (addr >> 8) as u8).collect();
+        pages.sort_unstable();
+        pages.dedup();
+
+        let module = self.module.as_mut()?;
+        let mut ctx = module.make_context();
+        ctx.func.signature = block_signature(module);
+        let id = module
+            .declare_anonymous_function(&ctx.func.signature)
+            .ok()?;
+        {
+            let builder = FunctionBuilder::new(&mut ctx.func, &mut self.builder);
+            let mut emitter = Emitter::new(builder, module);
+            for (i, insn) in instructions.iter().enumerate() {
+                emitter.instruction(insn, i + 1 == instructions.len());
+            }
+            emitter.finish(module);
+        }
+        let defined = module.define_function(id, &mut ctx);
+        module.clear_context(&mut ctx);
+        defined.ok()?;
+        module.finalize_definitions().ok()?;
+        self.in_module += 1;
+        self.stats.compiled += 1;
+        let code = module.get_finalized_function(id);
+        // SAFETY: the function was compiled with `block_signature`, the
+        // signature of `BlockFn`.
+        let func = unsafe { std::mem::transmute::<*const u8, BlockFn>(code) };
+        Some(Block {
+            func,
+            source,
+            pages,
+        })
+    }
+}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // SAFETY: dropping the recompiler drops every block with it.
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+fn decode(cpu: &Cpu, addr: u16) -> u16 {
+    (u16::from(cpu.page_map[addr as usize >> 8]) << 8) | (addr & 0xFF)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Lda,
+    Ldx,
+    Ldy,
+    Lsr,
+    Adc,
+    Sbc,
+    Sed,
+    Cld,
+    Cli,
+    Sei,
+}
+
+/// A decoded instruction the recompiler handles.
+struct Instruction {
+    addr: u16,
+    opcode: u8,
+    op: Op,
+    mode: AddressingMode,
+    size: u8,
+    /// The operand bytes, little-endian.
+    operand: u16,
+    /// The physical page of each byte fetched, opcode first.
+    fetches: Vec<u8>,
+}
+
+impl Instruction {
+    /// Decodes the instruction at `addr`, or `None` if it is undocumented
+    /// or any of its bytes lies in a hooked page.
+    fn decode(cpu: &Cpu, addr: u16) -> Option<Self> {
+        // SAFETY: `memory` spans every 16-bit address.
+        let byte = |addr: u16| unsafe { *cpu.memory.add(decode(cpu, addr) as usize) };
+        let opcode = byte(addr);
+        let desc = lookup(opcode)?;
+        let op = match desc.mnemonic {
+            "LDA" => Op::Lda,
+            "LDX" => Op::Ldx,
+            "LDY" => Op::Ldy,
+            "LSR" => Op::Lsr,
+            "ADC" => Op::Adc,
+            "SBC" => Op::Sbc,
+            "SED" => Op::Sed,
+            "CLD" => Op::Cld,
+            "CLI" => Op::Cli,
+            "SEI" => Op::Sei,
+            _ => return None,
+        };
+        let size = instruction_size(desc.mode);
+        let mut fetches = Vec::with_capacity(size as usize);
+        for i in 0..u16::from(size) {
+            let page = (decode(cpu, addr.wrapping_add(i)) >> 8) as u8;
+            if cpu.hook_pages[page as usize] != 0 {
+                return None;
+            }
+            fetches.push(page);
+        }
+        let operand = match size {
+            2 => u16::from(byte(addr.wrapping_add(1))),
+            3 => u16::from_le_bytes([byte(addr.wrapping_add(1)), byte(addr.wrapping_add(2))]),
+            _ => 0,
+        };
+        Some(Self {
+            addr,
+            opcode,
+            op,
+            mode: desc.mode,
+            size,
+            operand,
+            fetches,
+        })
+    }
+
+    /// The address past the instruction, where the PC is while it
+    /// accesses memory.
+    fn next(&self) -> u16 {
+        self.addr.wrapping_add(u16::from(self.size))
+    }
+
+    /// The cycles the kernel's handler returns, before any page-crossing
+    /// penalty.
+    fn cycles(&self) -> i64 {
+        use AddressingMode::*;
+        match (self.op, self.mode) {
+            (Op::Adc, _) => 2,
+            (Op::Lsr, Accumulator) => 2,
+            (Op::Lsr, ZeroPage) => 5,
+            (Op::Lsr, ZeroPageX | Absolute) => 6,
+            (Op::Lsr, AbsoluteX) => 7,
+            (Op::Ldx | Op::Ldy, Absolute) => 3,
+            (Op::Lda, IndirectX) => 6,
+            (Op::Lda, IndirectY) => 5,
+            (_, Immediate | Implied) => 2,
+            (_, ZeroPage) => 3,
+            (_, Absolute | ZeroPageX | ZeroPageY | AbsoluteX | AbsoluteY) => 4,
+            _ => 2,
+        }
+    }
+}
+
+fn block_signature(module: &JITModule) -> Signature {
+    let ptr = module.target_config().pointer_type();
+    let mut sig = module.make_signature();
+    sig.params.extend([ptr, ptr, I32, I32].map(AbiParam::new));
+    sig
+}
+
+const A: i32 = offset_of!(Cpu, a) as i32;
+const X: i32 = offset_of!(Cpu, x) as i32;
+const Y: i32 = offset_of!(Cpu, y) as i32;
+const PC: i32 = offset_of!(Cpu, pc) as i32;
+const FLAGS: i32 = offset_of!(Cpu, flags) as i32;
+const CYCLES: i32 = offset_of!(Cpu, cycles) as i32;
+const WAIT_PAGES: i32 = offset_of!(Cpu, wait_pages) as i32;
+const IRQ: i32 = offset_of!(Cpu, irq) as i32;
+const NMI: i32 = offset_of!(Cpu, nmi) as i32;
+const INT_STATE: i32 = offset_of!(Cpu, int_state) as i32;
+const BUS_CLAIMED: i32 = offset_of!(Cpu, bus_claimed) as i32;
+const STATS: i32 = offset_of!(Cpu, stats) as i32;
+const STATS_OPCODES: i32 = STATS + offset_of!(CpuStats, opcodes) as i32;
+const STATS_CYCLES: i32 = STATS + offset_of!(CpuStats, cycles) as i32;
+const STATS_READS: i32 = STATS + offset_of!(CpuStats, reads) as i32;
+
+/// Builds the IR of one block, keeping the registers and the cycle counter
+/// in variables and storing them back to the `Cpu` before every helper
+/// call and on the way out.
+struct Emitter<'a> {
+    b: FunctionBuilder<'a>,
+    ptr: Type,
+    cpu: Value,
+    runtime: Value,
+    start: Value,
+    budget: Value,
+    a: Variable,
+    x: Variable,
+    y: Variable,
+    flags: Variable,
+    cycles: Variable,
+    /// Stores the variables back and returns, with the PC to leave.
+    exit: ir::Block,
+    read: SigRef,
+    write: SigRef,
+    arith: SigRef,
+}
+
+impl<'a> Emitter<'a> {
+    fn new(mut b: FunctionBuilder<'a>, module: &JITModule) -> Self {
+        let ptr = module.target_config().pointer_type();
+        let entry = b.create_block();
+        b.append_block_params_for_function_params(entry);
+        b.switch_to_block(entry);
+        let &[cpu, runtime, start, budget] = b.block_params(entry) else {
+            unreachable!("the block signature has four parameters");
+        };
+        let mut sig = |params: &[Type]| {
+            let mut sig = module.make_signature();
+            sig.params.extend(params.iter().copied().map(AbiParam::new));
+            sig.returns.push(AbiParam::new(I32));
+            b.import_signature(sig)
+        };
+        let read = sig(&[ptr, ptr, I32]);
+        let write = sig(&[ptr, ptr, I32, I32]);
+        let arith = sig(&[I32, I32, I32]);
+        let exit = b.create_block();
+        b.append_block_param(exit, I32);
+        let [a, x, y, flags, cycles] = [(); 5].map(|_| b.declare_var(I32));
+        let mut emitter = Self {
+            b,
+            ptr,
+            cpu,
+            runtime,
+            start,
+            budget,
+            a,
+            x,
+            y,
+            flags,
+            cycles,
+            exit,
+            read,
+            write,
+            arith,
+        };
+        for (var, offset) in [(a, A), (x, X), (y, Y), (flags, FLAGS)] {
+            let value = emitter.load8(offset);
+            emitter.b.def_var(var, value);
+        }
+        let value = emitter.load32(CYCLES);
+        emitter.b.def_var(cycles, value);
+        emitter
+    }
+
+    fn load8(&mut self, offset: i32) -> Value {
+        let value = self
+            .b
+            .ins()
+            .load(I8, MemFlagsData::trusted(), self.cpu, offset);
+        self.b.ins().uextend(I32, value)
+    }
+
+    fn store8(&mut self, offset: i32, value: Value) {
+        let value = self.b.ins().ireduce(I8, value);
+        self.b
+            .ins()
+            .store(MemFlagsData::trusted(), value, self.cpu, offset);
+    }
+
+    fn load32(&mut self, offset: i32) -> Value {
+        self.b
+            .ins()
+            .load(I32, MemFlagsData::trusted(), self.cpu, offset)
+    }
+
+    /// Adds `value`, an `I64`, to a counter in `stats`.
+    fn count(&mut self, offset: i32, value: Value) {
+        let old = self
+            .b
+            .ins()
+            .load(I64, MemFlagsData::trusted(), self.cpu, offset);
+        let new = self.b.ins().iadd(old, value);
+        self.b
+            .ins()
+            .store(MemFlagsData::trusted(), new, self.cpu, offset);
+    }
+
+    fn add_cycles(&mut self, value: Value) {
+        let cycles = self.b.use_var(self.cycles);
+        let cycles = self.b.ins().iadd(cycles, value);
+        self.b.def_var(self.cycles, cycles);
+    }
+
+    /// Stores the variables back, with `pc` as the PC.
+    fn flush(&mut self, pc: Value) {
+        for (var, offset) in [(self.a, A), (self.x, X), (self.y, Y), (self.flags, FLAGS)] {
+            let value = self.b.use_var(var);
+            self.store8(offset, value);
+        }
+        let cycles = self.b.use_var(self.cycles);
+        self.b
+            .ins()
+            .store(MemFlagsData::trusted(), cycles, self.cpu, CYCLES);
+        let pc = self.b.ins().ireduce(I16, pc);
+        self.b
+            .ins()
+            .store(MemFlagsData::trusted(), pc, self.cpu, PC);
+    }
+
+    /// Calls a helper taking the runtime and the CPU first, with the state
+    /// flushed and the PC at `pc`, picking up the wait states it charged.
+    fn call(&mut self, sig: SigRef, helper: *const (), pc: u16, args: &[Value]) -> Value {
+        let pc = self.b.ins().iconst(I32, i64::from(pc));
+        self.flush(pc);
+        let callee = self.b.ins().iconst(self.ptr, helper as i64);
+        let mut all = vec![self.runtime, self.cpu];
+        all.extend_from_slice(args);
+        let call = self.b.ins().call_indirect(sig, callee, &all);
+        let result = self.b.inst_results(call)[0];
+        let cycles = self.load32(CYCLES);
+        self.b.def_var(self.cycles, cycles);
+        result
+    }
+
+    fn read(&mut self, pc: u16, addr: Value) -> Value {
+        self.call(self.read, jit_read as *const (), pc, &[addr])
+    }
+
+    fn const32(&mut self, value: u16) -> Value {
+        self.b.ins().iconst(I32, i64::from(value))
+    }
+
+    /// Reads the zero-page pointer at `ptr`, wrapping within the page.
+    fn pointer(&mut self, pc: u16, ptr: Value) -> Value {
+        let lo = self.read(pc, ptr);
+        let next = self.b.ins().iadd_imm_u(ptr, 1);
+        let next = self.b.ins().band_imm_u(next, 0xFF);
+        let hi = self.read(pc, next);
+        let hi = self.b.ins().ishl_imm_u(hi, 8);
+        self.b.ins().bor(lo, hi)
+    }
+
+    /// `base + index` and whether it crossed a page, as 0 or 1.
+    fn indexed(&mut self, base: Value, index: Variable) -> (Value, Value) {
+        let index = self.b.use_var(index);
+        let sum = self.b.ins().iadd(base, index);
+        let addr = self.b.ins().band_imm_u(sum, 0xFFFF);
+        let pages = self.b.ins().bxor(addr, base);
+        let pages = self.b.ins().band_imm_u(pages, 0xFF00);
+        let crossed = self.b.ins().icmp_imm_u(IntCC::NotEqual, pages, 0);
+        let crossed = self.b.ins().uextend(I32, crossed);
+        (addr, crossed)
+    }
+
+    /// The effective address of a memory operand and the page-crossing
+    /// penalty, if the mode has one.
+    fn address(&mut self, insn: &Instruction) -> (Value, Option<Value>) {
+        let pc = insn.next();
+        let operand = self.const32(insn.operand);
+        match insn.mode {
+            AddressingMode::ZeroPageX | AddressingMode::ZeroPageY => {
+                let index = match insn.mode {
+                    AddressingMode::ZeroPageX => self.x,
+                    _ => self.y,
+                };
+                let index = self.b.use_var(index);
+                let sum = self.b.ins().iadd(operand, index);
+                (self.b.ins().band_imm_u(sum, 0xFF), None)
+            }
+            AddressingMode::AbsoluteX => {
+                let (addr, crossed) = self.indexed(operand, self.x);
+                (addr, Some(crossed))
+            }
+            AddressingMode::AbsoluteY => {
+                let (addr, crossed) = self.indexed(operand, self.y);
+                (addr, Some(crossed))
+            }
+            AddressingMode::IndirectX => {
+                let x = self.b.use_var(self.x);
+                let ptr = self.b.ins().iadd(operand, x);
+                let ptr = self.b.ins().band_imm_u(ptr, 0xFF);
+                (self.pointer(pc, ptr), None)
+            }
+            AddressingMode::IndirectY => {
+                let base = self.pointer(pc, operand);
+                let (addr, crossed) = self.indexed(base, self.y);
+                (addr, Some(crossed))
+            }
+            _ => (operand, None),
+        }
+    }
+
+    /// The operand's value, read through the bus unless it is immediate.
+    fn value(&mut self, insn: &Instruction) -> (Value, Option<Value>) {
+        if insn.mode == AddressingMode::Immediate {
+            return (self.const32(insn.operand), None);
+        }
+        let (addr, penalty) = self.address(insn);
+        (self.read(insn.next(), addr), penalty)
+    }
+
+    /// Sets Z and N from `value`.
+    fn set_zn(&mut self, value: Value) {
+        let flags = self.b.use_var(self.flags);
+        let flags = self
+            .b
+            .ins()
+            .band_imm_u(flags, i64::from(!(FLAG_Z | FLAG_N)));
+        let zero = self.b.ins().icmp_imm_u(IntCC::Equal, value, 0);
+        let zero = self.b.ins().uextend(I32, zero);
+        let zero = self
+            .b
+            .ins()
+            .ishl_imm_u(zero, i64::from(FLAG_Z.trailing_zeros()));
+        let negative = self.b.ins().band_imm_u(value, i64::from(FLAG_N));
+        let flags = self.b.ins().bor(flags, zero);
+        let flags = self.b.ins().bor(flags, negative);
+        self.b.def_var(self.flags, flags);
+    }
+
+    /// Shifts `value` right, setting C, Z and N like `lsr_op`.
+    fn lsr(&mut self, value: Value) -> Value {
+        let carry = self.b.ins().band_imm_u(value, 1);
+        let result = self.b.ins().ushr_imm_u(value, 1);
+        self.set_zn(result);
+        let flags = self.b.use_var(self.flags);
+        let flags = self.b.ins().band_imm_u(flags, i64::from(!FLAG_C));
+        let flags = self.b.ins().bor(flags, carry);
+        self.b.def_var(self.flags, flags);
+        result
+    }
+
+    fn set_flags(&mut self, set: u8, on: bool) {
+        let flags = self.b.use_var(self.flags);
+        let flags = match on {
+            true => self.b.ins().bor_imm_u(flags, i64::from(set)),
+            false => self.b.ins().band_imm_u(flags, i64::from(!set)),
+        };
+        self.b.def_var(self.flags, flags);
+    }
+
+    /// Emits one instruction the way `step` executes it and the exit test
+    /// after it; the last instruction always exits.
+    fn instruction(&mut self, insn: &Instruction, last: bool) {
+        let flags_before = self.b.use_var(self.flags);
+        let cycles_before = self.b.use_var(self.cycles);
+        for &page in &insn.fetches {
+            let wait = self.load8(WAIT_PAGES + i32::from(page));
+            self.add_cycles(wait);
+        }
+        let fetches = self.b.ins().iconst(I64, i64::from(insn.size));
+        self.count(STATS_READS, fetches);
+
+        let mut penalty = None;
+        let mut wrote = None;
+        let memory = !matches!(
+            insn.mode,
+            AddressingMode::Immediate | AddressingMode::Implied | AddressingMode::Accumulator
+        );
+        match insn.op {
+            Op::Lda | Op::Ldx | Op::Ldy => {
+                let (value, crossed) = self.value(insn);
+                penalty = crossed;
+                match insn.op {
+                    Op::Lda => {
+                        self.b.def_var(self.a, value);
+                        self.set_zn(value);
+                    }
+                    Op::Ldx => self.b.def_var(self.x, value),
+                    _ => self.b.def_var(self.y, value),
+                }
+            }
+            Op::Lsr if insn.mode == AddressingMode::Accumulator => {
+                let a = self.b.use_var(self.a);
+                let a = self.lsr(a);
+                self.b.def_var(self.a, a);
+            }
+            Op::Lsr => {
+                let (addr, crossed) = self.address(insn);
+                penalty = crossed;
+                let value = self.read(insn.next(), addr);
+                let value = self.lsr(value);
+                wrote = Some(self.call(
+                    self.write,
+                    jit_write as *const (),
+                    insn.next(),
+                    &[addr, value],
+                ));
+            }
+            Op::Adc | Op::Sbc => {
+                let (value, _) = self.value(insn);
+                let helper = match insn.op {
+                    Op::Adc => jit_adc as *const (),
+                    _ => jit_sbc as *const (),
+                };
+                let a = self.b.use_var(self.a);
+                let flags = self.b.use_var(self.flags);
+                let callee = self.b.ins().iconst(self.ptr, helper as i64);
+                let call = self
+                    .b
+                    .ins()
+                    .call_indirect(self.arith, callee, &[a, value, flags]);
+                let result = self.b.inst_results(call)[0];
+                let a = self.b.ins().band_imm_u(result, 0xFF);
+                let flags = self.b.ins().ushr_imm_u(result, 8);
+                self.b.def_var(self.a, a);
+                self.b.def_var(self.flags, flags);
+            }
+            Op::Sed => self.set_flags(FLAG_D, true),
+            Op::Cld => self.set_flags(FLAG_D, false),
+            Op::Cli => self.set_flags(FLAG_I, false),
+            Op::Sei => self.set_flags(FLAG_I, true),
+        }
+
+        let used = self.b.ins().iconst(I32, insn.cycles());
+        self.add_cycles(used);
+        if let Some(penalty) = penalty {
+            self.add_cycles(penalty);
+        }
+        let one = self.b.ins().iconst(I64, 1);
+        self.count(STATS_OPCODES + 8 * i32::from(insn.opcode), one);
+        let cycles = self.b.use_var(self.cycles);
+        let elapsed = self.b.ins().isub(cycles, cycles_before);
+        let elapsed = self.b.ins().uextend(I64, elapsed);
+        self.count(STATS_CYCLES, elapsed);
+        let pending = self.poll(flags_before);
+
+        let next = self.const32(insn.next());
+        if last {
+            self.b.ins().jump(self.exit, &[next.into()]);
+            return;
+        }
+        let ran = self.b.ins().isub(cycles, self.start);
+        let mut stop = self
+            .b
+            .ins()
+            .icmp(IntCC::UnsignedGreaterThanOrEqual, ran, self.budget);
+        stop = self.b.ins().bor(stop, pending);
+        if memory {
+            let claimed = self.load8(BUS_CLAIMED);
+            let claimed = self.b.ins().icmp_imm_u(IntCC::NotEqual, claimed, 0);
+            stop = self.b.ins().bor(stop, claimed);
+        }
+        if let Some(wrote) = wrote {
+            let wrote = self.b.ins().icmp_imm_u(IntCC::NotEqual, wrote, 0);
+            stop = self.b.ins().bor(stop, wrote);
+        }
+        let next_block = self.b.create_block();
+        self.b
+            .ins()
+            .brif(stop, self.exit, &[next.into()], next_block, &[]);
+        self.b.switch_to_block(next_block);
+    }
+
+    /// Samples the interrupt lines like `poll_interrupts`, returning
+    /// whether an interrupt is now latched.
+    fn poll(&mut self, flags: Value) -> Value {
+        let int_state = self.load8(INT_STATE);
+        let nmi = self.load8(NMI);
+        let irq = self.load8(IRQ);
+        let zero = self.b.ins().iconst(I32, 0);
+        let state = self.b.ins().band_imm_u(int_state, i64::from(INT_NMI));
+
+        let nmi = self.b.ins().icmp_imm_u(IntCC::NotEqual, nmi, 0);
+        let level = self.b.ins().band_imm_u(int_state, i64::from(INT_NMI_LEVEL));
+        let edge = self.b.ins().icmp_imm_u(IntCC::Equal, level, 0);
+        let edge = self.b.ins().band(nmi, edge);
+        let latched = self.b.ins().iconst(I32, i64::from(INT_NMI));
+        let latched = self.b.ins().select(edge, latched, zero);
+        let level = self.b.ins().iconst(I32, i64::from(INT_NMI_LEVEL));
+        let level = self.b.ins().select(nmi, level, zero);
+
+        let masked = self.b.ins().band_imm_u(flags, i64::from(FLAG_I));
+        let masked = self.b.ins().icmp_imm_u(IntCC::NotEqual, masked, 0);
+        let irq = self.b.ins().icmp_imm_u(IntCC::NotEqual, irq, 0);
+        let taken = self.b.ins().iconst(I32, i64::from(INT_IRQ));
+        let taken = self.b.ins().select(irq, taken, zero);
+        let held = self.b.ins().iconst(I32, i64::from(INT_IRQ_MASKED));
+        let irq = self.b.ins().select(masked, held, taken);
+
+        let state = self.b.ins().bor(state, latched);
+        let state = self.b.ins().bor(state, level);
+        let state = self.b.ins().bor(state, irq);
+        self.store8(INT_STATE, state);
+        let pending = self.b.ins().band_imm_u(state, i64::from(INT_NMI | INT_IRQ));
+        self.b.ins().icmp_imm_u(IntCC::NotEqual, pending, 0)
+    }
+
+    fn finish(mut self, module: &JITModule) {
+        self.b.switch_to_block(self.exit);
+        let pc = self.b.block_params(self.exit)[0];
+        self.flush(pc);
+        self.b.ins().return_(&[]);
+        self.b.seal_all_blocks();
+        self.b.finalize(module.target_config());
+    }
+}
+
+/// Reads through the backend's `mem_read`, as the kernel's handlers do.
+extern "C" fn jit_read(runtime: *mut Runtime, cpu: *mut Cpu, addr: u32) -> u32 {
+    // SAFETY: compiled code passes the runtime and CPU it was called with.
+    unsafe { u32::from(kernel!((*runtime).backend, mem_read(cpu, addr as u16))) }
+}
+
+/// Writes through the backend's `mem_write`, returning non-zero if the
+/// write landed on a page holding compiled code.
+extern "C" fn jit_write(runtime: *mut Runtime, cpu: *mut Cpu, addr: u32, val: u32) -> u32 {
+    // SAFETY: see `jit_read`.
+    unsafe {
+        let runtime = &mut *runtime;
+        kernel!(runtime.backend, mem_write(cpu, addr as u16, val as u8));
+        let page = decode(&*cpu, addr as u16) as usize >> 8;
+        if runtime.code_pages[page] == 0 {
+            return 0;
+        }
+        runtime.written[page] = true;
+        1
+    }
+}
+
+fn set(flags: &mut u8, flag: u8, on: bool) {
+    if on {
+        *flags |= flag;
+    } else {
+        *flags &= !flag;
+    }
+}
+
+/// `adc_op` on A and the flags, returning them as `flags << 8 | a`.
+extern "C" fn jit_adc(a: u32, value: u32, flags: u32) -> u32 {
+    let (a, value, mut flags) = (a as u8, value as u8, flags as u8);
+    let carry_in = u16::from(flags & FLAG_C != 0);
+    let result_16 = a as u16 + value as u16 + carry_in;
+    let final_result = result_16 as u8;
+
+    set(&mut flags, FLAG_Z, final_result == 0);
+    let result = if flags & FLAG_D == 0 {
+        set(&mut flags, FLAG_N, final_result & 0x80 != 0);
+        set(
+            &mut flags,
+            FLAG_V,
+            !(a ^ value) & (a ^ final_result) & 0x80 != 0,
+        );
+        set(&mut flags, FLAG_C, result_16 & 0x100 != 0);
+        final_result
+    } else {
+        let mut low = (a & 0x0F) as i32 + (value & 0x0F) as i32 + carry_in as i32;
+        if low >= 0x0A {
+            low = ((low + 0x06) & 0x0F) + 0x10;
+        }
+        let mut sum = (a & 0xF0) as i32 + (value & 0xF0) as i32 + low;
+        let signed_sum = (a & 0xF0) as i8 as i32 + (value & 0xF0) as i8 as i32 + low;
+        set(&mut flags, FLAG_N, sum & 0x80 != 0);
+        set(&mut flags, FLAG_V, !(-128..=127).contains(&signed_sum));
+        if sum >= 0xA0 {
+            sum += 0x60;
+        }
+        set(&mut flags, FLAG_C, sum >= 0x100);
+        sum as u8
+    };
+    u32::from(flags) << 8 | u32::from(result)
+}
+
+/// `sbc_op` on A and the flags, returning them as `flags << 8 | a`.
+extern "C" fn jit_sbc(a: u32, value: u32, flags: u32) -> u32 {
+    let (a, value, mut flags) = (a as u8, value as u8, flags as u8);
+    let carry_in = u16::from(flags & FLAG_C != 0);
+    let result_16 = a as u16 + (value ^ 0xFF) as u16 + carry_in;
+    let mut final_result = result_16 as u8;
+
+    set(&mut flags, FLAG_Z, final_result == 0);
+    set(&mut flags, FLAG_N, final_result & 0x80 != 0);
+    set(
+        &mut flags,
+        FLAG_V,
+        (a ^ value) & (a ^ final_result) & 0x80 != 0,
+    );
+    set(&mut flags, FLAG_C, result_16 & 0x100 != 0);
+
+    if flags & FLAG_D != 0 {
+        let mut low = (a & 0x0F) as i32 - (value & 0x0F) as i32 + carry_in as i32 - 1;
+        if low < 0 {
+            low = ((low - 0x06) & 0x0F) - 0x10;
+        }
+        let mut difference = (a & 0xF0) as i32 - (value & 0xF0) as i32 + low;
+        if difference < 0 {
+            difference -= 0x60;
+        }
+        final_result = difference as u8;
+    }
+    u32::from(flags) << 8 | u32::from(final_result)
+}
+
+impl Vm {
+    /// Runs batches through compiled blocks from now on. Stepping, and
+    /// everything that makes the machine step, is unaffected.
+    pub fn enable_jit(&mut self) -> Result<(), JitError> {
+        if self.jit.is_none() {
+            self.jit = Some(Box::new(Jit::new()?));
+        }
+        Ok(())
+    }
+
+    /// Goes back to running batches on the kernel, dropping every compiled
+    /// block.
+    pub fn disable_jit(&mut self) {
+        self.jit = None;
+    }
+
+    /// The recompiler's counters, if it is enabled.
+    pub fn jit_stats(&self) -> Option<JitStats> {
+        self.jit.as_ref().map(|jit| jit.stats)
+    }
+}
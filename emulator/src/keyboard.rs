@@ -0,0 +1,386 @@
+//! Keyboard.
+//!
+//! A [`Keyboard`] queues key events from the host for programs that want
+//! text, such as monitors and BASIC interpreters. By default it queues the
+//! characters the keys type: letters come out lowercase, or uppercase with
+//! [`Key::Shift`] held, and [`Key::Control`] with a letter gives the
+//! control code, so Control-C reads as `$03`. In raw mode it queues PS/2
+//! scan codes (set 1, without the `$E0` prefixes): a key's
+//! [`Key::scancode`] when it goes down and the same code with bit 7 set
+//! when it comes up. It is not mapped by default:
+//!
+//! ```
+//! # use emulator::{keyboard::{Keyboard, KEYBOARD, KEYBOARD_PORTS}, BusDevice, Vm};
+//! let mut vm = Vm::new();
+//! vm.bus_mut().map(KEYBOARD_PORTS, Keyboard::new()).unwrap();
+//! vm.type_text("10 PRINT \"HI\"\n");
+//! let keyboard = vm.bus_mut().device_mut::<Keyboard>(KEYBOARD).unwrap();
+//! assert_eq!(keyboard.read8(0), b'1');
+//! ```
+//!
+//! | Offset | Register                                                      |
+//! | ------ | ------------------------------------------------------------- |
+//! | 0      | `DATA`: reading takes the next queued byte (0 if none)        |
+//! | 1      | `STATUS`: [`STATUS_READY`], [`STATUS_OVERFLOW`], [`STATUS_SHIFT`], [`STATUS_CONTROL`] |
+//! | 2      | `CTRL`: [`CTRL_IRQ`], [`CTRL_RAW`]                            |
+//!
+//! The queue holds [`KEYBOARD_DEPTH`] bytes; events arriving while it is
+//! full are dropped and set [`STATUS_OVERFLOW`] until `STATUS` is next
+//! read. Writing `CTRL` with a different [`CTRL_RAW`] empties the queue, so
+//! characters and scan codes never mix. When [`CTRL_IRQ`] is set the
+//! keyboard holds interrupt line [`KEYBOARD_IRQ`] while the queue is not
+//! empty.
+//!
+//! Keys that type nothing, such as the arrows, only show in raw mode. Key
+//! events are part of an [input recording](crate::replay), and the keys
+//! held and the queue are saved in a [`Snapshot`](crate::Snapshot).
+
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+
+use crate::bus::BusDevice;
+use crate::replay::InputEvent;
+use crate::reset::ResetKind;
+use crate::vm::Vm;
+
+/// Suggested place for the keyboard registers.
+pub const KEYBOARD_PORTS: RangeInclusive<u16> = 0x2760..=0x2763;
+/// Address of the keyboard registers that [`Vm::key_down`] and friends use.
+pub const KEYBOARD: u16 = *KEYBOARD_PORTS.start();
+/// Interrupt line raised while the queue is not empty.
+pub const KEYBOARD_IRQ: u8 = 5;
+/// Bytes the queue holds.
+pub const KEYBOARD_DEPTH: usize = 16;
+
+/// `STATUS` bit set while a byte is queued.
+pub const STATUS_READY: u8 = 1 << 0;
+/// `STATUS` bit set when an event was dropped since `STATUS` was last read.
+pub const STATUS_OVERFLOW: u8 = 1 << 1;
+/// `STATUS` bit set while [`Key::Shift`] is held.
+pub const STATUS_SHIFT: u8 = 1 << 2;
+/// `STATUS` bit set while [`Key::Control`] is held.
+pub const STATUS_CONTROL: u8 = 1 << 3;
+/// `CTRL` bit enabling the interrupt.
+pub const CTRL_IRQ: u8 = 1 << 0;
+/// `CTRL` bit selecting scan codes instead of characters.
+pub const CTRL_RAW: u8 = 1 << 1;
+
+/// Bit 7 of a scan code, set when the key comes up.
+const RELEASE: u8 = 0x80;
+
+const DATA: u16 = 0;
+const STATUS: u16 = 1;
+const CTRL: u16 = 2;
+
+macro_rules! keys {
+    ($($key:ident = $code:literal, $plain:literal, $shifted:literal;)*) => {
+        /// A key on the keyboard.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum Key {
+            $($key,)*
+        }
+
+        impl Key {
+            /// Every key, in scan code order.
+            pub const ALL: &[Key] = &[$(Key::$key,)*];
+
+            /// The key's PS/2 set 1 make code.
+            pub fn scancode(self) -> u8 {
+                match self {
+                    $(Key::$key => $code,)*
+                }
+            }
+
+            /// The key with make code `code`.
+            pub fn from_scancode(code: u8) -> Option<Key> {
+                match code {
+                    $($code => Some(Key::$key),)*
+                    _ => None,
+                }
+            }
+
+            /// What the key types, unshifted and shifted, 0 for nothing.
+            fn chars(self) -> (u8, u8) {
+                match self {
+                    $(Key::$key => ($plain, $shifted),)*
+                }
+            }
+        }
+    };
+}
+
+keys! {
+    Escape = 0x01, 0x1B, 0x1B;
+    Digit1 = 0x02, b'1', b'!';
+    Digit2 = 0x03, b'2', b'@';
+    Digit3 = 0x04, b'3', b'#';
+    Digit4 = 0x05, b'4', b'$';
+    Digit5 = 0x06, b'5', b'%';
+    Digit6 = 0x07, b'6', b'^';
+    Digit7 = 0x08, b'7', b'&';
+    Digit8 = 0x09, b'8', b'*';
+    Digit9 = 0x0A, b'9', b'(';
+    Digit0 = 0x0B, b'0', b')';
+    Minus = 0x0C, b'-', b'_';
+    Equals = 0x0D, b'=', b'+';
+    Backspace = 0x0E, 0x08, 0x08;
+    Tab = 0x0F, b'\t', b'\t';
+    Q = 0x10, b'q', b'Q';
+    W = 0x11, b'w', b'W';
+    E = 0x12, b'e', b'E';
+    R = 0x13, b'r', b'R';
+    T = 0x14, b't', b'T';
+    Y = 0x15, b'y', b'Y';
+    U = 0x16, b'u', b'U';
+    I = 0x17, b'i', b'I';
+    O = 0x18, b'o', b'O';
+    P = 0x19, b'p', b'P';
+    LeftBracket = 0x1A, b'[', b'{';
+    RightBracket = 0x1B, b']', b'}';
+    Enter = 0x1C, b'\r', b'\r';
+    Control = 0x1D, 0, 0;
+    A = 0x1E, b'a', b'A';
+    S = 0x1F, b's', b'S';
+    D = 0x20, b'd', b'D';
+    F = 0x21, b'f', b'F';
+    G = 0x22, b'g', b'G';
+    H = 0x23, b'h', b'H';
+    J = 0x24, b'j', b'J';
+    K = 0x25, b'k', b'K';
+    L = 0x26, b'l', b'L';
+    Semicolon = 0x27, b';', b':';
+    Quote = 0x28, b'\'', b'"';
+    Backquote = 0x29, b'`', b'~';
+    Shift = 0x2A, 0, 0;
+    Backslash = 0x2B, b'\\', b'|';
+    Z = 0x2C, b'z', b'Z';
+    X = 0x2D, b'x', b'X';
+    C = 0x2E, b'c', b'C';
+    V = 0x2F, b'v', b'V';
+    B = 0x30, b'b', b'B';
+    N = 0x31, b'n', b'N';
+    M = 0x32, b'm', b'M';
+    Comma = 0x33, b',', b'<';
+    Period = 0x34, b'.', b'>';
+    Slash = 0x35, b'/', b'?';
+    Space = 0x39, b' ', b' ';
+    Up = 0x48, 0, 0;
+    Left = 0x4B, 0, 0;
+    Right = 0x4D, 0, 0;
+    Down = 0x50, 0, 0;
+}
+
+impl Key {
+    /// The key typing `c` and whether it needs [`Key::Shift`]. A newline
+    /// types as [`Key::Enter`].
+    pub fn from_char(c: char) -> Option<(Key, bool)> {
+        let byte = match c {
+            '\n' => b'\r',
+            c if c.is_ascii() => c as u8,
+            _ => return None,
+        };
+        Key::ALL.iter().find_map(|&key| match key.chars() {
+            (0, _) => None,
+            (plain, _) if plain == byte => Some((key, false)),
+            (_, shifted) if shifted == byte => Some((key, true)),
+            _ => None,
+        })
+    }
+}
+
+/// The keyboard device.
+#[derive(Debug, Clone, Default)]
+pub struct Keyboard {
+    queue: VecDeque<u8>,
+    /// Held keys, one bit per make code.
+    held: u128,
+    ctrl: u8,
+    overflow: bool,
+}
+
+impl Keyboard {
+    /// A keyboard with nothing queued or held, typing characters.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether `key` is held.
+    pub fn is_held(&self, key: Key) -> bool {
+        self.held & (1 << key.scancode()) != 0
+    }
+
+    /// Puts `key` down, queuing what it types or its make code.
+    pub fn key_down(&mut self, key: Key) {
+        self.held |= 1 << key.scancode();
+        if self.ctrl & CTRL_RAW != 0 {
+            self.push(key.scancode());
+            return;
+        }
+        let (plain, shifted) = key.chars();
+        let byte = if self.is_held(Key::Shift) {
+            shifted
+        } else {
+            plain
+        };
+        match byte {
+            0 => {}
+            b'a'..=b'z' if self.is_held(Key::Control) => self.push(byte - b'a' + 1),
+            b'A'..=b'Z' if self.is_held(Key::Control) => self.push(byte - b'A' + 1),
+            byte => self.push(byte),
+        }
+    }
+
+    /// Lets `key` up, queuing its break code in raw mode.
+    pub fn key_up(&mut self, key: Key) {
+        self.held &= !(1 << key.scancode());
+        if self.ctrl & CTRL_RAW != 0 {
+            self.push(key.scancode() | RELEASE);
+        }
+    }
+
+    /// Bytes queued and not yet read.
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.queue.len() < KEYBOARD_DEPTH {
+            self.queue.push_back(byte);
+        } else {
+            self.overflow = true;
+        }
+    }
+}
+
+impl BusDevice for Keyboard {
+    fn read8(&mut self, offset: u16) -> u8 {
+        match offset {
+            DATA => self.queue.pop_front().unwrap_or(0),
+            STATUS => {
+                let mut status = 0;
+                if !self.queue.is_empty() {
+                    status |= STATUS_READY;
+                }
+                if std::mem::take(&mut self.overflow) {
+                    status |= STATUS_OVERFLOW;
+                }
+                if self.is_held(Key::Shift) {
+                    status |= STATUS_SHIFT;
+                }
+                if self.is_held(Key::Control) {
+                    status |= STATUS_CONTROL;
+                }
+                status
+            }
+            CTRL => self.ctrl,
+            _ => 0,
+        }
+    }
+
+    fn write8(&mut self, offset: u16, val: u8) {
+        if offset == CTRL {
+            if (self.ctrl ^ val) & CTRL_RAW != 0 {
+                self.queue.clear();
+            }
+            self.ctrl = val;
+        }
+    }
+
+    /// Unbounded: the queue only changes when read or fed by the host.
+    fn batch_cycles(&self) -> u32 {
+        u32::MAX
+    }
+
+    /// Writes 0 to the control register and clears the overflow, and on a
+    /// hard reset drops the codes queued. Keys held down stay held.
+    fn reset(&mut self, kind: ResetKind) {
+        self.write8(CTRL, 0);
+        self.overflow = false;
+        if kind == ResetKind::Hard {
+            self.queue.clear();
+        }
+    }
+
+    /// The control register, the overflow, the keys held and the queue.
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&[self.ctrl, self.overflow.into()]);
+        state.extend_from_slice(&self.held.to_le_bytes());
+        state.extend(&self.queue);
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        let Some((&[ctrl, overflow], rest)) = state.split_first_chunk() else {
+            return;
+        };
+        let Some((held, queue)) = rest.split_first_chunk() else {
+            return;
+        };
+        if queue.len() > KEYBOARD_DEPTH {
+            return;
+        }
+        self.ctrl = ctrl;
+        self.overflow = overflow != 0;
+        self.held = u128::from_le_bytes(*held);
+        self.queue = queue.iter().copied().collect();
+    }
+
+    fn irq_lines(&self) -> u8 {
+        if self.ctrl & CTRL_IRQ != 0 && !self.queue.is_empty() {
+            1 << KEYBOARD_IRQ
+        } else {
+            0
+        }
+    }
+}
+
+impl Vm {
+    /// Puts `key` down on the keyboard at [`KEYBOARD`], if one is mapped
+    /// there. Ignored while a [replay](crate::replay) is running.
+    pub fn key_down(&mut self, key: Key) {
+        if self.inputs.is_replaying() {
+            return;
+        }
+        self.inputs.record(InputEvent::KeyDown(key.scancode()));
+        self.apply_key(key.scancode());
+    }
+
+    /// Lets `key` up on the keyboard at [`KEYBOARD`].
+    pub fn key_up(&mut self, key: Key) {
+        if self.inputs.is_replaying() {
+            return;
+        }
+        self.inputs.record(InputEvent::KeyUp(key.scancode()));
+        self.apply_key(key.scancode() | RELEASE);
+    }
+
+    /// Types `text` key by key, holding [`Key::Shift`] where a character
+    /// needs it. Characters no key types are skipped.
+    pub fn type_text(&mut self, text: &str) {
+        for (key, shift) in text.chars().filter_map(Key::from_char) {
+            if shift {
+                self.key_down(Key::Shift);
+            }
+            self.key_down(key);
+            self.key_up(key);
+            if shift {
+                self.key_up(Key::Shift);
+            }
+        }
+    }
+
+    /// Applies a make or break code recorded by [`Vm::key_down`] or
+    /// [`Vm::key_up`].
+    pub(crate) fn apply_key(&mut self, code: u8) {
+        let (Some(key), Some(keyboard)) = (
+            Key::from_scancode(code & !RELEASE),
+            self.bus_mut().device_mut::<Keyboard>(KEYBOARD),
+        ) else {
+            return;
+        };
+        if code & RELEASE != 0 {
+            keyboard.key_up(key);
+        } else {
+            keyboard.key_down(key);
+        }
+    }
+}
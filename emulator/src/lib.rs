@@ -0,0 +1,134 @@
+//! Host side of the rvm-8 emulator.
+//!
+//! The CPU core comes from the C kernel by default, compiled from the copy
+//! of its sources the crate carries in `kernel/`. Enabling the `pure-rust`
+//! feature, or turning off the default `c-kernel` one, swaps in the `cpu`
+//! module, a Rust reimplementation behind the same [`ffi`] functions, for
+//! targets without a C toolchain such as `wasm32-unknown-unknown`; the
+//! `wasm` feature adds browser bindings on top. `rust-core` builds the two
+//! side by side, for [switching](backend) between them at run time. Most
+//! users want [`Vm`], which wraps either core behind a safe API.
+
+#[cfg(all(feature = "difftest", not(c_kernel)))]
+compile_error!("`difftest` compares the Rust core with the C kernel and cannot use `pure-rust`");
+
+#[cfg(feature = "compression")]
+pub mod archive;
+pub mod asm;
+pub mod audio;
+pub mod backend;
+mod blit;
+pub mod bus;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod chrome;
+pub mod clock;
+pub mod compare;
+pub mod config;
+pub mod coverage;
+#[cfg(feature = "dap")]
+pub mod dap;
+#[cfg(rust_core)]
+pub use rvm8_core::cpu;
+pub mod debugger;
+#[cfg(feature = "difftest")]
+pub mod difftest;
+pub mod disasm;
+pub mod display;
+pub mod dma;
+#[cfg(feature = "async")]
+pub mod driver;
+pub mod elf;
+pub mod error;
+pub mod events;
+pub mod extension;
+pub mod ffi;
+pub mod files;
+pub mod flow;
+pub mod fuzz;
+#[cfg(feature = "gdb")]
+pub mod gdb;
+pub mod golden;
+pub mod heatmap;
+pub mod hexfile;
+pub mod history;
+pub mod hooks;
+pub mod hostcall;
+pub mod input;
+#[cfg(feature = "instrument")]
+pub mod instrument;
+pub mod irq;
+#[cfg(feature = "jit")]
+pub mod jit;
+#[cfg(feature = "dap")]
+mod json;
+pub mod keyboard;
+#[cfg(feature = "libretro")]
+pub mod libretro;
+pub mod manifest;
+pub mod mapper;
+pub mod memory;
+pub mod monitor;
+pub mod mpu;
+#[cfg(feature = "netplay")]
+pub mod netplay;
+pub mod pacer;
+pub mod paged;
+pub mod patches;
+pub mod ppu_debug;
+pub mod profile;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "remote")]
+pub mod remote;
+pub mod replay;
+pub mod reset;
+pub mod rewind;
+pub mod rng;
+pub mod rom;
+pub mod rtc;
+pub mod scheduler;
+pub mod screenshot;
+#[cfg(feature = "script")]
+pub mod script;
+pub mod snapshot;
+pub mod snoop;
+pub mod spi;
+pub mod sram;
+pub mod stack;
+pub mod stats;
+pub mod stepping;
+pub mod symbols;
+pub mod testkit;
+pub mod throttle;
+pub mod timer;
+pub mod trace;
+pub mod uart;
+pub mod vcd;
+pub mod vm;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod watches;
+pub mod wav;
+
+pub use bus::{
+    Bus, BusConfig, BusDevice, DmaBus, RomWrite, TimingMode, UnmappedRead, UnmappedWrite,
+};
+pub use config::MachineConfig;
+pub use debugger::{Condition, StopReason, WatchKind};
+pub use error::VmError;
+pub use history::HistoryEntry;
+pub use hooks::HookId;
+pub use input::Button;
+pub use mapper::Mapper;
+pub use paged::PagedSnapshot;
+pub use reset::ResetKind;
+pub use rewind::RewindBuffer;
+pub use rom::{ReloadPolicy, Rom, RomError, RomInfo};
+pub use scheduler::{EventId, ScheduledEvent, Scheduler};
+pub use snapshot::{DeviceState, Snapshot, SnapshotError, StateDiff};
+pub use stats::Stats;
+pub use symbols::SymbolTable;
+pub use trace::{TraceConfig, TraceRecord, TraceSink};
+pub use vm::{CpuConfig, IllegalOpcodes, Registers, Vm};
+pub use watches::{Watch, WatchChange, WatchId};
@@ -0,0 +1,500 @@
+//! libretro core.
+//!
+//! Only compiled with the `libretro` feature. The crate's `cdylib` then
+//! exports the libretro API, so RetroArch and other libretro frontends can
+//! load it as a core and run `.rvm` ROMs:
+//!
+//! ```text
+//! cargo build --release --features libretro
+//! retroarch -L target/release/libemulator.so game.rvm
+//! ```
+//!
+//! Video is delivered as XRGB8888 at the native 160×144, audio as the mono
+//! output duplicated to both stereo channels at [`DEFAULT_SAMPLE_RATE`], and
+//! the RetroPad on port 0 drives the controller. Save states use the start
+//! state layout of an [input recording](crate::replay), and the whole
+//! address space is exposed as system RAM for frontend cheat searches.
+//! Cheats are [patch lists](crate::patches), `+` also separating entries,
+//! applied as [freezes](Vm::freeze).
+//!
+//! The frontend calls every entry point from one thread, so the core lives
+//! in a thread local.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ffi::{CStr, c_char, c_uint, c_void};
+
+use crate::audio::DEFAULT_SAMPLE_RATE;
+use crate::display::{HEIGHT, WIDTH};
+use crate::events::Event;
+use crate::input::{Button, CONTROLLER, Controller};
+use crate::patches::{self, Patch};
+use crate::replay::Recording;
+use crate::reset::ResetKind;
+use crate::rom::Rom;
+use crate::vm::{FRAME_RATE, Vm};
+
+/// `RETRO_API_VERSION`.
+pub const API_VERSION: c_uint = 1;
+
+const ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const PIXEL_FORMAT_XRGB8888: c_uint = 1;
+const DEVICE_JOYPAD: c_uint = 1;
+const MEMORY_SYSTEM_RAM: c_uint = 2;
+const REGION_NTSC: c_uint = 0;
+
+/// RetroPad button ids for each [`Button`], in port order.
+const JOYPAD_IDS: [(Button, c_uint); 8] = [
+    (Button::A, 8),
+    (Button::B, 0),
+    (Button::Select, 2),
+    (Button::Start, 3),
+    (Button::Up, 4),
+    (Button::Down, 5),
+    (Button::Left, 6),
+    (Button::Right, 7),
+];
+
+pub type EnvironmentFn = unsafe extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+pub type VideoRefreshFn =
+    unsafe extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+pub type AudioSampleFn = unsafe extern "C" fn(left: i16, right: i16);
+pub type AudioSampleBatchFn = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+pub type InputPollFn = unsafe extern "C" fn();
+pub type InputStateFn =
+    unsafe extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+/// `struct retro_system_info`.
+#[repr(C)]
+pub struct SystemInfo {
+    pub library_name: *const c_char,
+    pub library_version: *const c_char,
+    pub valid_extensions: *const c_char,
+    pub need_fullpath: bool,
+    pub block_extract: bool,
+}
+
+/// `struct retro_game_geometry`.
+#[repr(C)]
+pub struct GameGeometry {
+    pub base_width: c_uint,
+    pub base_height: c_uint,
+    pub max_width: c_uint,
+    pub max_height: c_uint,
+    pub aspect_ratio: f32,
+}
+
+/// `struct retro_system_timing`.
+#[repr(C)]
+pub struct SystemTiming {
+    pub fps: f64,
+    pub sample_rate: f64,
+}
+
+/// `struct retro_system_av_info`.
+#[repr(C)]
+pub struct SystemAvInfo {
+    pub geometry: GameGeometry,
+    pub timing: SystemTiming,
+}
+
+/// `struct retro_game_info`.
+#[repr(C)]
+pub struct GameInfo {
+    pub path: *const c_char,
+    pub data: *const c_void,
+    pub size: usize,
+    pub meta: *const c_char,
+}
+
+/// Callbacks the frontend has registered.
+#[derive(Default)]
+struct Callbacks {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+}
+
+/// The loaded game.
+struct Core {
+    vm: Vm,
+    /// Stereo samples handed to the frontend.
+    stereo: Vec<i16>,
+    /// XRGB8888 pixels handed to the frontend.
+    video: Vec<u32>,
+    /// Enabled cheats by frontend index.
+    cheats: BTreeMap<c_uint, Vec<Patch>>,
+}
+
+impl Core {
+    fn new(rom: &Rom) -> Self {
+        let mut vm = Vm::new();
+        vm.load_rom(rom)
+            .expect("a new machine has nothing on the mapper ports");
+        vm.enable_events();
+        Self {
+            vm,
+            stereo: Vec::new(),
+            video: vec![0; WIDTH * HEIGHT],
+            cheats: BTreeMap::new(),
+        }
+    }
+
+    fn apply_cheats(&mut self) {
+        self.vm.clear_freezes();
+        for patch in self.cheats.values().flatten() {
+            self.vm.freeze(patch.addr, patch.value);
+        }
+    }
+
+    /// The save state: an input recording with no events.
+    fn serialize(&self) -> Vec<u8> {
+        Recording {
+            start: self.vm.save_state(),
+            controller: self
+                .vm
+                .bus()
+                .device::<Controller>(CONTROLLER)
+                .cloned()
+                .unwrap_or_default(),
+            events: Vec::new(),
+        }
+        .to_bytes()
+    }
+
+    fn unserialize(&mut self, bytes: &[u8]) -> bool {
+        let Ok(recording) = Recording::from_bytes(bytes) else {
+            return false;
+        };
+        if self.vm.load_state(&recording.start).is_err() {
+            return false;
+        }
+        if let Some(controller) = self.vm.bus_mut().device_mut::<Controller>(CONTROLLER) {
+            *controller = recording.controller;
+        }
+        true
+    }
+}
+
+// Callbacks are stored as given; the frontend keeps them valid while the
+// core is loaded.
+thread_local! {
+    static CALLBACKS: RefCell<Callbacks> = RefCell::default();
+    static CORE: RefCell<Option<Core>> = const { RefCell::new(None) };
+}
+
+fn with_core<T>(default: T, f: impl FnOnce(&mut Core) -> T) -> T {
+    CORE.with_borrow_mut(|core| core.as_mut().map_or(default, f))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_api_version() -> c_uint {
+    API_VERSION
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_environment(callback: EnvironmentFn) {
+    CALLBACKS.with_borrow_mut(|c| c.environment = Some(callback));
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_video_refresh(callback: VideoRefreshFn) {
+    CALLBACKS.with_borrow_mut(|c| c.video_refresh = Some(callback));
+}
+
+/// Unused: audio always goes through the batch callback.
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_audio_sample(_: AudioSampleFn) {}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_audio_sample_batch(callback: AudioSampleBatchFn) {
+    CALLBACKS.with_borrow_mut(|c| c.audio_sample_batch = Some(callback));
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_input_poll(callback: InputPollFn) {
+    CALLBACKS.with_borrow_mut(|c| c.input_poll = Some(callback));
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_input_state(callback: InputStateFn) {
+    CALLBACKS.with_borrow_mut(|c| c.input_state = Some(callback));
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_init() {}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_deinit() {
+    CORE.set(None);
+}
+
+/// # Safety
+///
+/// `info` must point to a writable `retro_system_info`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    const VERSION: &CStr =
+        match CStr::from_bytes_with_nul(concat!(env!("CARGO_PKG_VERSION"), "\0").as_bytes()) {
+            Ok(version) => version,
+            Err(_) => panic!("the version has no NUL"),
+        };
+    // SAFETY: the caller passes a valid pointer.
+    unsafe {
+        info.write(SystemInfo {
+            library_name: c"rvm-8".as_ptr(),
+            library_version: VERSION.as_ptr(),
+            valid_extensions: if cfg!(feature = "compression") {
+                c"rvm|gz|zip"
+            } else {
+                c"rvm"
+            }
+            .as_ptr(),
+            need_fullpath: false,
+            block_extract: false,
+        });
+    }
+}
+
+/// # Safety
+///
+/// `info` must point to a writable `retro_system_av_info`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    // SAFETY: the caller passes a valid pointer.
+    unsafe {
+        info.write(SystemAvInfo {
+            geometry: GameGeometry {
+                base_width: WIDTH as c_uint,
+                base_height: HEIGHT as c_uint,
+                max_width: WIDTH as c_uint,
+                max_height: HEIGHT as c_uint,
+                aspect_ratio: WIDTH as f32 / HEIGHT as f32,
+            },
+            timing: SystemTiming {
+                fps: f64::from(FRAME_RATE),
+                sample_rate: f64::from(DEFAULT_SAMPLE_RATE),
+            },
+        });
+    }
+}
+
+/// Every port takes a RetroPad; only port 0 is read.
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_reset() {
+    with_core((), |core| core.vm.reset(ResetKind::Soft));
+}
+
+/// Runs one frame: polls the RetroPad, runs the machine and delivers the
+/// frame's video and audio. A frame that fails on an illegal opcode is
+/// still delivered as it stands, and the next call carries on after it.
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_run() {
+    let callbacks = CALLBACKS.with_borrow(|c| {
+        (
+            c.input_poll,
+            c.input_state,
+            c.video_refresh,
+            c.audio_sample_batch,
+        )
+    });
+    let (input_poll, input_state, video_refresh, audio_sample_batch) = callbacks;
+    with_core((), |core| {
+        if let (Some(poll), Some(state)) = (input_poll, input_state) {
+            // SAFETY: the frontend keeps its callbacks valid while the
+            // core is loaded.
+            let mask = unsafe {
+                poll();
+                JOYPAD_IDS
+                    .iter()
+                    .filter(|&&(_, id)| state(0, DEVICE_JOYPAD, 0, id) != 0)
+                    .fold(0, |mask, &(button, _)| mask | button.mask())
+            };
+            core.vm.set_buttons(mask);
+        }
+        let _ = core.vm.run_frame();
+
+        for (pixel, rgba) in core
+            .video
+            .iter_mut()
+            .zip(core.vm.framebuffer().chunks_exact(4))
+        {
+            *pixel = u32::from_be_bytes([0, rgba[0], rgba[1], rgba[2]]);
+        }
+        if let Some(refresh) = video_refresh {
+            // SAFETY: as above; `video` holds WIDTH * HEIGHT pixels of
+            // `pitch` bytes per row.
+            unsafe {
+                refresh(
+                    core.video.as_ptr().cast(),
+                    WIDTH as c_uint,
+                    HEIGHT as c_uint,
+                    WIDTH * 4,
+                );
+            }
+        }
+
+        core.stereo.clear();
+        for event in core.vm.drain_events() {
+            if let Event::Audio(samples) = event {
+                core.stereo
+                    .extend(samples.into_iter().flat_map(|sample| [sample, sample]));
+            }
+        }
+        if let Some(batch) = audio_sample_batch {
+            let mut sent = 0;
+            while sent < core.stereo.len() {
+                // SAFETY: as above; the pointer covers the unsent frames.
+                let taken =
+                    unsafe { batch(core.stereo[sent..].as_ptr(), (core.stereo.len() - sent) / 2) };
+                if taken == 0 {
+                    break;
+                }
+                sent += taken * 2;
+            }
+        }
+    });
+}
+
+/// Bytes a save state takes, always the same.
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_serialize_size() -> usize {
+    with_core(0, |core| core.serialize().len())
+}
+
+/// # Safety
+///
+/// `data` must point to `size` writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    with_core(false, |core| {
+        let state = core.serialize();
+        if size < state.len() {
+            return false;
+        }
+        // SAFETY: the caller passes `size` writable bytes, at least
+        // `state.len()`.
+        unsafe { std::ptr::copy_nonoverlapping(state.as_ptr(), data.cast(), state.len()) };
+        true
+    })
+}
+
+/// # Safety
+///
+/// `data` must point to `size` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    // SAFETY: the caller passes `size` readable bytes.
+    let bytes = unsafe { std::slice::from_raw_parts(data.cast::<u8>(), size) };
+    with_core(false, |core| core.unserialize(bytes))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_cheat_reset() {
+    with_core((), |core| {
+        core.cheats.clear();
+        core.apply_cheats();
+    });
+}
+
+/// Enables or disables cheat `index`. Codes that do not parse are ignored.
+///
+/// # Safety
+///
+/// `code` must be a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_cheat_set(index: c_uint, enabled: bool, code: *const c_char) {
+    // SAFETY: the caller passes a C string.
+    let code = unsafe { CStr::from_ptr(code) }
+        .to_string_lossy()
+        .replace('+', "\n");
+    with_core((), |core| {
+        core.cheats.remove(&index);
+        if enabled && let Ok(list) = patches::parse_list(&code) {
+            core.cheats.insert(index, list);
+        }
+        core.apply_cheats();
+    });
+}
+
+/// Loads a ROM from the frontend's buffer, or from its path when there is
+/// no buffer, and sets the pixel format.
+///
+/// # Safety
+///
+/// `game` must be null or point to a valid `retro_game_info`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_load_game(game: *const GameInfo) -> bool {
+    // SAFETY: the caller passes null or a valid pointer.
+    let Some(game) = (unsafe { game.as_ref() }) else {
+        return false;
+    };
+    let rom = if !game.data.is_null() {
+        // SAFETY: the frontend passes `size` readable bytes.
+        let bytes = unsafe { std::slice::from_raw_parts(game.data.cast::<u8>(), game.size) };
+        Rom::from_bytes(bytes)
+    } else if !game.path.is_null() {
+        // SAFETY: the frontend passes a C string.
+        let path = unsafe { CStr::from_ptr(game.path) }.to_string_lossy();
+        Rom::from_file(&*path)
+    } else {
+        return false;
+    };
+    let Ok(rom) = rom else {
+        return false;
+    };
+
+    if let Some(environment) = CALLBACKS.with_borrow(|c| c.environment) {
+        let mut format = PIXEL_FORMAT_XRGB8888;
+        // SAFETY: the frontend keeps its callback valid and reads a
+        // `retro_pixel_format` for this command.
+        if !unsafe { environment(ENVIRONMENT_SET_PIXEL_FORMAT, (&raw mut format).cast()) } {
+            return false;
+        }
+    }
+    CORE.set(Some(Core::new(&rom)));
+    true
+}
+
+/// No special game types are supported.
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_load_game_special(
+    _game_type: c_uint,
+    _info: *const GameInfo,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_unload_game() {
+    CORE.set(None);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_get_region() -> c_uint {
+    REGION_NTSC
+}
+
+/// The address space as system RAM, valid until the game is unloaded;
+/// null for any other kind of memory.
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    if id != MEMORY_SYSTEM_RAM {
+        return std::ptr::null_mut();
+    }
+    with_core(std::ptr::null_mut(), |core| core.vm.memory_ptr().cast())
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    if id != MEMORY_SYSTEM_RAM {
+        return 0;
+    }
+    with_core(0, |core| core.vm.memory().len())
+}
@@ -0,0 +1,175 @@
+//! Manifests of known ROM builds.
+//!
+//! A [`Manifest`] lists the SHA-256 digest of each ROM by file name, in the
+//! format `sha256sum` writes and `sha256sum -c` reads, so one can be made
+//! with `sha256sum *.rvm > roms.sha256`:
+//!
+//! ```text
+//! # release 1.2
+//! 3f2a...9c1e  demo.rvm
+//! 77b0...04d5 *roms/game.rvm
+//! ```
+//!
+//! Entries are keyed by file name, directories dropped, and `#` starts a
+//! comment line. [`Manifest::verify`] checks a loaded [`Rom`] against its
+//! entry, so a run fails up front instead of producing a trace of the wrong
+//! build. The digest covers the ROM image as [`RomInfo::sha256`] does: for
+//! a gzipped or zipped ROM it is the digest of the unpacked `.rvm`, not of
+//! the archive.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use crate::rom::{Hex, Rom, RomError, RomInfo};
+
+/// SHA-256 digests of ROM builds by file name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    entries: BTreeMap<String, [u8; 32]>,
+}
+
+/// A manifest line that could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestError {
+    /// 1-based line.
+    pub line: usize,
+    pub kind: ManifestErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ManifestErrorKind {
+    /// The line is not `digest  name`.
+    Syntax,
+    /// The digest is not 64 hex digits.
+    BadDigest(String),
+    DuplicateName(String),
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            ManifestErrorKind::Syntax => write!(f, "expected `digest  name`"),
+            ManifestErrorKind::BadDigest(digest) => {
+                write!(f, "`{digest}` is not a SHA-256 digest")
+            }
+            ManifestErrorKind::DuplicateName(name) => {
+                write!(f, "`{name}` is already listed")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ManifestError {}
+
+fn parse_digest(text: &str) -> Option<[u8; 32]> {
+    if text.len() != 64 || !text.is_ascii() {
+        return None;
+    }
+    let mut digest = [0; 32];
+    for (byte, pair) in digest.iter_mut().zip(text.as_bytes().chunks_exact(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(digest)
+}
+
+/// The file name `path` ends in.
+fn file_name(path: &str) -> &str {
+    Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(path)
+}
+
+impl Manifest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses a manifest.
+    pub fn parse(text: &str) -> Result<Self, ManifestError> {
+        let mut manifest = Self::new();
+        for (index, line) in text.lines().enumerate() {
+            let error = |kind| ManifestError {
+                line: index + 1,
+                kind,
+            };
+            let line = line.trim_end();
+            if line.trim_start().is_empty() || line.trim_start().starts_with('#') {
+                continue;
+            }
+            let (digest, name) = line
+                .split_once(' ')
+                .ok_or(error(ManifestErrorKind::Syntax))?;
+            // Two spaces for text mode, a space and `*` for binary mode.
+            let name = name.strip_prefix([' ', '*']).unwrap_or(name);
+            if name.is_empty() {
+                return Err(error(ManifestErrorKind::Syntax));
+            }
+            let digest = parse_digest(digest)
+                .ok_or_else(|| error(ManifestErrorKind::BadDigest(digest.into())))?;
+            if !manifest.insert(name, digest) {
+                return Err(error(ManifestErrorKind::DuplicateName(
+                    file_name(name).into(),
+                )));
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Lists `digest` for the file `name`, returning `false` and changing
+    /// nothing if the name is already listed. Directories in `name` are
+    /// dropped.
+    pub fn insert(&mut self, name: &str, digest: [u8; 32]) -> bool {
+        let name = file_name(name);
+        if self.entries.contains_key(name) {
+            return false;
+        }
+        self.entries.insert(name.into(), digest);
+        true
+    }
+
+    /// The digest listed for the file `name`, directories dropped.
+    pub fn get(&self, name: &str) -> Option<[u8; 32]> {
+        self.entries.get(file_name(name)).copied()
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Checks `rom`, loaded from the file `name`, against its entry and
+    /// returns its checksums.
+    ///
+    /// Fails with [`RomError::NotInManifest`] if the name is not listed, or
+    /// [`RomError::DigestMismatch`] if the ROM is not the build listed.
+    pub fn verify(&self, name: &str, rom: &Rom) -> Result<RomInfo, RomError> {
+        let expected = self
+            .get(name)
+            .ok_or_else(|| RomError::NotInManifest(file_name(name).into()))?;
+        let info = rom.info();
+        if info.sha256 != expected {
+            return Err(RomError::DigestMismatch {
+                expected,
+                actual: info.sha256,
+            });
+        }
+        Ok(info)
+    }
+}
+
+impl fmt::Display for Manifest {
+    /// Writes the manifest in `sha256sum` format, sorted by name.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, digest) in &self.entries {
+            writeln!(f, "{}  {name}", Hex(digest))?;
+        }
+        Ok(())
+    }
+}
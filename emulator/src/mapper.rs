@@ -0,0 +1,368 @@
+//! Bank-switching mappers.
+//!
+//! The flat address space holds at most [`MAX_BANKS`] 16 KiB ROM banks. A
+//! ROM whose header names a [`Mapper`] can hold up to [`MAX_MAPPED_BANKS`]:
+//! [`Vm::load_rom`] keeps every bank on the host side and shows the selected
+//! ones in the address space. The last ROM bank is fixed at 0xC000–0xFFFF,
+//! so the reset vector, interrupt handlers and bank-switching code are
+//! always there, and [`ROM_WINDOW`] shows any bank, bank 0 at first.
+//! [`Mapper::BankedRam`] adds 8 KiB RAM banks switched into [`RAM_WINDOW`].
+//!
+//! The program selects banks through [`BankRegisters`] at [`MAPPER_PORTS`]:
+//!
+//! | Offset | Register                                          |
+//! | ------ | ------------------------------------------------- |
+//! | 0      | ROM bank shown in [`ROM_WINDOW`]                  |
+//! | 1      | RAM bank shown in [`RAM_WINDOW`], if there are any |
+//!
+//! Reading a register returns what was written. Banks switch once the
+//! instruction writing the register completes, and bank numbers wrap
+//! around the bank count. Switching copies the bank into memory; the RAM
+//! bank leaving the window is copied out first, so it keeps what the
+//! program stored there.
+//!
+//! The bank registers save the selection and the RAM banks outside the
+//! address space with the other devices in a [`Snapshot`](crate::Snapshot).
+//! The ROM banks are the ROM's and are not saved.
+
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::bus::BusDevice;
+use crate::reset::ResetKind;
+use crate::rom::{BANK_SIZE, MAX_BANKS, Rom, RomError};
+use crate::vm::Vm;
+
+/// The bank registers.
+pub const MAPPER_PORTS: RangeInclusive<u16> = 0x2730..=0x2731;
+/// Where the switchable ROM bank is shown.
+pub const ROM_WINDOW: RangeInclusive<u16> = 0x8000..=0xBFFF;
+/// Where the switchable RAM bank is shown.
+pub const RAM_WINDOW: RangeInclusive<u16> = 0x6000..=0x7FFF;
+/// Size of one RAM bank in bytes.
+pub const RAM_BANK_SIZE: usize = 0x2000;
+/// Most ROM banks a mapper can switch between.
+pub const MAX_MAPPED_BANKS: usize = 255;
+
+/// How a ROM's banks reach the address space, from its header.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mapper {
+    /// No banking: every bank is mapped at the top of the address space.
+    #[default]
+    Flat,
+    /// The last bank fixed at 0xC000–0xFFFF and any bank switchable into
+    /// [`ROM_WINDOW`].
+    Banked,
+    /// [`Mapper::Banked`], plus `ram_banks` RAM banks switchable into
+    /// [`RAM_WINDOW`].
+    BankedRam { ram_banks: u8 },
+}
+
+impl Mapper {
+    /// Decodes the mapper bytes of a ROM header.
+    pub(crate) fn from_header([id, ram_banks]: [u8; 2]) -> Result<Self, RomError> {
+        match id {
+            0 => Ok(Self::Flat),
+            1 => Ok(Self::Banked),
+            2 if ram_banks == 0 => Err(RomError::BadRamBankCount(0)),
+            2 => Ok(Self::BankedRam { ram_banks }),
+            _ => Err(RomError::UnknownMapper(id)),
+        }
+    }
+
+    /// The mapper bytes of a ROM header.
+    pub(crate) fn to_header(self) -> [u8; 2] {
+        match self {
+            Self::Flat => [0, 0],
+            Self::Banked => [1, 0],
+            Self::BankedRam { ram_banks } => [2, ram_banks],
+        }
+    }
+
+    /// Most ROM banks the mapper takes.
+    pub fn max_banks(self) -> usize {
+        match self {
+            Self::Flat => MAX_BANKS,
+            Self::Banked | Self::BankedRam { .. } => MAX_MAPPED_BANKS,
+        }
+    }
+
+    /// Number of RAM banks.
+    pub fn ram_banks(self) -> usize {
+        match self {
+            Self::BankedRam { ram_banks } => ram_banks.into(),
+            Self::Flat | Self::Banked => 0,
+        }
+    }
+}
+
+const ROM_BANK: u16 = 0;
+const RAM_BANK: u16 = 1;
+
+/// The bank select registers, mapped at [`MAPPER_PORTS`] while a banked ROM
+/// is loaded.
+#[derive(Debug, Clone, Default)]
+pub struct BankRegisters {
+    rom: u8,
+    ram: u8,
+    banks: Banks,
+}
+
+impl BankRegisters {
+    /// The ROM bank number last written.
+    pub fn rom_bank(&self) -> u8 {
+        self.rom
+    }
+
+    /// The RAM bank number last written.
+    pub fn ram_bank(&self) -> u8 {
+        self.ram
+    }
+}
+
+impl BusDevice for BankRegisters {
+    fn read8(&mut self, offset: u16) -> u8 {
+        match offset {
+            ROM_BANK => self.rom,
+            RAM_BANK => self.ram,
+            _ => 0,
+        }
+    }
+
+    fn write8(&mut self, offset: u16, val: u8) {
+        match offset {
+            ROM_BANK => self.rom = val,
+            RAM_BANK => self.ram = val,
+            _ => {}
+        }
+    }
+
+    fn batch_cycles(&self) -> u32 {
+        u32::MAX
+    }
+
+    /// Both registers select bank 0 again. The banks keep their contents.
+    fn reset(&mut self, _: ResetKind) {
+        self.rom = 0;
+        self.ram = 0;
+    }
+
+    /// The registers, the banks shown and the RAM banks; the ROM banks are
+    /// the ROM's.
+    fn save_state(&self, state: &mut Vec<u8>) {
+        let Banks {
+            ram,
+            rom_bank,
+            ram_bank,
+            ..
+        } = &self.banks;
+        state.extend_from_slice(&[self.rom, self.ram, *rom_bank as u8, *ram_bank as u8]);
+        state.extend_from_slice(ram);
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        let Some((&[rom, ram, rom_bank, ram_bank], banks)) = state.split_first_chunk() else {
+            return;
+        };
+        // A state from a ROM with other banks does not fit these.
+        if banks.len() != self.banks.ram.len()
+            || usize::from(rom_bank) >= self.banks.rom_banks().max(1)
+            || usize::from(ram_bank) >= self.banks.ram_banks().max(1)
+        {
+            return;
+        }
+        self.rom = rom;
+        self.ram = ram;
+        self.banks.ram.copy_from_slice(banks);
+        self.banks.rom_bank = rom_bank.into();
+        self.banks.ram_bank = ram_bank.into();
+    }
+}
+
+/// The host side of a banked ROM: every bank, and which ones are in the
+/// address space.
+#[derive(Clone, Default)]
+struct Banks {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rom_bank: usize,
+    ram_bank: usize,
+}
+
+impl fmt::Debug for Banks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Banks")
+            .field("rom_banks", &self.rom_banks())
+            .field("ram_banks", &self.ram_banks())
+            .field("rom_bank", &self.rom_bank)
+            .field("ram_bank", &self.ram_bank)
+            .finish()
+    }
+}
+
+impl Banks {
+    fn rom_banks(&self) -> usize {
+        self.rom.len() / BANK_SIZE
+    }
+
+    fn ram_banks(&self) -> usize {
+        self.ram.len() / RAM_BANK_SIZE
+    }
+}
+
+impl Vm {
+    /// The banks of the banked ROM loaded.
+    fn banks_mut(&mut self) -> Option<&mut Banks> {
+        if !self.banked {
+            return None;
+        }
+        let registers = self
+            .bus_mut()
+            .device_mut::<BankRegisters>(*MAPPER_PORTS.start())?;
+        Some(&mut registers.banks)
+    }
+
+    /// Fails with [`RomError::MapConflict`] if a device other than the bank
+    /// registers of the ROM loaded is mapped on [`MAPPER_PORTS`].
+    pub(crate) fn check_mapper_ports(&self) -> Result<(), RomError> {
+        let ours = self.banked.then_some(MAPPER_PORTS);
+        let conflict = self.bus().mappings().find(|range| {
+            Some(range) != ours.as_ref()
+                && range.start() <= MAPPER_PORTS.end()
+                && MAPPER_PORTS.start() <= range.end()
+        });
+        match conflict {
+            Some(range) => Err(RomError::MapConflict {
+                start: *range.start(),
+                end: *range.end(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Shows the banks of a mapped `rom` and maps its bank registers,
+    /// replacing those of any ROM loaded before.
+    ///
+    /// Fails as [`Vm::check_mapper_ports`], before changing anything.
+    pub(crate) fn load_banks(&mut self, rom: &Rom) -> Result<(), RomError> {
+        self.check_mapper_ports()?;
+        self.unload_banks();
+        let fixed = (rom.banks() - 1) * BANK_SIZE;
+        let memory = self.memory_mut();
+        memory[0xC000..].copy_from_slice(&rom.data[fixed..]);
+        memory[rom_window()].copy_from_slice(&rom.data[..BANK_SIZE]);
+        let ram = vec![0; rom.mapper().ram_banks() * RAM_BANK_SIZE];
+        if !ram.is_empty() {
+            memory[ram_window()].fill(0);
+        }
+        let registers = BankRegisters {
+            banks: Banks {
+                rom: rom.data.clone(),
+                ram,
+                rom_bank: 0,
+                ram_bank: 0,
+            },
+            ..BankRegisters::default()
+        };
+        self.bus_mut()
+            .map(MAPPER_PORTS, registers)
+            .expect("checked that MAPPER_PORTS is free");
+        self.banked = true;
+        Ok(())
+    }
+
+    /// Swaps the banks of `rom` in for those of the banked ROM loaded,
+    /// keeping the selection and the RAM banks if both have as many RAM
+    /// banks, or loads it from scratch otherwise.
+    ///
+    /// Fails as [`Vm::load_banks`], before changing anything.
+    pub(crate) fn swap_banks(&mut self, rom: &Rom) -> Result<(), RomError> {
+        let ram_banks = rom.mapper().ram_banks();
+        let Some(banks) = self
+            .banks_mut()
+            .filter(|banks| banks.ram_banks() == ram_banks)
+        else {
+            return self.load_banks(rom);
+        };
+        banks.rom = rom.data.clone();
+        banks.rom_bank %= banks.rom_banks();
+        let shown = banks.rom_bank * BANK_SIZE;
+        let fixed = (rom.banks() - 1) * BANK_SIZE;
+        let memory = self.memory_mut();
+        memory[0xC000..].copy_from_slice(&rom.data[fixed..]);
+        memory[rom_window()].copy_from_slice(&rom.data[shown..][..BANK_SIZE]);
+        Ok(())
+    }
+
+    /// Unmaps the bank registers of a previously loaded banked ROM.
+    pub(crate) fn unload_banks(&mut self) {
+        if std::mem::take(&mut self.banked) {
+            self.bus_mut().unmap(*MAPPER_PORTS.start());
+        }
+    }
+
+    /// Zeroes the RAM banks of the banked ROM loaded, the one switched in
+    /// included.
+    pub(crate) fn clear_ram_banks(&mut self) {
+        let Some(banks) = self.banks_mut() else {
+            return;
+        };
+        banks.ram.fill(0);
+        if !banks.ram.is_empty() {
+            self.memory_mut()[ram_window()].fill(0);
+        }
+    }
+
+    /// Switches in the banks the registers select, after an instruction.
+    pub(crate) fn switch_banks(&mut self) {
+        if !self.banked {
+            return;
+        }
+        let Some(registers) = self
+            .bus_mut()
+            .device_mut::<BankRegisters>(*MAPPER_PORTS.start())
+        else {
+            return;
+        };
+        let (rom, ram) = (registers.rom, registers.ram);
+        let mut banks = std::mem::take(&mut registers.banks);
+        let rom_bank = rom as usize % banks.rom_banks();
+        let ram_bank = match banks.ram_banks() {
+            0 => 0,
+            count => ram as usize % count,
+        };
+        if rom_bank != banks.rom_bank {
+            self.mark_dirty(rom_window());
+        }
+        if ram_bank != banks.ram_bank {
+            self.mark_dirty(ram_window());
+        }
+        let memory = self.memory_untracked_mut();
+        if rom_bank != banks.rom_bank {
+            let bank = &banks.rom[rom_bank * BANK_SIZE..][..BANK_SIZE];
+            memory[rom_window()].copy_from_slice(bank);
+            banks.rom_bank = rom_bank;
+        }
+        if ram_bank != banks.ram_bank {
+            let old = banks.ram_bank * RAM_BANK_SIZE..(banks.ram_bank + 1) * RAM_BANK_SIZE;
+            banks.ram[old].copy_from_slice(&memory[ram_window()]);
+            let new = &banks.ram[ram_bank * RAM_BANK_SIZE..][..RAM_BANK_SIZE];
+            memory[ram_window()].copy_from_slice(new);
+            banks.ram_bank = ram_bank;
+        }
+        if let Some(registers) = self
+            .bus_mut()
+            .device_mut::<BankRegisters>(*MAPPER_PORTS.start())
+        {
+            registers.banks = banks;
+        }
+    }
+}
+
+fn rom_window() -> RangeInclusive<usize> {
+    *ROM_WINDOW.start() as usize..=*ROM_WINDOW.end() as usize
+}
+
+fn ram_window() -> RangeInclusive<usize> {
+    *RAM_WINDOW.start() as usize..=*RAM_WINDOW.end() as usize
+}
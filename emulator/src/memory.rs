@@ -0,0 +1,158 @@
+//! Memory access for debugger frontends such as hex editors.
+//!
+//! [`Vm::read_mem`] and [`Vm::write_mem`] see the address space as the CPU
+//! does. Addresses decode through [mirrors](Vm::mirror), read and write
+//! hooks apply, mapped devices and the [MPU](crate::mpu) registers answer
+//! for their ranges with whatever side effects they have, unmapped pages
+//! read as [`BusConfig`](crate::BusConfig) says and writes to ROM are
+//! dropped. An access the MPU would refuse the CPU is refused too, reading
+//! as open bus, but neither it nor a trapping unmapped page is latched as
+//! a fault. The `_raw` variants go straight to the backing store instead,
+//! undecoded, skipping devices and reaching ROM. Neither kind fires
+//! watchpoints or access hooks, and none of them advance the clock.
+//!
+//! [`hexdump`] lays bytes out in rows of 16 with the names a
+//! [`SymbolTable`] gives their addresses, and [`Vm::hexdump`] dumps the
+//! machine's memory that way with its own table:
+//!
+//! ```text
+//! 0200  05 00 00 03 00 00 00 00 00 00 00 00 00 00 00 00  ................  ; $0200 player_hp, $0203 lives
+//! 0310  00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00  ................  ; oam+16
+//! ```
+
+use std::ops::RangeInclusive;
+
+use crate::bus::PAGE_SIZE;
+use crate::error::VmError;
+use crate::ffi::BusAccess;
+use crate::symbols::SymbolTable;
+use crate::vm::Vm;
+
+/// Bytes per [`hexdump`] row.
+const ROW: usize = 16;
+
+impl Vm {
+    /// Reads `range` through the bus.
+    pub fn read_mem(&mut self, range: RangeInclusive<u16>) -> Vec<u8> {
+        let (page_map, rom_pages) = (self.cpu.page_map, self.cpu.rom_pages);
+        let (bus, memory) = self.bus_and_memory();
+        range
+            .map(|addr| {
+                let mut val = 0;
+                bus.host_access(
+                    memory,
+                    &page_map,
+                    &rom_pages,
+                    BusAccess::Read,
+                    addr,
+                    &mut val,
+                );
+                val
+            })
+            .collect()
+    }
+
+    /// Writes `bytes` from `addr` on through the bus, failing without
+    /// writing anything if they run past the end of the address space.
+    pub fn write_mem(&mut self, addr: u16, bytes: &[u8]) -> Result<(), VmError> {
+        check_bounds(addr, bytes.len())?;
+        let (page_map, rom_pages) = (self.cpu.page_map, self.cpu.rom_pages);
+        let (bus, memory) = self.bus_and_memory();
+        for (offset, &val) in bytes.iter().enumerate() {
+            let (addr, mut val) = (addr + offset as u16, val);
+            bus.host_access(
+                memory,
+                &page_map,
+                &rom_pages,
+                BusAccess::Write,
+                addr,
+                &mut val,
+            );
+        }
+        if !bytes.is_empty() {
+            let last = addr as usize + bytes.len() - 1;
+            for &page in &page_map[addr as usize / PAGE_SIZE..=last / PAGE_SIZE] {
+                self.cpu.dirty_pages[page as usize] = 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads `range` from the backing store, ignoring mapped devices. An
+    /// empty range reads nothing, as with [`Vm::read_mem`].
+    pub fn read_mem_raw(&self, range: RangeInclusive<u16>) -> Vec<u8> {
+        if range.is_empty() {
+            return Vec::new();
+        }
+        self.memory()[*range.start() as usize..=*range.end() as usize].to_vec()
+    }
+
+    /// Writes `bytes` from `addr` on into the backing store, under mapped
+    /// devices and into ROM alike; the bounds are checked as for
+    /// [`Vm::write_mem`].
+    pub fn write_mem_raw(&mut self, addr: u16, bytes: &[u8]) -> Result<(), VmError> {
+        self.load(addr, bytes)
+    }
+
+    /// A [`hexdump`] of `range` from the backing store, with names from
+    /// the attached [symbol table](Vm::symbols). An empty range dumps as
+    /// an empty string.
+    pub fn hexdump(&self, range: RangeInclusive<u16>) -> String {
+        hexdump(
+            *range.start(),
+            &self.read_mem_raw(range),
+            self.symbols.as_ref(),
+        )
+    }
+}
+
+/// `bytes` from `start` on, one line per 16: the address, the bytes in hex
+/// and as ASCII, and after a `;` the names `symbols` has for addresses in
+/// the row, along with the region the row starts inside of, if any.
+/// Addresses wrap past 0xFFFF. Lines are separated, not terminated, by
+/// newlines.
+pub fn hexdump(start: u16, bytes: &[u8], symbols: Option<&SymbolTable>) -> String {
+    let mut lines = Vec::new();
+    for (row, chunk) in bytes.chunks(ROW).enumerate() {
+        let addr = start.wrapping_add((row * ROW) as u16);
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{b:02X}")).collect();
+        let text: String = chunk
+            .iter()
+            .map(|&b| if b.is_ascii_graphic() { b as char } else { '.' })
+            .collect();
+        let hex = hex.join(" ");
+        let mut line = format!(
+            "{addr:04X}  {hex:<width$}  {text:<ROW$}",
+            width = ROW * 3 - 1
+        );
+        let names = symbols.map(|symbols| row_names(symbols, addr, chunk.len()));
+        match names.filter(|names| !names.is_empty()) {
+            Some(names) => line = format!("{line}  ; {}", names.join(", ")),
+            None => line.truncate(line.trim_end().len()),
+        }
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+/// What [`hexdump`] lists for the `len` bytes from `start`.
+fn row_names(symbols: &SymbolTable, start: u16, len: usize) -> Vec<String> {
+    let mut names = Vec::new();
+    if let Some((region, offset)) = symbols.in_region(start) {
+        names.push(format!("{region}+{offset}"));
+    }
+    for offset in 0..len as u16 {
+        let addr = start.wrapping_add(offset);
+        if let Some(name) = symbols.name(addr) {
+            names.push(format!("${addr:04X} {name}"));
+        }
+    }
+    names
+}
+
+fn check_bounds(addr: u16, len: usize) -> Result<(), VmError> {
+    if addr as usize + len > 0x10000 {
+        return Err(VmError::OutOfBounds { addr, len });
+    }
+    Ok(())
+}
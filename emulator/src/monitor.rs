@@ -0,0 +1,384 @@
+//! Machine-code monitor.
+//!
+//! A [`Monitor`] takes the terse one-letter commands of the classic 8-bit
+//! ROM monitors, a line at a time, and answers with text.
+//! [`Vm::run_monitor`] runs one over any line-based stream, such as stdin
+//! and stdout, and [`Vm::serve_monitor`] over a TCP connection, which is
+//! what a terminal behind a serial-to-TCP bridge talks to:
+//!
+//! ```text
+//! m [start [end]]    examine memory, 8 rows from start or through end
+//! : addr bb bb ...   deposit bytes
+//! d [start [n]]      disassemble n instructions (default 16)
+//! a addr instr       assemble one line in place
+//! r [reg=val ...]    show or set A, X, Y, P, SP and PC
+//! g [addr]           go, from addr if given, to a breakpoint or error
+//! s [n]              step one or n instructions
+//! b [addr]           toggle a breakpoint, or list them
+//! q                  quit
+//! ```
+//!
+//! Numbers are hex, with or without a `$` or `0x`, and addresses may be
+//! names from the machine's [symbol table](crate::symbols). `a` takes
+//! [assembler](crate::asm) syntax, where hex needs its `$`. `m` and `d`
+//! with no address carry on where the last one stopped, and an empty line
+//! repeats the last `m`, `d` or `s` that way.
+//!
+//! `:` and `a` store through [`Vm::write`], so they never touch devices
+//! and may patch ROM, and `m` and `d` read through [`Vm::read`]. `m` lists
+//! the names [labels](Vm::label) give the addresses in each row, as
+//! [`memory::hexdump`] does.
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, ToSocketAddrs};
+
+use crate::asm::{self, AsmError};
+use crate::memory;
+use crate::vm::{Registers, Vm};
+
+/// Rows of 16 bytes `m` shows with no end address.
+const MEMORY_ROWS: u16 = 8;
+/// Instructions `d` lists with no count.
+const DISASSEMBLY_LINES: u16 = 16;
+
+const HELP: &str = "\
+m [start [end]]    examine memory
+: addr bb bb ...   deposit bytes
+d [start [n]]      disassemble n instructions
+a addr instr       assemble one line in place
+r [reg=val ...]    show or set A X Y P SP PC
+g [addr]           go to a breakpoint or error
+s [n]              step one or n instructions
+b [addr]           toggle a breakpoint, or list them
+q                  quit";
+
+/// What a command produced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    /// Text to show, without a trailing newline; empty for commands that
+    /// only change something.
+    Text(String),
+    /// The session should end.
+    Quit,
+}
+
+/// A command that could not be carried out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MonitorError {
+    UnknownCommand(String),
+    /// The command needs this argument.
+    Missing(&'static str),
+    /// Not a hex number of the right size, nor a symbol.
+    BadNumber(String),
+    UnknownRegister(String),
+    /// The line given to `a` does not assemble.
+    Asm(AsmError),
+}
+
+impl fmt::Display for MonitorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownCommand(command) => {
+                write!(f, "unknown command `{command}`; ? for help")
+            }
+            Self::Missing(what) => write!(f, "missing {what}"),
+            Self::BadNumber(text) => write!(f, "bad number `{text}`"),
+            Self::UnknownRegister(name) => write!(f, "unknown register `{name}`"),
+            // The monitor assembles a single line, so its number is noise.
+            Self::Asm(err) => {
+                let message = err.to_string();
+                let message = message.split_once(": ").map_or(&*message, |(_, rest)| rest);
+                write!(f, "{message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MonitorError {}
+
+/// A monitor session's state; see the [module docs](self).
+#[derive(Debug, Clone, Default)]
+pub struct Monitor {
+    /// Where `m` with no address starts.
+    examine: u16,
+    /// Where `d` with no address starts.
+    disassemble: u16,
+    /// The command an empty line repeats.
+    repeat: Option<char>,
+}
+
+impl Monitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Carries out one command line on `vm`.
+    pub fn command(&mut self, vm: &mut Vm, line: &str) -> Result<Reply, MonitorError> {
+        let line = line.trim();
+        let (word, rest) = match line.strip_prefix(':') {
+            Some(rest) => (":", rest),
+            None => line.split_once(char::is_whitespace).unwrap_or((line, "")),
+        };
+        let rest = rest.trim();
+        let mut chars = word.chars();
+        let command = match (chars.next(), chars.next()) {
+            (Some(command), None) => command,
+            (Some(_), Some(_)) => return Err(MonitorError::UnknownCommand(word.into())),
+            (None, _) => match self.repeat {
+                Some(command) => command,
+                None => return Ok(Reply::Text(String::new())),
+            },
+        };
+        let args: Vec<&str> = rest.split_whitespace().collect();
+        self.repeat = None;
+        let text = match command.to_ascii_lowercase() {
+            'm' => {
+                self.repeat = Some('m');
+                self.examine(vm, &args)?
+            }
+            ':' => self.deposit(vm, &args)?,
+            'd' => {
+                self.repeat = Some('d');
+                self.disassembly(vm, &args)?
+            }
+            'a' => self.assemble(vm, rest)?,
+            'r' => registers(vm, &args)?,
+            'g' => {
+                if let Some(addr) = args.first() {
+                    let pc = address(vm, addr)?;
+                    vm.set_registers(Registers {
+                        pc,
+                        ..vm.registers()
+                    });
+                }
+                let stop = match (vm.run_until_break(), vm.symbols()) {
+                    (Ok(reason), Some(symbols)) => reason.with_symbols(symbols).to_string(),
+                    (Ok(reason), None) => reason.to_string(),
+                    (Err(err), _) => err.to_string(),
+                };
+                format!("{stop}\n{}", self.status(vm))
+            }
+            's' => {
+                self.repeat = Some('s');
+                let count = match args.first() {
+                    Some(count) => number(count, u32::MAX.into())? as u32,
+                    None => 1,
+                };
+                match (0..count).try_for_each(|_| vm.step()) {
+                    Ok(()) => self.status(vm),
+                    Err(err) => format!("{err}\n{}", self.status(vm)),
+                }
+            }
+            'b' => breakpoint(vm, args.first().copied())?,
+            '?' | 'h' => HELP.into(),
+            'q' | 'x' => return Ok(Reply::Quit),
+            _ => return Err(MonitorError::UnknownCommand(command.into())),
+        };
+        Ok(Reply::Text(text))
+    }
+
+    fn examine(&mut self, vm: &Vm, args: &[&str]) -> Result<String, MonitorError> {
+        let start = match args.first() {
+            Some(addr) => address(vm, addr)?,
+            None => self.examine,
+        };
+        let rows = match args.get(1) {
+            Some(end) => (address(vm, end)?.saturating_sub(start) / 16).saturating_add(1),
+            None => MEMORY_ROWS,
+        };
+        let len = u32::from(rows) * 16;
+        let bytes: Vec<u8> = (0..len)
+            .map(|i| vm.read(start.wrapping_add(i as u16)))
+            .collect();
+        self.examine = start.wrapping_add(len as u16);
+        Ok(memory::hexdump(start, &bytes, vm.symbols()))
+    }
+
+    fn deposit(&mut self, vm: &mut Vm, args: &[&str]) -> Result<String, MonitorError> {
+        let (addr, bytes) = args.split_first().ok_or(MonitorError::Missing("address"))?;
+        let start = address(vm, addr)?;
+        if bytes.is_empty() {
+            return Err(MonitorError::Missing("bytes"));
+        }
+        let bytes = bytes
+            .iter()
+            .map(|byte| number(byte, 0xFF).map(|byte| byte as u8))
+            .collect::<Result<Vec<_>, _>>()?;
+        for (offset, &byte) in bytes.iter().enumerate() {
+            vm.write(start.wrapping_add(offset as u16), byte);
+        }
+        self.examine = start;
+        Ok(String::new())
+    }
+
+    fn disassembly(&mut self, vm: &Vm, args: &[&str]) -> Result<String, MonitorError> {
+        let mut addr = match args.first() {
+            Some(addr) => address(vm, addr)?,
+            None => self.disassemble,
+        };
+        let count = match args.get(1) {
+            Some(count) => number(count, 0xFFFF)? as u16,
+            None => DISASSEMBLY_LINES,
+        };
+        let lines: Vec<String> = (0..count)
+            .map(|_| {
+                let (line, size) = instruction(vm, addr);
+                addr = addr.wrapping_add(size);
+                line
+            })
+            .collect();
+        self.disassemble = addr;
+        Ok(lines.join("\n"))
+    }
+
+    fn assemble(&mut self, vm: &mut Vm, rest: &str) -> Result<String, MonitorError> {
+        let (addr, source) = rest
+            .split_once(char::is_whitespace)
+            .ok_or(MonitorError::Missing("instruction"))?;
+        let addr = address(vm, addr)?;
+        let assembly =
+            asm::assemble(&format!(".org ${addr:04X}\n{source}\n")).map_err(MonitorError::Asm)?;
+        let mut lines = Vec::new();
+        for segment in &assembly.segments {
+            for (offset, &byte) in segment.bytes.iter().enumerate() {
+                vm.write(segment.origin.wrapping_add(offset as u16), byte);
+            }
+            let mut at = segment.origin;
+            while at.wrapping_sub(segment.origin) < segment.bytes.len() as u16 {
+                let (line, size) = instruction(vm, at);
+                lines.push(line);
+                at = at.wrapping_add(size);
+            }
+            self.disassemble = at;
+        }
+        Ok(lines.join("\n"))
+    }
+
+    /// The registers and the instruction at the PC.
+    fn status(&mut self, vm: &Vm) -> String {
+        let pc = vm.registers().pc;
+        let (line, size) = instruction(vm, pc);
+        self.disassemble = pc.wrapping_add(size);
+        format!("{}\n{line}", register_line(vm))
+    }
+}
+
+/// A hex number up to `max`.
+fn number(text: &str, max: u64) -> Result<u64, MonitorError> {
+    let digits = text
+        .strip_prefix('$')
+        .or_else(|| text.strip_prefix("0x"))
+        .unwrap_or(text);
+    u64::from_str_radix(digits, 16)
+        .ok()
+        .filter(|&value| value <= max)
+        .ok_or_else(|| MonitorError::BadNumber(text.into()))
+}
+
+/// A symbol's address or a hex number.
+fn address(vm: &Vm, text: &str) -> Result<u16, MonitorError> {
+    if let Some(addr) = vm.symbols().and_then(|symbols| symbols.address(text)) {
+        return Ok(addr);
+    }
+    number(text, 0xFFFF).map(|addr| addr as u16)
+}
+
+/// The listing line of the instruction at `addr`, and its size.
+fn instruction(vm: &Vm, addr: u16) -> (String, u16) {
+    let instr = vm.disassemble(addr);
+    let size = u16::from(instr.size);
+    let hex: Vec<String> = (0..size)
+        .map(|i| format!("{:02X}", vm.read(addr.wrapping_add(i))))
+        .collect();
+    let text = match vm.symbols() {
+        Some(symbols) => instr.with_symbols(symbols).to_string(),
+        None => instr.to_string(),
+    };
+    (format!("{addr:04X}  {:<8}  {text}", hex.join(" ")), size)
+}
+
+fn register_line(vm: &Vm) -> String {
+    let regs = vm.registers();
+    format!(
+        "A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PC:{:04X}  CYC:{}",
+        regs.a,
+        regs.x,
+        regs.y,
+        regs.flags,
+        regs.sp,
+        regs.pc,
+        vm.cycles()
+    )
+}
+
+fn registers(vm: &mut Vm, args: &[&str]) -> Result<String, MonitorError> {
+    let mut regs = vm.registers();
+    for arg in args {
+        let (name, value) = arg
+            .split_once('=')
+            .ok_or_else(|| MonitorError::UnknownRegister((*arg).into()))?;
+        match name.to_ascii_lowercase().as_str() {
+            "a" => regs.a = number(value, 0xFF)? as u8,
+            "x" => regs.x = number(value, 0xFF)? as u8,
+            "y" => regs.y = number(value, 0xFF)? as u8,
+            "p" => regs.flags = number(value, 0xFF)? as u8,
+            "sp" => regs.sp = number(value, 0xFFFF)? as u16,
+            "pc" => regs.pc = address(vm, value)?,
+            _ => return Err(MonitorError::UnknownRegister(name.into())),
+        }
+    }
+    vm.set_registers(regs);
+    Ok(register_line(vm))
+}
+
+fn breakpoint(vm: &mut Vm, arg: Option<&str>) -> Result<String, MonitorError> {
+    let Some(arg) = arg else {
+        let lines: Vec<String> = vm
+            .breakpoints()
+            .iter()
+            .map(|addr| match vm.symbols() {
+                Some(symbols) => format!("{addr:04X}  {}", symbols.describe(addr)),
+                None => format!("{addr:04X}"),
+            })
+            .collect();
+        return Ok(lines.join("\n"));
+    };
+    let addr = address(vm, arg)?;
+    Ok(if vm.add_breakpoint(addr) {
+        format!("breakpoint set at {addr:04X}")
+    } else {
+        vm.remove_breakpoint(addr);
+        format!("breakpoint cleared at {addr:04X}")
+    })
+}
+
+impl Vm {
+    /// Runs a [`Monitor`] session, reading commands from `input` and
+    /// answering on `output`, until a `q` or the end of the input. Errors
+    /// are answered with `?` and the reason, as monitors do.
+    pub fn run_monitor(&mut self, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        let mut monitor = Monitor::new();
+        write!(output, "rvm8 monitor; ? for help\n> ")?;
+        output.flush()?;
+        for line in input.lines() {
+            match monitor.command(self, &line?) {
+                Ok(Reply::Quit) => return Ok(()),
+                Ok(Reply::Text(text)) if text.is_empty() => {}
+                Ok(Reply::Text(text)) => writeln!(output, "{text}")?,
+                Err(err) => writeln!(output, "? {err}")?,
+            }
+            write!(output, "> ")?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Listens on `addr`, waits for one terminal to connect and runs a
+    /// monitor session over the connection with [`Vm::run_monitor`].
+    pub fn serve_monitor(&mut self, addr: impl ToSocketAddrs) -> io::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, _) = listener.accept()?;
+        self.run_monitor(BufReader::new(stream.try_clone()?), stream)
+    }
+}
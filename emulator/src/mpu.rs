@@ -0,0 +1,349 @@
+//! Memory protection unit.
+//!
+//! An [`Mpu`] installed with [`Bus::set_mpu`] checks the CPU's accesses
+//! against protected regions, the way debugging hardware catches stray
+//! writes. Each region carries [`READ_ONLY`], [`NO_EXECUTE`] and
+//! [`SUPERVISOR`] flags, and an address inside several regions gets all of
+//! their flags:
+//!
+//! ```
+//! # use emulator::{mpu::{Mpu, READ_ONLY, NO_EXECUTE}, ResetKind, Vm, VmError};
+//! let mut vm = Vm::new();
+//! let mut mpu = Mpu::default();
+//! mpu.protect(0x8000..=0xFFFF, READ_ONLY);
+//! mpu.protect(0x0000..=0x7FFF, NO_EXECUTE);
+//! vm.bus_mut().set_mpu(mpu);
+//! // LSR $8000 writes back to protected memory.
+//! vm.load(0x8000, &[0x4E, 0x00, 0x80]).unwrap();
+//! vm.load(0xFFFC, &[0x00, 0x80]).unwrap();
+//! vm.reset(ResetKind::Soft);
+//! assert!(matches!(vm.step(), Err(VmError::MpuFault { pc: 0x8000, .. })));
+//! ```
+//!
+//! The CPU runs in supervisor or user mode. Reset and interrupt entry
+//! switch to supervisor mode, and the program drops to user mode by
+//! writing `MODE`; the kernel has no return-from-interrupt, so handlers
+//! write it again before they finish. Outside supervisor mode any access to
+//! a [`SUPERVISOR`] region is a violation.
+//!
+//! While installed the MPU answers at [`MPU_PORTS`], ahead of any device
+//! mapped there:
+//!
+//! | Offset | Register                                                   |
+//! | ------ | ---------------------------------------------------------- |
+//! | 0–1    | address of the latched violation, little-endian            |
+//! | 2      | `STATUS`: the [`Violation::bit`] latched; any write clears |
+//! | 3      | `MODE`: 1 in supervisor mode; writing 0 enters user mode   |
+//!
+//! Writing a nonzero `MODE` in user mode is itself a [`SUPERVISOR`]
+//! violation.
+//!
+//! A violating read returns open bus and a violating write is dropped;
+//! neither reaches a device, RAM, hook or watchpoint. What happens next
+//! depends on the [`MpuResponse`]. [`MpuResponse::Trap`] stops the machine
+//! with [`VmError::MpuFault`] once the instruction completes, and an
+//! instruction fetched from [`NO_EXECUTE`] memory is not executed at all.
+//! [`MpuResponse::Interrupt`] latches the first violation in `STATUS` and
+//! holds an interrupt line until it is cleared; an instruction fetched from
+//! [`NO_EXECUTE`] memory then still runs, ahead of the handler.
+//!
+//! Checking fetches needs the host before every instruction, so a machine
+//! with an MPU installed runs step by step like a traced one. A
+//! [`Snapshot`](crate::Snapshot) saves the mode and the latched violation
+//! as the state of a device at [`MPU_PORTS`], ahead of any device mapped
+//! there; the regions and the response are the host's.
+
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::bus::Bus;
+use crate::error::VmError;
+use crate::ffi::BusAccess;
+use crate::irq::IRQ_LINES;
+use crate::vm::Vm;
+
+/// The MPU registers.
+pub const MPU_PORTS: RangeInclusive<u16> = 0x2740..=0x2743;
+/// Interrupt line used for violations by convention.
+pub const MPU_IRQ: u8 = 4;
+
+/// Region flag: the CPU may not write.
+pub const READ_ONLY: u8 = 1 << 0;
+/// Region flag: the CPU may not fetch instructions.
+pub const NO_EXECUTE: u8 = 1 << 1;
+/// Region flag: only supervisor mode may access.
+pub const SUPERVISOR: u8 = 1 << 2;
+
+const STATUS: u16 = 2;
+const MODE: u16 = 3;
+
+/// The kind of access the MPU refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    /// A write to [`READ_ONLY`] memory.
+    WriteProtect,
+    /// An instruction fetched from [`NO_EXECUTE`] memory.
+    NoExecute,
+    /// A user-mode access to [`SUPERVISOR`] memory, or to `MODE`.
+    Supervisor,
+}
+
+impl Violation {
+    /// The violation's bit in `STATUS`: the flag of the region it broke.
+    pub fn bit(self) -> u8 {
+        match self {
+            Self::WriteProtect => READ_ONLY,
+            Self::NoExecute => NO_EXECUTE,
+            Self::Supervisor => SUPERVISOR,
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::WriteProtect => "write to read-only memory",
+            Self::NoExecute => "fetch from no-execute memory",
+            Self::Supervisor => "user-mode access to supervisor memory",
+        })
+    }
+}
+
+/// What a violation does besides blocking the access.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MpuResponse {
+    /// Stops the machine with [`VmError::MpuFault`].
+    #[default]
+    Trap,
+    /// Latches the violation and holds interrupt `line` until the program
+    /// or [`Mpu::acknowledge`] clears it.
+    Interrupt { line: u8 },
+}
+
+/// The memory protection unit.
+#[derive(Debug, Clone)]
+pub struct Mpu {
+    regions: Vec<(RangeInclusive<u16>, u8)>,
+    response: MpuResponse,
+    supervisor: bool,
+    /// The first violation not yet reported or cleared, and its address.
+    fault: Option<(u16, Violation)>,
+}
+
+impl Default for Mpu {
+    /// A trapping MPU in supervisor mode that protects nothing.
+    fn default() -> Self {
+        Self::new(MpuResponse::Trap)
+    }
+}
+
+impl Mpu {
+    /// An MPU in supervisor mode, protecting nothing, that answers
+    /// violations with `response`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an interrupt line is not below [`IRQ_LINES`].
+    pub fn new(response: MpuResponse) -> Self {
+        if let MpuResponse::Interrupt { line } = response {
+            assert!(line < IRQ_LINES, "IRQ line {line} out of range");
+        }
+        Self {
+            regions: Vec::new(),
+            response,
+            supervisor: true,
+            fault: None,
+        }
+    }
+
+    /// Adds a region with `flags` over `range`.
+    pub fn protect(&mut self, range: RangeInclusive<u16>, flags: u8) {
+        self.regions.push((range, flags));
+    }
+
+    /// Removes every region.
+    pub fn clear_regions(&mut self) {
+        self.regions.clear();
+    }
+
+    /// The flags of every region containing `addr`.
+    pub fn flags(&self, addr: u16) -> u8 {
+        self.regions
+            .iter()
+            .filter(|(range, _)| range.contains(&addr))
+            .fold(0, |flags, (_, f)| flags | f)
+    }
+
+    pub fn response(&self) -> MpuResponse {
+        self.response
+    }
+
+    pub fn is_supervisor(&self) -> bool {
+        self.supervisor
+    }
+
+    pub fn set_supervisor(&mut self, supervisor: bool) {
+        self.supervisor = supervisor;
+    }
+
+    /// The latched violation and the address it happened at.
+    pub fn fault(&self) -> Option<(u16, Violation)> {
+        self.fault
+    }
+
+    /// Clears the latched violation, releasing the interrupt line.
+    pub fn acknowledge(&mut self) {
+        self.fault = None;
+    }
+
+    /// Interrupt lines held for a latched violation.
+    pub(crate) fn irq_lines(&self) -> u8 {
+        match (self.response, self.fault) {
+            (MpuResponse::Interrupt { line }, Some(_)) => 1 << line,
+            _ => 0,
+        }
+    }
+
+    fn violate(&mut self, addr: u16, violation: Violation) {
+        if self.fault.is_none() {
+            self.fault = Some((addr, violation));
+        }
+    }
+
+    /// The violation a data access would be, if any.
+    pub(crate) fn refuses(&self, kind: BusAccess, addr: u16) -> Option<Violation> {
+        let flags = self.flags(addr);
+        if flags & SUPERVISOR != 0 && !self.supervisor {
+            Some(Violation::Supervisor)
+        } else if flags & READ_ONLY != 0 && kind == BusAccess::Write {
+            Some(Violation::WriteProtect)
+        } else {
+            None
+        }
+    }
+
+    /// Checks a CPU access, returning whether it is blocked.
+    pub(crate) fn check(&mut self, kind: BusAccess, addr: u16) -> bool {
+        let Some(violation) = self.refuses(kind, addr) else {
+            return false;
+        };
+        self.violate(addr, violation);
+        true
+    }
+
+    /// Serves a CPU access to the register at `offset`.
+    pub(crate) fn register(&mut self, kind: BusAccess, offset: u16, val: &mut u8) {
+        let [lo, hi] = self.fault.map_or(0, |(addr, _)| addr).to_le_bytes();
+        match (kind, offset) {
+            (BusAccess::Read, 0) => *val = lo,
+            (BusAccess::Read, 1) => *val = hi,
+            (BusAccess::Read, STATUS) => *val = self.fault.map_or(0, |(_, v)| v.bit()),
+            (BusAccess::Read, MODE) => *val = u8::from(self.supervisor),
+            (BusAccess::Write, STATUS) => self.fault = None,
+            (BusAccess::Write, MODE) if *val == 0 => self.supervisor = false,
+            (BusAccess::Write, MODE) if !self.supervisor => {
+                self.violate(*MPU_PORTS.start() + MODE, Violation::Supervisor);
+            }
+            (BusAccess::Read, _) => *val = 0,
+            (BusAccess::Write, _) => {}
+        }
+    }
+
+    /// The mode and the latched violation, for a snapshot; the regions and
+    /// the response are the host's.
+    pub(crate) fn save_state(&self, state: &mut Vec<u8>) {
+        let (bit, [lo, hi]) = self
+            .fault
+            .map_or((0, [0, 0]), |(addr, v)| (v.bit(), addr.to_le_bytes()));
+        state.extend_from_slice(&[self.supervisor.into(), bit, lo, hi]);
+    }
+
+    pub(crate) fn load_state(&mut self, state: &[u8]) {
+        let &[supervisor, bit, lo, hi] = state else {
+            return;
+        };
+        let violation = match bit {
+            0 => None,
+            READ_ONLY => Some(Violation::WriteProtect),
+            NO_EXECUTE => Some(Violation::NoExecute),
+            SUPERVISOR => Some(Violation::Supervisor),
+            _ => return,
+        };
+        self.supervisor = supervisor != 0;
+        self.fault = violation.map(|v| (u16::from_le_bytes([lo, hi]), v));
+    }
+
+    /// Pages that need the host: the registers and the regions that guard
+    /// data accesses.
+    pub(crate) fn hooked_ranges(&self) -> impl Iterator<Item = &RangeInclusive<u16>> + '_ {
+        let guarded = self
+            .regions
+            .iter()
+            .filter(|(_, flags)| flags & (READ_ONLY | SUPERVISOR) != 0)
+            .map(|(range, _)| range);
+        std::iter::once(&MPU_PORTS).chain(guarded)
+    }
+}
+
+impl Bus {
+    /// Installs `mpu`, replacing any installed before.
+    pub fn set_mpu(&mut self, mpu: Mpu) {
+        self.mpu = Some(mpu);
+        self.pages_dirty = true;
+    }
+
+    /// Uninstalls the MPU and returns it.
+    pub fn remove_mpu(&mut self) -> Option<Mpu> {
+        self.pages_dirty = true;
+        self.mpu.take()
+    }
+
+    pub fn mpu(&self) -> Option<&Mpu> {
+        self.mpu.as_ref()
+    }
+
+    pub fn mpu_mut(&mut self) -> Option<&mut Mpu> {
+        self.mpu.as_mut()
+    }
+}
+
+impl Vm {
+    /// Checks the fetch of the instruction at `pc` before a step, or enters
+    /// supervisor mode if the step takes an interrupt instead.
+    pub(crate) fn mpu_fetch(&mut self, pc: u16, interrupt: bool) -> Result<(), VmError> {
+        let Some(mpu) = self.bus_mut().mpu.as_mut() else {
+            return Ok(());
+        };
+        if interrupt {
+            mpu.supervisor = true;
+            return Ok(());
+        }
+        if mpu.flags(pc) & NO_EXECUTE == 0 {
+            return Ok(());
+        }
+        mpu.violate(pc, Violation::NoExecute);
+        match mpu.response {
+            MpuResponse::Trap => self.take_mpu_fault(pc),
+            MpuResponse::Interrupt { .. } => Ok(()),
+        }
+    }
+
+    /// Reports a violation of the instruction at `pc` as an error if the
+    /// MPU traps.
+    pub(crate) fn take_mpu_fault(&mut self, pc: u16) -> Result<(), VmError> {
+        let Some(mpu) = self.bus_mut().mpu.as_mut() else {
+            return Ok(());
+        };
+        if mpu.response != MpuResponse::Trap {
+            return Ok(());
+        }
+        match mpu.fault.take() {
+            Some((addr, violation)) => Err(VmError::MpuFault {
+                pc,
+                addr,
+                violation,
+            }),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,389 @@
+//! Rollback netplay between two peers.
+//!
+//! Each peer runs its own [`Vm`] through a [`Netplay`] session, one
+//! [`Netplay::run_frame`] per frame with the local player's buttons. The
+//! session sends those buttons to the other peer and runs the frame
+//! straight away, guessing that the remote player still holds whatever they
+//! held last. When the real remote input for a frame arrives and differs
+//! from the guess, the session loads the state it saved before that frame
+//! and runs every frame since again with the corrected input. The core is
+//! deterministic, so both peers end up with the same machine.
+//!
+//! A session runs at most [`MAX_ROLLBACK`] frames ahead of the last remote
+//! input it has; further calls wait for the remote peer and run nothing.
+//! The CPU state, memory, controller and every device that
+//! [saves its state](crate::BusDevice::save_state) are saved and restored,
+//! so other mapped devices must not carry state between frames, and frames
+//! that are run again render, play their audio and call frame hooks again.
+//!
+//! How the two players' buttons reach the program is up to the caller: the
+//! `apply` callback gets both masks, indexed by player, before every frame.
+//! [`shared_controller`] merges them onto the one controller.
+//!
+//! Packets are sent over any [`Transport`], normally a connected
+//! [`UdpSocket`]. Every packet repeats all local input the peer has not
+//! acknowledged yet, so lost packets only delay it:
+//!
+//! | Size | Contents                                                  |
+//! | ---- | --------------------------------------------------------- |
+//! | 4    | magic `RVNP`                                              |
+//! | 1    | format version, currently 1                               |
+//! | 8    | remote frames received, acknowledging the peer's input    |
+//! | 8    | frame of the first input that follows                     |
+//! | 1    | input count                                               |
+//! | n    | button masks, one per frame                               |
+//!
+//! Multi-byte fields are little-endian. Packets that do not parse are
+//! dropped.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::net::{ToSocketAddrs, UdpSocket};
+
+use crate::error::VmError;
+use crate::input::{CONTROLLER, Controller};
+use crate::paged::PagedSnapshot;
+use crate::vm::Vm;
+
+/// Packet magic.
+pub const MAGIC: [u8; 4] = *b"RVNP";
+/// Packet format version written and accepted by this crate.
+pub const VERSION: u8 = 1;
+/// Frames a session runs ahead of the remote input it has.
+pub const MAX_ROLLBACK: u64 = 8;
+/// Players in a session.
+pub const PLAYERS: usize = 2;
+
+const HEADER_SIZE: usize = 4 + 1 + 8 + 8 + 1;
+/// Largest packet a session sends.
+const MAX_PACKET: usize = HEADER_SIZE + u8::MAX as usize;
+
+/// Carries packets between the two peers. Delivery may be lossy and out of
+/// order.
+pub trait Transport {
+    fn send(&mut self, packet: &[u8]) -> io::Result<()>;
+
+    /// Receives the next waiting packet into `buf`, returning its length,
+    /// or `None` if there is none.
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>>;
+}
+
+/// A non-blocking socket [connected](UdpSocket::connect) to the peer.
+impl Transport for UdpSocket {
+    fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+        match UdpSocket::send(self, packet) {
+            // The peer has not bound its socket yet.
+            Err(err) if err.kind() == ErrorKind::ConnectionRefused => Ok(()),
+            result => result.map(drop),
+        }
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        match UdpSocket::recv(self, buf) {
+            Ok(len) => Ok(Some(len)),
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    ErrorKind::WouldBlock | ErrorKind::ConnectionRefused
+                ) =>
+            {
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Why a session could not continue.
+#[derive(Debug)]
+pub enum NetplayError {
+    /// The transport failed.
+    Io(io::Error),
+    /// A frame failed to run, possibly while running it again.
+    Vm(VmError),
+}
+
+impl fmt::Display for NetplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "netplay transport failed: {err}"),
+            Self::Vm(err) => write!(f, "netplay frame failed: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for NetplayError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Vm(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for NetplayError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<VmError> for NetplayError {
+    fn from(err: VmError) -> Self {
+        Self::Vm(err)
+    }
+}
+
+/// What [`Netplay::run_frame`] did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Advance {
+    /// The frame ran, after running `resimulated` earlier frames again to
+    /// correct mispredicted remote input.
+    Ran { resimulated: u64 },
+    /// The session is [`MAX_ROLLBACK`] frames ahead of the remote input and
+    /// ran nothing; call again with the same input.
+    Waiting,
+}
+
+/// Merges both players' buttons onto the one controller, for `apply`.
+pub fn shared_controller(vm: &mut Vm, inputs: [u8; PLAYERS]) {
+    vm.set_buttons(inputs[0] | inputs[1]);
+}
+
+/// Hands both players' buttons to the machine before a frame.
+type Apply = Box<dyn FnMut(&mut Vm, [u8; PLAYERS])>;
+
+/// The machine state saved before a frame that may be run again.
+struct SavedFrame {
+    snapshot: PagedSnapshot,
+    controller: Option<Controller>,
+}
+
+/// One peer's side of a rollback session.
+pub struct Netplay<T: Transport = UdpSocket> {
+    transport: T,
+    player: usize,
+    apply: Apply,
+    /// Next frame to run.
+    frame: u64,
+    /// Local input by frame, kept until the peer acknowledges it and no
+    /// rollback can need it.
+    local: BTreeMap<u64, u8>,
+    /// Remote input received so far by frame, kept while a rollback can
+    /// still need it.
+    remote: BTreeMap<u64, u8>,
+    /// Remote frames received, all of them below this.
+    confirmed: u64,
+    /// Local frames the peer has acknowledged.
+    acked: u64,
+    /// Remote input guessed for frames at or past `confirmed` that ran.
+    predicted: BTreeMap<u64, u8>,
+    /// State before every frame at or past `confirmed` that ran.
+    saved: BTreeMap<u64, SavedFrame>,
+    rollbacks: u64,
+}
+
+impl Netplay<UdpSocket> {
+    /// Binds a UDP socket to `local` and connects it to `peer`.
+    pub fn udp(
+        local: impl ToSocketAddrs,
+        peer: impl ToSocketAddrs,
+        player: usize,
+        apply: impl FnMut(&mut Vm, [u8; PLAYERS]) + 'static,
+    ) -> io::Result<Self> {
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(peer)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self::new(socket, player, apply))
+    }
+}
+
+impl<T: Transport> Netplay<T> {
+    /// Starts a session as `player`, 0 or 1, with the other peer at the
+    /// far end of `transport`. Both peers must start from the same state.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `player` is not 0 or 1.
+    pub fn new(
+        transport: T,
+        player: usize,
+        apply: impl FnMut(&mut Vm, [u8; PLAYERS]) + 'static,
+    ) -> Self {
+        assert!(player < PLAYERS, "netplay supports players 0 and 1");
+        Self {
+            transport,
+            player,
+            apply: Box::new(apply),
+            frame: 0,
+            local: BTreeMap::new(),
+            remote: BTreeMap::new(),
+            confirmed: 0,
+            acked: 0,
+            predicted: BTreeMap::new(),
+            saved: BTreeMap::new(),
+            rollbacks: 0,
+        }
+    }
+
+    /// Frames run so far, not counting frames run again.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Remote frames received; every frame below this ran with the real
+    /// remote input.
+    pub fn confirmed_frame(&self) -> u64 {
+        self.confirmed
+    }
+
+    /// Mispredictions corrected so far.
+    pub fn rollbacks(&self) -> u64 {
+        self.rollbacks
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Runs the next frame on `vm` with `buttons` held by the local player,
+    /// first running earlier frames again if remote input has arrived that
+    /// proves a guess wrong. `vm` must be the machine the session started
+    /// on and must not run frames outside the session.
+    pub fn run_frame(&mut self, vm: &mut Vm, buttons: u8) -> Result<Advance, NetplayError> {
+        let resimulated = self.sync(vm)?;
+        if self.frame >= self.confirmed + MAX_ROLLBACK {
+            self.send()?;
+            return Ok(Advance::Waiting);
+        }
+        let frame = self.frame;
+        self.local.insert(frame, buttons);
+        self.run(vm, frame)?;
+        self.frame += 1;
+        self.send()?;
+        Ok(Advance::Ran { resimulated })
+    }
+
+    /// Takes in every waiting packet and runs frames again where they prove
+    /// a guess wrong, without running a new frame, returning the frames run
+    /// again. [`Netplay::run_frame`] starts with this.
+    pub fn sync(&mut self, vm: &mut Vm) -> Result<u64, NetplayError> {
+        let mut buf = [0; MAX_PACKET];
+        let mut rollback = None;
+        while let Some(len) = self.transport.recv(&mut buf)? {
+            let Some((ack, first, inputs)) = parse_packet(&buf[..len]) else {
+                continue;
+            };
+            self.acked = self.acked.max(ack);
+            for (frame, &input) in (first..).zip(inputs) {
+                if frame != self.confirmed {
+                    continue;
+                }
+                if self
+                    .predicted
+                    .get(&frame)
+                    .is_some_and(|&guess| guess != input)
+                {
+                    rollback.get_or_insert(frame);
+                }
+                self.remote.insert(frame, input);
+                self.confirmed += 1;
+            }
+        }
+
+        let mut resimulated = 0;
+        if let Some(from) = rollback {
+            self.rollbacks += 1;
+            let saved = &self.saved[&from];
+            vm.load_paged(&saved.snapshot);
+            if let (Some(controller), Some(device)) = (
+                &saved.controller,
+                vm.bus_mut().device_mut::<Controller>(CONTROLLER),
+            ) {
+                device.clone_from(controller);
+            }
+            for frame in from..self.frame {
+                self.run(vm, frame)?;
+                resimulated += 1;
+            }
+        }
+
+        // Frames below `confirmed` can no longer roll back. Keep remote
+        // input for frames yet to run, and the last of it to predict from.
+        let keep = self.frame.min(self.confirmed.saturating_sub(1));
+        self.remote = self.remote.split_off(&keep);
+        self.predicted = self.predicted.split_off(&self.confirmed);
+        self.saved = self.saved.split_off(&self.confirmed);
+        self.local = self.local.split_off(&self.acked.min(self.confirmed));
+        Ok(resimulated)
+    }
+
+    /// Runs `frame` with the local and best known remote input, saving the
+    /// state before it if that input is a guess.
+    fn run(&mut self, vm: &mut Vm, frame: u64) -> Result<(), VmError> {
+        let remote = match self.remote.get(&frame) {
+            Some(&input) => {
+                self.predicted.remove(&frame);
+                self.saved.remove(&frame);
+                input
+            }
+            None => {
+                let guess = self.remote.values().next_back().copied().unwrap_or(0);
+                self.predicted.insert(frame, guess);
+                self.saved.insert(
+                    frame,
+                    SavedFrame {
+                        snapshot: vm.save_paged(),
+                        controller: vm.bus().device::<Controller>(CONTROLLER).cloned(),
+                    },
+                );
+                guess
+            }
+        };
+        let mut inputs = [remote; PLAYERS];
+        inputs[self.player] = self.local[&frame];
+        (self.apply)(vm, inputs);
+        vm.run_frame()
+    }
+
+    /// Sends the local input the peer has not acknowledged.
+    fn send(&mut self) -> io::Result<()> {
+        let inputs: Vec<u8> = self
+            .local
+            .range(self.acked..)
+            .map(|(_, &input)| input)
+            .take(u8::MAX as usize)
+            .collect();
+        let mut packet = Vec::with_capacity(HEADER_SIZE + inputs.len());
+        packet.extend_from_slice(&MAGIC);
+        packet.push(VERSION);
+        packet.extend_from_slice(&self.confirmed.to_le_bytes());
+        packet.extend_from_slice(&self.acked.to_le_bytes());
+        packet.push(inputs.len() as u8);
+        packet.extend_from_slice(&inputs);
+        self.transport.send(&packet)
+    }
+}
+
+impl<T: Transport> fmt::Debug for Netplay<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Netplay")
+            .field("player", &self.player)
+            .field("frame", &self.frame)
+            .field("confirmed", &self.confirmed)
+            .field("acked", &self.acked)
+            .field("rollbacks", &self.rollbacks)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Splits a packet into the acknowledgement, first frame and inputs.
+fn parse_packet(packet: &[u8]) -> Option<(u64, u64, &[u8])> {
+    let (header, inputs) = packet.split_first_chunk::<HEADER_SIZE>()?;
+    if header[..4] != MAGIC || header[4] != VERSION {
+        return None;
+    }
+    let ack = u64::from_le_bytes(header[5..13].try_into().ok()?);
+    let first = u64::from_le_bytes(header[13..21].try_into().ok()?);
+    (inputs.len() == usize::from(header[21])).then_some((ack, first, inputs))
+}
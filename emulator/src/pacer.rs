@@ -0,0 +1,185 @@
+//! Frame pacing for frontends that draw on the host's refresh.
+//!
+//! A frontend that waits for vsync gets called at the display's rate, which
+//! is rarely exactly [`FRAME_RATE`](crate::vm::FRAME_RATE): 59.94 Hz,
+//! 75 Hz, 120 Hz and 144 Hz panels are all common. A [`FramePacer`] says,
+//! at each host refresh, how many frames to run and whether the last
+//! picture should simply be shown again.
+//!
+//! When the display runs close to a whole multiple or fraction of the frame
+//! rate, within [`SYNC_TOLERANCE`], the pacer locks to it: every frame is
+//! shown for the same number of refreshes, so motion stays smooth, and the
+//! machine runs that little bit fast or slow. Otherwise it runs frames as
+//! emulated time falls due, repeating or dropping pictures when the two
+//! rates beat against each other.
+//!
+//! Either way the display clock, not the sound card's, now sets the pace,
+//! and the two drift apart. Reporting how much sound is queued with
+//! [`FramePacer::audio_queued`] bends the resampling of the audio callback
+//! by up to [`MAX_SKEW`] to keep the queue at its target, too little to
+//! hear as a change of pitch:
+//!
+//! ```no_run
+//! # use emulator::{pacer::FramePacer, Vm};
+//! # fn wait_for_vsync() {}
+//! # fn queued_samples() -> usize { 0 }
+//! # fn present(_: &[u8]) {}
+//! let mut vm = Vm::new();
+//! let mut pacer = FramePacer::new(59.94, 60.0);
+//! loop {
+//!     wait_for_vsync();
+//!     pacer.audio_queued(queued_samples(), 2048);
+//!     pacer.apply(&mut vm);
+//!     let pace = pacer.refresh();
+//!     for _ in 0..pace.frames {
+//!         vm.run_frame().unwrap();
+//!     }
+//!     present(vm.framebuffer());
+//! }
+//! ```
+
+use crate::vm::Vm;
+
+/// How far the host refresh may be from a multiple or fraction of the
+/// frame rate for the pacer to lock to it, as a fraction of the rate.
+pub const SYNC_TOLERANCE: f64 = 0.01;
+/// The most the audio queue may speed up or slow down resampling, as a
+/// fraction of the speed.
+pub const MAX_SKEW: f64 = 0.005;
+
+/// What to do at one host refresh.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pace {
+    /// Frames to run before presenting.
+    pub frames: u32,
+    /// No frame is due, so the picture already shown is shown again.
+    pub repeat: bool,
+    /// Frames run whose pictures nobody sees, all but the last.
+    pub dropped: u32,
+}
+
+impl Pace {
+    fn running(frames: u32) -> Self {
+        Self {
+            frames,
+            repeat: frames == 0,
+            dropped: frames.saturating_sub(1),
+        }
+    }
+}
+
+/// Decides at each host refresh how many frames to run.
+#[derive(Debug, Clone)]
+pub struct FramePacer {
+    host_hz: f64,
+    frame_rate: f64,
+    /// Frames run and refreshes they span, when locked.
+    lock: Option<(u32, u32)>,
+    /// Refreshes and frames since pacing started, counted rather than
+    /// summed so rounding cannot wear frames away.
+    refreshes: u64,
+    frames: u64,
+    /// Resampling correction from the audio queue.
+    skew: f64,
+}
+
+impl FramePacer {
+    /// A pacer for a display refreshing `host_hz` times a second and a
+    /// machine running `frame_rate` frames a second, usually
+    /// [`FRAME_RATE`](crate::vm::FRAME_RATE).
+    ///
+    /// # Panics
+    ///
+    /// Panics unless both rates are finite and positive.
+    pub fn new(host_hz: f64, frame_rate: f64) -> Self {
+        assert!(
+            host_hz.is_finite() && host_hz > 0.0 && frame_rate.is_finite() && frame_rate > 0.0,
+            "rates must be finite and positive, not {host_hz} and {frame_rate}"
+        );
+        // Refreshes per frame, or frames per refresh, close to a whole number.
+        let ratio = host_hz / frame_rate;
+        let (refreshes, frames) = (ratio.round(), ratio.recip().round());
+        let lock = if refreshes >= 1.0 && (ratio / refreshes - 1.0).abs() <= SYNC_TOLERANCE {
+            Some((1, refreshes as u32))
+        } else if frames >= 2.0 && (ratio * frames - 1.0).abs() <= SYNC_TOLERANCE {
+            Some((frames as u32, 1))
+        } else {
+            None
+        };
+        Self {
+            host_hz,
+            frame_rate,
+            lock,
+            refreshes: 0,
+            frames: 0,
+            skew: 0.0,
+        }
+    }
+
+    pub fn host_hz(&self) -> f64 {
+        self.host_hz
+    }
+
+    pub fn frame_rate(&self) -> f64 {
+        self.frame_rate
+    }
+
+    /// Whether every frame is shown for the same number of refreshes, the
+    /// machine running off real time to match.
+    pub fn is_locked(&self) -> bool {
+        self.lock.is_some()
+    }
+
+    /// Emulated time per host second, 1 for real time.
+    ///
+    /// Locked to a refresh a little off the frame rate, this is a little off
+    /// 1; the audio queue's correction comes on top.
+    pub fn speed(&self) -> f64 {
+        let base = match self.lock {
+            Some((frames, refreshes)) => {
+                self.host_hz * f64::from(frames) / f64::from(refreshes) / self.frame_rate
+            }
+            None => 1.0,
+        };
+        base * (1.0 + self.skew)
+    }
+
+    /// Called once per host refresh: how many frames to run now.
+    pub fn refresh(&mut self) -> Pace {
+        self.refreshes += 1;
+        let due = match self.lock {
+            Some((frames, refreshes)) => self.refreshes / u64::from(refreshes) * u64::from(frames),
+            None => (self.refreshes as f64 * self.frame_rate / self.host_hz) as u64,
+        };
+        let frames = due - self.frames;
+        self.frames = due;
+        Pace::running(frames as u32)
+    }
+
+    /// Starts pacing afresh, after the frontend stopped calling
+    /// [`FramePacer::refresh`] for a while.
+    pub fn reset(&mut self) {
+        self.refreshes = 0;
+        self.frames = 0;
+        self.skew = 0.0;
+    }
+
+    /// Steers the audio resampling toward `target` queued samples, given
+    /// that `queued` are waiting to play: a short queue slows the
+    /// resampling to fill it, a long one speeds it up to drain it.
+    pub fn audio_queued(&mut self, queued: usize, target: usize) {
+        if target == 0 {
+            self.skew = 0.0;
+            return;
+        }
+        let excess = (queued as f64 - target as f64) / target as f64;
+        self.skew = excess.clamp(-1.0, 1.0) * MAX_SKEW;
+    }
+
+    /// Makes `vm` resample its audio callback's samples to
+    /// [`FramePacer::speed`], so that each frame's sound lasts as long as
+    /// the host takes to show it.
+    pub fn apply(&self, vm: &mut Vm) {
+        vm.audio.playback.speed = Some(self.speed() as f32);
+    }
+}
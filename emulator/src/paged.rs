@@ -0,0 +1,222 @@
+//! Copy-on-write save states.
+//!
+//! A [`PagedSnapshot`] holds the address space as [`PAGES`] shared pages of
+//! 256 bytes. The kernel marks every page the CPU stores to, and
+//! DMA and host writes do the same, so [`Vm::save_paged`] only copies the
+//! pages written since the last paged snapshot was taken or restored and
+//! shares the others with it. Taking one every frame, as
+//! [rewind](crate::rewind) and rollback netplay do, costs the pages a frame
+//! dirties rather than the whole 64 KiB, and restoring one only copies back
+//! the pages that differ:
+//!
+//! ```
+//! # use emulator::Vm;
+//! let mut vm = Vm::new();
+//! let before = vm.save_paged();
+//! vm.write(0x0200, 1);
+//! let after = vm.save_paged();
+//! assert!(!after.shares_page(&before, 0x02));
+//! assert!(after.shares_page(&before, 0x03));
+//! vm.load_paged(&before);
+//! assert_eq!(vm.read(0x0200), 0);
+//! ```
+//!
+//! Paged snapshots hold the same state as a [`Snapshot`] and convert to and
+//! from one. Writes through [`Vm::memory_mut`] count as touching every page,
+//! and once the C API or libretro has handed out a pointer to memory, whose
+//! writes cannot be seen, pages are compared byte for byte instead.
+
+use std::sync::Arc;
+
+use crate::bus::PAGE_SIZE;
+use crate::error::VmError;
+use crate::ffi::RVM_MEM_SIZE;
+use crate::irq::IrqState;
+use crate::scheduler::ScheduledEvent;
+use crate::snapshot::{DeviceState, Snapshot};
+use crate::vm::{Registers, Vm};
+
+/// Pages in the address space.
+pub const PAGES: usize = RVM_MEM_SIZE / PAGE_SIZE;
+
+/// One page of memory.
+pub type Page = [u8; PAGE_SIZE];
+
+/// A captured machine state whose memory pages are shared with the
+/// snapshots taken before and after it; see the [module docs](self).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PagedSnapshot {
+    pub registers: Registers,
+    pub cycles: u32,
+    /// Frames completed, see [`Vm::frame`].
+    pub frame: u64,
+    pub irq: IrqState,
+    pages: Vec<Arc<Page>>,
+    /// What the devices that save state saved, as for a [`Snapshot`].
+    pub devices: Vec<DeviceState>,
+    /// The host's pending scheduled events, as for a [`Snapshot`].
+    pub events: Vec<ScheduledEvent>,
+}
+
+impl PagedSnapshot {
+    /// The contents of page `page`.
+    pub fn page(&self, page: u8) -> &Page {
+        &self.pages[page as usize]
+    }
+
+    /// The byte at `addr`.
+    pub fn read(&self, addr: u16) -> u8 {
+        self.pages[addr as usize / PAGE_SIZE][addr as usize % PAGE_SIZE]
+    }
+
+    /// Whether page `page` is the same copy in both snapshots rather than
+    /// merely equal.
+    pub fn shares_page(&self, other: &Self, page: u8) -> bool {
+        Arc::ptr_eq(&self.pages[page as usize], &other.pages[page as usize])
+    }
+
+    /// The pages, in address order.
+    pub(crate) fn pages(&self) -> &[Arc<Page>] {
+        &self.pages
+    }
+
+    /// A flat copy of the state.
+    pub fn to_snapshot(&self) -> Snapshot {
+        Snapshot {
+            registers: self.registers,
+            cycles: self.cycles,
+            frame: self.frame,
+            irq: self.irq,
+            memory: self
+                .pages
+                .iter()
+                .flat_map(|page| page.iter().copied())
+                .collect(),
+            devices: self.devices.clone(),
+            events: self.events.clone(),
+        }
+    }
+}
+
+impl From<&PagedSnapshot> for Snapshot {
+    fn from(snapshot: &PagedSnapshot) -> Self {
+        snapshot.to_snapshot()
+    }
+}
+
+impl TryFrom<&Snapshot> for PagedSnapshot {
+    type Error = VmError;
+
+    /// Splits a snapshot into pages, none shared, failing if its memory is
+    /// not `RVM_MEM_SIZE` bytes.
+    fn try_from(snapshot: &Snapshot) -> Result<Self, VmError> {
+        if snapshot.memory.len() != RVM_MEM_SIZE {
+            return Err(VmError::InvalidSnapshot("memory size mismatch"));
+        }
+        Ok(Self {
+            registers: snapshot.registers,
+            cycles: snapshot.cycles,
+            frame: snapshot.frame,
+            irq: snapshot.irq,
+            pages: split(&snapshot.memory),
+            devices: snapshot.devices.clone(),
+            events: snapshot.events.clone(),
+        })
+    }
+}
+
+/// The pages of the last paged snapshot taken or restored, which memory
+/// still matches except where the dirty bits say otherwise.
+#[derive(Debug, Default)]
+pub(crate) struct PageCache {
+    /// Empty until the first paged snapshot.
+    pages: Vec<Arc<Page>>,
+    /// Set once a raw pointer to memory has been handed out.
+    pub(crate) escaped: bool,
+}
+
+fn split(memory: &[u8]) -> Vec<Arc<Page>> {
+    memory
+        .chunks_exact(PAGE_SIZE)
+        .map(|page| Arc::new(page.try_into().expect("chunks are a page long")))
+        .collect()
+}
+
+impl Vm {
+    /// Captures the current machine state, sharing every page not written
+    /// since the last paged snapshot with it.
+    pub fn save_paged(&mut self) -> PagedSnapshot {
+        let dirty = std::mem::replace(&mut self.cpu.dirty_pages, [0; PAGES]);
+        let mut pages = std::mem::take(&mut self.paged.pages);
+        let memory = self.memory();
+        if pages.is_empty() {
+            pages = split(memory);
+        } else {
+            for (index, (page, bytes)) in pages
+                .iter_mut()
+                .zip(memory.chunks_exact(PAGE_SIZE))
+                .enumerate()
+            {
+                // Stores of the value already there leave the page shared.
+                if (self.paged.escaped || dirty[index] != 0) && page[..] != *bytes {
+                    *page = Arc::new(bytes.try_into().expect("chunks are a page long"));
+                }
+            }
+        }
+        self.paged.pages = pages.clone();
+        PagedSnapshot {
+            registers: self.registers(),
+            cycles: self.cycles(),
+            frame: self.frame(),
+            irq: self.saved_irq(),
+            pages,
+            devices: self.bus().save_devices(),
+            events: self.save_events(),
+        }
+    }
+
+    /// Restores a state captured by [`Vm::save_paged`], copying back only
+    /// the pages that differ from memory.
+    ///
+    /// Paged snapshots are whole by construction, so unlike
+    /// [`Vm::load_state`] this cannot fail. Rewind history is cleared as
+    /// for `load_state`.
+    pub fn load_paged(&mut self, snapshot: &PagedSnapshot) {
+        self.restore_paged(snapshot);
+        if let Some(rewind) = &mut self.rewind {
+            rewind.clear();
+        }
+    }
+
+    /// Applies a paged snapshot, keeping rewind history.
+    pub(crate) fn restore_paged(&mut self, snapshot: &PagedSnapshot) {
+        self.abandon_frame();
+        let dirty = std::mem::replace(&mut self.cpu.dirty_pages, [0; PAGES]);
+        let cached = std::mem::replace(&mut self.paged.pages, snapshot.pages.clone());
+        let escaped = self.paged.escaped;
+        let memory = self.memory_untracked_mut();
+        for (index, (page, bytes)) in snapshot
+            .pages
+            .iter()
+            .zip(memory.chunks_exact_mut(PAGE_SIZE))
+            .enumerate()
+        {
+            let current = cached
+                .get(index)
+                .is_some_and(|cached| Arc::ptr_eq(cached, page))
+                && dirty[index] == 0
+                && !escaped;
+            if !current {
+                bytes.copy_from_slice(&page[..]);
+            }
+        }
+        self.bus_mut().load_devices(&snapshot.devices);
+        self.restore_machine(
+            snapshot.registers,
+            snapshot.cycles,
+            snapshot.frame,
+            snapshot.irq,
+        );
+        self.restore_events(&snapshot.events);
+    }
+}
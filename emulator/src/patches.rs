@@ -0,0 +1,244 @@
+//! RAM freezes and ROM patches, for cheats and for hotfixing test ROMs.
+//!
+//! A patch list has one `addr = value` pair per line, numbers written as
+//! in a [symbol map](crate::symbols) and `;` starting a comment:
+//!
+//! ```text
+//! $0042 = $63   ; lives
+//! $C010 = $EA
+//! ```
+//!
+//! The same list can freeze RAM with [`Vm::freeze`], which stores each
+//! value again at the start of every frame, or patch a [`Rom`] before it is
+//! loaded with [`Rom::patch`]. [`Rom::apply_ips`] applies an [`Ips`] patch
+//! file to the bank data instead.
+
+use std::fmt;
+
+use crate::rom::Rom;
+use crate::symbols::parse_value;
+use crate::vm::Vm;
+
+/// One byte to store at an address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Patch {
+    pub addr: u16,
+    pub value: u8,
+}
+
+/// Why a patch could not be parsed or applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchError {
+    /// A line of a patch list is not `addr = value`; lines are 1-based.
+    Syntax { line: usize },
+    /// The address is not a 16-bit number.
+    BadAddress { line: usize, text: String },
+    /// The value is not an 8-bit number.
+    BadValue { line: usize, text: String },
+    /// The file does not start with the IPS magic `PATCH`.
+    BadMagic,
+    /// The IPS file ends in the middle of a record or without its `EOF`
+    /// marker.
+    Truncated,
+    /// An address patch lies outside the ROM.
+    NotInRom(u16),
+    /// An IPS record starting at `offset` runs past the end of the data.
+    PastEnd { offset: usize },
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Syntax { line } => write!(f, "line {line}: expected `addr = value`"),
+            Self::BadAddress { line, text } => {
+                write!(f, "line {line}: `{text}` is not a 16-bit address")
+            }
+            Self::BadValue { line, text } => {
+                write!(f, "line {line}: `{text}` is not an 8-bit value")
+            }
+            Self::BadMagic => write!(f, "not an IPS patch"),
+            Self::Truncated => write!(f, "IPS patch is truncated"),
+            Self::NotInRom(addr) => write!(f, "address ${addr:04X} is not in the ROM"),
+            Self::PastEnd { offset } => {
+                write!(f, "IPS record at offset 0x{offset:06X} runs past the end")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+/// Parses a patch list.
+pub fn parse_list(text: &str) -> Result<Vec<Patch>, PatchError> {
+    let mut patches = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line_no = index + 1;
+        let line = line.split(';').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (addr, value) = line
+            .split_once('=')
+            .ok_or(PatchError::Syntax { line: line_no })?;
+        let (addr, value) = (addr.trim(), value.trim());
+        let addr = parse_value(addr).ok_or_else(|| PatchError::BadAddress {
+            line: line_no,
+            text: addr.into(),
+        })?;
+        let value = parse_value(value)
+            .and_then(|value| u8::try_from(value).ok())
+            .ok_or_else(|| PatchError::BadValue {
+                line: line_no,
+                text: value.into(),
+            })?;
+        patches.push(Patch { addr, value });
+    }
+    Ok(patches)
+}
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+
+/// The bytes one IPS record stores.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Hunk {
+    Bytes(Vec<u8>),
+    /// `len` copies of `value`.
+    Run {
+        len: usize,
+        value: u8,
+    },
+}
+
+impl Hunk {
+    fn len(&self) -> usize {
+        match self {
+            Self::Bytes(bytes) => bytes.len(),
+            Self::Run { len, .. } => *len,
+        }
+    }
+}
+
+/// A parsed IPS patch: `PATCH`, then records of a 24-bit big-endian offset
+/// and a 16-bit length followed by that many bytes, or by a 16-bit count
+/// and a byte to repeat when the length is 0, then `EOF`.
+///
+/// Anything after `EOF`, such as the truncation extension, is ignored:
+/// ROMs keep their size.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ips {
+    records: Vec<(usize, Hunk)>,
+}
+
+impl Ips {
+    pub fn parse(bytes: &[u8]) -> Result<Self, PatchError> {
+        let mut rest = bytes.strip_prefix(IPS_MAGIC).ok_or(PatchError::BadMagic)?;
+        let mut take = |len: usize| -> Result<&[u8], PatchError> {
+            let (taken, tail) = rest.split_at_checked(len).ok_or(PatchError::Truncated)?;
+            rest = tail;
+            Ok(taken)
+        };
+        let mut records = Vec::new();
+        loop {
+            let offset = take(3)?;
+            if offset == IPS_EOF {
+                return Ok(Self { records });
+            }
+            let offset =
+                usize::from(offset[0]) << 16 | usize::from(offset[1]) << 8 | usize::from(offset[2]);
+            let len = take(2)?;
+            let hunk = match usize::from(u16::from_be_bytes([len[0], len[1]])) {
+                0 => {
+                    let run = take(3)?;
+                    Hunk::Run {
+                        len: usize::from(u16::from_be_bytes([run[0], run[1]])),
+                        value: run[2],
+                    }
+                }
+                len => Hunk::Bytes(take(len)?.to_vec()),
+            };
+            records.push((offset, hunk));
+        }
+    }
+
+    /// Applies every record to `data`, checking first that they all fit so
+    /// a failed patch changes nothing.
+    pub fn apply(&self, data: &mut [u8]) -> Result<(), PatchError> {
+        if let Some((offset, _)) = self
+            .records
+            .iter()
+            .find(|(offset, hunk)| offset + hunk.len() > data.len())
+        {
+            return Err(PatchError::PastEnd { offset: *offset });
+        }
+        for (offset, hunk) in &self.records {
+            let dest = &mut data[*offset..offset + hunk.len()];
+            match hunk {
+                Hunk::Bytes(bytes) => dest.copy_from_slice(bytes),
+                Hunk::Run { value, .. } => dest.fill(*value),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Rom {
+    /// Stores each patch in the bank data, addressed as the CPU sees the
+    /// ROM once loaded, with bank 0 in a banked ROM's switchable window.
+    /// Fails without changing anything if one lies below [`Rom::base`].
+    /// The reset vector is still replaced by the entry point when the ROM
+    /// loads.
+    pub fn patch(&mut self, patches: &[Patch]) -> Result<(), PatchError> {
+        if let Some(patch) = patches.iter().find(|p| self.offset(p.addr).is_none()) {
+            return Err(PatchError::NotInRom(patch.addr));
+        }
+        for patch in patches {
+            let offset = self.offset(patch.addr).expect("checked above");
+            self.data[offset] = patch.value;
+        }
+        Ok(())
+    }
+
+    /// Applies `ips` to the bank data, with offsets counted from the start
+    /// of the first bank rather than of the file, since the header is
+    /// rebuilt from the data.
+    pub fn apply_ips(&mut self, ips: &Ips) -> Result<(), PatchError> {
+        ips.apply(&mut self.data)
+    }
+}
+
+impl Vm {
+    /// Stores `value` at `addr` now and again at the start of every frame,
+    /// replacing any freeze already on `addr`. Freezes write RAM as
+    /// [`Vm::write`] does, so they bypass devices and ROM protection.
+    pub fn freeze(&mut self, addr: u16, value: u8) {
+        self.unfreeze(addr);
+        self.freezes.push(Patch { addr, value });
+        self.write(addr, value);
+    }
+
+    /// Stops freezing `addr`, returning `false` if it was not frozen. The
+    /// frozen value stays until the program changes it.
+    pub fn unfreeze(&mut self, addr: u16) -> bool {
+        let before = self.freezes.len();
+        self.freezes.retain(|p| p.addr != addr);
+        self.freezes.len() != before
+    }
+
+    /// Active freezes in the order they were added.
+    pub fn freezes(&self) -> &[Patch] {
+        &self.freezes
+    }
+
+    pub fn clear_freezes(&mut self) {
+        self.freezes.clear();
+    }
+
+    /// Called by [`Vm::run_frame`] before the frame runs.
+    pub(crate) fn apply_freezes(&mut self) {
+        for index in 0..self.freezes.len() {
+            let Patch { addr, value } = self.freezes[index];
+            self.write(addr, value);
+        }
+    }
+}
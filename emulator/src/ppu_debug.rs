@@ -0,0 +1,230 @@
+//! Decoded views of video memory for VRAM viewers.
+//!
+//! [`Vm::ppu_debug`] reads the display's memory the way the PPU does and
+//! hands back tiles, sprites, palettes and the background tilemap as plain
+//! values, so a frontend can show them without knowing the
+//! [layout](crate::display):
+//!
+//! ```
+//! # use emulator::{display::{SPRITES, TILE_DATA}, Vm};
+//! let mut vm = Vm::new();
+//! vm.write(TILE_DATA + 16, 0x80); // tile 1, top-left pixel value 1
+//! vm.load(SPRITES, &[10, 20, 1, 0b01]).unwrap();
+//! let ppu = vm.ppu_debug();
+//! assert_eq!(ppu.tile(1).pixel(0, 0), 1);
+//! let sprite = ppu.sprite(0);
+//! assert_eq!((sprite.x, sprite.y, sprite.tile), (20, 10, 1));
+//! assert!(sprite.flip_x());
+//! ```
+//!
+//! The views are taken from memory as it is now, not as it was when the
+//! last frame was rendered. The `*_rgba` images use the shades of
+//! [`PALETTE`].
+
+use crate::display::{
+    self, BG_PALETTE, CTRL, CTRL_BACKGROUND, CTRL_SPRITES, HEIGHT, MAP_HEIGHT, MAP_WIDTH, PALETTE,
+    SCROLL_X, SCROLL_Y, SPRITE_COUNT, SPRITE_PALETTE, SPRITES, TILE_COUNT, TILEMAP, WIDTH,
+};
+use crate::vm::Vm;
+
+/// Tiles per row of [`PpuDebug::tile_sheet_rgba`].
+pub const SHEET_COLUMNS: usize = 8;
+
+/// One 8×8 tile, as pixel values 0–3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    pub index: u8,
+    /// Rows top to bottom, pixels left to right.
+    pub pixels: [[u8; 8]; 8],
+}
+
+impl Tile {
+    /// The value of the pixel at `x`, `y`.
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y][x]
+    }
+}
+
+/// One entry of sprite attribute memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sprite {
+    pub index: u8,
+    /// Top row on screen.
+    pub y: u8,
+    /// Leftmost column on screen.
+    pub x: u8,
+    pub tile: u8,
+    pub attributes: u8,
+}
+
+impl Sprite {
+    /// Whether the tile is mirrored left to right.
+    pub fn flip_x(&self) -> bool {
+        self.attributes & 0b01 != 0
+    }
+
+    /// Whether the tile is mirrored top to bottom.
+    pub fn flip_y(&self) -> bool {
+        self.attributes & 0b10 != 0
+    }
+
+    /// Whether any of the sprite lies on the screen.
+    pub fn on_screen(&self) -> bool {
+        (self.x as usize) < WIDTH && (self.y as usize) < HEIGHT
+    }
+}
+
+/// A palette register decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    /// The shade of each pixel value, 0 white to 3 black.
+    pub shades: [u8; 4],
+}
+
+impl Palette {
+    fn decode(register: u8) -> Self {
+        Self {
+            shades: [0, 1, 2, 3].map(|value| display::shade(register, value)),
+        }
+    }
+
+    /// The RGBA colour of pixel value `value`.
+    pub fn rgba(&self, value: u8) -> [u8; 4] {
+        PALETTE[self.shades[value as usize & 0b11] as usize]
+    }
+}
+
+/// The background tilemap: a tile index per cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tilemap {
+    /// Rows top to bottom, cells left to right.
+    pub cells: [[u8; MAP_WIDTH]; MAP_HEIGHT],
+    pub scroll_x: u8,
+    pub scroll_y: u8,
+}
+
+/// Decoded video memory, borrowed from a [`Vm`]; see the
+/// [module docs](self).
+#[derive(Debug, Clone, Copy)]
+pub struct PpuDebug<'a> {
+    memory: &'a [u8],
+}
+
+impl PpuDebug<'_> {
+    /// Tile `index`, which wraps at [`TILE_COUNT`] as it does when drawn.
+    pub fn tile(&self, index: u8) -> Tile {
+        let mut pixels = [[0; 8]; 8];
+        for (y, row) in pixels.iter_mut().enumerate() {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = display::tile_pixel(self.memory, index, x, y);
+            }
+        }
+        Tile {
+            index: index % TILE_COUNT as u8,
+            pixels,
+        }
+    }
+
+    /// Every tile, in order.
+    pub fn tiles(&self) -> Vec<Tile> {
+        (0..TILE_COUNT as u8)
+            .map(|index| self.tile(index))
+            .collect()
+    }
+
+    /// Sprite `index`, below [`SPRITE_COUNT`].
+    ///
+    /// # Panics
+    ///
+    /// If `index` is [`SPRITE_COUNT`] or more.
+    pub fn sprite(&self, index: usize) -> Sprite {
+        assert!(index < SPRITE_COUNT, "there are {SPRITE_COUNT} sprites");
+        let entry = &self.memory[SPRITES as usize + index * 4..][..4];
+        Sprite {
+            index: index as u8,
+            y: entry[0],
+            x: entry[1],
+            tile: entry[2],
+            attributes: entry[3],
+        }
+    }
+
+    /// Every sprite, in order; lower-numbered sprites are drawn on top.
+    pub fn sprites(&self) -> Vec<Sprite> {
+        (0..SPRITE_COUNT).map(|index| self.sprite(index)).collect()
+    }
+
+    pub fn background_palette(&self) -> Palette {
+        Palette::decode(self.memory[BG_PALETTE as usize])
+    }
+
+    pub fn sprite_palette(&self) -> Palette {
+        Palette::decode(self.memory[SPRITE_PALETTE as usize])
+    }
+
+    /// The background tilemap and its scroll.
+    pub fn tilemap(&self) -> Tilemap {
+        let mut cells = [[0; MAP_WIDTH]; MAP_HEIGHT];
+        for (y, row) in cells.iter_mut().enumerate() {
+            row.copy_from_slice(&self.memory[TILEMAP as usize + y * MAP_WIDTH..][..MAP_WIDTH]);
+        }
+        Tilemap {
+            cells,
+            scroll_x: self.memory[SCROLL_X as usize],
+            scroll_y: self.memory[SCROLL_Y as usize],
+        }
+    }
+
+    /// Whether `CTRL` enables the background.
+    pub fn background_enabled(&self) -> bool {
+        self.memory[CTRL as usize] & CTRL_BACKGROUND != 0
+    }
+
+    /// Whether `CTRL` enables sprites.
+    pub fn sprites_enabled(&self) -> bool {
+        self.memory[CTRL as usize] & CTRL_SPRITES != 0
+    }
+
+    /// Every tile in `palette` as an RGBA image [`SHEET_COLUMNS`] tiles
+    /// wide, tile 0 at the top left. Returns the image and its width and
+    /// height in pixels.
+    pub fn tile_sheet_rgba(&self, palette: Palette) -> (Vec<u8>, usize, usize) {
+        let (width, height) = (SHEET_COLUMNS * 8, TILE_COUNT / SHEET_COLUMNS * 8);
+        let mut image = vec![0; width * height * 4];
+        for tile in self.tiles() {
+            let left = tile.index as usize % SHEET_COLUMNS * 8;
+            let top = tile.index as usize / SHEET_COLUMNS * 8;
+            for (y, row) in tile.pixels.iter().enumerate() {
+                for (x, &value) in row.iter().enumerate() {
+                    let at = ((top + y) * width + left + x) * 4;
+                    image[at..at + 4].copy_from_slice(&palette.rgba(value));
+                }
+            }
+        }
+        (image, width, height)
+    }
+
+    /// The whole background tilemap as a [`WIDTH`]×[`HEIGHT`] RGBA image,
+    /// unscrolled and drawn whether or not `CTRL` enables it.
+    pub fn tilemap_rgba(&self) -> Vec<u8> {
+        let map = self.tilemap();
+        let palette = self.background_palette();
+        let tiles = self.tiles();
+        let mut image = vec![0; WIDTH * HEIGHT * 4];
+        for (pixel, rgba) in image.chunks_exact_mut(4).enumerate() {
+            let (x, y) = (pixel % WIDTH, pixel / WIDTH);
+            let tile = map.cells[y / 8][x / 8] as usize % TILE_COUNT;
+            rgba.copy_from_slice(&palette.rgba(tiles[tile].pixel(x % 8, y % 8)));
+        }
+        image
+    }
+}
+
+impl Vm {
+    /// Video memory decoded; see the [module docs](crate::ppu_debug).
+    pub fn ppu_debug(&self) -> PpuDebug<'_> {
+        PpuDebug {
+            memory: self.memory(),
+        }
+    }
+}
@@ -0,0 +1,297 @@
+//! Python bindings.
+//!
+//! Only compiled with the `python` feature. The `rvm8` module wraps a
+//! [`Vm`] for scripts and notebooks, with maturin building it into an
+//! extension from `pyproject.toml`:
+//!
+//! ```text
+//! maturin develop --release
+//! ```
+//!
+//! Memory and the framebuffer are exposed in place through the buffer
+//! protocol, so `numpy.asarray` wraps them without copying, and a view
+//! keeps its machine alive:
+//!
+//! ```python
+//! vm = rvm8.Vm()
+//! vm.load_rom(open("game.rvm", "rb").read())
+//! vm.run_frame()
+//! pixels = numpy.asarray(vm.framebuffer)  # (144, 160, 4) uint8, RGBA
+//! ```
+//!
+//! Failing calls raise `rvm8.VmError` with the error's message.
+
+use std::ffi::c_int;
+use std::fmt::Display;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::ffi;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyMemoryView};
+
+use crate::display::{BYTES_PER_PIXEL, HEIGHT, WIDTH};
+use crate::input::Button;
+use crate::reset::ResetKind;
+use crate::rom::Rom;
+use crate::snapshot::Snapshot;
+use crate::vm::{Registers, Vm};
+
+create_exception!(rvm8, VmError, PyException, "A machine call failed.");
+
+fn error(err: impl Display) -> PyErr {
+    VmError::new_err(err.to_string())
+}
+
+/// The CPU registers, copied out of or into a machine.
+#[pyclass(name = "Registers", get_all, set_all, eq, from_py_object)]
+#[derive(Clone, PartialEq)]
+pub struct PyRegisters {
+    a: u8,
+    x: u8,
+    y: u8,
+    flags: u8,
+    pc: u16,
+    sp: u16,
+}
+
+#[pymethods]
+impl PyRegisters {
+    #[new]
+    #[pyo3(signature = (a = 0, x = 0, y = 0, flags = 0, pc = 0, sp = 0))]
+    fn new(a: u8, x: u8, y: u8, flags: u8, pc: u16, sp: u16) -> Self {
+        Self {
+            a,
+            x,
+            y,
+            flags,
+            pc,
+            sp,
+        }
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Registers(a=0x{:02X}, x=0x{:02X}, y=0x{:02X}, flags=0x{:02X}, pc=0x{:04X}, sp=0x{:04X})",
+            self.a, self.x, self.y, self.flags, self.pc, self.sp
+        )
+    }
+}
+
+impl From<Registers> for PyRegisters {
+    fn from(regs: Registers) -> Self {
+        Self::new(regs.a, regs.x, regs.y, regs.flags, regs.pc, regs.sp)
+    }
+}
+
+impl From<PyRegisters> for Registers {
+    fn from(regs: PyRegisters) -> Self {
+        Self {
+            a: regs.a,
+            x: regs.x,
+            y: regs.y,
+            pc: regs.pc,
+            sp: regs.sp,
+            flags: regs.flags,
+        }
+    }
+}
+
+/// A machine exported to Python.
+#[pyclass(name = "Vm", frozen)]
+pub struct PyVm {
+    vm: Mutex<Vm>,
+}
+
+impl PyVm {
+    fn vm(&self) -> MutexGuard<'_, Vm> {
+        self.vm.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[pymethods]
+impl PyVm {
+    /// Creates a machine with zeroed memory.
+    #[new]
+    fn new() -> Self {
+        Self {
+            vm: Mutex::new(Vm::new()),
+        }
+    }
+
+    /// Validates an `.rvm` image and resets into it.
+    fn load_rom(&self, image: &[u8]) -> PyResult<()> {
+        let rom = Rom::from_bytes(image).map_err(error)?;
+        self.vm().load_rom(&rom).map_err(error)
+    }
+
+    /// Copies `data` into memory at `addr`.
+    fn load(&self, addr: u16, data: &[u8]) -> PyResult<()> {
+        self.vm().load(addr, data).map_err(error)
+    }
+
+    /// Resets the CPU, keeping memory.
+    fn reset(&self) {
+        self.vm().reset(ResetKind::Soft);
+    }
+
+    /// Executes one instruction.
+    fn step(&self) -> PyResult<()> {
+        self.vm().step().map_err(error)
+    }
+
+    /// Runs for at least `cycles` cycles.
+    fn run_cycles(&self, cycles: u32) -> PyResult<()> {
+        self.vm().run_cycles(cycles).map_err(error)
+    }
+
+    /// Runs to the end of the frame and renders it.
+    fn run_frame(&self) -> PyResult<()> {
+        self.vm().run_frame().map_err(error)
+    }
+
+    /// Frames completed since construction or the last reset.
+    #[getter]
+    fn frame(&self) -> u64 {
+        self.vm().frame()
+    }
+
+    /// The cycle counter, wrapping at 2**32.
+    #[getter]
+    fn cycles(&self) -> u32 {
+        self.vm().cycles()
+    }
+
+    /// A copy of the CPU registers; assign a `Registers` to set them.
+    #[getter]
+    fn registers(&self) -> PyRegisters {
+        self.vm().registers().into()
+    }
+
+    #[setter]
+    fn set_registers(&self, regs: PyRegisters) {
+        self.vm().set_registers(regs.into());
+    }
+
+    /// Reads memory directly, bypassing devices.
+    fn read(&self, addr: u16) -> u8 {
+        self.vm().read(addr)
+    }
+
+    /// Writes memory directly, bypassing devices.
+    fn write(&self, addr: u16, val: u8) {
+        self.vm().write(addr, val);
+    }
+
+    /// The 64 KiB address space as a writable memoryview, in place.
+    #[getter]
+    fn memory<'py>(slf: &Bound<'py, Self>) -> PyResult<Bound<'py, PyMemoryView>> {
+        View::of(slf, false)
+    }
+
+    /// The last rendered frame as a read-only (height, width, RGBA)
+    /// memoryview, in place.
+    #[getter]
+    fn framebuffer<'py>(slf: &Bound<'py, Self>) -> PyResult<Bound<'py, PyAny>> {
+        View::of(slf, true)?.call_method1("cast", ("B", (HEIGHT, WIDTH, BYTES_PER_PIXEL)))
+    }
+
+    /// Replaces every held button with a union of `BUTTON_*` bits.
+    fn set_buttons(&self, mask: u8) {
+        self.vm().set_buttons(mask);
+    }
+
+    fn raise_irq(&self, line: u8) {
+        self.vm().raise_irq(line);
+    }
+
+    fn ack_irq(&self, line: u8) {
+        self.vm().ack_irq(line);
+    }
+
+    /// The machine state as a [`Snapshot`]'s bytes.
+    fn save_state<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.vm().save_state().to_bytes())
+    }
+
+    /// Restores a state from `save_state`.
+    fn load_state(&self, state: &[u8]) -> PyResult<()> {
+        let snapshot = Snapshot::from_bytes(state).map_err(error)?;
+        self.vm().load_state(&snapshot).map_err(error)
+    }
+}
+
+/// The memory or the framebuffer of a [`PyVm`], exported through the
+/// buffer protocol. It holds the machine, so the buffer outlives neither.
+#[pyclass(frozen)]
+struct View {
+    vm: Py<PyVm>,
+    framebuffer: bool,
+}
+
+impl View {
+    fn of<'py>(vm: &Bound<'py, PyVm>, framebuffer: bool) -> PyResult<Bound<'py, PyMemoryView>> {
+        let view = Bound::new(
+            vm.py(),
+            View {
+                vm: vm.clone().unbind(),
+                framebuffer,
+            },
+        )?;
+        PyMemoryView::from(view.as_any())
+    }
+}
+
+#[pymethods]
+impl View {
+    unsafe fn __getbuffer__(
+        slf: Bound<'_, Self>,
+        view: *mut ffi::Py_buffer,
+        flags: c_int,
+    ) -> PyResult<()> {
+        let this = slf.borrow();
+        let mut vm = this.vm.get().vm();
+        let (buf, len, readonly) = if this.framebuffer {
+            let framebuffer = vm.framebuffer();
+            (framebuffer.as_ptr().cast_mut(), framebuffer.len(), 1)
+        } else {
+            let len = vm.memory().len();
+            (vm.memory_ptr(), len, 0)
+        };
+        // SAFETY: `view` comes from the interpreter, and `buf` stays valid
+        // for `len` bytes while the machine lives, which the view's
+        // reference to `slf` ensures; the framebuffer only moves on a
+        // display change, which Python cannot make.
+        let status = unsafe {
+            ffi::PyBuffer_FillInfo(
+                view,
+                slf.as_ptr(),
+                buf.cast(),
+                len as ffi::Py_ssize_t,
+                readonly,
+                flags,
+            )
+        };
+        if status == -1 {
+            return Err(PyErr::fetch(slf.py()));
+        }
+        Ok(())
+    }
+}
+
+/// The `rvm8` module.
+#[pymodule]
+pub fn rvm8(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyVm>()?;
+    m.add_class::<PyRegisters>()?;
+    m.add("VmError", m.py().get_type::<VmError>())?;
+    m.add("WIDTH", WIDTH)?;
+    m.add("HEIGHT", HEIGHT)?;
+    m.add("MEMORY_SIZE", 0x1_0000)?;
+    for button in Button::ALL {
+        let name = format!("BUTTON_{}", format!("{button:?}").to_uppercase());
+        m.add(name, button.mask())?;
+    }
+    Ok(())
+}
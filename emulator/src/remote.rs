@@ -0,0 +1,375 @@
+//! Line-based remote control over TCP or a Unix socket.
+//!
+//! A [`RemoteControl`] lets scripts, test harnesses and other tools drive a
+//! running emulator: the frontend calls [`RemoteControl::poll`] once per
+//! pass of its loop, which accepts connections and carries out whatever
+//! commands have arrived without ever blocking, and stops running frames
+//! while [`RemoteControl::is_paused`]:
+//!
+//! ```no_run
+//! # use emulator::{remote::RemoteControl, Vm};
+//! let mut vm = Vm::new();
+//! let mut remote = RemoteControl::bind_tcp("127.0.0.1:6502").unwrap();
+//! loop {
+//!     remote.poll(&mut vm).unwrap();
+//!     if remote.is_paused() {
+//!         std::thread::sleep(std::time::Duration::from_millis(10));
+//!     } else {
+//!         vm.run_frame().unwrap();
+//!     }
+//! }
+//! ```
+//!
+//! Clients send one command per line and get one line back, `ok` with any
+//! result or `err` with a message. Any number of clients may be connected;
+//! `nc` is enough to try it out.
+//!
+//! | Command                | Effect                                          |
+//! | ---------------------- | ----------------------------------------------- |
+//! | `status`               | `ok paused` or `ok running`, the frame and PC   |
+//! | `pause`, `resume`      | stop or restart running frames                  |
+//! | `frame [n]`            | run n frames now, default 1, even when paused   |
+//! | `step [n]`             | execute n instructions now, default 1           |
+//! | `regs`                 | the registers                                   |
+//! | `peek <addr> [len]`    | len bytes from addr in hex, default 1           |
+//! | `poke <addr> <byte>..` | write the bytes from addr on                    |
+//! | `save <path>`          | save the machine state to a file                |
+//! | `load <path>`          | restore a state saved before                    |
+//! | `screenshot <path>`    | save the framebuffer as a PNG                   |
+//! | `reset`                | reset the CPU                                   |
+//! | `quit`                 | close this connection                           |
+//!
+//! Numbers use the assembler's `$`, `0x`, `%` or decimal notation, and
+//! addresses may also be names from the [symbol table](Vm::set_symbols).
+//! Memory goes through [`Vm::read`] and [`Vm::write`], and paths are the
+//! emulator's, relative to its working directory. There is no
+//! authentication, so bind to a loopback address or a socket only trusted
+//! users can reach.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+
+use crate::reset::ResetKind;
+use crate::snapshot::Snapshot;
+use crate::symbols::parse_value;
+use crate::vm::Vm;
+
+/// Longest command line accepted; a client sending more is disconnected.
+pub const MAX_LINE: usize = 4096;
+
+enum Listener {
+    Tcp(TcpListener),
+    /// The socket file is removed again on drop.
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.read(buffer),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.read(buffer),
+        }
+    }
+
+    /// Writes a whole reply, waiting for the client to take it.
+    fn reply(&mut self, line: &str) -> io::Result<()> {
+        let line = format!("{line}\n");
+        let result = self.set_nonblocking(false).and_then(|()| match self {
+            Self::Tcp(stream) => stream.write_all(line.as_bytes()),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.write_all(line.as_bytes()),
+        });
+        self.set_nonblocking(true)?;
+        result
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+struct Client {
+    stream: Stream,
+    /// Bytes of the command line being received.
+    input: Vec<u8>,
+}
+
+/// A remote control server; see the [module docs](self).
+pub struct RemoteControl {
+    listener: Listener,
+    clients: Vec<Client>,
+    paused: bool,
+}
+
+impl RemoteControl {
+    /// Listens for clients on a TCP address.
+    pub fn bind_tcp(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self::new(Listener::Tcp(listener)))
+    }
+
+    /// Listens for clients on a Unix socket created at `path`, which must
+    /// not exist yet. The socket is removed when the server is dropped.
+    #[cfg(unix)]
+    pub fn bind_unix(path: impl AsRef<Path>) -> io::Result<Self> {
+        let listener = UnixListener::bind(&path)?;
+        let path = path.as_ref().to_path_buf();
+        listener.set_nonblocking(true)?;
+        Ok(Self::new(Listener::Unix(listener, path)))
+    }
+
+    fn new(listener: Listener) -> Self {
+        Self {
+            listener,
+            clients: Vec::new(),
+            paused: false,
+        }
+    }
+
+    /// The TCP address listened on, with the port picked if it was 0.
+    /// `None` for a Unix socket.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match &self.listener {
+            Listener::Tcp(listener) => listener.local_addr().ok(),
+            #[cfg(unix)]
+            Listener::Unix(..) => None,
+        }
+    }
+
+    /// Accepts new clients and carries out every complete command they
+    /// sent, replying to each. Never waits for a client; one that hangs up
+    /// or misbehaves is dropped.
+    ///
+    /// Fails only if accepting connections fails.
+    pub fn poll(&mut self, vm: &mut Vm) -> io::Result<()> {
+        self.accept()?;
+        let mut clients = std::mem::take(&mut self.clients);
+        clients.retain_mut(|client| self.serve(client, vm).unwrap_or(false));
+        self.clients.append(&mut clients);
+        Ok(())
+    }
+
+    /// Whether a client paused the machine, so the frontend should not run
+    /// frames.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Pauses or resumes as the `pause` and `resume` commands do.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// Number of connected clients.
+    pub fn clients(&self) -> usize {
+        self.clients.len()
+    }
+
+    fn accept(&mut self) -> io::Result<()> {
+        loop {
+            let stream = match &self.listener {
+                Listener::Tcp(listener) => listener.accept().map(|(s, _)| Stream::Tcp(s)),
+                #[cfg(unix)]
+                Listener::Unix(listener, _) => listener.accept().map(|(s, _)| Stream::Unix(s)),
+            };
+            match stream {
+                Ok(stream) => {
+                    stream.set_nonblocking(true)?;
+                    self.clients.push(Client {
+                        stream,
+                        input: Vec::new(),
+                    });
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Reads what `client` sent and runs its complete lines. Returns
+    /// whether to keep the client.
+    fn serve(&mut self, client: &mut Client, vm: &mut Vm) -> io::Result<bool> {
+        let mut buffer = [0; 1024];
+        let mut open = true;
+        loop {
+            match client.stream.read(&mut buffer) {
+                Ok(0) => {
+                    open = false;
+                    break;
+                }
+                Ok(n) => client.input.extend_from_slice(&buffer[..n]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        while let Some(end) = client.input.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = client.input.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line == "quit" {
+                client.stream.reply("ok")?;
+                return Ok(false);
+            }
+            let reply = match self.command(line, vm) {
+                Ok(result) if result.is_empty() => "ok".to_string(),
+                Ok(result) => format!("ok {result}"),
+                Err(message) => format!("err {message}"),
+            };
+            client.stream.reply(&reply)?;
+        }
+        Ok(open && client.input.len() <= MAX_LINE)
+    }
+
+    /// Carries out one command, returning its result.
+    fn command(&mut self, line: &str, vm: &mut Vm) -> Result<String, String> {
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let args: Vec<&str> = words.collect();
+        let path = || match args[..] {
+            [path] => Ok(path),
+            _ => Err(format!("usage: {name} <path>")),
+        };
+        match name {
+            "status" => {
+                let state = if self.paused { "paused" } else { "running" };
+                let pc = vm.registers().pc;
+                Ok(format!("{state} frame={} pc=${pc:04X}", vm.frame()))
+            }
+            "pause" => {
+                self.paused = true;
+                Ok(String::new())
+            }
+            "resume" => {
+                self.paused = false;
+                Ok(String::new())
+            }
+            "frame" => {
+                for _ in 0..count(&args)? {
+                    vm.run_frame().map_err(|err| err.to_string())?;
+                }
+                Ok(format!("frame={}", vm.frame()))
+            }
+            "step" => {
+                for _ in 0..count(&args)? {
+                    vm.step().map_err(|err| err.to_string())?;
+                }
+                Ok(format!("pc=${:04X}", vm.registers().pc))
+            }
+            "regs" => {
+                let regs = vm.registers();
+                Ok(format!(
+                    "a=${:02X} x=${:02X} y=${:02X} p=${:02X} sp=${:02X} pc=${:04X}",
+                    regs.a, regs.x, regs.y, regs.flags, regs.sp, regs.pc
+                ))
+            }
+            "peek" => {
+                let (addr, len) = match args[..] {
+                    [addr] => (address(vm, addr)?, 1),
+                    [addr, len] => (address(vm, addr)?, usize::from(number(len)?)),
+                    _ => return Err("usage: peek <addr> [len]".into()),
+                };
+                let len = len.min(0x10000 - usize::from(addr));
+                let bytes: Vec<String> = (0..len)
+                    .map(|i| format!("{:02X}", vm.read(addr + i as u16)))
+                    .collect();
+                Ok(bytes.join(" "))
+            }
+            "poke" => {
+                let [addr, ref bytes @ ..] = args[..] else {
+                    return Err("usage: poke <addr> <byte>...".into());
+                };
+                let addr = address(vm, addr)?;
+                let bytes = bytes
+                    .iter()
+                    .map(|text| {
+                        let value = number(text)?;
+                        u8::try_from(value).map_err(|_| format!("`{text}` is not a byte"))
+                    })
+                    .collect::<Result<Vec<u8>, String>>()?;
+                if bytes.is_empty() || usize::from(addr) + bytes.len() > 0x10000 {
+                    return Err("usage: poke <addr> <byte>...".into());
+                }
+                for (i, byte) in bytes.into_iter().enumerate() {
+                    vm.write(addr + i as u16, byte);
+                }
+                Ok(String::new())
+            }
+            "save" => {
+                let path = path()?;
+                let state = vm.save_state().to_bytes();
+                std::fs::write(path, state).map_err(|err| format!("{path}: {err}"))?;
+                Ok(String::new())
+            }
+            "load" => {
+                let path = path()?;
+                let bytes = std::fs::read(path).map_err(|err| format!("{path}: {err}"))?;
+                let state = Snapshot::from_bytes(&bytes).map_err(|err| format!("{path}: {err}"))?;
+                vm.load_state(&state)
+                    .map_err(|err| format!("{path}: {err}"))?;
+                Ok(String::new())
+            }
+            "screenshot" => {
+                let path = path()?;
+                vm.screenshot_png(path)
+                    .map_err(|err| format!("{path}: {err}"))?;
+                Ok(String::new())
+            }
+            "reset" => {
+                vm.reset(ResetKind::Soft);
+                Ok(String::new())
+            }
+            _ => Err(format!("unknown command `{name}`")),
+        }
+    }
+}
+
+impl Drop for RemoteControl {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Listener::Unix(_, path) = &self.listener {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+fn number(text: &str) -> Result<u16, String> {
+    parse_value(text).ok_or_else(|| format!("`{text}` is not a 16-bit number"))
+}
+
+/// A number or symbol name.
+fn address(vm: &Vm, text: &str) -> Result<u16, String> {
+    vm.symbols()
+        .and_then(|symbols| symbols.address(text))
+        .map_or_else(|| number(text), Ok)
+}
+
+/// The optional repeat count of `frame` and `step`.
+fn count(args: &[&str]) -> Result<u16, String> {
+    match args {
+        [] => Ok(1),
+        [count] => number(count),
+        _ => Err("expected at most one count".into()),
+    }
+}
@@ -0,0 +1,374 @@
+//! Deterministic input recording and replay.
+//!
+//! The CPU and memory are deterministic, so a run is fully described by the
+//! state it started from, controller included, and the host inputs it
+//! received: controller changes, [keys](crate::keyboard), host interrupt
+//! lines and mask, the NMI input, and resets of either kind.
+//! [`Vm::record_inputs`] writes exactly that to a file, stamping each input
+//! with the number of instructions executed before it; [`Vm::replay_inputs`]
+//! restores the start state and feeds every input back at the same
+//! instruction, reproducing the original run bit for bit. This serves
+//! regression tests as well as tool-assisted play.
+//!
+//! While a replay runs, the recorded inputs are the only ones: host calls to
+//! [`Vm::set_buttons`], [`Vm::key_down`], [`Vm::key_up`], [`Vm::raise_irq`],
+//! [`Vm::ack_irq`], [`Vm::set_irq_mask`], [`Vm::set_nmi`], [`Vm::reset`] and
+//! [`Vm::reset`] are ignored until the last event has been applied or
+//! [`Vm::stop_replay`] is called. State held by mapped devices other than
+//! the controller, such as bytes arriving on a [`Uart`](crate::uart::Uart)
+//! or keys queued on a keyboard, is not recorded, and neither
+//! [`Vm::load_state`] nor [`Vm::rewind`] can be represented, so both break
+//! the timeline of a recording or replay in progress.
+//!
+//! A recording file is a header and start state followed by events to the
+//! end of the file, all little-endian:
+//!
+//! | Size    | Contents                                            |
+//! | ------- | --------------------------------------------------- |
+//! | 8       | magic `RVM8INP` and format version, currently 2     |
+//! | 8       | registers: A, X, Y, P, PC, SP                       |
+//! | 12      | cycle counter (4) and frame counter (8)             |
+//! | 4       | interrupt lines raised and mask, NMI, CPU poll      |
+//! | 65536   | memory                                              |
+//! | 4       | controller: held buttons, strobe, latch, bits left  |
+//! | 10 each | event: instruction count (8), kind (1), value (1)   |
+//!
+//! Event kinds are 0 buttons, 1 raise IRQ, 2 acknowledge IRQ, 3 IRQ mask,
+//! 4 reset, whose value is 0 for a soft reset and 1 for a hard one, 5 NMI,
+//! whose value is 1 when asserted and 0 when released, and 6 key down and
+//! 7 key up, whose value is the key's
+//! [scan code](crate::keyboard::Key::scancode).
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::input::{CONTROLLER, Controller};
+use crate::irq::IRQ_LINES;
+use crate::keyboard::Key;
+use crate::reset::ResetKind;
+use crate::snapshot::{self, Snapshot};
+use crate::vm::Vm;
+
+/// File magic, the format version byte excluded.
+pub const MAGIC: [u8; 7] = *b"RVM8INP";
+/// Format version written and accepted by this crate.
+pub const VERSION: u8 = 2;
+
+/// The start state as [`Snapshot::to_bytes`] lays it out, then the controller.
+const STATE_SIZE: usize = snapshot::STATE_SIZE + 4;
+const HEADER_SIZE: usize = 8 + STATE_SIZE;
+const EVENT_SIZE: usize = 10;
+
+/// One recorded host input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    /// The held buttons became this mask.
+    Buttons(u8),
+    RaiseIrq(u8),
+    AckIrq(u8),
+    IrqMask(u8),
+    Reset,
+    /// A [hard](crate::reset::ResetKind::Hard) reset.
+    HardReset,
+    Nmi(bool),
+    /// A key went down, by make code.
+    KeyDown(u8),
+    /// A key came up, by make code.
+    KeyUp(u8),
+}
+
+impl InputEvent {
+    fn encode(self) -> [u8; 2] {
+        match self {
+            Self::Buttons(mask) => [0, mask],
+            Self::RaiseIrq(line) => [1, line],
+            Self::AckIrq(line) => [2, line],
+            Self::IrqMask(mask) => [3, mask],
+            Self::Reset => [4, 0],
+            Self::HardReset => [4, 1],
+            Self::Nmi(asserted) => [5, asserted.into()],
+            Self::KeyDown(code) => [6, code],
+            Self::KeyUp(code) => [7, code],
+        }
+    }
+
+    fn decode([kind, val]: [u8; 2]) -> Option<Self> {
+        Some(match kind {
+            0 => Self::Buttons(val),
+            1 if val < IRQ_LINES => Self::RaiseIrq(val),
+            2 if val < IRQ_LINES => Self::AckIrq(val),
+            3 => Self::IrqMask(val),
+            4 if val == 0 => Self::Reset,
+            4 if val == 1 => Self::HardReset,
+            5 if val <= 1 => Self::Nmi(val == 1),
+            6 if Key::from_scancode(val).is_some() => Self::KeyDown(val),
+            7 if Key::from_scancode(val).is_some() => Self::KeyUp(val),
+            _ => return None,
+        })
+    }
+}
+
+/// Why a recording could not be loaded.
+#[derive(Debug)]
+pub enum ReplayError {
+    /// The file could not be read.
+    Io(io::Error),
+    /// The data is shorter than its header and start state, or ends inside
+    /// an event.
+    Truncated,
+    /// The data does not start with [`MAGIC`].
+    BadMagic,
+    /// The header names a format version this crate does not know.
+    UnsupportedVersion(u8),
+    /// The event at `offset` has an unknown kind or an invalid value.
+    BadEvent { offset: usize },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "cannot read recording: {err}"),
+            Self::Truncated => write!(f, "recording is truncated"),
+            Self::BadMagic => write!(f, "not an rvm-8 input recording"),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported recording format version {v}"),
+            Self::BadEvent { offset } => write!(f, "invalid event at byte {offset}"),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ReplayError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// A parsed recording: where the run started and what happened to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recording {
+    pub start: Snapshot,
+    /// The controller's latch state at the start; a fresh one if it was
+    /// unmapped.
+    pub controller: Controller,
+    /// Inputs in order, each with the number of instructions executed
+    /// before it.
+    pub events: Vec<(u64, InputEvent)>,
+}
+
+impl Recording {
+    /// Parses and validates a recording file's contents.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ReplayError> {
+        let (header, events) = bytes
+            .split_first_chunk::<HEADER_SIZE>()
+            .ok_or(ReplayError::Truncated)?;
+        if header[..7] != MAGIC {
+            return Err(ReplayError::BadMagic);
+        }
+        if header[7] != VERSION {
+            return Err(ReplayError::UnsupportedVersion(header[7]));
+        }
+        if events.len() % EVENT_SIZE != 0 {
+            return Err(ReplayError::Truncated);
+        }
+        let events = events
+            .chunks_exact(EVENT_SIZE)
+            .enumerate()
+            .map(|(i, event)| {
+                let (at, event) = event.split_at(8);
+                let at = u64::from_le_bytes(at.try_into().expect("8 bytes"));
+                InputEvent::decode([event[0], event[1]])
+                    .map(|event| (at, event))
+                    .ok_or(ReplayError::BadEvent {
+                        offset: HEADER_SIZE + i * EVENT_SIZE,
+                    })
+            })
+            .collect::<Result<_, _>>()?;
+        let state = &header[8..];
+        let controller = state[STATE_SIZE - 4..].try_into().expect("4 bytes");
+        Ok(Self {
+            start: Snapshot::decode(state),
+            controller: Controller::from_bytes(controller),
+            events,
+        })
+    }
+
+    /// Reads and validates a recording file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ReplayError> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+
+    /// Serializes the recording in the file format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = header(&self.start, &self.controller);
+        for &(at, event) in &self.events {
+            bytes.extend_from_slice(&encode_event(at, event));
+        }
+        bytes
+    }
+}
+
+fn header(start: &Snapshot, controller: &Controller) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_SIZE);
+    bytes.extend_from_slice(&MAGIC);
+    bytes.push(VERSION);
+    start.encode(&mut bytes);
+    bytes.extend_from_slice(&controller.to_bytes());
+    bytes
+}
+
+fn encode_event(at: u64, event: InputEvent) -> [u8; EVENT_SIZE] {
+    let mut bytes = [0; EVENT_SIZE];
+    bytes[..8].copy_from_slice(&at.to_le_bytes());
+    bytes[8..].copy_from_slice(&event.encode());
+    bytes
+}
+
+struct Recorder {
+    writer: BufWriter<File>,
+    /// The first write error, which stops further writes.
+    error: Option<io::Error>,
+}
+
+/// Recording and replay progress.
+#[derive(Default)]
+pub(crate) struct InputLog {
+    /// Instructions executed since recording or replay began.
+    position: u64,
+    recorder: Option<Recorder>,
+    replay: VecDeque<(u64, InputEvent)>,
+}
+
+impl InputLog {
+    /// Whether [`Vm::step`] has to count instructions.
+    pub(crate) fn is_active(&self) -> bool {
+        self.recorder.is_some() || !self.replay.is_empty()
+    }
+
+    /// Whether host inputs are being replaced by recorded ones.
+    pub(crate) fn is_replaying(&self) -> bool {
+        !self.replay.is_empty()
+    }
+
+    /// Appends `event` to the recording, if one is running.
+    pub(crate) fn record(&mut self, event: InputEvent) {
+        let Some(recorder) = &mut self.recorder else {
+            return;
+        };
+        if recorder.error.is_none() {
+            let bytes = encode_event(self.position, event);
+            recorder.error = recorder.writer.write_all(&bytes).err();
+        }
+    }
+}
+
+impl Vm {
+    /// Starts recording host inputs to a new file at `path`, beginning with
+    /// the current machine state. Any recording already running is stopped
+    /// first and its result discarded.
+    pub fn record_inputs(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let controller = self
+            .bus()
+            .device::<Controller>(CONTROLLER)
+            .cloned()
+            .unwrap_or_default();
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(&header(&self.save_state(), &controller))?;
+        self.inputs.recorder = Some(Recorder {
+            writer,
+            error: None,
+        });
+        self.inputs.position = 0;
+        Ok(())
+    }
+
+    /// Finishes the recording, reporting the first error met while writing
+    /// it. Does nothing if no recording is running.
+    pub fn stop_recording(&mut self) -> io::Result<()> {
+        let Some(mut recorder) = self.inputs.recorder.take() else {
+            return Ok(());
+        };
+        match recorder.error.take() {
+            Some(err) => Err(err),
+            None => recorder.writer.flush(),
+        }
+    }
+
+    /// Whether inputs are being recorded.
+    pub fn is_recording(&self) -> bool {
+        self.inputs.recorder.is_some()
+    }
+
+    /// Loads the recording at `path` and starts replaying it.
+    pub fn replay_inputs(&mut self, path: impl AsRef<Path>) -> Result<(), ReplayError> {
+        self.replay_recording(Recording::from_file(path)?);
+        Ok(())
+    }
+
+    /// Restores the recording's start state, controller included, and
+    /// starts replaying its events. Rewind history is cleared as by
+    /// [`Vm::load_state`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the start state's memory is not the size of the address
+    /// space, which [`Recording::from_bytes`] rules out.
+    pub fn replay_recording(&mut self, recording: Recording) {
+        self.load_state(&recording.start)
+            .expect("recording start state has a full address space");
+        if let Some(controller) = self.bus_mut().device_mut::<Controller>(CONTROLLER) {
+            *controller = recording.controller;
+        }
+        self.inputs.replay = recording.events.into();
+        self.inputs.position = 0;
+        // Inputs recorded before the first instruction take effect now.
+        self.apply_replayed_inputs();
+    }
+
+    /// Whether a replay is in progress, i.e. recorded events remain.
+    pub fn is_replaying(&self) -> bool {
+        self.inputs.is_replaying()
+    }
+
+    /// Abandons the replay, giving input back to the host.
+    pub fn stop_replay(&mut self) {
+        self.inputs.replay.clear();
+    }
+
+    /// Called by [`Vm::step`] before every instruction while recording or
+    /// replaying.
+    pub(crate) fn advance_inputs(&mut self) {
+        self.apply_replayed_inputs();
+        self.inputs.position += 1;
+    }
+
+    fn apply_replayed_inputs(&mut self) {
+        while let Some(&(at, event)) = self.inputs.replay.front() {
+            if at > self.inputs.position {
+                break;
+            }
+            self.inputs.replay.pop_front();
+            match event {
+                InputEvent::Buttons(mask) => self.apply_buttons(mask),
+                InputEvent::RaiseIrq(line) => self.irq.raised |= 1 << line,
+                InputEvent::AckIrq(line) => self.irq.raised &= !(1 << line),
+                InputEvent::IrqMask(mask) => self.irq.mask = mask,
+                InputEvent::Reset => self.reset_machine(ResetKind::Soft),
+                InputEvent::HardReset => self.reset_machine(ResetKind::Hard),
+                InputEvent::Nmi(asserted) => self.irq.nmi = asserted,
+                InputEvent::KeyDown(code) => self.apply_key(code),
+                InputEvent::KeyUp(code) => self.apply_key(code | 0x80),
+            }
+        }
+    }
+}
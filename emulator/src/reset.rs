@@ -0,0 +1,98 @@
+//! Soft and hard resets.
+//!
+//! [`Vm::reset`] models the two ways a real board restarts. A
+//! [`ResetKind::Soft`] reset is the reset button: the CPU restarts from the
+//! reset vector and every device's registers return to their power-on
+//! values, but memory keeps what the program stored, so a program can tell
+//! a warm start from a cold one by what it left in RAM. A
+//! [`ResetKind::Hard`] reset is a power cycle: RAM is cleared as well, and
+//! devices lose what they were holding for the program. Neither re-creates
+//! the machine: the ROM, the bus layout, the devices mapped, host
+//! connections such as a UART's streams and debugging state all stay, and
+//! battery-backed state, an RTC's time, an EEPROM's contents and an
+//! attached [save RAM](Vm::attach_sram) range, survives both.
+//!
+//! ```
+//! # use emulator::{ResetKind, Vm};
+//! let mut vm = Vm::new();
+//! vm.write(0x0200, 0x42);
+//! vm.reset(ResetKind::Soft);
+//! assert_eq!(vm.read(0x0200), 0x42);
+//! vm.reset(ResetKind::Hard);
+//! assert_eq!(vm.read(0x0200), 0);
+//! ```
+//!
+//! Devices are reset first, through [`BusDevice::reset`](crate::BusDevice::reset),
+//! so the CPU fetches the reset vector with the mapper back on its first
+//! bank. The built-in devices reset to these states:
+//!
+//! | Device          | Soft reset                                      | Hard reset also                 |
+//! | --------------- | ----------------------------------------------- | ------------------------------- |
+//! | `Controller`    | strobe low, nothing left to shift out           |                                 |
+//! | `Timer`         | stopped; counter, reload, prescaler, status 0   |                                 |
+//! | `Uart`          | control 0                                       | drops the bytes received        |
+//! | `Dma`           | transfer abandoned; registers and interrupt 0   |                                 |
+//! | `Rtc`           | latch reloaded from the clock                   |                                 |
+//! | `Keyboard`      | control written 0, overflow cleared             | drops the codes queued          |
+//! | `Spi`           | nothing selected, received byte 0               |                                 |
+//! | `BankRegisters` | ROM and RAM bank 0                              | clears the RAM banks            |
+//! | `Rng`           | latch reloaded from the state                   | restarts from its seed          |
+//!
+//! Buttons and keys held down stay held, since they are outside the
+//! machine. The [MPU](crate::mpu) reports no fault and returns to
+//! supervisor mode on either kind. A device of the host's own keeps all
+//! its state unless it implements [`BusDevice::reset`](crate::BusDevice::reset).
+
+use crate::bus::PAGE_SIZE;
+use crate::replay::InputEvent;
+use crate::vm::Vm;
+
+/// What a [`Vm::reset`] restarts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ResetKind {
+    /// The reset button: registers restart, memory is kept.
+    #[default]
+    Soft,
+    /// A power cycle: RAM and what devices hold is lost too.
+    Hard,
+}
+
+impl Vm {
+    /// Resets the machine as `kind` says, see the
+    /// [module documentation](self). Either kind reloads the PC from the
+    /// reset vector and resets the interrupt controller.
+    ///
+    /// A reset is recorded as a host input and ignored while a
+    /// [replay](crate::replay) is running.
+    pub fn reset(&mut self, kind: ResetKind) {
+        if self.inputs.is_replaying() {
+            return;
+        }
+        self.inputs.record(match kind {
+            ResetKind::Soft => InputEvent::Reset,
+            ResetKind::Hard => InputEvent::HardReset,
+        });
+        self.reset_machine(kind);
+    }
+
+    /// Zeroes every page that is not ROM, save for the attached save RAM,
+    /// and the RAM banks outside the address space.
+    pub(crate) fn clear_ram(&mut self) {
+        let rom_pages = self.cpu.rom_pages;
+        let kept = self.sram_span();
+        self.mark_dirty(0..=0xFFFF);
+        let memory = self.memory_untracked_mut();
+        for (page, bytes) in memory.chunks_mut(PAGE_SIZE).enumerate() {
+            if rom_pages[page] != 0 {
+                continue;
+            }
+            for (addr, byte) in (page * PAGE_SIZE..).zip(bytes) {
+                if !kept.as_ref().is_some_and(|span| span.contains(&addr)) {
+                    *byte = 0;
+                }
+            }
+        }
+        self.clear_ram_banks();
+    }
+}
@@ -0,0 +1,236 @@
+//! Rewind history for time-travel debugging.
+//!
+//! While rewind is enabled, [`Vm::run_frame`] records a
+//! [`PagedSnapshot`] every `interval` frames into a bounded
+//! [`RewindBuffer`]. Snapshots share the memory pages nothing wrote to
+//! between them, so history costs roughly as much as the pages a program
+//! actually touches, and recording one costs only copying those.
+//!
+//! Frames between two snapshots are recovered by restoring the earlier one
+//! and running forward again. The buffer also logs the controller state
+//! whenever it changes at a frame start, and replays feed those input deltas
+//! back, so the replayed frames are exactly the ones that ran originally.
+
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
+
+use crate::bus::PAGE_SIZE;
+use crate::error::VmError;
+use crate::paged::PagedSnapshot;
+use crate::vm::Vm;
+
+/// Bounded history of periodic snapshots sharing unchanged pages.
+#[derive(Debug, Clone)]
+pub struct RewindBuffer {
+    capacity: usize,
+    interval: u32,
+    /// Oldest first.
+    snapshots: VecDeque<PagedSnapshot>,
+    /// Controller state from each frame on, oldest first.
+    inputs: VecDeque<(u64, u8)>,
+}
+
+impl RewindBuffer {
+    /// Creates an empty buffer keeping up to `capacity` snapshots, one every
+    /// `interval` frames. Both are clamped to at least 1.
+    pub fn new(capacity: usize, interval: u32) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            interval: interval.max(1),
+            snapshots: VecDeque::new(),
+            inputs: VecDeque::new(),
+        }
+    }
+
+    /// Maximum number of snapshots retained.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Frames between two recorded snapshots.
+    pub fn interval(&self) -> u32 {
+        self.interval
+    }
+
+    /// Number of snapshots currently held.
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    /// Whether no snapshot has been recorded yet.
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    /// Frame of the oldest snapshot still held, i.e. how far back
+    /// [`Vm::rewind`] can go.
+    pub fn oldest_frame(&self) -> Option<u64> {
+        self.snapshots.front().map(|snapshot| snapshot.frame)
+    }
+
+    /// Bytes of machine memory stored across all snapshots, each shared
+    /// page counted once.
+    pub fn memory_usage(&self) -> usize {
+        let pages: HashSet<*const [u8; PAGE_SIZE]> = self
+            .snapshots
+            .iter()
+            .flat_map(|snapshot| snapshot.pages().iter().map(Arc::as_ptr))
+            .collect();
+        pages.len() * PAGE_SIZE
+    }
+
+    /// Drops every recorded snapshot.
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.inputs.clear();
+    }
+
+    /// Records `snapshot` as the newest entry, evicting the oldest one when
+    /// the buffer is full.
+    pub fn push(&mut self, snapshot: PagedSnapshot) {
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(snapshot);
+        // Keep the entry in effect at the oldest snapshot and everything after.
+        let oldest = self.oldest_frame().unwrap_or(0);
+        while self
+            .inputs
+            .get(1)
+            .is_some_and(|&(frame, _)| frame <= oldest)
+        {
+            self.inputs.pop_front();
+        }
+    }
+
+    /// Notes the controller state at the start of `frame`.
+    fn log_input(&mut self, frame: u64, buttons: u8) {
+        if self.inputs.back().is_none_or(|&(_, last)| last != buttons) {
+            self.inputs.push_back((frame, buttons));
+        }
+    }
+
+    /// Controller state during `frame`.
+    fn input_at(&self, frame: u64) -> u8 {
+        self.inputs
+            .iter()
+            .rev()
+            .find(|&&(from, _)| from <= frame)
+            .map_or(0, |&(_, buttons)| buttons)
+    }
+
+    /// Discards snapshots newer than `frame` and returns the latest remaining
+    /// one, or the oldest held if none is that old.
+    fn seek(&mut self, frame: u64) -> Option<&PagedSnapshot> {
+        while self.snapshots.len() > 1 && self.snapshots.back()?.frame > frame {
+            self.snapshots.pop_back();
+        }
+        self.snapshots.back()
+    }
+}
+
+impl Vm {
+    /// Starts recording rewind history, keeping up to `capacity` snapshots
+    /// taken every `interval` frames. The current state is recorded first.
+    ///
+    /// Holding `capacity * interval` frames of history costs one full
+    /// snapshot plus whatever pages the program writes in that time.
+    pub fn enable_rewind(&mut self, capacity: usize, interval: u32) {
+        let mut buffer = RewindBuffer::new(capacity, interval);
+        buffer.push(self.save_paged());
+        buffer.log_input(self.frame, self.buttons());
+        self.rewind = Some(buffer);
+    }
+
+    /// Stops recording and drops the history.
+    pub fn disable_rewind(&mut self) {
+        self.rewind = None;
+    }
+
+    /// The recorded history, if rewind is enabled.
+    pub fn rewind_buffer(&self) -> Option<&RewindBuffer> {
+        self.rewind.as_ref()
+    }
+
+    /// Steps the machine back `frames` frames and returns how many it
+    /// actually went back, which is less when the history does not reach that
+    /// far and 0 when rewind is disabled.
+    ///
+    /// The machine always lands on a frame boundary, replaying from the
+    /// nearest earlier snapshot when the target frame was not recorded.
+    /// History after the new position is discarded, and neither the vblank
+    /// and audio callbacks, the [audio capture](Vm::start_audio_capture),
+    /// the trace, the [frame dump](Vm::dump_frames), the profile, the
+    /// [execution counters](Vm::stats), the [event queue](crate::events) nor
+    /// PC, access and frame hooks see replayed frames; read and write hooks
+    /// still apply. The controller is left with the buttons the host holds
+    /// now.
+    pub fn rewind(&mut self, frames: u64) -> Result<u64, VmError> {
+        let Some(mut buffer) = self.rewind.take() else {
+            return Ok(0);
+        };
+        let start = self.frame;
+        let target = start.saturating_sub(frames);
+        if let Some(snapshot) = buffer.seek(target) {
+            self.restore_paged(snapshot);
+        }
+        let replay: Vec<u8> = (self.frame..target).map(|f| buffer.input_at(f)).collect();
+        buffer.inputs.retain(|&(frame, _)| frame < self.frame);
+        self.rewind = Some(buffer);
+
+        // Frames being replayed were already presented once.
+        let vblank = self.display.vblank.take();
+        let audio = self.audio.callback.take();
+        let capture = self.audio.capture.take();
+        let trace = self.tracer.config.take();
+        let dump = self.dumper.dump.take();
+        let profile = self.profile.take();
+        let stats = self.cpu.stats;
+        let hooks = std::mem::take(&mut self.hooks);
+        let inputs = std::mem::take(&mut self.inputs);
+        let events = std::mem::take(&mut self.events);
+        let held = self.buttons();
+        let mut replayed = Ok(());
+        for buttons in replay {
+            self.set_buttons(buttons);
+            replayed = self.run_frame();
+            if replayed.is_err() {
+                break;
+            }
+        }
+        self.set_buttons(held);
+        self.display.vblank = vblank;
+        self.audio.callback = audio;
+        self.audio.capture = capture;
+        self.tracer.config = trace;
+        self.dumper.dump = dump;
+        self.profile = profile;
+        self.cpu.stats = stats;
+        self.hooks = hooks;
+        self.inputs = inputs;
+        self.events = events;
+        replayed.map(|()| start.saturating_sub(self.frame))
+    }
+
+    /// Called at the start of every frame by [`Vm::run_frame`].
+    pub(crate) fn log_input(&mut self) {
+        let buttons = self.buttons();
+        if let Some(buffer) = &mut self.rewind {
+            buffer.log_input(self.frame, buttons);
+        }
+    }
+
+    /// Called at the end of every frame by [`Vm::run_frame`].
+    pub(crate) fn record_rewind(&mut self) {
+        let due = self
+            .rewind
+            .as_ref()
+            .is_some_and(|buffer| self.frame.is_multiple_of(buffer.interval as u64));
+        if due {
+            let snapshot = self.save_paged();
+            if let Some(buffer) = &mut self.rewind {
+                buffer.push(snapshot);
+            }
+        }
+    }
+}
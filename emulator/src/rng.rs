@@ -0,0 +1,162 @@
+//! Deterministic random-number generator.
+//!
+//! An [`Rng`] hands programs pseudo-random bytes from a fixed, seedable
+//! algorithm, so a game can shuffle and roll dice while a replay or a test
+//! started from the same seed sees the same numbers on every run. It is not
+//! mapped by default; [`RNG_PORTS`] is its conventional slot, and
+//! [`Vm::seed_rng`] reseeds every mapped generator from the host:
+//!
+//! ```
+//! # use emulator::{rng::{Rng, RNG_PORTS}, Vm};
+//! let mut vm = Vm::new();
+//! vm.bus_mut().map(RNG_PORTS, Rng::default()).unwrap();
+//! vm.seed_rng(42);
+//! ```
+//!
+//! | Offset | Register                                                |
+//! | ------ | ------------------------------------------------------- |
+//! | 0      | `VALUE`: reading steps the generator and returns a byte |
+//! | 1–4    | `SEED`, little-endian; writing offset 4 loads it        |
+//!
+//! The generator is Marsaglia's xorshift32 with shifts 13, 17 and 5: each
+//! step replaces the 32-bit state `s` with `s ^= s << 13; s ^= s >> 17;
+//! s ^= s << 5`, and `VALUE` returns the top byte of the new state. The
+//! state is never 0, so a seed of 0 is taken as [`RNG_SEED`].
+//!
+//! Reading `SEED` returns the current state, and writes go to a latch that
+//! writing offset 4 copies into the state, so a program writes the low bytes
+//! first. Only reads of `VALUE` step the generator; the clock does not, so
+//! the numbers do not depend on timing. The generator is saved in a
+//! [`Snapshot`](crate::Snapshot), but not in an input
+//! [recording](crate::replay): seed it before starting one. A
+//! [hard reset](crate::reset) starts it over from the seed last loaded.
+
+use std::ops::RangeInclusive;
+
+use crate::bus::BusDevice;
+use crate::reset::ResetKind;
+use crate::vm::Vm;
+
+/// The RNG registers.
+pub const RNG_PORTS: RangeInclusive<u16> = 0x2780..=0x2784;
+/// The seed of an [`Rng::default`], and what a seed of 0 stands for.
+pub const RNG_SEED: u32 = 2_463_534_242;
+
+const VALUE: u16 = 0;
+const SEED_HI: u16 = 4;
+
+/// The random-number generator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rng {
+    /// The seed last loaded, which a hard reset restarts from.
+    seed: u32,
+    state: u32,
+    latch: [u8; 4],
+}
+
+impl Default for Rng {
+    /// A generator seeded with [`RNG_SEED`].
+    fn default() -> Self {
+        Self::new(RNG_SEED)
+    }
+}
+
+impl Rng {
+    pub fn new(seed: u32) -> Self {
+        let mut rng = Self {
+            seed: 0,
+            state: 0,
+            latch: [0; 4],
+        };
+        rng.seed(seed);
+        rng
+    }
+
+    /// Restarts the sequence from `seed`, or from [`RNG_SEED`] for 0.
+    pub fn seed(&mut self, seed: u32) {
+        self.seed = if seed == 0 { RNG_SEED } else { seed };
+        self.state = self.seed;
+        self.latch = self.state.to_le_bytes();
+    }
+
+    /// The current state, which as a seed continues the sequence from here.
+    pub fn state(&self) -> u32 {
+        self.state
+    }
+
+    /// Steps the generator and returns the byte `VALUE` would read.
+    pub fn next_byte(&mut self) -> u8 {
+        let mut s = self.state;
+        s ^= s << 13;
+        s ^= s >> 17;
+        s ^= s << 5;
+        self.state = s;
+        (s >> 24) as u8
+    }
+}
+
+impl BusDevice for Rng {
+    fn read8(&mut self, offset: u16) -> u8 {
+        match offset {
+            VALUE => self.next_byte(),
+            _ => self
+                .state
+                .to_le_bytes()
+                .get(usize::from(offset) - 1)
+                .copied()
+                .unwrap_or(0),
+        }
+    }
+
+    fn write8(&mut self, offset: u16, val: u8) {
+        if let Some(byte) = self.latch.get_mut(usize::from(offset).wrapping_sub(1)) {
+            *byte = val;
+        }
+        if offset == SEED_HI {
+            self.seed(u32::from_le_bytes(self.latch));
+        }
+    }
+
+    /// The generator only changes when it is read.
+    fn batch_cycles(&self) -> u32 {
+        u32::MAX
+    }
+
+    /// Clears what a program left in the latch; a hard reset also restarts
+    /// the sequence from the seed last loaded, by the host or a program.
+    fn reset(&mut self, kind: ResetKind) {
+        match kind {
+            ResetKind::Soft => self.latch = self.state.to_le_bytes(),
+            ResetKind::Hard => self.seed(self.seed),
+        }
+    }
+
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&self.seed.to_le_bytes());
+        state.extend_from_slice(&self.state.to_le_bytes());
+        state.extend_from_slice(&self.latch);
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        let &[s0, s1, s2, s3, t0, t1, t2, t3, l0, l1, l2, l3] = state else {
+            return;
+        };
+        self.seed(u32::from_le_bytes([s0, s1, s2, s3]));
+        // The state is never 0.
+        if let state @ 1.. = u32::from_le_bytes([t0, t1, t2, t3]) {
+            self.state = state;
+        }
+        self.latch = [l0, l1, l2, l3];
+    }
+}
+
+impl Vm {
+    /// Seeds every mapped [`Rng`] with `seed` and returns how many there
+    /// were.
+    pub fn seed_rng(&mut self, seed: u32) -> usize {
+        self.bus_mut()
+            .devices_mut::<Rng>()
+            .map(|(_, rng)| rng.seed(seed))
+            .count()
+    }
+}
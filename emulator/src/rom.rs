@@ -0,0 +1,507 @@
+//! ROM images (`.rvm`).
+//!
+//! A ROM file is a 16-byte header followed by the program in 16 KiB banks:
+//!
+//! | Offset | Size | Contents                                      |
+//! | ------ | ---- | --------------------------------------------- |
+//! | 0      | 4    | magic `RVM8`                                  |
+//! | 4      | 1    | format version, currently 2                   |
+//! | 5      | 1    | bank count                                    |
+//! | 6      | 2    | entry point, little-endian                    |
+//! | 8      | 4    | CRC-32 of the bank data, little-endian        |
+//! | 12     | 1    | mapper: 0 flat, 1 banked, 2 banked with RAM   |
+//! | 13     | 1    | RAM bank count, for mapper 2                  |
+//! | 14     | 2    | reserved, zero                                |
+//!
+//! Version 1 had no mapper bytes and is read as flat.
+//!
+//! [`Vm::load_rom`] maps a flat ROM's banks at the top of the address space
+//! and write-protects them, so one bank fills 0xC000–0xFFFF and two fill
+//! 0x8000–0xFFFF. Other mappers switch more banks through 0x8000–0xBFFF,
+//! see [`crate::mapper`]. The loader stores the entry point in the reset
+//! vector at 0xFFFC–0xFFFD, overwriting whatever the last bank has there.
+//!
+//! [`Rom::info`] identifies a ROM build by checksums, which the `Vm` keeps
+//! for the ROM it loaded and [`crate::manifest`] checks against a list of
+//! known builds.
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use crate::ffi::RVM_MEM_SIZE;
+use crate::mapper::{Mapper, ROM_WINDOW};
+use crate::reset::ResetKind;
+use crate::vm::Vm;
+
+/// File magic.
+pub const MAGIC: [u8; 4] = *b"RVM8";
+/// Format version written by this crate, and the newest it reads.
+pub const VERSION: u8 = 2;
+/// Header size in bytes.
+pub const HEADER_SIZE: usize = 16;
+/// Size of one bank in bytes.
+pub const BANK_SIZE: usize = 0x4000;
+/// Most banks the flat address space can hold, see [`Mapper::max_banks`]
+/// for the others.
+pub const MAX_BANKS: usize = 2;
+
+const RESET_VECTOR: usize = 0xFFFC;
+const PAGE_SIZE: usize = 256;
+
+/// Why a ROM could not be loaded.
+#[derive(Debug)]
+pub enum RomError {
+    /// The file could not be read.
+    Io(io::Error),
+    /// The data is shorter than a header.
+    Truncated,
+    /// The data does not start with [`MAGIC`].
+    BadMagic,
+    /// The header names a format version this crate does not know.
+    UnsupportedVersion(u8),
+    /// The bank count is 0 or above what the mapper takes.
+    BadBankCount(u8),
+    /// The header names a mapper this crate does not know.
+    UnknownMapper(u8),
+    /// A mapper with RAM banks has none.
+    BadRamBankCount(u8),
+    /// The data after the header is not `banks * BANK_SIZE` bytes.
+    SizeMismatch { expected: usize, actual: usize },
+    /// The bank data does not match the header checksum.
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// A [manifest](crate::manifest) has no entry for the ROM's name.
+    NotInManifest(String),
+    /// The ROM does not match the SHA-256 digest its manifest lists.
+    DigestMismatch {
+        expected: [u8; 32],
+        actual: [u8; 32],
+    },
+    /// The data looks like a gzip file or zip archive but cannot be
+    /// unpacked.
+    #[cfg(feature = "compression")]
+    Archive(crate::archive::ArchiveError),
+    /// The ROM is banked and `start..=end` has a device where its
+    /// [bank registers](crate::mapper::MAPPER_PORTS) go.
+    MapConflict { start: u16, end: u16 },
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "cannot read ROM: {err}"),
+            Self::Truncated => write!(f, "ROM is shorter than its header"),
+            Self::BadMagic => write!(f, "not an rvm-8 ROM"),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported ROM format version {v}"),
+            Self::BadBankCount(n) => write!(f, "{n} banks do not fit the ROM's mapper"),
+            Self::UnknownMapper(id) => write!(f, "unknown mapper {id}"),
+            Self::BadRamBankCount(n) => write!(f, "{n} RAM banks do not fit the ROM's mapper"),
+            Self::SizeMismatch { expected, actual } => {
+                write!(f, "expected {expected} bytes of bank data, found {actual}")
+            }
+            Self::ChecksumMismatch { expected, actual } => {
+                write!(
+                    f,
+                    "checksum 0x{actual:08X} does not match header 0x{expected:08X}"
+                )
+            }
+            Self::NotInManifest(name) => write!(f, "`{name}` is not in the manifest"),
+            Self::DigestMismatch { expected, actual } => write!(
+                f,
+                "SHA-256 {} does not match manifest {}",
+                Hex(actual),
+                Hex(expected)
+            ),
+            #[cfg(feature = "compression")]
+            Self::Archive(err) => write!(f, "cannot unpack ROM: {err}"),
+            Self::MapConflict { start, end } => write!(
+                f,
+                "0x{start:04X}..=0x{end:04X} already has a device where the bank registers go"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RomError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            #[cfg(feature = "compression")]
+            Self::Archive(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RomError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// A validated ROM image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rom {
+    entry: u16,
+    mapper: Mapper,
+    pub(crate) data: Vec<u8>,
+}
+
+impl Rom {
+    /// Builds a flat ROM from raw bank data, padding it with zeros to whole
+    /// banks.
+    ///
+    /// Fails with [`RomError::BadBankCount`] if `data` is empty or needs
+    /// more than [`MAX_BANKS`] banks.
+    pub fn new(entry: u16, data: &[u8]) -> Result<Self, RomError> {
+        Self::with_mapper(entry, data, Mapper::Flat)
+    }
+
+    /// Builds a ROM for `mapper` from raw bank data, padding it with zeros
+    /// to whole banks. For a banked ROM the data starts with bank 0 and
+    /// ends with the fixed bank, which holds `entry`.
+    ///
+    /// Fails with [`RomError::BadBankCount`] if `data` is empty or needs
+    /// more than [`Mapper::max_banks`] banks, or
+    /// [`RomError::BadRamBankCount`] for [`Mapper::BankedRam`] without RAM
+    /// banks.
+    pub fn with_mapper(entry: u16, data: &[u8], mapper: Mapper) -> Result<Self, RomError> {
+        let banks = data.len().div_ceil(BANK_SIZE);
+        if banks == 0 || banks > mapper.max_banks() {
+            return Err(RomError::BadBankCount(banks.min(u8::MAX as usize) as u8));
+        }
+        if mapper == (Mapper::BankedRam { ram_banks: 0 }) {
+            return Err(RomError::BadRamBankCount(0));
+        }
+        let mut data = data.to_vec();
+        data.resize(banks * BANK_SIZE, 0);
+        Ok(Self {
+            entry,
+            mapper,
+            data,
+        })
+    }
+
+    /// Parses and validates a ROM file's contents. With the `compression`
+    /// feature, gzipped and zipped ROMs are unpacked first.
+    #[cfg_attr(feature = "compression", doc = "")]
+    #[cfg_attr(
+        feature = "compression",
+        doc = "See [`archive`](crate::archive) for the formats."
+    )]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, RomError> {
+        #[cfg(feature = "compression")]
+        let bytes = &*crate::archive::unpack(bytes).map_err(RomError::Archive)?;
+        let (header, data) = bytes
+            .split_first_chunk::<HEADER_SIZE>()
+            .ok_or(RomError::Truncated)?;
+        if header[..4] != MAGIC {
+            return Err(RomError::BadMagic);
+        }
+        let mapper = match header[4] {
+            1 => Mapper::Flat,
+            2 => Mapper::from_header([header[12], header[13]])?,
+            version => return Err(RomError::UnsupportedVersion(version)),
+        };
+        let banks = header[5];
+        if banks == 0 || banks as usize > mapper.max_banks() {
+            return Err(RomError::BadBankCount(banks));
+        }
+        let expected = banks as usize * BANK_SIZE;
+        if data.len() != expected {
+            return Err(RomError::SizeMismatch {
+                expected,
+                actual: data.len(),
+            });
+        }
+        let checksum = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+        let actual = crc32(data);
+        if actual != checksum {
+            return Err(RomError::ChecksumMismatch {
+                expected: checksum,
+                actual,
+            });
+        }
+        Ok(Self {
+            entry: u16::from_le_bytes([header[6], header[7]]),
+            mapper,
+            data: data.to_vec(),
+        })
+    }
+
+    /// Reads and validates a ROM file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, RomError> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+
+    /// Serializes the ROM, header included.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + self.data.len());
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(VERSION);
+        bytes.push(self.banks() as u8);
+        bytes.extend_from_slice(&self.entry.to_le_bytes());
+        bytes.extend_from_slice(&crc32(&self.data).to_le_bytes());
+        bytes.extend_from_slice(&self.mapper.to_header());
+        bytes.extend_from_slice(&[0; 2]);
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    /// Address execution starts at.
+    pub fn entry(&self) -> u16 {
+        self.entry
+    }
+
+    /// How the banks reach the address space.
+    pub fn mapper(&self) -> Mapper {
+        self.mapper
+    }
+
+    /// Number of 16 KiB banks.
+    pub fn banks(&self) -> usize {
+        self.data.len() / BANK_SIZE
+    }
+
+    /// The bank data, without the header.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// First address the ROM is mapped at.
+    pub fn base(&self) -> u16 {
+        match self.mapper {
+            Mapper::Flat => (RVM_MEM_SIZE - self.data.len()) as u16,
+            Mapper::Banked | Mapper::BankedRam { .. } => *ROM_WINDOW.start(),
+        }
+    }
+
+    /// Checksums identifying this build of the ROM.
+    pub fn info(&self) -> RomInfo {
+        RomInfo {
+            crc32: crc32(&self.data),
+            sha256: sha256(&self.to_bytes()),
+        }
+    }
+
+    /// Offset into the bank data of the byte the CPU sees at `addr` once
+    /// the ROM is loaded, with bank 0 in [`ROM_WINDOW`].
+    pub(crate) fn offset(&self, addr: u16) -> Option<usize> {
+        let addr = addr.checked_sub(self.base())? as usize;
+        match self.mapper {
+            Mapper::Flat => Some(addr),
+            _ if addr < BANK_SIZE => Some(addr),
+            _ => Some(self.data.len() - BANK_SIZE + (addr - BANK_SIZE)),
+        }
+    }
+}
+
+/// Checksums identifying a ROM build, from [`Rom::info`].
+///
+/// Displays as `crc32 <hex> sha256 <hex>`, for stamping logs and traces
+/// with the build they came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RomInfo {
+    /// CRC-32 of the bank data, as stored in the header.
+    pub crc32: u32,
+    /// SHA-256 of the ROM image, header included, which is what
+    /// `sha256sum` prints for an uncompressed `.rvm` file.
+    pub sha256: [u8; 32],
+}
+
+impl fmt::Display for RomInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "crc32 {:08x} sha256 {}", self.crc32, Hex(&self.sha256))
+    }
+}
+
+/// Lowercase hex digits of the bytes.
+pub(crate) struct Hex<'a>(pub &'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
+
+/// CRC-32 (IEEE 802.3), as used by zip and PNG.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                crc >> 1 ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// SHA-256 (FIPS 180-4).
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+        0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+        0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+        0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+        0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+        0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+        0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+        0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+        0xc67178f2,
+    ];
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+
+    // The message, a 1 bit, zeros up to 8 bytes short of a whole block,
+    // and the message length in bits.
+    let mut padded = data.to_vec();
+    padded.push(0x80);
+    let len = (data.len() + 9).next_multiple_of(64);
+    padded.resize(len, 0);
+    padded[len - 8..].copy_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in padded.chunks_exact(64) {
+        let mut w = [0u32; 64];
+        for (word, bytes) in w.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ w[i - 15] >> 3;
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ w[i - 2] >> 10;
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for (k, w) in K.iter().zip(w) {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let choice = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(choice)
+                .wrapping_add(*k)
+                .wrapping_add(w);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(majority);
+            (h, g, f, e) = (g, f, e, d.wrapping_add(t1));
+            (d, c, b, a) = (c, b, a, t1.wrapping_add(t2));
+        }
+        for (word, add) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *word = word.wrapping_add(add);
+        }
+    }
+
+    let mut digest = [0; 32];
+    for (bytes, word) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+/// What [`Vm::reload_rom`] keeps of the running machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReloadPolicy {
+    /// Keep every byte outside the new ROM, RAM banks and the bank
+    /// selection included, instead of zeroing them.
+    pub keep_ram: bool,
+    /// Keep the CPU running where it is instead of resetting into the new
+    /// entry point.
+    pub keep_registers: bool,
+}
+
+impl Default for ReloadPolicy {
+    /// Keeps everything but the ROM.
+    fn default() -> Self {
+        Self {
+            keep_ram: true,
+            keep_registers: true,
+        }
+    }
+}
+
+impl Vm {
+    /// Maps `rom` at the top of the address space and resets into its entry
+    /// point. A banked ROM also gets its [bank registers](crate::mapper).
+    ///
+    /// Pages holding the ROM become read-only to the CPU; writes from the
+    /// host through [`Vm::write`] still go through. Any previously loaded ROM
+    /// is unmapped first.
+    ///
+    /// Fails with [`RomError::MapConflict`], changing nothing, if `rom` is
+    /// banked and another device is mapped on
+    /// [`MAPPER_PORTS`](crate::mapper::MAPPER_PORTS).
+    pub fn load_rom(&mut self, rom: &Rom) -> Result<(), RomError> {
+        self.map_rom(rom, false)?;
+        self.reset(ResetKind::Soft);
+        Ok(())
+    }
+
+    /// Swaps the ROM file in `bytes` in for the one loaded, without
+    /// resetting unless `policy` says so, for iterating on a program
+    /// without losing its state.
+    ///
+    /// The new ROM is mapped as by [`Vm::load_rom`]. Pages the old ROM held
+    /// and the new one does not become RAM with the old bytes in them, or
+    /// zeros if RAM is not kept. A banked ROM replacing one with as many RAM
+    /// banks keeps the bank selection, wrapped around the new bank count.
+    /// Devices, hooks and the interrupt controller are left alone.
+    ///
+    /// Fails, changing nothing, if `bytes` is not a valid ROM or, as
+    /// [`Vm::load_rom`], with [`RomError::MapConflict`].
+    pub fn reload_rom(&mut self, bytes: &[u8], policy: ReloadPolicy) -> Result<(), RomError> {
+        let rom = Rom::from_bytes(bytes)?;
+        self.map_rom(&rom, policy.keep_ram)?;
+        if !policy.keep_ram {
+            let rom_pages = self.cpu.rom_pages;
+            self.preserving_sram(|vm| {
+                for (page, bytes) in vm.memory_mut().chunks_mut(PAGE_SIZE).enumerate() {
+                    if rom_pages[page] == 0 {
+                        bytes.fill(0);
+                    }
+                }
+            });
+        }
+        if !policy.keep_registers {
+            self.reset(ResetKind::Soft);
+        }
+        Ok(())
+    }
+
+    /// Maps `rom` without resetting, keeping the banks of a banked ROM
+    /// already loaded if `keep_banks` is set and they fit.
+    ///
+    /// Fails as [`Vm::load_rom`], before changing anything.
+    fn map_rom(&mut self, rom: &Rom, keep_banks: bool) -> Result<(), RomError> {
+        let base = rom.base() as usize;
+        match rom.mapper {
+            Mapper::Flat => {
+                self.unload_banks();
+                self.memory_mut()[base..].copy_from_slice(&rom.data);
+            }
+            Mapper::Banked | Mapper::BankedRam { .. } if keep_banks => self.swap_banks(rom)?,
+            Mapper::Banked | Mapper::BankedRam { .. } => self.load_banks(rom)?,
+        }
+        self.cpu.rom_pages = [0; 256];
+        let memory = self.memory_mut();
+        memory[RESET_VECTOR..RESET_VECTOR + 2].copy_from_slice(&rom.entry.to_le_bytes());
+        self.cpu.rom_pages[base / PAGE_SIZE..].fill(1);
+        self.bus_mut().pages_dirty = true;
+        self.rom_info = Some(rom.info());
+        Ok(())
+    }
+
+    /// Checksums of the ROM loaded last, or `None` before the first
+    /// [`Vm::load_rom`].
+    pub fn rom_info(&self) -> Option<RomInfo> {
+        self.rom_info
+    }
+}
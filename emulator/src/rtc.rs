@@ -0,0 +1,268 @@
+//! Real-time clock.
+//!
+//! An [`Rtc`] keeps the date and time of day, to the second. It runs in one
+//! of two [`RtcMode`]s: [`RtcMode::Host`] follows the host's wall clock, for
+//! programs that show the real time, and [`RtcMode::Cycles`] counts emulated
+//! seconds by the CPU clock from a fixed start, so a replay or a test sees
+//! the same time at the same cycle on every run. The RTC is not mapped by
+//! default; [`RTC_PORTS`] is its conventional slot:
+//!
+//! ```
+//! # use emulator::{rtc::{Rtc, RtcMode, RTC_PORTS}, vm::CLOCK_HZ, Vm};
+//! let mut vm = Vm::new();
+//! let rtc = Rtc::new(RtcMode::Cycles { start: 1_700_000_000, clock_hz: CLOCK_HZ });
+//! vm.bus_mut().map(RTC_PORTS, rtc).unwrap();
+//! ```
+//!
+//! | Offset | Register                                          |
+//! | ------ | ------------------------------------------------- |
+//! | 0      | `SECONDS`, 0–59; reading latches every register   |
+//! | 1      | `MINUTES`, 0–59                                   |
+//! | 2      | `HOURS`, 0–23                                     |
+//! | 3      | `WEEKDAY`, 0 for Sunday to 6; read-only           |
+//! | 4      | `DAY` of the month, 1–31                          |
+//! | 5      | `MONTH`, 1–12                                     |
+//! | 6–7    | `YEAR`, little-endian                             |
+//!
+//! Times are UTC. Reading `SECONDS` latches the whole time, so a program
+//! reads it first and then the rest without the time rolling over in
+//! between. Writes go to the latch, and writing `SECONDS` sets the clock to
+//! the latched date and time and restarts the current second; a program
+//! writes the other fields first. Fields out of range carry over, so
+//! 31 February sets 3 March, or 2 March in a leap year.
+//!
+//! A cycle-counting RTC assumes the clock rate it was given, which
+//! [`Vm::set_clock_hz`](crate::Vm::set_clock_hz) does not update. A
+//! [`Snapshot`](crate::Snapshot) saves the time and the latch, so restoring
+//! one turns a cycle-counting clock back with the machine; one following
+//! the host keeps its offset from the host's time.
+
+use std::ops::RangeInclusive;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::bus::BusDevice;
+use crate::reset::ResetKind;
+
+/// The RTC registers.
+pub const RTC_PORTS: RangeInclusive<u16> = 0x2750..=0x2757;
+/// 2000-01-01 00:00:00 UTC, where a [`Rtc::default`] starts counting.
+pub const RTC_EPOCH: i64 = 946_684_800;
+
+const SECONDS: u16 = 0;
+const WEEKDAY: u16 = 3;
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// Where an [`Rtc`] gets the time from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtcMode {
+    /// The host's wall clock.
+    Host,
+    /// Seconds counted on a `clock_hz` CPU clock, from `start` in seconds
+    /// since the Unix epoch.
+    Cycles { start: i64, clock_hz: u32 },
+}
+
+/// The real-time clock.
+#[derive(Debug, Clone)]
+pub struct Rtc {
+    mode: RtcMode,
+    /// The time in seconds since the Unix epoch when counting cycles, or its
+    /// offset from the host's when following the host.
+    seconds: i64,
+    /// Cycles into the current second.
+    phase: u32,
+    latch: [u8; 8],
+}
+
+impl Default for Rtc {
+    /// An RTC counting cycles at [`CLOCK_HZ`](crate::vm::CLOCK_HZ) from
+    /// [`RTC_EPOCH`].
+    fn default() -> Self {
+        Self::new(RtcMode::Cycles {
+            start: RTC_EPOCH,
+            clock_hz: crate::vm::CLOCK_HZ,
+        })
+    }
+}
+
+impl Rtc {
+    /// An RTC reading the time from `mode`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a cycle-counting `mode` has a `clock_hz` of 0.
+    pub fn new(mode: RtcMode) -> Self {
+        let seconds = match mode {
+            RtcMode::Host => 0,
+            RtcMode::Cycles { start, clock_hz } => {
+                assert!(clock_hz > 0, "the RTC clock must be non-zero");
+                start
+            }
+        };
+        let mut rtc = Self {
+            mode,
+            seconds,
+            phase: 0,
+            latch: [0; 8],
+        };
+        rtc.latch = fields(rtc.time());
+        rtc
+    }
+
+    pub fn mode(&self) -> RtcMode {
+        self.mode
+    }
+
+    /// The current time in seconds since the Unix epoch.
+    pub fn time(&self) -> i64 {
+        match self.mode {
+            RtcMode::Host => host_time() + self.seconds,
+            RtcMode::Cycles { .. } => self.seconds,
+        }
+    }
+
+    /// Sets the clock to `time` in seconds since the Unix epoch, at the
+    /// start of that second.
+    pub fn set_time(&mut self, time: i64) {
+        self.seconds = match self.mode {
+            RtcMode::Host => time - host_time(),
+            RtcMode::Cycles { .. } => time,
+        };
+        self.phase = 0;
+    }
+}
+
+fn host_time() -> i64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs() as i64),
+    }
+}
+
+/// The register values for `time`.
+fn fields(time: i64) -> [u8; 8] {
+    let days = time.div_euclid(SECONDS_PER_DAY);
+    let secs = time.rem_euclid(SECONDS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+    let weekday = (days + 4).rem_euclid(7);
+    let [year_lo, year_hi] = (year as u16).to_le_bytes();
+    [
+        (secs % 60) as u8,
+        (secs / 60 % 60) as u8,
+        (secs / 3600) as u8,
+        weekday as u8,
+        day as u8,
+        month as u8,
+        year_lo,
+        year_hi,
+    ]
+}
+
+/// The time the register values `latch` name, carrying fields over.
+fn time_of(latch: &[u8; 8]) -> i64 {
+    let [sec, min, hour, _, day, month, year_lo, year_hi] = latch.map(i64::from);
+    // Carry whole years out of the month first, with month 0 the December
+    // before.
+    let year = year_lo | year_hi << 8;
+    let months = year * 12 + month - 1;
+    let days = days_from_civil(months.div_euclid(12), months.rem_euclid(12) + 1, day);
+    days * SECONDS_PER_DAY + hour * 3600 + min * 60 + sec
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date, for `day` of any
+/// size.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// The year, month and day `days` after 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+impl BusDevice for Rtc {
+    fn read8(&mut self, offset: u16) -> u8 {
+        if offset == SECONDS {
+            self.latch = fields(self.time());
+        }
+        self.latch.get(usize::from(offset)).copied().unwrap_or(0)
+    }
+
+    fn write8(&mut self, offset: u16, val: u8) {
+        match offset {
+            SECONDS => {
+                self.latch[0] = val;
+                self.set_time(time_of(&self.latch));
+                self.latch = fields(self.time());
+            }
+            WEEKDAY => {}
+            _ => {
+                if let Some(field) = self.latch.get_mut(usize::from(offset)) {
+                    *field = val;
+                }
+            }
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        let RtcMode::Cycles { clock_hz, .. } = self.mode else {
+            return;
+        };
+        let total = u64::from(self.phase) + u64::from(cycles);
+        self.seconds += (total / u64::from(clock_hz)) as i64;
+        self.phase = (total % u64::from(clock_hz)) as u32;
+    }
+
+    /// Until the next second when counting cycles, since the registers only
+    /// change then; unbounded when following the host.
+    fn batch_cycles(&self) -> u32 {
+        match self.mode {
+            RtcMode::Host => u32::MAX,
+            RtcMode::Cycles { clock_hz, .. } => clock_hz - self.phase,
+        }
+    }
+
+    /// Latches the time again; the clock itself runs on.
+    fn reset(&mut self, _: ResetKind) {
+        self.latch = fields(self.time());
+    }
+
+    /// The seconds, the phase and the latch; the mode is the RTC's own.
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&self.seconds.to_le_bytes());
+        state.extend_from_slice(&self.phase.to_le_bytes());
+        state.extend_from_slice(&self.latch);
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        let Some((seconds, rest)) = state.split_first_chunk::<8>() else {
+            return;
+        };
+        let Some((phase, latch)) = rest.split_first_chunk::<4>() else {
+            return;
+        };
+        let Ok(latch) = latch.try_into() else {
+            return;
+        };
+        self.seconds = i64::from_le_bytes(*seconds);
+        self.phase = u32::from_le_bytes(*phase);
+        if let RtcMode::Cycles { clock_hz, .. } = self.mode {
+            self.phase %= clock_hz;
+        }
+        self.latch = latch;
+    }
+}
@@ -0,0 +1,383 @@
+//! Events at absolute cycle timestamps.
+//!
+//! The [`Scheduler`] a [`Vm`] owns runs each pending event once the
+//! machine's clock reaches the event's cycle. The clock is [`Vm::clock`]:
+//! cycles since construction or the last reset, like [`Vm::cycles`] but
+//! without wrapping. An event is a kind number the host picks and a cycle;
+//! when it falls due the scheduler calls the handler the host set for its
+//! kind with [`Scheduler::on`]. A handler gets the whole machine, so it can
+//! raise an interrupt line, poke memory or schedule the next event itself,
+//! which is how a periodic event repeats:
+//!
+//! ```
+//! # use emulator::{ResetKind, Vm};
+//! const TICK: u32 = 0;
+//!
+//! let mut vm = Vm::new();
+//! // LDA #$A9, over and over.
+//! vm.load(0x8000, &[0xA9; 0x4000]).unwrap();
+//! vm.load(0xFFFC, &[0x00, 0x80]).unwrap();
+//! vm.reset(ResetKind::Soft);
+//! vm.scheduler_mut().on(TICK, |vm| {
+//!     let count = vm.read(0x0200);
+//!     vm.write(0x0200, count + 1);
+//!     vm.scheduler_mut().after(1_000, TICK);
+//! });
+//! vm.scheduler_mut().at(1_000, TICK);
+//! vm.run_cycles(10_500).unwrap();
+//! assert_eq!(vm.read(0x0200), 10);
+//! ```
+//!
+//! Events run between instructions, at the first boundary at or past their
+//! cycle, in order of cycle and then of scheduling, after any of the
+//! machine's own events due at the same cycle. One scheduled for a
+//! cycle already reached, including by another event, runs at the next
+//! boundary. Batched runs end at the next event's cycle, so it runs after
+//! the very instruction it would when stepping. A step that fails leaves
+//! the events due for the next one.
+//!
+//! The machine schedules its own events too. A begun frame ends with a
+//! vblank event at the frame's last cycle, which renders the picture and
+//! finishes the frame; frames are begun by [`Vm::run_frame`] and the
+//! [frame stepping](crate::stepping) calls, so plain steps run past a
+//! vblank without taking it. A device that knows when it will next raise an
+//! interrupt line, such as a [`Timer`](crate::timer::Timer) counting
+//! towards its overflow, reports it through
+//! [`BusDevice::next_irq`](crate::BusDevice::next_irq) and gets an event
+//! there, so batches end on time without the device bounding them.
+//!
+//! Pending events are machine state: a [`Snapshot`](crate::Snapshot) saves
+//! the host's by cycle and kind, and restoring one, rewinding or rolling
+//! back replaces them with those saved, so the run continues exactly as it
+//! did. The machine's own events follow from the rest of the state and are
+//! scheduled afresh. Handlers belong to the host, like hooks, and stay as
+//! they are; a machine loading a save state from another process needs the
+//! same handlers set. Resetting moves the clock but leaves the pending
+//! events in place.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::vm::Vm;
+
+type Handler = Box<dyn FnMut(&mut Vm) + Send>;
+
+/// Identifies a scheduled event for [`Scheduler::cancel`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EventId {
+    cycle: u64,
+    /// Whether the host scheduled it; the machine's own events due at the
+    /// same cycle come first.
+    host: bool,
+    seq: u64,
+}
+
+impl EventId {
+    /// The cycle the event is due at.
+    pub fn cycle(self) -> u64 {
+        self.cycle
+    }
+}
+
+/// A pending event of the host's, as a [`Snapshot`](crate::Snapshot)
+/// saves it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScheduledEvent {
+    /// Cycles from the snapshot until the event is due, negative if it
+    /// already was.
+    pub delay: i64,
+    /// The kind whose handler runs.
+    pub kind: u32,
+}
+
+/// What an event does when it falls due.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    /// Runs the host's handler for the kind.
+    Host(u32),
+    /// Ends the current frame, if one was begun.
+    Vblank,
+    /// Asks the devices when they next raise an interrupt line.
+    Device,
+}
+
+/// Pending events, by due cycle, and the handlers of their kinds.
+#[derive(Default)]
+pub struct Scheduler {
+    events: BTreeMap<EventId, Action>,
+    /// Each kind's handler; `None` while it runs.
+    handlers: BTreeMap<u32, Option<Handler>>,
+    next_seq: u64,
+    /// The clock when it was last brought up to the CPU's counter.
+    now: u64,
+    /// The CPU's counter at that point.
+    seen: u32,
+    /// The due cycle of the event running, which [`Scheduler::after`]
+    /// counts from.
+    running: Option<u64>,
+    vblank: Option<EventId>,
+}
+
+impl Scheduler {
+    /// Runs `handler` for every event of `kind` that falls due, replacing
+    /// any handler the kind had.
+    pub fn on(&mut self, kind: u32, handler: impl FnMut(&mut Vm) + Send + 'static) {
+        self.handlers.insert(kind, Some(Box::new(handler)));
+    }
+
+    /// Removes the handler of `kind`, returning whether it had one. Events
+    /// of a kind without a handler do nothing when they fall due.
+    pub fn remove_handler(&mut self, kind: u32) -> bool {
+        self.handlers.remove(&kind).is_some()
+    }
+
+    /// Schedules an event of `kind` at `cycle`.
+    pub fn at(&mut self, cycle: u64, kind: u32) -> EventId {
+        self.schedule(cycle, Action::Host(kind))
+    }
+
+    /// Schedules an event of `kind` `cycles` cycles from now, or from the
+    /// cycle the running event was due at when a handler calls it, so an
+    /// event that schedules the next one keeps a steady period however late
+    /// the boundary it ran at.
+    pub fn after(&mut self, cycles: u64, kind: u32) -> EventId {
+        let from = self.running.unwrap_or(self.now);
+        self.at(from.saturating_add(cycles), kind)
+    }
+
+    /// Drops a pending event, returning whether it had yet to run.
+    pub fn cancel(&mut self, id: EventId) -> bool {
+        self.events.remove(&id).is_some()
+    }
+
+    /// The cycle the earliest pending event of the host's is due at.
+    pub fn next_due(&self) -> Option<u64> {
+        self.pending().next().map(|(id, _)| id.cycle)
+    }
+
+    /// The host's pending events and their kinds, in the order they run.
+    pub fn pending(&self) -> impl Iterator<Item = (EventId, u32)> + '_ {
+        self.events
+            .iter()
+            .filter_map(|(&id, &action)| match action {
+                Action::Host(kind) => Some((id, kind)),
+                Action::Vblank | Action::Device => None,
+            })
+    }
+
+    /// The number of the host's pending events.
+    pub fn len(&self) -> usize {
+        self.pending().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending().next().is_none()
+    }
+
+    /// Drops every pending event of the host's.
+    pub fn clear(&mut self) {
+        self.events
+            .retain(|_, action| !matches!(action, Action::Host(_)));
+    }
+
+    fn schedule(&mut self, cycle: u64, action: Action) -> EventId {
+        let id = EventId {
+            cycle,
+            host: matches!(action, Action::Host(_)),
+            seq: self.next_seq,
+        };
+        self.next_seq += 1;
+        self.events.insert(id, action);
+        id
+    }
+
+    /// Moves the vblank event to `cycle`.
+    pub(crate) fn schedule_vblank(&mut self, cycle: u64) {
+        if let Some(id) = self.vblank.take() {
+            self.events.remove(&id);
+        }
+        self.vblank = Some(self.schedule(cycle, Action::Vblank));
+    }
+
+    /// Schedules a device's next interrupt at `cycle`.
+    pub(crate) fn schedule_device(&mut self, cycle: u64) {
+        self.schedule(cycle, Action::Device);
+    }
+
+    /// Drops every device's event, for them to be scheduled afresh.
+    pub(crate) fn clear_devices(&mut self) {
+        self.events.retain(|_, action| *action != Action::Device);
+    }
+
+    /// The earliest event that may run, with a frame begun or not.
+    fn next_runnable(&self, framing: bool) -> Option<(EventId, Action)> {
+        self.events
+            .iter()
+            .find(|&(_, &action)| framing || action != Action::Vblank)
+            .map(|(&id, &action)| (id, action))
+    }
+
+    /// Advances the clock to the CPU's counter `cycles`, which moved on by
+    /// less than 2^32 since the last call.
+    fn sync(&mut self, cycles: u32) {
+        self.now += u64::from(cycles.wrapping_sub(self.seen));
+        self.seen = cycles;
+    }
+
+    /// Sets the clock to `now` with the CPU's counter at `cycles`, after
+    /// either jumped.
+    pub(crate) fn rebase(&mut self, now: u64, cycles: u32) {
+        self.now = now;
+        self.seen = cycles;
+    }
+
+    fn elapsed(&self, cycles: u32) -> u64 {
+        self.now + u64::from(cycles.wrapping_sub(self.seen))
+    }
+}
+
+impl fmt::Debug for Scheduler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scheduler")
+            .field("now", &self.now)
+            .field("events", &self.pending().collect::<Vec<_>>())
+            .field("handlers", &self.handlers.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl Vm {
+    /// Cycles since construction or the last reset, unwrapped; the clock
+    /// [scheduled events](crate::scheduler) are due on.
+    pub fn clock(&self) -> u64 {
+        self.scheduler.elapsed(self.cpu.cycles)
+    }
+
+    pub fn scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
+
+    /// The scheduler, with its clock brought up to date for
+    /// [`Scheduler::after`].
+    pub fn scheduler_mut(&mut self) -> &mut Scheduler {
+        self.scheduler.sync(self.cpu.cycles);
+        &mut self.scheduler
+    }
+
+    /// Runs the events due by now, leaving those they schedule for later.
+    /// Vblank waits for a frame to have begun.
+    pub(crate) fn run_scheduled(&mut self) {
+        self.schedule_devices();
+        self.scheduler.sync(self.cpu.cycles);
+        let fresh = self.scheduler.next_seq;
+        loop {
+            // A handler may have restored a snapshot, moving the clock.
+            let now = self.scheduler.now;
+            let Some((id, action)) = self
+                .scheduler
+                .events
+                .range(
+                    ..=EventId {
+                        cycle: now,
+                        host: true,
+                        seq: u64::MAX,
+                    },
+                )
+                .find(|&(id, &action)| {
+                    id.seq < fresh && (self.frame_started || action != Action::Vblank)
+                })
+                .map(|(&id, &action)| (id, action))
+            else {
+                return;
+            };
+            self.scheduler.events.remove(&id);
+            self.scheduler.running = Some(id.cycle);
+            match action {
+                Action::Host(kind) => self.run_handler(kind),
+                Action::Vblank => self.finish_frame(),
+                Action::Device => self.bus_mut().devices_stale = true,
+            }
+            self.scheduler.running = None;
+            self.schedule_devices();
+        }
+    }
+
+    fn run_handler(&mut self, kind: u32) {
+        let Some(mut handler) = self
+            .scheduler
+            .handlers
+            .get_mut(&kind)
+            .and_then(Option::take)
+        else {
+            return;
+        };
+        handler(self);
+        // Unless the handler removed or replaced itself.
+        if let Some(slot @ None) = self.scheduler.handlers.get_mut(&kind) {
+            *slot = Some(handler);
+        }
+    }
+
+    /// The host's pending events for a snapshot, due as many cycles from
+    /// now as they are.
+    pub(crate) fn save_events(&self) -> Vec<ScheduledEvent> {
+        let now = self.clock();
+        self.scheduler
+            .pending()
+            .map(|(id, kind)| ScheduledEvent {
+                delay: id.cycle.wrapping_sub(now) as i64,
+                kind,
+            })
+            .collect()
+    }
+
+    /// Replaces the host's pending events with those a snapshot saved, due
+    /// as many cycles from now as they were from it and in the same order.
+    pub(crate) fn restore_events(&mut self, events: &[ScheduledEvent]) {
+        let now = self.clock();
+        self.scheduler.clear();
+        for event in events {
+            let cycle = now.saturating_add_signed(event.delay);
+            self.scheduler.at(cycle, event.kind);
+        }
+    }
+
+    /// Schedules the end of the current frame.
+    pub(crate) fn schedule_vblank(&mut self) {
+        let end = self.frame_cycle + u64::from(self.cycles_per_frame());
+        self.scheduler.schedule_vblank(end);
+    }
+
+    /// Schedules the machine's own events afresh after the clock jumped.
+    pub(crate) fn reschedule_machine(&mut self) {
+        self.schedule_vblank();
+        self.bus_mut().devices_stale = true;
+        self.schedule_devices();
+    }
+
+    /// Asks the devices when they next raise an interrupt line, if anything
+    /// reached them since they were last asked, and schedules events there.
+    fn schedule_devices(&mut self) {
+        if !self.bus().devices_stale {
+            return;
+        }
+        self.scheduler.clear_devices();
+        let now = self.clock();
+        let (bus, scheduler) = self.bus_and_scheduler();
+        bus.schedule_irqs(now, scheduler);
+    }
+
+    /// Cycles the kernel can run before the next event falls due, or 0 if
+    /// one already has.
+    pub(crate) fn scheduled_batch_cycles(&self) -> u32 {
+        match self.scheduler.next_runnable(self.frame_started) {
+            Some((id, _)) => id
+                .cycle
+                .saturating_sub(self.clock())
+                .try_into()
+                .unwrap_or(u32::MAX),
+            None => u32::MAX,
+        }
+    }
+}
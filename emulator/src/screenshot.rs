@@ -0,0 +1,244 @@
+//! Screenshots and frame dumps.
+//!
+//! [`Vm::screenshot_png`] saves the framebuffer as a PNG, and
+//! [`Vm::dump_frames`] saves every frame from then on as it reaches
+//! vblank: as numbered PNG files, or as raw framebuffer bytes streamed to a
+//! writer for a video encoder to pick up.
+//!
+//! The PNGs show the framebuffer exactly as the [`DisplayConfig`] lays it
+//! out, scale included: RGBA frames become RGBA images, RGB565 frames RGB
+//! images with each channel widened to 8 bits, and indexed frames paletted
+//! images over [`PALETTE`]. Image data is stored without compression, so
+//! the same frame always encodes to the same bytes and golden images can be
+//! compared byte for byte.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::display::{DisplayConfig, PALETTE, PixelFormat};
+use crate::vm::Vm;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+/// Longest stored deflate block.
+const STORED_BLOCK: usize = 0xFFFF;
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xEDB8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+};
+
+fn crc32(chunks: &[&[u8]]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in chunks.iter().copied().flatten() {
+        crc = CRC_TABLE[((crc ^ u32::from(byte)) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + u32::from(byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+fn chunk(out: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    out.write_all(&crc32(&[kind, data]).to_be_bytes())
+}
+
+/// Widens a `bits`-bit channel to 8 bits.
+fn widen(value: u16, bits: u32) -> u8 {
+    let value = value as u8;
+    value << (8 - bits) | value >> (2 * bits - 8)
+}
+
+/// Writes `framebuffer`, laid out as `config` says, as a PNG.
+fn encode(out: &mut impl Write, config: DisplayConfig, framebuffer: &[u8]) -> io::Result<()> {
+    let (width, height) = (config.width(), config.height());
+    let (color_type, channels) = match config.format {
+        PixelFormat::Rgba8888 => (6, 4),
+        PixelFormat::Rgb565 => (2, 3),
+        PixelFormat::Indexed => (3, 1),
+    };
+    let row_len = width * config.format.bytes_per_pixel();
+    let mut image = Vec::with_capacity(height * (1 + width * channels));
+    for row in framebuffer.chunks_exact(row_len) {
+        // Filter type 0: the row as it is.
+        image.push(0);
+        match config.format {
+            PixelFormat::Rgba8888 | PixelFormat::Indexed => image.extend_from_slice(row),
+            PixelFormat::Rgb565 => {
+                for pixel in row.chunks_exact(2) {
+                    let rgb = u16::from_le_bytes([pixel[0], pixel[1]]);
+                    image.extend([
+                        widen(rgb >> 11, 5),
+                        widen(rgb >> 5 & 0x3F, 6),
+                        widen(rgb & 0x1F, 5),
+                    ]);
+                }
+            }
+        }
+    }
+
+    let palette: Option<Vec<u8>> = (config.format == PixelFormat::Indexed)
+        .then(|| PALETTE.iter().flat_map(|&[r, g, b, _]| [r, g, b]).collect());
+    write_png(out, (width, height), color_type, palette.as_deref(), &image)
+}
+
+/// Writes a PNG of 8-bit `color_type` pixels from `image`, its rows each
+/// led by a filter type byte, with `palette` as its `PLTE` chunk.
+pub(crate) fn write_png(
+    out: &mut impl Write,
+    (width, height): (usize, usize),
+    color_type: u8,
+    palette: Option<&[u8]>,
+    image: &[u8],
+) -> io::Result<()> {
+    let mut zlib = Vec::with_capacity(image.len() + image.len() / STORED_BLOCK * 5 + 11);
+    zlib.extend([0x78, 0x01]);
+    let blocks = image.chunks(STORED_BLOCK);
+    let last = blocks.len() - 1;
+    for (i, block) in blocks.enumerate() {
+        let len = block.len() as u16;
+        zlib.push(u8::from(i == last));
+        zlib.extend(len.to_le_bytes());
+        zlib.extend((!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend(adler32(image).to_be_bytes());
+
+    let mut header = Vec::with_capacity(13);
+    header.extend((width as u32).to_be_bytes());
+    header.extend((height as u32).to_be_bytes());
+    header.extend([8, color_type, 0, 0, 0]);
+
+    out.write_all(&SIGNATURE)?;
+    chunk(out, b"IHDR", &header)?;
+    if let Some(palette) = palette {
+        chunk(out, b"PLTE", palette)?;
+    }
+    chunk(out, b"IDAT", &zlib)?;
+    chunk(out, b"IEND", &[])
+}
+
+/// Where [`Vm::dump_frames`] puts frames.
+pub struct FrameDump {
+    target: Target,
+}
+
+enum Target {
+    Png(PathBuf),
+    Raw(Box<dyn Write + Send>),
+}
+
+impl FrameDump {
+    /// Saves each frame as `frame_NNNNNN.png` in `dir`, which must exist,
+    /// numbered by [`Vm::frame`].
+    pub fn png_files(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            target: Target::Png(dir.into()),
+        }
+    }
+
+    /// Writes each frame's framebuffer bytes to `writer`, one frame after
+    /// another with nothing in between.
+    pub fn raw(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            target: Target::Raw(Box::new(writer)),
+        }
+    }
+}
+
+impl fmt::Debug for FrameDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.target {
+            Target::Png(dir) => f.debug_tuple("PngFiles").field(dir).finish(),
+            Target::Raw(_) => f.write_str("Raw"),
+        }
+    }
+}
+
+/// Host-side frame dump state.
+#[derive(Default)]
+pub(crate) struct Dumper {
+    pub(crate) dump: Option<FrameDump>,
+    error: Option<io::Error>,
+}
+
+impl Vm {
+    /// Saves the framebuffer to `path` as a PNG.
+    pub fn screenshot_png(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        save(path.as_ref(), self.display_config(), self.framebuffer())
+    }
+
+    /// Writes the framebuffer to `writer` as a PNG.
+    pub fn write_png(&self, mut writer: impl Write) -> io::Result<()> {
+        encode(&mut writer, self.display_config(), self.framebuffer())
+    }
+
+    /// Saves every frame from now on at vblank, after the vblank callback,
+    /// replacing any previous dump. The first error stops the dump and is
+    /// kept for [`Vm::take_frame_dump_error`].
+    ///
+    /// Frames replayed by [`Vm::rewind`] are not dumped again.
+    pub fn dump_frames(&mut self, dump: FrameDump) {
+        self.dumper.dump = Some(dump);
+    }
+
+    /// Stops dumping frames.
+    pub fn stop_frame_dump(&mut self) {
+        self.dumper.dump = None;
+    }
+
+    /// Takes the error that stopped the last frame dump, if any.
+    pub fn take_frame_dump_error(&mut self) -> Option<io::Error> {
+        self.dumper.error.take()
+    }
+
+    /// Saves the frame that just reached vblank.
+    pub(crate) fn dump_frame(&mut self) {
+        let Some(dump) = &mut self.dumper.dump else {
+            return;
+        };
+        let display = &self.display;
+        let result = match &mut dump.target {
+            Target::Png(dir) => {
+                let path = dir.join(format!("frame_{:06}.png", self.frame));
+                save(&path, display.config, &display.framebuffer)
+            }
+            Target::Raw(writer) => writer.write_all(&display.framebuffer),
+        };
+        if let Err(err) = result {
+            self.dumper.dump = None;
+            self.dumper.error = Some(err);
+        }
+    }
+}
+
+fn save(path: &Path, config: DisplayConfig, framebuffer: &[u8]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    encode(&mut file, config, framebuffer)?;
+    file.flush()
+}
@@ -0,0 +1,342 @@
+//! Rhai scripts driving the machine through hooks.
+//!
+//! [`Vm::load_script`] compiles a [Rhai](https://rhai.rs) script and runs
+//! its top level once, with the machine in a `vm` variable. There the
+//! script registers callbacks, which run as [hooks](crate::hooks) for as
+//! long as the script is loaded, so test and tool-assisted runs can change
+//! without recompiling anything:
+//!
+//! ```
+//! # use emulator::{ResetKind, Vm};
+//! let mut vm = Vm::new();
+//! // LDA #$42, over and over.
+//! vm.load(0x8000, &[0xA9, 0x42].repeat(0x100)).unwrap();
+//! vm.load(0xFFFC, &[0x00, 0x80]).unwrap();
+//! vm.reset(ResetKind::Soft);
+//! let script = vm
+//!     .load_script(
+//!         r#"
+//!         vm.on_pc(0x8004, |vm| {
+//!             vm.poke(0x0200, vm.a);
+//!             vm.pc = 0x8000;
+//!         });
+//!         "#,
+//!     )
+//!     .unwrap();
+//! vm.run_cycles(100).unwrap();
+//! assert_eq!(vm.read(0x0200), 0x42);
+//! assert_eq!(script.take_errors(), []);
+//! ```
+//!
+//! Through `vm` a script reads and sets the registers `a`, `x`, `y`,
+//! `flags`, `sp` and `pc` and the held `buttons`, and reads `cycles`,
+//! `frame` and `clock`. `vm.peek(addr)` and `vm.poke(addr, value)` read and
+//! write RAM, bypassing devices as [`Vm::read`] and [`Vm::write`] do. These
+//! callbacks can be registered, each returning an id for
+//! `vm.remove_hook(id)`:
+//!
+//! | Call                          | Runs                                      |
+//! | ----------------------------- | ----------------------------------------- |
+//! | `vm.on_pc(addr, f)`           | `f(vm)` before the instruction at `addr`  |
+//! | `vm.on_read(first, last, f)`  | `f(vm, addr, value)` after reads there    |
+//! | `vm.on_write(first, last, f)` | `f(vm, addr, value)` after writes there   |
+//! | `vm.on_access(first, last, f)`| `f(vm, addr, value)` after either         |
+//! | `vm.on_frame(f)`              | `f(vm)` at the end of every frame         |
+//!
+//! The access callbacks are [`Vm::on_access`] hooks: they observe what the
+//! program read or wrote once the instruction has finished. `vm` is only
+//! usable while the script's top level or one of its callbacks runs.
+//!
+//! An error in the top level or a failed compile is returned. Callbacks
+//! run in the middle of the machine's work, so an error there is kept for
+//! [`Script::take_errors`] and the machine carries on.
+
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+
+use rhai::{AST, Dynamic, Engine, EvalAltResult, FnPtr, FuncArgs, INT, Scope};
+
+use crate::debugger::WatchKind;
+use crate::hooks::{Access, HookId};
+use crate::vm::{Registers, Vm};
+
+type RhaiResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// Why a script failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptError {
+    /// The script does not compile.
+    Compile(String),
+    /// The script raised an error while running.
+    Run(String),
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Compile(err) => write!(f, "script does not compile: {err}"),
+            Self::Run(err) => write!(f, "script failed: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+/// A loaded script, whose callbacks stay registered until
+/// [`Script::unload`].
+pub struct Script {
+    runtime: Arc<Runtime>,
+}
+
+impl Script {
+    /// Errors the callbacks raised since the last call, oldest first.
+    pub fn take_errors(&self) -> Vec<ScriptError> {
+        std::mem::take(&mut *lock(&self.runtime.errors))
+    }
+
+    /// Removes every callback the script registered from `vm`.
+    pub fn unload(self, vm: &mut Vm) {
+        for id in std::mem::take(&mut *lock(&self.runtime.hooks)) {
+            vm.remove_hook(id);
+        }
+    }
+}
+
+impl fmt::Debug for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Script")
+            .field("hooks", &*lock(&self.runtime.hooks))
+            .finish_non_exhaustive()
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// What a script's callbacks share.
+struct Runtime {
+    engine: Engine,
+    ast: AST,
+    machine: Machine,
+    hooks: Mutex<Vec<HookId>>,
+    errors: Mutex<Vec<ScriptError>>,
+}
+
+impl Runtime {
+    /// Calls `f` with the machine reachable through `vm`, keeping any
+    /// error it raises.
+    fn call(&self, vm: &mut Vm, f: &FnPtr, args: impl FuncArgs) {
+        let _entered = self.machine.enter(vm);
+        if let Err(err) = f.call::<Dynamic>(&self.engine, &self.ast, args) {
+            lock(&self.errors).push(ScriptError::Run(err.to_string()));
+        }
+    }
+}
+
+/// The `vm` a script sees: the machine running it, while it runs.
+#[derive(Clone)]
+struct Machine {
+    /// The machine whose hook or load is running, or null.
+    vm: Arc<AtomicPtr<Vm>>,
+    runtime: Weak<Runtime>,
+}
+
+/// Makes the machine unreachable again when dropped.
+struct Entered<'a> {
+    vm: &'a AtomicPtr<Vm>,
+    outer: *mut Vm,
+}
+
+impl Drop for Entered<'_> {
+    fn drop(&mut self) {
+        self.vm.store(self.outer, Ordering::Relaxed);
+    }
+}
+
+impl Machine {
+    fn enter(&self, vm: &mut Vm) -> Entered<'_> {
+        let outer = self.vm.swap(vm, Ordering::Relaxed);
+        Entered {
+            vm: &self.vm,
+            outer,
+        }
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&mut Vm) -> T) -> RhaiResult<T> {
+        let vm = self.vm.load(Ordering::Relaxed);
+        if vm.is_null() {
+            return Err("the machine is only reachable while the script runs".into());
+        }
+        // SAFETY: the pointer is set only while `Runtime::call` or
+        // `Vm::load_script` holds the machine's `&mut Vm` and does nothing
+        // with it but run the script, on this thread.
+        Ok(f(unsafe { &mut *vm }))
+    }
+
+    fn registers(&self, f: impl FnOnce(&mut Registers)) -> RhaiResult<()> {
+        self.with(|vm| {
+            let mut registers = vm.registers();
+            f(&mut registers);
+            vm.set_registers(registers);
+        })
+    }
+
+    /// Registers the hook `register` makes with the runtime, for
+    /// [`Script::unload`].
+    fn hook(&self, register: impl FnOnce(&mut Vm, Arc<Runtime>) -> HookId) -> RhaiResult<HookId> {
+        let runtime = self.runtime.upgrade().ok_or("the script was unloaded")?;
+        let hooks = Arc::clone(&runtime);
+        let id = self.with(|vm| register(vm, runtime))?;
+        lock(&hooks.hooks).push(id);
+        Ok(id)
+    }
+
+    fn on_access(
+        &self,
+        range: RangeInclusive<u16>,
+        kind: WatchKind,
+        f: FnPtr,
+    ) -> RhaiResult<HookId> {
+        self.hook(|vm, runtime| {
+            vm.on_access(range, kind, move |vm, access: Access| {
+                let args = (
+                    runtime.machine.clone(),
+                    INT::from(access.addr),
+                    INT::from(access.value),
+                );
+                runtime.call(vm, &f, args);
+            })
+        })
+    }
+}
+
+fn byte(value: INT) -> RhaiResult<u8> {
+    u8::try_from(value).map_err(|_| format!("{value} is not a byte").into())
+}
+
+fn address(value: INT) -> RhaiResult<u16> {
+    u16::try_from(value).map_err(|_| format!("{value} is not an address").into())
+}
+
+fn range(first: INT, last: INT) -> RhaiResult<RangeInclusive<u16>> {
+    Ok(address(first)?..=address(last)?)
+}
+
+/// An engine knowing the `vm` type.
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .register_type_with_name::<Machine>("Machine")
+        .register_type_with_name::<HookId>("HookId");
+    macro_rules! registers {
+        ($($name:ident: $convert:ident),*) => {$(
+            engine.register_get_set(
+                stringify!($name),
+                |m: &mut Machine| m.with(|vm| INT::from(vm.registers().$name)),
+                |m: &mut Machine, value: INT| {
+                    let value = $convert(value)?;
+                    m.registers(|registers| registers.$name = value)
+                },
+            );
+        )*};
+    }
+    registers!(a: byte, x: byte, y: byte, flags: byte, sp: address, pc: address);
+    engine
+        .register_get_set(
+            "buttons",
+            |m: &mut Machine| m.with(|vm| INT::from(vm.buttons())),
+            |m: &mut Machine, value: INT| {
+                let mask = byte(value)?;
+                m.with(|vm| vm.set_buttons(mask))
+            },
+        )
+        .register_get("cycles", |m: &mut Machine| {
+            m.with(|vm| INT::from(vm.cycles()))
+        })
+        .register_get("frame", |m: &mut Machine| m.with(|vm| vm.frame() as INT))
+        .register_get("clock", |m: &mut Machine| m.with(|vm| vm.clock() as INT))
+        .register_fn("peek", |m: &mut Machine, addr: INT| {
+            let addr = address(addr)?;
+            m.with(|vm| INT::from(vm.read(addr)))
+        })
+        .register_fn("poke", |m: &mut Machine, addr: INT, value: INT| {
+            let (addr, value) = (address(addr)?, byte(value)?);
+            m.with(|vm| vm.write(addr, value))
+        })
+        .register_fn("on_pc", |m: &mut Machine, addr: INT, f: FnPtr| {
+            let addr = address(addr)?;
+            m.hook(|vm, runtime| {
+                vm.on_pc(addr, move |vm| {
+                    runtime.call(vm, &f, (runtime.machine.clone(),));
+                })
+            })
+        })
+        .register_fn(
+            "on_read",
+            |m: &mut Machine, first: INT, last: INT, f: FnPtr| {
+                m.on_access(range(first, last)?, WatchKind::Read, f)
+            },
+        )
+        .register_fn(
+            "on_write",
+            |m: &mut Machine, first: INT, last: INT, f: FnPtr| {
+                m.on_access(range(first, last)?, WatchKind::Write, f)
+            },
+        )
+        .register_fn(
+            "on_access",
+            |m: &mut Machine, first: INT, last: INT, f: FnPtr| {
+                m.on_access(range(first, last)?, WatchKind::Access, f)
+            },
+        )
+        .register_fn("on_frame", |m: &mut Machine, f: FnPtr| {
+            m.hook(|vm, runtime| {
+                vm.on_frame(move |vm| {
+                    runtime.call(vm, &f, (runtime.machine.clone(),));
+                })
+            })
+        })
+        .register_fn("remove_hook", |m: &mut Machine, id: HookId| {
+            m.with(|vm| vm.remove_hook(id))
+        });
+    engine
+}
+
+impl Vm {
+    /// Compiles `source` and runs its top level, which registers the
+    /// script's callbacks; see the [module docs](crate::script).
+    pub fn load_script(&mut self, source: &str) -> Result<Script, ScriptError> {
+        let engine = engine();
+        let ast = engine
+            .compile(source)
+            .map_err(|err| ScriptError::Compile(err.to_string()))?;
+        let runtime = Arc::new_cyclic(|runtime| Runtime {
+            engine,
+            ast,
+            machine: Machine {
+                vm: Arc::new(AtomicPtr::new(ptr::null_mut())),
+                runtime: runtime.clone(),
+            },
+            hooks: Mutex::default(),
+            errors: Mutex::default(),
+        });
+        let mut scope = Scope::new();
+        scope.push_constant("vm", runtime.machine.clone());
+        let result = {
+            let _entered = runtime.machine.enter(self);
+            runtime.engine.run_ast_with_scope(&mut scope, &runtime.ast)
+        };
+        let script = Script { runtime };
+        match result {
+            Ok(()) => Ok(script),
+            Err(err) => {
+                script.unload(self);
+                Err(ScriptError::Run(err.to_string()))
+            }
+        }
+    }
+}
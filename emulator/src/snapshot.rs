@@ -0,0 +1,615 @@
+//! Save states.
+//!
+//! A [`Snapshot`] is a plain copy of everything that determines how the
+//! machine continues: registers, the cycle and frame counters, the interrupt
+//! controller and what the CPU's interrupt poll latched, and the full
+//! address space, along with the state of every device mapped on the
+//! [`Bus`](crate::Bus) that [saves one](crate::BusDevice::save_state) and
+//! the host's pending [scheduled events](crate::scheduler). Debugger state
+//! such as breakpoints is not part of it, and neither are the handlers the
+//! events run. With the `serde`
+//! feature snapshots can be serialized with any serde format.
+//!
+//! [`Snapshot::diff`] lists what changed between two snapshots, such as the
+//! states before and after a frame. For a snapshot every frame, a
+//! [`PagedSnapshot`](crate::paged::PagedSnapshot) holds the same state but
+//! copies only the memory pages written since the last one.
+//!
+//! [`Snapshot::to_bytes`] writes the crate's own save-state format, which
+//! [`Snapshot::from_bytes`] reads back, all little-endian:
+//!
+//! | Size  | Contents                                   |
+//! | ----- | ------------------------------------------ |
+//! | 8     | magic `RVM8SAV` and format version         |
+//! | 8     | registers: A, X, Y, P, PC, SP              |
+//! | 12    | cycle counter (4) and frame counter (8)    |
+//! | 2     | interrupt lines raised and mask            |
+//! | 2     | NMI input (0 or 1) and CPU poll state      |
+//! | 65536 | memory                                     |
+//! | 2     | number of device states                    |
+//! | n     | each device state: start address (2), length (4), bytes |
+//! | 2     | number of scheduled events                 |
+//! | 12n   | each event: cycles until due (8, signed) and kind (4) |
+//!
+//! The version is bumped whenever the layout changes, and `from_bytes`
+//! upgrades every earlier version it knows to the current one, so states
+//! saved by an older crate keep loading. Versions newer than [`VERSION`] are
+//! rejected with [`SnapshotError::UnsupportedVersion`] rather than guessed
+//! at.
+
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::error::VmError;
+use crate::ffi::{INT_IRQ_MASKED, RVM_MEM_SIZE};
+use crate::irq::IrqState;
+use crate::scheduler::ScheduledEvent;
+use crate::vm::{Registers, Vm};
+
+/// Save-state magic, the format version byte excluded.
+pub const MAGIC: [u8; 7] = *b"RVM8SAV";
+/// Format version written by this crate, and the newest it reads.
+pub const VERSION: u8 = 4;
+
+/// Size of the state that follows the header.
+pub(crate) const STATE_SIZE: usize = 8 + 12 + 4 + RVM_MEM_SIZE;
+/// Size of a version 1 state, which lacked the NMI and poll state.
+const STATE_SIZE_V1: usize = STATE_SIZE - 2;
+
+/// A captured machine state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Snapshot {
+    pub registers: Registers,
+    pub cycles: u32,
+    /// Frames completed, see [`Vm::frame`].
+    pub frame: u64,
+    pub irq: IrqState,
+    /// The whole address space, `RVM_MEM_SIZE` bytes.
+    pub memory: Vec<u8>,
+    /// What the devices that save state saved, in the order they were
+    /// mapped.
+    pub devices: Vec<DeviceState>,
+    /// The host's pending scheduled events, in the order they run.
+    pub events: Vec<ScheduledEvent>,
+}
+
+/// What a [`BusDevice::save_state`](crate::BusDevice::save_state) saved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceState {
+    /// The start of the device's range, which restoring hands the state to.
+    pub start: u16,
+    pub state: Vec<u8>,
+}
+
+/// Why [`Snapshot::from_bytes`] rejected a save state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// The data does not start with [`MAGIC`].
+    BadMagic,
+    /// The state was written in a format version newer than
+    /// [`VERSION`], or one that never existed.
+    UnsupportedVersion(u8),
+    /// The data is `actual` bytes long, but its version's layout takes
+    /// `expected`.
+    BadLength { expected: usize, actual: usize },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "not an rvm-8 save state"),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported save state version {v}"),
+            Self::BadLength { expected, actual } => {
+                write!(f, "save state is {actual} bytes, expected {expected}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+/// A register in a [`StateDiff`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Register {
+    A,
+    X,
+    Y,
+    Pc,
+    Sp,
+    Flags,
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::A => "A",
+            Self::X => "X",
+            Self::Y => "Y",
+            Self::Pc => "PC",
+            Self::Sp => "SP",
+            Self::Flags => "P",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A register that holds a different value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RegisterChange {
+    pub register: Register,
+    pub old: u16,
+    pub new: u16,
+}
+
+/// A run of consecutive changed bytes, each different in `old` and `new`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemoryChange {
+    pub addr: u16,
+    pub old: Vec<u8>,
+    pub new: Vec<u8>,
+}
+
+impl MemoryChange {
+    /// The addresses the run covers.
+    pub fn range(&self) -> RangeInclusive<u16> {
+        self.addr..=self.addr + (self.new.len() - 1) as u16
+    }
+}
+
+/// What changed from one [`Snapshot`] to another, as [`Snapshot::diff`]
+/// finds it.
+///
+/// Displays one change per line:
+///
+/// ```text
+/// A: $01 -> $02
+/// PC: $8000 -> $8002
+/// cycles: 0 -> 2
+/// $0010-$0011: 00 00 -> 01 02
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StateDiff {
+    /// Changed registers, in [`Register`] order.
+    pub registers: Vec<RegisterChange>,
+    /// Old and new cycle counts, if they differ.
+    pub cycles: Option<(u32, u32)>,
+    /// Old and new frame counts, if they differ.
+    pub frame: Option<(u64, u64)>,
+    /// Old and new interrupt controller state, if it differs.
+    pub irq: Option<(IrqState, IrqState)>,
+    /// Changed memory in ascending address order.
+    pub memory: Vec<MemoryChange>,
+    /// Starts of the devices whose saved state differs or was saved in one
+    /// snapshot only, in ascending order.
+    pub devices: Vec<u16>,
+    /// Old and new pending scheduled events, if they differ.
+    pub events: Option<(Vec<ScheduledEvent>, Vec<ScheduledEvent>)>,
+}
+
+impl StateDiff {
+    /// Whether the snapshots were identical.
+    pub fn is_empty(&self) -> bool {
+        self.registers.is_empty()
+            && self.cycles.is_none()
+            && self.frame.is_none()
+            && self.irq.is_none()
+            && self.memory.is_empty()
+            && self.devices.is_empty()
+            && self.events.is_none()
+    }
+
+    /// The change to `register`, if it changed.
+    pub fn register(&self, register: Register) -> Option<RegisterChange> {
+        self.registers
+            .iter()
+            .copied()
+            .find(|c| c.register == register)
+    }
+
+    /// Whether the byte at `addr` changed.
+    pub fn changed(&self, addr: u16) -> bool {
+        self.memory.iter().any(|c| c.range().contains(&addr))
+    }
+
+    /// Number of changed bytes.
+    pub fn changed_bytes(&self) -> usize {
+        self.memory.iter().map(|c| c.new.len()).sum()
+    }
+}
+
+impl fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in &self.registers {
+            if matches!(change.register, Register::Pc | Register::Sp) {
+                writeln!(
+                    f,
+                    "{}: ${:04X} -> ${:04X}",
+                    change.register, change.old, change.new
+                )?;
+            } else {
+                writeln!(
+                    f,
+                    "{}: ${:02X} -> ${:02X}",
+                    change.register, change.old, change.new
+                )?;
+            }
+        }
+        if let Some((old, new)) = self.cycles {
+            writeln!(f, "cycles: {old} -> {new}")?;
+        }
+        if let Some((old, new)) = self.frame {
+            writeln!(f, "frame: {old} -> {new}")?;
+        }
+        if let Some((old, new)) = self.irq {
+            writeln!(
+                f,
+                "irq: raised {:08b} mask {:08b} nmi {} poll {:03b} -> raised {:08b} mask {:08b} nmi {} poll {:03b}",
+                old.raised,
+                old.mask,
+                u8::from(old.nmi),
+                old.poll,
+                new.raised,
+                new.mask,
+                u8::from(new.nmi),
+                new.poll
+            )?;
+        }
+        let hex = |bytes: &[u8]| {
+            let hex: Vec<_> = bytes.iter().map(|b| format!("{b:02X}")).collect();
+            hex.join(" ")
+        };
+        for change in &self.memory {
+            let range = change.range();
+            if range.start() == range.end() {
+                write!(f, "${:04X}", range.start())?;
+            } else {
+                write!(f, "${:04X}-${:04X}", range.start(), range.end())?;
+            }
+            writeln!(f, ": {} -> {}", hex(&change.old), hex(&change.new))?;
+        }
+        for start in &self.devices {
+            writeln!(f, "device ${start:04X}: state changed")?;
+        }
+        if let Some((old, new)) = &self.events {
+            writeln!(f, "events: {} -> {} pending", old.len(), new.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// `Some((old, new))` if they differ.
+fn changed<T: PartialEq>(old: T, new: T) -> Option<(T, T)> {
+    (old != new).then_some((old, new))
+}
+
+impl Snapshot {
+    /// What changed going from `self` to `other`: `old` values come from
+    /// `self` and `new` ones from `other`. Memory is compared up to the
+    /// shorter of the two, which is all of it for snapshots from
+    /// [`Vm::save_state`].
+    pub fn diff(&self, other: &Snapshot) -> StateDiff {
+        let (old, new) = (self.registers, other.registers);
+        let registers = [
+            (Register::A, u16::from(old.a), u16::from(new.a)),
+            (Register::X, u16::from(old.x), u16::from(new.x)),
+            (Register::Y, u16::from(old.y), u16::from(new.y)),
+            (Register::Pc, old.pc, new.pc),
+            (Register::Sp, old.sp, new.sp),
+            (Register::Flags, u16::from(old.flags), u16::from(new.flags)),
+        ]
+        .into_iter()
+        .filter(|&(_, old, new)| old != new)
+        .map(|(register, old, new)| RegisterChange { register, old, new })
+        .collect();
+
+        let mut memory: Vec<MemoryChange> = Vec::new();
+        let bytes = self.memory.iter().zip(&other.memory).take(RVM_MEM_SIZE);
+        for (addr, (&old, &new)) in bytes.enumerate() {
+            if old == new {
+                continue;
+            }
+            match memory.last_mut() {
+                Some(run) if run.addr as usize + run.new.len() == addr => {
+                    run.old.push(old);
+                    run.new.push(new);
+                }
+                _ => memory.push(MemoryChange {
+                    addr: addr as u16,
+                    old: vec![old],
+                    new: vec![new],
+                }),
+            }
+        }
+
+        let state = |snapshot: &Snapshot, start: u16| {
+            let saved = snapshot.devices.iter().find(|saved| saved.start == start);
+            saved.map(|saved| saved.state.clone())
+        };
+        let mut devices: Vec<u16> = self
+            .devices
+            .iter()
+            .chain(&other.devices)
+            .map(|saved| saved.start)
+            .filter(|&start| state(self, start) != state(other, start))
+            .collect();
+        devices.sort_unstable();
+        devices.dedup();
+
+        StateDiff {
+            registers,
+            cycles: changed(self.cycles, other.cycles),
+            frame: changed(self.frame, other.frame),
+            irq: changed(self.irq, other.irq),
+            memory,
+            devices,
+            events: changed(&self.events, &other.events)
+                .map(|(old, new)| (old.clone(), new.clone())),
+        }
+    }
+}
+
+impl Snapshot {
+    /// Serializes the snapshot in the current save-state format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(8 + STATE_SIZE + 4);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(VERSION);
+        self.encode(&mut bytes);
+        bytes.extend_from_slice(&(self.devices.len() as u16).to_le_bytes());
+        for saved in &self.devices {
+            bytes.extend_from_slice(&saved.start.to_le_bytes());
+            bytes.extend_from_slice(&(saved.state.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&saved.state);
+        }
+        bytes.extend_from_slice(&(self.events.len() as u16).to_le_bytes());
+        for event in &self.events {
+            bytes.extend_from_slice(&event.delay.to_le_bytes());
+            bytes.extend_from_slice(&event.kind.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Parses a save state written by [`Snapshot::to_bytes`] in this or any
+    /// earlier version of the format.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let Some((header, state)) = bytes.split_first_chunk::<8>() else {
+            return Err(if MAGIC.starts_with(bytes) {
+                SnapshotError::BadLength {
+                    expected: 8 + STATE_SIZE + 4,
+                    actual: bytes.len(),
+                }
+            } else {
+                SnapshotError::BadMagic
+            });
+        };
+        if header[..7] != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        // One arm per version. When the layout changes, bump VERSION, add
+        // its arm, and turn the previous one into an upgrade that decodes
+        // the old layout and fills in what it lacked.
+        let expected = match header[7] {
+            1 => 8 + STATE_SIZE_V1,
+            2 => 8 + STATE_SIZE,
+            3 | 4 => return Self::decode_v3(bytes),
+            version => return Err(SnapshotError::UnsupportedVersion(version)),
+        };
+        if bytes.len() != expected {
+            return Err(SnapshotError::BadLength {
+                expected,
+                actual: bytes.len(),
+            });
+        }
+        if header[7] == 1 {
+            // Nothing was asserted or polled.
+            let mut upgraded = state[..22].to_vec();
+            upgraded.extend_from_slice(&[0, 0]);
+            upgraded.extend_from_slice(&state[22..]);
+            return Ok(Self::decode(&upgraded));
+        }
+        // No device saved state.
+        Ok(Self::decode(state))
+    }
+
+    /// Parses a version 3 or 4 state, whose device states and, from
+    /// version 4, scheduled events make its length vary.
+    fn decode_v3(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let mut at = 8;
+        let mut take = |len: usize| {
+            let taken = bytes.get(at..at + len).ok_or(SnapshotError::BadLength {
+                expected: at + len,
+                actual: bytes.len(),
+            })?;
+            at += len;
+            Ok(taken)
+        };
+        let state = take(STATE_SIZE)?;
+        let count = u16::from_le_bytes(take(2)?.try_into().expect("2 bytes"));
+        let mut devices = Vec::with_capacity(count.into());
+        for _ in 0..count {
+            let start = u16::from_le_bytes(take(2)?.try_into().expect("2 bytes"));
+            let len = u32::from_le_bytes(take(4)?.try_into().expect("4 bytes"));
+            let state = take(len as usize)?.to_vec();
+            devices.push(DeviceState { start, state });
+        }
+        let mut events = Vec::new();
+        // Version 3 had no events.
+        if bytes[7] >= 4 {
+            let count = u16::from_le_bytes(take(2)?.try_into().expect("2 bytes"));
+            for _ in 0..count {
+                let delay = i64::from_le_bytes(take(8)?.try_into().expect("8 bytes"));
+                let kind = u32::from_le_bytes(take(4)?.try_into().expect("4 bytes"));
+                events.push(ScheduledEvent { delay, kind });
+            }
+        }
+        if at != bytes.len() {
+            return Err(SnapshotError::BadLength {
+                expected: at,
+                actual: bytes.len(),
+            });
+        }
+        Ok(Self {
+            devices,
+            events,
+            ..Self::decode(state)
+        })
+    }
+
+    /// Appends the state in the current layout, without a header or the
+    /// device states. Input recordings embed the same layout for their start
+    /// state.
+    pub(crate) fn encode(&self, bytes: &mut Vec<u8>) {
+        let Registers {
+            a,
+            x,
+            y,
+            pc,
+            sp,
+            flags,
+        } = self.registers;
+        bytes.extend_from_slice(&[a, x, y, flags]);
+        bytes.extend_from_slice(&pc.to_le_bytes());
+        bytes.extend_from_slice(&sp.to_le_bytes());
+        bytes.extend_from_slice(&self.cycles.to_le_bytes());
+        bytes.extend_from_slice(&self.frame.to_le_bytes());
+        let irq = self.irq;
+        bytes.extend_from_slice(&[irq.raised, irq.mask, irq.nmi.into(), irq.poll]);
+        bytes.extend_from_slice(&self.memory);
+    }
+
+    /// Decodes the first [`STATE_SIZE`] bytes of `state`, as written by
+    /// [`Snapshot::encode`].
+    pub(crate) fn decode(state: &[u8]) -> Self {
+        let word = |i: usize| u16::from_le_bytes([state[i], state[i + 1]]);
+        Self {
+            registers: Registers {
+                a: state[0],
+                x: state[1],
+                y: state[2],
+                flags: state[3],
+                pc: word(4),
+                sp: word(6),
+            },
+            cycles: u32::from_le_bytes(state[8..12].try_into().expect("4 bytes")),
+            frame: u64::from_le_bytes(state[12..20].try_into().expect("8 bytes")),
+            irq: IrqState {
+                raised: state[20],
+                mask: state[21],
+                nmi: state[22] != 0,
+                poll: state[23],
+            },
+            memory: state[24..STATE_SIZE].to_vec(),
+            devices: Vec::new(),
+            events: Vec::new(),
+        }
+    }
+}
+
+impl Vm {
+    /// Captures the current machine state.
+    pub fn save_state(&self) -> Snapshot {
+        Snapshot {
+            registers: self.registers(),
+            cycles: self.cycles(),
+            frame: self.frame(),
+            irq: self.saved_irq(),
+            memory: self.memory().to_vec(),
+            devices: self.bus().save_devices(),
+            events: self.save_events(),
+        }
+    }
+
+    /// The interrupt state as a snapshot records it.
+    pub(crate) fn saved_irq(&self) -> IrqState {
+        IrqState {
+            // Only the step that made the poll reads the mask it saw.
+            poll: self.cpu.int_state & !INT_IRQ_MASKED,
+            ..self.irq
+        }
+    }
+
+    /// Restores a state captured by [`Vm::save_state`].
+    ///
+    /// The snapshot is validated before anything is modified, so a rejected
+    /// snapshot leaves the machine untouched: its memory must be whole,
+    /// every device state must have a device starting where it was saved
+    /// from, and its frame count must fit in the cycle clock at the
+    /// current [clock speed](Vm::cycles_per_frame). Devices that saved nothing keep their state. Rewind history
+    /// belongs to the timeline being left and is cleared.
+    pub fn load_state(&mut self, snapshot: &Snapshot) -> Result<(), VmError> {
+        if snapshot.memory.len() != RVM_MEM_SIZE {
+            return Err(VmError::InvalidSnapshot("memory size mismatch"));
+        }
+        if !snapshot
+            .devices
+            .iter()
+            .all(|saved| self.bus().maps_device_at(saved.start))
+        {
+            return Err(VmError::InvalidSnapshot("no device for a device state"));
+        }
+        if self.frame_clock(snapshot.frame, snapshot.cycles).is_none() {
+            return Err(VmError::InvalidSnapshot("frame count overflows the clock"));
+        }
+        self.restore(snapshot);
+        if let Some(rewind) = &mut self.rewind {
+            rewind.clear();
+        }
+        Ok(())
+    }
+
+    /// Applies an already validated snapshot.
+    pub(crate) fn restore(&mut self, snapshot: &Snapshot) {
+        self.abandon_frame();
+        self.memory_mut().copy_from_slice(&snapshot.memory);
+        self.bus_mut().load_devices(&snapshot.devices);
+        self.restore_machine(
+            snapshot.registers,
+            snapshot.cycles,
+            snapshot.frame,
+            snapshot.irq,
+        );
+        self.restore_events(&snapshot.events);
+    }
+
+    /// Applies everything but the memory of a snapshot, once the memory is
+    /// in place.
+    pub(crate) fn restore_machine(
+        &mut self,
+        registers: Registers,
+        cycles: u32,
+        frame: u64,
+        irq: IrqState,
+    ) {
+        self.set_registers(registers);
+        self.cpu.cycles = cycles;
+        self.frame = frame;
+        // Paged snapshots are not validated; if the frame cannot be counted
+        // in cycles, start it afresh at `cycles` instead.
+        let (frame_cycle, clock) = self
+            .frame_clock(frame, cycles)
+            .unwrap_or((u64::from(cycles), u64::from(cycles)));
+        self.frame_cycle = frame_cycle;
+        self.scheduler.rebase(clock, cycles);
+        self.reschedule_machine();
+        self.irq = IrqState { poll: 0, ..irq };
+        self.cpu.int_state = irq.poll;
+        self.render_display();
+    }
+
+    /// The cycle `frame` starts on and the clock `cycles` falls on within
+    /// it, or `None` if either, or the cycle the frame ends on, overflows.
+    /// Snapshots do not record clock changes, so the current rate is
+    /// assumed to have held throughout.
+    fn frame_clock(&self, frame: u64, cycles: u32) -> Option<(u64, u64)> {
+        let per_frame = u64::from(self.cycles_per_frame());
+        let frame_cycle = frame.checked_add(1)?.checked_mul(per_frame)? - per_frame;
+        let clock = frame_cycle.checked_add(u64::from(cycles.wrapping_sub(frame_cycle as u32)))?;
+        Some((frame_cycle, clock))
+    }
+}
@@ -0,0 +1,88 @@
+//! Passive bus snooping.
+//!
+//! A decoder for a memory-mapped protocol, say one that reassembles the
+//! packets a program writes to a port, needs every access in order but must
+//! not change any of them, and hooks sit in the path of the accesses they
+//! see. While snooping is on, the [`Bus`](crate::Bus) quietly keeps a
+//! [`BusEvent`] for each access the CPU makes, and [`Vm::bus_events`] hands
+//! over those since the last call:
+//!
+//! ```
+//! # use emulator::{ResetKind, Vm};
+//! let mut vm = Vm::new();
+//! vm.load(0x8000, &[0xAD, 0x00, 0x02]).unwrap(); // LDA $0200
+//! vm.load(0xFFFC, &[0x00, 0x80]).unwrap();
+//! vm.reset(ResetKind::Soft);
+//! vm.set_bus_snooping(true);
+//! vm.step().unwrap();
+//! let reads: Vec<u16> = vm.bus_events().map(|event| event.addr).collect();
+//! assert_eq!(reads, [0x8000, 0x8001, 0x8002, 0x0200]);
+//! ```
+//!
+//! Only the CPU's accesses are seen, with the values they ended up with
+//! after hooks and devices: device DMA and the host's [`Vm::read`] and
+//! [`Vm::write`] are not. Events pile up until taken, so a long run should
+//! take them every frame or so. Like a heatmap, snooping needs every access
+//! to reach the host, so the machine runs instruction by instruction.
+
+use crate::bus::WatchHit;
+use crate::ffi::BusAccess;
+use crate::vm::Vm;
+
+/// One CPU access to the bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusEvent {
+    pub addr: u16,
+    pub value: u8,
+    pub kind: BusAccess,
+    /// The cycle counter when the instruction making the access started,
+    /// plus, in [cycle-accurate](crate::TimingMode::CycleAccurate) mode, the
+    /// accesses it made before this one.
+    pub cycle: u32,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Snoop {
+    events: Vec<BusEvent>,
+    /// Cycle counter at the start of the current instruction.
+    pub(crate) start: u32,
+}
+
+impl Snoop {
+    /// Keeps `hit`, the access made after `ticks` accesses of the current
+    /// instruction ticked the clock.
+    pub(crate) fn record(&mut self, hit: WatchHit, ticks: u32) {
+        self.events.push(BusEvent {
+            addr: hit.addr,
+            value: hit.val,
+            kind: hit.kind,
+            cycle: self.start.wrapping_add(ticks),
+        });
+    }
+}
+
+impl Vm {
+    /// Starts or stops keeping every bus access for [`Vm::bus_events`].
+    /// Stopping drops the ones not yet taken.
+    pub fn set_bus_snooping(&mut self, on: bool) {
+        let bus = self.bus_mut();
+        if on == bus.snoop.is_some() {
+            return;
+        }
+        bus.snoop = on.then(Snoop::default);
+        bus.pages_dirty = true;
+    }
+
+    pub fn bus_snooping(&self) -> bool {
+        self.bus().snoop.is_some()
+    }
+
+    /// Takes the accesses made since the last call, oldest first; none
+    /// while snooping is off.
+    pub fn bus_events(&mut self) -> impl Iterator<Item = BusEvent> + '_ {
+        self.bus_mut()
+            .snoop
+            .iter_mut()
+            .flat_map(|snoop| snoop.events.drain(..))
+    }
+}
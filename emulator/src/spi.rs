@@ -0,0 +1,436 @@
+//! SPI controller.
+//!
+//! An [`Spi`] is a bus master for up to [`SPI_SLOTS`] slave devices, such
+//! as the bundled [`SpiEeprom`] or a mock sensor, each implementing
+//! [`SpiDevice`]. Firmware selects slaves through chip-select lines and
+//! swaps a byte with them per transfer, as on real hardware. It is not
+//! mapped by default:
+//!
+//! ```
+//! # use emulator::{spi::{Spi, SpiEeprom, SPI_PORTS}, Vm};
+//! let mut spi = Spi::new();
+//! spi.attach(0, SpiEeprom::new(0x2000));
+//! let mut vm = Vm::new();
+//! vm.bus_mut().map(SPI_PORTS, spi).unwrap();
+//! ```
+//!
+//! | Offset | Register                                                      |
+//! | ------ | ------------------------------------------------------------- |
+//! | 0      | `DATA`: writing sends a byte to the selected slaves; reading gives the byte received during the last transfer |
+//! | 1      | `SELECT`: chip-select lines, bit `n` selecting slot `n`       |
+//!
+//! Transfers finish at once. A slave is told when its chip-select line goes
+//! active and inactive, which frames its commands. Every selected slave
+//! receives the byte sent; their replies are ORed, and with none selected
+//! the controller receives `$FF`, as from a pulled-up MISO line. Slots
+//! with nothing attached reply `$00`.
+
+use std::any::Any;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::bus::BusDevice;
+use crate::reset::ResetKind;
+
+/// Suggested place for the SPI registers.
+pub const SPI_PORTS: RangeInclusive<u16> = 0x2770..=0x2771;
+/// Chip-select lines, and so slaves.
+pub const SPI_SLOTS: usize = 8;
+
+const DATA: u16 = 0;
+const SELECT: u16 = 1;
+
+/// A slave on an [`Spi`] controller.
+pub trait SpiDevice: Any + Send {
+    /// The chip-select line went active.
+    fn select(&mut self) {}
+
+    /// Receives `mosi` and returns the byte shifted out at the same time.
+    fn transfer(&mut self, mosi: u8) -> u8;
+
+    /// The chip-select line went inactive.
+    fn deselect(&mut self) {}
+
+    /// Appends whatever [`SpiDevice::load_state`] needs to pick up where
+    /// the slave is now; the controller saves it with its own state. The
+    /// default saves nothing, which leaves the slave out of snapshots.
+    fn save_state(&self, state: &mut Vec<u8>) {
+        let _ = state;
+    }
+
+    /// Returns the slave to a non-empty `state` that
+    /// [`SpiDevice::save_state`] wrote, without panicking on state it
+    /// cannot make sense of. The default does nothing.
+    fn load_state(&mut self, state: &[u8]) {
+        let _ = state;
+    }
+}
+
+/// The SPI controller device.
+#[derive(Default)]
+pub struct Spi {
+    slots: [Option<Box<dyn SpiDevice>>; SPI_SLOTS],
+    select: u8,
+    received: u8,
+}
+
+impl Spi {
+    /// A controller with nothing attached.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attaches `device` to `slot`, handing back what was there.
+    ///
+    /// # Panics
+    ///
+    /// If `slot` is not below [`SPI_SLOTS`].
+    pub fn attach(&mut self, slot: usize, device: impl SpiDevice) -> Option<Box<dyn SpiDevice>> {
+        self.slots[slot].replace(Box::new(device))
+    }
+
+    /// Detaches and returns the device in `slot`.
+    pub fn detach(&mut self, slot: usize) -> Option<Box<dyn SpiDevice>> {
+        self.slots.get_mut(slot)?.take()
+    }
+
+    /// The device in `slot`, if it is a `T`.
+    pub fn slave<T: SpiDevice>(&self, slot: usize) -> Option<&T> {
+        let device = self.slots.get(slot)?.as_deref()?;
+        (device as &dyn Any).downcast_ref()
+    }
+
+    /// The device in `slot`, mutably, if it is a `T`.
+    pub fn slave_mut<T: SpiDevice>(&mut self, slot: usize) -> Option<&mut T> {
+        let device = self.slots.get_mut(slot)?.as_deref_mut()?;
+        (device as &mut dyn Any).downcast_mut()
+    }
+
+    fn set_select(&mut self, select: u8) {
+        let changed = self.select ^ select;
+        for (slot, device) in self.slots.iter_mut().enumerate() {
+            let Some(device) = device else { continue };
+            if changed & (1 << slot) == 0 {
+                continue;
+            }
+            if select & (1 << slot) != 0 {
+                device.select();
+            } else {
+                device.deselect();
+            }
+        }
+        self.select = select;
+    }
+
+    fn transfer(&mut self, mosi: u8) {
+        if self.select == 0 {
+            self.received = 0xFF;
+            return;
+        }
+        self.received = self
+            .slots
+            .iter_mut()
+            .enumerate()
+            .filter(|&(slot, _)| self.select & (1 << slot) != 0)
+            .filter_map(|(_, device)| device.as_mut())
+            .fold(0, |miso, device| miso | device.transfer(mosi));
+    }
+}
+
+impl fmt::Debug for Spi {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let attached: Vec<usize> = (0..SPI_SLOTS)
+            .filter(|&slot| self.slots[slot].is_some())
+            .collect();
+        f.debug_struct("Spi")
+            .field("attached", &attached)
+            .field("select", &self.select)
+            .field("received", &self.received)
+            .finish()
+    }
+}
+
+impl BusDevice for Spi {
+    fn read8(&mut self, offset: u16) -> u8 {
+        match offset {
+            DATA => self.received,
+            SELECT => self.select,
+            _ => 0,
+        }
+    }
+
+    fn write8(&mut self, offset: u16, val: u8) {
+        match offset {
+            DATA => self.transfer(val),
+            SELECT => self.set_select(val),
+            _ => {}
+        }
+    }
+
+    /// Unbounded: transfers only happen when the CPU asks for them.
+    fn batch_cycles(&self) -> u32 {
+        u32::MAX
+    }
+
+    /// Deselects every slot and clears the byte received. The devices
+    /// attached keep their contents.
+    fn reset(&mut self, _: ResetKind) {
+        self.set_select(0);
+        self.received = 0;
+    }
+
+    /// The registers, then each slot's number and the length and bytes of
+    /// what its slave saved. The slaves themselves are the host's.
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&[self.select, self.received]);
+        for (slot, device) in self.slots.iter().enumerate() {
+            let Some(device) = device else { continue };
+            let mut saved = Vec::new();
+            device.save_state(&mut saved);
+            if saved.is_empty() {
+                continue;
+            }
+            state.push(slot as u8);
+            state.extend_from_slice(&(saved.len() as u32).to_le_bytes());
+            state.extend_from_slice(&saved);
+        }
+    }
+
+    /// Restores the chip-select lines without telling the slaves, whose
+    /// own states say whether they were selected.
+    fn load_state(&mut self, state: &[u8]) {
+        let Some((&[select, received], mut rest)) = state.split_first_chunk() else {
+            return;
+        };
+        let mut slaves = Vec::new();
+        while let Some((&slot, tail)) = rest.split_first() {
+            let Some((len, tail)) = tail.split_first_chunk() else {
+                return;
+            };
+            let Some((saved, tail)) = tail.split_at_checked(u32::from_le_bytes(*len) as usize)
+            else {
+                return;
+            };
+            if usize::from(slot) >= SPI_SLOTS {
+                return;
+            }
+            slaves.push((usize::from(slot), saved));
+            rest = tail;
+        }
+        self.select = select;
+        self.received = received;
+        for (slot, saved) in slaves {
+            if let Some(device) = &mut self.slots[slot] {
+                device.load_state(saved);
+            }
+        }
+    }
+}
+
+/// `READ hi lo`: bytes from the address on, for as long as selected.
+pub const EEPROM_READ: u8 = 0x03;
+/// `WRITE hi lo`: stores the bytes that follow from the address on.
+pub const EEPROM_WRITE: u8 = 0x02;
+/// Sets the write-enable latch.
+pub const EEPROM_WREN: u8 = 0x06;
+/// Clears the write-enable latch.
+pub const EEPROM_WRDI: u8 = 0x04;
+/// Reads the status register: bit 1 is the write-enable latch.
+pub const EEPROM_RDSR: u8 = 0x05;
+
+const STATUS_WEL: u8 = 1 << 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EepromState {
+    Command,
+    Address { command: u8, high: Option<u8> },
+    Read(usize),
+    Write(usize),
+    Status,
+    Done,
+}
+
+/// A 25xx-style serial EEPROM with 16-bit addresses.
+///
+/// Each command is framed by a selection. A `WRITE` only takes effect after
+/// a `WREN` in an earlier selection, and deselecting at the end of it
+/// clears the latch again. Addresses wrap at the end of the memory, and
+/// writes finish at once, so the status register never shows one in
+/// progress.
+#[derive(Debug, Clone)]
+pub struct SpiEeprom {
+    data: Vec<u8>,
+    write_enabled: bool,
+    wrote: bool,
+    state: EepromState,
+}
+
+impl SpiEeprom {
+    /// An erased EEPROM of `size` bytes, every byte `$FF`.
+    ///
+    /// # Panics
+    ///
+    /// If `size` is 0 or more than 64 KiB.
+    pub fn new(size: usize) -> Self {
+        Self::with_contents(vec![0xFF; size])
+    }
+
+    /// An EEPROM holding `data`.
+    ///
+    /// # Panics
+    ///
+    /// If `data` is empty or longer than 64 KiB.
+    pub fn with_contents(data: Vec<u8>) -> Self {
+        assert!(
+            (1..=0x10000).contains(&data.len()),
+            "EEPROM size must be 1 to 65536 bytes"
+        );
+        Self {
+            data,
+            write_enabled: false,
+            wrote: false,
+            state: EepromState::Done,
+        }
+    }
+
+    /// The stored bytes.
+    pub fn contents(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The stored bytes, to change from the host.
+    pub fn contents_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
+
+impl SpiDevice for SpiEeprom {
+    fn select(&mut self) {
+        self.state = EepromState::Command;
+    }
+
+    fn transfer(&mut self, mosi: u8) -> u8 {
+        let len = self.data.len();
+        match self.state {
+            EepromState::Command => {
+                self.state = match mosi {
+                    EEPROM_READ | EEPROM_WRITE => EepromState::Address {
+                        command: mosi,
+                        high: None,
+                    },
+                    EEPROM_WREN => {
+                        self.write_enabled = true;
+                        EepromState::Done
+                    }
+                    EEPROM_WRDI => {
+                        self.write_enabled = false;
+                        EepromState::Done
+                    }
+                    EEPROM_RDSR => EepromState::Status,
+                    _ => EepromState::Done,
+                };
+                0xFF
+            }
+            EepromState::Address {
+                command,
+                high: None,
+            } => {
+                self.state = EepromState::Address {
+                    command,
+                    high: Some(mosi),
+                };
+                0xFF
+            }
+            EepromState::Address {
+                command,
+                high: Some(high),
+            } => {
+                let addr = usize::from(u16::from_be_bytes([high, mosi])) % len;
+                self.state = match command {
+                    EEPROM_READ => EepromState::Read(addr),
+                    _ if self.write_enabled => {
+                        self.wrote = true;
+                        EepromState::Write(addr)
+                    }
+                    _ => EepromState::Done,
+                };
+                0xFF
+            }
+            EepromState::Read(addr) => {
+                self.state = EepromState::Read((addr + 1) % len);
+                self.data[addr]
+            }
+            EepromState::Write(addr) => {
+                self.data[addr] = mosi;
+                self.state = EepromState::Write((addr + 1) % len);
+                0xFF
+            }
+            EepromState::Status => {
+                if self.write_enabled {
+                    STATUS_WEL
+                } else {
+                    0
+                }
+            }
+            EepromState::Done => 0xFF,
+        }
+    }
+
+    fn deselect(&mut self) {
+        if std::mem::take(&mut self.wrote) {
+            self.write_enabled = false;
+        }
+        self.state = EepromState::Done;
+    }
+
+    /// The latches, the command in progress and the contents.
+    fn save_state(&self, state: &mut Vec<u8>) {
+        let (tag, [a, b]) = match self.state {
+            EepromState::Command => (0, [0, 0]),
+            EepromState::Address {
+                command,
+                high: None,
+            } => (1, [command, 0]),
+            EepromState::Address {
+                command,
+                high: Some(high),
+            } => (2, [command, high]),
+            EepromState::Read(addr) => (3, (addr as u16).to_le_bytes()),
+            EepromState::Write(addr) => (4, (addr as u16).to_le_bytes()),
+            EepromState::Status => (5, [0, 0]),
+            EepromState::Done => (6, [0, 0]),
+        };
+        state.extend_from_slice(&[self.write_enabled.into(), self.wrote.into(), tag, a, b]);
+        state.extend_from_slice(&self.data);
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        let Some((&[write_enabled, wrote, tag, a, b], data)) = state.split_first_chunk() else {
+            return;
+        };
+        // The contents are the EEPROM's size; another one's do not fit.
+        if data.len() != self.data.len() {
+            return;
+        }
+        let addr = usize::from(u16::from_le_bytes([a, b]));
+        self.state = match tag {
+            0 => EepromState::Command,
+            1 => EepromState::Address {
+                command: a,
+                high: None,
+            },
+            2 => EepromState::Address {
+                command: a,
+                high: Some(b),
+            },
+            3 if addr < data.len() => EepromState::Read(addr),
+            4 if addr < data.len() => EepromState::Write(addr),
+            5 => EepromState::Status,
+            6 => EepromState::Done,
+            _ => return,
+        };
+        self.write_enabled = write_enabled != 0;
+        self.wrote = wrote != 0;
+        self.data.copy_from_slice(data);
+    }
+}
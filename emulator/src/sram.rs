@@ -0,0 +1,144 @@
+//! Battery-backed save RAM.
+//!
+//! [`Vm::attach_sram`] makes a range of RAM persistent: its contents are
+//! loaded from a save file, conventionally `.sav`, and written back by
+//! [`Vm::flush_sram`] and when the `Vm` is dropped, so a program's saved
+//! games and settings survive between runs:
+//!
+//! ```no_run
+//! # use emulator::Vm;
+//! let mut vm = Vm::new();
+//! vm.attach_sram(0x6000..=0x7FFF, "game.sav").unwrap();
+//! vm.run_frame().unwrap();
+//! // Saved again when `vm` is dropped, if the program changed it.
+//! vm.flush_sram().unwrap();
+//! ```
+//!
+//! A save is only written when the range differs from what was last loaded
+//! or saved, so flushing every frame costs a comparison rather than a
+//! write. The save RAM is ordinary memory to everything else: snapshots,
+//! rewinds and host writes change it like any other RAM, and the next flush
+//! saves the result. [`Vm::reload_rom`] keeps it even when it clears the
+//! rest of RAM, as a battery would.
+//!
+//! Dropping a `Vm` cannot report errors, so one that fails to save there is
+//! lost; frontends that care call [`Vm::flush_sram`] before letting go.
+
+use std::fs;
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use crate::vm::Vm;
+
+/// An attached save RAM range.
+#[derive(Debug)]
+pub(crate) struct Sram {
+    range: RangeInclusive<u16>,
+    path: PathBuf,
+    /// The contents last loaded or saved.
+    saved: Vec<u8>,
+}
+
+impl Sram {
+    fn span(&self) -> RangeInclusive<usize> {
+        usize::from(*self.range.start())..=usize::from(*self.range.end())
+    }
+}
+
+impl Vm {
+    /// Makes `range` battery-backed, stored in the file at `path`, after
+    /// saving the range attached before if it changed. A missing file is
+    /// created on the first flush after the range changes; an existing one
+    /// is loaded into the range at once.
+    ///
+    /// Fails if the range attached before cannot be saved, or if the file
+    /// cannot be read or is not exactly as long as `range`, which is then
+    /// left alone. The range attached before is detached either way.
+    pub fn attach_sram(
+        &mut self,
+        range: RangeInclusive<u16>,
+        path: impl AsRef<Path>,
+    ) -> io::Result<()> {
+        self.detach_sram()?;
+        let path = path.as_ref().to_path_buf();
+        let span = usize::from(*range.start())..=usize::from(*range.end());
+        let saved = match fs::read(&path) {
+            Ok(saved) if saved.len() == span.clone().count() => {
+                self.memory_mut()[span].copy_from_slice(&saved);
+                saved
+            }
+            Ok(saved) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "save file is {} bytes but the save RAM is {}",
+                        saved.len(),
+                        span.count()
+                    ),
+                ));
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => self.memory()[span].to_vec(),
+            Err(err) => return Err(err),
+        };
+        self.sram = Some(Sram { range, path, saved });
+        Ok(())
+    }
+
+    /// Saves the attached range if it changed and stops persisting it. The
+    /// range is detached even if saving fails.
+    pub fn detach_sram(&mut self) -> io::Result<()> {
+        let result = self.flush_sram();
+        self.sram = None;
+        result.map(|_| ())
+    }
+
+    /// The attached range and its save file.
+    pub fn sram(&self) -> Option<(RangeInclusive<u16>, &Path)> {
+        self.sram
+            .as_ref()
+            .map(|sram| (sram.range.clone(), sram.path.as_path()))
+    }
+
+    /// Whether the attached range differs from its save file.
+    pub fn sram_dirty(&self) -> bool {
+        self.sram
+            .as_ref()
+            .is_some_and(|sram| self.memory()[sram.span()] != sram.saved[..])
+    }
+
+    /// Writes the attached range to its save file if it changed, returning
+    /// whether it did.
+    pub fn flush_sram(&mut self) -> io::Result<bool> {
+        let Some(sram) = &self.sram else {
+            return Ok(false);
+        };
+        let contents = &self.memory()[sram.span()];
+        if *contents == sram.saved[..] {
+            return Ok(false);
+        }
+        let contents = contents.to_vec();
+        fs::write(&sram.path, &contents)?;
+        if let Some(sram) = &mut self.sram {
+            sram.saved = contents;
+        }
+        Ok(true)
+    }
+
+    /// The addresses of the attached range.
+    pub(crate) fn sram_span(&self) -> Option<RangeInclusive<usize>> {
+        self.sram.as_ref().map(Sram::span)
+    }
+
+    /// Runs `f` and then puts back the save RAM's contents from before.
+    pub(crate) fn preserving_sram(&mut self, f: impl FnOnce(&mut Self)) {
+        let kept = self
+            .sram
+            .as_ref()
+            .map(|sram| (sram.span(), self.memory()[sram.span()].to_vec()));
+        f(self);
+        if let Some((span, contents)) = kept {
+            self.memory_mut()[span].copy_from_slice(&contents);
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! Stack bounds checking.
+//!
+//! The hardware stack lives in page 1 and wraps silently, so a runaway
+//! push sequence or an interrupt arriving on a full stack overwrites
+//! whatever is below it without a trace. [`Vm::set_stack_bounds`] lets a
+//! debugging session say which stack pointer values the program may use;
+//! an instruction that pushes the SP below them stops the machine with
+//! [`VmError::StackOverflow`], and one that pops it above them with
+//! [`VmError::StackUnderflow`]:
+//!
+//! ```
+//! # use emulator::{ResetKind, Vm, VmError};
+//! let mut vm = Vm::new();
+//! vm.load(0x8000, &[0xA9, 0x00, 0xA9, 0x00]).unwrap();
+//! vm.load(0xFFFA, &[0x00, 0x90, 0x00, 0x80]).unwrap();
+//! vm.reset(ResetKind::Soft);
+//! // Room for two bytes below the SP of 0xFD reset leaves.
+//! vm.set_stack_bounds(Some(0xFB..=0xFD));
+//! vm.set_nmi(true);
+//! vm.step().unwrap();
+//! // Entering the handler pushes three.
+//! assert_eq!(vm.step(), Err(VmError::StackOverflow { pc: 0x8002, sp: 0xFA }));
+//! ```
+//!
+//! Only moves of the SP count: an instruction that pushes it below the
+//! bounds, wrapping around page 1 included, or pops it above them.
+//! Interrupt entry pushes like any other instruction, and is reported with
+//! the PC of the instruction it interrupted. The instruction completes
+//! either way, so the bytes it pushed are already in memory. Setting the SP
+//! from the host is not checked.
+//!
+//! Checking needs the host after every instruction, so a machine with
+//! bounds set runs step by step like a traced one.
+
+use std::ops::RangeInclusive;
+
+use crate::error::VmError;
+use crate::vm::Vm;
+
+impl Vm {
+    /// Checks every push and pop against `bounds`, the SP values the
+    /// program may use, or stops checking with `None`. Resets keep them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bounds` is empty.
+    pub fn set_stack_bounds(&mut self, bounds: Option<RangeInclusive<u8>>) {
+        if let Some(bounds) = &bounds {
+            assert!(!bounds.is_empty(), "stack bounds cannot be empty");
+        }
+        self.stack_bounds = bounds;
+    }
+
+    pub fn stack_bounds(&self) -> Option<RangeInclusive<u8>> {
+        self.stack_bounds.clone()
+    }
+
+    /// Checks the SP move of the instruction at `pc`, which began with the
+    /// SP at `before`.
+    pub(crate) fn check_stack(&self, pc: u16, before: u16) -> Result<(), VmError> {
+        let Some(bounds) = &self.stack_bounds else {
+            return Ok(());
+        };
+        let sp = self.cpu.sp;
+        // Pushes are positive, so a push past 0x00 still moves downwards.
+        let moved = i16::from((before as u8).wrapping_sub(sp as u8) as i8);
+        let to = i16::from(before as u8) - moved;
+        if moved > 0 && to < i16::from(*bounds.start()) {
+            return Err(VmError::StackOverflow { pc, sp });
+        }
+        if moved < 0 && to > i16::from(*bounds.end()) {
+            return Err(VmError::StackUnderflow { pc, sp });
+        }
+        Ok(())
+    }
+}
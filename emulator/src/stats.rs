@@ -0,0 +1,94 @@
+//! Execution statistics.
+//!
+//! The CPU counts what it does as it goes, in the kernel, so the counters
+//! hold for batches as well as single steps and cost nothing to keep on.
+//! [`Vm::stats`] reads them; [`Vm::reset_stats`] starts a fresh count. A
+//! reset or a restored [`Snapshot`](crate::Snapshot) keeps the counts, and
+//! frames replayed by [`Vm::rewind`] are not counted again.
+//!
+//! The [`Display`](fmt::Display) form summarizes the totals and lists the
+//! executed opcodes, most frequent first:
+//!
+//! ```text
+//! ; 300 instructions in 807 cycles, 1 interrupts, 802 reads, 3 writes
+//!      count      %  opcode
+//!        200  66.7%  A9  LDA
+//!        100  33.3%  AD  LDA
+//! ```
+
+use std::fmt;
+
+use crate::ffi::CpuStats;
+use crate::vm::Vm;
+
+/// What the CPU has done since construction or [`Vm::reset_stats`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// Instructions executed; illegal opcodes and interrupt entries are not
+    /// instructions.
+    pub instructions: u64,
+    /// Cycles elapsed, including wait states, interrupt entry and the
+    /// cycles DMA held the CPU off the bus.
+    pub cycles: u64,
+    /// Interrupt handlers entered.
+    pub irqs: u64,
+    /// CPU bus reads, opcode and operand fetches included.
+    pub reads: u64,
+    /// CPU bus writes, interrupt entry's pushes included.
+    pub writes: u64,
+    /// Executions of each opcode.
+    pub opcodes: [u64; 256],
+}
+
+impl Stats {
+    fn from_cpu(stats: &CpuStats) -> Self {
+        Self {
+            instructions: stats.opcodes.iter().sum(),
+            cycles: stats.cycles,
+            irqs: stats.irqs,
+            reads: stats.reads,
+            writes: stats.writes,
+            opcodes: stats.opcodes,
+        }
+    }
+
+    /// Every opcode that executed with its count, most executed first; ties
+    /// go to the lower opcode.
+    pub fn hot_opcodes(&self) -> Vec<(u8, u64)> {
+        let mut hot: Vec<_> = (0..=255)
+            .map(|opcode| (opcode, self.opcodes[opcode as usize]))
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hot
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "; {} instructions in {} cycles, {} interrupts, {} reads, {} writes",
+            self.instructions, self.cycles, self.irqs, self.reads, self.writes
+        )?;
+        writeln!(f, "{:>10} {:>6}  opcode", "count", "%")?;
+        for (opcode, count) in self.hot_opcodes() {
+            let name = rvm8_core::OPCODES[opcode as usize].map_or("???", |op| op.mnemonic);
+            let percent = 100.0 * count as f64 / self.instructions as f64;
+            writeln!(f, "{count:>10} {percent:>5.1}%  {opcode:02X}  {name}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Vm {
+    /// The execution counters; see [`crate::stats`].
+    pub fn stats(&self) -> Stats {
+        Stats::from_cpu(&self.cpu.stats)
+    }
+
+    /// Zeroes every execution counter.
+    pub fn reset_stats(&mut self) {
+        self.cpu.stats = CpuStats::default();
+    }
+}
@@ -0,0 +1,69 @@
+//! Stepping a frame, a scanline or an instruction at a time.
+//!
+//! [`Vm::step_frame`], [`Vm::step_scanline`] and [`Vm::step_instruction`]
+//! run the machine in progressively finer steps while keeping the frame
+//! boundary where it would be under [`Vm::run_frame`]: whichever step
+//! reaches the end of a frame finishes it, rendering the picture, raising
+//! vblank, delivering the frame's audio and running the frame hooks, and
+//! the step that begins a frame logs inputs and applies freezes first.
+//! Mixing granularities therefore delivers the same events, in the same
+//! order, as running whole frames. [`Vm::step`] and [`Vm::run_cycles`]
+//! begin no frames, so they deliver none of these events unless they reach
+//! the end of a frame a finer step had begun; the frame's end is a
+//! [scheduled event](crate::scheduler) waiting for one to begin.
+//!
+//! A frame is divided into [`SCANLINES`] lines as near equal in length as
+//! whole cycles allow, and the [display](crate::display) draws a row of
+//! the screen as each one ends.
+
+use crate::error::VmError;
+use crate::vm::Vm;
+
+/// Scanlines in one frame, one per row of the display.
+pub const SCANLINES: u32 = crate::display::HEIGHT as u32;
+
+impl Vm {
+    /// Runs to the end of the current frame, finishing it; the same as
+    /// [`Vm::run_frame`].
+    pub fn step_frame(&mut self) -> Result<(), VmError> {
+        self.run_frame()
+    }
+
+    /// Runs to the end of the current scanline, finishing the frame if it
+    /// was the last. An instruction that overshoots the boundary is not
+    /// split, so the next scanline starts late and may be skipped entirely.
+    pub fn step_scanline(&mut self) -> Result<(), VmError> {
+        self.begin_frame();
+        let end = self.scanline_end(self.scanline());
+        self.run_until(end)
+    }
+
+    /// Executes one instruction, finishing the frame if it reached the end.
+    pub fn step_instruction(&mut self) -> Result<(), VmError> {
+        self.begin_frame();
+        self.step()
+    }
+
+    /// The scanline the next instruction starts in, from 0 to
+    /// `SCANLINES - 1`.
+    pub fn scanline(&self) -> u32 {
+        self.scanlines_ended().min(SCANLINES - 1)
+    }
+
+    /// How many scanlines of the current frame have ended, up to
+    /// `SCANLINES`: those whose [end](Vm::scanline_end) the cycle counter
+    /// has reached.
+    pub(crate) fn scanlines_ended(&self) -> u32 {
+        let elapsed = self.cpu.cycles.wrapping_sub(self.frame_cycle as u32);
+        let lines = (u64::from(elapsed) + 1) * u64::from(SCANLINES) - 1;
+        let lines = lines / u64::from(self.cycles_per_frame());
+        lines.min(u64::from(SCANLINES)) as u32
+    }
+
+    /// Cycle count at which scanline `line` ends, modulo 2^32.
+    pub(crate) fn scanline_end(&self, line: u32) -> u32 {
+        let offset =
+            u64::from(line + 1) * u64::from(self.cycles_per_frame()) / u64::from(SCANLINES);
+        (self.frame_cycle + offset) as u32
+    }
+}
@@ -0,0 +1,462 @@
+//! Symbol tables for symbolic debugging output.
+//!
+//! A [`SymbolTable`] maps label names to addresses. It comes from an
+//! [`Assembly`], or from a map file with one `name = value` line per symbol,
+//! which is also what the table's [`Display`](fmt::Display) form writes:
+//!
+//! ```text
+//! ; rvm-8 symbols
+//! player_hp = $0200
+//! oam = $0300..$03FF
+//! start = $8000
+//! ```
+//!
+//! Values use the assembler's `$`, `0x`, `%` or decimal notation, and `;`
+//! starts a comment. A `start..end` value names a region, inclusive of
+//! both ends; the name is also a symbol for its first address. Addresses
+//! inside a region without a name of their own are shown relative to it,
+//! `oam+16`, however far in.
+//!
+//! Instructions, trace records and debugger stop reasons print with names
+//! in place of raw addresses through their `with_symbols` methods, which
+//! return a [`Symbolic`] view. A machine's own table, attached with
+//! [`Vm::set_symbols`] or filled in by [`Vm::label`], also names addresses
+//! in its [hexdumps](Vm::hexdump), in [trace lines](crate::trace::TextSink)
+//! written without a table of their own and in the
+//! [monitor](crate::monitor):
+//!
+//! ```
+//! # use emulator::Vm;
+//! let mut vm = Vm::new();
+//! vm.label(0x0200, "player_hp");
+//! vm.label_region(0x0300..=0x03FF, "oam");
+//! let symbols = vm.symbols().unwrap();
+//! assert_eq!(symbols.describe(0x0201), "player_hp+1");
+//! assert_eq!(symbols.describe(0x0340), "oam+64");
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use crate::asm::Assembly;
+use crate::debugger::StopReason;
+use crate::disasm::{Instruction, Operand};
+use crate::ffi::BusAccess;
+use crate::trace::TraceRecord;
+use crate::vm::Vm;
+
+/// Farthest past a label [`SymbolTable::describe`] still names an address
+/// relative to it.
+pub const MAX_OFFSET: u16 = 0xFF;
+
+/// Names for addresses.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    by_name: BTreeMap<String, u16>,
+    /// The first name given to each address.
+    by_addr: BTreeMap<u16, String>,
+    /// The names that label regions, with their ranges.
+    regions: BTreeMap<String, RangeInclusive<u16>>,
+}
+
+/// A map file line that could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolError {
+    /// 1-based line.
+    pub line: usize,
+    pub kind: SymbolErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SymbolErrorKind {
+    /// The line is not `name = value`.
+    Syntax,
+    /// The name is not an assembler identifier.
+    BadName(String),
+    /// The value is not a 16-bit number.
+    BadValue(String),
+    DuplicateSymbol(String),
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            SymbolErrorKind::Syntax => write!(f, "expected `name = value`"),
+            SymbolErrorKind::BadName(name) => write!(f, "`{name}` is not a valid symbol name"),
+            SymbolErrorKind::BadValue(value) => write!(f, "`{value}` is not a 16-bit value"),
+            SymbolErrorKind::DuplicateSymbol(name) => {
+                write!(f, "symbol `{name}` is already defined")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SymbolError {}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// A map file value: one address, or a region's first and last.
+fn parse_range(text: &str) -> Option<RangeInclusive<u16>> {
+    match text.split_once("..") {
+        Some((start, end)) => {
+            let range = parse_value(start.trim())?..=parse_value(end.trim())?;
+            (!range.is_empty()).then_some(range)
+        }
+        None => parse_value(text).map(|addr| addr..=addr),
+    }
+}
+
+pub(crate) fn parse_value(text: &str) -> Option<u16> {
+    let (digits, radix) = if let Some(hex) = text.strip_prefix('$') {
+        (hex, 16)
+    } else if let Some(hex) = text.strip_prefix("0x") {
+        (hex, 16)
+    } else if let Some(bin) = text.strip_prefix('%') {
+        (bin, 2)
+    } else {
+        (text, 10)
+    };
+    u16::from_str_radix(digits, radix).ok()
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses a map file.
+    pub fn parse(text: &str) -> Result<Self, SymbolError> {
+        let mut table = Self::new();
+        for (index, line) in text.lines().enumerate() {
+            let error = |kind| SymbolError {
+                line: index + 1,
+                kind,
+            };
+            let line = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (name, value) = line.split_once('=').ok_or(error(SymbolErrorKind::Syntax))?;
+            let (name, value) = (name.trim(), value.trim());
+            if !is_identifier(name) {
+                return Err(error(SymbolErrorKind::BadName(name.into())));
+            }
+            let range =
+                parse_range(value).ok_or_else(|| error(SymbolErrorKind::BadValue(value.into())))?;
+            let inserted = if value.contains("..") {
+                table.insert_region(name, range)
+            } else {
+                table.insert(name, *range.start())
+            };
+            if !inserted {
+                return Err(error(SymbolErrorKind::DuplicateSymbol(name.into())));
+            }
+        }
+        Ok(table)
+    }
+
+    /// Adds `name` for `addr`, returning `false` and changing nothing if the
+    /// name is already taken. An address keeps the first name it was given
+    /// for display.
+    pub fn insert(&mut self, name: &str, addr: u16) -> bool {
+        if self.by_name.contains_key(name) {
+            return false;
+        }
+        self.by_name.insert(name.into(), addr);
+        self.by_addr.entry(addr).or_insert_with(|| name.into());
+        true
+    }
+
+    /// Adds `name` for the region `range`, and as a symbol for its first
+    /// address, returning `false` and changing nothing if the name is
+    /// already taken.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is empty.
+    pub fn insert_region(&mut self, name: &str, range: RangeInclusive<u16>) -> bool {
+        assert!(!range.is_empty(), "region `{name}` is empty");
+        if !self.insert(name, *range.start()) {
+            return false;
+        }
+        self.regions.insert(name.into(), range);
+        true
+    }
+
+    /// The address of `name`.
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.by_name.get(name).copied()
+    }
+
+    /// The name displayed for exactly `addr`.
+    pub fn name(&self, addr: u16) -> Option<&str> {
+        self.by_addr.get(&addr).map(String::as_str)
+    }
+
+    /// The smallest region containing `addr`, with its range; of regions
+    /// the same size, the one starting last.
+    pub fn region(&self, addr: u16) -> Option<(&str, RangeInclusive<u16>)> {
+        self.regions
+            .iter()
+            .filter(|(_, range)| range.contains(&addr))
+            .min_by_key(|(_, range)| (range.end() - range.start(), u16::MAX - range.start()))
+            .map(|(name, range)| (name.as_str(), range.clone()))
+    }
+
+    /// Every region with its range, by name.
+    pub fn regions(&self) -> impl Iterator<Item = (&str, RangeInclusive<u16>)> + '_ {
+        self.regions
+            .iter()
+            .map(|(name, range)| (name.as_str(), range.clone()))
+    }
+
+    /// `addr` as the region containing it and the distance into it, unless
+    /// the address has a name of its own.
+    pub(crate) fn in_region(&self, addr: u16) -> Option<(&str, u16)> {
+        if self.name(addr).is_some() {
+            return None;
+        }
+        let (name, range) = self.region(addr)?;
+        Some((name, addr - range.start()))
+    }
+
+    /// The nearest name at or below `addr`, at most [`MAX_OFFSET`] bytes
+    /// back, with the distance to it.
+    pub fn nearest(&self, addr: u16) -> Option<(&str, u16)> {
+        let (&base, name) = self.by_addr.range(..=addr).next_back()?;
+        let offset = addr - base;
+        (offset <= MAX_OFFSET).then_some((name.as_str(), offset))
+    }
+
+    /// `addr` as `name`, `name+offset` from the region containing it or
+    /// else the nearest label below it or, with no label close enough,
+    /// `$XXXX`.
+    pub fn describe(&self, addr: u16) -> String {
+        match self.in_region(addr).or_else(|| self.nearest(addr)) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, offset)) => format!("{name}+{offset}"),
+            None => format!("${addr:04X}"),
+        }
+    }
+
+    /// Every symbol with its address, by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u16)> + '_ {
+        self.by_name
+            .iter()
+            .map(|(name, &addr)| (name.as_str(), addr))
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+}
+
+impl FromStr for SymbolTable {
+    type Err = SymbolError;
+
+    fn from_str(text: &str) -> Result<Self, SymbolError> {
+        Self::parse(text)
+    }
+}
+
+impl From<&Assembly> for SymbolTable {
+    /// Every label and constant of the program, constants included since the
+    /// assembler does not tell them apart.
+    fn from(program: &Assembly) -> Self {
+        let mut table = Self::new();
+        for (name, &addr) in &program.symbols {
+            table.insert(name, addr);
+        }
+        table
+    }
+}
+
+impl fmt::Display for SymbolTable {
+    /// The map file form, ordered by address with each address's displayed
+    /// name first, so that parsing it back gives the same table.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut symbols: Vec<_> = self.iter().collect();
+        symbols.sort_by_key(|&(name, addr)| (addr, self.name(addr) != Some(name), name));
+        for (name, addr) in symbols {
+            match self.regions.get(name) {
+                Some(range) => writeln!(f, "{name} = ${addr:04X}..${:04X}", range.end())?,
+                None => writeln!(f, "{name} = ${addr:04X}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Vm {
+    /// Attaches `symbols` for output that names addresses, such as
+    /// [`Vm::profile_report`], replacing any table attached before.
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = Some(symbols);
+    }
+
+    /// The table attached with [`Vm::set_symbols`].
+    pub fn symbols(&self) -> Option<&SymbolTable> {
+        self.symbols.as_ref()
+    }
+
+    /// Detaches the symbol table, handing it back.
+    pub fn take_symbols(&mut self) -> Option<SymbolTable> {
+        self.symbols.take()
+    }
+
+    /// Names `addr` in the attached symbol table, attaching an empty one
+    /// first if there is none. Returns `false` and changes nothing if the
+    /// name is taken.
+    pub fn label(&mut self, addr: u16, name: &str) -> bool {
+        self.symbols.get_or_insert_default().insert(name, addr)
+    }
+
+    /// Names the region `range` as [`Vm::label`] names an address, as
+    /// [`SymbolTable::insert_region`] does.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is empty.
+    pub fn label_region(&mut self, range: RangeInclusive<u16>, name: &str) -> bool {
+        self.symbols
+            .get_or_insert_default()
+            .insert_region(name, range)
+    }
+}
+
+/// A value displayed with names from a [`SymbolTable`].
+pub struct Symbolic<'a, T> {
+    value: &'a T,
+    symbols: &'a SymbolTable,
+}
+
+impl<'a, T> Symbolic<'a, T> {
+    pub(crate) fn new(value: &'a T, symbols: &'a SymbolTable) -> Self {
+        Self { value, symbols }
+    }
+}
+
+impl Operand {
+    /// Displays the operand with a name for an address that has one, or
+    /// relative to the region containing it. Immediate values and branch
+    /// offsets are left numeric.
+    pub fn with_symbols<'a>(&'a self, symbols: &'a SymbolTable) -> Symbolic<'a, Operand> {
+        Symbolic::new(self, symbols)
+    }
+}
+
+impl fmt::Display for Symbolic<'_, Operand> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operand = *self.value;
+        let addr = match operand {
+            Operand::ZeroPage(a)
+            | Operand::ZeroPageX(a)
+            | Operand::ZeroPageY(a)
+            | Operand::IndirectX(a)
+            | Operand::IndirectY(a) => a.into(),
+            Operand::Absolute(a)
+            | Operand::AbsoluteX(a)
+            | Operand::AbsoluteY(a)
+            | Operand::Indirect(a) => a,
+            _ => return operand.fmt(f),
+        };
+        let name = match (self.symbols.name(addr), self.symbols.in_region(addr)) {
+            (Some(name), _) => name.to_string(),
+            (None, Some((region, offset))) => format!("{region}+{offset}"),
+            (None, None) => return operand.fmt(f),
+        };
+        match operand {
+            Operand::ZeroPageX(_) | Operand::AbsoluteX(_) => write!(f, "{name},X"),
+            Operand::ZeroPageY(_) | Operand::AbsoluteY(_) => write!(f, "{name},Y"),
+            Operand::Indirect(_) => write!(f, "({name})"),
+            Operand::IndirectX(_) => write!(f, "({name},X)"),
+            Operand::IndirectY(_) => write!(f, "({name}),Y"),
+            _ => f.write_str(&name),
+        }
+    }
+}
+
+impl Instruction {
+    /// Displays the instruction with its operand named where possible.
+    pub fn with_symbols<'a>(&'a self, symbols: &'a SymbolTable) -> Symbolic<'a, Instruction> {
+        Symbolic::new(self, symbols)
+    }
+}
+
+impl fmt::Display for Symbolic<'_, Instruction> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let instr = self.value;
+        match instr.operands {
+            Operand::None => f.write_str(instr.mnemonic),
+            operand => write!(
+                f,
+                "{} {}",
+                instr.mnemonic,
+                operand.with_symbols(self.symbols)
+            ),
+        }
+    }
+}
+
+impl TraceRecord {
+    /// Displays the record in the usual layout with the instruction's
+    /// operand named.
+    pub fn with_symbols<'a>(&'a self, symbols: &'a SymbolTable) -> Symbolic<'a, TraceRecord> {
+        Symbolic::new(self, symbols)
+    }
+}
+
+impl fmt::Display for Symbolic<'_, TraceRecord> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let record = self.value;
+        let instr = record.instruction.with_symbols(self.symbols).to_string();
+        record.fmt_with(f, &instr)
+    }
+}
+
+impl StopReason {
+    /// Displays the reason with addresses described relative to the nearest
+    /// label.
+    pub fn with_symbols<'a>(&'a self, symbols: &'a SymbolTable) -> Symbolic<'a, StopReason> {
+        Symbolic::new(self, symbols)
+    }
+}
+
+impl fmt::Display for Symbolic<'_, StopReason> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let describe = |addr| self.symbols.describe(addr);
+        match *self.value {
+            StopReason::Breakpoint { pc } => write!(f, "breakpoint at {}", describe(pc)),
+            StopReason::Watchpoint {
+                pc,
+                addr,
+                access,
+                value,
+            } => {
+                let access = match access {
+                    BusAccess::Read => "read of",
+                    BusAccess::Write => "write of",
+                };
+                write!(
+                    f,
+                    "watchpoint: {access} ${value:02X} at {} by the instruction at {}",
+                    describe(addr),
+                    describe(pc)
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,292 @@
+//! Conformance test ROMs.
+//!
+//! A test ROM checks the CPU or a device from the inside and reports
+//! through four [extension opcodes](crate::extension), which
+//! [`TestKit::install`] registers:
+//!
+//! | Opcode          | Bytes            | Effect                                   |
+//! | --------------- | ---------------- | ---------------------------------------- |
+//! | [`ASSERT_EQ`]   | `$42 r nn`       | check register `r` ([`REG_A`]..) is `nn` |
+//! | [`PASS`]        | `$22`            | end the test                             |
+//! | [`FAIL`]        | `$32 nn`         | end the test as failed with code `nn`    |
+//! | [`PRINT`]       | `$52 lo hi`      | log the 0-terminated text at `hi:lo`     |
+//!
+//! A failed assertion is recorded and the ROM carries on, so one run
+//! reports every mismatch; the test passes only if it reaches `PASS` with
+//! none. The assembler does not know the opcodes, so write them with
+//! `.byte`:
+//!
+//! ```text
+//!         LDA #$80
+//!         LSR A
+//!         .byte $42, 0, $40   ; ASSERT_EQ A, $40
+//!         .byte $52           ; PRINT done
+//!         .word done
+//!         .byte $22           ; PASS
+//! done:   .byte "lsr ok", 0
+//! ```
+//!
+//! [`run_test_rom`] runs a ROM file with the kit installed:
+//!
+//! ```no_run
+//! let report = emulator::testkit::run_test_rom("tests/lsr.rvm").unwrap();
+//! assert!(report.passed(), "{report}");
+//! ```
+//!
+//! The opcodes stay claimed, so the illegal-opcode behaviour of these four
+//! bytes cannot be tested with the kit installed.
+
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::error::VmError;
+use crate::extension::OpcodeCall;
+use crate::rom::{Rom, RomError};
+use crate::vm::Vm;
+
+/// `ASSERT_EQ r nn`: record whether register `r` holds `nn`.
+pub const ASSERT_EQ: u8 = 0x42;
+/// `PASS`: end the test.
+pub const PASS: u8 = 0x22;
+/// `FAIL nn`: end the test as failed with code `nn`.
+pub const FAIL: u8 = 0x32;
+/// `PRINT lo hi`: log the 0-terminated text at `hi:lo`.
+pub const PRINT: u8 = 0x52;
+
+/// `ASSERT_EQ` register operands.
+pub const REG_A: u8 = 0;
+pub const REG_X: u8 = 1;
+pub const REG_Y: u8 = 2;
+/// The status flags.
+pub const REG_P: u8 = 3;
+/// The low byte of the SP.
+pub const REG_SP: u8 = 4;
+
+/// Cycles [`run_test_rom`] gives a ROM to finish.
+pub const DEFAULT_CYCLE_LIMIT: u64 = 100_000_000;
+
+/// Longest `PRINT` text; longer text is cut off.
+const MAX_PRINT: usize = 256;
+
+/// How a test run ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The ROM reached `PASS`.
+    Passed,
+    /// The ROM reached `FAIL` at `pc` with `code`.
+    Failed { pc: u16, code: u8 },
+    /// The machine stopped with an error, such as an illegal opcode or a
+    /// bad `ASSERT_EQ` register.
+    Error(VmError),
+    /// The ROM did not finish within the cycle limit.
+    TimedOut,
+}
+
+/// An `ASSERT_EQ` that did not hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AssertFailure {
+    /// Address of the opcode.
+    pub pc: u16,
+    pub register: u8,
+    pub expected: u8,
+    pub actual: u8,
+}
+
+/// What a test ROM reported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestReport {
+    pub outcome: Outcome,
+    /// `ASSERT_EQ`s that held.
+    pub assertions: u32,
+    pub failures: Vec<AssertFailure>,
+    /// Lines logged by `PRINT`, in order.
+    pub output: Vec<String>,
+    /// Cycles the run took.
+    pub cycles: u64,
+}
+
+impl TestReport {
+    /// Whether the ROM reached `PASS` with every assertion holding.
+    pub fn passed(&self) -> bool {
+        self.outcome == Outcome::Passed && self.failures.is_empty()
+    }
+}
+
+fn register_name(register: u8) -> &'static str {
+    match register {
+        REG_A => "A",
+        REG_X => "X",
+        REG_Y => "Y",
+        REG_P => "P",
+        _ => "SP",
+    }
+}
+
+/// A summary line, then one line per failed assertion.
+impl fmt::Display for TestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.outcome {
+            Outcome::Passed => write!(f, "passed")?,
+            Outcome::Failed { pc, code } => write!(f, "failed with code {code} at ${pc:04X}")?,
+            Outcome::Error(err) => write!(f, "stopped: {err}")?,
+            Outcome::TimedOut => write!(f, "timed out")?,
+        }
+        write!(
+            f,
+            ", {} of {} assertions held, {} cycles",
+            self.assertions,
+            self.assertions as usize + self.failures.len(),
+            self.cycles
+        )?;
+        for failure in &self.failures {
+            write!(
+                f,
+                "\n${:04X}: expected {} = ${:02X}, found ${:02X}",
+                failure.pc,
+                register_name(failure.register),
+                failure.expected,
+                failure.actual
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// What the opcodes recorded so far.
+#[derive(Default)]
+struct State {
+    outcome: Option<Outcome>,
+    assertions: u32,
+    failures: Vec<AssertFailure>,
+    output: Vec<String>,
+}
+
+/// The test opcodes installed on a machine, sharing what they record.
+pub struct TestKit {
+    state: Arc<Mutex<State>>,
+}
+
+impl TestKit {
+    /// Registers the test opcodes on `vm`, replacing any handlers they had.
+    pub fn install(vm: &mut Vm) -> Self {
+        let state = Arc::new(Mutex::new(State::default()));
+        let registered = [
+            vm.register_opcode(ASSERT_EQ, handler(&state, assert_eq)),
+            vm.register_opcode(
+                PASS,
+                handler(&state, |_, state| {
+                    state.outcome = Some(Outcome::Passed);
+                    Ok(())
+                }),
+            ),
+            vm.register_opcode(
+                FAIL,
+                handler(&state, |call, state| {
+                    let pc = call.registers().pc.wrapping_sub(1);
+                    let code = call.fetch();
+                    state.outcome = Some(Outcome::Failed { pc, code });
+                    Ok(())
+                }),
+            ),
+            vm.register_opcode(PRINT, handler(&state, print)),
+        ];
+        for result in registered {
+            result.expect("test opcodes are not kernel opcodes");
+        }
+        Self { state }
+    }
+
+    /// Runs `vm` until the ROM reaches `PASS` or `FAIL`, stops with an
+    /// error or has run `cycle_limit` cycles, and reports what it recorded.
+    /// The record is cleared for the next run.
+    pub fn run(&self, vm: &mut Vm, cycle_limit: u64) -> TestReport {
+        let mut cycles = 0;
+        let outcome = loop {
+            if let Some(outcome) = self.lock().outcome.take() {
+                break outcome;
+            }
+            if cycles >= cycle_limit {
+                break Outcome::TimedOut;
+            }
+            let before = vm.cycles();
+            let result = vm.step();
+            cycles += u64::from(vm.cycles().wrapping_sub(before));
+            if let Err(err) = result {
+                break Outcome::Error(err);
+            }
+        };
+        let state = std::mem::take(&mut *self.lock());
+        TestReport {
+            outcome,
+            assertions: state.assertions,
+            failures: state.failures,
+            output: state.output,
+            cycles,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Wraps a test opcode as an extension handler taking 2 cycles.
+fn handler(
+    state: &Arc<Mutex<State>>,
+    mut op: impl FnMut(&mut OpcodeCall<'_>, &mut State) -> Result<(), String> + Send + 'static,
+) -> impl FnMut(&mut OpcodeCall<'_>) -> Result<u8, String> + Send + 'static {
+    let state = Arc::clone(state);
+    move |call| {
+        let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+        op(call, &mut state).map(|()| 2)
+    }
+}
+
+fn assert_eq(call: &mut OpcodeCall<'_>, state: &mut State) -> Result<(), String> {
+    let pc = call.registers().pc.wrapping_sub(1);
+    let register = call.fetch();
+    let expected = call.fetch();
+    let regs = call.registers();
+    let actual = match register {
+        REG_A => regs.a,
+        REG_X => regs.x,
+        REG_Y => regs.y,
+        REG_P => regs.flags,
+        REG_SP => regs.sp as u8,
+        _ => return Err(format!("ASSERT_EQ of unknown register {register}")),
+    };
+    if actual == expected {
+        state.assertions += 1;
+    } else {
+        state.failures.push(AssertFailure {
+            pc,
+            register,
+            expected,
+            actual,
+        });
+    }
+    Ok(())
+}
+
+fn print(call: &mut OpcodeCall<'_>, state: &mut State) -> Result<(), String> {
+    let addr = u16::from_le_bytes([call.fetch(), call.fetch()]);
+    let text: Vec<u8> = (0..MAX_PRINT as u16)
+        .map(|i| call.read(addr.wrapping_add(i)))
+        .take_while(|&byte| byte != 0)
+        .collect();
+    state
+        .output
+        .push(String::from_utf8_lossy(&text).into_owned());
+    Ok(())
+}
+
+/// Loads the ROM at `path` on a new machine with the [`TestKit`]
+/// installed and runs it for up to [`DEFAULT_CYCLE_LIMIT`] cycles.
+pub fn run_test_rom(path: impl AsRef<Path>) -> Result<TestReport, RomError> {
+    let rom = Rom::from_file(path)?;
+    let mut vm = Vm::new();
+    vm.load_rom(&rom)?;
+    let kit = TestKit::install(&mut vm);
+    Ok(kit.run(&mut vm, DEFAULT_CYCLE_LIMIT))
+}
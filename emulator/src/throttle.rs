@@ -0,0 +1,214 @@
+//! Host-speed control: fast-forward, slow motion and pause.
+//!
+//! A [`Throttle`] decides how fast emulated time passes on the host. At
+//! speed 1 a frame takes 1/[`FRAME_RATE`] of a second, at speed 2 half
+//! that, and at speed 0.5 twice that. Frame pacing and sound change
+//! together: the audio callback still receives samples at the
+//! [sample rate](Vm::set_sample_rate) the host plays, resampled so that
+//! each frame's audio lasts exactly as long as the frame does, and pitch
+//! follows the speed the way a tape played fast does. An [audio
+//! capture](Vm::start_audio_capture) is not affected and always records
+//! emulated time.
+//!
+//! Turbo runs frames as fast as the host can and mutes the audio callback,
+//! since no output device could keep up. Pausing stops running frames at
+//! all while the frontend keeps pumping its loop:
+//!
+//! ```no_run
+//! # use emulator::{throttle::Throttle, Vm};
+//! let mut vm = Vm::new();
+//! let mut throttle = Throttle::new();
+//! throttle.set_speed(2.0);
+//! loop {
+//!     // Runs a frame and sleeps until the next one is due.
+//!     throttle.run_frame(&mut vm).unwrap();
+//! }
+//! ```
+//!
+//! Frontends with their own event loop call [`Throttle::apply`] when the
+//! settings change and [`Throttle::frame_finished`] after each frame, and
+//! wait on the instant it returns instead of sleeping.
+//!
+//! Pacing keeps an ideal schedule, so a frame that starts late is followed
+//! by one that starts early. A host that falls more than [`MAX_LAG`] frames
+//! behind gives up catching up and starts the schedule afresh.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::VmError;
+use crate::vm::{FRAME_RATE, Vm};
+
+/// Frames the host may fall behind before the schedule restarts.
+pub const MAX_LAG: u32 = 4;
+
+/// How fast emulated time passes on the host.
+#[derive(Debug, Clone)]
+pub struct Throttle {
+    speed: f32,
+    paused: bool,
+    turbo: bool,
+    /// When the next frame is due, once one has run.
+    deadline: Option<Instant>,
+}
+
+impl Default for Throttle {
+    /// Full speed, running.
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Throttle {
+    pub fn new() -> Self {
+        Self {
+            speed: 1.0,
+            paused: false,
+            turbo: false,
+            deadline: None,
+        }
+    }
+
+    /// Sets the speed relative to real time: above 1 fast-forwards, below
+    /// slows down.
+    ///
+    /// # Panics
+    ///
+    /// Panics unless `speed` is finite and positive.
+    pub fn set_speed(&mut self, speed: f32) {
+        assert!(
+            speed.is_finite() && speed > 0.0,
+            "speed must be finite and positive, not {speed}"
+        );
+        self.speed = speed;
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// Stops running frames until [`Throttle::resume`].
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    /// Runs frames again, on a fresh schedule so the pause is not caught
+    /// up.
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.deadline = None;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Runs frames uncapped with the audio callback muted, or paces them
+    /// at the speed set again.
+    pub fn set_turbo(&mut self, turbo: bool) {
+        self.turbo = turbo;
+        self.deadline = None;
+    }
+
+    pub fn is_turbo(&self) -> bool {
+        self.turbo
+    }
+
+    /// Host time one frame takes at the speed set, or `None` in turbo.
+    pub fn frame_interval(&self) -> Option<Duration> {
+        if self.turbo {
+            return None;
+        }
+        Some(Duration::from_secs_f64(1.0 / f64::from(self.speed)) / FRAME_RATE)
+    }
+
+    /// Makes `vm` resample its audio for the current settings: muted in
+    /// turbo, and stretched or squeezed to the speed otherwise.
+    pub fn apply(&self, vm: &mut Vm) {
+        vm.audio.playback.speed = (!self.turbo).then_some(self.speed);
+    }
+
+    /// Records that a frame finished at `now` and returns when the next is
+    /// due, or `None` if it may start at once. Paused, the next check is due
+    /// one full-speed frame later.
+    pub fn frame_finished(&mut self, now: Instant) -> Option<Instant> {
+        if self.paused {
+            return Some(now + Duration::from_secs(1) / FRAME_RATE);
+        }
+        let interval = self.frame_interval()?;
+        let next = match self.deadline {
+            Some(deadline) if now <= deadline + interval * MAX_LAG => deadline + interval,
+            _ => now + interval,
+        };
+        self.deadline = Some(next);
+        Some(next).filter(|&next| next > now)
+    }
+
+    /// Runs one frame of `vm` unless paused, then sleeps until the next is
+    /// due. Returns whether a frame ran.
+    pub fn run_frame(&mut self, vm: &mut Vm) -> Result<bool, VmError> {
+        let ran = !self.paused;
+        if ran {
+            self.apply(vm);
+            vm.run_frame()?;
+        }
+        let now = Instant::now();
+        if let Some(next) = self.frame_finished(now) {
+            thread::sleep(next - now);
+        }
+        Ok(ran)
+    }
+}
+
+/// Resampling of the audio callback's samples, set by [`Throttle::apply`].
+#[derive(Debug, Clone)]
+pub(crate) struct Playback {
+    /// Speed to resample for, or `None` to mute.
+    pub speed: Option<f32>,
+    /// Position of the next output sample relative to the frame's first
+    /// input sample.
+    phase: f64,
+    /// The previous frame's last sample, to interpolate from.
+    last: i16,
+    out: Vec<i16>,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Self {
+            speed: Some(1.0),
+            phase: 0.0,
+            last: 0,
+            out: Vec::new(),
+        }
+    }
+}
+
+impl Playback {
+    /// `samples` as the audio callback should hear them, or `None` if it is
+    /// muted.
+    pub(crate) fn resample<'a>(&'a mut self, samples: &'a [i16]) -> Option<&'a [i16]> {
+        let speed = self.speed?;
+        if speed == 1.0 {
+            self.phase = 0.0;
+            self.last = samples.last().copied().unwrap_or(self.last);
+            return Some(samples);
+        }
+        // Linear interpolation over the previous frame's last sample
+        // followed by this frame's, carrying the fractional position over.
+        self.out.clear();
+        let step = f64::from(speed);
+        let len = samples.len() as f64;
+        let at = |i: usize| if i == 0 { self.last } else { samples[i - 1] };
+        while self.phase < len {
+            let i = self.phase as usize;
+            let frac = self.phase - i as f64;
+            let (a, b) = (f64::from(at(i)), f64::from(at(i + 1)));
+            self.out.push((a + (b - a) * frac).round() as i16);
+            self.phase += step;
+        }
+        self.phase -= len;
+        self.last = samples.last().copied().unwrap_or(self.last);
+        Some(&self.out)
+    }
+}
@@ -0,0 +1,219 @@
+//! Programmable timers.
+//!
+//! A [`Timer`] is a 16-bit up-counter clocked by the CPU clock through a
+//! power-of-two prescaler. When it overflows past 0xFFFF it reloads from its
+//! reload register, sets its overflow flag and, if enabled, holds its
+//! interrupt line until the flag is acknowledged. Timers are not mapped by
+//! default; each takes [`TIMER_SIZE`] bytes and [`timer_ports`] gives the
+//! conventional slot for the n-th one:
+//!
+//! ```
+//! # use emulator::{timer::{timer_ports, Timer, TIMER_IRQ}, Vm};
+//! let mut vm = Vm::new();
+//! vm.bus_mut().map(timer_ports(0), Timer::new(TIMER_IRQ)).unwrap();
+//! vm.bus_mut().map(timer_ports(1), Timer::new(TIMER_IRQ + 1)).unwrap();
+//! ```
+//!
+//! | Offset | Register                                                  |
+//! | ------ | --------------------------------------------------------- |
+//! | 0–1    | counter, little-endian                                    |
+//! | 2–3    | reload value, little-endian                               |
+//! | 4      | prescaler: the counter advances every 2^n cycles, n ≤ 15  |
+//! | 5      | `CTRL`: [`CTRL_ENABLE`], [`CTRL_IRQ`], [`CTRL_ONE_SHOT`]  |
+//! | 6      | `STATUS`: bit 0 overflowed; any write acknowledges        |
+//!
+//! A timer reloading from `r` overflows every `(0x10000 - r) << n` cycles. A
+//! one-shot timer clears [`CTRL_ENABLE`] when it overflows.
+
+use std::ops::RangeInclusive;
+
+use crate::bus::{BusDevice, set_word_byte};
+use crate::reset::ResetKind;
+
+/// Start of the timer slots.
+pub const TIMER_BASE: u16 = 0x2710;
+/// Bytes of registers per timer.
+pub const TIMER_SIZE: u16 = 8;
+/// Interrupt line used by the first timer by convention.
+pub const TIMER_IRQ: u8 = 2;
+
+/// `CTRL` bit letting the counter run.
+pub const CTRL_ENABLE: u8 = 1 << 0;
+/// `CTRL` bit raising the timer's interrupt line on overflow.
+pub const CTRL_IRQ: u8 = 1 << 1;
+/// `CTRL` bit stopping the timer after its first overflow.
+pub const CTRL_ONE_SHOT: u8 = 1 << 2;
+/// `STATUS` bit set on overflow.
+pub const STATUS_OVERFLOW: u8 = 1 << 0;
+
+const PRESCALE: u16 = 4;
+const CTRL: u16 = 5;
+const STATUS: u16 = 6;
+const MAX_PRESCALE: u8 = 15;
+
+/// The address range of the `index`-th timer slot.
+pub const fn timer_ports(index: u16) -> RangeInclusive<u16> {
+    let start = TIMER_BASE + index * TIMER_SIZE;
+    RangeInclusive::new(start, start + TIMER_SIZE - 1)
+}
+
+/// One timer channel.
+#[derive(Debug, Clone)]
+pub struct Timer {
+    counter: u16,
+    reload: u16,
+    prescale: u8,
+    ctrl: u8,
+    status: u8,
+    line: u8,
+    /// Cycles accumulated towards the next prescaled tick.
+    phase: u32,
+}
+
+impl Timer {
+    /// A stopped timer that raises interrupt `line` when allowed to.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `line` is not below [`IRQ_LINES`](crate::irq::IRQ_LINES).
+    pub fn new(line: u8) -> Self {
+        assert!(line < crate::irq::IRQ_LINES, "IRQ line {line} out of range");
+        Self {
+            counter: 0,
+            reload: 0,
+            prescale: 0,
+            ctrl: 0,
+            status: 0,
+            line,
+            phase: 0,
+        }
+    }
+
+    pub fn counter(&self) -> u16 {
+        self.counter
+    }
+
+    /// Whether the counter is enabled.
+    pub fn running(&self) -> bool {
+        self.ctrl & CTRL_ENABLE != 0
+    }
+
+    /// Whether the timer has overflowed since it was last acknowledged.
+    pub fn overflowed(&self) -> bool {
+        self.status & STATUS_OVERFLOW != 0
+    }
+
+    /// Advances the counter by `ticks` prescaled ticks.
+    fn advance(&mut self, mut ticks: u32) {
+        while ticks > 0 && self.ctrl & CTRL_ENABLE != 0 {
+            let to_overflow = 0x10000 - u32::from(self.counter);
+            if ticks < to_overflow {
+                self.counter += ticks as u16;
+                return;
+            }
+            ticks -= to_overflow;
+            self.counter = self.reload;
+            self.status |= STATUS_OVERFLOW;
+            if self.ctrl & CTRL_ONE_SHOT != 0 {
+                self.ctrl &= !CTRL_ENABLE;
+            }
+            // Skip whole periods rather than looping through them.
+            ticks %= 0x10000 - u32::from(self.reload);
+        }
+    }
+}
+
+impl Default for Timer {
+    /// A timer on [`TIMER_IRQ`].
+    fn default() -> Self {
+        Self::new(TIMER_IRQ)
+    }
+}
+
+impl BusDevice for Timer {
+    fn read8(&mut self, offset: u16) -> u8 {
+        let high = usize::from(offset & 1 == 1);
+        match offset {
+            0..=1 => self.counter.to_le_bytes()[high],
+            2..=3 => self.reload.to_le_bytes()[high],
+            PRESCALE => self.prescale,
+            CTRL => self.ctrl,
+            STATUS => self.status,
+            _ => 0,
+        }
+    }
+
+    fn write8(&mut self, offset: u16, val: u8) {
+        let high = offset & 1 == 1;
+        match offset {
+            0..=1 => set_word_byte(&mut self.counter, high, val),
+            2..=3 => set_word_byte(&mut self.reload, high, val),
+            PRESCALE => {
+                self.prescale = val.min(MAX_PRESCALE);
+                self.phase = 0;
+            }
+            CTRL => self.ctrl = val,
+            STATUS => self.status = 0,
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        if self.ctrl & CTRL_ENABLE == 0 {
+            return;
+        }
+        self.phase += cycles;
+        let ticks = self.phase >> self.prescale;
+        self.phase &= (1 << self.prescale) - 1;
+        self.advance(ticks);
+    }
+
+    /// Unbounded, as reads of the counter catch it up anyway and the next
+    /// overflow is a scheduled event.
+    fn batch_cycles(&self) -> u32 {
+        u32::MAX
+    }
+
+    /// The next overflow, if it would raise the interrupt line.
+    fn next_irq(&self) -> Option<u32> {
+        if !self.running() || self.ctrl & CTRL_IRQ == 0 || self.overflowed() {
+            return None;
+        }
+        let ticks = 0x10000 - u32::from(self.counter);
+        Some((ticks << self.prescale) - self.phase)
+    }
+
+    /// Stops the timer with every register cleared, on the same line.
+    fn reset(&mut self, _: ResetKind) {
+        *self = Self::new(self.line);
+    }
+
+    /// The registers and the prescaler's phase; the line is the mapping's.
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&self.counter.to_le_bytes());
+        state.extend_from_slice(&self.reload.to_le_bytes());
+        state.extend_from_slice(&[self.prescale, self.ctrl, self.status]);
+        state.extend_from_slice(&self.phase.to_le_bytes());
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        let &[c0, c1, r0, r1, prescale, ctrl, status, p0, p1, p2, p3] = state else {
+            return;
+        };
+        self.counter = u16::from_le_bytes([c0, c1]);
+        self.reload = u16::from_le_bytes([r0, r1]);
+        self.prescale = prescale.min(MAX_PRESCALE);
+        self.ctrl = ctrl;
+        self.status = status;
+        // Below one prescaled tick, as `tick` leaves it.
+        self.phase = u32::from_le_bytes([p0, p1, p2, p3]) & ((1 << self.prescale) - 1);
+    }
+
+    fn irq_lines(&self) -> u8 {
+        if self.overflowed() && self.ctrl & CTRL_IRQ != 0 {
+            1 << self.line
+        } else {
+            0
+        }
+    }
+}
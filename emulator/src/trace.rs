@@ -0,0 +1,781 @@
+//! Per-instruction execution traces.
+//!
+//! While a trace is set, [`Vm::step`] emits a [`TraceRecord`] before every
+//! instruction it executes, describing the machine as the instruction found
+//! it. The [`Display`](fmt::Display) form is one line per instruction in the
+//! layout most 6502 emulators log, so traces can be diffed against theirs:
+//!
+//! ```text
+//! 8002  AE 34 12  LDX $1234      A:42 X:00 Y:00 P:04 SP:FD CYC:2
+//! ```
+//!
+//! Records go to [`TraceSink`]s: [`TextSink`] writes those lines,
+//! [`BinarySink`] a dense encoding of what changed from one instruction to
+//! the next, which [`TraceReader`] reads back, and [`TraceBuffer`] keeps
+//! the latest in memory.
+//! A [`TraceConfig`] picks any number of them, alongside the
+//! [Chrome](crate::chrome) and [VCD](crate::vcd) exports, which describe a
+//! run in their own terms rather than by instruction.
+//!
+//! The sinks and exports that take a writer write every record or event to
+//! it as it comes, so a file wants a [`BufWriter`](io::BufWriter) around
+//! it. The first write error stops the trace and is kept for
+//! [`Vm::take_trace_error`].
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::chrome::ChromeTrace;
+use crate::disasm::{self, Instruction};
+use crate::symbols::SymbolTable;
+use crate::vcd::VcdTrace;
+use crate::vm::{Registers, Vm};
+
+/// One executed instruction and the state it started from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub pc: u16,
+    /// The instruction bytes; only the first `instruction.size` are part of
+    /// it.
+    pub bytes: [u8; 3],
+    pub instruction: Instruction,
+    pub registers: Registers,
+    /// Cycle counter before the instruction.
+    pub cycles: u32,
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with(f, &self.instruction.to_string())
+    }
+}
+
+impl TraceRecord {
+    pub fn opcode(&self) -> u8 {
+        self.instruction.opcode
+    }
+
+    /// Writes the record's line with `instr` as the disassembly column.
+    pub(crate) fn fmt_with(&self, f: &mut fmt::Formatter<'_>, instr: &str) -> fmt::Result {
+        let mut hex = String::with_capacity(8);
+        for (i, byte) in self.bytes[..self.instruction.size as usize]
+            .iter()
+            .enumerate()
+        {
+            if i > 0 {
+                hex.push(' ');
+            }
+            hex.push_str(&format!("{byte:02X}"));
+        }
+        let Registers {
+            a, x, y, sp, flags, ..
+        } = self.registers;
+        write!(
+            f,
+            "{:04X}  {hex:<8}  {instr:<13}  A:{a:02X} X:{x:02X} Y:{y:02X} P:{flags:02X} SP:{sp:02X} CYC:{}",
+            self.pc, self.cycles
+        )
+    }
+}
+
+/// Something that takes the records of a trace.
+///
+/// [`TraceConfig::sink`] sets one; [`TextSink`], [`BinarySink`] and
+/// [`TraceBuffer`] are built in.
+pub trait TraceSink: Send {
+    /// Takes the record of the instruction about to execute. An error stops
+    /// the trace and is kept for [`Vm::take_trace_error`].
+    fn record(&mut self, record: &TraceRecord) -> io::Result<()>;
+
+    /// Called once when the trace stops, for anything left to write.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes each record's [`Display`](fmt::Display) form as one line.
+///
+/// Addresses are named from the sink's own symbol table, given with
+/// [`TextSink::with_symbols`], or else from the
+/// [machine's](Vm::set_symbols), [labels](Vm::label) included, while it is
+/// the machine's trace.
+pub struct TextSink {
+    writer: Box<dyn Write + Send>,
+    symbols: Option<SymbolTable>,
+}
+
+impl TextSink {
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            writer: Box::new(writer),
+            symbols: None,
+        }
+    }
+
+    /// Names addresses from `symbols`, as [`TraceRecord::with_symbols`]
+    /// does.
+    pub fn with_symbols(mut self, symbols: SymbolTable) -> Self {
+        self.symbols = Some(symbols);
+        self
+    }
+}
+
+impl TextSink {
+    /// Writes `record`'s line, naming addresses from `machine` unless the
+    /// sink has a table of its own.
+    fn write(&mut self, record: &TraceRecord, machine: Option<&SymbolTable>) -> io::Result<()> {
+        match self.symbols.as_ref().or(machine) {
+            Some(symbols) => writeln!(self.writer, "{}", record.with_symbols(symbols)),
+            None => writeln!(self.writer, "{record}"),
+        }
+    }
+}
+
+impl TraceSink for TextSink {
+    fn record(&mut self, record: &TraceRecord) -> io::Result<()> {
+        self.write(record, None)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Writes the records in a dense binary form, a byte or three for most
+/// instructions against some forty for a text line; [`TraceReader`] reads
+/// them back.
+///
+/// The stream starts with [`BinarySink::MAGIC`]. Each record then holds
+/// only what changed since the one before: a header byte saying which
+/// parts follow, the PC unless it is the address after the previous
+/// instruction, the instruction bytes unless they are the ones last seen
+/// at that PC, each register that changed and the cycles since the
+/// previous record as a LEB128 number. Bytes after the instruction are
+/// not kept, and read back as zero.
+pub struct BinarySink {
+    writer: Box<dyn Write + Send>,
+    delta: Option<Delta>,
+    bytes: Vec<u8>,
+}
+
+impl BinarySink {
+    pub const MAGIC: [u8; 8] = *b"RVM8TRC\x02";
+
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            writer: Box::new(writer),
+            delta: None,
+            bytes: Vec::with_capacity(16),
+        }
+    }
+}
+
+impl TraceSink for BinarySink {
+    fn record(&mut self, record: &TraceRecord) -> io::Result<()> {
+        let delta = match &mut self.delta {
+            Some(delta) => delta,
+            None => {
+                self.writer.write_all(&BinarySink::MAGIC)?;
+                self.delta.insert(Delta::new())
+            }
+        };
+        self.bytes.clear();
+        delta.encode(record, &mut self.bytes);
+        self.writer.write_all(&self.bytes)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Header bits of a binary record.
+const PC_NEXT: u8 = 0b00;
+const PC_NEAR: u8 = 0b01;
+const PC_FAR: u8 = 0b10;
+const PC_MASK: u8 = 0b11;
+const NEW_CODE: u8 = 1 << 2;
+/// A, X, Y and P, in that order.
+const REGISTER_CHANGED: [u8; 4] = [1 << 3, 1 << 4, 1 << 5, 1 << 6];
+const SP_CHANGED: u8 = 1 << 7;
+
+/// What a binary record is relative to, kept alike by both ends.
+struct Delta {
+    next_pc: u16,
+    /// A, X, Y and P.
+    registers: [u8; 4],
+    sp: u16,
+    cycles: u32,
+    /// The instruction bytes last seen at each address.
+    code: Vec<Option<[u8; 3]>>,
+}
+
+impl Delta {
+    fn new() -> Self {
+        Self {
+            next_pc: 0,
+            registers: [0; 4],
+            sp: 0,
+            cycles: 0,
+            code: vec![None; 0x10000],
+        }
+    }
+
+    fn encode(&mut self, record: &TraceRecord, out: &mut Vec<u8>) {
+        let Registers {
+            a, x, y, sp, flags, ..
+        } = record.registers;
+        let registers = [a, x, y, flags];
+        let size = usize::from(record.instruction.size);
+        let mut code = [0; 3];
+        code[..size].copy_from_slice(&record.bytes[..size]);
+
+        let offset = record.pc.wrapping_sub(self.next_pc) as i16;
+        let mut header = match i8::try_from(offset) {
+            Ok(0) => PC_NEXT,
+            Ok(_) => PC_NEAR,
+            Err(_) => PC_FAR,
+        };
+        let new_code = self.code[usize::from(record.pc)] != Some(code);
+        if new_code {
+            header |= NEW_CODE;
+        }
+        for (i, bit) in REGISTER_CHANGED.into_iter().enumerate() {
+            if registers[i] != self.registers[i] {
+                header |= bit;
+            }
+        }
+        if sp != self.sp {
+            header |= SP_CHANGED;
+        }
+
+        out.push(header);
+        match header & PC_MASK {
+            PC_NEAR => out.push(offset as u8),
+            PC_FAR => out.extend_from_slice(&record.pc.to_le_bytes()),
+            _ => {}
+        }
+        if new_code {
+            out.extend_from_slice(&code[..size]);
+        }
+        for (i, bit) in REGISTER_CHANGED.into_iter().enumerate() {
+            if header & bit != 0 {
+                out.push(registers[i]);
+            }
+        }
+        if header & SP_CHANGED != 0 {
+            out.extend_from_slice(&sp.to_le_bytes());
+        }
+        let mut cycles = record.cycles.wrapping_sub(self.cycles);
+        loop {
+            let byte = (cycles & 0x7F) as u8;
+            cycles >>= 7;
+            if cycles == 0 {
+                out.push(byte);
+                break;
+            }
+            out.push(byte | 0x80);
+        }
+
+        self.advance(record.pc, code, registers, sp, record.cycles);
+    }
+
+    /// Reads the record after `header`, or `None` if the stream ends first.
+    fn decode(&mut self, header: u8, input: &mut impl Read) -> io::Result<Option<TraceRecord>> {
+        let mut byte = || -> io::Result<Option<u8>> {
+            let mut byte = [0];
+            match input.read_exact(&mut byte) {
+                Ok(()) => Ok(Some(byte[0])),
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+                Err(err) => Err(err),
+            }
+        };
+        macro_rules! next {
+            () => {
+                match byte()? {
+                    Some(byte) => byte,
+                    None => return Ok(None),
+                }
+            };
+        }
+
+        let pc = match header & PC_MASK {
+            PC_NEXT => self.next_pc,
+            PC_NEAR => self.next_pc.wrapping_add_signed(i16::from(next!() as i8)),
+            PC_FAR => u16::from_le_bytes([next!(), next!()]),
+            _ => return Err(invalid("bad PC encoding")),
+        };
+        let code = if header & NEW_CODE != 0 {
+            let mut code = [next!(), 0, 0];
+            let size = usize::from(disasm::decode(&code).expect("an opcode is there").size);
+            for byte in &mut code[1..size] {
+                *byte = next!();
+            }
+            code
+        } else {
+            self.code[usize::from(pc)].ok_or_else(|| invalid("no instruction seen at this PC"))?
+        };
+        let mut registers = self.registers;
+        for (i, bit) in REGISTER_CHANGED.into_iter().enumerate() {
+            if header & bit != 0 {
+                registers[i] = next!();
+            }
+        }
+        let sp = if header & SP_CHANGED != 0 {
+            u16::from_le_bytes([next!(), next!()])
+        } else {
+            self.sp
+        };
+        let mut elapsed = 0u32;
+        for shift in (0..35).step_by(7) {
+            let byte = next!();
+            elapsed |= u32::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            if shift == 28 {
+                return Err(invalid("cycle count too long"));
+            }
+        }
+        let cycles = self.cycles.wrapping_add(elapsed);
+
+        self.advance(pc, code, registers, sp, cycles);
+        let [a, x, y, flags] = registers;
+        Ok(Some(TraceRecord {
+            pc,
+            bytes: code,
+            instruction: disasm::decode(&code).expect("three bytes hold any instruction"),
+            registers: Registers {
+                a,
+                x,
+                y,
+                pc,
+                sp,
+                flags,
+            },
+            cycles,
+        }))
+    }
+
+    fn advance(&mut self, pc: u16, code: [u8; 3], registers: [u8; 4], sp: u16, cycles: u32) {
+        let size = disasm::decode(&code)
+            .expect("three bytes hold any instruction")
+            .size;
+        self.next_pc = pc.wrapping_add(u16::from(size));
+        self.code[usize::from(pc)] = Some(code);
+        self.registers = registers;
+        self.sp = sp;
+        self.cycles = cycles;
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Why a binary trace could not be read.
+#[derive(Debug)]
+pub enum TraceReadError {
+    Io(io::Error),
+    /// The data does not start with [`BinarySink::MAGIC`].
+    BadMagic,
+    /// The stream ends in the middle of a record.
+    Truncated,
+    /// A record does not decode, after `records` that did.
+    Corrupt {
+        records: u64,
+    },
+}
+
+impl fmt::Display for TraceReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "cannot read trace: {err}"),
+            Self::BadMagic => write!(f, "not an rvm8 binary trace"),
+            Self::Truncated => write!(f, "trace ends in the middle of a record"),
+            Self::Corrupt { records } => write!(f, "trace record {records} is corrupt"),
+        }
+    }
+}
+
+impl std::error::Error for TraceReadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for TraceReadError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// Reads back the records a [`BinarySink`] wrote, in order:
+///
+/// ```no_run
+/// # use std::{fs::File, io::BufReader};
+/// # use emulator::trace::TraceReader;
+/// let file = BufReader::new(File::open("run.trace").unwrap());
+/// for record in TraceReader::new(file).unwrap() {
+///     let record = record.unwrap();
+///     if record.instruction.mnemonic == "SEI" {
+///         println!("{record}");
+///     }
+/// }
+/// ```
+///
+/// The reader is not buffered here; wrap files in a
+/// [`BufReader`](io::BufReader). Iteration ends after the first error.
+pub struct TraceReader<R> {
+    input: R,
+    delta: Delta,
+    records: u64,
+    done: bool,
+}
+
+impl<R: Read> TraceReader<R> {
+    /// Checks that `input` starts with [`BinarySink::MAGIC`].
+    ///
+    /// An empty input is an empty trace, since a [`BinarySink`] writes
+    /// nothing until the first record.
+    pub fn new(mut input: R) -> Result<Self, TraceReadError> {
+        let mut magic = [0; 8];
+        let mut read = 0;
+        while read < magic.len() {
+            match input.read(&mut magic[read..]) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        if read > 0 && magic != BinarySink::MAGIC {
+            return Err(TraceReadError::BadMagic);
+        }
+        Ok(Self {
+            input,
+            delta: Delta::new(),
+            records: 0,
+            done: read == 0,
+        })
+    }
+
+    fn read_record(&mut self) -> Result<Option<TraceRecord>, TraceReadError> {
+        let mut header = [0];
+        match self.input.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+        match self.delta.decode(header[0], &mut self.input) {
+            Ok(Some(record)) => {
+                self.records += 1;
+                Ok(Some(record))
+            }
+            Ok(None) => Err(TraceReadError::Truncated),
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => Err(TraceReadError::Corrupt {
+                records: self.records,
+            }),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = Result<TraceRecord, TraceReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = self.read_record().transpose();
+        self.done = !matches!(result, Some(Ok(_)));
+        result
+    }
+}
+
+/// Keeps the last records in memory, to look at once something went wrong.
+///
+/// Clones share the records, so keep one and give the machine another:
+///
+/// ```
+/// # use emulator::{trace::TraceBuffer, ResetKind, TraceConfig, Vm};
+/// let buffer = TraceBuffer::new(1000);
+/// let mut vm = Vm::new();
+/// vm.load(0x8000, &[0xA9, 0x01]).unwrap(); // LDA #$01
+/// vm.load(0xFFFC, &[0x00, 0x80]).unwrap();
+/// vm.reset(ResetKind::Soft);
+/// vm.set_trace(TraceConfig::sink(buffer.clone()));
+/// vm.step().unwrap();
+/// assert_eq!(buffer.records()[0].pc, 0x8000);
+/// ```
+#[derive(Debug, Clone)]
+pub struct TraceBuffer {
+    records: Arc<Mutex<VecDeque<TraceRecord>>>,
+    capacity: usize,
+}
+
+impl TraceBuffer {
+    /// A buffer keeping the last `capacity` records.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    /// The kept records, oldest first.
+    pub fn records(&self) -> Vec<TraceRecord> {
+        self.lock().iter().copied().collect()
+    }
+
+    /// Takes the kept records, oldest first, leaving the buffer empty.
+    pub fn take(&self) -> Vec<TraceRecord> {
+        self.lock().drain(..).collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, VecDeque<TraceRecord>> {
+        self.records.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl TraceSink for TraceBuffer {
+    fn record(&mut self, record: &TraceRecord) -> io::Result<()> {
+        if self.capacity == 0 {
+            return Ok(());
+        }
+        let mut records = self.lock();
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        records.push_back(*record);
+        Ok(())
+    }
+}
+
+struct Callback<F>(F);
+
+impl<F: FnMut(&TraceRecord) + Send> TraceSink for Callback<F> {
+    fn record(&mut self, record: &TraceRecord) -> io::Result<()> {
+        (self.0)(record);
+        Ok(())
+    }
+}
+
+/// Where a trace goes, for [`Vm::set_trace`]: any number of
+/// [record sinks](TraceSink), a [Chrome timeline](TraceConfig::chrome)
+/// and a [bus waveform](TraceConfig::vcd), combined with
+/// [`TraceConfig::and`]:
+///
+/// ```
+/// # use emulator::{trace::{BinarySink, TraceBuffer}, TraceConfig};
+/// let buffer = TraceBuffer::new(1000);
+/// let config = TraceConfig::sink(BinarySink::new(std::io::sink()))
+///     .and(TraceConfig::sink(buffer.clone()))
+///     .and(TraceConfig::chrome(std::io::sink()));
+/// ```
+pub struct TraceConfig {
+    records: Vec<Records>,
+    pub(crate) chrome: Option<Box<ChromeTrace>>,
+    pub(crate) vcd: Option<Box<VcdTrace>>,
+}
+
+enum Records {
+    /// Kept apart so [`TraceConfig::with_symbols`] can reach it.
+    Text(TextSink),
+    Sink(Box<dyn TraceSink>),
+}
+
+impl Records {
+    fn sink(&mut self) -> &mut dyn TraceSink {
+        match self {
+            Self::Text(text) => text,
+            Self::Sink(sink) => sink.as_mut(),
+        }
+    }
+
+    /// Passes on a record of a machine whose symbol table is `symbols`.
+    fn record(&mut self, record: &TraceRecord, symbols: Option<&SymbolTable>) -> io::Result<()> {
+        match self {
+            Self::Text(text) => text.write(record, symbols),
+            Self::Sink(sink) => sink.record(record),
+        }
+    }
+}
+
+impl TraceConfig {
+    /// A config with no sinks, for the constructors to fill in.
+    pub(crate) fn empty() -> Self {
+        Self {
+            records: Vec::new(),
+            chrome: None,
+            vcd: None,
+        }
+    }
+
+    /// Passes every record to `sink`.
+    pub fn sink(sink: impl TraceSink + 'static) -> Self {
+        Self {
+            records: vec![Records::Sink(Box::new(sink))],
+            ..Self::empty()
+        }
+    }
+
+    /// Writes each record's [`Display`](fmt::Display) form as one line; see
+    /// [`TextSink`].
+    pub fn writer(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            records: vec![Records::Text(TextSink::new(writer))],
+            ..Self::empty()
+        }
+    }
+
+    /// Calls `callback` with each record.
+    pub fn callback(callback: impl FnMut(&TraceRecord) + Send + 'static) -> Self {
+        Self::sink(Callback(callback))
+    }
+
+    /// Names addresses from `symbols` in the lines the
+    /// [writers](TraceConfig::writer) write, as [`TraceRecord::with_symbols`]
+    /// does, instead of from the machine's table. Other sinks get plain
+    /// records.
+    pub fn with_symbols(mut self, symbols: SymbolTable) -> Self {
+        for records in &mut self.records {
+            if let Records::Text(text) = records {
+                text.symbols = Some(symbols.clone());
+            }
+        }
+        self
+    }
+
+    /// Sends the trace to `other`'s sinks as well. A trace writes one
+    /// Chrome timeline and one VCD at most, so those of `other` replace
+    /// these.
+    pub fn and(mut self, other: TraceConfig) -> Self {
+        self.records.extend(other.records);
+        self.chrome = other.chrome.or(self.chrome);
+        self.vcd = other.vcd.or(self.vcd);
+        self
+    }
+
+    /// Finishes every sink, returning the first error.
+    fn finish(self, clock_hz: u32) -> io::Result<()> {
+        let mut result = Ok(());
+        for mut records in self.records {
+            result = result.and(records.sink().finish());
+        }
+        if let Some(mut chrome) = self.chrome {
+            result = result.and(chrome.finish());
+        }
+        if let Some(mut vcd) = self.vcd {
+            result = result.and(vcd.finish(clock_hz));
+        }
+        result
+    }
+}
+
+impl fmt::Debug for TraceConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sinks: Vec<&str> = (self.records.iter())
+            .map(|records| match records {
+                Records::Text(_) => "text",
+                Records::Sink(_) => "sink",
+            })
+            .chain(self.chrome.as_ref().map(|_| "chrome"))
+            .chain(self.vcd.as_ref().map(|_| "vcd"))
+            .collect();
+        f.debug_struct("TraceConfig")
+            .field("sinks", &sinks)
+            .finish()
+    }
+}
+
+/// Host-side trace state.
+#[derive(Default)]
+pub(crate) struct Tracer {
+    pub(crate) config: Option<TraceConfig>,
+    pub(crate) error: Option<io::Error>,
+}
+
+impl Vm {
+    /// Starts sending the trace to `config`'s sinks, replacing any previous
+    /// trace.
+    ///
+    /// Frames replayed by [`Vm::rewind`] are not traced again.
+    pub fn set_trace(&mut self, config: TraceConfig) {
+        self.clear_trace();
+        let bus = self.bus_mut();
+        bus.device_log = config.chrome.is_some().then(Vec::new);
+        if config.vcd.is_some() {
+            bus.access_log = Some(Vec::new());
+            bus.pages_dirty = true;
+        }
+        self.tracer.config = Some(config);
+    }
+
+    /// Stops tracing and drops the sinks, finishing each first: flushing
+    /// writers and closing a [Chrome trace](TraceConfig::chrome) or
+    /// [VCD](TraceConfig::vcd).
+    pub fn clear_trace(&mut self) {
+        if let Err(err) = self.stop_trace() {
+            self.tracer.error = Some(err);
+        }
+    }
+
+    /// Takes the write error that stopped the last trace, if any.
+    pub fn take_trace_error(&mut self) -> Option<io::Error> {
+        self.tracer.error.take()
+    }
+
+    /// Stops the trace with `err`, finishing what can be finished.
+    pub(crate) fn fail_trace(&mut self, err: io::Error) {
+        let _ = self.stop_trace();
+        self.tracer.error = Some(err);
+    }
+
+    fn stop_trace(&mut self) -> io::Result<()> {
+        let bus = self.bus_mut();
+        bus.device_log = None;
+        if bus.access_log.take().is_some() {
+            bus.pages_dirty = true;
+        }
+        match self.tracer.config.take() {
+            Some(config) => config.finish(self.clock_hz),
+            None => Ok(()),
+        }
+    }
+
+    /// Emits the record for the instruction about to execute at the PC.
+    pub(crate) fn trace_instruction(&mut self) {
+        if self
+            .tracer
+            .config
+            .as_ref()
+            .is_none_or(|config| config.records.is_empty())
+        {
+            return;
+        }
+        let pc = self.cpu.pc;
+        let record = TraceRecord {
+            pc,
+            bytes: [0, 1, 2].map(|i| self.read(pc.wrapping_add(i))),
+            instruction: self.disassemble(pc),
+            registers: self.registers(),
+            cycles: self.cpu.cycles,
+        };
+        let Some(config) = self.tracer.config.as_mut() else {
+            return;
+        };
+        let symbols = self.symbols.as_ref();
+        let result =
+            (config.records.iter_mut()).try_for_each(|records| records.record(&record, symbols));
+        if let Err(err) = result {
+            self.fail_trace(err);
+        }
+    }
+}
@@ -0,0 +1,226 @@
+//! Serial port.
+//!
+//! A [`Uart`] gives programs a byte-wide console. Transmitted bytes go to a
+//! host [`Write`], and received bytes come from a host [`Read`] pumped by a
+//! background thread so a slow source never stalls emulation. A UART with
+//! nothing connected buffers both directions for the host to drive with
+//! [`Uart::push_rx`] and [`Uart::take_tx`]. It is not mapped by default:
+//!
+//! ```
+//! # use emulator::{uart::{Uart, UART_PORTS}, Vm};
+//! let mut vm = Vm::new();
+//! vm.bus_mut().map(UART_PORTS, Uart::stdio()).unwrap();
+//! ```
+//!
+//! | Offset | Register                                                |
+//! | ------ | ------------------------------------------------------- |
+//! | 0      | `DATA`: reading takes the next received byte (0 if none), writing sends one |
+//! | 1      | `STATUS`: [`STATUS_RX_READY`], [`STATUS_TX_READY`]      |
+//! | 2      | `CTRL`: [`CTRL_RX_IRQ`]                                 |
+//!
+//! When [`CTRL_RX_IRQ`] is set the UART holds interrupt line [`UART_IRQ`]
+//! while received data is waiting.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::ops::RangeInclusive;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use crate::bus::BusDevice;
+use crate::reset::ResetKind;
+
+/// Suggested place for the UART registers.
+pub const UART_PORTS: RangeInclusive<u16> = 0x2720..=0x2727;
+/// Interrupt line raised while received data is waiting.
+pub const UART_IRQ: u8 = 3;
+
+/// `STATUS` bit set while a received byte is waiting.
+pub const STATUS_RX_READY: u8 = 1 << 0;
+/// `STATUS` bit set while `DATA` accepts a byte to send; always set.
+pub const STATUS_TX_READY: u8 = 1 << 1;
+/// `CTRL` bit enabling the receive interrupt.
+pub const CTRL_RX_IRQ: u8 = 1 << 0;
+
+const DATA: u16 = 0;
+const STATUS: u16 = 1;
+const CTRL: u16 = 2;
+
+/// The serial port device.
+#[derive(Default)]
+pub struct Uart {
+    rx: VecDeque<u8>,
+    /// Bytes from the pump thread not yet moved into `rx`.
+    input: Option<Receiver<u8>>,
+    tx: Vec<u8>,
+    output: Option<Box<dyn Write + Send>>,
+    ctrl: u8,
+    error: Option<io::Error>,
+}
+
+impl Uart {
+    /// A UART with nothing connected.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A UART receiving from `reader` and sending to `writer`.
+    ///
+    /// `reader` is read on its own thread until it reports end of file or an
+    /// error. `writer` is flushed after every byte.
+    pub fn connect(
+        reader: impl Read + Send + 'static,
+        writer: impl Write + Send + 'static,
+    ) -> Self {
+        let (send, recv) = mpsc::channel();
+        thread::spawn(move || {
+            let mut reader = reader;
+            let mut buf = [0; 256];
+            loop {
+                let n = match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => break,
+                };
+                // Stop once the UART is dropped.
+                if buf[..n].iter().any(|&byte| send.send(byte).is_err()) {
+                    break;
+                }
+            }
+        });
+        Self {
+            input: Some(recv),
+            output: Some(Box::new(writer)),
+            ..Self::default()
+        }
+    }
+
+    /// A UART on the process's stdin and stdout.
+    pub fn stdio() -> Self {
+        Self::connect(io::stdin(), io::stdout())
+    }
+
+    /// A UART on both directions of a TCP connection.
+    pub fn tcp(stream: TcpStream) -> io::Result<Self> {
+        Ok(Self::connect(stream.try_clone()?, stream))
+    }
+
+    /// Queues bytes as if they had been received.
+    pub fn push_rx(&mut self, bytes: &[u8]) {
+        self.rx.extend(bytes);
+    }
+
+    /// Takes the bytes sent while no writer was connected.
+    pub fn take_tx(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.tx)
+    }
+
+    /// Takes the write error that disconnected the writer, if any. Bytes
+    /// sent after it are kept for [`Uart::take_tx`].
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    /// Moves bytes delivered by the pump thread into the receive queue.
+    fn poll_input(&mut self) {
+        if let Some(input) = &self.input {
+            self.rx.extend(input.try_iter());
+        }
+    }
+
+    fn send(&mut self, val: u8) {
+        let Some(output) = &mut self.output else {
+            self.tx.push(val);
+            return;
+        };
+        if let Err(err) = output.write_all(&[val]).and_then(|()| output.flush()) {
+            self.output = None;
+            self.error = Some(err);
+        }
+    }
+}
+
+impl fmt::Debug for Uart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Uart")
+            .field("rx", &self.rx)
+            .field("tx", &self.tx)
+            .field("connected", &self.output.is_some())
+            .field("ctrl", &self.ctrl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl BusDevice for Uart {
+    fn read8(&mut self, offset: u16) -> u8 {
+        match offset {
+            DATA => {
+                self.poll_input();
+                self.rx.pop_front().unwrap_or(0)
+            }
+            STATUS => {
+                self.poll_input();
+                let rx = if self.rx.is_empty() {
+                    0
+                } else {
+                    STATUS_RX_READY
+                };
+                rx | STATUS_TX_READY
+            }
+            CTRL => self.ctrl,
+            _ => 0,
+        }
+    }
+
+    fn write8(&mut self, offset: u16, val: u8) {
+        match offset {
+            DATA => self.send(val),
+            CTRL => self.ctrl = val,
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, _: u32) {
+        self.poll_input();
+    }
+
+    /// Unbounded: host input arriving during a batch is picked up after it.
+    fn batch_cycles(&self) -> u32 {
+        u32::MAX
+    }
+
+    /// Clears the control register, and on a hard reset the bytes received
+    /// and not yet read. The host streams stay connected.
+    fn reset(&mut self, kind: ResetKind) {
+        self.ctrl = 0;
+        if kind == ResetKind::Hard {
+            self.rx.clear();
+        }
+    }
+
+    /// The control register and the bytes received and not yet read. The
+    /// host streams and the bytes sent are the host's.
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.push(self.ctrl);
+        state.extend(&self.rx);
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        let Some((&ctrl, rx)) = state.split_first() else {
+            return;
+        };
+        self.ctrl = ctrl;
+        self.rx = rx.iter().copied().collect();
+    }
+
+    fn irq_lines(&self) -> u8 {
+        if self.ctrl & CTRL_RX_IRQ != 0 && !self.rx.is_empty() {
+            1 << UART_IRQ
+        } else {
+            0
+        }
+    }
+}
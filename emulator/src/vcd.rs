@@ -0,0 +1,228 @@
+//! VCD waveform export.
+//!
+//! [`TraceConfig::vcd`] makes a trace write the CPU's bus as a Value
+//! Change Dump, the waveform format GTKWave and most HDL simulators read.
+//! Instead of one record per instruction it dumps these signals in module
+//! `rvm8`:
+//!
+//! | Signal      | Width | Meaning                                         |
+//! | ----------- | ----- | ----------------------------------------------- |
+//! | `addr`      | 16    | address of the current access                   |
+//! | `data`      | 8     | byte read or written                            |
+//! | `rd`        | 1     | high for each cycle the CPU reads               |
+//! | `wr`        | 1     | high for each cycle the CPU writes              |
+//! | `irq`       | 8     | pending interrupt lines, one bit per line       |
+//!
+//! The kernel does not time accesses inside an instruction, so the dump
+//! puts an instruction's accesses on consecutive cycles from its first,
+//! which is exactly right in [cycle-accurate](crate::TimingMode) mode and
+//! the order they happened in otherwise. `addr` and `data` hold their last
+//! value while the strobes are low. Accesses the CPU does not make, such
+//! as DMA and host reads, are not on the bus.
+//!
+//! Time is emulated time in nanoseconds at the [clock rate](Vm::clock_hz).
+//! It never goes backwards; a reset or restored state just continues the
+//! dump. Tracing every access makes the kernel route all of memory through
+//! the host, so a VCD trace is slower than a text one.
+
+use std::io::{self, Write};
+
+use crate::bus::WatchHit;
+use crate::ffi::BusAccess;
+use crate::trace::TraceConfig;
+use crate::vm::Vm;
+
+/// Values of the dumped signals.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Signals {
+    addr: u16,
+    data: u8,
+    rd: bool,
+    wr: bool,
+    irq: u8,
+}
+
+/// A value change dump being written.
+pub(crate) struct VcdTrace {
+    writer: Box<dyn Write + Send>,
+    started: bool,
+    /// Cycles elapsed on the dump's timeline.
+    time: u64,
+    /// Cycle counter when `time` was last advanced.
+    last: u32,
+    /// Time of the last timestamp written, in nanoseconds.
+    stamp: u64,
+    /// The signals as last written.
+    signals: Signals,
+    /// The latest change and its cycle, held back until a later cycle
+    /// changes them again so that one cycle gets one timestamp.
+    pending: Option<(u64, Signals)>,
+}
+
+impl VcdTrace {
+    pub(crate) fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            writer,
+            started: false,
+            time: 0,
+            last: 0,
+            stamp: 0,
+            signals: Signals::default(),
+            pending: None,
+        }
+    }
+
+    /// Moves the timeline to the cycle counter `cycles`, ignoring jumps
+    /// backwards.
+    fn advance(&mut self, cycles: u32) -> u64 {
+        let elapsed = cycles.wrapping_sub(self.last);
+        if (elapsed as i32) > 0 {
+            self.time += u64::from(elapsed);
+        }
+        self.last = cycles;
+        self.time
+    }
+
+    /// Declares the signals and dumps their initial values.
+    fn start(&mut self) -> io::Result<()> {
+        self.started = true;
+        write!(
+            self.writer,
+            "$version rvm-8 $end\n\
+             $timescale 1ns $end\n\
+             $scope module rvm8 $end\n\
+             $var wire 16 a addr $end\n\
+             $var wire 8 d data $end\n\
+             $var wire 1 r rd $end\n\
+             $var wire 1 w wr $end\n\
+             $var wire 8 i irq $end\n\
+             $upscope $end\n\
+             $enddefinitions $end\n\
+             #0\n\
+             $dumpvars\n\
+             b0 a\n\
+             b0 d\n\
+             0r\n\
+             0w\n\
+             b0 i\n\
+             $end\n"
+        )
+    }
+
+    /// Sets the signals to `to` from `cycle` on.
+    fn change(&mut self, cycle: u64, to: Signals, clock_hz: u32) -> io::Result<()> {
+        if let Some((at, signals)) = self.pending
+            && at < cycle
+        {
+            self.write(at, signals, clock_hz)?;
+        }
+        self.pending = Some((cycle, to));
+        Ok(())
+    }
+
+    /// Writes the signals that differ from `to`, stamped with `cycle`.
+    fn write(&mut self, cycle: u64, to: Signals, clock_hz: u32) -> io::Result<()> {
+        let from = std::mem::replace(&mut self.signals, to);
+        if from == to {
+            return Ok(());
+        }
+        let ns = (u128::from(cycle) * 1_000_000_000 / u128::from(clock_hz)) as u64;
+        if ns > self.stamp {
+            self.stamp = ns;
+            writeln!(self.writer, "#{ns}")?;
+        }
+        if from.addr != to.addr {
+            writeln!(self.writer, "b{:b} a", to.addr)?;
+        }
+        if from.data != to.data {
+            writeln!(self.writer, "b{:b} d", to.data)?;
+        }
+        if from.rd != to.rd {
+            writeln!(self.writer, "{}r", u8::from(to.rd))?;
+        }
+        if from.wr != to.wr {
+            writeln!(self.writer, "{}w", u8::from(to.wr))?;
+        }
+        if from.irq != to.irq {
+            writeln!(self.writer, "b{:b} i", to.irq)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the change held back, or just the header if nothing was
+    /// dumped.
+    pub(crate) fn finish(&mut self, clock_hz: u32) -> io::Result<()> {
+        if !self.started {
+            self.start()?;
+        }
+        if let Some((at, signals)) = self.pending.take() {
+            self.write(at, signals, clock_hz)?;
+        }
+        self.writer.flush()
+    }
+}
+
+impl TraceConfig {
+    /// Writes the CPU's bus signals as a Value Change Dump instead of
+    /// instruction records; see [`crate::vcd`].
+    pub fn vcd(writer: impl Write + Send + 'static) -> Self {
+        let mut config = Self::empty();
+        config.vcd = Some(Box::new(VcdTrace::new(Box::new(writer))));
+        config
+    }
+}
+
+impl Vm {
+    /// Dumps the instruction that started at `cycles`, with `irqs` pending
+    /// then, and the accesses it made.
+    pub(crate) fn vcd_instruction(&mut self, cycles: u32, irqs: u8) {
+        let Some(log) = self.bus_mut().access_log.as_mut() else {
+            return;
+        };
+        let accesses: Vec<WatchHit> = std::mem::take(log);
+        let clock_hz = self.clock_hz;
+        let Some(TraceConfig { vcd: Some(vcd), .. }) = &mut self.tracer.config else {
+            return;
+        };
+        let result = (|| {
+            if !vcd.started {
+                vcd.start()?;
+            }
+            // Never before the end of the previous instruction's accesses.
+            let start = vcd.advance(cycles).max(vcd.pending.map_or(0, |(at, _)| at));
+            let mut signals = Signals {
+                rd: false,
+                wr: false,
+                irq: irqs,
+                ..vcd.pending.map_or(vcd.signals, |(_, signals)| signals)
+            };
+            vcd.change(start, signals, clock_hz)?;
+            for (cycle, access) in (start..).zip(&accesses) {
+                signals = Signals {
+                    addr: access.addr,
+                    data: access.val,
+                    rd: access.kind == BusAccess::Read,
+                    wr: access.kind == BusAccess::Write,
+                    irq: irqs,
+                };
+                vcd.change(cycle, signals, clock_hz)?;
+            }
+            if !accesses.is_empty() {
+                let end = start + accesses.len() as u64;
+                signals.rd = false;
+                signals.wr = false;
+                vcd.change(end, signals, clock_hz)?;
+            }
+            Ok(())
+        })();
+        match result {
+            Ok(()) => {
+                if let Some(log) = self.bus_mut().access_log.as_mut() {
+                    *log = accesses;
+                    log.clear();
+                }
+            }
+            Err(err) => self.fail_trace(err),
+        }
+    }
+}
@@ -0,0 +1,760 @@
+//! Safe, owning wrapper around the kernel CPU.
+
+use std::ops::RangeInclusive;
+use std::ptr::{self, NonNull};
+
+use crate::audio::Audio;
+use crate::backend::{Backend, kernel};
+use crate::bus::{self, Bus, RomWrite};
+use crate::coverage::Coverage;
+use crate::debugger::Breakpoints;
+use crate::display::Display;
+use crate::error::VmError;
+use crate::events::{Event, EventQueue};
+use crate::extension;
+use crate::ffi::{
+    self, Cpu, ILLEGAL_NOP, ILLEGAL_TRAP, ILLEGAL_UNDOCUMENTED, RVM_ILLEGAL_OPCODE, RVM_MEM_SIZE,
+    RVM_OK,
+};
+use crate::history::History;
+use crate::hooks::Hooks;
+use crate::hostcall::Hostcalls;
+use crate::input::{Controller, INPUT_PORTS};
+use crate::irq::IrqState;
+use crate::paged::PageCache;
+use crate::patches::Patch;
+use crate::profile::Profile;
+use crate::replay::InputLog;
+use crate::reset::ResetKind;
+use crate::rewind::RewindBuffer;
+use crate::rom::RomInfo;
+use crate::scheduler::Scheduler;
+use crate::screenshot::Dumper;
+use crate::sram::Sram;
+use crate::symbols::SymbolTable;
+use crate::trace::Tracer;
+use crate::watches::Watches;
+
+/// Nominal CPU clock in cycles per second, until [`Vm::set_clock_hz`].
+pub const CLOCK_HZ: u32 = 1_000_000;
+/// Display refresh rate; one frame is the unit of [`Vm::run_frame`].
+pub const FRAME_RATE: u32 = 60;
+/// CPU cycles in one frame at [`CLOCK_HZ`].
+pub const CYCLES_PER_FRAME: u32 = CLOCK_HZ / FRAME_RATE;
+
+/// Snapshot of the programmer-visible CPU registers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Registers {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub pc: u16,
+    pub sp: u16,
+    pub flags: u8,
+}
+
+/// What the CPU does with an opcode that is neither an instruction nor a
+/// registered [extension].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum IllegalOpcodes {
+    /// Skips the opcode byte and fails the step with
+    /// [`VmError::IllegalOpcode`].
+    #[default]
+    Trap,
+    /// Executes it as a one-byte NOP taking 2 cycles.
+    Nop,
+    /// Executes what the NMOS 6502 does, for the undocumented opcodes that
+    /// only combine instructions rvm-8 has (the multi-byte NOPs, LAX and
+    /// the SBC at 0xEB, listed in
+    /// [`UNDOCUMENTED`](rvm8_core::UNDOCUMENTED)), and traps on the rest.
+    Undocumented,
+}
+
+/// CPU behavior that test suites and boards disagree on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CpuConfig {
+    /// The clock rate, see [`Vm::set_clock_hz`], or `None` to keep the
+    /// current one.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub clock_hz: Option<u32>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub illegal_opcodes: IllegalOpcodes,
+}
+
+/// An rvm-8 machine: one CPU and the 64 KiB of memory attached to it.
+///
+/// The `Vm` owns both the kernel CPU state and its backing store. The memory
+/// and the [`Bus`] serving the hook are handed to the kernel as raw pointers at
+/// construction and released in `Drop`, so they can never dangle or be freed
+/// while the kernel still uses them.
+///
+/// A `Vm` is [`Send`]: the kernel keeps no state outside the CPU, and
+/// every device, callback and writer a `Vm` takes must be `Send` too, so a
+/// whole machine can move to another thread and many can run in parallel,
+/// one per thread. It is not [`Sync`]; even reading registers goes through
+/// state the kernel may be changing, so share one behind a `Mutex`.
+pub struct Vm {
+    pub(crate) cpu: Box<Cpu>,
+    pub(crate) backend: Backend,
+    bus: NonNull<Bus>,
+    pub(crate) breakpoints: Breakpoints,
+    pub(crate) watches: Watches,
+    pub(crate) frame: u64,
+    /// Cycles from reset to the start of the current frame, unwrapped.
+    pub(crate) frame_cycle: u64,
+    /// Set once the current frame's start-of-frame work has run, until the
+    /// frame ends.
+    pub(crate) frame_started: bool,
+    pub(crate) clock_hz: u32,
+    pub(crate) rewind: Option<RewindBuffer>,
+    pub(crate) display: Display,
+    pub(crate) dumper: Dumper,
+    pub(crate) audio: Audio,
+    pub(crate) tracer: Tracer,
+    pub(crate) hooks: Hooks,
+    pub(crate) irq: IrqState,
+    pub(crate) inputs: InputLog,
+    pub(crate) coverage: Option<Coverage>,
+    pub(crate) history: Option<History>,
+    /// SP values the program may use; see [`crate::stack`].
+    pub(crate) stack_bounds: Option<RangeInclusive<u8>>,
+    pub(crate) sram: Option<Sram>,
+    pub(crate) profile: Option<Profile>,
+    pub(crate) symbols: Option<SymbolTable>,
+    pub(crate) freezes: Vec<Patch>,
+    /// Whether a banked ROM has its bank registers mapped.
+    pub(crate) banked: bool,
+    pub(crate) rom_info: Option<RomInfo>,
+    pub(crate) hostcalls: Option<Hostcalls>,
+    pub(crate) paged: PageCache,
+    pub(crate) events: EventQueue,
+    pub(crate) scheduler: Scheduler,
+    #[cfg(feature = "instrument")]
+    pub(crate) frame_span: tracing::Span,
+    #[cfg(feature = "jit")]
+    pub(crate) jit: Option<Box<crate::jit::Jit>>,
+}
+
+impl Vm {
+    /// Creates a machine with zeroed memory and a [`Controller`] mapped at
+    /// [`INPUT_PORTS`].
+    ///
+    /// The reset vector therefore points at 0x0000; load a program and call
+    /// [`Vm::reset`] to start it at its own entry point.
+    pub fn new() -> Self {
+        let memory = Box::into_raw(vec![0u8; RVM_MEM_SIZE].into_boxed_slice()).cast::<u8>();
+        let mut cpu = Box::new(Cpu::default());
+        // SAFETY: `memory` is a fresh RVM_MEM_SIZE allocation owned by this
+        // `Vm` until `Drop`, which outlives every kernel call made through it.
+        unsafe { ffi::cpu_init(&mut *cpu, memory) };
+
+        let bus = NonNull::from(Box::leak(Box::<Bus>::default()));
+        cpu.bus_hook = Some(bus::trampoline);
+        cpu.bus_ctx = bus.as_ptr().cast();
+        cpu.ext_handler = Some(extension::trampoline);
+        cpu.ext_ctx = bus.as_ptr().cast();
+        // SAFETY: the bus was just leaked and nothing else refers to it.
+        unsafe { (*bus.as_ptr()).clock = &raw const cpu.cycles };
+
+        let mut vm = Self {
+            cpu,
+            backend: Backend::DEFAULT,
+            bus,
+            breakpoints: Breakpoints::new(),
+            watches: Watches::default(),
+            frame: 0,
+            frame_cycle: 0,
+            frame_started: false,
+            clock_hz: CLOCK_HZ,
+            rewind: None,
+            display: Display::default(),
+            dumper: Dumper::default(),
+            audio: Audio::default(),
+            tracer: Tracer::default(),
+            hooks: Hooks::default(),
+            irq: IrqState::default(),
+            inputs: InputLog::default(),
+            coverage: None,
+            history: None,
+            stack_bounds: None,
+            sram: None,
+            profile: None,
+            symbols: None,
+            freezes: Vec::new(),
+            banked: false,
+            rom_info: None,
+            hostcalls: None,
+            paged: PageCache::default(),
+            events: EventQueue::default(),
+            scheduler: Scheduler::default(),
+            #[cfg(feature = "instrument")]
+            frame_span: tracing::Span::none(),
+            #[cfg(feature = "jit")]
+            jit: None,
+        };
+        vm.bus_mut()
+            .map(INPUT_PORTS, Controller::default())
+            .expect("the bus starts empty");
+        vm.reschedule_machine();
+        vm
+    }
+
+    pub(crate) fn reset_machine(&mut self, kind: ResetKind) {
+        self.abandon_frame();
+        self.bus_mut().reset_devices(kind);
+        if kind == ResetKind::Hard {
+            self.clear_ram();
+        }
+        self.switch_banks();
+        // SAFETY: `self.cpu` was initialized by `cpu_init` in `Vm::new`.
+        unsafe { kernel!(self.backend, cpu_reset(&mut *self.cpu)) };
+        self.frame = 0;
+        self.frame_cycle = 0;
+        self.scheduler.rebase(0, self.cpu.cycles);
+        self.reschedule_machine();
+        self.irq = IrqState::default();
+        if let Some(mpu) = self.bus_mut().mpu_mut() {
+            mpu.acknowledge();
+            mpu.set_supervisor(true);
+        }
+        if let Some(rewind) = &mut self.rewind {
+            rewind.clear();
+        }
+    }
+
+    /// Executes a single instruction, ignoring breakpoints.
+    pub fn step(&mut self) -> Result<(), VmError> {
+        #[cfg(feature = "instrument")]
+        let _step = self.step_span().entered();
+        let result = self.execute();
+        #[cfg(feature = "instrument")]
+        if let Err(err) = &result {
+            self.instrument_fault(err);
+        }
+        result
+    }
+
+    fn execute(&mut self) -> Result<(), VmError> {
+        if self.inputs.is_active() {
+            self.advance_inputs();
+        }
+        let bus = self.bus_mut();
+        bus.watch_hit = None;
+        if bus.pages_dirty {
+            self.sync_hook_pages();
+        }
+        if !self.hooks.is_empty() {
+            self.run_pc_hooks();
+            if self.bus().pages_dirty {
+                self.sync_hook_pages();
+            }
+        }
+        self.update_irq_input();
+        let pc = self.cpu.pc;
+        let sp = self.cpu.sp;
+        let interrupt = self.latched_interrupt();
+        let entering_irq = interrupt.is_some();
+        self.mpu_fetch(pc, entering_irq)?;
+        self.trace_instruction();
+        if self.history.is_some() {
+            self.record_history(entering_irq);
+        }
+        let cycles = self.cpu.cycles;
+        let bus = self.bus_mut();
+        bus.begin_instruction(cycles);
+        if let Some(snoop) = &mut bus.snoop {
+            snoop.start = cycles;
+        }
+        let vcd_irqs = self.bus().access_log.is_some().then(|| self.pending_irqs());
+        if self.chrome_tracing() {
+            self.chrome_irq_lines();
+            match interrupt {
+                Some(true) => self.chrome_irq_entry(cycles, "nmi", None),
+                Some(false) => self.chrome_irq_entry(cycles, "irq", self.active_irq()),
+                None => {}
+            }
+        }
+        #[cfg(feature = "instrument")]
+        let interrupt = interrupt.map(|nmi| {
+            let line = if nmi { None } else { self.active_irq() };
+            self.interrupt_span(nmi, line).entered()
+        });
+        // SAFETY: see `Vm::reset`.
+        let status = unsafe { kernel!(self.backend, cpu_step(&mut *self.cpu)) };
+        #[cfg(feature = "instrument")]
+        if let Some(span) = interrupt {
+            span.record("end", self.cpu.cycles);
+        }
+        if self.coverage.is_some() {
+            let executed = status == RVM_OK && !entering_irq;
+            self.record_coverage(executed.then_some(pc));
+        }
+        if let Some(heatmap) = &mut self.bus_mut().heatmap
+            && status == RVM_OK
+            && !entering_irq
+        {
+            heatmap.count_execute(pc);
+        }
+        let elapsed = self.cpu.cycles.wrapping_sub(cycles);
+        if self.profile.is_some() && status == RVM_OK {
+            self.record_profile((!entering_irq).then_some(pc), elapsed);
+        }
+        self.bus_mut().end_instruction(elapsed);
+        if status == RVM_OK && !entering_irq {
+            self.mark_executed(pc);
+        }
+        if !self.bus().accesses.is_empty() {
+            self.run_access_hooks(pc);
+        }
+        if self.bus().device_log.is_some() {
+            self.chrome_device_accesses(cycles);
+        }
+        if let Some(irqs) = vcd_irqs {
+            self.vcd_instruction(cycles, irqs);
+        }
+        self.run_dma();
+        self.switch_banks();
+        self.draw_lines();
+        self.repoll_irq_input();
+        self.take_faults(pc)?;
+        self.check_stack(pc, sp)?;
+        if status == RVM_ILLEGAL_OPCODE {
+            return Err(VmError::IllegalOpcode {
+                pc,
+                opcode: self.fetched_opcode(pc),
+            });
+        }
+        self.run_scheduled();
+        Ok(())
+    }
+
+    /// Runs instructions until at least `cycles` more cycles have elapsed,
+    /// ignoring breakpoints.
+    ///
+    /// When nothing needs to see individual instructions (no trace, hooks,
+    /// coverage, history, profile, heatmap, bus snooping, input recording,
+    /// replay, MPU, stack bounds, ROM write trap, held line interrupt or
+    /// `tracing` subscriber taking DEBUG spans) the kernel runs them in
+    /// batches, crossing into the host only for device accesses and once
+    /// per batch. Batches end after any instruction that accesses a
+    /// device and never outlast a device's
+    /// [`batch_cycles`](crate::BusDevice::batch_cycles), a scanline or the
+    /// next [scheduled event](crate::scheduler), so the result is the same
+    /// as stepping.
+    pub fn run_cycles(&mut self, cycles: u32) -> Result<(), VmError> {
+        self.run_until(self.cpu.cycles.wrapping_add(cycles))
+    }
+
+    /// Runs until the cycle counter reaches `end`, modulo 2^32.
+    pub(crate) fn run_until(&mut self, end: u32) -> Result<(), VmError> {
+        self.run_scheduled();
+        loop {
+            let remaining = end.wrapping_sub(self.cpu.cycles);
+            if remaining as i32 <= 0 {
+                return Ok(());
+            }
+            let observed = self.inputs.is_active()
+                || !self.hooks.is_empty()
+                || self.tracer.config.is_some()
+                || self.coverage.is_some()
+                || self.history.is_some()
+                || self.profile.is_some()
+                || self.bus().mpu.is_some()
+                || self.bus().config().rom_write == RomWrite::Trap
+                || self.stack_bounds.is_some()
+                || self.bus().heatmap.is_some()
+                || self.bus().snoop.is_some()
+                || self.display_irq_lines() != 0
+                || self.instrumented();
+            let budget = remaining
+                .min(self.bus().batch_cycles())
+                .min(self.display_batch_cycles())
+                .min(self.scheduled_batch_cycles());
+            if observed || budget == 0 {
+                self.step()?;
+            } else {
+                self.run_batch(budget)?;
+            }
+        }
+    }
+
+    /// Runs the kernel for `budget` cycles in one call, then does the
+    /// host's end-of-instruction work once for the whole batch.
+    fn run_batch(&mut self, budget: u32) -> Result<(), VmError> {
+        if self.bus().pages_dirty {
+            self.sync_hook_pages();
+        }
+        self.update_irq_input();
+        let cycles = self.cpu.cycles;
+        self.bus_mut().begin_instruction(cycles);
+        #[cfg(feature = "jit")]
+        let status = match &mut self.jit {
+            Some(jit) => jit.run(&mut self.cpu, self.backend, budget),
+            // SAFETY: see `Vm::reset`.
+            None => unsafe { kernel!(self.backend, cpu_run(&mut *self.cpu, budget)) },
+        };
+        #[cfg(not(feature = "jit"))]
+        // SAFETY: see `Vm::reset`.
+        let status = unsafe { kernel!(self.backend, cpu_run(&mut *self.cpu, budget)) };
+        self.bus_mut().watch_hit = None;
+        let elapsed = self.cpu.cycles.wrapping_sub(cycles);
+        self.bus_mut().end_instruction(elapsed);
+        self.run_dma();
+        self.switch_banks();
+        self.draw_lines();
+        self.repoll_irq_input();
+        // Batches only run while ROM writes and the MPU do not trap, so
+        // there is no instruction to charge any other fault to.
+        self.take_faults(self.cpu.pc)?;
+        if status == RVM_ILLEGAL_OPCODE {
+            // The kernel stopped just past the opcode byte.
+            let pc = self.cpu.pc.wrapping_sub(1);
+            return Err(VmError::IllegalOpcode {
+                pc,
+                opcode: self.fetched_opcode(pc),
+            });
+        }
+        self.run_scheduled();
+        Ok(())
+    }
+
+    /// The opcode byte the kernel fetched from `pc`: what the bus answered
+    /// if the fetch went through it, or else the RAM `pc` decodes to.
+    fn fetched_opcode(&self, pc: u16) -> u8 {
+        let addr = bus::decode(&self.cpu.page_map, pc);
+        match self.bus().last_read {
+            Some((read, opcode)) if read == addr => opcode,
+            _ => self.memory()[addr as usize],
+        }
+    }
+
+    /// Takes every fault the instruction at `pc` left pending, so none is
+    /// reported against a later instruction, and reports the first of them
+    /// in this order: a trapped unmapped access or a device panic, a
+    /// trapped ROM write, an MPU violation. A fault comes before an illegal
+    /// opcode, which fetching from unmapped memory may have caused.
+    fn take_faults(&mut self, pc: u16) -> Result<(), VmError> {
+        let bus = self.take_bus_fault();
+        let rom = self.take_rom_fault(pc);
+        let mpu = self.take_mpu_fault(pc);
+        bus.and(rom).and(mpu)
+    }
+
+    fn take_bus_fault(&mut self) -> Result<(), VmError> {
+        match self.bus_mut().fault.take() {
+            Some(fault) => Err(fault),
+            None => Ok(()),
+        }
+    }
+
+    /// Reports a trapped write to ROM by the instruction at `pc`.
+    fn take_rom_fault(&mut self, pc: u16) -> Result<(), VmError> {
+        match self.bus_mut().rom_fault.take() {
+            Some((addr, val)) => Err(VmError::RomWrite { pc, addr, val }),
+            None => Ok(()),
+        }
+    }
+
+    /// Applies any [freezes](Vm::freeze), then runs instructions until the
+    /// cycle counter reaches the end of the current frame, ignoring
+    /// breakpoints, where the vblank [event](crate::scheduler) renders the
+    /// frame, signals vblank, delivers the frame's audio and runs the frame
+    /// hooks.
+    /// Instructions run in batches as for [`Vm::run_cycles`].
+    ///
+    /// Each frame ends [`Vm::cycles_per_frame`] cycles after the previous
+    /// boundary, so an instruction that overshoots one shortens the next
+    /// frame instead of drifting the clock. On error the frame is left
+    /// unfinished. A frame already begun by [`Vm::step_scanline`] or
+    /// [`Vm::step_instruction`] is run to its end.
+    pub fn run_frame(&mut self) -> Result<(), VmError> {
+        self.begin_frame();
+        if let Err(err) = self.run_until(self.frame_end()) {
+            self.queue_serial();
+            self.queue_event(Event::Fault(err.clone()));
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Does the start-of-frame work, unless the current frame has begun.
+    pub(crate) fn begin_frame(&mut self) {
+        if self.frame_started {
+            return;
+        }
+        self.frame_started = true;
+        self.log_input();
+        self.apply_freezes();
+        self.chrome_frame_start();
+        #[cfg(feature = "instrument")]
+        self.open_frame_span();
+    }
+
+    /// Forgets that the current frame has begun, as when the machine is
+    /// reset or restored mid-frame.
+    pub(crate) fn abandon_frame(&mut self) {
+        #[cfg(feature = "instrument")]
+        self.close_frame_span();
+        self.frame_started = false;
+    }
+
+    #[cfg(not(feature = "instrument"))]
+    fn instrumented(&self) -> bool {
+        false
+    }
+
+    /// Ends the current frame, whose cycles have all run, and schedules the
+    /// end of the next.
+    pub(crate) fn finish_frame(&mut self) {
+        self.frame_started = false;
+        #[cfg(feature = "instrument")]
+        self.close_frame_span();
+        self.frame += 1;
+        self.chrome_frame_end();
+        self.frame_cycle += u64::from(self.cycles_per_frame());
+        self.schedule_vblank();
+        self.queue_serial();
+        self.vblank();
+        self.flush_audio();
+        self.run_frame_hooks();
+        self.record_rewind();
+    }
+
+    /// Frames completed since construction or the last reset.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Cycle count at which the current frame ends, modulo 2^32 like the
+    /// cycle counter itself.
+    pub(crate) fn frame_end(&self) -> u32 {
+        (self.frame_cycle + u64::from(self.cycles_per_frame())) as u32
+    }
+
+    /// Copies `bytes` into memory starting at `addr`.
+    pub fn load(&mut self, addr: u16, bytes: &[u8]) -> Result<(), VmError> {
+        let start = addr as usize;
+        if !bytes.is_empty() {
+            self.mark_dirty(start..=(start + bytes.len() - 1).min(RVM_MEM_SIZE - 1));
+        }
+        let dest = self
+            .memory_untracked_mut()
+            .get_mut(start..start + bytes.len())
+            .ok_or(VmError::OutOfBounds {
+                addr,
+                len: bytes.len(),
+            })?;
+        dest.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Reads one byte of RAM, bypassing mapped devices.
+    pub fn read(&self, addr: u16) -> u8 {
+        self.memory()[addr as usize]
+    }
+
+    /// Writes one byte of RAM, bypassing mapped devices.
+    pub fn write(&mut self, addr: u16, val: u8) {
+        self.mark_dirty(addr as usize..=addr as usize);
+        self.memory_untracked_mut()[addr as usize] = val;
+    }
+
+    /// The whole address space.
+    pub fn memory(&self) -> &[u8] {
+        // SAFETY: `cpu.memory` is the RVM_MEM_SIZE allocation made in
+        // `Vm::new`; the kernel only touches it during calls that borrow
+        // `self` mutably.
+        unsafe { std::slice::from_raw_parts(self.cpu.memory, RVM_MEM_SIZE) }
+    }
+
+    /// The whole address space, mutably. The next
+    /// [paged snapshot](crate::paged) copies every page afresh.
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        self.cpu.dirty_pages = [1; 256];
+        self.memory_untracked_mut()
+    }
+
+    /// The whole address space, mutably, for a caller that marks what it
+    /// writes with [`Vm::mark_dirty`].
+    pub(crate) fn memory_untracked_mut(&mut self) -> &mut [u8] {
+        // SAFETY: see `Vm::memory`.
+        unsafe { std::slice::from_raw_parts_mut(self.cpu.memory, RVM_MEM_SIZE) }
+    }
+
+    /// Marks the pages `span` covers as written since the last paged
+    /// snapshot.
+    pub(crate) fn mark_dirty(&mut self, span: RangeInclusive<usize>) {
+        self.cpu.dirty_pages[span.start() / bus::PAGE_SIZE..=span.end() / bus::PAGE_SIZE].fill(1);
+    }
+
+    /// A pointer to the address space for a foreign caller. Writes through
+    /// it cannot be tracked, so paged snapshots compare every page from
+    /// then on.
+    #[cfg(any(feature = "capi", feature = "libretro", feature = "python"))]
+    pub(crate) fn memory_ptr(&mut self) -> *mut u8 {
+        self.paged.escaped = true;
+        self.cpu.dirty_pages = [1; 256];
+        self.cpu.memory
+    }
+
+    /// Current register values.
+    pub fn registers(&self) -> Registers {
+        Registers {
+            a: self.cpu.a,
+            x: self.cpu.x,
+            y: self.cpu.y,
+            pc: self.cpu.pc,
+            sp: self.cpu.sp,
+            flags: self.cpu.flags,
+        }
+    }
+
+    /// Overwrites every register with `regs`.
+    pub fn set_registers(&mut self, regs: Registers) {
+        self.cpu.a = regs.a;
+        self.cpu.x = regs.x;
+        self.cpu.y = regs.y;
+        self.cpu.pc = regs.pc;
+        self.cpu.sp = regs.sp;
+        self.cpu.flags = regs.flags;
+    }
+
+    /// How the CPU currently treats the behavior [`CpuConfig`] covers,
+    /// with the clock rate always given.
+    pub fn cpu_config(&self) -> CpuConfig {
+        let illegal_opcodes = match self.cpu.illegal_mode {
+            ILLEGAL_NOP => IllegalOpcodes::Nop,
+            ILLEGAL_UNDOCUMENTED => IllegalOpcodes::Undocumented,
+            _ => IllegalOpcodes::Trap,
+        };
+        CpuConfig {
+            clock_hz: Some(self.clock_hz),
+            illegal_opcodes,
+        }
+    }
+
+    /// Changes the CPU's behavior from the next instruction on, and its
+    /// clock rate from the next frame. Resets keep both.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `clock_hz` is below [`FRAME_RATE`], as
+    /// [`Vm::set_clock_hz`] does.
+    pub fn set_cpu_config(&mut self, config: CpuConfig) {
+        if let Some(hz) = config.clock_hz {
+            self.set_clock_hz(hz);
+        }
+        self.cpu.illegal_mode = match config.illegal_opcodes {
+            IllegalOpcodes::Trap => ILLEGAL_TRAP,
+            IllegalOpcodes::Nop => ILLEGAL_NOP,
+            IllegalOpcodes::Undocumented => ILLEGAL_UNDOCUMENTED,
+        };
+    }
+
+    /// Total cycles executed since construction or the last reset.
+    pub fn cycles(&self) -> u32 {
+        self.cpu.cycles
+    }
+
+    /// The devices and watchpoints between the CPU and RAM.
+    pub fn bus(&self) -> &Bus {
+        // SAFETY: the bus is owned by this `Vm` and only aliased by the
+        // kernel while a call borrowing `self` mutably is in progress.
+        unsafe { self.bus.as_ref() }
+    }
+
+    /// The bus, mutably, e.g. to [`Bus::map`] a device.
+    pub fn bus_mut(&mut self) -> &mut Bus {
+        // SAFETY: see `Vm::bus`.
+        unsafe { self.bus.as_mut() }
+    }
+
+    /// Runs device DMA after an instruction, stalling the CPU for the
+    /// cycles it takes.
+    fn run_dma(&mut self) {
+        // SAFETY: the bus and the memory are separate allocations owned by
+        // this `Vm`, neither aliased by the kernel outside a kernel call.
+        let (bus, memory) = unsafe {
+            (
+                &mut *self.bus.as_ptr(),
+                std::slice::from_raw_parts_mut(self.cpu.memory, RVM_MEM_SIZE),
+            )
+        };
+        let cpu = &mut *self.cpu;
+        let stolen = bus.run_dma(
+            memory,
+            &cpu.page_map,
+            &cpu.rom_pages,
+            &mut cpu.dirty_pages,
+            &cpu.wait_pages,
+        );
+        if stolen > 0 {
+            self.cpu.cycles = self.cpu.cycles.wrapping_add(stolen);
+            self.cpu.stats.cycles += u64::from(stolen);
+            bus.end_instruction(stolen);
+            self.chrome_dma(stolen);
+            #[cfg(feature = "instrument")]
+            self.instrument_dma(stolen);
+        }
+    }
+
+    /// The bus and the address space at once.
+    pub(crate) fn bus_and_memory(&mut self) -> (&mut Bus, &mut [u8]) {
+        // SAFETY: as in `Vm::run_dma`.
+        unsafe {
+            (
+                &mut *self.bus.as_ptr(),
+                std::slice::from_raw_parts_mut(self.cpu.memory, RVM_MEM_SIZE),
+            )
+        }
+    }
+
+    /// The bus and the scheduler at once.
+    pub(crate) fn bus_and_scheduler(&mut self) -> (&mut Bus, &mut Scheduler) {
+        // SAFETY: the bus is a separate allocation owned by this `Vm`, not
+        // aliased by the kernel outside a kernel call.
+        (unsafe { &mut *self.bus.as_ptr() }, &mut self.scheduler)
+    }
+
+    /// Recomputes which pages the kernel reports to the bus hook.
+    pub(crate) fn sync_hook_pages(&mut self) {
+        let trap = self.bus().config().rom_write == RomWrite::Trap;
+        let rom_trap = self.cpu.rom_pages.map(|rom| trap && rom != 0);
+        self.bus_mut().rom_trap = rom_trap;
+        self.cpu.hook_pages = self.bus().hook_pages();
+        self.bus_mut().pages_dirty = false;
+    }
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// SAFETY: the raw pointers in `cpu` and `bus` point into allocations the
+// `Vm` owns exclusively, the kernel has no global mutable state, and every
+// boxed device, callback and writer reachable from the `Vm` is `Send`, so
+// moving the whole machine to another thread moves everything it uses.
+unsafe impl Send for Vm {}
+
+impl Drop for Vm {
+    fn drop(&mut self) {
+        // Nothing can report a failure here; see `crate::sram`.
+        let _ = self.flush_sram();
+        // SAFETY: `cpu.memory` came from `Box::into_raw` in `Vm::new` and the
+        // kernel keeps no other reference to it once we stop calling in.
+        drop(unsafe {
+            Box::from_raw(ptr::slice_from_raw_parts_mut(self.cpu.memory, RVM_MEM_SIZE))
+        });
+        // SAFETY: likewise for the bus, which came from `Box::leak`.
+        drop(unsafe { Box::from_raw(self.bus.as_ptr()) });
+    }
+}
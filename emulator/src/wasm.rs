@@ -0,0 +1,110 @@
+//! WebAssembly bindings.
+//!
+//! Only compiled with the `wasm` feature. [`WebVm`] wraps a [`Vm`] behind a
+//! surface wasm-bindgen can export: plain integers, byte slices and a raw
+//! framebuffer pointer that JavaScript can view in place, e.g. as
+//! `new Uint8ClampedArray(memory.buffer, vm.framebuffer_ptr(), vm.framebuffer_len())`
+//! handed to `ImageData`, without copying a frame per vblank.
+
+use wasm_bindgen::prelude::*;
+
+use crate::display::{BYTES_PER_PIXEL, DisplayConfig, HEIGHT, PixelFormat, WIDTH};
+use crate::input::Button;
+use crate::reset::ResetKind;
+use crate::rom::Rom;
+use crate::vm::Vm;
+
+/// A machine exported to JavaScript.
+#[wasm_bindgen]
+pub struct WebVm {
+    vm: Vm,
+}
+
+#[wasm_bindgen]
+impl WebVm {
+    /// Creates a machine with zeroed memory.
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> WebVm {
+        WebVm { vm: Vm::new() }
+    }
+
+    /// Validates an `.rvm` image and resets into it.
+    pub fn load_rom(&mut self, bytes: &[u8]) -> Result<(), JsError> {
+        let rom = Rom::from_bytes(bytes)?;
+        self.vm.load_rom(&rom)?;
+        Ok(())
+    }
+
+    /// Resets the CPU, keeping memory.
+    pub fn reset(&mut self) {
+        self.vm.reset(ResetKind::Soft);
+    }
+
+    /// Runs one frame and renders it into the framebuffer.
+    pub fn step_frame(&mut self) -> Result<(), JsError> {
+        Ok(self.vm.run_frame()?)
+    }
+
+    /// Address of the framebuffer in wasm linear memory, RGBA unless
+    /// [`set_display`](Self::set_display) says otherwise. It stays valid
+    /// until `set_display` or the end of this `WebVm`.
+    pub fn framebuffer_ptr(&self) -> *const u8 {
+        self.vm.framebuffer().as_ptr()
+    }
+
+    /// Length of the framebuffer in bytes.
+    pub fn framebuffer_len(&self) -> usize {
+        self.vm.framebuffer().len()
+    }
+
+    /// Switches the framebuffer to `format` (`rgba8888`, `rgb565` or
+    /// `indexed`) scaled up `scale` times, which moves it.
+    pub fn set_display(&mut self, format: &str, scale: u8) -> Result<(), JsError> {
+        let format =
+            PixelFormat::from_name(format).ok_or_else(|| JsError::new("unknown pixel format"))?;
+        if scale == 0 {
+            return Err(JsError::new("the display scale must be at least 1"));
+        }
+        self.vm.set_display_config(DisplayConfig { format, scale });
+        Ok(())
+    }
+
+    /// Screen width in pixels.
+    pub fn width() -> usize {
+        WIDTH
+    }
+
+    /// Screen height in pixels.
+    pub fn height() -> usize {
+        HEIGHT
+    }
+
+    /// Bytes per pixel of the default RGBA framebuffer.
+    pub fn bytes_per_pixel() -> usize {
+        BYTES_PER_PIXEL
+    }
+
+    /// Presses or releases a button by its port index (0 = A ... 7 = Right).
+    /// Other indices are ignored.
+    pub fn set_button(&mut self, index: u8, pressed: bool) {
+        if let Some(&button) = Button::ALL.get(index as usize) {
+            self.vm.set_button(button, pressed);
+        }
+    }
+
+    /// Replaces every held button with a mask, bit N being port index N.
+    pub fn set_buttons(&mut self, mask: u8) {
+        self.vm.set_buttons(mask);
+    }
+
+    /// Frames completed since the last reset.
+    pub fn frame(&self) -> u64 {
+        self.vm.frame()
+    }
+}
+
+impl Default for WebVm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
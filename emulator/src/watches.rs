@@ -0,0 +1,105 @@
+//! Watch expressions for a debugger's watch window.
+//!
+//! A watch is a [`Condition`] the machine evaluates again each time
+//! [`Vm::run_until_break`] stops, keeping its latest value, so a frontend
+//! shows the list and highlights what changed instead of reading memory on
+//! a timer. Frontends with their own run control, stepping one instruction
+//! at a time for example, call [`Vm::update_watches`] when they stop:
+//!
+//! ```
+//! # use emulator::{Condition, Vm};
+//! let mut vm = Vm::new();
+//! let id = vm.add_watch(Condition::parse("[0x0200] + X").unwrap());
+//! vm.write(0x0200, 5);
+//! let changes = vm.update_watches();
+//! assert_eq!((changes[0].id, changes[0].old, changes[0].new), (id, 0, 5));
+//! ```
+//!
+//! Expressions read memory through [`Vm::read`], so evaluating one has no
+//! effect on the machine.
+
+use crate::debugger::Condition;
+use crate::vm::Vm;
+
+/// Names a watch for [`Vm::remove_watch`] and in [`WatchChange`]s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct WatchId(u32);
+
+/// A registered watch expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watch {
+    pub id: WatchId,
+    pub expression: Condition,
+    /// What the expression evaluated to at the last update.
+    pub value: i64,
+}
+
+/// A watch whose value differs from the update before.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchChange {
+    pub id: WatchId,
+    pub old: i64,
+    pub new: i64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Watches {
+    watches: Vec<Watch>,
+    changes: Vec<WatchChange>,
+    next_id: u32,
+}
+
+impl Vm {
+    /// Registers `expression` as a watch, evaluated now and after every
+    /// stop.
+    pub fn add_watch(&mut self, expression: Condition) -> WatchId {
+        let id = WatchId(self.watches.next_id);
+        self.watches.next_id += 1;
+        let value = expression.eval(self);
+        self.watches.watches.push(Watch {
+            id,
+            expression,
+            value,
+        });
+        id
+    }
+
+    /// Removes a watch, returning `false` if there was none.
+    pub fn remove_watch(&mut self, id: WatchId) -> bool {
+        let before = self.watches.watches.len();
+        self.watches.watches.retain(|watch| watch.id != id);
+        self.watches.changes.retain(|change| change.id != id);
+        self.watches.watches.len() != before
+    }
+
+    /// Every watch, in the order they were added.
+    pub fn watches(&self) -> &[Watch] {
+        &self.watches.watches
+    }
+
+    /// Evaluates every watch again and returns those whose value changed,
+    /// in the order they were added.
+    pub fn update_watches(&mut self) -> &[WatchChange] {
+        let mut watches = std::mem::take(&mut self.watches.watches);
+        self.watches.changes.clear();
+        for watch in &mut watches {
+            let new = watch.expression.eval(self);
+            if new != watch.value {
+                self.watches.changes.push(WatchChange {
+                    id: watch.id,
+                    old: watch.value,
+                    new,
+                });
+                watch.value = new;
+            }
+        }
+        self.watches.watches = watches;
+        &self.watches.changes
+    }
+
+    /// The changes the last update found, whether [`Vm::run_until_break`]
+    /// or the caller made it.
+    pub fn watch_changes(&self) -> &[WatchChange] {
+        &self.watches.changes
+    }
+}
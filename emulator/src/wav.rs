@@ -0,0 +1,139 @@
+//! Audio capture to WAV.
+//!
+//! [`Vm::start_audio_capture`] records the sound generator's output as a
+//! mono 16-bit PCM WAV stream, whether or not an audio callback is
+//! installed, and [`Vm::stop_audio_capture`] finishes it. The samples are
+//! exactly those the callback gets, so a capture doubles as a golden file
+//! for the sound device and as an offline render.
+//!
+//! The header's lengths are only known at the end: the capture writes
+//! placeholders first and seeks back to fill them in when it stops, or on a
+//! best-effort basis when it is dropped still running.
+
+use std::io::{self, Seek, SeekFrom, Write};
+
+use crate::vm::Vm;
+
+/// Bytes before the first sample.
+const HEADER_LEN: u32 = 44;
+
+/// A writer that can also seek, as a capture needs.
+pub(crate) trait CaptureWriter: Write + Seek + Send {}
+
+impl<T: Write + Seek + Send> CaptureWriter for T {}
+
+fn header(sample_rate: u32, data_len: u32) -> Vec<u8> {
+    let mut header = Vec::with_capacity(HEADER_LEN as usize);
+    header.extend_from_slice(b"RIFF");
+    header.extend((HEADER_LEN - 8 + data_len).to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend(16u32.to_le_bytes());
+    // PCM, one channel.
+    header.extend([1, 0, 1, 0]);
+    header.extend(sample_rate.to_le_bytes());
+    header.extend((sample_rate * 2).to_le_bytes());
+    // Two bytes per sample, 16 bits each.
+    header.extend([2, 0, 16, 0]);
+    header.extend_from_slice(b"data");
+    header.extend(data_len.to_le_bytes());
+    header
+}
+
+/// A running capture.
+pub(crate) struct AudioCapture {
+    writer: Box<dyn CaptureWriter>,
+    sample_rate: u32,
+    /// Stream position of the header.
+    start: u64,
+    /// Sample bytes written so far.
+    data_len: u32,
+    /// The first write error, which stops the capture.
+    error: Option<io::Error>,
+    finished: bool,
+}
+
+impl AudioCapture {
+    fn start(mut writer: Box<dyn CaptureWriter>, sample_rate: u32) -> io::Result<Self> {
+        let start = writer.stream_position()?;
+        writer.write_all(&header(sample_rate, 0))?;
+        Ok(Self {
+            writer,
+            sample_rate,
+            start,
+            data_len: 0,
+            error: None,
+            finished: false,
+        })
+    }
+
+    /// Appends one frame's samples, unless an earlier write failed. The
+    /// data chunk stops growing at the 4 GiB WAV limit.
+    pub(crate) fn write(&mut self, samples: &[i16]) {
+        if self.error.is_some() {
+            return;
+        }
+        let room = (u32::MAX - HEADER_LEN - self.data_len) as usize / 2;
+        let bytes: Vec<u8> = samples[..samples.len().min(room)]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        match self.writer.write_all(&bytes) {
+            Ok(()) => self.data_len += bytes.len() as u32,
+            Err(err) => self.error = Some(err),
+        }
+    }
+
+    /// Fills in the header's lengths and leaves the writer after the last
+    /// sample.
+    fn finish(&mut self) -> io::Result<()> {
+        self.finished = true;
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.writer.seek(SeekFrom::Start(self.start))?;
+        self.writer
+            .write_all(&header(self.sample_rate, self.data_len))?;
+        let end = self.start + u64::from(HEADER_LEN + self.data_len);
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()
+    }
+}
+
+impl Drop for AudioCapture {
+    fn drop(&mut self) {
+        if !self.finished {
+            // Nobody is left to hear about a failure here.
+            let _ = self.finish();
+        }
+    }
+}
+
+impl Vm {
+    /// Starts recording the audio of every frame from now on to `writer`
+    /// as a WAV stream at the current [sample rate](Vm::sample_rate). Fails
+    /// if the header cannot be written.
+    ///
+    /// A capture already running is finished first; call
+    /// [`Vm::stop_audio_capture`] beforehand to see how it went.
+    ///
+    /// The sample rate should not change during a capture: the header
+    /// records the rate at the start. Frames replayed by [`Vm::rewind`] are
+    /// not captured again.
+    pub fn start_audio_capture(
+        &mut self,
+        writer: impl Write + Seek + Send + 'static,
+    ) -> io::Result<()> {
+        let _ = self.stop_audio_capture();
+        self.audio.capture = Some(AudioCapture::start(Box::new(writer), self.sample_rate())?);
+        Ok(())
+    }
+
+    /// Finishes the capture, filling in the WAV header. Returns the first
+    /// error the capture met, which stopped it, or `Ok` if none was running.
+    pub fn stop_audio_capture(&mut self) -> io::Result<()> {
+        match self.audio.capture.take() {
+            Some(mut capture) => capture.finish(),
+            None => Ok(()),
+        }
+    }
+}
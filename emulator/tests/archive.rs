@@ -0,0 +1,82 @@
+#![cfg(feature = "compression")]
+
+use emulator::archive::{self, ArchiveError};
+use emulator::{Rom, RomError};
+
+/// `LDA #$42; LSR $0200` at 0xC000, gzipped at level 9.
+const ROM_GZ: &str = "1f8b0800000000000203edc1b11180201000b0e717610d6b7b28b8c3f1e8ddc255d844b7b04932ae76648967cd5de3739f3d32000000000000000000000000000000803fbdf5825ffe10400000";
+
+/// The same ROM deflated as `games/demo.RVM` in a zip archive, after a
+/// stored `README.txt`.
+const ROM_ZIP: &str = "504b03041400000000000000210086a6103605000000050000000a000000524541444d452e74787468656c6c6f504b030414000000080000002100f5825ffe3b000000104000000e00000067616d65732f64656d6f2e52564dedc1b11180201000b0e717610d6b7b28b8c3f1e8ddc255d844b7b04932ae76648967cd5de3739f3d32000000000000000000000000000000803fbd504b010214031400000000000000210086a6103605000000050000000a0000000000000000000000800100000000524541444d452e747874504b0102140314000000080000002100f5825ffe3b000000104000000e000000000000000000000080012d00000067616d65732f64656d6f2e52564d504b0506000000000200020074000000940000000000";
+
+/// A short text gzipped with fixed Huffman codes, and stored.
+const TEXT_FIXED_GZ: &str =
+    "1f8b0800000000000203cb48cdc9c9d751c840a2148aca72752d002575598419000000";
+const TEXT_STORED_GZ: &str = "1f8b0800000000000403011900e6ff68656c6c6f2c2068656c6c6f2c2068656c6c6f2072766d2d382575598419000000";
+const TEXT: &[u8] = b"hello, hello, hello rvm-8";
+
+/// Zip archives holding only a stored `README.txt`, and `a.txt` and
+/// `b.txt`.
+const README_ZIP: &str = "504b03041400000000000000210086a6103605000000050000000a000000524541444d452e74787468656c6c6f504b010214031400000000000000210086a6103605000000050000000a0000000000000000000000800100000000524541444d452e747874504b05060000000001000100380000002d0000000000";
+const TWO_TEXTS_ZIP: &str = "504b03041400000000000000210086a61036050000000500000005000000612e74787468656c6c6f504b03041400000000000000210086a61036050000000500000005000000622e74787468656c6c6f504b010214031400000000000000210086a610360500000005000000050000000000000000000000800100000000612e747874504b010214031400000000000000210086a610360500000005000000050000000000000000000000800128000000622e747874504b0506000000000200020066000000500000000000";
+
+fn bytes(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+fn rom() -> Rom {
+    Rom::new(0xC000, &[0xA9, 0x42, 0x4E, 0x00, 0x02]).unwrap()
+}
+
+#[test]
+fn gzipped_roms_load_transparently() {
+    assert_eq!(Rom::from_bytes(&bytes(ROM_GZ)).unwrap(), rom());
+}
+
+#[test]
+fn zipped_roms_load_from_their_rvm_entry() {
+    assert_eq!(Rom::from_bytes(&bytes(ROM_ZIP)).unwrap(), rom());
+}
+
+#[test]
+fn every_block_type_inflates() {
+    assert_eq!(archive::unpack(&bytes(TEXT_FIXED_GZ)).unwrap(), TEXT);
+    assert_eq!(archive::unpack(&bytes(TEXT_STORED_GZ)).unwrap(), TEXT);
+}
+
+#[test]
+fn plain_roms_pass_through() {
+    let plain = rom().to_bytes();
+    let unpacked = archive::unpack(&plain).unwrap();
+    assert!(matches!(unpacked, std::borrow::Cow::Borrowed(_)));
+    assert_eq!(Rom::from_bytes(&plain).unwrap(), rom());
+}
+
+#[test]
+fn damaged_archives_are_refused() {
+    let mut gz = bytes(ROM_GZ);
+    let crc = gz.len() - 8;
+    gz[crc] ^= 1;
+    assert!(matches!(
+        Rom::from_bytes(&gz),
+        Err(RomError::Archive(ArchiveError::ChecksumMismatch { .. }))
+    ));
+
+    let gz = bytes(ROM_GZ);
+    let err = Rom::from_bytes(&gz[..40]).unwrap_err();
+    assert!(matches!(err, RomError::Archive(ArchiveError::Truncated)));
+    assert_eq!(err.to_string(), "cannot unpack ROM: archive is truncated");
+
+    // A lone file is taken whatever its name, and is not a ROM at all.
+    let readme = bytes(README_ZIP);
+    assert_eq!(*archive::unpack(&readme).unwrap(), *b"hello");
+    assert!(matches!(Rom::from_bytes(&readme), Err(RomError::Truncated)));
+    assert_eq!(
+        archive::unpack(&bytes(TWO_TEXTS_ZIP)),
+        Err(ArchiveError::NoRom)
+    );
+}
@@ -0,0 +1,104 @@
+use emulator::asm::{self, AsmErrorKind};
+use emulator::{ResetKind, Vm};
+
+#[test]
+fn assembles_and_runs_demo_room() {
+    let source = include_str!("../../rooms/demo/room0.asm");
+    let program = asm::assemble(&format!(
+        "  .org $8000\n{source}\n  .org $FFFC\n  .word $8000"
+    ))
+    .unwrap();
+
+    let mut vm = Vm::new();
+    vm.load_assembly(&program).unwrap();
+    vm.reset(ResetKind::Soft);
+    vm.step().unwrap();
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 15);
+}
+
+#[test]
+fn labels_constants_and_directives() {
+    let program = asm::assemble(
+        r#"
+COUNT = 2
+ZP    = $10
+        .org $8000
+start:  LDX #COUNT      ; immediate constant
+        LDA ZP          ; defined before use: zero page
+        LDA table,X     ; forward label: absolute
+        LSR A
+        LDA (ZP),Y
+table:  .byte 1, $02, %11, 'A', "hi"
+        .dw start, table+1
+BASE = table - 1        ; forward-referencing constant
+"#,
+    )
+    .unwrap();
+
+    let (origin, image) = program.image();
+    assert_eq!(origin, 0x8000);
+    assert_eq!(
+        image,
+        [
+            0xA2, 0x02, // LDX #2
+            0xA5, 0x10, // LDA $10
+            0xBD, 0x0A, 0x80, // LDA $800A,X
+            0x4A, // LSR A
+            0xB1, 0x10, // LDA ($10),Y
+            1, 2, 3, b'A', b'h', b'i', // .byte
+            0x00, 0x80, 0x0B, 0x80, // .dw
+        ]
+    );
+    assert_eq!(program.symbols["table"], 0x800A);
+    assert_eq!(program.symbols["BASE"], 0x8009);
+    let lines: Vec<_> = program.lines.into_iter().collect();
+    assert_eq!(
+        lines,
+        [
+            (5, 0x8000),
+            (6, 0x8002),
+            (7, 0x8004),
+            (8, 0x8007),
+            (9, 0x8008)
+        ]
+    );
+}
+
+#[test]
+fn org_starts_new_segments() {
+    let program = asm::assemble(".org $10\n.byte 1\n.org $20\n.byte 2").unwrap();
+    assert_eq!(program.segments.len(), 2);
+    assert_eq!(program.segments[1].origin, 0x20);
+
+    let (origin, image) = program.image();
+    assert_eq!(origin, 0x10);
+    assert_eq!(image.len(), 0x11);
+    assert_eq!(image[0x10], 2);
+}
+
+#[test]
+fn reports_errors_with_line_numbers() {
+    let cases = [
+        ("NOP", 1, AsmErrorKind::UnknownMnemonic("NOP".into())),
+        ("LDX $10,X", 1, AsmErrorKind::InvalidMode("LDX".into())),
+        ("LDA #nope", 1, AsmErrorKind::UndefinedSymbol("nope".into())),
+        ("LDA #$100", 1, AsmErrorKind::OutOfRange(0x100)),
+        (".fill 3", 1, AsmErrorKind::UnknownDirective(".fill".into())),
+        (
+            "x: .db 1\nx: .db 2",
+            2,
+            AsmErrorKind::DuplicateSymbol("x".into()),
+        ),
+        (".org $FFFF\nLDA $1234", 2, AsmErrorKind::Overflow),
+        (
+            ".org 1\n.db 1, 2\n.org 2\n.db 3",
+            3,
+            AsmErrorKind::Overlap { addr: 2 },
+        ),
+    ];
+    for (source, line, kind) in cases {
+        let err = asm::assemble(source).unwrap_err();
+        assert_eq!((err.line, err.kind), (line, kind), "{source}");
+    }
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::vm_with;
+use emulator::Vm;
+use emulator::audio::{
+    AUDIO_ENABLE, Channel, ENABLE_NOISE, ENABLE_SQUARE1, ENABLE_SQUARE2, NOISE_CTRL, SQUARE1_CTRL,
+    SQUARE1_PERIOD, SQUARE2_PERIOD,
+};
+
+/// A machine that spends a few frames executing `LSR $0300` from 0x8000.
+fn idle_vm() -> Vm {
+    vm_with(&[0x4E, 0x00, 0x03].repeat(10921))
+}
+
+/// Installs a callback collecting every frame's samples.
+fn capture(vm: &mut Vm) -> Arc<Mutex<Vec<Vec<i16>>>> {
+    let frames = Arc::new(Mutex::new(Vec::new()));
+    let sink = frames.clone();
+    vm.set_audio_callback(move |samples| sink.lock().unwrap().push(samples.to_vec()));
+    frames
+}
+
+#[test]
+fn delivers_one_frame_of_samples_at_the_host_rate() {
+    let mut vm = idle_vm();
+    let frames = capture(&mut vm);
+    vm.run_frame().unwrap();
+    vm.run_frame().unwrap();
+
+    let frames = frames.lock().unwrap();
+    assert_eq!(frames.len(), 2);
+    let total: usize = frames.iter().map(Vec::len).sum();
+    assert!((1469..=1470).contains(&total), "{total} samples");
+    assert!(frames.iter().flatten().all(|&s| s == 0));
+}
+
+#[test]
+fn square_channel_follows_period_duty_and_volume() {
+    let mut vm = idle_vm();
+    // One sample every 16 cycles; a period of 1 lasts 32 cycles.
+    vm.set_sample_rate(62_500);
+    vm.load(SQUARE1_PERIOD, &[0x01, 0x00]).unwrap();
+    vm.write(SQUARE1_CTRL, 0x2F);
+    vm.write(AUDIO_ENABLE, ENABLE_SQUARE1);
+    let frames = capture(&mut vm);
+    vm.run_frame().unwrap();
+
+    let frames = frames.lock().unwrap();
+    let level = 15 * 682;
+    assert_eq!(frames[0][..4], [level, -level, level, -level]);
+}
+
+#[test]
+fn noise_channel_is_not_constant() {
+    let mut vm = idle_vm();
+    vm.write(NOISE_CTRL, 0x0F);
+    vm.write(AUDIO_ENABLE, ENABLE_NOISE);
+    let frames = capture(&mut vm);
+    vm.run_frame().unwrap();
+
+    let frames = frames.lock().unwrap();
+    assert!(frames[0].iter().any(|&s| s > 0));
+    assert!(frames[0].iter().any(|&s| s < 0));
+}
+
+#[test]
+fn the_mixer_isolates_and_scales_channels() {
+    let mut vm = idle_vm();
+    vm.set_sample_rate(62_500);
+    vm.load(SQUARE1_PERIOD, &[0x01, 0x00, 0x2F]).unwrap();
+    vm.load(SQUARE2_PERIOD, &[0x01, 0x00, 0x24]).unwrap();
+    vm.write(NOISE_CTRL, 0x0F);
+    vm.write(AUDIO_ENABLE, ENABLE_SQUARE1 | ENABLE_SQUARE2 | ENABLE_NOISE);
+    let frames = capture(&mut vm);
+
+    vm.audio_mixer_mut().solo(Channel::Square2);
+    vm.run_frame().unwrap();
+    vm.audio_mixer_mut().set_muted(Channel::Square1, false);
+    vm.audio_mixer_mut().set_volume(Channel::Square1, 0.5);
+    vm.audio_mixer_mut().set_volume(Channel::Square2, 2.0);
+    vm.run_frame().unwrap();
+
+    let mixer = vm.audio_mixer();
+    assert!(mixer.is_muted(Channel::Noise));
+    assert_eq!(mixer.volume(Channel::Square2), 1.0, "volumes stop at 1");
+    let frames = frames.lock().unwrap();
+    let (square1, square2) = (15 * 682, 4 * 682);
+    assert_eq!(frames[0][..2], [square2, -square2]);
+    let mix = square1 / 2 + square2;
+    assert_eq!(frames[1][..1], [mix]);
+    assert_eq!(vm.read(AUDIO_ENABLE), 0b111, "the program sees no change");
+}
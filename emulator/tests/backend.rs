@@ -0,0 +1,107 @@
+mod common;
+
+use common::vm_with;
+use emulator::backend::{Backend, BackendError};
+use emulator::ffi::RVM_STATE_SIZE;
+use emulator::{Registers, Vm};
+
+#[test]
+fn exported_states_bring_the_kernel_back() {
+    // LDA #$11; LDX #$22; LSR $0300
+    let mut vm = vm_with(&[0xA9, 0x11, 0xA2, 0x22, 0x4E, 0x00, 0x03]);
+    vm.write(0x0300, 0x08);
+    vm.step().unwrap();
+    let state = vm.export_kernel_state();
+    assert_eq!(state.len(), RVM_STATE_SIZE);
+    let (registers, cycles) = (vm.registers(), vm.cycles());
+
+    vm.step().unwrap();
+    vm.step().unwrap();
+    assert_eq!(vm.read(0x0300), 0x04);
+    vm.import_kernel_state(&state).unwrap();
+    assert_eq!((vm.registers(), vm.cycles()), (registers, cycles));
+    assert_eq!(vm.read(0x0300), 0x08, "memory travels too");
+
+    // Another machine picks up where this one was.
+    let mut other = Vm::new();
+    other.import_kernel_state(&state).unwrap();
+    other.step().unwrap();
+    assert_eq!(
+        other.registers(),
+        Registers {
+            x: 0x22,
+            pc: 0x8004,
+            ..registers
+        }
+    );
+}
+
+#[test]
+fn foreign_bytes_are_refused() {
+    let mut vm = vm_with(&[0xA9, 0x11]);
+    let mut state = vm.export_kernel_state();
+    assert_eq!(
+        vm.import_kernel_state(&state[1..]),
+        Err(BackendError::BadState)
+    );
+    state[4] = 0xFF;
+    assert_eq!(vm.import_kernel_state(&state), Err(BackendError::BadState));
+    assert_eq!(vm.registers().pc, 0x8000);
+}
+
+#[test]
+fn only_linked_backends_can_be_chosen() {
+    let mut vm = Vm::new();
+    assert_eq!(vm.backend(), Backend::DEFAULT);
+    assert!(Backend::DEFAULT.is_available());
+    vm.set_backend(Backend::DEFAULT).unwrap();
+    for backend in [Backend::C, Backend::Rust] {
+        if !backend.is_available() {
+            assert_eq!(
+                vm.set_backend(backend),
+                Err(BackendError::Unavailable(backend))
+            );
+            assert_eq!(vm.backend(), Backend::DEFAULT);
+        }
+    }
+}
+
+#[cfg(all(c_kernel, rust_core))]
+#[test]
+fn machines_switch_backends_mid_run() {
+    use emulator::ffi;
+
+    // LDA #$01; ADC #$02; LSR $0300; LDY $0300
+    let program = [0xA9, 0x01, 0x69, 0x02, 0x4E, 0x00, 0x03, 0xAC, 0x00, 0x03];
+    let mut reference = vm_with(&program);
+    let mut vm = vm_with(&program);
+    for vm in [&mut reference, &mut vm] {
+        vm.write(0x0300, 0x10);
+    }
+    for backend in [Backend::Rust, Backend::C, Backend::Rust, Backend::C] {
+        vm.set_backend(backend).unwrap();
+        assert_eq!(vm.backend(), backend);
+        vm.step().unwrap();
+        reference.step().unwrap();
+        assert_eq!(vm.registers(), reference.registers());
+        assert_eq!(vm.cycles(), reference.cycles());
+    }
+    assert_eq!(vm.registers().y, 0x08);
+    assert_eq!(vm.stats().instructions, 4);
+
+    // Both cores write the very same bytes.
+    let c = vm.export_kernel_state();
+    vm.set_backend(Backend::Rust).unwrap();
+    assert_eq!(vm.export_kernel_state(), c);
+    let mut memory = vec![0; ffi::RVM_MEM_SIZE];
+    let mut cpu = ffi::Cpu::default();
+    // SAFETY: `memory` spans the address space and outlives `cpu`.
+    unsafe {
+        emulator::cpu::cpu_init(&mut cpu, memory.as_mut_ptr());
+        assert_eq!(
+            ffi::rvm8_import_state(&mut cpu, c.as_ptr(), c.len()),
+            ffi::RVM_OK
+        );
+    }
+    assert_eq!((cpu.pc, cpu.y), (0x800A, 0x08));
+}
@@ -0,0 +1,379 @@
+mod common;
+
+use common::vm_with;
+use emulator::ffi::BusAccess;
+use emulator::input::INPUT_PORTS;
+use emulator::mpu::{Mpu, READ_ONLY};
+use emulator::{
+    Bus, BusConfig, BusDevice, ResetKind, Rom, RomWrite, StopReason, TimingMode, UnmappedRead,
+    UnmappedWrite, Vm, VmError, WatchKind,
+};
+
+/// Two registers: offset 0 reads as the cycles ticked so far, offset 1 is a
+/// latch that remembers the last byte written.
+#[derive(Default)]
+struct Counter {
+    cycles: u32,
+    latch: u8,
+}
+
+impl BusDevice for Counter {
+    fn read8(&mut self, offset: u16) -> u8 {
+        match offset {
+            0 => self.cycles as u8,
+            _ => self.latch,
+        }
+    }
+
+    fn write8(&mut self, offset: u16, val: u8) {
+        if offset == 1 {
+            self.latch = val;
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.cycles += cycles;
+    }
+}
+
+fn counter(bus: &Bus) -> &Counter {
+    bus.device::<Counter>(0x4000).unwrap()
+}
+
+#[test]
+fn mapped_device_serves_reads_and_writes() {
+    // LDA #$01; LDA $4000; LSR $4001
+    let mut vm = vm_with(&[0xA9, 0x01, 0xAD, 0x00, 0x40, 0x4E, 0x01, 0x40]);
+    vm.write(0x4001, 0xEE);
+    vm.bus_mut()
+        .map(
+            0x4000..=0x4001,
+            Counter {
+                latch: 0x10,
+                ..Counter::default()
+            },
+        )
+        .unwrap();
+
+    vm.step().unwrap();
+    assert_eq!(counter(vm.bus()).cycles, 2);
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 2);
+
+    vm.step().unwrap();
+    assert_eq!(counter(vm.bus()).latch, 0x08);
+    assert_eq!(vm.read(0x4001), 0xEE);
+}
+
+#[test]
+fn unmap_reverts_to_ram() {
+    // LDA $4001; LDA $4001
+    let mut vm = vm_with(&[0xAD, 0x01, 0x40, 0xAD, 0x01, 0x40]);
+    vm.write(0x4001, 0x42);
+    vm.bus_mut()
+        .map(
+            0x4000..=0x4001,
+            Counter {
+                latch: 0x10,
+                ..Counter::default()
+            },
+        )
+        .unwrap();
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 0x10);
+
+    assert!(vm.bus_mut().unmap(0x4001).is_some());
+    assert_eq!(vm.bus().mappings().collect::<Vec<_>>(), [INPUT_PORTS]);
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 0x42);
+}
+
+#[test]
+fn overlapping_mappings_are_rejected() {
+    let mut vm = Vm::new();
+    vm.bus_mut()
+        .map(0x4000..=0x40FF, Counter::default())
+        .unwrap();
+    assert_eq!(
+        vm.bus_mut().map(0x40F0..=0x4100, Counter::default()),
+        Err(VmError::MapConflict {
+            start: 0x4000,
+            end: 0x40FF,
+        })
+    );
+    assert_eq!(
+        vm.bus().mappings().collect::<Vec<_>>(),
+        [INPUT_PORTS, 0x4000..=0x40FF]
+    );
+}
+
+#[test]
+fn watchpoints_see_device_values() {
+    // LDA $4001
+    let mut vm = vm_with(&[0xAD, 0x01, 0x40]);
+    vm.bus_mut()
+        .map(
+            0x4000..=0x4001,
+            Counter {
+                latch: 0x77,
+                ..Counter::default()
+            },
+        )
+        .unwrap();
+    vm.add_watchpoint(0x4001..=0x4001, WatchKind::Read);
+
+    let stop = vm.run_until_break().unwrap();
+    assert_eq!(
+        stop,
+        StopReason::Watchpoint {
+            pc: 0x8000,
+            addr: 0x4001,
+            access: BusAccess::Read,
+            value: 0x77,
+        }
+    );
+}
+
+#[test]
+fn cycle_accurate_devices_see_the_clock_at_each_access() {
+    // LDA #$01; LDA $4000; LSR $4001
+    let program = [0xA9, 0x01, 0xAD, 0x00, 0x40, 0x4E, 0x01, 0x40];
+    let mut reads = Vec::new();
+    for mode in [TimingMode::Instruction, TimingMode::CycleAccurate] {
+        let mut vm = vm_with(&program);
+        vm.bus_mut().set_timing_mode(mode);
+        vm.bus_mut()
+            .map(0x4000..=0x4001, Counter::default())
+            .unwrap();
+        vm.step().unwrap();
+        vm.step().unwrap();
+        reads.push(vm.registers().a);
+        vm.step().unwrap();
+        // Both modes tick the same total; LSR spends one cycle off the bus.
+        assert_eq!(counter(vm.bus()).cycles, 12);
+    }
+    // The read is the fourth access of LDA abs, after the two of LDA #.
+    assert_eq!(reads, [2, 6]);
+}
+
+#[test]
+fn unmapped_reads_follow_the_config() {
+    // LDA $4123; LSR $4000
+    let program = [0xAD, 0x23, 0x41, 0x4E, 0x00, 0x40];
+    for (read, value) in [(UnmappedRead::OpenBus, 0x41), (UnmappedRead::Zero, 0x00)] {
+        let mut vm = vm_with(&program);
+        vm.write(0x4123, 0xEE);
+        vm.write(0x4000, 0xEE);
+        vm.bus_mut().unmap_ram(0x4000..=0x4FFF);
+        vm.bus_mut().set_config(BusConfig {
+            unmapped_read: read,
+            ..BusConfig::default()
+        });
+        assert!(!vm.bus().is_ram(0x4FFF));
+        assert!(vm.bus().is_ram(0x5000));
+        vm.run_cycles(4).unwrap();
+        assert_eq!(vm.registers().a, value);
+        vm.step().unwrap();
+        assert_eq!(vm.read(0x4000), 0xEE, "unmapped writes are dropped");
+    }
+}
+
+#[test]
+fn trapped_accesses_fail_the_instruction() {
+    // LDA $4123
+    let mut vm = vm_with(&[0xAD, 0x23, 0x41]);
+    vm.bus_mut().unmap_ram(0x4000..=0x40FF);
+    vm.bus_mut().unmap_ram(0x4100..=0x41FF);
+    vm.bus_mut().set_config(BusConfig {
+        unmapped_read: UnmappedRead::Trap,
+        unmapped_write: UnmappedWrite::Trap,
+        ..BusConfig::default()
+    });
+    let fault = VmError::BusFault {
+        addr: 0x4123,
+        kind: BusAccess::Read,
+    };
+    assert_eq!(vm.run_cycles(100), Err(fault.clone()));
+    assert_eq!(vm.registers().pc, 0x8003);
+    assert_eq!(vm.registers().a, 0x41);
+    assert_eq!(fault.to_string(), "unmapped read at 0x4123");
+
+    // LSR $4000 reads before it writes, so trap only writes to see that.
+    vm.load(0x8003, &[0x4E, 0x00, 0x40]).unwrap();
+    vm.bus_mut().set_config(BusConfig {
+        unmapped_write: UnmappedWrite::Trap,
+        ..BusConfig::default()
+    });
+    assert_eq!(
+        vm.step(),
+        Err(VmError::BusFault {
+            addr: 0x4000,
+            kind: BusAccess::Write,
+        })
+    );
+
+    // Fetching from unmapped memory faults before the opcode it read.
+    vm.bus_mut().unmap_ram(0x8000..=0x80FF);
+    vm.bus_mut().set_config(BusConfig {
+        unmapped_read: UnmappedRead::Trap,
+        ..BusConfig::default()
+    });
+    vm.reset(ResetKind::Soft);
+    assert_eq!(
+        vm.step(),
+        Err(VmError::BusFault {
+            addr: 0x8000,
+            kind: BusAccess::Read,
+        })
+    );
+}
+
+#[test]
+fn rom_writes_trap_with_the_writer_pc() {
+    // LSR $C005 in the ROM itself, then the halt it would have shifted.
+    let rom = Rom::new(0xC000, &[0xA9, 0x01, 0x4E, 0x05, 0xC0, 0x02]).unwrap();
+    let mut vm = Vm::new();
+    vm.load_rom(&rom).unwrap();
+    vm.step().unwrap();
+    vm.step().unwrap();
+    assert_eq!(vm.read(0xC005), 0x02, "ROM writes are ignored by default");
+
+    vm.reset(ResetKind::Soft);
+    vm.bus_mut().set_config(BusConfig {
+        rom_write: RomWrite::Trap,
+        ..BusConfig::default()
+    });
+    let fault = VmError::RomWrite {
+        pc: 0xC002,
+        addr: 0xC005,
+        val: 0x01,
+    };
+    assert_eq!(vm.run_cycles(100), Err(fault.clone()));
+    assert_eq!(vm.read(0xC005), 0x02);
+    assert_eq!(
+        fault.to_string(),
+        "write of 0x01 to ROM at 0xC005 from PC 0xC002"
+    );
+
+    // RAM stays writable with the trap on.
+    vm.load(0x8000, &[0x4E, 0x00, 0x02]).unwrap();
+    vm.write(0x0200, 0x08);
+    vm.set_registers(emulator::Registers {
+        pc: 0x8000,
+        ..vm.registers()
+    });
+    vm.step().unwrap();
+    assert_eq!(vm.read(0x0200), 0x04);
+}
+
+#[test]
+fn every_fault_of_an_instruction_is_taken_with_it() {
+    // 0x02, an extension, then LDA #$01.
+    let rom = Rom::new(0xC000, &[0x02, 0xA9, 0x01]).unwrap();
+    let mut vm = Vm::new();
+    vm.load_rom(&rom).unwrap();
+    vm.register_opcode(0x02, |call| {
+        call.write(0x0300, 0x01);
+        call.write(0xC005, 0x01);
+        call.read(0x4000);
+        Ok(2)
+    })
+    .unwrap();
+    let mut mpu = Mpu::default();
+    mpu.protect(0x0300..=0x03FF, READ_ONLY);
+    vm.bus_mut().set_mpu(mpu);
+    vm.bus_mut().unmap_ram(0x4000..=0x40FF);
+    vm.bus_mut().set_config(BusConfig {
+        unmapped_read: UnmappedRead::Trap,
+        rom_write: RomWrite::Trap,
+        ..BusConfig::default()
+    });
+
+    assert_eq!(
+        vm.step(),
+        Err(VmError::BusFault {
+            addr: 0x4000,
+            kind: BusAccess::Read,
+        })
+    );
+    // The ROM write and the MPU violation went with it.
+    assert_eq!(vm.step(), Ok(()));
+    assert_eq!(vm.registers().a, 0x01);
+}
+
+#[test]
+fn devices_and_restored_ram_serve_unmapped_pages() {
+    // LDA $4000; LDA $4101
+    let mut vm = vm_with(&[0xAD, 0x00, 0x40, 0xAD, 0x01, 0x41]);
+    vm.write(0x4101, 0x33);
+    vm.bus_mut().unmap_ram(0x4000..=0x41FF);
+    vm.bus_mut().set_config(BusConfig {
+        unmapped_read: UnmappedRead::Trap,
+        ..BusConfig::default()
+    });
+    vm.bus_mut()
+        .map(
+            0x4000..=0x4001,
+            Counter {
+                latch: 0x10,
+                ..Counter::default()
+            },
+        )
+        .unwrap();
+    vm.bus_mut().map_ram(0x4100..=0x41FF);
+
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 0);
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 0x33);
+}
+
+/// Panics on reads of offset 1 and on every write; offset 0 reads as 0x20.
+struct Faulty;
+
+impl BusDevice for Faulty {
+    fn read8(&mut self, offset: u16) -> u8 {
+        match offset {
+            0 => 0x20,
+            _ => panic!("read of register {offset}"),
+        }
+    }
+
+    fn write8(&mut self, _: u16, val: u8) {
+        panic!("write of {val:#04X}");
+    }
+}
+
+#[test]
+fn device_panics_come_back_as_errors() {
+    // LDA $4001; LDA #$07; LSR $4000
+    let mut vm = vm_with(&[0xAD, 0x01, 0x40, 0xA9, 0x07, 0x4E, 0x00, 0x40]);
+    vm.write(0x4000, 0x55);
+    vm.write(0x4001, 0x55);
+    vm.bus_mut().map(0x4000..=0x40FF, Faulty).unwrap();
+    let fault = VmError::DevicePanic {
+        addr: 0x4001,
+        kind: BusAccess::Read,
+        message: "read of register 1".to_string(),
+    };
+    assert_eq!(vm.run_cycles(100), Err(fault.clone()));
+    assert_eq!(vm.registers().pc, 0x8003, "the instruction completed");
+    assert_eq!(vm.registers().a, 0);
+    assert_eq!(
+        fault.to_string(),
+        "device panicked on read at 0x4001: read of register 1"
+    );
+
+    // The machine keeps running.
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 0x07);
+    assert_eq!(
+        vm.step(),
+        Err(VmError::DevicePanic {
+            addr: 0x4000,
+            kind: BusAccess::Write,
+            message: "write of 0x10".to_string(),
+        })
+    );
+    assert_eq!(vm.read(0x4000), 0x55, "the write was claimed");
+}
@@ -0,0 +1,209 @@
+#![cfg(feature = "capi")]
+
+use std::collections::BTreeSet;
+use std::ffi::c_void;
+
+use emulator::capi::*;
+use emulator::display::{HEIGHT, WIDTH};
+use emulator::{Button, Rom};
+
+/// `LDA #$A9` over both banks, about two frames of it.
+fn rom() -> Vec<u8> {
+    Rom::new(0x8000, &[0xA9; 0x8000]).unwrap().to_bytes()
+}
+
+unsafe extern "C" fn count_samples(ctx: *mut c_void, _samples: *const i16, len: usize) {
+    unsafe { *ctx.cast::<usize>() += len };
+}
+
+#[test]
+fn constants_match_the_machine() {
+    assert_eq!(RVM8_WIDTH as usize, WIDTH);
+    assert_eq!(RVM8_HEIGHT as usize, HEIGHT);
+    assert_eq!(RVM8_MEMORY_SIZE, emulator::ffi::RVM_MEM_SIZE);
+    let buttons = [
+        RVM8_BUTTON_A,
+        RVM8_BUTTON_B,
+        RVM8_BUTTON_SELECT,
+        RVM8_BUTTON_START,
+        RVM8_BUTTON_UP,
+        RVM8_BUTTON_DOWN,
+        RVM8_BUTTON_LEFT,
+        RVM8_BUTTON_RIGHT,
+    ];
+    for (bit, button) in buttons.into_iter().zip(Button::ALL) {
+        assert_eq!(bit, button.mask());
+    }
+}
+
+#[test]
+fn runs_a_rom() {
+    let rom = rom();
+    unsafe {
+        let vm = rvm8_create();
+        assert_eq!(rvm8_load_rom(vm, rom.as_ptr(), rom.len()), Rvm8Status::Ok);
+        let mut regs = Rvm8Registers::default();
+        rvm8_registers(vm, &mut regs);
+        assert_eq!(regs.pc, 0x8000);
+
+        assert_eq!(rvm8_step(vm), Rvm8Status::Ok);
+        rvm8_registers(vm, &mut regs);
+        assert_eq!((regs.a, regs.pc), (0xA9, 0x8002));
+        assert_eq!(rvm8_cycles(vm), 2);
+
+        let mut samples = 0usize;
+        rvm8_set_audio_callback(vm, Some(count_samples), (&raw mut samples).cast());
+        rvm8_set_buttons(vm, RVM8_BUTTON_A | RVM8_BUTTON_START);
+        assert_eq!(rvm8_run_frame(vm), Rvm8Status::Ok);
+        assert_eq!(rvm8_frame(vm), 1);
+        assert_eq!(samples, 44_100 / 60);
+        let pixels = std::slice::from_raw_parts(rvm8_framebuffer(vm), WIDTH * HEIGHT * 4);
+        assert!(pixels.chunks_exact(4).all(|pixel| pixel[3] == 0xFF));
+
+        rvm8_reset(vm);
+        rvm8_registers(vm, &mut regs);
+        assert_eq!(regs.pc, 0x8000);
+        rvm8_set_audio_callback(vm, None, std::ptr::null_mut());
+        assert_eq!(rvm8_run_frame(vm), Rvm8Status::Ok);
+        assert_eq!(samples, 44_100 / 60);
+        rvm8_destroy(vm);
+    }
+}
+
+#[test]
+fn queues_events_for_polling() {
+    let rom = rom();
+    unsafe {
+        let vm = rvm8_create();
+        rvm8_load_rom(vm, rom.as_ptr(), rom.len());
+        rvm8_enable_events(vm, true);
+        assert_eq!(rvm8_run_frame(vm), Rvm8Status::Ok);
+        let mut event = std::mem::zeroed::<Rvm8Event>();
+        assert!(rvm8_next_event(vm, &mut event));
+        assert_eq!((event.kind, event.frame), (Rvm8EventKind::Vblank, 1));
+        assert!(rvm8_next_event(vm, &mut event));
+        assert_eq!(event.kind, Rvm8EventKind::Audio);
+        assert_eq!(event.len, 44_100 / 60);
+        let samples = std::slice::from_raw_parts(event.data.cast::<i16>(), event.len);
+        assert_eq!(samples.len(), event.len);
+        assert!(!rvm8_next_event(vm, &mut event));
+
+        rvm8_reset(vm);
+        rvm8_write(vm, 0x8100, 0x02);
+        assert_eq!(rvm8_run_frame(vm), Rvm8Status::IllegalOpcode);
+        assert!(rvm8_next_event(vm, &mut event));
+        assert_eq!(
+            (event.kind, event.status),
+            (Rvm8EventKind::Fault, Rvm8Status::IllegalOpcode)
+        );
+        rvm8_enable_events(vm, false);
+        assert_eq!(rvm8_run_frame(vm), Rvm8Status::Ok);
+        assert!(!rvm8_next_event(vm, &mut event));
+        rvm8_destroy(vm);
+    }
+}
+
+#[test]
+fn reports_errors_as_status_codes() {
+    unsafe {
+        let vm = rvm8_create();
+        assert_eq!(
+            rvm8_load_rom(vm, b"RVM8".as_ptr(), 4),
+            Rvm8Status::InvalidRom
+        );
+        assert_eq!(
+            rvm8_load(vm, 0xFFFF, [1, 2].as_ptr(), 2),
+            Rvm8Status::OutOfBounds
+        );
+        assert_eq!(
+            rvm8_load_state(vm, [0; 8].as_ptr(), 8),
+            Rvm8Status::InvalidState
+        );
+
+        // Memory starts empty, and 0x00 has no handler.
+        assert_eq!(rvm8_step(vm), Rvm8Status::IllegalOpcode);
+        rvm8_destroy(vm);
+        rvm8_destroy(std::ptr::null_mut());
+    }
+}
+
+#[test]
+fn accesses_memory_and_registers() {
+    unsafe {
+        let vm = rvm8_create();
+        assert_eq!(rvm8_load(vm, 0x0200, [1, 2, 3].as_ptr(), 3), Rvm8Status::Ok);
+        assert_eq!(rvm8_read(vm, 0x0201), 2);
+        rvm8_write(vm, 0x0201, 7);
+        let memory = std::slice::from_raw_parts_mut(rvm8_memory(vm), RVM8_MEMORY_SIZE);
+        assert_eq!(memory[0x0200..0x0203], [1, 7, 3]);
+        memory[0x0300] = 9;
+        assert_eq!(rvm8_read(vm, 0x0300), 9);
+
+        let regs = Rvm8Registers {
+            a: 1,
+            x: 2,
+            y: 3,
+            flags: 0x04,
+            pc: 0x1234,
+            sp: 0x01F0,
+        };
+        rvm8_set_registers(vm, &regs);
+        let mut read = Rvm8Registers::default();
+        rvm8_registers(vm, &mut read);
+        assert_eq!(read, regs);
+        rvm8_destroy(vm);
+    }
+}
+
+#[test]
+fn save_states_round_trip() {
+    let rom = rom();
+    unsafe {
+        let vm = rvm8_create();
+        rvm8_load_rom(vm, rom.as_ptr(), rom.len());
+        rvm8_set_buttons(vm, RVM8_BUTTON_UP);
+        let size = rvm8_save_state(vm, std::ptr::null_mut(), 0);
+        let mut state = vec![0; size];
+        assert_eq!(rvm8_save_state(vm, state.as_mut_ptr(), size - 1), size);
+        assert!(state.iter().all(|&byte| byte == 0));
+        assert_eq!(rvm8_save_state(vm, state.as_mut_ptr(), size), size);
+
+        rvm8_run_frame(vm);
+        let after = std::slice::from_raw_parts(rvm8_memory(vm), RVM8_MEMORY_SIZE).to_vec();
+        rvm8_set_buttons(vm, 0);
+        assert_eq!(rvm8_load_state(vm, state.as_ptr(), size), Rvm8Status::Ok);
+        assert_eq!(rvm8_frame(vm), 0);
+        rvm8_run_frame(vm);
+        assert_eq!(
+            std::slice::from_raw_parts(rvm8_memory(vm), RVM8_MEMORY_SIZE),
+            &after[..]
+        );
+        rvm8_destroy(vm);
+    }
+}
+
+/// Every `rvm8_*` export in `src/capi.rs` and the functions declared in
+/// `include/rvm8.h`, which must agree.
+#[test]
+fn header_declares_every_export() {
+    let names = |text: &str, marker: &str| -> BTreeSet<String> {
+        text.lines()
+            .filter(|line| !line.trim_start().starts_with(['*', '/']))
+            .filter_map(|line| {
+                let at = line.find(marker)? + marker.len();
+                let rest = &line[at - "rvm8_".len()..];
+                let end = rest.find('(')?;
+                Some(rest[..end].to_string())
+            })
+            .collect()
+    };
+    let dir = env!("CARGO_MANIFEST_DIR");
+    let source = std::fs::read_to_string(format!("{dir}/src/capi.rs")).unwrap();
+    let header = std::fs::read_to_string(format!("{dir}/include/rvm8.h")).unwrap();
+    let exports = names(&source, "extern \"C\" fn rvm8_");
+    let declared = names(&header, " rvm8_");
+    let declared_pointers = names(&header, "*rvm8_");
+    let declared: BTreeSet<_> = declared.union(&declared_pointers).cloned().collect();
+    assert!(exports.len() > 20, "{exports:?}");
+    assert_eq!(declared, exports);
+}
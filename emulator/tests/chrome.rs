@@ -0,0 +1,208 @@
+mod common;
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use common::vm_with_handler;
+use emulator::dma::{CTRL_STEAL, DMA_PORTS, Dma};
+use emulator::{BusDevice, Registers, ResetKind, TraceConfig};
+
+/// A writer whose output the test can still read after handing it to the
+/// `Vm`, failing every write once `limit` lines have been written.
+#[derive(Clone, Default)]
+struct Shared {
+    out: Arc<Mutex<Vec<u8>>>,
+    limit: Option<usize>,
+}
+
+impl Shared {
+    /// The events written, one per line as the trace writes them.
+    fn events(&self) -> Vec<String> {
+        let out = String::from_utf8(self.out.lock().unwrap().clone()).unwrap();
+        assert!(out.starts_with("[\n"), "{out}");
+        assert!(out.ends_with("\n]\n"), "{out}");
+        let lines: Vec<_> = out.lines().collect();
+        let events = &lines[1..lines.len() - 1];
+        for (i, event) in events.iter().enumerate() {
+            let comma = i + 1 < events.len();
+            assert_eq!(event.ends_with("},"), comma, "{event}");
+            assert!(event.starts_with("{\"pid\":1,"), "{event}");
+        }
+        events
+            .iter()
+            .map(|event| event.trim_end_matches(',').to_string())
+            .collect()
+    }
+}
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut out = self.out.lock().unwrap();
+        let lines = out.iter().filter(|&&b| b == b'\n').count();
+        if self.limit.is_some_and(|limit| lines >= limit) {
+            return Err(io::Error::other("full"));
+        }
+        out.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn find<'a>(events: &'a [String], needle: &str) -> &'a str {
+    events
+        .iter()
+        .find(|event| event.contains(needle))
+        .unwrap_or_else(|| panic!("no event with {needle} in {events:#?}"))
+}
+
+#[test]
+fn frames_dma_and_device_accesses_get_their_own_tracks() {
+    // LSR $2706 turns CTRL_STEAL into CTRL_START, then LDA $8000.
+    let mut program = vec![0x4E, 0x06, 0x27];
+    program.extend([0xAD, 0x00, 0x80].repeat(10_000));
+    let mut vm = vm_with_handler(&program);
+    vm.bus_mut().map(DMA_PORTS, Dma::default()).unwrap();
+    let dma = vm.bus_mut().device_mut::<Dma>(*DMA_PORTS.start()).unwrap();
+    dma.write8(4, 4);
+    dma.write8(6, CTRL_STEAL);
+
+    let out = Shared::default();
+    vm.set_trace(TraceConfig::chrome(out.clone()));
+    vm.run_frame().unwrap();
+    vm.run_frame().unwrap();
+    vm.clear_trace();
+    let events = out.events();
+
+    find(
+        &events,
+        "\"name\":\"process_name\",\"args\":{\"name\":\"rvm-8\"}",
+    );
+    for track in ["frames", "cpu", "dma"] {
+        find(
+            &events,
+            &format!("\"thread_name\",\"args\":{{\"name\":\"{track}\"}}"),
+        );
+    }
+    // 16666 cycles a frame at 1 MHz; the second overshoots by two cycles.
+    let frame = find(&events, "\"name\":\"frame 0\"");
+    assert!(frame.contains("\"ph\":\"X\",\"cat\":\"frame\""), "{frame}");
+    assert!(frame.contains("\"ts\":0.000,\"dur\":16666.000"), "{frame}");
+    let frame = find(&events, "\"name\":\"frame 1\"");
+    assert!(
+        frame.contains("\"ts\":16666.000,\"dur\":16668.000"),
+        "{frame}"
+    );
+
+    // Six cycles of LSR, then two per byte.
+    let dma = find(&events, "\"cat\":\"dma\"");
+    assert!(dma.contains("\"tid\":3,\"ph\":\"X\""), "{dma}");
+    assert!(dma.contains("\"ts\":6.000,\"dur\":8.000"), "{dma}");
+
+    let track = find(&events, "\"name\":\"Dma @ $2700\"");
+    let tid = &track[track.find("\"tid\"").unwrap()..track.find(",\"ph\"").unwrap()];
+    let read = find(&events, "\"name\":\"read\"");
+    assert!(read.contains(tid), "{read}");
+    assert!(
+        read.contains("\"ts\":0.000,\"args\":{\"addr\":\"$2706\",\"value\":\"$02\"}"),
+        "{read}"
+    );
+    let write = find(&events, "\"name\":\"write\"");
+    assert!(write.contains("\"value\":\"$01\""), "{write}");
+    assert!(!events.iter().any(|event| event.contains("LDA")));
+}
+
+#[test]
+fn interrupts_show_on_the_cpu_track() {
+    let mut vm = vm_with_handler(&[0xA9, 0x01]);
+    vm.load(0x9000, &[0xA9, 0x02, 0xA9, 0x03]).unwrap();
+    vm.set_registers(Registers {
+        flags: 0,
+        ..vm.registers()
+    });
+    let out = Shared::default();
+    vm.set_trace(TraceConfig::chrome(out.clone()));
+    vm.raise_irq(2);
+    vm.step().unwrap();
+    vm.step().unwrap();
+    vm.ack_irq(2);
+    vm.step().unwrap();
+    vm.set_nmi(true);
+    vm.step().unwrap();
+    vm.step().unwrap();
+    vm.clear_trace();
+    let events = out.events();
+
+    let counters: Vec<_> = events
+        .iter()
+        .filter(|event| event.contains("\"name\":\"pending irqs\""))
+        .collect();
+    assert_eq!(counters.len(), 2, "{counters:#?}");
+    assert!(counters[0].contains("\"ts\":0.000,\"args\":{\"lines\":4}"));
+    assert!(counters[1].contains("\"args\":{\"lines\":0}"));
+    // Taken after the instruction that polled it.
+    let irq = find(&events, "\"name\":\"irq\"");
+    assert!(irq.contains("\"tid\":2,\"ph\":\"i\""), "{irq}");
+    assert!(irq.contains("\"ts\":2.000,\"args\":{\"line\":2}"), "{irq}");
+    let nmi = find(&events, "\"name\":\"nmi\"");
+    assert!(nmi.contains("\"cat\":\"irq\""), "{nmi}");
+    assert!(nmi.contains("\"args\":{\"line\":null}"), "{nmi}");
+}
+
+#[test]
+fn timestamps_follow_the_clock_and_never_go_back() {
+    let mut vm = vm_with_handler(&[0xAD, 0x00, 0x80].repeat(10_000));
+    vm.set_clock_hz(2_000_000);
+    let out = Shared::default();
+    vm.set_trace(TraceConfig::chrome(out.clone()));
+    vm.run_frame().unwrap();
+    vm.reset(ResetKind::Soft);
+    vm.run_frame().unwrap();
+    // Replacing the trace closes it.
+    vm.set_trace(TraceConfig::writer(io::sink()));
+    let events = out.events();
+
+    let frames: Vec<_> = events
+        .iter()
+        .filter(|event| event.contains("\"cat\":\"frame\""))
+        .collect();
+    // 33336 cycles each at 2 MHz, the second after the first despite the
+    // reset.
+    assert_eq!(frames.len(), 2);
+    assert!(
+        frames[0].contains("\"ts\":0.000,\"dur\":16668.000"),
+        "{}",
+        frames[0]
+    );
+    assert!(
+        frames[1].contains("\"ts\":16668.000,\"dur\":16668.000"),
+        "{}",
+        frames[1]
+    );
+}
+
+#[test]
+fn an_empty_trace_is_an_empty_array() {
+    let mut vm = vm_with_handler(&[]);
+    let out = Shared::default();
+    vm.set_trace(TraceConfig::chrome(out.clone()));
+    vm.clear_trace();
+    assert_eq!(*out.out.lock().unwrap(), b"[\n]\n");
+}
+
+#[test]
+fn write_errors_stop_the_trace() {
+    let mut vm = vm_with_handler(&[0xAD, 0x00, 0x80].repeat(10_000));
+    let out = Shared {
+        limit: Some(1),
+        ..Default::default()
+    };
+    vm.set_trace(TraceConfig::chrome(out.clone()));
+    vm.run_frame().unwrap();
+    assert_eq!(vm.take_trace_error().unwrap().to_string(), "full");
+    vm.run_frame().unwrap();
+    vm.clear_trace();
+    assert!(vm.take_trace_error().is_none());
+}
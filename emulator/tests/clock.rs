@@ -0,0 +1,94 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::vm_with;
+use emulator::ResetKind;
+use emulator::audio::{AUDIO_ENABLE, ENABLE_SQUARE1, SQUARE1_CTRL, SQUARE1_PERIOD};
+use emulator::vm::{CLOCK_HZ, CYCLES_PER_FRAME};
+
+#[test]
+fn frames_last_one_frame_of_the_clock() {
+    let mut vm = vm_with(&[0xA9; 0x1000]);
+    assert_eq!(vm.clock_hz(), CLOCK_HZ);
+    assert_eq!(vm.cycles_per_frame(), CYCLES_PER_FRAME);
+
+    vm.set_clock_hz(6_000);
+    assert_eq!(vm.cycles_per_frame(), 100);
+    vm.run_frame().unwrap();
+    assert_eq!(vm.cycles(), 100);
+
+    vm.set_clock_hz(12_000);
+    assert_eq!(vm.cycles_per_frame(), 200);
+    vm.run_frame().unwrap();
+    assert_eq!(vm.cycles(), 300);
+
+    vm.reset(ResetKind::Soft);
+    vm.run_frame().unwrap();
+    assert_eq!(vm.cycles(), 200, "a reset keeps the clock");
+}
+
+#[test]
+fn the_sound_chip_runs_off_the_cpu_clock() {
+    let levels = |clock_hz| {
+        // LDA $8000, enough of it for a frame at 2 MHz.
+        let mut vm = vm_with(&[0xAD, 0x00, 0x80].repeat(10_000));
+        vm.set_clock_hz(clock_hz);
+        vm.set_sample_rate(125_000);
+        vm.load(SQUARE1_PERIOD, &[0x01, 0x00]).unwrap();
+        vm.write(SQUARE1_CTRL, 0x2F);
+        vm.write(AUDIO_ENABLE, ENABLE_SQUARE1);
+        let frames = Arc::new(Mutex::new(Vec::new()));
+        let sink = frames.clone();
+        vm.set_audio_callback(move |samples| sink.lock().unwrap().push(samples.to_vec()));
+        vm.run_frame().unwrap();
+        let frame = frames.lock().unwrap()[0].clone();
+        assert!(
+            (2083..=2084).contains(&frame.len()),
+            "{} samples",
+            frame.len()
+        );
+        frame[..4].iter().map(|&s| s > 0).collect::<Vec<_>>()
+    };
+    // A period of 1 lasts 32 cycles: 4 samples at 1 MHz, 2 at 2 MHz.
+    assert_eq!(levels(1_000_000), [true, true, false, false]);
+    assert_eq!(levels(2_000_000), [true, false, true, false]);
+}
+
+#[test]
+fn wait_states_slow_accesses() {
+    // LDA $9000; LDA $A000
+    let mut vm = vm_with(&[0xAD, 0x00, 0x90, 0xAD, 0x00, 0xA0]);
+    vm.set_wait_states(0x8000..=0x80FF, 1);
+    vm.set_wait_states(0x9000..=0x9FFF, 2);
+    vm.set_wait_states(0xA000..=0xA0FF, 7);
+    assert_eq!(vm.wait_states(0x9ABC), 2);
+    vm.step().unwrap();
+    assert_eq!(vm.cycles(), 4 + 3 + 2);
+
+    // A mirror costs what its target does.
+    vm.mirror(0xA000..=0xA0FF, 0x9000).unwrap();
+    vm.step().unwrap();
+    assert_eq!(vm.cycles(), 9 + 4 + 3 + 2);
+
+    vm.set_wait_states(0x0000..=0xFFFF, 0);
+    vm.reset(ResetKind::Soft);
+    vm.run_cycles(8).unwrap();
+    assert_eq!(vm.cycles(), 8);
+}
+
+#[test]
+fn batches_charge_wait_states_too() {
+    let mut stepped = vm_with(&[0xAD, 0x00, 0x90].repeat(100));
+    let mut batched = vm_with(&[0xAD, 0x00, 0x90].repeat(100));
+    for vm in [&mut stepped, &mut batched] {
+        vm.set_wait_states(0x8000..=0x81FF, 1);
+        vm.set_wait_states(0x9000..=0x90FF, 3);
+    }
+    for _ in 0..50 {
+        stepped.step().unwrap();
+    }
+    batched.run_cycles(50 * 10 - 1).unwrap();
+    assert_eq!(batched.cycles(), stepped.cycles());
+    assert_eq!(batched.registers(), stepped.registers());
+}
@@ -0,0 +1,22 @@
+//! Fixtures shared by the integration tests.
+
+#![allow(dead_code)]
+
+use emulator::{ResetKind, Vm};
+
+/// A machine reset into `program`, loaded at 0x8000.
+pub fn vm_with(program: &[u8]) -> Vm {
+    let mut vm = Vm::new();
+    vm.load(0x8000, program).unwrap();
+    vm.load(0xFFFC, &[0x00, 0x80]).unwrap();
+    vm.reset(ResetKind::Soft);
+    vm
+}
+
+/// Like [`vm_with`], with the IRQ vector pointing at 0x9000 for a handler
+/// the test loads there.
+pub fn vm_with_handler(program: &[u8]) -> Vm {
+    let mut vm = vm_with(program);
+    vm.load(0xFFFE, &[0x00, 0x90]).unwrap();
+    vm
+}
@@ -0,0 +1,93 @@
+mod common;
+
+use common::vm_with;
+use emulator::compare::{self, Checks, Divergence, Mismatch};
+use emulator::display::{BG_PALETTE, CTRL, CTRL_BACKGROUND, TILE_DATA, TILEMAP};
+use emulator::{Vm, VmError};
+
+/// A machine running `program` from 0x8000, then `LSR $0300` over and over.
+fn vm_running(program: &[u8]) -> Vm {
+    let mut image = program.to_vec();
+    image.extend([0x4E, 0x00, 0x03].repeat(10000));
+    vm_with(&image)
+}
+
+#[test]
+fn identical_machines_agree() {
+    let (mut left, mut right) = (vm_running(&[0xA9, 0x01]), vm_running(&[0xA9, 0x01]));
+    assert_eq!(
+        compare::frames(&mut left, &mut right, 3, &Checks::default()),
+        Ok(())
+    );
+    assert_eq!(left.frame(), 3);
+}
+
+#[test]
+fn reports_the_first_difference_checked() {
+    let (mut left, mut right) = (vm_running(&[0xA9, 0x01]), vm_running(&[0xA9, 0x02]));
+    let divergence = compare::steps(&mut left, &mut right, 10, &Checks::default()).unwrap_err();
+    assert_eq!(divergence.after, 0);
+    assert!(matches!(divergence.mismatch, Mismatch::Registers(l, r) if (l.a, r.a) == (1, 2)));
+
+    let (mut left, mut right) = (vm_running(&[]), vm_running(&[]));
+    right.write(0x0300, 0x80);
+    let checks = Checks {
+        registers: false,
+        ..Checks::default()
+    };
+    let divergence = compare::steps(&mut left, &mut right, 10, &checks).unwrap_err();
+    assert_eq!(
+        divergence,
+        Divergence {
+            after: 0,
+            mismatch: Mismatch::Memory {
+                addr: 0x0300,
+                left: 0,
+                right: 0x40,
+            },
+        }
+    );
+    assert_eq!(
+        divergence.to_string(),
+        "machines diverged after 0: memory 0x0300: 0x00 and 0x40"
+    );
+}
+
+#[test]
+fn compares_whether_each_side_failed() {
+    let (mut left, mut right) = (vm_running(&[0xA9, 0x01]), vm_running(&[0xA9, 0x01, 0x02]));
+    let checks = Checks {
+        memory: Some(0x0000..=0x7FFF),
+        ..Checks::default()
+    };
+    let divergence = compare::steps(&mut left, &mut right, 10, &checks).unwrap_err();
+    assert_eq!(divergence.after, 1);
+    assert_eq!(
+        divergence.mismatch,
+        Mismatch::Result(
+            None,
+            Some(VmError::IllegalOpcode {
+                pc: 0x8002,
+                opcode: 0x02
+            })
+        )
+    );
+}
+
+#[test]
+fn compares_framebuffers_once_a_frame_ends() {
+    let (mut left, mut right) = (vm_running(&[]), vm_running(&[]));
+    for vm in [&mut left, &mut right] {
+        vm.load(TILE_DATA + 16, &[0xFF; 16]).unwrap();
+        vm.write(BG_PALETTE, 0b11_10_01_00);
+        vm.write(CTRL, CTRL_BACKGROUND);
+    }
+    right.write(TILEMAP + 1, 1);
+    let checks = Checks {
+        memory: Some(0x0000..=0x1FFF),
+        ..Checks::default()
+    };
+    let divergence = compare::frames(&mut left, &mut right, 3, &checks).unwrap_err();
+    assert_eq!(divergence.after, 0);
+    assert_eq!(divergence.mismatch, Mismatch::Framebuffer { offset: 8 * 4 });
+}
@@ -0,0 +1,363 @@
+use emulator::config::{ConfigError, ConfigErrorKind, DeviceConfig, Mirror, Region};
+use emulator::display::{DisplayConfig, HEIGHT, PixelFormat, WIDTH};
+use emulator::input::{Controller, INPUT_PORTS};
+use emulator::timer::Timer;
+use emulator::{
+    BusConfig, CpuConfig, IllegalOpcodes, MachineConfig, ResetKind, RomWrite, UnmappedRead,
+    UnmappedWrite, Vm, VmError,
+};
+
+const BOARD: &str = r#"
+# 16 KiB of RAM, half of it seen twice, a slow ROM window and two devices.
+[cpu]
+clock_hz = 2_000_000
+illegal_opcodes = "undocumented"
+
+[bus]
+unmapped_read = "trap"    # "open-bus", "zero" or "trap"
+unmapped_write = "ignore"
+rom_write = "trap"
+
+[display]
+format = "rgb565"
+scale = 2
+
+[[ram]]
+start = 0x0000
+end = 0x3FFF
+
+[[mirror]]
+start = 0x4000
+end = 0x5FFF
+target = 0x0000
+
+[[rom]]
+start = 0x8000
+end = 0xFFFF
+wait = 1
+
+[[device]]
+kind = "controller"
+
+[[device]]
+kind = "timer"
+start = 0x2718
+line = 3
+"#;
+
+#[test]
+fn parses_a_board() {
+    let config = MachineConfig::from_toml(BOARD).unwrap();
+    assert_eq!(
+        config,
+        MachineConfig {
+            cpu: CpuConfig {
+                clock_hz: Some(2_000_000),
+                illegal_opcodes: IllegalOpcodes::Undocumented,
+            },
+            bus: BusConfig {
+                unmapped_read: UnmappedRead::Trap,
+                unmapped_write: UnmappedWrite::Ignore,
+                rom_write: RomWrite::Trap,
+            },
+            display: DisplayConfig {
+                format: PixelFormat::Rgb565,
+                scale: 2,
+            },
+            ram: vec![Region {
+                start: 0x0000,
+                end: 0x3FFF,
+                wait: 0,
+            }],
+            rom: vec![Region {
+                start: 0x8000,
+                end: 0xFFFF,
+                wait: 1,
+            }],
+            mirrors: vec![Mirror {
+                start: 0x4000,
+                end: 0x5FFF,
+                target: 0x0000,
+            }],
+            devices: vec![
+                DeviceConfig::Controller { start: None },
+                DeviceConfig::Timer {
+                    start: Some(0x2718),
+                    line: 3,
+                },
+            ],
+        }
+    );
+    assert_eq!(MachineConfig::from_toml(""), Ok(MachineConfig::default()));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_reads_descriptions_as_the_parser_does() {
+    let devices = r#"
+[[device]]
+kind = "timer"
+[[device]]
+kind = "uart"
+[[device]]
+kind = "rtc"
+start = 0x3000
+[[device]]
+kind = "rng"
+[[device]]
+kind = "dma"
+"#;
+    for text in [BOARD, devices, "[cpu]\nillegal_opcodes = \"nop\"", ""] {
+        let parsed = MachineConfig::from_toml(text).unwrap();
+        let deserialized: MachineConfig = toml::from_str(text).unwrap();
+        assert_eq!(deserialized, parsed, "{text}");
+    }
+}
+
+#[test]
+fn builds_the_described_board() {
+    let config = MachineConfig::from_toml(BOARD).unwrap();
+    let mut vm = Vm::with_config(&config).unwrap();
+    assert!(
+        vm.bus()
+            .device::<Controller>(*INPUT_PORTS.start())
+            .is_some()
+    );
+    assert!(vm.bus().device::<Timer>(0x271F).is_some());
+    assert!(vm.bus().is_ram(0x3FFF));
+    assert!(!vm.bus().is_ram(0x4000));
+    assert_eq!(vm.clock_hz(), 2_000_000);
+    assert_eq!(vm.cpu_config(), config.cpu);
+    assert_eq!((vm.wait_states(0x3FFF), vm.wait_states(0x8000)), (0, 1));
+    assert_eq!(vm.framebuffer().len(), WIDTH * 2 * HEIGHT * 2 * 2);
+
+    // LDA $4010; LSR $4011; LSR $8100
+    vm.load(
+        0x8000,
+        &[0xAD, 0x10, 0x40, 0x4E, 0x11, 0x40, 0x4E, 0x00, 0x81],
+    )
+    .unwrap();
+    vm.load(0xFFFC, &[0x00, 0x80]).unwrap();
+    vm.load(0x0010, &[0x42, 0x80]).unwrap();
+    vm.write(0x8100, 0x80);
+    vm.reset(ResetKind::Soft);
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 0x42);
+    vm.step().unwrap();
+    assert_eq!(vm.read(0x0011), 0x40, "the mirror reaches RAM");
+    assert_eq!(
+        vm.step(),
+        Err(VmError::RomWrite {
+            pc: 0x8006,
+            addr: 0x8100,
+            val: 0x40,
+        })
+    );
+    assert_eq!(vm.read(0x8100), 0x80, "ROM is write-protected");
+}
+
+#[test]
+fn unlisted_pages_are_unmapped() {
+    let config = MachineConfig::from_toml(BOARD).unwrap();
+    let mut vm = Vm::with_config(&config).unwrap();
+    // LDA $6000
+    vm.load(0x8000, &[0xAD, 0x00, 0x60]).unwrap();
+    vm.set_registers(emulator::Registers {
+        pc: 0x8000,
+        ..vm.registers()
+    });
+    vm.write(0x6000, 0x42);
+    assert!(matches!(
+        vm.step(),
+        Err(VmError::BusFault { addr: 0x6000, .. })
+    ));
+}
+
+#[test]
+fn the_stock_board_is_vm_new() {
+    let vm = Vm::with_config(&MachineConfig::stock()).unwrap();
+    assert!(
+        vm.bus()
+            .device::<Controller>(*INPUT_PORTS.start())
+            .is_some()
+    );
+    assert_eq!(
+        vm.bus().mappings().count(),
+        Vm::new().bus().mappings().count()
+    );
+    assert!((0..=0xFFFF).all(|addr| vm.bus().is_ram(addr)));
+}
+
+#[test]
+fn mirrors_must_fit() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        vm.mirror(0x0000..=0x0FFF, 0xF800),
+        Err(VmError::OutOfBounds {
+            addr: 0xF800,
+            len: 0x1000,
+        })
+    );
+    vm.mirror(0x0000..=0x0FFF, 0xF000).unwrap();
+
+    let config = MachineConfig {
+        devices: vec![
+            DeviceConfig::Controller { start: None },
+            DeviceConfig::Dma {
+                start: Some(*INPUT_PORTS.start()),
+            },
+        ],
+        ..MachineConfig::stock()
+    };
+    assert!(matches!(
+        Vm::with_config(&config),
+        Err(VmError::MapConflict { .. })
+    ));
+}
+
+#[test]
+fn reports_bad_descriptions_by_line() {
+    let cases: &[(&str, usize, ConfigErrorKind)] = &[
+        ("[[ram]]\nstart 0", 2, ConfigErrorKind::Syntax),
+        ("[ram]", 1, ConfigErrorKind::UnknownTable("ram".into())),
+        (
+            "[[flash]]",
+            1,
+            ConfigErrorKind::UnknownTable("flash".into()),
+        ),
+        (
+            "[bus]\n[bus]",
+            2,
+            ConfigErrorKind::DuplicateTable("bus".into()),
+        ),
+        (
+            "[cpu]\n[bus]\n[cpu]",
+            3,
+            ConfigErrorKind::DuplicateTable("cpu".into()),
+        ),
+        (
+            "[cpu]\nclock_hz = 59",
+            1,
+            ConfigErrorKind::BadValue("clock_hz".into()),
+        ),
+        (
+            "[cpu]\nillegal_opcodes = \"halt\"",
+            2,
+            ConfigErrorKind::BadValue("illegal_opcodes".into()),
+        ),
+        (
+            "[display]\nformat = \"rgb888\"",
+            2,
+            ConfigErrorKind::BadValue("format".into()),
+        ),
+        (
+            "[display]\nscale = 0",
+            1,
+            ConfigErrorKind::BadValue("scale".into()),
+        ),
+        ("start = 0", 1, ConfigErrorKind::UnknownKey("start".into())),
+        (
+            "[[ram]]\nstart = 0\nend = 1\nsize = 2",
+            4,
+            ConfigErrorKind::UnknownKey("size".into()),
+        ),
+        (
+            "[[ram]]\nstart = 0\nstart = 1",
+            3,
+            ConfigErrorKind::DuplicateKey("start".into()),
+        ),
+        (
+            "\n[[rom]]\nstart = 0",
+            2,
+            ConfigErrorKind::MissingKey("end"),
+        ),
+        (
+            "[[ram]]\nstart = 0x10000\nend = 0",
+            2,
+            ConfigErrorKind::BadValue("start".into()),
+        ),
+        (
+            "[[ram]]\nstart = \"0\"\nend = 0",
+            2,
+            ConfigErrorKind::BadValue("start".into()),
+        ),
+        (
+            "[bus]\nunmapped_read = \"float\"",
+            2,
+            ConfigErrorKind::BadValue("unmapped_read".into()),
+        ),
+        (
+            "[[ram]]\nstart = 0x200\nend = 0x1FF",
+            1,
+            ConfigErrorKind::BadRange,
+        ),
+        (
+            "[[mirror]]\nstart = 0\nend = 0xFF\ntarget = 0x100\nwait = 1",
+            5,
+            ConfigErrorKind::UnknownKey("wait".into()),
+        ),
+        (
+            "[[rom]]\nstart = 0\nend = 0xFF\nwait = 256",
+            4,
+            ConfigErrorKind::BadValue("wait".into()),
+        ),
+        (
+            "[[mirror]]\nstart = 0x100\nend = 0x17F\ntarget = 0",
+            1,
+            ConfigErrorKind::Misaligned,
+        ),
+        (
+            "[[mirror]]\nstart = 0\nend = 0x1FF\ntarget = 0xFF00",
+            1,
+            ConfigErrorKind::BadRange,
+        ),
+        (
+            "[[device]]\nkind = \"gpu\"",
+            2,
+            ConfigErrorKind::UnknownDevice("gpu".into()),
+        ),
+        (
+            "[[device]]\nkind = \"timer\"\nline = 8",
+            1,
+            ConfigErrorKind::BadValue("line".into()),
+        ),
+        (
+            "[[device]]\nkind = \"dma\"\nstart = 0xFFF8",
+            1,
+            ConfigErrorKind::BadRange,
+        ),
+    ];
+    for (text, line, kind) in cases {
+        assert_eq!(
+            MachineConfig::from_toml(text),
+            Err(ConfigError {
+                line: *line,
+                kind: kind.clone(),
+            }),
+            "{text}"
+        );
+    }
+    let err = MachineConfig::from_toml("[[rom]]\nstart = 0").unwrap_err();
+    assert_eq!(err.to_string(), "line 1: missing key `end`");
+}
+
+#[test]
+fn reads_numbers_strings_and_comments() {
+    let text = "[[device]] # the UART\nkind = \"uart\" # on stdio\nstart = 0b0010_0111_0010_0000\nstdio = false\n[[ram]]\nstart = 1_024\nend = 0x7F_FF";
+    let config = MachineConfig::from_toml(text).unwrap();
+    assert_eq!(
+        config.devices,
+        [DeviceConfig::Uart {
+            start: Some(0x2720),
+            stdio: false,
+        }]
+    );
+    assert_eq!(
+        config.ram,
+        [Region {
+            start: 1024,
+            end: 0x7FFF,
+            wait: 0,
+        }]
+    );
+}
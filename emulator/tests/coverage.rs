@@ -0,0 +1,119 @@
+use emulator::coverage::Coverage;
+use emulator::{Registers, ResetKind, Rom, Vm};
+
+/// `LDA #$42; LSR $0010; LDX #$01; LDY #$02`, then an illegal opcode.
+const PROGRAM: [u8; 9] = [0xA9, 0x42, 0x4E, 0x10, 0x00, 0xA2, 0x01, 0xA0, 0x02];
+
+fn boot() -> Vm {
+    let mut vm = Vm::new();
+    vm.load_rom(&Rom::new(0xC000, &PROGRAM).unwrap()).unwrap();
+    vm
+}
+
+fn jump(vm: &mut Vm, pc: u16) {
+    vm.set_registers(Registers {
+        pc,
+        ..vm.registers()
+    });
+}
+
+#[test]
+fn disabled_by_default() {
+    let mut vm = boot();
+    vm.step().unwrap();
+    assert!(vm.coverage().is_none());
+    assert!(vm.take_coverage().is_none());
+}
+
+#[test]
+fn executed_instructions_are_marked() {
+    let mut vm = boot();
+    vm.enable_coverage();
+    vm.step().unwrap();
+    vm.step().unwrap();
+    let coverage = vm.coverage().unwrap();
+    assert_eq!(coverage.executed().collect::<Vec<_>>(), [0xC000, 0xC002]);
+    assert_eq!(coverage.len(), 2);
+    assert!(!coverage.is_executed(0xC001));
+    assert_eq!(coverage.bitmap()[0xC000 / 8], 0b0000_0101);
+}
+
+#[test]
+fn illegal_opcodes_are_not_marked() {
+    let mut vm = boot();
+    vm.enable_coverage();
+    while vm.step().is_ok() {}
+    assert!(!vm.coverage().unwrap().is_executed(0xC009));
+    assert_eq!(vm.coverage().unwrap().len(), 4);
+}
+
+#[test]
+fn blocks_split_where_control_jumps() {
+    let mut vm = boot();
+    vm.enable_coverage();
+    vm.step().unwrap();
+    vm.step().unwrap();
+    // Skip the LDX and land on the LDY.
+    jump(&mut vm, 0xC007);
+    vm.step().unwrap();
+    jump(&mut vm, 0xC005);
+    vm.step().unwrap();
+    assert_eq!(
+        vm.coverage().unwrap().blocks(&vm),
+        [0xC000..=0xC002, 0xC005..=0xC005, 0xC007..=0xC007]
+    );
+}
+
+#[test]
+fn coverage_survives_reset_and_merges() {
+    let mut vm = boot();
+    vm.enable_coverage();
+    vm.step().unwrap();
+    vm.reset(ResetKind::Soft);
+    jump(&mut vm, 0xC005);
+    vm.step().unwrap();
+    let first = vm.take_coverage().unwrap();
+    assert_eq!(first.executed().collect::<Vec<_>>(), [0xC000, 0xC005]);
+
+    let mut other = boot();
+    other.enable_coverage();
+    jump(&mut other, 0xC007);
+    other.step().unwrap();
+    let mut merged = Coverage::new();
+    merged.merge(&first);
+    merged.merge(other.coverage().unwrap());
+    assert_eq!(merged.len(), 3);
+}
+
+#[test]
+fn listing_marks_executed_code() {
+    let mut vm = boot();
+    vm.enable_coverage();
+    vm.step().unwrap();
+    jump(&mut vm, 0xC006);
+    // Run code from the middle of the LDX: its operand becomes an LDA.
+    vm.write(0xC006, 0xA9);
+    vm.step().unwrap();
+    assert_eq!(
+        vm.coverage().unwrap().listing(&vm, 0xC000..=0xC008),
+        "\
+* C000  A9 42     LDA #$42
+  C002  4E 10 00  LSR $0010
+  C005  A2        .byte $A2
+* C006  A9 A0     LDA #$A0
+  C008  02        ???
+; 2 of 4 instructions executed (50.0%)
+"
+    );
+}
+
+#[test]
+fn bitmap_dumps_to_a_writer() {
+    let mut vm = boot();
+    vm.enable_coverage();
+    vm.step().unwrap();
+    let mut out = Vec::new();
+    vm.coverage().unwrap().write_bitmap(&mut out).unwrap();
+    assert_eq!(out.len(), 0x2000);
+    assert_eq!(out, vm.coverage().unwrap().bitmap());
+}
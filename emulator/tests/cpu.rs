@@ -0,0 +1,203 @@
+//! Kernel behavior checks, run against whichever core the crate was built
+//! with (`cargo test` for C, `cargo test --features pure-rust` for Rust).
+
+use emulator::ffi::{
+    self, Cpu, FLAG_C, FLAG_D, FLAG_I, FLAG_N, FLAG_V, FLAG_Z, ILLEGAL_NOP, ILLEGAL_UNDOCUMENTED,
+    INT_IRQ, RVM_ILLEGAL_OPCODE, RVM_MEM_SIZE, RVM_OK,
+};
+
+/// Places `program` at 0x8000, points the reset vector at it and initializes
+/// a CPU over the resulting memory.
+fn boot(program: &[u8]) -> (Box<Cpu>, Box<[u8]>) {
+    let mut memory = vec![0u8; RVM_MEM_SIZE].into_boxed_slice();
+    memory[0xFFFC] = 0x00;
+    memory[0xFFFD] = 0x80;
+    memory[0x8000..0x8000 + program.len()].copy_from_slice(program);
+
+    let mut cpu = Box::<Cpu>::default();
+    unsafe { ffi::cpu_init(&mut *cpu, memory.as_mut_ptr()) };
+    (cpu, memory)
+}
+
+fn step(cpu: &mut Cpu) -> i32 {
+    unsafe { ffi::cpu_step(cpu) }
+}
+
+#[test]
+fn init_loads_reset_vector() {
+    let (cpu, _memory) = boot(&[]);
+    assert_eq!(cpu.pc, 0x8000);
+    assert_eq!(cpu.sp, 0xFD);
+    assert_eq!(cpu.cycles, 0);
+}
+
+#[test]
+fn simple_addition() {
+    // LDA #$05; ADC #$0A
+    let (mut cpu, _memory) = boot(&[0xA9, 0x05, 0x69, 0x0A]);
+
+    step(&mut cpu);
+    assert_eq!(cpu.pc, 0x8002);
+    assert_eq!(cpu.a, 5);
+    assert_eq!(cpu.flags & (FLAG_Z | FLAG_N), 0);
+
+    step(&mut cpu);
+    assert_eq!(cpu.pc, 0x8004);
+    assert_eq!(cpu.a, 15);
+    assert_eq!(cpu.cycles, 4);
+}
+
+#[test]
+fn adc_sets_carry_and_zero() {
+    // LDA #$FF; ADC #$01
+    let (mut cpu, _memory) = boot(&[0xA9, 0xFF, 0x69, 0x01]);
+    step(&mut cpu);
+    step(&mut cpu);
+    assert_eq!(cpu.a, 0);
+    assert_ne!(cpu.flags & FLAG_Z, 0);
+    assert_ne!(cpu.flags & FLAG_C, 0);
+}
+
+#[test]
+fn ldy_loads_y() {
+    // LDX #$11; LDY #$22
+    let (mut cpu, _memory) = boot(&[0xA2, 0x11, 0xA0, 0x22]);
+    step(&mut cpu);
+    step(&mut cpu);
+    assert_eq!(cpu.x, 0x11);
+    assert_eq!(cpu.y, 0x22);
+}
+
+#[test]
+fn lsr_zeropage_shifts_memory() {
+    // LSR $10; LSR A
+    let (mut cpu, mut memory) = boot(&[0x46, 0x10, 0x4A]);
+    memory[0x10] = 0x03;
+    step(&mut cpu);
+    assert_eq!(memory[0x10], 0x01);
+    assert_ne!(cpu.flags & FLAG_C, 0);
+    assert_eq!(cpu.pc, 0x8002);
+    assert_eq!(cpu.cycles, 5);
+
+    step(&mut cpu);
+    assert_eq!(cpu.a, 0);
+    assert_ne!(cpu.flags & FLAG_Z, 0);
+    assert_eq!(cpu.cycles, 7);
+}
+
+/// Runs `SED; LDA #a; op #value` with the carry set to `carry` and returns
+/// A and the flags.
+fn decimal_op(op: u8, a: u8, value: u8, carry: bool) -> (u8, u8) {
+    let (mut cpu, _memory) = boot(&[0xF8, 0xA9, a, op, value]);
+    cpu.flags = if carry { FLAG_C } else { 0 };
+    for _ in 0..3 {
+        assert_eq!(step(&mut cpu), RVM_OK);
+    }
+    assert_ne!(cpu.flags & FLAG_D, 0);
+    (cpu.a, cpu.flags & (FLAG_N | FLAG_V | FLAG_Z | FLAG_C))
+}
+
+#[test]
+fn decimal_mode_adds_and_subtracts_bcd() {
+    const ADC: u8 = 0x69;
+    const SBC: u8 = 0xE9;
+    assert_eq!(decimal_op(ADC, 0x12, 0x34, false), (0x46, 0));
+    // N and V come from the sum before the high digit is adjusted (0xA5).
+    assert_eq!(
+        decimal_op(ADC, 0x58, 0x46, true),
+        (0x05, FLAG_N | FLAG_V | FLAG_C)
+    );
+    assert_eq!(decimal_op(ADC, 0x81, 0x92, false), (0x73, FLAG_V | FLAG_C));
+    // Z comes from the binary sum (0x9A).
+    assert_eq!(decimal_op(ADC, 0x99, 0x01, false), (0x00, FLAG_N | FLAG_C));
+
+    assert_eq!(decimal_op(SBC, 0x46, 0x12, true), (0x34, FLAG_C));
+    assert_eq!(decimal_op(SBC, 0x32, 0x02, false), (0x29, FLAG_C));
+    assert_eq!(decimal_op(SBC, 0x12, 0x21, true), (0x91, FLAG_N));
+    assert_eq!(decimal_op(SBC, 0x21, 0x34, true), (0x87, FLAG_N));
+}
+
+#[test]
+fn cld_returns_to_binary_arithmetic() {
+    // SED; CLD; LDA #$50; SBC #$B0, with the carry set
+    let (mut cpu, _memory) = boot(&[0xF8, 0xD8, 0xA9, 0x50, 0xE9, 0xB0]);
+    cpu.flags = FLAG_C;
+    for _ in 0..4 {
+        assert_eq!(step(&mut cpu), RVM_OK);
+    }
+    assert_eq!(cpu.a, 0xA0);
+    assert_eq!(
+        cpu.flags & (FLAG_D | FLAG_N | FLAG_V | FLAG_C),
+        FLAG_N | FLAG_V
+    );
+    assert_eq!(cpu.cycles, 2 + 2 + 2 + 2);
+}
+
+#[test]
+fn interrupts_are_polled_at_the_end_of_instructions() {
+    // CLI; LDA #$01; LDA #$02, with an IRQ handler at 0x9000 and an NMI
+    // handler at 0xA000.
+    let (mut cpu, mut memory) = boot(&[0x58, 0xA9, 0x01, 0xA9, 0x02]);
+    memory[0xFFFA..0xFFFC].copy_from_slice(&[0x00, 0xA0]);
+    memory[0xFFFE..].copy_from_slice(&[0x00, 0x90]);
+    cpu.irq = 1;
+
+    // CLI's own poll still sees the I flag set.
+    step(&mut cpu);
+    assert_eq!(cpu.flags & FLAG_I, 0);
+    step(&mut cpu);
+    assert_ne!(cpu.int_state & INT_IRQ, 0);
+    step(&mut cpu);
+    assert_eq!(cpu.pc, 0x9000);
+
+    // An NMI ignores the I flag the entry set, but needs an edge.
+    cpu.pc = 0x8001;
+    cpu.nmi = 1;
+    step(&mut cpu);
+    step(&mut cpu);
+    assert_eq!(cpu.pc, 0xA000);
+    cpu.pc = 0x8001;
+    step(&mut cpu);
+    step(&mut cpu);
+    assert_eq!(cpu.pc, 0x8005);
+    assert_eq!(cpu.stats.irqs, 2);
+}
+
+#[test]
+fn illegal_opcode_is_reported_and_skipped() {
+    let (mut cpu, _memory) = boot(&[0xFF, 0xA9, 0x42]);
+    assert_eq!(step(&mut cpu), RVM_ILLEGAL_OPCODE);
+    assert_eq!(cpu.pc, 0x8001);
+    assert_eq!(step(&mut cpu), RVM_OK);
+    assert_eq!(cpu.a, 0x42);
+}
+
+#[test]
+fn illegal_opcodes_can_be_nops() {
+    let (mut cpu, _memory) = boot(&[0xFF, 0xAF, 0x34, 0x12]);
+    cpu.illegal_mode = ILLEGAL_NOP;
+    assert_eq!(step(&mut cpu), RVM_OK);
+    assert_eq!(step(&mut cpu), RVM_OK);
+    assert_eq!(cpu.pc, 0x8002, "one byte each");
+    assert_eq!(cpu.cycles, 4);
+    assert_eq!(cpu.stats.opcodes[0xFF], 1);
+}
+
+#[test]
+fn undocumented_opcodes_do_what_a_6502_does() {
+    // LAX $1300; NOP $20,X; NOP #$EA; then a jam.
+    let (mut cpu, mut memory) = boot(&[0xAF, 0x00, 0x13, 0x34, 0x20, 0x80, 0xEA, 0x02]);
+    memory[0x1300] = 0x80;
+    cpu.x = 2;
+    cpu.illegal_mode = ILLEGAL_UNDOCUMENTED;
+
+    assert_eq!(step(&mut cpu), RVM_OK);
+    assert_eq!((cpu.a, cpu.x), (0x80, 0x80));
+    assert_ne!(cpu.flags & FLAG_N, 0);
+    assert_eq!(step(&mut cpu), RVM_OK);
+    assert_eq!(step(&mut cpu), RVM_OK);
+    assert_eq!(cpu.pc, 0x8007);
+    assert_eq!(cpu.cycles, 4 + 4 + 2);
+    assert_eq!((cpu.a, cpu.x), (0x80, 0x80), "NOPs leave the registers");
+    assert_eq!(step(&mut cpu), RVM_ILLEGAL_OPCODE);
+}
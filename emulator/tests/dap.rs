@@ -0,0 +1,286 @@
+#![cfg(feature = "dap")]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::thread;
+
+use emulator::ffi::RVM_MEM_SIZE;
+use emulator::input::CONTROLLER;
+use emulator::{ResetKind, Vm};
+
+/// Minimal client side of the protocol, working on raw JSON text.
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    seq: u32,
+}
+
+impl Client {
+    /// Sends a request and returns its response, asserting it succeeded.
+    fn request(&mut self, command: &str, arguments: &str) -> String {
+        let response = self.try_request(command, arguments);
+        assert!(response.contains(r#""success":true"#), "{response}");
+        response
+    }
+
+    fn try_request(&mut self, command: &str, arguments: &str) -> String {
+        self.send(command, arguments);
+        let response = self.next();
+        assert!(
+            response.contains(&format!(r#""request_seq":{}"#, self.seq)),
+            "{response}"
+        );
+        response
+    }
+
+    fn send(&mut self, command: &str, arguments: &str) {
+        self.seq += 1;
+        let content = format!(
+            r#"{{"seq":{},"type":"request","command":"{command}","arguments":{arguments}}}"#,
+            self.seq
+        );
+        write!(
+            self.writer,
+            "Content-Length: {}\r\n\r\n{content}",
+            content.len()
+        )
+        .unwrap();
+    }
+
+    /// The next message from the adapter.
+    fn next(&mut self) -> String {
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).unwrap();
+            match line.trim_end().split_once(": ") {
+                Some(("Content-Length", value)) => length = value.parse().unwrap(),
+                _ if line == "\r\n" => break,
+                _ => panic!("bad header {line:?}"),
+            }
+        }
+        let mut content = vec![0; length];
+        self.reader.read_exact(&mut content).unwrap();
+        String::from_utf8(content).unwrap()
+    }
+
+    /// The next message, asserting it is the event `name`.
+    fn event(&mut self, name: &str) -> String {
+        let event = self.next();
+        assert!(
+            event.contains(&format!(r#""type":"event","event":"{name}""#)),
+            "{event}"
+        );
+        event
+    }
+
+    fn stopped(&mut self, reason: &str) -> String {
+        let event = self.event("stopped");
+        assert!(
+            event.contains(&format!(r#""reason":"{reason}""#)),
+            "{event}"
+        );
+        event
+    }
+
+    fn initialize(&mut self) {
+        let response = self.request("initialize", r#"{"adapterID":"rvm8"}"#);
+        assert!(response.contains(r#""supportsDisassembleRequest":true"#));
+        self.event("initialized");
+    }
+}
+
+/// Runs a session on `vm` against `script`, which drives the client.
+fn debug(vm: &mut Vm, script: impl FnOnce(&mut Client) + Send + 'static) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = thread::spawn(move || {
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut client = Client {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            writer: stream,
+            seq: 0,
+        };
+        script(&mut client);
+        client.request("disconnect", "{}");
+    });
+    let (stream, _) = listener.accept().unwrap();
+    vm.run_dap_session(stream).unwrap();
+    client.join().unwrap();
+}
+
+fn temp(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rvm8-dap-{}-{name}", std::process::id()))
+}
+
+const SOURCE: &str = "\
+        .org $C000
+start:  LDA #$01
+        LDX #$02
+        ; the loop
+loop:   LDY #$03
+        LDA $0200
+        .byte $02
+        .org $FFFC
+        .word start
+";
+
+#[test]
+fn debugs_assembly_at_the_source_level() {
+    let path = temp("demo.asm");
+    std::fs::write(&path, SOURCE).unwrap();
+    let program = path.to_str().unwrap().replace('\\', "\\\\");
+    let mut vm = Vm::new();
+    debug(&mut vm, move |dap| {
+        dap.initialize();
+        let launch = format!(r#"{{"program":"{program}","stopOnEntry":true}}"#);
+        dap.request("launch", &launch);
+        let breakpoints = dap.request(
+            "setBreakpoints",
+            &format!(
+                r#"{{"source":{{"path":"{program}"}},"breakpoints":[{{"line":4}},{{"line":8}}]}}"#
+            ),
+        );
+        assert!(
+            breakpoints.contains(r#"{"verified":true,"line":5,"instructionReference":"0xC004"}"#),
+            "{breakpoints}"
+        );
+        assert!(breakpoints.contains(r#""verified":false"#));
+        dap.request("configurationDone", "{}");
+        dap.stopped("entry");
+
+        let trace = dap.request("stackTrace", r#"{"threadId":1}"#);
+        assert!(trace.contains(r#""name":"start""#), "{trace}");
+        assert!(trace.contains(r#""line":2"#));
+        assert!(trace.contains(r#""instructionPointerReference":"0xC000""#));
+
+        dap.request("next", r#"{"threadId":1}"#);
+        dap.stopped("step");
+        let registers = dap.request("variables", r#"{"variablesReference":1}"#);
+        assert!(
+            registers.contains(r#""name":"A","value":"$01""#),
+            "{registers}"
+        );
+        assert!(registers.contains(r#""name":"PC","value":"$C002""#));
+
+        dap.request("continue", r#"{"threadId":1}"#);
+        dap.stopped("breakpoint");
+        let trace = dap.request("stackTrace", r#"{"threadId":1}"#);
+        assert!(trace.contains(r#""name":"loop","#), "{trace}");
+        assert!(trace.contains(r#""line":5"#));
+
+        let set = dap.request(
+            "setVariable",
+            r#"{"variablesReference":1,"name":"A","value":"$10"}"#,
+        );
+        assert!(set.contains(r#""value":"$10""#));
+        let value = dap.request("evaluate", r#"{"expression":"A + X"}"#);
+        assert!(value.contains(r#""result":"18 ($12)""#), "{value}");
+        let error = dap.try_request("evaluate", r#"{"expression":"Q"}"#);
+        assert!(error.contains(r#""success":false"#));
+        assert!(error.contains("is not a register or symbol"));
+
+        dap.request("continue", r#"{"threadId":1}"#);
+        let stop = dap.stopped("exception");
+        assert!(stop.contains("illegal opcode 0x02"), "{stop}");
+    });
+    assert_eq!(vm.registers().a, 0x00, "loaded from $0200");
+    assert!(vm.breakpoints().is_empty());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn reads_writes_and_disassembles_memory() {
+    let mut vm = Vm::new();
+    vm.load(0x8000, &[0xA9, 0x42, 0xA2, 0x01, 0x4E, 0x00, 0x02])
+        .unwrap();
+    debug(&mut vm, |dap| {
+        dap.initialize();
+        dap.request("attach", r#"{"stopOnEntry":true}"#);
+        dap.request("configurationDone", "{}");
+        dap.stopped("entry");
+
+        let read = dap.request(
+            "readMemory",
+            r#"{"memoryReference":"0x8000","offset":2,"count":2}"#,
+        );
+        assert!(
+            read.contains(r#""address":"0x8002","data":"ogE=""#),
+            "{read}"
+        );
+        let read = dap.request("readMemory", r#"{"memoryReference":"0xFFFF","count":4}"#);
+        assert!(read.contains(r#""unreadableBytes":3"#), "{read}");
+
+        let written = dap.request(
+            "writeMemory",
+            r#"{"memoryReference":"0x1000","data":"AQID"}"#,
+        );
+        assert!(written.contains(r#""bytesWritten":3"#));
+
+        let code = dap.request(
+            "disassemble",
+            r#"{"memoryReference":"0x8002","instructionOffset":-1,"instructionCount":3}"#,
+        );
+        assert!(
+            code.contains(
+                r#"{"address":"0x8000","instructionBytes":"A9 42","instruction":"LDA #$42"}"#
+            ),
+            "{code}"
+        );
+        assert!(code.contains(r#""instruction":"LDX #$01""#));
+        assert!(code.contains(r#""instructionBytes":"4E 00 02","instruction":"LSR $0200""#));
+
+        let error = dap.try_request("stepBack", "{}");
+        assert!(error.contains(r#""message":"unsupported request `stepBack`""#));
+    });
+    assert_eq!(vm.read(0x1002), 3);
+}
+
+#[test]
+fn pauses_a_running_program_and_breaks_on_addresses() {
+    // `LDA #$A9` over the whole address space runs forever on odd addresses.
+    let mut vm = Vm::new();
+    vm.bus_mut().unmap(CONTROLLER);
+    vm.load(0, &vec![0xA9; RVM_MEM_SIZE]).unwrap();
+    vm.reset(ResetKind::Soft);
+    // Never reached, so only there to outlive the session.
+    vm.add_breakpoint(0x2000);
+    debug(&mut vm, |dap| {
+        dap.initialize();
+        dap.request("attach", "{}");
+        dap.request("configurationDone", "{}");
+        thread::sleep(std::time::Duration::from_millis(20));
+        dap.request("pause", r#"{"threadId":1}"#);
+        dap.stopped("pause");
+
+        let set = dap.request(
+            "setFunctionBreakpoints",
+            r#"{"breakpoints":[{"name":"$1001","condition":"A == $A9"},{"name":"nowhere"}]}"#,
+        );
+        assert!(set.contains(r#""instructionReference":"0x1001""#), "{set}");
+        assert!(set.contains("`nowhere` is not a symbol or address"));
+        dap.request("continue", r#"{"threadId":1}"#);
+        dap.stopped("breakpoint");
+        let trace = dap.request("stackTrace", r#"{"threadId":1}"#);
+        assert!(
+            trace.contains(r#""instructionPointerReference":"0x1001""#),
+            "{trace}"
+        );
+
+        dap.request("setFunctionBreakpoints", r#"{"breakpoints":[]}"#);
+        dap.request(
+            "setInstructionBreakpoints",
+            r#"{"breakpoints":[{"instructionReference":"0x3000","offset":1}]}"#,
+        );
+        dap.request("continue", r#"{"threadId":1}"#);
+        dap.stopped("breakpoint");
+        let trace = dap.request("stackTrace", r#"{"threadId":1}"#);
+        assert!(
+            trace.contains(r#""instructionPointerReference":"0x3001""#),
+            "{trace}"
+        );
+    });
+    assert!(vm.breakpoints().contains(0x2000));
+    assert!(!vm.breakpoints().contains(0x3001));
+}
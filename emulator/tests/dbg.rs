@@ -0,0 +1,102 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use emulator::Rom;
+
+fn path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rvm8-dbg-{}-{name}", std::process::id()))
+}
+
+/// Runs the debugger on a ROM holding `LDA #$42; LDX #$07; LDY #$01` and
+/// returns what it printed for `commands`.
+fn session(name: &str, commands: &str, symbols: Option<&str>) -> String {
+    let rom = path(name);
+    let program = [0xA9, 0x42, 0xA2, 0x07, 0xA0, 0x01];
+    std::fs::write(&rom, Rom::new(0xC000, &program).unwrap().to_bytes()).unwrap();
+    let mut command = Command::new(env!("CARGO_BIN_EXE_rvm8-dbg"));
+    command.arg(&rom);
+    let map = path(&format!("{name}.sym"));
+    if let Some(symbols) = symbols {
+        std::fs::write(&map, symbols).unwrap();
+        command.arg("--symbols").arg(&map);
+    }
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(commands.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    std::fs::remove_file(rom).unwrap();
+    let _ = std::fs::remove_file(map);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn steps_and_shows_registers() {
+    let out = session("step", "s\n\nm C000\nq\n", None);
+    let screens: Vec<_> = out.split("rvm8> ").collect();
+    assert!(screens[0].starts_with("A:00 X:00 Y:00 P:04 SP:FD PC:C000"));
+    assert!(screens[0].contains(">  C000  A9 42     LDA #$42\n"));
+    assert!(screens[1].starts_with("A:42 X:00 Y:00 P:04 SP:FD PC:C002"));
+    // The empty line repeated the step.
+    assert!(screens[2].starts_with("A:42 X:07 Y:00 P:04 SP:FD PC:C004"));
+    assert!(screens[2].contains("-- memory\n0000  00 00"));
+    assert!(screens[3].contains("-- memory\nC000  A9 42 A2 07 A0 01 00"));
+}
+
+#[test]
+fn continues_to_a_named_breakpoint() {
+    let out = session("break", "b last\nc\nq\n", Some("last = $C004\n"));
+    let screens: Vec<_> = out.split("rvm8> ").collect();
+    assert!(screens[1].contains("breakpoint set at last"));
+    assert!(screens[1].contains("-- breakpoints\nC004  last\n"));
+    assert!(screens[1].contains("            last:\n * C004  A0 01     LDY #$01\n"));
+    assert!(screens[2].contains("PC:C004"));
+    assert!(screens[2].contains("\nbreakpoint at last\n"));
+}
+
+#[test]
+fn reports_bad_commands() {
+    let out = session("bad", "x\nb nowhere\ns z\n", None);
+    assert!(out.contains("unknown command `x`; h for help"));
+    assert!(out.contains("bad address `nowhere`"));
+    assert!(out.contains("bad count `z`"));
+}
+
+#[test]
+fn conditional_breakpoints_skip_until_true() {
+    let out = session(
+        "cond",
+        "b C002 if A == 0\nb C004 if X == 7 && [end] == 0\nb C004 if X ==\nc\nq\n",
+        Some("end = $C006\n"),
+    );
+    let screens: Vec<_> = out.split("rvm8> ").collect();
+    assert!(screens[1].contains("breakpoint set at $C002 if A == 0"));
+    assert!(screens[2].contains("C004  $C004 if X == 7 && [end] == 0\n"));
+    assert!(screens[3].contains("bad condition: column 5: unexpected end of condition"));
+    assert!(screens[4].contains("PC:C004"));
+    assert!(screens[4].contains("\nbreakpoint at $C004\n"));
+}
+
+#[test]
+fn labels_show_up_in_the_hexdump_and_reports() {
+    let out = session(
+        "label",
+        "l 0 zero\nl 10-1F scratch\nl 20 zero\nw scratch\nq\n",
+        None,
+    );
+    let screens: Vec<_> = out.split("rvm8> ").collect();
+    assert!(screens[1].contains("\nlabelled $0000 as zero\n"));
+    assert!(screens[2].contains("\nlabelled $0010-$001F as scratch\n"));
+    assert!(screens[2].contains("................  ; $0000 zero\n0010  00"));
+    assert!(screens[3].contains("`zero` is already a symbol"));
+    assert!(screens[4].contains("watching writes to scratch"));
+}
@@ -0,0 +1,208 @@
+mod common;
+
+use common::vm_with;
+use emulator::debugger::{ConditionError, ConditionErrorKind};
+use emulator::ffi::BusAccess;
+use emulator::{Condition, Registers, ResetKind, StopReason, SymbolTable, Vm, VmError, WatchKind};
+
+/// LDA #$01; LDA #$02; LDA #$03; then an illegal 0x00 at 0x8006.
+fn vm_with_program() -> Vm {
+    vm_with(&[0xA9, 0x01, 0xA9, 0x02, 0xA9, 0x03])
+}
+
+#[test]
+fn run_stops_before_breakpoint_instruction() {
+    let mut vm = vm_with_program();
+    assert!(vm.add_breakpoint(0x8004));
+    assert!(!vm.add_breakpoint(0x8004));
+
+    assert_eq!(
+        vm.run_until_break(),
+        Ok(StopReason::Breakpoint { pc: 0x8004 })
+    );
+    assert_eq!(vm.registers().a, 0x02);
+}
+
+#[test]
+fn continuing_steps_past_current_breakpoint() {
+    let mut vm = vm_with_program();
+    vm.add_breakpoint(0x8002);
+    vm.add_breakpoint(0x8004);
+
+    assert_eq!(
+        vm.run_until_break(),
+        Ok(StopReason::Breakpoint { pc: 0x8002 })
+    );
+    assert_eq!(
+        vm.run_until_break(),
+        Ok(StopReason::Breakpoint { pc: 0x8004 })
+    );
+    assert_eq!(
+        vm.breakpoints().iter().collect::<Vec<_>>(),
+        [0x8002, 0x8004]
+    );
+}
+
+#[test]
+fn run_without_breakpoints_stops_on_error() {
+    let mut vm = vm_with_program();
+    vm.add_breakpoint(0x8002);
+    assert!(vm.remove_breakpoint(0x8002));
+    assert!(vm.breakpoints().is_empty());
+
+    assert_eq!(
+        vm.run_until_break(),
+        Err(VmError::IllegalOpcode {
+            pc: 0x8006,
+            opcode: 0x00
+        })
+    );
+    assert_eq!(vm.registers().a, 0x03);
+}
+
+#[test]
+fn run_without_breakpoints_fails_where_stepping_does() {
+    // LDA #$A9 for a few frames, then an illegal 0x00 at 0xF000.
+    let long_program = || vm_with(&[0xA9; 0x7000]);
+    let mut stepped = long_program();
+    let error = loop {
+        if let Err(error) = stepped.step() {
+            break error;
+        }
+    };
+    let mut vm = long_program();
+    assert_eq!(vm.run_until_break(), Err(error));
+    assert_eq!(vm.cycles(), stepped.cycles());
+    assert_eq!(vm.registers(), stepped.registers());
+}
+
+#[test]
+fn write_watchpoint_reports_writer_pc() {
+    // LDA $10; LSR $3001; LDA #$07
+    let mut vm = vm_with(&[0xA5, 0x10, 0x4E, 0x01, 0x30, 0xA9, 0x07]);
+    vm.write(0x3001, 0x10);
+
+    assert!(vm.add_watchpoint(0x3000..=0x30FF, WatchKind::Write));
+    assert_eq!(
+        vm.run_until_break(),
+        Ok(StopReason::Watchpoint {
+            pc: 0x8002,
+            addr: 0x3001,
+            access: BusAccess::Write,
+            value: 0x08,
+        })
+    );
+    assert_eq!(vm.registers().pc, 0x8005);
+}
+
+#[test]
+fn read_watchpoint_ignores_other_pages_and_kinds() {
+    let mut vm = vm_with_program();
+    vm.add_watchpoint(0x0000..=0x00FF, WatchKind::Access);
+    vm.add_watchpoint(0x8004..=0x8004, WatchKind::Write);
+    vm.add_watchpoint(0x8005..=0x8005, WatchKind::Read);
+    assert!(vm.remove_watchpoint(0x0000..=0x00FF, WatchKind::Access));
+    assert_eq!(vm.watchpoints().len(), 2);
+
+    // The immediate operand of the third LDA is fetched from 0x8005.
+    assert_eq!(
+        vm.run_until_break(),
+        Ok(StopReason::Watchpoint {
+            pc: 0x8004,
+            addr: 0x8005,
+            access: BusAccess::Read,
+            value: 0x03,
+        })
+    );
+}
+
+#[test]
+fn conditions_evaluate_registers_and_memory() {
+    let mut vm = vm_with_program();
+    vm.write(0x2000, 0x11);
+    vm.set_registers(Registers {
+        a: 0x3F,
+        ..vm.registers()
+    });
+    let eval = |text: &str| text.parse::<Condition>().unwrap().eval(&vm);
+
+    assert_eq!(eval("A == 0x3F && [0x2000] != 0"), 1);
+    assert_eq!(eval("a == $3f && [$2000] == 0"), 0);
+    assert_eq!(eval("[$1FFF + 1] & %10000"), 0x10);
+    assert_eq!(eval("PC - $8000 == 0 || !1"), 1);
+    assert_eq!(eval("1 + 2 == 3 & 3"), 1);
+    assert_eq!(eval("(1 | 2) ^ 1"), 2);
+    assert_eq!(eval("-1 < 0 && ~0 == -1"), 1);
+    assert_eq!(eval("SP >= $FD && (P & 4) != 0"), 1);
+
+    let symbols = SymbolTable::parse("flag = $2000\n").unwrap();
+    let condition = Condition::parse_with_symbols(" [flag] == $11 ", &symbols).unwrap();
+    assert!(condition.holds(&vm));
+    assert_eq!(condition.to_string(), "[flag] == $11");
+}
+
+#[test]
+fn condition_errors_point_at_the_problem() {
+    let error = |text: &str| text.parse::<Condition>().unwrap_err();
+    assert_eq!(
+        error("A == #3"),
+        ConditionError {
+            column: 6,
+            kind: ConditionErrorKind::UnexpectedChar('#')
+        }
+    );
+    assert_eq!(
+        error("[A"),
+        ConditionError {
+            column: 3,
+            kind: ConditionErrorKind::UnexpectedEnd
+        }
+    );
+    assert_eq!(error("(A ]").kind, ConditionErrorKind::Expected("`)`"));
+    assert_eq!(
+        error("A 1").kind,
+        ConditionErrorKind::Expected("an operator")
+    );
+    assert_eq!(
+        error("A == )").kind,
+        ConditionErrorKind::Expected("a value")
+    );
+    assert_eq!(
+        error("$FG").kind,
+        ConditionErrorKind::BadNumber("$FG".into())
+    );
+    assert_eq!(
+        error("count > 1").to_string(),
+        "column 1: `count` is not a register or symbol"
+    );
+}
+
+#[test]
+fn conditional_breakpoint_stops_only_when_true() {
+    let mut vm = vm_with_program();
+    // Skipped: A is still 1 when the PC reaches 0x8002...
+    assert!(vm.add_conditional_breakpoint(0x8002, "A == 2".parse().unwrap()));
+    assert!(vm.add_conditional_breakpoint(0x8004, "A == 2".parse().unwrap()));
+    assert_eq!(
+        vm.run_until_break(),
+        Ok(StopReason::Breakpoint { pc: 0x8004 })
+    );
+
+    // ...and replacing the condition keeps the breakpoint.
+    vm.reset(ResetKind::Soft);
+    assert!(!vm.add_conditional_breakpoint(0x8002, "A == 1".parse().unwrap()));
+    assert_eq!(vm.breakpoints().len(), 2);
+    assert_eq!(
+        vm.run_until_break(),
+        Ok(StopReason::Breakpoint { pc: 0x8002 })
+    );
+
+    // Removing a breakpoint drops its condition.
+    assert!(vm.remove_breakpoint(0x8004));
+    vm.add_breakpoint(0x8004);
+    assert!(vm.breakpoints().condition(0x8004).is_none());
+    assert_eq!(
+        vm.breakpoints().condition(0x8002).unwrap().to_string(),
+        "A == 1"
+    );
+}
@@ -0,0 +1,139 @@
+#![cfg(feature = "difftest")]
+
+use emulator::difftest::{DiffTest, Divergence, Outcome};
+use emulator::disasm::{instruction_size, opcodes};
+use emulator::ffi::{BusAccess, FLAG_I, RVM_ILLEGAL_OPCODE, RVM_OK};
+use emulator::hooks::Access;
+use emulator::rom::BANK_SIZE;
+use emulator::{Registers, Rom, Vm};
+
+/// xorshift32, so failures reproduce from the seed alone.
+fn rng(mut state: u32) -> impl FnMut() -> u32 {
+    move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    }
+}
+
+/// Random legal instructions with random operands in a 32 KiB ROM, over
+/// RAM full of random bytes, starting with random registers. The ROM keeps
+/// stray writes from turning the code into illegal opcodes.
+fn random_vm(seed: u32) -> Vm {
+    let legal: Vec<_> = opcodes().collect();
+    let mut next = rng(seed);
+    let mut code = Vec::with_capacity(2 * BANK_SIZE + 2);
+    while code.len() < 2 * BANK_SIZE {
+        let (opcode, op) = legal[next() as usize % legal.len()];
+        code.push(opcode);
+        for _ in 1..instruction_size(op.mode) {
+            code.push(next() as u8);
+        }
+    }
+    code.truncate(2 * BANK_SIZE);
+    let ram: Vec<u8> = (0..0x8000).map(|_| next() as u8).collect();
+
+    let mut vm = Vm::new();
+    vm.load_rom(&Rom::new(0x8000, &code).unwrap()).unwrap();
+    vm.load(0, &ram).unwrap();
+    let [a, x, y, flags] = next().to_le_bytes();
+    vm.set_registers(Registers {
+        a,
+        x,
+        y,
+        pc: 0x8000,
+        sp: 0xFD,
+        flags,
+    });
+    vm
+}
+
+#[test]
+fn cores_agree_on_random_programs() {
+    for seed in 1..=64 {
+        let mut diff = DiffTest::from_vm(&random_vm(seed));
+        assert_eq!(diff.run(10_000), 10_000, "seed {seed}");
+    }
+}
+
+#[test]
+fn cores_agree_on_rom_writes() {
+    // LSR $C010 targets the write-protected ROM, LSR $10 plain RAM.
+    let mut program = vec![0xA9, 0x42, 0x4E, 0x10, 0xC0, 0x46, 0x10];
+    program.resize(0x11, 0xA9);
+    let rom = Rom::new(0xC000, &program).unwrap();
+    let mut diff = DiffTest::from_rom(&rom);
+    // Eight instructions, then the padding's illegal opcode.
+    assert_eq!(diff.run(100), 9);
+    assert_eq!(diff.registers().pc, 0xC012);
+    assert_eq!(diff.step(), Ok(RVM_ILLEGAL_OPCODE));
+}
+
+#[test]
+fn cores_agree_on_interrupt_entry() {
+    let mut vm = random_vm(7);
+    vm.load(0xFFFE, &[0x34, 0x12]).unwrap();
+    vm.set_registers(Registers {
+        flags: 0,
+        ..vm.registers()
+    });
+    vm.load(0xFFFA, &[0x78, 0x56]).unwrap();
+    let mut diff = DiffTest::from_vm(&vm);
+    diff.set_irq(true);
+    // The first instruction polls the line, then the handler is entered.
+    assert_eq!(diff.step(), Ok(RVM_OK));
+    assert_eq!(diff.step(), Ok(RVM_OK));
+    let regs = diff.registers();
+    assert_eq!(regs.pc, 0x1234);
+    assert_ne!(regs.flags & FLAG_I, 0);
+
+    // Polled by whatever the handler starts with, legal or not.
+    diff.set_nmi(true);
+    assert!(diff.step().is_ok());
+    assert_eq!(diff.step(), Ok(RVM_OK));
+    assert_eq!(diff.registers().pc, 0x5678);
+}
+
+#[test]
+fn divergence_lists_both_sides() {
+    let regs = Registers {
+        pc: 0x8003,
+        ..Registers::default()
+    };
+    let read = Access {
+        addr: 0x8000,
+        kind: BusAccess::Read,
+        value: 0xA9,
+    };
+    let c = Outcome {
+        status: RVM_OK,
+        registers: Registers { a: 1, ..regs },
+        cycles: 2,
+        accesses: vec![read],
+    };
+    let divergence = Divergence {
+        step: 5,
+        before: Registers::default(),
+        rust: Outcome {
+            registers: Registers { a: 2, ..regs },
+            accesses: vec![
+                read,
+                Access {
+                    addr: 0x0010,
+                    kind: BusAccess::Write,
+                    value: 3,
+                },
+            ],
+            ..c.clone()
+        },
+        c,
+        memory: vec![(0x0010, 0, 3)],
+    };
+    let report = divergence.to_string();
+    assert!(report.starts_with("cores diverged on instruction 5 at PC 0x0000\n"));
+    assert!(report.contains("  A               01        02  <--\n"));
+    assert!(report.contains("  PC            8003      8003\n"));
+    assert!(report.contains("    C:    R8000=A9\n    Rust: R8000=A9 W0010=03\n"));
+    assert!(report.contains("  memory 0x0010: C 0x00, Rust 0x03\n"));
+}
@@ -0,0 +1,67 @@
+use emulator::Vm;
+use emulator::disasm::{self, Operand};
+use emulator::ffi::AddressingMode;
+
+#[test]
+fn decodes_every_addressing_mode_used_by_lda() {
+    let cases: &[(&[u8], &str)] = &[
+        (&[0xA9, 0x10], "LDA #$10"),
+        (&[0xA5, 0x20], "LDA $20"),
+        (&[0xAD, 0x00, 0x30], "LDA $3000"),
+        (&[0xB5, 0x20], "LDA $20,X"),
+        (&[0xBD, 0x00, 0x40], "LDA $4000,X"),
+        (&[0xB9, 0x00, 0x50], "LDA $5000,Y"),
+        (&[0xA1, 0x30], "LDA ($30,X)"),
+        (&[0xB1, 0x40], "LDA ($40),Y"),
+        (&[0x4A], "LSR A"),
+    ];
+    for &(bytes, text) in cases {
+        let instr = disasm::decode(bytes).unwrap();
+        assert_eq!(instr.to_string(), text);
+        assert_eq!(instr.size as usize, bytes.len(), "{text}");
+    }
+}
+
+#[test]
+fn reports_table_cycles_and_operands() {
+    let instr = disasm::decode(&[0x6D, 0x34, 0x12]).unwrap();
+    assert_eq!(instr.mnemonic, "ADC");
+    assert_eq!(instr.operands, Operand::Absolute(0x1234));
+    assert_eq!(instr.cycles, 4);
+    assert_eq!(disasm::lookup(0x6D).unwrap().mode, AddressingMode::Absolute);
+}
+
+#[test]
+fn undefined_and_truncated_input() {
+    let illegal = disasm::decode(&[0xFF]).unwrap();
+    assert_eq!(illegal.to_string(), "???");
+    assert_eq!(illegal.size, 1);
+
+    assert_eq!(disasm::decode(&[]), None);
+    assert_eq!(disasm::decode(&[0xAD, 0x00]), None);
+}
+
+#[test]
+fn linear_disassembly_tracks_addresses() {
+    let listing: Vec<_> = disasm::disassemble(&[0xA9, 0x05, 0x69, 0x0A, 0x4A, 0xAD], 0x8000)
+        .map(|(addr, instr)| format!("{addr:04X} {instr}"))
+        .collect();
+    assert_eq!(listing, ["8000 LDA #$05", "8002 ADC #$0A", "8004 LSR A"]);
+}
+
+#[test]
+fn live_memory_wraps_at_end_of_address_space() {
+    let mut vm = Vm::new();
+    vm.load(0xFFFF, &[0xAD]).unwrap();
+    vm.load(0x0000, &[0x34, 0x12]).unwrap();
+    assert_eq!(vm.disassemble(0xFFFF).to_string(), "LDA $1234");
+}
+
+#[test]
+fn every_table_entry_decodes_to_itself() {
+    for (code, op) in disasm::opcodes() {
+        let instr = disasm::decode(&[code, 0, 0]).unwrap();
+        assert_eq!(instr.mnemonic, op.mnemonic);
+        assert_eq!(instr.size, disasm::instruction_size(op.mode));
+    }
+}
@@ -0,0 +1,236 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::{vm_with, vm_with_handler};
+use emulator::Vm;
+use emulator::display::{
+    BG_PALETTE, BYTES_PER_PIXEL, CTRL, CTRL_BACKGROUND, CTRL_LINE_IRQ, CTRL_SPRITES, DISPLAY_IRQ,
+    DisplayConfig, HEIGHT, LINE_COMPARE, MAP_HEIGHT, MAP_WIDTH, PALETTE, PixelFormat, SCROLL_X,
+    SPRITE_PALETTE, SPRITES, STATUS, STATUS_LINE, STATUS_VBLANK, TILE_DATA, TILEMAP, WIDTH,
+};
+
+/// Identity palette: pixel value N is shade N.
+const IDENTITY: u8 = 0b11_10_01_00;
+
+const WHITE: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+const LIGHT: [u8; 4] = [0xAA, 0xAA, 0xAA, 0xFF];
+const BLACK: [u8; 4] = [0x00, 0x00, 0x00, 0xFF];
+
+/// A machine that spends a few frames executing `LSR $0300` from 0x8000.
+fn idle_vm() -> Vm {
+    vm_with(&[0x4E, 0x00, 0x03].repeat(10921))
+}
+
+fn pixel(vm: &Vm, x: usize, y: usize) -> [u8; 4] {
+    let at = (y * WIDTH + x) * BYTES_PER_PIXEL;
+    vm.framebuffer()[at..at + BYTES_PER_PIXEL]
+        .try_into()
+        .unwrap()
+}
+
+/// Tile 1: every pixel has value 3. Tile 2: only the top-left pixel, value 1.
+fn load_tiles(vm: &mut Vm) {
+    vm.load(TILE_DATA + 16, &[0xFF; 16]).unwrap();
+    vm.write(TILE_DATA + 32, 0x80);
+}
+
+#[test]
+fn renders_background_tiles() {
+    let mut vm = idle_vm();
+    load_tiles(&mut vm);
+    vm.write(TILEMAP + 1, 1);
+    vm.write(BG_PALETTE, IDENTITY);
+    vm.write(CTRL, CTRL_BACKGROUND);
+
+    vm.run_frame().unwrap();
+    assert_eq!(pixel(&vm, 7, 0), WHITE);
+    assert_eq!(pixel(&vm, 8, 0), BLACK);
+    assert_eq!(pixel(&vm, 15, 7), BLACK);
+    assert_eq!(pixel(&vm, 16, 0), WHITE);
+
+    vm.write(SCROLL_X, 4);
+    vm.run_frame().unwrap();
+    assert_eq!(pixel(&vm, 4, 0), BLACK);
+    assert_eq!(pixel(&vm, 3, 0), WHITE);
+}
+
+#[test]
+fn sprites_draw_over_the_background_with_transparency() {
+    let mut vm = idle_vm();
+    load_tiles(&mut vm);
+    vm.write(BG_PALETTE, IDENTITY);
+    vm.write(SPRITE_PALETTE, IDENTITY);
+    vm.write(CTRL, CTRL_BACKGROUND | CTRL_SPRITES);
+    // Sprite 0 at (20, 10) using tile 2, flipped horizontally.
+    vm.load(SPRITES, &[10, 20, 2, 0b01]).unwrap();
+
+    vm.run_frame().unwrap();
+    assert_eq!(pixel(&vm, 27, 10), LIGHT);
+    assert_eq!(pixel(&vm, 20, 10), WHITE);
+}
+
+#[test]
+fn vblank_raises_status_and_calls_back_once_per_frame() {
+    let mut vm = idle_vm();
+    let frames = Arc::new(Mutex::new(0));
+    let seen = frames.clone();
+    vm.set_vblank_callback(move |framebuffer| {
+        assert_eq!(framebuffer.len(), 160 * 144 * 4);
+        *seen.lock().unwrap() += 1;
+    });
+
+    vm.run_frame().unwrap();
+    vm.run_frame().unwrap();
+    assert_eq!(*frames.lock().unwrap(), 2);
+    assert_ne!(vm.read(STATUS) & STATUS_VBLANK, 0);
+}
+
+#[test]
+fn renders_in_the_configured_format() {
+    let mut vm = idle_vm();
+    load_tiles(&mut vm);
+    vm.write(TILEMAP, 2);
+    vm.write(BG_PALETTE, IDENTITY);
+    vm.write(CTRL, CTRL_BACKGROUND);
+    vm.run_frame().unwrap();
+
+    vm.set_display_config(DisplayConfig {
+        format: PixelFormat::Indexed,
+        scale: 1,
+    });
+    assert_eq!(vm.framebuffer().len(), WIDTH * HEIGHT);
+    assert_eq!(vm.framebuffer()[..2], [1, 0], "re-rendered at once");
+    assert_eq!(PALETTE[1], LIGHT);
+
+    vm.set_display_config(DisplayConfig {
+        format: PixelFormat::Rgb565,
+        scale: 1,
+    });
+    vm.run_frame().unwrap();
+    assert_eq!(vm.framebuffer().len(), WIDTH * HEIGHT * 2);
+    // 0xAA is 21 of 31 red and blue, 42 of 63 green.
+    assert_eq!(vm.framebuffer()[..4], [0x55, 0xAD, 0xFF, 0xFF]);
+}
+
+#[test]
+fn scales_each_pixel_to_a_block() {
+    let mut vm = idle_vm();
+    load_tiles(&mut vm);
+    vm.write(TILEMAP, 2);
+    vm.write(BG_PALETTE, IDENTITY);
+    vm.write(CTRL, CTRL_BACKGROUND);
+    vm.set_display_config(DisplayConfig {
+        format: PixelFormat::Indexed,
+        scale: 3,
+    });
+    vm.run_frame().unwrap();
+
+    let width = WIDTH * 3;
+    assert_eq!(vm.framebuffer().len(), width * HEIGHT * 3);
+    for row in vm.framebuffer().chunks_exact(width).take(3) {
+        assert_eq!(row[..4], [1, 1, 1, 0]);
+    }
+    assert_eq!(vm.framebuffer()[width * 3], 0);
+}
+
+#[test]
+fn every_format_and_scale_encodes_the_same_shades() {
+    let mut vm = idle_vm();
+    let tiles: Vec<u8> = (0..32 * 16).map(|i: u32| (i * 37 % 251) as u8).collect();
+    vm.load(TILE_DATA, &tiles).unwrap();
+    let map: Vec<u8> = (0..MAP_WIDTH * MAP_HEIGHT)
+        .map(|i| (i * 7 % 32) as u8)
+        .collect();
+    vm.load(TILEMAP, &map).unwrap();
+    vm.write(BG_PALETTE, IDENTITY);
+    vm.write(CTRL, CTRL_BACKGROUND);
+    vm.set_display_config(DisplayConfig {
+        format: PixelFormat::Indexed,
+        scale: 1,
+    });
+    vm.run_frame().unwrap();
+    let shades = vm.framebuffer().to_vec();
+    assert!((0..4).all(|shade| shades.contains(&shade)));
+
+    for format in [
+        PixelFormat::Rgba8888,
+        PixelFormat::Rgb565,
+        PixelFormat::Indexed,
+    ] {
+        let bytes = format.bytes_per_pixel();
+        for scale in [1, 2, 3, 5] {
+            vm.set_display_config(DisplayConfig { format, scale });
+            let width = WIDTH * scale as usize;
+            for (y, row) in vm.framebuffer().chunks_exact(width * bytes).enumerate() {
+                for (x, pixel) in row.chunks_exact(bytes).enumerate() {
+                    let shade = shades[y / scale as usize * WIDTH + x / scale as usize];
+                    let expected = match format {
+                        PixelFormat::Rgba8888 => PALETTE[shade as usize].to_vec(),
+                        PixelFormat::Rgb565 => {
+                            let [r, g, b, _] = PALETTE[shade as usize].map(u16::from);
+                            (r >> 3 << 11 | g >> 2 << 5 | b >> 3).to_le_bytes().to_vec()
+                        }
+                        PixelFormat::Indexed => vec![shade],
+                    };
+                    assert_eq!(pixel, expected, "{format:?} ×{scale} at {x}, {y}");
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn writes_mid_frame_show_from_the_next_line_on() {
+    let mut vm = idle_vm();
+    load_tiles(&mut vm);
+    vm.load(TILEMAP, &[1; MAP_WIDTH * MAP_HEIGHT]).unwrap();
+    vm.write(BG_PALETTE, IDENTITY);
+    vm.write(CTRL, CTRL_BACKGROUND);
+
+    for _ in 0..72 {
+        vm.step_scanline().unwrap();
+    }
+    vm.write(BG_PALETTE, 0);
+    vm.run_frame().unwrap();
+    assert_eq!(pixel(&vm, 0, 71), BLACK);
+    assert_eq!(pixel(&vm, 0, 72), WHITE);
+    assert_eq!(pixel(&vm, WIDTH - 1, HEIGHT - 1), WHITE);
+
+    vm.run_frame().unwrap();
+    assert_eq!(pixel(&vm, 0, 0), WHITE, "the next frame starts white");
+}
+
+#[test]
+fn the_line_interrupt_follows_the_compare_line() {
+    // CLI, then LSR $0300 over and over; the handler at 0x9000 clears
+    // STATUS with LSR $2401 and then runs LDA $0300 for the rest of the
+    // frame.
+    let mut program = vec![0x58];
+    program.extend([0x4E, 0x00, 0x03].repeat(4000));
+    let mut vm = vm_with_handler(&program);
+    let mut handler = vec![0x4E, 0x01, 0x24];
+    handler.extend([0xAD, 0x00, 0x03].repeat(4500));
+    vm.load(0x9000, &handler).unwrap();
+    vm.write(LINE_COMPARE, 9);
+    vm.write(CTRL, CTRL_LINE_IRQ);
+
+    for _ in 0..9 {
+        vm.step_scanline().unwrap();
+    }
+    assert_eq!(vm.read(STATUS) & STATUS_LINE, 0);
+    assert!(vm.registers().pc < 0x9000);
+    vm.step_scanline().unwrap();
+    assert_eq!(vm.read(STATUS) & STATUS_LINE, STATUS_LINE);
+    assert_eq!(vm.pending_irqs(), 1 << DISPLAY_IRQ);
+
+    // The line rose during the last instruction, whose poll saw it.
+    vm.step().unwrap();
+    assert_eq!(vm.registers().pc, 0x9000);
+    vm.step().unwrap();
+    assert_eq!(vm.read(STATUS) & STATUS_LINE, 0);
+    assert_eq!(vm.pending_irqs(), 0);
+
+    vm.run_frame().unwrap();
+    assert_eq!(vm.read(STATUS), STATUS_VBLANK, "once a frame");
+}
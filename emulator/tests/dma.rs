@@ -0,0 +1,132 @@
+mod common;
+
+use common::vm_with;
+use emulator::dma::{CTRL_FIXED_SRC, CTRL_IRQ, CTRL_START, CTRL_STEAL, DMA_IRQ, DMA_PORTS, Dma};
+use emulator::{BusDevice, Vm};
+
+const DMA: u16 = *DMA_PORTS.start();
+
+fn vm_with_dma(program: &[u8]) -> Vm {
+    let mut vm = vm_with(program);
+    vm.bus_mut().map(DMA_PORTS, Dma::default()).unwrap();
+    vm
+}
+
+/// Programs the registers from the host, without starting unless `ctrl`
+/// says so.
+fn program(vm: &mut Vm, src: u16, dst: u16, len: u16, ctrl: u8) {
+    let dma = vm.bus_mut().device_mut::<Dma>(DMA).unwrap();
+    let words = [src, dst, len];
+    for (i, byte) in words.iter().flat_map(|w| w.to_le_bytes()).enumerate() {
+        dma.write8(i as u16, byte);
+    }
+    dma.write8(6, ctrl);
+}
+
+/// A FIFO register that reads out 1, 2, 3, ...
+struct Counter(u8);
+
+impl BusDevice for Counter {
+    fn read8(&mut self, _: u16) -> u8 {
+        self.0 += 1;
+        self.0
+    }
+
+    fn write8(&mut self, _: u16, _: u8) {}
+}
+
+#[test]
+fn burst_transfer_stalls_the_cpu() {
+    // LSR $2706 turns CTRL_STEAL into CTRL_START, then LDA #$01.
+    let mut vm = vm_with_dma(&[0x4E, 0x06, 0x27, 0xA9, 0x01]);
+    vm.load(0x1000, &[1, 2, 3, 4]).unwrap();
+    program(&mut vm, 0x1000, 0x3000, 4, CTRL_STEAL);
+
+    vm.step().unwrap();
+    assert_eq!(&vm.memory()[0x3000..0x3004], [1, 2, 3, 4]);
+    // Six cycles of LSR plus two per byte.
+    assert_eq!(vm.cycles(), 6 + 8);
+    assert!(!vm.bus().device::<Dma>(DMA).unwrap().busy());
+}
+
+#[test]
+fn cycle_steal_moves_one_byte_per_instruction() {
+    let mut vm = vm_with_dma(&[0xA9, 0x01, 0xA9, 0x02, 0xA9, 0x03]);
+    vm.load(0x1000, &[7, 8, 9]).unwrap();
+    program(&mut vm, 0x1000, 0x3000, 3, CTRL_START | CTRL_STEAL);
+
+    vm.step().unwrap();
+    assert_eq!(&vm.memory()[0x3000..0x3003], [7, 0, 0]);
+    assert_eq!(vm.cycles(), 4);
+    vm.step().unwrap();
+    vm.step().unwrap();
+    assert_eq!(&vm.memory()[0x3000..0x3003], [7, 8, 9]);
+    assert_eq!(vm.cycles(), 12);
+}
+
+#[test]
+fn fixed_source_drains_a_device_and_signals_completion() {
+    let mut vm = vm_with_dma(&[0xA9, 0x01, 0xA9, 0x02]);
+    vm.bus_mut().map(0x4000..=0x4000, Counter(0)).unwrap();
+    program(
+        &mut vm,
+        0x4000,
+        0x3000,
+        3,
+        CTRL_START | CTRL_FIXED_SRC | CTRL_IRQ,
+    );
+    assert_eq!(vm.pending_irqs(), 0);
+
+    vm.step().unwrap();
+    assert_eq!(&vm.memory()[0x3000..0x3003], [1, 2, 3]);
+    assert_eq!(vm.pending_irqs(), 1 << DMA_IRQ);
+    vm.bus_mut().device_mut::<Dma>(DMA).unwrap().write8(6, 0);
+    assert_eq!(vm.pending_irqs(), 0);
+}
+
+#[test]
+fn rom_pages_are_not_written() {
+    let mut vm = vm_with_dma(&[0xA9, 0x01]);
+    vm.load(0x1000, &[0xAA]).unwrap();
+    vm.load_rom(&emulator::Rom::new(0x8000, &[0xA9, 0x01]).unwrap())
+        .unwrap();
+    program(&mut vm, 0x1000, 0xC100, 1, CTRL_START);
+    vm.step().unwrap();
+    assert_eq!(vm.read(0xC100), 0);
+}
+
+#[test]
+fn slow_pages_hold_the_cpu_off_the_bus_longer() {
+    let mut vm = vm_with_dma(&[0x4E, 0x06, 0x27, 0xA9, 0x01]);
+    vm.load(0x1000, &[1, 2, 3, 4]).unwrap();
+    vm.set_wait_states(0x1000..=0x10FF, 2);
+    vm.set_wait_states(0x3000..=0x30FF, 1);
+    program(&mut vm, 0x1000, 0x3000, 4, CTRL_STEAL);
+
+    vm.step().unwrap();
+    assert_eq!(&vm.memory()[0x3000..0x3004], [1, 2, 3, 4]);
+    // Two cycles per byte, plus two for each read and one for each write.
+    assert_eq!(vm.cycles(), 6 + 4 * (2 + 2 + 1));
+}
+
+#[test]
+fn mirrors_decode_as_for_the_cpu() {
+    let mut vm = vm_with_dma(&[0xA9, 0x01, 0xA9, 0x02]);
+    vm.mirror(0x1000..=0x10FF, 0x2000).unwrap();
+    vm.mirror(0x3000..=0x30FF, 0x5000).unwrap();
+    vm.mirror(0x6000..=0x60FF, 0x4000).unwrap();
+    vm.bus_mut().map(0x4000..=0x4000, Counter(0)).unwrap();
+    vm.load(0x2000, &[5, 6, 7]).unwrap();
+    vm.set_wait_states(0x2000..=0x20FF, 1);
+    program(&mut vm, 0x1000, 0x3000, 3, CTRL_START);
+    vm.step().unwrap();
+    // Both ends reach their targets' RAM, at the targets' wait states.
+    assert_eq!(&vm.memory()[0x5000..0x5003], [5, 6, 7]);
+    assert_eq!(&vm.memory()[0x3000..0x3003], [0, 0, 0]);
+    assert_eq!(vm.cycles(), 2 + 3 * (2 + 1));
+
+    // A device under a target answers for its mirror.
+    program(&mut vm, 0x6000, 0x0200, 2, CTRL_START | CTRL_FIXED_SRC);
+    vm.step().unwrap();
+    assert_eq!(&vm.memory()[0x0200..0x0202], [1, 2]);
+}
@@ -0,0 +1,147 @@
+#![cfg(feature = "async")]
+
+mod common;
+
+use std::future::Future;
+use std::pin::pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+use common::vm_with;
+use emulator::driver::{StopToken, channel};
+use emulator::{Vm, VmError};
+
+/// A machine running `LDA $8000` for a few hundred frames of 100 cycles.
+fn vm() -> Vm {
+    let mut vm = vm_with(&[0xAD, 0x00, 0x80].repeat(0x2000));
+    vm.set_clock_hz(6_000);
+    vm
+}
+
+/// Counts wakes, and unparks `thread` on each.
+struct Counter {
+    wakes: AtomicUsize,
+    thread: Thread,
+}
+
+impl Wake for Counter {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wakes.fetch_add(1, Ordering::SeqCst);
+        self.thread.unpark();
+    }
+}
+
+fn counter() -> (Arc<Counter>, Waker) {
+    let counter = Arc::new(Counter {
+        wakes: AtomicUsize::new(0),
+        thread: thread::current(),
+    });
+    (counter.clone(), Waker::from(counter))
+}
+
+/// Polls `future` on this thread until it completes, parking while it has
+/// nothing to do.
+fn block_on<T>(future: impl Future<Output = T>) -> T {
+    let (_, waker) = counter();
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(value) => return value,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[test]
+fn yields_at_frame_boundaries_and_waits_for_the_receiver() {
+    let mut vm = vm();
+    let (events, mut frames) = channel(2);
+    let stop = StopToken::new();
+    let (counter, waker) = counter();
+    let mut cx = Context::from_waker(&waker);
+    let mut run = pin!(vm.run_async(&stop, &events));
+
+    for wakes in 1..=2 {
+        assert!(run.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(counter.wakes.load(Ordering::SeqCst), wakes, "it yields");
+    }
+    // The channel is full, so nothing runs until a frame is taken.
+    assert!(run.as_mut().poll(&mut cx).is_pending());
+    assert_eq!(counter.wakes.load(Ordering::SeqCst), 2);
+    assert_eq!(frames.try_recv().unwrap().frame, 1);
+    assert_eq!(counter.wakes.load(Ordering::SeqCst), 3);
+    assert!(run.as_mut().poll(&mut cx).is_pending());
+    assert_eq!(frames.try_recv().unwrap().frame, 2);
+    assert_eq!(frames.try_recv().unwrap().frame, 3);
+    assert!(frames.try_recv().is_none());
+
+    stop.stop();
+    assert!(matches!(run.as_mut().poll(&mut cx), Poll::Ready(Ok(3))));
+}
+
+#[test]
+fn frames_reach_another_thread() {
+    let mut vm = vm();
+    vm.set_sample_rate(6_000);
+    let heard = Arc::new(AtomicUsize::new(0));
+    let counted = heard.clone();
+    vm.set_audio_callback(move |samples| {
+        counted.fetch_add(samples.len(), Ordering::SeqCst);
+    });
+    let (events, mut frames) = channel(1);
+    let stop = StopToken::new();
+
+    let consumer = thread::spawn({
+        let stop = stop.clone();
+        move || {
+            let received: Vec<_> = (0..3).map(|_| block_on(frames.recv()).unwrap()).collect();
+            stop.stop();
+            received
+        }
+    });
+    let run = block_on(vm.run_async(&stop, &events));
+    drop(events);
+    let received = consumer.join().unwrap();
+
+    let run = run.unwrap();
+    assert!((3..=4).contains(&run), "{run} frames");
+    assert_eq!(
+        received.iter().map(|f| f.frame).collect::<Vec<_>>(),
+        [1, 2, 3]
+    );
+    for frame in &received {
+        assert_eq!(frame.pixels.len(), vm.framebuffer().len());
+        assert_eq!(frame.samples.len(), 100);
+    }
+    assert_eq!(
+        heard.load(Ordering::SeqCst),
+        100 * run as usize,
+        "the callback still hears it"
+    );
+}
+
+#[test]
+fn dropping_the_receiver_ends_the_run() {
+    let mut vm = vm();
+    let (events, frames) = channel(4);
+    drop(frames);
+    assert_eq!(block_on(vm.run_async(&StopToken::new(), &events)), Ok(0));
+    assert_eq!(vm.frame(), 0);
+}
+
+#[test]
+fn errors_end_the_run() {
+    let mut vm = Vm::new();
+    let (events, mut frames) = channel(4);
+    let run = block_on(vm.run_async(&StopToken::new(), &events));
+    assert!(matches!(run, Err(VmError::IllegalOpcode { pc: 0, .. })));
+    drop(events);
+    assert!(block_on(frames.recv()).is_none());
+}
@@ -0,0 +1,247 @@
+use std::process::Command;
+
+use emulator::elf::{Elf, ElfError, Segment};
+use emulator::{SymbolTable, Vm};
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_NOBITS: u32 = 8;
+const SHF_ALLOC: u32 = 0x2;
+const STT_FUNC: u8 = 2;
+const STT_SECTION: u8 = 3;
+
+/// A loadable segment: virtual and load address, file bytes, memory size.
+struct Load(u32, u32, &'static [u8], u32);
+
+/// A section: type, flags, address, contents.
+struct Section(u32, u32, u32, &'static [u8]);
+
+/// Links a little-endian ELF32 file by hand, with a `.symtab` of `symbols`
+/// as (name, value, type, section index) after `sections`.
+fn link(
+    entry: u32,
+    loads: &[Load],
+    sections: &[Section],
+    symbols: &[(&str, u32, u8, u16)],
+) -> Vec<u8> {
+    let phnum = loads.len();
+    let mut file = vec![0; 52 + 32 * phnum];
+    let mut headers = Vec::new();
+    for (index, Load(vaddr, paddr, data, memsz)) in loads.iter().enumerate() {
+        let offset = file.len() as u32;
+        file.extend_from_slice(data);
+        let fields = [1, offset, *vaddr, *paddr, data.len() as u32, *memsz, 5, 1];
+        let at = 52 + 32 * index;
+        for (field, value) in fields.iter().enumerate() {
+            file[at + 4 * field..][..4].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    // NOBITS sections take up memory but no file space.
+    let mut section =
+        |file: &mut Vec<u8>, kind: u32, flags: u32, addr: u32, data: &[u8], link: u32| {
+            let offset = file.len() as u32;
+            if kind != SHT_NOBITS {
+                file.extend_from_slice(data);
+            }
+            headers.push([
+                0,
+                kind,
+                flags,
+                addr,
+                offset,
+                data.len() as u32,
+                link,
+                0,
+                1,
+                0,
+            ]);
+        };
+    section(&mut file, 0, 0, 0, &[], 0);
+    for Section(kind, flags, addr, data) in sections {
+        section(&mut file, *kind, *flags, *addr, data, 0);
+    }
+
+    let (mut strings, mut table) = (vec![0], vec![0; 16]);
+    for (name, value, kind, shndx) in symbols {
+        let name_at = strings.len() as u32;
+        strings.extend_from_slice(name.as_bytes());
+        strings.push(0);
+        table.extend_from_slice(&name_at.to_le_bytes());
+        table.extend_from_slice(&value.to_le_bytes());
+        table.extend_from_slice(&0u32.to_le_bytes());
+        table.extend_from_slice(&[0x10 | kind, 0]);
+        table.extend_from_slice(&shndx.to_le_bytes());
+    }
+    let strtab = sections.len() as u32 + 2;
+    section(&mut file, 2, 0, 0, &table, strtab);
+    section(&mut file, 3, 0, 0, &strings, 0);
+
+    let shoff = file.len() as u32;
+    for header in &headers {
+        for value in header {
+            file.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    file[..6].copy_from_slice(b"\x7FELF\x01\x01");
+    file[6] = 1;
+    file[16..18].copy_from_slice(&2u16.to_le_bytes());
+    file[24..28].copy_from_slice(&entry.to_le_bytes());
+    file[28..32].copy_from_slice(&if phnum == 0 { 0u32 } else { 52 }.to_le_bytes());
+    file[32..36].copy_from_slice(&shoff.to_le_bytes());
+    file[40..42].copy_from_slice(&52u16.to_le_bytes());
+    file[42..44].copy_from_slice(&32u16.to_le_bytes());
+    file[44..46].copy_from_slice(&(phnum as u16).to_le_bytes());
+    file[46..48].copy_from_slice(&40u16.to_le_bytes());
+    file[48..50].copy_from_slice(&(headers.len() as u16).to_le_bytes());
+    file
+}
+
+/// A program whose data is linked to run at $0300 and stored after its
+/// code at $C010, with a zeroed `.bss` after it.
+fn program() -> Vec<u8> {
+    link(
+        0xC000,
+        &[
+            Load(0xC000, 0xC000, &[0xAD, 0x00, 0x03, 0x02], 4),
+            Load(0x0300, 0xC010, &[0x11, 0x22], 4),
+        ],
+        &[
+            Section(SHT_PROGBITS, SHF_ALLOC, 0xC000, &[0xAD, 0x00, 0x03, 0x02]),
+            Section(SHT_PROGBITS, SHF_ALLOC, 0x0300, &[0x11, 0x22]),
+        ],
+        &[
+            ("main", 0xC000, STT_FUNC, 1),
+            ("table", 0x0300, 1, 2),
+            (".text", 0xC000, STT_SECTION, 1),
+            ("putchar", 0, STT_FUNC, 0),
+            ("__stack", 0x01FF, 0, 0xFFF1),
+        ],
+    )
+}
+
+#[test]
+fn loads_segments_at_their_load_address() {
+    let elf = Elf::parse(&program()).unwrap();
+    assert_eq!(elf.entry(), 0xC000);
+    assert_eq!(
+        elf.segments(),
+        [
+            Segment {
+                addr: 0xC000,
+                data: vec![0xAD, 0x00, 0x03, 0x02],
+            },
+            Segment {
+                addr: 0xC010,
+                data: vec![0x11, 0x22, 0, 0],
+            },
+        ]
+    );
+
+    let mut vm = Vm::new();
+    vm.load(0xC010, &[0xFF; 8]).unwrap();
+    let mut symbols = SymbolTable::new();
+    symbols.insert("kept", 0x1234);
+    vm.set_symbols(symbols);
+    vm.load_elf(&elf);
+    assert_eq!(vm.registers().pc, 0xC000);
+    assert_eq!(vm.read_mem_raw(0xC010..=0xC014), [0x11, 0x22, 0, 0, 0xFF]);
+    assert_eq!(vm.read(0x0300), 0);
+
+    let symbols = vm.symbols().unwrap();
+    assert_eq!(symbols.address("main"), Some(0xC000));
+    assert_eq!(symbols.address("table"), Some(0x0300));
+    assert_eq!(symbols.address("__stack"), Some(0x01FF));
+    assert_eq!(symbols.address("kept"), Some(0x1234));
+    assert_eq!(symbols.address(".text"), None);
+    assert_eq!(symbols.address("putchar"), None);
+    assert_eq!(symbols.len(), 4);
+}
+
+#[test]
+fn loads_sections_without_program_headers() {
+    let file = link(
+        0x0400,
+        &[],
+        &[
+            Section(SHT_PROGBITS, SHF_ALLOC, 0x0400, &[0xEA, 0x02]),
+            Section(SHT_PROGBITS, 0, 0, b"comment"),
+            Section(SHT_NOBITS, SHF_ALLOC, 0x0200, &[0; 3]),
+        ],
+        &[],
+    );
+    let elf = Elf::parse(&file).unwrap();
+    assert_eq!(
+        elf.segments(),
+        [
+            Segment {
+                addr: 0x0400,
+                data: vec![0xEA, 0x02],
+            },
+            Segment {
+                addr: 0x0200,
+                data: vec![0; 3],
+            },
+        ]
+    );
+    assert!(elf.symbols().is_empty());
+}
+
+#[test]
+fn rejects_files_that_do_not_fit() {
+    assert!(matches!(Elf::parse(b"\x7FEL"), Err(ElfError::BadMagic)));
+    assert!(matches!(Elf::parse(b"MZ\x90\x00"), Err(ElfError::BadMagic)));
+    let file = program();
+    assert!(matches!(Elf::parse(&file[..100]), Err(ElfError::Truncated)));
+
+    let mut wide = file.clone();
+    wide[4] = 2;
+    assert!(matches!(
+        Elf::parse(&wide),
+        Err(ElfError::Unsupported("not a 32-bit file"))
+    ));
+
+    let high = link(0xC000, &[Load(0xFFFE, 0xFFFE, &[1, 2, 3], 3)], &[], &[]);
+    let err = Elf::parse(&high).unwrap_err();
+    assert!(matches!(
+        err,
+        ElfError::OutOfRange {
+            addr: 0xFFFE,
+            len: 3
+        }
+    ));
+    assert_eq!(
+        err.to_string(),
+        "3 bytes at 0xFFFE do not fit the address space"
+    );
+
+    let entry = link(0x1_0000, &[], &[], &[]);
+    assert_eq!(
+        Elf::parse(&entry).unwrap_err().to_string(),
+        "entry point 0x10000 is outside the address space"
+    );
+}
+
+#[test]
+fn rvm8_runs_elf_files() {
+    let path = std::env::temp_dir().join(format!("rvm8-elf-{}-program.elf", std::process::id()));
+    std::fs::write(&path, program()).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_rvm8"))
+        .arg("run")
+        .arg(&path)
+        .args(["--exit-on-halt", "--trace", "-"])
+        .output()
+        .unwrap();
+    let patched = Command::new(env!("CARGO_BIN_EXE_rvm8"))
+        .arg("run")
+        .arg(&path)
+        .args(["--patch", "none.ips"])
+        .output()
+        .unwrap();
+    std::fs::remove_file(path).unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("C000  AD 00 03  LDA table"), "{stdout}");
+    assert_eq!(patched.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&patched.stderr).contains("take a ROM, not an ELF file"));
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use common::vm_with;
+use emulator::events::Event;
+use emulator::uart::{UART_PORTS, Uart};
+use emulator::vm::CLOCK_HZ;
+use emulator::{Vm, VmError};
+
+/// Runs `program` from 0x8000, padded with `LDA #$A9` to last a few
+/// frames at a slow clock, with an unconnected UART mapped.
+fn vm(program: &[u8]) -> Vm {
+    let mut image = vec![0xA9; 0x8000];
+    image[..program.len()].copy_from_slice(program);
+    let mut vm = vm_with(&image);
+    vm.bus_mut().map(UART_PORTS, Uart::new()).unwrap();
+    vm.set_clock_hz(CLOCK_HZ / 8);
+    vm
+}
+
+#[test]
+fn a_frame_queues_its_events_in_order() {
+    // LSR $2720: sends what it received, shifted right.
+    let mut vm = vm(&[0x4E, 0x20, 0x27]);
+    vm.enable_events();
+    vm.bus_mut()
+        .device_mut::<Uart>(*UART_PORTS.start())
+        .unwrap()
+        .push_rx(&[0x82]);
+    vm.run_frame().unwrap();
+    let events: Vec<Event> = vm.drain_events().collect();
+    assert_eq!(events.len(), 3);
+    assert_eq!(
+        events[0],
+        Event::Serial {
+            port: 0x2720,
+            bytes: vec![0x41],
+        }
+    );
+    assert_eq!(events[1], Event::Vblank { frame: 1 });
+    let Event::Audio(samples) = &events[2] else {
+        panic!("{:?} is not audio", events[2]);
+    };
+    assert_eq!(samples.len(), 44_100 / 60);
+
+    vm.run_frame().unwrap();
+    assert_eq!(vm.next_event(), Some(Event::Vblank { frame: 2 }));
+    vm.disable_events();
+    assert_eq!(vm.pending_events(), 0);
+    vm.run_frame().unwrap();
+    assert_eq!(vm.next_event(), None);
+}
+
+#[test]
+fn faults_and_rewinds() {
+    let mut vm = vm(&[0x02]);
+    vm.enable_events();
+    let err = vm.run_frame().unwrap_err();
+    assert_eq!(
+        err,
+        VmError::IllegalOpcode {
+            pc: 0x8000,
+            opcode: 0x02,
+        }
+    );
+    assert_eq!(vm.drain_events().collect::<Vec<_>>(), [Event::Fault(err)]);
+
+    // Frames run again by a rewind were already reported.
+    vm.enable_rewind(8, 4);
+    for _ in 0..3 {
+        vm.run_frame().unwrap();
+    }
+    vm.drain_events().for_each(drop);
+    assert_eq!(vm.rewind(2), Ok(2));
+    assert_eq!(vm.pending_events(), 0);
+}
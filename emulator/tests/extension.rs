@@ -0,0 +1,120 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::vm_with;
+use emulator::{Vm, VmError};
+
+/// `0x02 lo hi`: stores X at the absolute address, taking 5 cycles.
+fn register_stx(vm: &mut Vm) {
+    vm.register_opcode(0x02, |call| {
+        let lo = call.fetch();
+        let hi = call.fetch();
+        let x = call.registers().x;
+        call.write(u16::from_le_bytes([lo, hi]), x);
+        Ok(5)
+    })
+    .unwrap();
+}
+
+#[test]
+fn registered_opcodes_execute_like_instructions() {
+    // LDX #$2A; STX $1234 (extension); LDX #$00; STX $1235
+    let mut vm = vm_with(&[0xA2, 0x2A, 0x02, 0x34, 0x12, 0xA2, 0x00, 0x02, 0x35, 0x12]);
+    register_stx(&mut vm);
+    vm.write(0x1235, 0xFF);
+    vm.run_cycles(2 + 5 + 2 + 5).unwrap();
+
+    assert_eq!(vm.registers().pc, 0x800A);
+    assert_eq!(vm.cycles(), 14);
+    assert_eq!(vm.read(0x1234), 0x2A);
+    assert_eq!(vm.read(0x1235), 0x00);
+    let stats = vm.stats();
+    assert_eq!(stats.opcodes[0x02], 2);
+    assert_eq!(stats.instructions, 4);
+    assert_eq!(stats.writes, 2);
+}
+
+#[test]
+fn handler_accesses_go_through_the_bus() {
+    let mut vm = vm_with(&[0xA2, 0x07, 0x02, 0x00, 0x30]);
+    register_stx(&mut vm);
+    let seen = Arc::new(Mutex::new(None));
+    let sink = seen.clone();
+    vm.on_write(0x3000..=0x3000, move |addr, val| {
+        *sink.lock().unwrap() = Some((addr, val));
+        val + 1
+    });
+    vm.step().unwrap();
+    vm.step().unwrap();
+    assert_eq!(*seen.lock().unwrap(), Some((0x3000, 0x07)));
+    assert_eq!(vm.read(0x3000), 0x08);
+}
+
+#[test]
+fn only_undefined_opcodes_can_be_registered() {
+    let mut vm = vm_with(&[0x02, 0x00, 0x30]);
+    assert_eq!(
+        vm.register_opcode(0xA9, |_| Ok(2)),
+        Err(VmError::OpcodeTaken { opcode: 0xA9 })
+    );
+    assert!(!vm.unregister_opcode(0x02));
+
+    register_stx(&mut vm);
+    assert!(vm.unregister_opcode(0x02));
+    assert_eq!(
+        vm.step(),
+        Err(VmError::IllegalOpcode {
+            pc: 0x8000,
+            opcode: 0x02,
+        })
+    );
+}
+
+#[test]
+fn failing_handlers_stop_the_machine() {
+    // Assertion opcode `0x03 nn`: fails unless A equals the operand.
+    let mut program = [0xA9, 0x05, 0x03, 0x05].repeat(4);
+    program.extend([0xA9, 0x06, 0x03, 0x05]);
+    program.extend([0xA9, 0x05].repeat(20));
+    let mut vm = vm_with(&program);
+    vm.register_opcode(0x03, |call| {
+        let expected = call.fetch();
+        let a = call.registers().a;
+        if a == expected {
+            Ok(2)
+        } else {
+            Err(format!("A is {a}, not {expected}"))
+        }
+    })
+    .unwrap();
+
+    let err = VmError::OpcodeFailed {
+        pc: 0x8012,
+        opcode: 0x03,
+        message: "A is 6, not 5".to_string(),
+    };
+    assert_eq!(vm.run_cycles(1_000), Err(err.clone()));
+    assert_eq!(vm.registers().pc, 0x8014, "the instruction completed");
+    assert_eq!(vm.cycles(), 5 * 2 + 4 * 2);
+    assert_eq!(
+        err.to_string(),
+        "extension opcode 0x03 at PC 0x8012 failed: A is 6, not 5"
+    );
+
+    vm.register_opcode(0x03, |_| panic!("handler panicked"))
+        .unwrap();
+    vm.set_registers(emulator::Registers {
+        pc: 0x8002,
+        ..vm.registers()
+    });
+    assert_eq!(
+        vm.step(),
+        Err(VmError::OpcodeFailed {
+            pc: 0x8002,
+            opcode: 0x03,
+            message: "handler panicked".to_string(),
+        })
+    );
+    assert_eq!(vm.registers().pc, 0x8003);
+}
@@ -0,0 +1,135 @@
+mod common;
+
+use std::path::PathBuf;
+
+use common::vm_with;
+use emulator::files::{
+    CMD_CLOSE, CMD_CREATE, CMD_END, CMD_OPEN, CMD_SEEK, FILE_PORTS, FileService, STATUS_EOF,
+    STATUS_ERROR, STATUS_NOT_FOUND, STATUS_OK,
+};
+use emulator::{BusDevice, ResetKind};
+
+const CMD: u16 = 0;
+const NAME: u16 = 1;
+const DATA: u16 = 2;
+const POS: u16 = 3;
+
+/// A fresh directory for one test.
+fn dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rvm8-files-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir(&dir).unwrap();
+    dir
+}
+
+/// Writes `name` and runs `cmd`, returning the status.
+fn command(files: &mut FileService, name: &str, cmd: u8) -> u8 {
+    for byte in name.bytes() {
+        files.write8(NAME, byte);
+    }
+    files.write8(CMD, cmd);
+    files.read8(CMD)
+}
+
+fn seek(files: &mut FileService, pos: u32) -> u8 {
+    for (i, byte) in pos.to_le_bytes().into_iter().enumerate() {
+        files.write8(POS + i as u16, byte);
+    }
+    command(files, "", CMD_SEEK)
+}
+
+fn pos(files: &mut FileService) -> u32 {
+    u32::from_le_bytes([0, 1, 2, 3].map(|i| files.read8(POS + i)))
+}
+
+#[test]
+fn programs_read_files_through_the_ports() {
+    let dir = dir("read");
+    std::fs::write(dir.join("level1.bin"), [0x11, 0x22, 0x33]).unwrap();
+    // LDA $2792; LDX $2792
+    let mut vm = vm_with(&[0xAD, 0x92, 0x27, 0xAE, 0x92, 0x27]);
+    let mut files = FileService::directory(&dir);
+    assert_eq!(command(&mut files, "level1.bin", CMD_OPEN), STATUS_OK);
+    vm.bus_mut().map(FILE_PORTS, files).unwrap();
+
+    vm.step().unwrap();
+    vm.step().unwrap();
+    assert_eq!((vm.registers().a, vm.registers().x), (0x11, 0x22));
+    let files = vm.bus_mut().device_mut::<FileService>(0x2790).unwrap();
+    assert_eq!(pos(files), 2);
+    assert_eq!(files.read8(DATA), 0x33);
+    assert_eq!(files.read8(DATA), 0);
+    assert_eq!(files.read8(CMD), STATUS_EOF);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn created_files_are_written_back_on_close() {
+    let dir = dir("write");
+    let mut files = FileService::directory(&dir);
+    assert_eq!(command(&mut files, "save.dat", CMD_CREATE), STATUS_OK);
+    for byte in b"hi" {
+        files.write8(DATA, *byte);
+    }
+    assert_eq!(seek(&mut files, 4), STATUS_OK);
+    files.write8(DATA, b'!');
+    assert_eq!(command(&mut files, "", CMD_END), STATUS_OK);
+    assert_eq!(pos(&mut files), 5, "the size");
+    assert_eq!(std::fs::read(dir.join("save.dat")).unwrap(), b"");
+
+    assert_eq!(command(&mut files, "", CMD_CLOSE), STATUS_OK);
+    assert_eq!(std::fs::read(dir.join("save.dat")).unwrap(), b"hi\0\0!");
+
+    // Opened for reading only, and written back on reset too.
+    assert_eq!(command(&mut files, "save.dat", CMD_OPEN), STATUS_OK);
+    files.write8(DATA, b'x');
+    assert_eq!(files.read8(CMD), STATUS_ERROR);
+    assert_eq!(command(&mut files, "new.dat", CMD_CREATE), STATUS_OK);
+    files.write8(DATA, b'y');
+    files.reset(ResetKind::Soft);
+    assert_eq!(std::fs::read(dir.join("new.dat")).unwrap(), b"y");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn names_stay_inside_the_directory() {
+    let dir = dir("names");
+    let mut files = FileService::directory(dir.join("inner"));
+    std::fs::create_dir(dir.join("inner")).unwrap();
+    std::fs::write(dir.join("secret"), b"x").unwrap();
+    assert_eq!(command(&mut files, "missing", CMD_OPEN), STATUS_NOT_FOUND);
+    for name in ["../secret", "..", "", "a/b"] {
+        assert_eq!(
+            command(&mut files, name, CMD_OPEN),
+            STATUS_ERROR,
+            "{name:?}"
+        );
+    }
+    assert_eq!(
+        command(&mut files, "", CMD_SEEK),
+        STATUS_ERROR,
+        "nothing open"
+    );
+    assert_eq!(command(&mut files, "", 0x7F), STATUS_ERROR);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn disk_images_are_rewritten_in_place() {
+    let dir = dir("image");
+    let image = dir.join("disk.img");
+    std::fs::write(&image, [0; 512]).unwrap();
+    let mut files = FileService::image(&image);
+    assert_eq!(command(&mut files, "", CMD_CREATE), STATUS_ERROR);
+    assert_eq!(command(&mut files, "anything", CMD_OPEN), STATUS_OK);
+    assert_eq!(seek(&mut files, 256), STATUS_OK);
+    files.write8(DATA, 0xAB);
+    assert_eq!(seek(&mut files, 512), STATUS_OK);
+    files.write8(DATA, 0xCD);
+    assert_eq!(files.read8(CMD), STATUS_ERROR, "images do not grow");
+    drop(files);
+
+    let bytes = std::fs::read(&image).unwrap();
+    assert_eq!((bytes.len(), bytes[256]), (512, 0xAB));
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,129 @@
+use emulator::flow::{Block, Exit, FlowGraph};
+use emulator::{ResetKind, SymbolTable, Vm};
+
+/// A machine with `program` at $C000 and every vector pointing into it.
+fn vm_with(program: &[u8], nmi: u16, reset: u16, irq: u16) -> Vm {
+    let mut vm = Vm::new();
+    vm.load(0xC000, program).unwrap();
+    let vectors: Vec<u8> = [nmi, reset, irq]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+    vm.load(0xFFFA, &vectors).unwrap();
+    vm
+}
+
+#[test]
+fn splits_code_at_entries_and_undefined_opcodes() {
+    // LDA #$01; LDX #$02; ???; LDY #$03; LSR $0200; ???
+    let program = [
+        0xA9, 0x01, 0xA2, 0x02, 0x02, 0xA0, 0x03, 0x4E, 0x00, 0x02, 0x02,
+    ];
+    let vm = vm_with(&program, 0xC002, 0xC000, 0xC005);
+    let graph = vm.flow_graph();
+    let blocks: Vec<Block> = graph.blocks().copied().collect();
+    assert_eq!(
+        blocks,
+        [
+            Block {
+                start: 0xC000,
+                end: 0xC000,
+                len: 1,
+                exit: Exit::Fall(0xC002),
+            },
+            Block {
+                start: 0xC002,
+                end: 0xC004,
+                len: 2,
+                exit: Exit::Halt,
+            },
+            Block {
+                start: 0xC005,
+                end: 0xC00A,
+                len: 3,
+                exit: Exit::Halt,
+            },
+        ]
+    );
+    assert_eq!(graph.block(0xC000).unwrap().successors(), [0xC002]);
+    assert!(graph.block(0xC003).is_none());
+    assert_eq!(graph.subroutines(), [0xC000, 0xC002, 0xC005]);
+    assert_eq!(graph.body(0xC000).len(), 2);
+}
+
+#[test]
+fn extra_entries_split_blocks_where_paths_join() {
+    // LDA #$01; LDA #$02; LDA #$03; ???
+    let mut vm = Vm::new();
+    vm.load(0x1000, &[0xA9, 0x01, 0xA9, 0x02, 0xA9, 0x03, 0x02])
+        .unwrap();
+    let graph = FlowGraph::analyze(&vm, [0x1004, 0x1000]);
+    let starts: Vec<(u16, u16)> = graph.blocks().map(|b| (b.start, b.end)).collect();
+    assert_eq!(starts, [(0x1000, 0x1002), (0x1004, 0x1006)]);
+    assert_eq!(graph.entries().collect::<Vec<_>>(), [0x1000, 0x1004]);
+
+    // Code running off the top of memory carries on at $0000.
+    let mut vm = Vm::new();
+    vm.load(0xFFFE, &[0xA9, 0x01]).unwrap();
+    vm.load(0x0000, &[0x02]).unwrap();
+    let graph = FlowGraph::analyze(&vm, [0xFFFE]);
+    let block = graph.block(0xFFFE).unwrap();
+    assert_eq!((block.end, block.len, block.exit), (0x0000, 2, Exit::Halt));
+}
+
+#[test]
+fn exports_the_control_flow_and_call_graphs() {
+    let mut vm = vm_with(
+        &[0xA9, 0x01, 0xAD, 0x34, 0x12, 0x02],
+        0xC002,
+        0xC000,
+        0xC002,
+    );
+    vm.set_symbols(SymbolTable::parse("main = $C000\ncounter = $1234\n").unwrap());
+    let graph = vm.flow_graph();
+    let dot = graph.to_dot(&vm, None);
+    assert!(dot.starts_with("digraph cfg {\n"), "{dot}");
+    assert!(
+        dot.contains("  bC000 [label=\"reset: main:\\l$C000  LDA #$01\\l\"];\n"),
+        "{dot}"
+    );
+    assert!(
+        dot.contains("  bC002 [label=\"nmi/irq:\\l$C002  LDA counter\\l$C005  ???\\l\"];\n"),
+        "{dot}"
+    );
+    assert!(dot.contains("  bC000 -> bC002;\n"));
+    assert!(dot.ends_with("}\n"));
+
+    let calls = graph.call_graph_dot(&vm, None);
+    assert!(
+        calls.contains("  sC000 [label=\"reset: main\"];\n"),
+        "{calls}"
+    );
+    assert!(calls.contains("  sC002 [label=\"nmi/irq\"];\n"), "{calls}");
+    assert!(!calls.contains("->"));
+}
+
+#[test]
+fn annotates_nodes_with_execution_counts() {
+    let mut vm = vm_with(&[0xA9, 0x01, 0xA9, 0x02, 0x02], 0xC004, 0xC000, 0xC002);
+    vm.reset(ResetKind::Soft);
+    vm.enable_profiling();
+    vm.step().unwrap();
+    let graph = vm.flow_graph();
+    let dot = graph.to_dot(&vm, vm.profile());
+    assert!(
+        dot.contains("$C000  LDA #$01\\lran 1x, 2 cycles\\l\"];"),
+        "{dot}"
+    );
+    assert!(
+        dot.contains("ran 0x, 0 cycles\\l\", style=dashed];"),
+        "{dot}"
+    );
+
+    let calls = graph.call_graph_dot(&vm, vm.profile());
+    assert!(
+        calls.contains("sC000 [label=\"reset\\nran 1x\"];"),
+        "{calls}"
+    );
+    assert!(calls.contains("sC004 [label=\"nmi\\nran 0x\", style=dashed];"));
+}
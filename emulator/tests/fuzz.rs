@@ -0,0 +1,71 @@
+use emulator::fuzz::ORIGIN;
+use emulator::{CpuConfig, IllegalOpcodes, Registers, Vm, VmError};
+
+/// xorshift32, so failures reproduce from the seed alone.
+fn rng(mut state: u32) -> impl FnMut() -> u32 {
+    move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    }
+}
+
+#[test]
+fn indirect_indexed_loads_take_cycles() {
+    // LDA ($40),Y from $12F0 and $1300.
+    let mut vm = Vm::new();
+    vm.load(0x0040, &[0xF0, 0x12]).unwrap();
+    vm.execute_raw(&[0xB1, 0x40], 1).unwrap();
+    assert_eq!(vm.cycles(), 5);
+
+    vm.set_registers(Registers {
+        y: 0x10,
+        ..vm.registers()
+    });
+    vm.execute_raw(&[0xB1, 0x40], 1).unwrap();
+    assert_eq!(vm.cycles(), 5 + 6, "crossing into $1300 costs one more");
+}
+
+#[test]
+fn random_bytes_run_to_the_budget_or_an_error() {
+    for (seed, illegal_opcodes) in (1..=48).zip(
+        [
+            IllegalOpcodes::Trap,
+            IllegalOpcodes::Nop,
+            IllegalOpcodes::Undocumented,
+        ]
+        .into_iter()
+        .cycle(),
+    ) {
+        let mut next = rng(seed);
+        let program: Vec<u8> = (0..0x800).map(|_| next() as u8).collect();
+        let mut vm = Vm::new();
+        vm.set_cpu_config(CpuConfig {
+            illegal_opcodes,
+            ..CpuConfig::default()
+        });
+        match vm.execute_raw(&program, 50_000) {
+            Ok(()) => assert!(vm.cycles() >= 50_000, "seed {seed}"),
+            Err(VmError::IllegalOpcode { .. }) => {
+                assert_ne!(illegal_opcodes, IllegalOpcodes::Nop, "seed {seed}")
+            }
+            Err(err) => panic!("seed {seed}: {err}"),
+        }
+    }
+}
+
+#[test]
+fn programs_are_clipped_to_the_address_space() {
+    let mut vm = Vm::new();
+    let err = vm.execute_raw(&[0x02; 0x10000], 1_000).unwrap_err();
+    assert_eq!(
+        err,
+        VmError::IllegalOpcode {
+            pc: ORIGIN,
+            opcode: 0x02,
+        }
+    );
+    assert_eq!(vm.read(0xFFFF), 0x02);
+    assert_eq!(vm.read(ORIGIN - 1), 0, "nothing wraps around");
+}
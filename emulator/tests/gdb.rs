@@ -0,0 +1,148 @@
+#![cfg(feature = "gdb")]
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+use common::vm_with;
+use emulator::Vm;
+use emulator::ffi::RVM_MEM_SIZE;
+use emulator::input::CONTROLLER;
+
+/// Minimal debugger side of the protocol.
+struct Client(TcpStream);
+
+impl Client {
+    fn request(&mut self, data: &str) -> String {
+        self.send(data);
+        self.reply()
+    }
+
+    fn send(&mut self, data: &str) {
+        let sum = data.bytes().fold(0u8, |s, b| s.wrapping_add(b));
+        write!(self.0, "${data}#{sum:02x}").unwrap();
+        assert_eq!(self.byte(), b'+');
+    }
+
+    fn reply(&mut self) -> String {
+        assert_eq!(self.byte(), b'$');
+        let mut reply = Vec::new();
+        loop {
+            match self.byte() {
+                b'#' => break,
+                byte => reply.push(byte),
+            }
+        }
+        let mut sum = [0; 2];
+        self.0.read_exact(&mut sum).unwrap();
+        self.0.write_all(b"+").unwrap();
+        String::from_utf8(reply).unwrap()
+    }
+
+    fn byte(&mut self) -> u8 {
+        let mut byte = [0];
+        self.0.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+}
+
+/// Runs a session on `vm` against `script`, which drives the client.
+fn debug(vm: &mut Vm, script: impl FnOnce(&mut Client) + Send + 'static) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = thread::spawn(move || {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_nodelay(true).unwrap();
+        let mut client = Client(stream);
+        script(&mut client);
+        assert_eq!(client.request("D"), "OK");
+    });
+    let (stream, _) = listener.accept().unwrap();
+    vm.run_gdb_session(stream).unwrap();
+    client.join().unwrap();
+}
+
+#[test]
+fn registers_and_memory() {
+    let mut vm = vm_with(&[0xA9, 0x42]);
+    debug(&mut vm, |gdb| {
+        assert!(
+            gdb.request("qSupported:swbreak+")
+                .contains("qXfer:features:read+")
+        );
+        assert!(
+            gdb.request("qXfer:features:read:target.xml:0,fff")
+                .starts_with("l<?xml")
+        );
+        assert_eq!(gdb.request("?"), "S05");
+        assert_eq!(gdb.request("g"), "00000004fd000080");
+        assert_eq!(gdb.request("P1=7f"), "OK");
+        assert_eq!(gdb.request("p1"), "7f");
+        assert_eq!(gdb.request("m8000,2"), "a942");
+        assert_eq!(gdb.request("M1000,3:010203"), "OK");
+        assert_eq!(gdb.request("m1000,3"), "010203");
+        assert_eq!(gdb.request("mffff,2"), "E01");
+        assert_eq!(gdb.request("vMustReplyEmpty"), "");
+    });
+    assert_eq!(vm.registers().x, 0x7F);
+    assert_eq!(vm.read(0x1002), 3);
+}
+
+#[test]
+fn step_and_continue_to_breakpoint() {
+    // LDA #$01; LDA #$02; LDA #$03; LDA #$04
+    let mut vm = vm_with(&[0xA9, 0x01, 0xA9, 0x02, 0xA9, 0x03, 0xA9, 0x04]);
+    debug(&mut vm, |gdb| {
+        assert_eq!(gdb.request("s"), "S05");
+        assert_eq!(gdb.request("p5"), "0280");
+        assert_eq!(gdb.request("Z0,8006,1"), "OK");
+        assert_eq!(gdb.request("c"), "S05");
+        assert_eq!(gdb.request("p5"), "0680");
+        assert_eq!(gdb.request("z0,8006,1"), "OK");
+        // Running off the end of the program hits an illegal opcode.
+        assert_eq!(gdb.request("c"), "S04");
+    });
+    assert!(vm.breakpoints().is_empty());
+}
+
+#[test]
+fn watchpoints_report_the_address() {
+    // LDA #$01; LSR $1234
+    let mut vm = vm_with(&[0xA9, 0x01, 0x4E, 0x34, 0x12]);
+    debug(&mut vm, |gdb| {
+        assert_eq!(gdb.request("Z2,1234,1"), "OK");
+        assert_eq!(gdb.request("c"), "T05watch:1234;");
+        assert_eq!(gdb.request("?"), "T05watch:1234;");
+        assert_eq!(gdb.request("z2,1234,1"), "OK");
+    });
+    assert!(vm.watchpoints().is_empty());
+}
+
+#[test]
+fn watchpoints_reach_the_end_of_memory() {
+    let mut vm = vm_with(&[0xA9, 0x01]);
+    debug(&mut vm, |gdb| {
+        assert_eq!(gdb.request("Z2,ffff,0"), "OK");
+        assert_eq!(gdb.request("Z3,0,10000"), "OK");
+        assert_eq!(gdb.request("Z4,ffff,2"), "E01");
+    });
+    let ranges: Vec<_> = vm.watchpoints().iter().map(|w| w.range.clone()).collect();
+    assert_eq!(ranges, [0xFFFF..=0xFFFF, 0x0000..=0xFFFF]);
+}
+
+#[test]
+fn ctrl_c_interrupts_a_running_target() {
+    // `LDA #$A9` over the whole address space runs forever.
+    let mut vm = Vm::new();
+    vm.bus_mut().unmap(CONTROLLER);
+    vm.load(0, &vec![0xA9; RVM_MEM_SIZE]).unwrap();
+    debug(&mut vm, |gdb| {
+        gdb.send("c");
+        thread::sleep(std::time::Duration::from_millis(20));
+        gdb.0.write_all(&[0x03]).unwrap();
+        assert_eq!(gdb.reply(), "S02");
+    });
+    assert!(vm.cycles() > 0);
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+
+use common::vm_with;
+use emulator::Vm;
+use emulator::audio::{AUDIO_ENABLE, ENABLE_SQUARE1, SQUARE1_CTRL, SQUARE1_PERIOD};
+use emulator::display::{BG_PALETTE, CTRL, CTRL_BACKGROUND, TILE_DATA, TILEMAP};
+use emulator::golden::{self, GoldenError};
+
+/// A machine drawing a tile and playing a square wave while it executes
+/// `LSR $0300`.
+fn playing_vm() -> Vm {
+    let mut vm = vm_with(&[0x4E, 0x00, 0x03].repeat(10921));
+    vm.load(TILE_DATA + 16, &[0xFF; 16]).unwrap();
+    vm.write(TILEMAP, 1);
+    vm.write(BG_PALETTE, 0b11_10_01_00);
+    vm.write(CTRL, CTRL_BACKGROUND);
+    vm.load(SQUARE1_PERIOD, &[0x40, 0x00, 0x2F]).unwrap();
+    vm.write(AUDIO_ENABLE, ENABLE_SQUARE1);
+    vm
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rvm8-{name}-{}.golden", std::process::id()))
+}
+
+#[test]
+fn a_recorded_run_verifies_until_something_changes() {
+    let path = golden_path("verify");
+    golden::record(&mut playing_vm(), 3, &path).unwrap();
+    let text = fs::read_to_string(&path).unwrap();
+    assert_eq!(
+        text.lines().filter(|line| !line.starts_with('#')).count(),
+        3
+    );
+    golden::verify(&mut playing_vm(), &path).unwrap();
+
+    let mut quieter = playing_vm();
+    quieter.write(SQUARE1_CTRL, 0x2E);
+    let err = golden::verify(&mut quieter, &path).unwrap_err();
+    assert!(
+        matches!(err, GoldenError::Mismatch { frame: 0, .. }),
+        "{err}"
+    );
+
+    fs::write(&path, text.replace("\n1 ", "\n2 ")).unwrap();
+    let err = golden::verify(&mut playing_vm(), &path).unwrap_err();
+    assert!(matches!(err, GoldenError::BadLine { line: 3 }), "{err}");
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn frame_hashes_cover_the_picture_and_the_sound() {
+    let (mut vm, mut silent) = (playing_vm(), playing_vm());
+    silent.write(AUDIO_ENABLE, 0);
+    vm.set_audio_callback(|_| {});
+    silent.set_audio_callback(|_| {});
+    vm.run_frame().unwrap();
+    silent.run_frame().unwrap();
+    assert_eq!(vm.framebuffer(), silent.framebuffer());
+    assert_ne!(vm.frame_hash(), silent.frame_hash());
+
+    let mut other = playing_vm();
+    other.set_audio_callback(|_| {});
+    other.run_frame().unwrap();
+    assert_eq!(other.frame_hash(), vm.frame_hash());
+}
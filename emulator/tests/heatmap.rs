@@ -0,0 +1,87 @@
+mod common;
+
+use common::vm_with;
+use emulator::ResetKind;
+use emulator::heatmap::{AccessCounts, Heatmap};
+
+#[test]
+fn counts_reads_writes_and_executes() {
+    // LDA $0200; LSR $0300; LDX #$01
+    let mut vm = vm_with(&[0xAD, 0x00, 0x02, 0x4E, 0x00, 0x03, 0xA2, 0x01]);
+    vm.enable_heatmap();
+    vm.run_cycles(4 + 6 + 2).unwrap();
+    vm.write(0x0400, 1);
+    let heatmap = vm.take_heatmap().unwrap();
+
+    assert_eq!(
+        heatmap.get(0x0200),
+        AccessCounts {
+            reads: 1,
+            writes: 0,
+            executes: 0,
+        }
+    );
+    assert_eq!(heatmap.get(0x0300).reads, 1);
+    assert_eq!(heatmap.get(0x0300).writes, 1);
+    // Fetches count as reads.
+    assert_eq!(
+        heatmap.get(0x8000),
+        AccessCounts {
+            reads: 1,
+            writes: 0,
+            executes: 1,
+        }
+    );
+    assert_eq!(heatmap.get(0x8001).executes, 0);
+    assert_eq!(heatmap.executes().iter().sum::<u64>(), 3);
+    assert_eq!(heatmap.writes().iter().sum::<u64>(), 1);
+    assert_eq!(heatmap.get(0x0400), AccessCounts::default());
+    assert!(vm.heatmap().is_none());
+}
+
+#[test]
+fn accumulates_across_resets_and_merges() {
+    let mut vm = vm_with(&[0xA9, 0x01]);
+    vm.enable_heatmap();
+    vm.step().unwrap();
+    vm.reset(ResetKind::Soft);
+    vm.step().unwrap();
+    let mut heatmap = vm.heatmap().unwrap().clone();
+    assert_eq!(heatmap.get(0x8000).executes, 2);
+
+    heatmap.merge(&heatmap.clone());
+    assert_eq!(heatmap.get(0x8000).executes, 4);
+    assert_eq!(Heatmap::new().get(0x8000), AccessCounts::default());
+}
+
+#[test]
+fn renders_a_png() {
+    let mut vm = vm_with(&[0xAD, 0x00, 0x02]);
+    vm.enable_heatmap();
+    vm.step().unwrap();
+    let mut png = Vec::new();
+    vm.heatmap().unwrap().write_png(&mut png).unwrap();
+    assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+    // 256x256, 8-bit RGB.
+    assert_eq!(&png[16..26], &[0, 0, 1, 0, 0, 0, 1, 0, 8, 2]);
+    // The image data is stored deflate blocks after a 2-byte zlib header.
+    let mut at = png.windows(4).position(|w| w == b"IDAT").unwrap() + 4 + 2;
+    let mut image = Vec::new();
+    loop {
+        let last = png[at] == 1;
+        let len = u16::from_le_bytes([png[at + 1], png[at + 2]]) as usize;
+        image.extend_from_slice(&png[at + 5..at + 5 + len]);
+        at += 5 + len;
+        if last {
+            break;
+        }
+    }
+    assert_eq!(image.len(), 256 * (1 + 256 * 3));
+    let pixel = |addr: usize| {
+        let at = addr / 256 * (1 + 256 * 3) + 1 + addr % 256 * 3;
+        &image[at..at + 3]
+    };
+    assert_eq!(pixel(0x0000), [0, 0, 0]);
+    assert_eq!(pixel(0x0200), [0, 255, 0]);
+    assert_eq!(pixel(0x8000), [0, 255, 255]);
+}
@@ -0,0 +1,220 @@
+use std::process::Command;
+
+use emulator::hexfile::{HexError, HexErrorKind, Image, Region};
+use emulator::{Registers, Vm};
+
+/// An Intel HEX record with its checksum.
+fn ihex(kind: u8, offset: u16, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend_from_slice(&offset.to_be_bytes());
+    bytes.push(kind);
+    bytes.extend_from_slice(data);
+    let sum = bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+    bytes.push(sum.wrapping_neg());
+    let digits: String = bytes.iter().map(|b| format!("{b:02X}")).collect();
+    format!(":{digits}\n")
+}
+
+/// An S-record of type `kind` with an address `width` bytes wide.
+fn srec(kind: u8, width: usize, addr: u32, data: &[u8]) -> String {
+    let mut bytes = vec![(width + data.len() + 1) as u8];
+    bytes.extend_from_slice(&addr.to_be_bytes()[4 - width..]);
+    bytes.extend_from_slice(data);
+    let sum = bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+    bytes.push(!sum);
+    let digits: String = bytes.iter().map(|b| format!("{b:02X}")).collect();
+    format!("S{kind}{digits}\n")
+}
+
+fn error(text: &str) -> HexError {
+    Image::parse(text).unwrap_err()
+}
+
+#[test]
+fn loads_sparse_intel_hex() {
+    let text = [
+        ihex(0x00, 0xC000, &[0xA9, 0x01]),
+        ihex(0x00, 0xC002, &[0x4E, 0xFF, 0x27]),
+        ihex(0x00, 0xFFFC, &[0x00, 0xC0]),
+        // Linear base 0 keeps the addresses, a segment base moves them up.
+        ihex(0x04, 0, &[0x00, 0x00]),
+        ihex(0x02, 0, &[0x01, 0x00]),
+        ihex(0x00, 0x0000, &[0x55]),
+        ihex(0x01, 0, &[]),
+    ]
+    .concat();
+    let image = Image::parse(&text).unwrap();
+    assert_eq!(
+        image.regions(),
+        [
+            Region {
+                addr: 0x1000,
+                data: vec![0x55],
+            },
+            Region {
+                addr: 0xC000,
+                data: vec![0xA9, 0x01, 0x4E, 0xFF, 0x27],
+            },
+            Region {
+                addr: 0xFFFC,
+                data: vec![0x00, 0xC0],
+            },
+        ]
+    );
+    assert_eq!(image.entry(), None);
+
+    let mut vm = Vm::new();
+    vm.load(0xC005, &[0xEE]).unwrap();
+    vm.load_image(&image);
+    assert_eq!(vm.read_mem_raw(0xC004..=0xC005), [0x27, 0xEE]);
+    assert_eq!(vm.registers().pc, Vm::new().registers().pc);
+
+    let (start, bytes) = image.flatten(0xFF).unwrap();
+    assert_eq!(start, 0x1000);
+    assert_eq!(bytes.len(), 0xFFFE - 0x1000);
+    assert_eq!(bytes[1], 0xFF);
+    assert_eq!(Image::default().flatten(0), None);
+}
+
+#[test]
+fn loads_s_records_and_their_start_address() {
+    let text = [
+        srec(0, 2, 0, b"hello"),
+        srec(1, 2, 0x0400, &[0xEA, 0xEA]),
+        srec(2, 3, 0x0402, &[0x02]),
+        srec(3, 4, 0x0200, &[7]),
+        srec(5, 2, 3, &[]),
+        srec(9, 2, 0x0400, &[]),
+    ]
+    .concat();
+    let image = Image::parse(&text).unwrap();
+    assert_eq!(image.entry(), Some(0x0400));
+    assert_eq!(
+        image.regions(),
+        [
+            Region {
+                addr: 0x0200,
+                data: vec![7],
+            },
+            Region {
+                addr: 0x0400,
+                data: vec![0xEA, 0xEA, 0x02],
+            },
+        ]
+    );
+    let mut vm = Vm::new();
+    vm.set_registers(Registers {
+        pc: 0x1234,
+        ..vm.registers()
+    });
+    vm.load_image(&image);
+    assert_eq!(vm.registers().pc, 0x0400);
+
+    let start = ihex(0x05, 0, &[0, 0, 0xC0, 0x00]);
+    assert_eq!(Image::parse(&start).unwrap().entry(), Some(0xC000));
+    let start = ihex(0x03, 0, &[0x0C, 0x00, 0x00, 0x10]);
+    assert_eq!(Image::parse(&start).unwrap().entry(), Some(0xC010));
+}
+
+#[test]
+fn overlapping_records_name_both_lines() {
+    let text = [
+        ihex(0x00, 0x8000, &[1, 2, 3, 4]),
+        ihex(0x00, 0x9000, &[5]),
+        ihex(0x00, 0x7FFE, &[6, 7, 8]),
+    ]
+    .concat();
+    let err = error(&text);
+    assert_eq!(
+        err,
+        HexError {
+            line: 3,
+            kind: HexErrorKind::Overlap {
+                addr: 0x8000,
+                line: 1,
+            },
+        }
+    );
+    assert_eq!(
+        err.to_string(),
+        "line 3: byte at 0x8000 was already given on line 1"
+    );
+}
+
+#[test]
+fn bad_records_are_reported_by_line() {
+    let good = ihex(0x00, 0x0000, &[1]);
+    let kind = |text: &str| error(text).kind;
+
+    let mut corrupt = good.clone();
+    corrupt.replace_range(11..13, "00");
+    assert_eq!(
+        kind(&corrupt),
+        HexErrorKind::BadChecksum {
+            expected: 0xFE,
+            found: 0x00,
+        }
+    );
+    assert_eq!(kind(":0200000001FD\n"), HexErrorKind::BadLength);
+    assert_eq!(kind(&ihex(0x07, 0, &[])), HexErrorKind::UnknownRecord(7));
+    assert_eq!(
+        kind(&[ihex(0x04, 0, &[0, 1]), good.clone()].concat()),
+        HexErrorKind::OutOfRange {
+            addr: 0x10000,
+            len: 1,
+        }
+    );
+    assert_eq!(
+        kind(&ihex(0x00, 0xFFFF, &[1, 2])),
+        HexErrorKind::OutOfRange {
+            addr: 0xFFFF,
+            len: 2,
+        }
+    );
+    assert_eq!(
+        kind(&[ihex(0x01, 0, &[]), good.clone()].concat()),
+        HexErrorKind::AfterEnd
+    );
+    assert_eq!(
+        error(&format!("{good}\n:01zz\n")),
+        HexError {
+            line: 3,
+            kind: HexErrorKind::Syntax,
+        }
+    );
+    assert_eq!(error("\n\nMZ").line, 3);
+
+    assert_eq!(
+        kind(&[srec(1, 2, 0, &[1]), srec(5, 2, 2, &[])].concat()),
+        HexErrorKind::CountMismatch {
+            count: 2,
+            records: 1,
+        }
+    );
+    assert_eq!(kind(&srec(4, 2, 0, &[])), HexErrorKind::UnknownRecord(4));
+    assert_eq!(
+        error(&srec(3, 4, 0x0001_0000, &[1, 2])).to_string(),
+        "line 1: 2 bytes at 0x10000 do not fit the address space"
+    );
+}
+
+#[test]
+fn rvm8_runs_hex_files() {
+    // LSR $27FF with $0A there exits with 5.
+    let text = [
+        ihex(0x00, 0x27FF, &[0x0A]),
+        ihex(0x00, 0xC000, &[0x4E, 0xFF, 0x27]),
+        ihex(0x00, 0xFFFC, &[0x00, 0xC0]),
+        ihex(0x01, 0, &[]),
+    ]
+    .concat();
+    let path = std::env::temp_dir().join(format!("rvm8-hex-{}-program.HEX", std::process::id()));
+    std::fs::write(&path, text).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_rvm8"))
+        .arg("run")
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(output.status.code(), Some(5), "{output:?}");
+}
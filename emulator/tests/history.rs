@@ -0,0 +1,41 @@
+mod common;
+
+use common::vm_with;
+use emulator::{Vm, VmError};
+
+/// `LDA #$01`, `LDX #$02`, `LDY #$03` and the illegal opcode 0x02 from
+/// 0x8000.
+fn vm_with_program() -> Vm {
+    vm_with(&[0xA9, 0x01, 0xA2, 0x02, 0xA0, 0x03, 0x02])
+}
+
+#[test]
+fn keeps_the_last_instructions_up_to_a_fault() {
+    let mut vm = vm_with_program();
+    vm.set_history_len(3);
+    let err = vm.run_frame().unwrap_err();
+    assert!(matches!(err, VmError::IllegalOpcode { pc: 0x8006, .. }));
+
+    let entries: Vec<_> = vm.history().collect();
+    let pcs: Vec<u16> = entries.iter().map(|entry| entry.pc).collect();
+    assert_eq!(pcs, [0x8002, 0x8004, 0x8006], "the oldest fell out");
+    assert_eq!(entries[2].opcode, 0x02);
+    assert_eq!(entries[1].registers.x, 0x02);
+    assert!(!entries[0].interrupt);
+    assert_eq!(
+        entries[0].to_string(),
+        "8002  A2   A:01 X:00 Y:00 P:04 SP:FD CYC:2"
+    );
+
+    vm.set_history_len(1);
+    assert_eq!(
+        vm.history().map(|entry| entry.pc).collect::<Vec<_>>(),
+        [0x8006]
+    );
+    vm.clear_history();
+    assert_eq!(vm.history().count(), 0);
+    assert_eq!(vm.history_len(), 1);
+    vm.set_history_len(0);
+    vm.step().unwrap_err();
+    assert_eq!(vm.history().count(), 0, "off");
+}
@@ -0,0 +1,225 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::vm_with;
+use emulator::ffi::{BusAccess, RVM_MEM_SIZE};
+use emulator::hooks::{Access, CodeWatch, CodeWrite};
+use emulator::input::CONTROLLER;
+use emulator::{Registers, Vm, WatchKind};
+
+#[test]
+fn pc_hooks_run_before_the_instruction_and_can_poke() {
+    // LDA #$01; LDX $1000
+    let mut vm = vm_with(&[0xA9, 0x01, 0xAE, 0x00, 0x10]);
+    vm.on_pc(0x8002, |vm| {
+        assert_eq!(vm.registers().a, 1);
+        vm.write(0x1000, 0x99);
+    });
+    vm.step().unwrap();
+    vm.step().unwrap();
+    assert_eq!(vm.registers().x, 0x99);
+}
+
+#[test]
+fn pc_hooks_can_redirect_execution() {
+    // LDA #$01 at 0x8000, LDA #$22 at 0x8010.
+    let mut vm = vm_with(&[0xA9, 0x01]);
+    vm.load(0x8010, &[0xA9, 0x22]).unwrap();
+    vm.on_pc(0x8000, |vm| {
+        vm.set_registers(Registers {
+            pc: 0x8010,
+            ..vm.registers()
+        })
+    });
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 0x22);
+    assert_eq!(vm.registers().pc, 0x8012);
+}
+
+#[test]
+fn access_hooks_see_matching_accesses_only() {
+    // LDA $1000; LSR $1000; LDA $2000
+    let mut vm = vm_with(&[0xAD, 0x00, 0x10, 0x4E, 0x00, 0x10, 0xAD, 0x00, 0x20]);
+    vm.write(0x1000, 0x84);
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let log = Arc::clone(&seen);
+    vm.on_access(0x1000..=0x10FF, WatchKind::Write, move |_, access| {
+        log.lock().unwrap().push(access);
+    });
+    for _ in 0..3 {
+        vm.step().unwrap();
+    }
+    assert_eq!(
+        *seen.lock().unwrap(),
+        [Access {
+            addr: 0x1000,
+            kind: BusAccess::Write,
+            value: 0x42,
+        }]
+    );
+}
+
+#[test]
+fn hooks_can_remove_themselves() {
+    let mut vm = vm_with(&[0xA9, 0x01, 0xA9, 0x02, 0xA9, 0x03]);
+    let calls = Arc::new(Mutex::new(0));
+    let count = Arc::clone(&calls);
+    let id = Arc::new(Mutex::new(None));
+    let slot = Arc::clone(&id);
+    let hook = vm.on_access(0x8000..=0x8005, WatchKind::Read, move |vm, _| {
+        *count.lock().unwrap() += 1;
+        assert!(vm.remove_hook(slot.lock().unwrap().unwrap()));
+    });
+    *id.lock().unwrap() = Some(hook);
+    vm.step().unwrap();
+    vm.step().unwrap();
+    // The first instruction made two matching fetches, but the hook was
+    // gone after the first.
+    assert_eq!(*calls.lock().unwrap(), 1);
+    assert!(!vm.remove_hook(hook));
+}
+
+#[test]
+fn frame_hooks_run_at_frame_end_but_not_on_replay() {
+    // `LDA #$A9` over the whole address space runs forever.
+    let mut vm = Vm::new();
+    vm.bus_mut().unmap(CONTROLLER);
+    vm.load(0, &vec![0xA9; RVM_MEM_SIZE]).unwrap();
+    let frames = Arc::new(Mutex::new(Vec::new()));
+    let log = Arc::clone(&frames);
+    vm.on_frame(move |vm| log.lock().unwrap().push(vm.frame()));
+    vm.enable_rewind(8, 4);
+    for _ in 0..6 {
+        vm.run_frame().unwrap();
+    }
+    assert_eq!(vm.rewind(1), Ok(1));
+    vm.run_frame().unwrap();
+    assert_eq!(*frames.lock().unwrap(), [1, 2, 3, 4, 5, 6, 6]);
+}
+
+#[test]
+fn read_hooks_rewrite_what_the_cpu_sees() {
+    // LDA $1000; LDX $1001
+    let mut vm = vm_with(&[0xAD, 0x00, 0x10, 0xAE, 0x01, 0x10]);
+    vm.write(0x1000, 0x10);
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let log = seen.clone();
+    vm.on_read(0x1000..=0x1000, move |addr, val| {
+        log.lock().unwrap().push((addr, val));
+        val + 1
+    });
+    vm.step().unwrap();
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 0x11);
+    assert_eq!(vm.registers().x, 0);
+    assert_eq!(*seen.lock().unwrap(), [(0x1000, 0x10)]);
+    // RAM itself is untouched.
+    assert_eq!(vm.read(0x1000), 0x10);
+}
+
+#[test]
+fn read_hooks_can_patch_code_fetches() {
+    // LDA #$01, fetched as LDA #$7F.
+    let mut vm = vm_with(&[0xA9, 0x01]);
+    vm.on_read(0x8001..=0x8001, |_, _| 0x7F);
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 0x7F);
+}
+
+#[test]
+fn write_hooks_rewrite_what_is_stored() {
+    // LSR $1000: reads 0x80 and stores 0x40, which the hook doubles.
+    let mut vm = vm_with(&[0x4E, 0x00, 0x10]);
+    vm.write(0x1000, 0x80);
+    let reads = Arc::new(Mutex::new(0));
+    let count = reads.clone();
+    vm.on_read(0x1000..=0x1000, move |_, val| {
+        *count.lock().unwrap() += 1;
+        val
+    });
+    vm.on_write(0x1000..=0x10FF, |_, val| val * 2);
+    vm.step().unwrap();
+    assert_eq!(vm.read(0x1000), 0x80);
+    assert_eq!(*reads.lock().unwrap(), 1);
+}
+
+#[test]
+fn write_hooks_run_before_devices() {
+    // LSR $2500 strobes the controller with 0 unless the hook turns it to 1,
+    // holding the strobe so every read reports the A button.
+    let mut vm = vm_with(&[0x4E, 0x00, 0x25, 0xAD, 0x00, 0x25]);
+    vm.set_buttons(1);
+    vm.on_write(CONTROLLER..=CONTROLLER, |_, _| 1);
+    vm.step().unwrap();
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 1);
+}
+
+#[test]
+fn value_hooks_chain_and_remove() {
+    let mut vm = vm_with(&[0xAD, 0x00, 0x10, 0xAD, 0x00, 0x10]);
+    let first = vm.on_read(0x1000..=0x1000, |_, val| val + 1);
+    vm.on_read(0x0F00..=0x10FF, |_, val| val * 3);
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 3);
+    assert!(vm.remove_hook(first));
+    assert!(!vm.remove_hook(first));
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 0);
+}
+
+#[test]
+fn value_hooks_leave_other_pages_alone() {
+    let mut vm = vm_with(&[0xAD, 0x00, 0x30]);
+    vm.write(0x3000, 5);
+    vm.on_read(0x1000..=0x1000, |_, _| unreachable!());
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 5);
+}
+
+#[test]
+fn access_hooks_see_rewritten_values() {
+    let mut vm = vm_with(&[0xAD, 0x00, 0x10]);
+    vm.on_read(0x1000..=0x1000, |_, _| 0x42);
+    let seen = Arc::new(Mutex::new(None));
+    let last = seen.clone();
+    vm.on_access(0x1000..=0x1000, WatchKind::Read, move |_, access| {
+        *last.lock().unwrap() = Some(access.value)
+    });
+    vm.step().unwrap();
+    assert_eq!(*seen.lock().unwrap(), Some(0x42));
+}
+
+#[test]
+fn code_write_hooks_catch_self_modifying_code() {
+    // LDA #$08; LSR $8001; LSR $9000; LSR $1000
+    let mut vm = vm_with(&[
+        0xA9, 0x08, 0x4E, 0x01, 0x80, 0x4E, 0x00, 0x90, 0x4E, 0x00, 0x10,
+    ]);
+    vm.write(0x9000, 0x10);
+    let executed = Arc::new(Mutex::new(Vec::new()));
+    let sink = executed.clone();
+    vm.on_code_write(CodeWatch::Executed, move |vm, write| {
+        assert_eq!(vm.registers().pc, write.pc + 3, "the write has finished");
+        sink.lock().unwrap().push(write);
+    });
+    let ranged = Arc::new(Mutex::new(Vec::new()));
+    let sink = ranged.clone();
+    let hook = vm.on_code_write(CodeWatch::Range(0x9000..=0x90FF), move |_, write| {
+        sink.lock().unwrap().push(write);
+    });
+    vm.run_cycles(2 + 6 + 6 + 6).unwrap();
+
+    let write = |pc, addr, value| CodeWrite { pc, addr, value };
+    assert_eq!(*executed.lock().unwrap(), [write(0x8002, 0x8001, 0x04)]);
+    assert_eq!(*ranged.lock().unwrap(), [write(0x8005, 0x9000, 0x08)]);
+
+    assert!(vm.remove_hook(hook));
+    vm.set_registers(Registers {
+        pc: 0x8005,
+        ..vm.registers()
+    });
+    vm.step().unwrap();
+    assert_eq!(ranged.lock().unwrap().len(), 1);
+}
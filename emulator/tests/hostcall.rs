@@ -0,0 +1,98 @@
+use std::sync::{Arc, Mutex};
+
+use emulator::asm::assemble;
+use emulator::ffi::FLAG_C;
+use emulator::hostcall::{HOSTCALL, HOSTCALL_CYCLES};
+use emulator::{ResetKind, Vm, VmError};
+
+/// A machine at the program assembled from `source` at $C000.
+fn machine(source: &str) -> Vm {
+    let program = assemble(&format!(".org $C000\n{source}\n.org $FFFC\n.word $C000\n")).unwrap();
+    let mut vm = Vm::new();
+    vm.load_assembly(&program).unwrap();
+    vm.reset(ResetKind::Soft);
+    vm
+}
+
+#[test]
+fn calls_closures_with_strings_and_buffers() {
+    let mut vm = machine(
+        "
+        LDX #$00
+        LDY #$C1
+        .byte $72, 1        ; puts
+        LDX #$00
+        LDY #$02
+        .byte $72, 2        ; fill the buffer at XY
+        LDA $0201
+        ",
+    );
+    vm.load(0xC100, b"hello\0").unwrap();
+    let printed = Arc::new(Mutex::new(Vec::new()));
+    let log = Arc::clone(&printed);
+    vm.register_hostcall(1, move |call| {
+        let text = call.read_string(call.pointer());
+        log.lock().unwrap().push(text);
+        call.set_carry(false);
+        Ok(())
+    });
+    vm.register_hostcall(2, |call| {
+        let to = call.pointer();
+        call.write_bytes(to, b"data");
+        call.set_a(4);
+        call.set_pointer(to.wrapping_add(4));
+        call.set_carry(true);
+        Ok(())
+    });
+
+    for _ in 0..3 {
+        vm.step().unwrap();
+    }
+    assert_eq!(*printed.lock().unwrap(), ["hello"]);
+    let before = vm.cycles();
+    for _ in 0..3 {
+        vm.step().unwrap();
+    }
+    assert_eq!(vm.cycles() - before, 2 + 2 + u32::from(HOSTCALL_CYCLES));
+    let regs = vm.registers();
+    assert_eq!((regs.a, regs.x, regs.y), (4, 0x04, 0x02));
+    assert_ne!(regs.flags & FLAG_C, 0);
+    assert_eq!(&vm.memory()[0x0200..0x0204], b"data");
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, b'a');
+}
+
+#[test]
+fn failures_stop_the_machine() {
+    let mut vm = machine(".byte $72, 7\n.byte $72, 8\n.byte $72, 9");
+    vm.register_hostcall(7, |_| Ok(()));
+    vm.register_hostcall(8, |call| Err(format!("call {} refused", call.id())));
+    vm.step().unwrap();
+    let err = vm.step().unwrap_err();
+    assert!(
+        matches!(&err, VmError::OpcodeFailed { pc: 0xC002, opcode: HOSTCALL, message } if message == "call 8 refused"),
+        "{err:?}"
+    );
+
+    let mut vm = machine(".byte $72, 7\n.byte $72, 7");
+    vm.register_hostcall(7, |_| Ok(()));
+    vm.step().unwrap();
+    assert!(vm.unregister_hostcall(7));
+    assert!(!vm.unregister_hostcall(7));
+    let err = vm.step().unwrap_err();
+    assert!(
+        err.to_string().contains("no host call 7 is registered"),
+        "{err}"
+    );
+
+    // Nothing registered leaves the opcode illegal.
+    let mut vm = machine(".byte $72, 7");
+    assert!(!vm.unregister_hostcall(7));
+    assert!(matches!(
+        vm.step(),
+        Err(VmError::IllegalOpcode {
+            opcode: HOSTCALL,
+            ..
+        })
+    ));
+}
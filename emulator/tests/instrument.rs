@@ -0,0 +1,240 @@
+#![cfg(feature = "instrument")]
+
+mod common;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use common::vm_with_handler;
+use emulator::dma::{CTRL_STEAL, DMA_PORTS, Dma};
+use emulator::{BusDevice, Registers, ResetKind, Vm, VmError};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Call {
+    /// A span opened, with its name, parent's name and fields.
+    New(&'static str, Option<&'static str>, String),
+    /// Fields recorded on an open span.
+    Record(&'static str, String),
+    /// An event, with its parent's name and fields.
+    Event(Option<&'static str>, String),
+}
+
+use Call::{New, Record as Rec};
+
+#[derive(Default)]
+struct Fields(Vec<String>);
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.push(format!("{}={value:?}", field.name()));
+    }
+}
+
+impl Fields {
+    fn of(record: impl FnOnce(&mut Self)) -> String {
+        let mut fields = Self::default();
+        record(&mut fields);
+        fields.0.join(" ")
+    }
+}
+
+/// Records the spans and events up to `level`, for the test to read after
+/// the subscriber is done.
+#[derive(Clone)]
+struct Recorder {
+    level: Level,
+    calls: Arc<Mutex<Vec<Call>>>,
+    names: Arc<Mutex<HashMap<u64, &'static str>>>,
+    entered: Arc<Mutex<Vec<u64>>>,
+    next: Arc<AtomicU64>,
+}
+
+impl Recorder {
+    fn new(level: Level) -> Self {
+        Self {
+            level,
+            calls: Arc::default(),
+            names: Arc::default(),
+            entered: Arc::default(),
+            next: Arc::new(AtomicU64::new(1)),
+        }
+    }
+
+    /// Runs `f` with the recorder as the default subscriber.
+    fn run(&self, vm: &mut Vm, f: impl FnOnce(&mut Vm)) {
+        tracing::subscriber::with_default(self.clone(), || f(vm));
+    }
+
+    fn calls(&self) -> Vec<Call> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    fn name(&self, id: &Id) -> &'static str {
+        self.names.lock().unwrap()[&id.into_u64()]
+    }
+
+    fn parent(&self, explicit: Option<&Id>, contextual: bool) -> Option<&'static str> {
+        match explicit {
+            Some(id) => Some(self.name(id)),
+            None if contextual => {
+                let current = self.entered.lock().unwrap().last().copied();
+                current.map(|id| self.name(&Id::from_u64(id)))
+            }
+            None => None,
+        }
+    }
+}
+
+impl tracing::Subscriber for Recorder {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        *metadata.level() <= self.level
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+        let name = attrs.metadata().name();
+        self.names.lock().unwrap().insert(id, name);
+        let parent = self.parent(attrs.parent(), attrs.is_contextual());
+        let fields = Fields::of(|fields| attrs.record(fields));
+        self.calls.lock().unwrap().push(New(name, parent, fields));
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let fields = Fields::of(|fields| values.record(fields));
+        self.calls
+            .lock()
+            .unwrap()
+            .push(Rec(self.name(span), fields));
+    }
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let parent = self.parent(event.parent(), event.is_contextual());
+        let fields = Fields::of(|fields| event.record(fields));
+        self.calls.lock().unwrap().push(Call::Event(parent, fields));
+    }
+
+    fn enter(&self, span: &Id) {
+        self.entered.lock().unwrap().push(span.into_u64());
+    }
+
+    fn exit(&self, _: &Id) {
+        self.entered.lock().unwrap().pop();
+    }
+}
+
+#[test]
+fn frames_and_dma_are_spans() {
+    // LSR $2706 turns CTRL_STEAL into CTRL_START, then LDA $8000.
+    let mut program = vec![0x4E, 0x06, 0x27];
+    program.extend([0xAD, 0x00, 0x80].repeat(10_000));
+    let mut vm = vm_with_handler(&program);
+    vm.bus_mut().map(DMA_PORTS, Dma::default()).unwrap();
+    let dma = vm.bus_mut().device_mut::<Dma>(*DMA_PORTS.start()).unwrap();
+    dma.write8(4, 4);
+    dma.write8(6, CTRL_STEAL);
+
+    let recorder = Recorder::new(Level::DEBUG);
+    recorder.run(&mut vm, |vm| {
+        vm.run_frame().unwrap();
+        vm.run_frame().unwrap();
+        vm.reset(ResetKind::Soft);
+    });
+
+    // Six cycles of LSR, then two per byte; 16666 cycles a frame, the
+    // second overshooting by two.
+    assert_eq!(
+        recorder.calls(),
+        [
+            New("frame", None, "frame=0 cycles=0".into()),
+            New("dma", Some("frame"), "cycles=6 stolen=8".into()),
+            Rec("frame", "end=16666".into()),
+            New("frame", None, "frame=1 cycles=16666".into()),
+            Rec("frame", "end=33334".into()),
+        ]
+    );
+}
+
+#[test]
+fn interrupt_entries_are_spans() {
+    let mut vm = vm_with_handler(&[0xA9, 0x01]);
+    vm.load(0x9000, &[0xA9, 0x02, 0xA9, 0x03]).unwrap();
+    vm.set_registers(Registers {
+        flags: 0,
+        ..vm.registers()
+    });
+    let recorder = Recorder::new(Level::DEBUG);
+    recorder.run(&mut vm, |vm| {
+        vm.raise_irq(2);
+        vm.step().unwrap();
+        vm.step().unwrap();
+        vm.ack_irq(2);
+        vm.step().unwrap();
+        vm.set_nmi(true);
+        vm.step().unwrap();
+        vm.step().unwrap();
+    });
+
+    let calls = recorder.calls();
+    assert_eq!(calls.len(), 4, "{calls:#?}");
+    // Taken after the instruction that polled it, in seven cycles.
+    assert_eq!(
+        calls[..2],
+        [
+            New("interrupt", None, "nmi=false line=2 cycles=2".into()),
+            Rec("interrupt", "end=9".into()),
+        ]
+    );
+    assert!(matches!(&calls[2], New("interrupt", None, f) if f.starts_with("nmi=true cycles=")));
+    assert!(matches!(&calls[3], Rec("interrupt", _)));
+}
+
+#[test]
+fn steps_and_faults_belong_to_the_frame() {
+    let mut vm = vm_with_handler(&[0xA9, 0x01, 0x02]);
+    let recorder = Recorder::new(Level::TRACE);
+    let mut errs = Vec::new();
+    recorder.run(&mut vm, |vm| {
+        errs.push(vm.run_frame().unwrap_err());
+        // The next run carries on with the frame the error interrupted.
+        errs.push(vm.run_frame().unwrap_err());
+        vm.reset(ResetKind::Soft);
+    });
+
+    let fault = |err: &VmError| format!("message={err} cycles=2");
+    assert_eq!(
+        recorder.calls(),
+        [
+            New("frame", None, "frame=0 cycles=0".into()),
+            New("step", Some("frame"), "pc=32768 cycles=0".into()),
+            New("step", Some("frame"), "pc=32770 cycles=2".into()),
+            Call::Event(Some("frame"), fault(&errs[0])),
+            New("step", Some("frame"), "pc=32771 cycles=2".into()),
+            Call::Event(Some("frame"), fault(&errs[1])),
+            Rec("frame", "end=2".into()),
+        ]
+    );
+}
+
+#[test]
+fn hook_events_fall_inside_the_step() {
+    let mut vm = vm_with_handler(&[0xA9, 0x01]);
+    vm.on_pc(0x8000, |_| tracing::info!("hook"));
+    let recorder = Recorder::new(Level::TRACE);
+    recorder.run(&mut vm, |vm| vm.step().unwrap());
+
+    assert_eq!(
+        recorder.calls(),
+        [
+            New("step", None, "pc=32768 cycles=0".into()),
+            Call::Event(Some("step"), "message=hook".into()),
+        ]
+    );
+}
@@ -0,0 +1,207 @@
+use emulator::ffi::{FLAG_I, IRQ_CYCLES};
+use emulator::{BusDevice, Registers, ResetKind, Vm};
+
+/// `LDA #$01; LDA #$03` at 0x8000, an IRQ handler at 0x9000 starting
+/// `LDA #$02` and an NMI handler at 0xA000 starting `LDA #$04`, with the I
+/// flag cleared by the host.
+fn vm() -> Vm {
+    let mut vm = Vm::new();
+    vm.load(0x8000, &[0xA9, 0x01, 0xA9, 0x03]).unwrap();
+    vm.load(0x9000, &[0xA9, 0x02]).unwrap();
+    vm.load(0xA000, &[0xA9, 0x04]).unwrap();
+    vm.load(0xFFFA, &[0x00, 0xA0, 0x00, 0x80, 0x00, 0x90])
+        .unwrap();
+    vm.reset(ResetKind::Soft);
+    vm.set_registers(Registers {
+        flags: 0,
+        ..vm.registers()
+    });
+    vm
+}
+
+/// Holds line 3 while `active` is set.
+struct Timer {
+    active: bool,
+}
+
+impl BusDevice for Timer {
+    fn read8(&mut self, _: u16) -> u8 {
+        0
+    }
+
+    fn write8(&mut self, _: u16, _: u8) {}
+
+    fn irq_lines(&self) -> u8 {
+        u8::from(self.active) << 3
+    }
+}
+
+#[test]
+fn raised_irq_enters_the_handler() {
+    let mut vm = vm();
+    vm.raise_irq(2);
+    // The next instruction polls the line; the handler is entered after it.
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 1);
+    vm.step().unwrap();
+    let regs = vm.registers();
+    assert_eq!(regs.pc, 0x9000);
+    assert_eq!(regs.sp, 0xFA);
+    assert_ne!(regs.flags & FLAG_I, 0);
+    assert_eq!(vm.cycles(), 2 + IRQ_CYCLES);
+    assert_eq!(vm.read(0x01FD), 0x80);
+    assert_eq!(vm.read(0x01FC), 0x02);
+    assert_eq!(vm.read(0x01FB), 0x00);
+
+    // The handler itself runs with interrupts masked.
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 2);
+}
+
+#[test]
+fn masked_lines_and_the_i_flag_hold_interrupts_off() {
+    let mut vm = vm();
+    vm.set_irq_mask(!(1 << 2));
+    vm.raise_irq(2);
+    assert_eq!(vm.pending_irqs(), 1 << 2);
+    assert_eq!(vm.active_irq(), None);
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 1);
+
+    let mut vm = self::vm();
+    vm.set_registers(Registers {
+        flags: FLAG_I,
+        ..vm.registers()
+    });
+    vm.raise_irq(0);
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 1);
+}
+
+#[test]
+fn polled_irqs_are_taken_even_once_acknowledged() {
+    let mut vm = vm();
+    vm.raise_irq(0);
+    vm.step().unwrap();
+    vm.ack_irq(0);
+    vm.step().unwrap();
+    assert_eq!(vm.registers().pc, 0x9000);
+    assert_eq!(vm.active_irq(), None);
+
+    // Acknowledged before any instruction polled it, it is never taken.
+    let mut vm = self::vm();
+    vm.raise_irq(0);
+    vm.ack_irq(0);
+    vm.step().unwrap();
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 3);
+}
+
+#[test]
+fn nmis_trigger_on_the_edge_whatever_the_i_flag() {
+    let mut vm = vm();
+    vm.set_registers(Registers {
+        flags: FLAG_I,
+        ..vm.registers()
+    });
+    vm.set_nmi(true);
+    assert!(vm.nmi());
+    vm.step().unwrap();
+    vm.step().unwrap();
+    assert_eq!(vm.registers().pc, 0xA000);
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 4);
+
+    // Held asserted, the line does not interrupt again, but releasing and
+    // asserting it does.
+    vm.set_registers(Registers {
+        pc: 0x8000,
+        ..vm.registers()
+    });
+    vm.step().unwrap();
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 3);
+    vm.set_nmi(false);
+    vm.set_registers(Registers {
+        pc: 0x8000,
+        ..vm.registers()
+    });
+    vm.step().unwrap();
+    vm.set_nmi(true);
+    vm.step().unwrap();
+    vm.step().unwrap();
+    assert_eq!(vm.registers().pc, 0xA000);
+}
+
+#[test]
+fn nmis_win_over_irqs_polled_at_once() {
+    let mut vm = vm();
+    vm.raise_irq(1);
+    vm.set_nmi(true);
+    vm.step().unwrap();
+    vm.step().unwrap();
+    assert_eq!(vm.registers().pc, 0xA000);
+    // The NMI entry set the I flag, so the IRQ waits.
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 4);
+}
+
+#[test]
+fn lower_lines_take_priority_until_acknowledged() {
+    let mut vm = vm();
+    vm.raise_irq(5);
+    vm.raise_irq(1);
+    assert_eq!(vm.active_irq(), Some(1));
+    vm.ack_irq(1);
+    assert_eq!(vm.active_irq(), Some(5));
+    vm.ack_irq(5);
+    assert_eq!(vm.active_irq(), None);
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 1);
+}
+
+#[test]
+fn devices_hold_lines_while_they_report_them() {
+    let mut vm = vm();
+    vm.bus_mut()
+        .map(0x4000..=0x4000, Timer { active: true })
+        .unwrap();
+    assert_eq!(vm.active_irq(), Some(3));
+    // Acknowledging does not clear a line the device still holds.
+    vm.ack_irq(3);
+    assert_eq!(vm.active_irq(), Some(3));
+    vm.bus_mut().device_mut::<Timer>(0x4000).unwrap().active = false;
+    assert_eq!(vm.active_irq(), None);
+}
+
+#[test]
+fn snapshots_capture_controller_state() {
+    let mut vm = vm();
+    vm.raise_irq(4);
+    vm.set_irq_mask(0x0F);
+    vm.set_nmi(true);
+    let saved = vm.save_state();
+    vm.reset(ResetKind::Soft);
+    assert_eq!(vm.pending_irqs(), 0);
+    assert_eq!(vm.irq_mask(), 0xFF);
+    assert!(!vm.nmi());
+    vm.load_state(&saved).unwrap();
+    assert_eq!(vm.pending_irqs(), 1 << 4);
+    assert_eq!(vm.irq_mask(), 0x0F);
+    assert!(vm.nmi());
+}
+
+#[test]
+fn snapshots_capture_polled_interrupts() {
+    let mut vm = vm();
+    vm.raise_irq(0);
+    vm.step().unwrap();
+    vm.ack_irq(0);
+    let saved = vm.save_state();
+    vm.step().unwrap();
+    assert_eq!(vm.registers().pc, 0x9000);
+
+    vm.load_state(&saved).unwrap();
+    vm.step().unwrap();
+    assert_eq!(vm.registers().pc, 0x9000, "the latched IRQ survived");
+}
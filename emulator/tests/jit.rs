@@ -0,0 +1,216 @@
+#![cfg(feature = "jit")]
+
+mod common;
+
+use common::vm_with;
+use emulator::disasm;
+use emulator::dma::{CTRL_STEAL, DMA_PORTS, Dma};
+use emulator::jit::JitStats;
+use emulator::{BusDevice, CpuConfig, IllegalOpcodes, Registers, ResetKind, Vm, VmError};
+
+/// xorshift32, so failures reproduce from the seed alone.
+fn rng(mut state: u32) -> impl FnMut() -> u32 {
+    move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    }
+}
+
+/// Two copies of `vm`, the second with the recompiler enabled.
+fn pair(vm: impl Fn() -> Vm) -> (Vm, Vm) {
+    let mut jit = vm();
+    jit.enable_jit().unwrap();
+    (vm(), jit)
+}
+
+/// Runs both machines with `run` and checks they end up in the same state.
+fn run_both(
+    interpreter: &mut Vm,
+    jit: &mut Vm,
+    run: impl Fn(&mut Vm) -> Result<(), VmError>,
+    context: &str,
+) -> Result<(), VmError> {
+    let expected = run(interpreter);
+    let actual = run(jit);
+    assert_eq!(actual, expected, "{context}");
+    assert_eq!(jit.registers(), interpreter.registers(), "{context}");
+    assert!(
+        jit.export_kernel_state() == interpreter.export_kernel_state(),
+        "{context}: kernel states differ"
+    );
+    actual
+}
+
+/// Random code, mostly documented instructions with random operands, and
+/// a random byte, likely an opcode left to the kernel, one time in eight.
+/// Absolute operands point at the program one time in four, so its own
+/// `LSR`s rewrite it.
+fn program(next: &mut impl FnMut() -> u32, len: usize) -> Vec<u8> {
+    let documented: Vec<_> = disasm::opcodes()
+        .map(|(opcode, op)| (opcode, disasm::instruction_size(op.mode) - 1))
+        .collect();
+    let mut program = Vec::with_capacity(len + 2);
+    while program.len() < len {
+        let choice = next();
+        if choice.is_multiple_of(8) {
+            program.push(choice as u8);
+            continue;
+        }
+        let (opcode, operands) = documented[(choice >> 8) as usize % documented.len()];
+        program.push(opcode);
+        let operand = next();
+        match operands {
+            1 => program.push(operand as u8),
+            2 if operand.is_multiple_of(4) => {
+                let addr = 0x8000 + (operand >> 8) as u16 % len as u16;
+                program.extend(addr.to_le_bytes());
+            }
+            2 => program.extend((operand as u16).to_le_bytes()),
+            _ => {}
+        }
+    }
+    program
+}
+
+#[test]
+fn random_code_matches_the_interpreter() {
+    let mut stats = JitStats::default();
+    for (seed, illegal_opcodes) in (1..=12).zip(
+        [
+            IllegalOpcodes::Nop,
+            IllegalOpcodes::Undocumented,
+            IllegalOpcodes::Trap,
+        ]
+        .into_iter()
+        .cycle(),
+    ) {
+        let mut next = rng(seed);
+        let program = program(&mut next, 0x800);
+        let (mut interpreter, mut jit) = pair(|| {
+            let mut vm = vm_with(&program);
+            vm.set_cpu_config(CpuConfig {
+                illegal_opcodes,
+                ..CpuConfig::default()
+            });
+            // Interrupts land in a mirror of the program, and its second
+            // page is slow.
+            vm.mirror(0xC000..=0xC7FF, 0x8000).unwrap();
+            vm.load(0xFFFA, &[0x00, 0xC2]).unwrap();
+            vm.load(0xFFFE, &[0x00, 0xC1]).unwrap();
+            vm.set_wait_states(0x8100..=0x81FF, 2);
+            vm
+        });
+        for chunk in 0..30 {
+            let budget = next() % 500 + 1;
+            for vm in [&mut interpreter, &mut jit] {
+                match chunk {
+                    // Runs the code compiled so far again.
+                    5 | 10 => vm.reset(ResetKind::Soft),
+                    15 => vm.raise_irq(1),
+                    20 => vm.set_nmi(true),
+                    22 => vm.set_nmi(false),
+                    25 => vm.ack_irq(1),
+                    _ => {}
+                }
+            }
+            let context = format!("seed {seed}, chunk {chunk}");
+            if run_both(
+                &mut interpreter,
+                &mut jit,
+                |vm| vm.run_cycles(budget),
+                &context,
+            )
+            .is_err()
+            {
+                break;
+            }
+        }
+        let jit = jit.jit_stats().unwrap();
+        stats.compiled += jit.compiled;
+        stats.invalidated += jit.invalidated;
+    }
+    assert!(stats.compiled > 0 && stats.invalidated > 0, "{stats:?}");
+}
+
+#[test]
+fn writes_to_compiled_code_invalidate_it() {
+    // LDA #$84, LSR $8006, LDX #$84 with the operand just shifted to $42,
+    // LDY $8006, then an illegal opcode.
+    let program = [
+        0xA9, 0x84, 0x4E, 0x06, 0x80, 0xA2, 0x84, 0xAC, 0x06, 0x80, 0x02,
+    ];
+    let (mut interpreter, mut jit) = pair(|| vm_with(&program));
+    let run = |vm: &mut Vm| vm.run_cycles(1000);
+    let err = run_both(&mut interpreter, &mut jit, run, "first run").unwrap_err();
+    assert_eq!(
+        err,
+        VmError::IllegalOpcode {
+            pc: 0x800A,
+            opcode: 0x02
+        }
+    );
+    let regs = jit.registers();
+    assert_eq!((regs.a, regs.x, regs.y), (0x84, 0x42, 0x42));
+    let stats = jit.jit_stats().unwrap();
+    assert_eq!(stats.invalidated, 1, "{stats:?}");
+
+    // A host write is caught when the block is entered again.
+    for vm in [&mut interpreter, &mut jit] {
+        vm.write(0x8001, 0x33);
+        vm.set_registers(Registers {
+            pc: 0x8000,
+            ..vm.registers()
+        });
+    }
+    run_both(&mut interpreter, &mut jit, run, "second run").unwrap_err();
+    let regs = jit.registers();
+    assert_eq!((regs.a, regs.x, regs.y), (0x33, 0x21, 0x21));
+    assert!(jit.jit_stats().unwrap().invalidated > stats.invalidated);
+}
+
+#[test]
+fn devices_end_blocks_like_the_kernel() {
+    // LSR $2706 turns CTRL_STEAL into CTRL_START, then seven LDA $8000 for
+    // every LDX $2500 from the controller.
+    let mut program = vec![0x4E, 0x06, 0x27];
+    let mut unit = [0xAD, 0x00, 0x80].repeat(7);
+    unit.extend([0xAE, 0x00, 0x25]);
+    program.extend(unit.repeat(400));
+    let (mut interpreter, mut jit) = pair(|| {
+        let mut vm = vm_with(&program);
+        vm.bus_mut().map(DMA_PORTS, Dma::default()).unwrap();
+        let dma = vm.bus_mut().device_mut::<Dma>(*DMA_PORTS.start()).unwrap();
+        dma.write8(4, 4);
+        dma.write8(6, CTRL_STEAL);
+        vm.set_buttons(0x05);
+        vm
+    });
+    for chunk in 0..3 {
+        let context = format!("chunk {chunk}");
+        run_both(
+            &mut interpreter,
+            &mut jit,
+            |vm| vm.run_cycles(2000),
+            &context,
+        )
+        .unwrap();
+    }
+    // Every controller read ends a batch.
+    let stats = jit.jit_stats().unwrap();
+    assert!(stats.entered >= 6000 / 31, "{stats:?}");
+}
+
+#[test]
+fn disabling_drops_the_blocks() {
+    let mut vm = vm_with(&[0xA9, 0x01].repeat(100));
+    assert_eq!(vm.jit_stats(), None);
+    vm.enable_jit().unwrap();
+    vm.run_cycles(50).unwrap();
+    assert_eq!(vm.jit_stats().unwrap().compiled, 1);
+    vm.disable_jit();
+    assert_eq!(vm.jit_stats(), None);
+    vm.run_cycles(50).unwrap();
+    assert_eq!(vm.registers().a, 0x01);
+}
@@ -0,0 +1,32 @@
+use std::fs;
+use std::path::Path;
+
+/// Checks that every file in the crate's copy `vendored` of the kernel is
+/// the one in `../kernel`, when the crate is built inside the repository.
+fn assert_vendored(vendored: &str) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let kernel = root.join("../kernel");
+    if !kernel.is_dir() {
+        return;
+    }
+    let copies = fs::read_dir(root.join(vendored)).unwrap();
+    let mut count = 0;
+    for copy in copies {
+        let copy = copy.unwrap().path();
+        let name = copy.file_name().unwrap();
+        let source = fs::read(kernel.join(name)).unwrap();
+        assert!(
+            fs::read(&copy).unwrap() == source,
+            "{} is out of date; run `make vendor` in kernel/",
+            copy.display()
+        );
+        count += 1;
+    }
+    assert!(count > 0, "{vendored} is empty");
+}
+
+#[test]
+fn vendored_kernel_sources_match_the_kernel() {
+    assert_vendored("kernel");
+    assert_vendored("core/kernel");
+}
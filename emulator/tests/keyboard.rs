@@ -0,0 +1,159 @@
+mod common;
+
+use common::vm_with;
+use emulator::keyboard::{
+    CTRL_IRQ, CTRL_RAW, KEYBOARD, KEYBOARD_DEPTH, KEYBOARD_IRQ, KEYBOARD_PORTS, Key, Keyboard,
+    STATUS_CONTROL, STATUS_OVERFLOW, STATUS_READY, STATUS_SHIFT,
+};
+use emulator::replay::{InputEvent, Recording};
+use emulator::{BusDevice, MachineConfig, ResetKind, Vm};
+
+/// Runs `program` from 0x8000 with the keyboard mapped.
+fn vm(program: &[u8]) -> Vm {
+    let mut vm = vm_with(program);
+    vm.bus_mut().map(KEYBOARD_PORTS, Keyboard::new()).unwrap();
+    vm
+}
+
+fn keyboard(vm: &mut Vm) -> &mut Keyboard {
+    vm.bus_mut().device_mut::<Keyboard>(KEYBOARD).unwrap()
+}
+
+/// Everything queued on the keyboard.
+fn drain(vm: &mut Vm) -> Vec<u8> {
+    let keyboard = keyboard(vm);
+    (0..keyboard.pending()).map(|_| keyboard.read8(0)).collect()
+}
+
+#[test]
+fn programs_read_typed_characters() {
+    // LDA $2761; LDX $2760; LDY $2760; LDA $2761
+    let mut vm = vm(&[
+        0xAD, 0x61, 0x27, 0xAE, 0x60, 0x27, 0xAC, 0x60, 0x27, 0xAD, 0x61, 0x27,
+    ]);
+    vm.type_text("Hi");
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, STATUS_READY);
+    vm.step().unwrap();
+    vm.step().unwrap();
+    assert_eq!((vm.registers().x, vm.registers().y), (b'H', b'i'));
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 0);
+
+    vm.type_text("10 PRINT \"A&B\"\n~é");
+    assert_eq!(drain(&mut vm), b"10 PRINT \"A&B\"\r~");
+    assert_eq!(keyboard(&mut vm).read8(0), 0);
+
+    vm.key_down(Key::Control);
+    vm.key_down(Key::C);
+    vm.key_down(Key::Shift);
+    assert_eq!(
+        keyboard(&mut vm).read8(1),
+        STATUS_READY | STATUS_SHIFT | STATUS_CONTROL
+    );
+    vm.key_down(Key::Digit2);
+    vm.key_down(Key::Up);
+    vm.key_up(Key::Control);
+    vm.key_down(Key::Z);
+    assert_eq!(drain(&mut vm), [0x03, b'@', b'Z']);
+    assert!(keyboard(&mut vm).is_held(Key::Shift));
+    assert!(!keyboard(&mut vm).is_held(Key::Control));
+}
+
+#[test]
+fn raw_mode_queues_scan_codes() {
+    let mut vm = vm(&[]);
+    vm.type_text("ab");
+    keyboard(&mut vm).write8(2, CTRL_RAW);
+    assert_eq!(keyboard(&mut vm).pending(), 0);
+    vm.key_down(Key::Shift);
+    vm.key_down(Key::A);
+    vm.key_up(Key::A);
+    vm.key_down(Key::Left);
+    assert_eq!(drain(&mut vm), [0x2A, 0x1E, 0x9E, 0x4B]);
+    assert_eq!(Key::from_scancode(0x4B), Some(Key::Left));
+    assert_eq!(Key::from_scancode(0x7F), None);
+    for &key in Key::ALL {
+        assert_eq!(Key::from_scancode(key.scancode()), Some(key));
+    }
+}
+
+#[test]
+fn overflows_and_interrupts() {
+    let mut vm = vm(&[]);
+    assert_eq!(keyboard(&mut vm).irq_lines(), 0);
+    vm.type_text(&"x".repeat(KEYBOARD_DEPTH + 1));
+    assert_eq!(keyboard(&mut vm).pending(), KEYBOARD_DEPTH);
+    assert_eq!(keyboard(&mut vm).read8(1), STATUS_READY | STATUS_OVERFLOW);
+    assert_eq!(keyboard(&mut vm).read8(1), STATUS_READY);
+    assert_eq!(keyboard(&mut vm).irq_lines(), 0);
+
+    keyboard(&mut vm).write8(2, CTRL_IRQ);
+    assert_eq!(keyboard(&mut vm).read8(2), CTRL_IRQ);
+    assert_eq!(keyboard(&mut vm).pending(), KEYBOARD_DEPTH);
+    assert_eq!(keyboard(&mut vm).irq_lines(), 1 << KEYBOARD_IRQ);
+    drain(&mut vm);
+    assert_eq!(keyboard(&mut vm).irq_lines(), 0);
+
+    // Keys go nowhere without a keyboard.
+    let mut vm = Vm::new();
+    vm.key_down(Key::A);
+}
+
+#[test]
+fn keys_are_configured_and_replayed() {
+    let config = MachineConfig::from_toml(
+        "[[ram]]\nstart = 0\nend = 0xFFFF\n[[device]]\nkind = \"keyboard\"",
+    )
+    .unwrap();
+    // LDA #$A9 over and over.
+    let program = [0xA9; 0x100];
+    let mut vm = Vm::with_config(&config).unwrap();
+    vm.load(0x8000, &program).unwrap();
+    vm.load(0xFFFC, &[0x00, 0x80]).unwrap();
+    vm.reset(ResetKind::Soft);
+
+    let file = std::env::temp_dir().join(format!("rvm8-keyboard-{}.inp", std::process::id()));
+    vm.record_inputs(&file).unwrap();
+    vm.step().unwrap();
+    vm.key_down(Key::Shift);
+    vm.step().unwrap();
+    vm.key_down(Key::Q);
+    vm.key_up(Key::Shift);
+    vm.step().unwrap();
+    vm.stop_recording().unwrap();
+    let recording = Recording::from_file(&file).unwrap();
+    assert_eq!(
+        recording.events,
+        [
+            (1, InputEvent::KeyDown(0x2A)),
+            (2, InputEvent::KeyDown(0x10)),
+            (2, InputEvent::KeyUp(0x2A)),
+        ]
+    );
+
+    let mut replayed = Vm::with_config(&config).unwrap();
+    replayed.replay_inputs(&file).unwrap();
+    for _ in 0..3 {
+        replayed.step().unwrap();
+    }
+    assert_eq!(drain(&mut replayed), b"Q");
+    std::fs::remove_file(&file).unwrap();
+}
+
+#[test]
+fn snapshots_keep_the_queue_and_held_keys() {
+    let mut vm = vm(&[0xEA]);
+    keyboard(&mut vm).write8(2, CTRL_IRQ);
+    vm.type_text("hi");
+    vm.key_down(Key::Shift);
+    let saved = vm.save_state();
+
+    vm.key_up(Key::Shift);
+    drain(&mut vm);
+    keyboard(&mut vm).write8(2, 0);
+    vm.load_state(&saved).unwrap();
+    assert!(keyboard(&mut vm).is_held(Key::Shift));
+    assert_eq!(vm.pending_irqs(), 1 << KEYBOARD_IRQ);
+    assert_eq!(drain(&mut vm), b"hi");
+}
@@ -0,0 +1,218 @@
+#![cfg(feature = "libretro")]
+
+// The core and these captures are thread locals, and every test runs on
+// its own thread, so tests load their own games independently.
+
+use std::cell::{Cell, RefCell};
+use std::ffi::{CStr, c_uint, c_void};
+
+use emulator::Rom;
+use emulator::input::Button;
+use emulator::libretro::*;
+use emulator::replay::Recording;
+
+/// Pixels, width, height and pitch as delivered.
+type Frame = (Vec<u32>, c_uint, c_uint, usize);
+
+thread_local! {
+    static PIXEL_FORMAT: Cell<Option<c_uint>> = const { Cell::new(None) };
+    static FRAME: RefCell<Option<Frame>> = const { RefCell::new(None) };
+    static AUDIO: RefCell<Vec<i16>> = const { RefCell::new(Vec::new()) };
+    static HELD: Cell<u16> = const { Cell::new(0) };
+}
+
+unsafe extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    if cmd != 10 {
+        return false;
+    }
+    PIXEL_FORMAT.set(Some(unsafe { *data.cast::<c_uint>() }));
+    true
+}
+
+unsafe extern "C" fn video_refresh(
+    data: *const c_void,
+    width: c_uint,
+    height: c_uint,
+    pitch: usize,
+) {
+    let pixels =
+        unsafe { std::slice::from_raw_parts(data.cast::<u32>(), pitch / 4 * height as usize) };
+    FRAME.set(Some((pixels.to_vec(), width, height, pitch)));
+}
+
+unsafe extern "C" fn audio_sample_batch(data: *const i16, frames: usize) -> usize {
+    // Take at most 100 frames per call so the core has to loop.
+    let frames = frames.min(100);
+    let samples = unsafe { std::slice::from_raw_parts(data, frames * 2) };
+    AUDIO.with_borrow_mut(|audio| audio.extend_from_slice(samples));
+    frames
+}
+
+unsafe extern "C" fn input_poll() {}
+
+unsafe extern "C" fn input_state(port: c_uint, device: c_uint, _index: c_uint, id: c_uint) -> i16 {
+    (port == 0 && device == 1 && HELD.get() & 1 << id != 0) as i16
+}
+
+/// `LDA #$A9` over both banks, which runs for about two frames.
+fn load() {
+    retro_set_environment(environment);
+    retro_set_video_refresh(video_refresh);
+    retro_set_audio_sample_batch(audio_sample_batch);
+    retro_set_input_poll(input_poll);
+    retro_set_input_state(input_state);
+    retro_init();
+    let bytes = Rom::new(0x8000, &[0xA9; 0x8000]).unwrap().to_bytes();
+    let game = GameInfo {
+        path: std::ptr::null(),
+        data: bytes.as_ptr().cast(),
+        size: bytes.len(),
+        meta: std::ptr::null(),
+    };
+    assert!(unsafe { retro_load_game(&game) });
+}
+
+fn memory() -> &'static mut [u8] {
+    let size = retro_get_memory_size(2);
+    unsafe { std::slice::from_raw_parts_mut(retro_get_memory_data(2).cast(), size) }
+}
+
+fn save_state() -> Vec<u8> {
+    let mut state = vec![0; retro_serialize_size()];
+    assert!(unsafe { retro_serialize(state.as_mut_ptr().cast(), state.len()) });
+    state
+}
+
+#[test]
+fn describes_the_core() {
+    assert_eq!(retro_api_version(), API_VERSION);
+    let mut info = std::mem::MaybeUninit::uninit();
+    let info = unsafe {
+        retro_get_system_info(info.as_mut_ptr());
+        info.assume_init()
+    };
+    assert_eq!(unsafe { CStr::from_ptr(info.library_name) }, c"rvm-8");
+    let extensions = if cfg!(feature = "compression") {
+        c"rvm|gz|zip"
+    } else {
+        c"rvm"
+    };
+    assert_eq!(unsafe { CStr::from_ptr(info.valid_extensions) }, extensions);
+    assert!(!info.need_fullpath);
+
+    let mut av = std::mem::MaybeUninit::uninit();
+    let av = unsafe {
+        retro_get_system_av_info(av.as_mut_ptr());
+        av.assume_init()
+    };
+    assert_eq!(
+        (av.geometry.base_width, av.geometry.base_height),
+        (160, 144)
+    );
+    assert_eq!(av.timing.fps, 60.0);
+    assert_eq!(av.timing.sample_rate, 44_100.0);
+}
+
+#[test]
+fn loads_a_game_and_sets_the_pixel_format() {
+    assert_eq!(retro_get_memory_size(2), 0);
+    load();
+    assert_eq!(PIXEL_FORMAT.get(), Some(1));
+    assert_eq!(retro_get_memory_size(2), 0x10000);
+    assert_eq!(retro_get_memory_size(0), 0);
+    assert!(retro_get_memory_data(0).is_null());
+    assert_eq!(memory()[0x8000], 0xA9);
+
+    retro_unload_game();
+    assert_eq!(retro_get_memory_size(2), 0);
+    retro_deinit();
+}
+
+#[test]
+fn rejects_bad_games() {
+    assert!(!unsafe { retro_load_game(std::ptr::null()) });
+    let game = GameInfo {
+        path: std::ptr::null(),
+        data: b"not a rom".as_ptr().cast(),
+        size: 9,
+        meta: std::ptr::null(),
+    };
+    assert!(!unsafe { retro_load_game(&game) });
+    assert!(!retro_load_game_special(0, &game, 1));
+}
+
+#[test]
+fn delivers_video_and_stereo_audio_each_frame() {
+    load();
+    retro_run();
+    let (pixels, width, height, pitch) = FRAME.take().unwrap();
+    assert_eq!((width, height, pitch), (160, 144, 640));
+    assert_eq!(pixels.len(), 160 * 144);
+    assert!(pixels.iter().all(|pixel| pixel >> 24 == 0));
+
+    let audio = AUDIO.take();
+    assert_eq!(audio.len(), 2 * 44_100 / 60);
+    assert!(audio.chunks_exact(2).all(|pair| pair[0] == pair[1]));
+}
+
+#[test]
+fn reads_the_retropad_on_port_zero() {
+    load();
+    // RetroPad A is id 8 and Start is id 3.
+    HELD.set(1 << 8 | 1 << 3);
+    retro_run();
+    let recording = Recording::from_bytes(&save_state()).unwrap();
+    assert_eq!(
+        recording.controller.buttons(),
+        Button::A.mask() | Button::Start.mask()
+    );
+}
+
+#[test]
+fn save_states_round_trip() {
+    load();
+    let size = retro_serialize_size();
+    let state = save_state();
+    retro_run();
+    assert_eq!(retro_serialize_size(), size);
+    let after = memory().to_vec();
+    let frame = FRAME.take().unwrap();
+
+    assert!(unsafe { retro_unserialize(state.as_ptr().cast(), state.len()) });
+    retro_run();
+    assert_eq!(memory(), &after[..]);
+    assert_eq!(FRAME.take().unwrap(), frame);
+
+    assert!(!unsafe { retro_serialize(state.as_ptr().cast_mut().cast(), size - 1) });
+    assert!(!unsafe { retro_unserialize(state.as_ptr().cast(), 3) });
+}
+
+#[test]
+fn cheats_freeze_memory() {
+    load();
+    unsafe { retro_cheat_set(0, true, c"$10 = $42+$11 = 7".as_ptr()) };
+    unsafe { retro_cheat_set(1, true, c"not a cheat".as_ptr()) };
+    retro_run();
+    assert_eq!(memory()[0x10..0x12], [0x42, 7]);
+
+    unsafe { retro_cheat_set(0, false, c"".as_ptr()) };
+    memory()[0x10] = 0;
+    retro_run();
+    assert_eq!(memory()[0x10], 0);
+
+    unsafe { retro_cheat_set(2, true, c"$20 = 1".as_ptr()) };
+    retro_cheat_reset();
+    memory()[0x20] = 0;
+    retro_run();
+    assert_eq!(memory()[0x20], 0);
+}
+
+#[test]
+fn resets_the_machine() {
+    load();
+    retro_run();
+    retro_reset();
+    let recording = Recording::from_bytes(&save_state()).unwrap();
+    assert_eq!(recording.start.registers.pc, 0x8000);
+    assert_eq!(recording.start.frame, 0);
+}
@@ -0,0 +1,85 @@
+use emulator::manifest::{Manifest, ManifestError, ManifestErrorKind};
+use emulator::{Rom, RomError};
+
+fn rom() -> Rom {
+    Rom::new(0xC000, &[0xA9, 0x42]).unwrap()
+}
+
+fn hex(digest: [u8; 32]) -> String {
+    digest.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[test]
+fn parses_sha256sum_output() {
+    let digest = rom().info().sha256;
+    let text = format!(
+        "# known builds\n\n{}  demo.rvm\n{} *roms/other.rvm\n",
+        hex(digest),
+        hex([0xAB; 32])
+    );
+    let manifest = Manifest::parse(&text).unwrap();
+    assert_eq!(manifest.len(), 2);
+    assert_eq!(manifest.get("demo.rvm"), Some(digest));
+    assert_eq!(manifest.get("/home/me/other.rvm"), Some([0xAB; 32]));
+    assert_eq!(manifest.get("missing.rvm"), None);
+
+    let written = manifest.to_string();
+    assert_eq!(
+        written,
+        format!(
+            "{}  demo.rvm\n{}  other.rvm\n",
+            hex(digest),
+            hex([0xAB; 32])
+        )
+    );
+    assert_eq!(Manifest::parse(&written).unwrap(), manifest);
+}
+
+#[test]
+fn verifies_roms_by_name() {
+    let rom = rom();
+    let mut manifest = Manifest::new();
+    assert!(manifest.insert("builds/demo.rvm", rom.info().sha256));
+    assert!(!manifest.insert("demo.rvm", [0; 32]));
+    assert_eq!(manifest.verify("games/demo.rvm", &rom).unwrap(), rom.info());
+
+    let err = manifest.verify("games/other.rvm", &rom).unwrap_err();
+    assert!(matches!(&err, RomError::NotInManifest(name) if name == "other.rvm"));
+    assert_eq!(err.to_string(), "`other.rvm` is not in the manifest");
+
+    let patched = Rom::new(0xC000, &[0xA9, 0x43]).unwrap();
+    let err = manifest.verify("demo.rvm", &patched).unwrap_err();
+    assert!(matches!(
+        err,
+        RomError::DigestMismatch { expected, actual }
+            if expected == rom.info().sha256 && actual == patched.info().sha256
+    ));
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "SHA-256 {} does not match manifest {}",
+            hex(patched.info().sha256),
+            hex(rom.info().sha256)
+        )
+    );
+}
+
+#[test]
+fn rejects_malformed_lines() {
+    let error = |line, kind| Err(ManifestError { line, kind });
+    assert_eq!(
+        Manifest::parse("# ok\nabcdef"),
+        error(2, ManifestErrorKind::Syntax)
+    );
+    assert_eq!(
+        Manifest::parse("abcdef  demo.rvm"),
+        error(1, ManifestErrorKind::BadDigest("abcdef".into()))
+    );
+    let line = format!("{}  demo.rvm\n", hex([1; 32]));
+    assert_eq!(
+        Manifest::parse(&line.repeat(2)),
+        error(2, ManifestErrorKind::DuplicateName("demo.rvm".into()))
+    );
+    let err = Manifest::parse("abcdef").unwrap_err();
+    assert_eq!(err.to_string(), "line 1: expected `digest  name`");
+}
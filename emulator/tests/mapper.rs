@@ -0,0 +1,307 @@
+use emulator::dma::Dma;
+use emulator::mapper::{BankRegisters, MAPPER_PORTS, RAM_WINDOW};
+use emulator::patches::Patch;
+use emulator::rom::{BANK_SIZE, HEADER_SIZE};
+use emulator::{BusDevice, Mapper, ReloadPolicy, Rom, RomError, Vm};
+
+/// `banks` banks, each starting with its own number, and code in the
+/// fixed last bank at 0xC000.
+fn banked_rom(banks: u8, mapper: Mapper, code: &[u8]) -> Rom {
+    let mut data = vec![0; banks as usize * BANK_SIZE];
+    for bank in 0..banks {
+        data[bank as usize * BANK_SIZE] = bank;
+    }
+    let fixed = (banks as usize - 1) * BANK_SIZE;
+    data[fixed + 1..fixed + 1 + code.len()].copy_from_slice(code);
+    Rom::with_mapper(0xC001, &data, mapper).unwrap()
+}
+
+fn select(vm: &mut Vm, register: u16, bank: u8) {
+    let registers = vm
+        .bus_mut()
+        .device_mut::<BankRegisters>(*MAPPER_PORTS.start());
+    registers.unwrap().write8(register, bank);
+}
+
+#[test]
+fn the_header_names_the_mapper() {
+    for mapper in [
+        Mapper::Flat,
+        Mapper::Banked,
+        Mapper::BankedRam { ram_banks: 4 },
+    ] {
+        let rom = banked_rom(2, mapper, &[]);
+        let bytes = rom.to_bytes();
+        assert_eq!(bytes[4], 2);
+        assert_eq!(Rom::from_bytes(&bytes).unwrap(), rom);
+    }
+    let bytes = banked_rom(2, Mapper::BankedRam { ram_banks: 4 }, &[]).to_bytes();
+    assert_eq!(bytes[12..16], [2, 4, 0, 0]);
+
+    // Version 1 reserved the mapper bytes.
+    let mut v1 = Rom::new(0xC000, &[0xA9]).unwrap().to_bytes();
+    v1[4] = 1;
+    v1[12] = 0xFF;
+    assert_eq!(Rom::from_bytes(&v1).unwrap().mapper(), Mapper::Flat);
+
+    let mut bad = Rom::new(0xC000, &[0xA9]).unwrap().to_bytes();
+    bad[12] = 3;
+    assert!(matches!(
+        Rom::from_bytes(&bad),
+        Err(RomError::UnknownMapper(3))
+    ));
+    bad[12] = 2;
+    assert!(matches!(
+        Rom::from_bytes(&bad),
+        Err(RomError::BadRamBankCount(0))
+    ));
+}
+
+#[test]
+fn mappers_take_more_banks() {
+    let data = vec![0; 3 * BANK_SIZE];
+    assert!(matches!(
+        Rom::new(0xC000, &data),
+        Err(RomError::BadBankCount(3))
+    ));
+    let rom = Rom::with_mapper(0xC000, &data, Mapper::Banked).unwrap();
+    assert_eq!(rom.banks(), 3);
+    assert_eq!(rom.base(), 0x8000);
+    assert_eq!(rom.to_bytes().len(), HEADER_SIZE + 3 * BANK_SIZE);
+
+    let data = vec![0; 256 * BANK_SIZE];
+    assert!(matches!(
+        Rom::with_mapper(0xC000, &data, Mapper::Banked),
+        Err(RomError::BadBankCount(255))
+    ));
+    assert!(matches!(
+        Rom::with_mapper(0xC000, &[0], Mapper::BankedRam { ram_banks: 0 }),
+        Err(RomError::BadRamBankCount(0))
+    ));
+}
+
+#[test]
+fn the_program_switches_rom_banks() {
+    // LSR $2730; LDA $8000; LSR $8000
+    let rom = banked_rom(
+        4,
+        Mapper::Banked,
+        &[0x4E, 0x30, 0x27, 0xAD, 0x00, 0x80, 0x4E, 0x00, 0x80],
+    );
+    let mut vm = Vm::new();
+    vm.load_rom(&rom).unwrap();
+    assert_eq!(vm.read(0x8000), 0);
+    assert_eq!(vm.read(0xC000), 3, "the last bank is fixed");
+    assert_eq!(vm.registers().pc, 0xC001);
+
+    select(&mut vm, 0, 6);
+    vm.step().unwrap();
+    let registers = vm.bus().device::<BankRegisters>(*MAPPER_PORTS.start());
+    assert_eq!(registers.unwrap().rom_bank(), 3);
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 3);
+    vm.step().unwrap();
+    assert_eq!(vm.read(0x8000), 3, "the window is ROM");
+
+    // Bank numbers wrap around the bank count.
+    select(&mut vm, 0, 5);
+    vm.set_registers(emulator::Registers {
+        pc: 0xC004,
+        ..vm.registers()
+    });
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 3, "the switch follows the instruction");
+    vm.set_registers(emulator::Registers {
+        pc: 0xC004,
+        ..vm.registers()
+    });
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 1);
+}
+
+#[test]
+fn batches_switch_after_the_writing_instruction() {
+    // LSR $2730; LDA $8000
+    let rom = banked_rom(4, Mapper::Banked, &[0x4E, 0x30, 0x27, 0xAD, 0x00, 0x80]);
+    let mut vm = Vm::new();
+    vm.load_rom(&rom).unwrap();
+    select(&mut vm, 0, 4);
+    vm.run_cycles(10).unwrap();
+    assert_eq!(vm.registers().a, 2);
+}
+
+#[test]
+fn ram_banks_keep_their_contents() {
+    // LDA #$00, three times.
+    let rom = banked_rom(
+        2,
+        Mapper::BankedRam { ram_banks: 2 },
+        &[0xA9, 0x00, 0xA9, 0x00, 0xA9, 0x00],
+    );
+    let mut vm = Vm::new();
+    vm.write(*RAM_WINDOW.start(), 0xEE);
+    vm.load_rom(&rom).unwrap();
+    assert_eq!(vm.read(*RAM_WINDOW.start()), 0, "RAM banks start zeroed");
+    vm.write(*RAM_WINDOW.start(), 0x11);
+
+    select(&mut vm, 1, 1);
+    vm.step().unwrap();
+    assert_eq!(vm.read(*RAM_WINDOW.start()), 0);
+    vm.write(*RAM_WINDOW.end(), 0x22);
+
+    select(&mut vm, 1, 0);
+    vm.step().unwrap();
+    assert_eq!(vm.read(*RAM_WINDOW.start()), 0x11);
+    assert_eq!(vm.read(*RAM_WINDOW.end()), 0);
+
+    select(&mut vm, 1, 3);
+    vm.step().unwrap();
+    assert_eq!(vm.read(*RAM_WINDOW.end()), 0x22);
+}
+
+#[test]
+fn a_flat_rom_removes_the_bank_registers() {
+    let mut vm = Vm::new();
+    vm.load_rom(&banked_rom(2, Mapper::Banked, &[])).unwrap();
+    vm.load_rom(&banked_rom(2, Mapper::Banked, &[])).unwrap();
+    assert!(vm.bus().device::<BankRegisters>(0x2730).is_some());
+    vm.load_rom(&Rom::new(0xC000, &[0xA9, 0x00]).unwrap())
+        .unwrap();
+    assert!(vm.bus().device::<BankRegisters>(0x2730).is_none());
+}
+
+#[test]
+fn a_device_on_the_mapper_ports_fails_the_load() {
+    let mut vm = Vm::new();
+    vm.load_rom(&Rom::new(0xC000, &[0xA9, 0x01]).unwrap())
+        .unwrap();
+    vm.bus_mut().map(0x2731..=0x2740, Dma::default()).unwrap();
+    let (memory, info) = (vm.read_mem_raw(0x0000..=0xFFFF), vm.rom_info());
+    let err = vm
+        .load_rom(&banked_rom(2, Mapper::Banked, &[]))
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RomError::MapConflict {
+            start: 0x2731,
+            end: 0x2740
+        }
+    ));
+    assert_eq!(vm.read_mem_raw(0x0000..=0xFFFF), memory);
+    assert_eq!(vm.rom_info(), info);
+    assert!(vm.bus().device::<Dma>(0x2731).is_some());
+}
+
+#[test]
+fn a_device_on_the_mapper_ports_fails_the_reload() {
+    let mut vm = Vm::new();
+    vm.load_rom(&Rom::new(0xC000, &[0xA9, 0x01]).unwrap())
+        .unwrap();
+    vm.step().unwrap();
+    vm.write(0x0200, 0x55);
+    vm.bus_mut().map(0x2720..=0x2730, Dma::default()).unwrap();
+    let (memory, registers) = (vm.read_mem_raw(0x0000..=0xFFFF), vm.registers());
+
+    let rom = banked_rom(2, Mapper::BankedRam { ram_banks: 1 }, &[]);
+    let fresh = ReloadPolicy {
+        keep_ram: false,
+        keep_registers: false,
+    };
+    for policy in [ReloadPolicy::default(), fresh] {
+        assert!(matches!(
+            vm.reload_rom(&rom.to_bytes(), policy),
+            Err(RomError::MapConflict {
+                start: 0x2720,
+                end: 0x2730
+            })
+        ));
+        assert_eq!(vm.read_mem_raw(0x0000..=0xFFFF), memory);
+        assert_eq!(vm.registers(), registers);
+        assert!(vm.bus().device::<BankRegisters>(0x2730).is_none());
+    }
+}
+
+#[test]
+fn patches_land_in_the_banks_the_cpu_sees() {
+    let mut rom = banked_rom(3, Mapper::Banked, &[]);
+    let patches = [
+        Patch {
+            addr: 0x8001,
+            value: 0xAA,
+        },
+        Patch {
+            addr: 0xC001,
+            value: 0xBB,
+        },
+    ];
+    rom.patch(&patches).unwrap();
+    assert_eq!(rom.data()[1], 0xAA);
+    assert_eq!(rom.data()[2 * BANK_SIZE + 1], 0xBB);
+    assert_eq!(rom.data()[BANK_SIZE + 1], 0);
+}
+
+#[test]
+fn reloading_keeps_the_bank_selection() {
+    let mut vm = Vm::new();
+    vm.load_rom(&banked_rom(
+        4,
+        Mapper::BankedRam { ram_banks: 2 },
+        &[0xA9, 0x00],
+    ))
+    .unwrap();
+    select(&mut vm, 0, 1);
+    vm.step().unwrap();
+    vm.write(*RAM_WINDOW.start(), 0x55);
+
+    // Bank n now starts with 0x10 + n.
+    let mut rom = banked_rom(3, Mapper::BankedRam { ram_banks: 2 }, &[0xA9, 0x00]);
+    let mut data = rom.data().to_vec();
+    for bank in 0..3 {
+        data[bank * BANK_SIZE] += 0x10;
+    }
+    rom = Rom::with_mapper(rom.entry(), &data, rom.mapper()).unwrap();
+    vm.reload_rom(&rom.to_bytes(), ReloadPolicy::default())
+        .unwrap();
+    assert_eq!(vm.read(0x8000), 0x11);
+    assert_eq!(vm.read(0xC000), 0x12);
+    assert_eq!(vm.read(*RAM_WINDOW.start()), 0x55);
+    assert!(vm.bus().device::<BankRegisters>(0x2730).is_some());
+
+    let reset = ReloadPolicy {
+        keep_ram: false,
+        ..ReloadPolicy::default()
+    };
+    vm.reload_rom(&rom.to_bytes(), reset).unwrap();
+    assert_eq!(vm.read(0x8000), 0x10);
+    assert_eq!(vm.read(*RAM_WINDOW.start()), 0x00);
+}
+
+#[test]
+fn snapshots_keep_the_banks_outside_the_address_space() {
+    // LDA #$00, four times.
+    let rom = banked_rom(
+        2,
+        Mapper::BankedRam { ram_banks: 2 },
+        &[0xA9, 0x00].repeat(4),
+    );
+    let mut vm = Vm::new();
+    vm.load_rom(&rom).unwrap();
+    vm.write(*RAM_WINDOW.start(), 0xAA);
+    let saved = vm.save_state();
+
+    select(&mut vm, 1, 1);
+    vm.step().unwrap();
+    vm.write(*RAM_WINDOW.start(), 0xBB);
+    vm.load_state(&saved).unwrap();
+    assert_eq!(vm.read(*RAM_WINDOW.start()), 0xAA);
+    let registers = vm.bus().device::<BankRegisters>(*MAPPER_PORTS.start());
+    assert_eq!(registers.unwrap().ram_bank(), 0);
+
+    // Bank 1 is as it was when saved, and bank 0 still holds AA.
+    select(&mut vm, 1, 1);
+    vm.step().unwrap();
+    assert_eq!(vm.read(*RAM_WINDOW.start()), 0);
+    select(&mut vm, 1, 0);
+    vm.step().unwrap();
+    assert_eq!(vm.read(*RAM_WINDOW.start()), 0xAA);
+}
@@ -0,0 +1,142 @@
+use emulator::mpu::{MPU_PORTS, Mpu, READ_ONLY, SUPERVISOR};
+use emulator::uart::{UART_PORTS, Uart};
+use emulator::{BusConfig, Rom, UnmappedRead, Vm, VmError};
+
+const DATA: u16 = *UART_PORTS.start();
+
+fn vm_with_uart() -> Vm {
+    let mut vm = Vm::new();
+    let mut uart = Uart::new();
+    uart.push_rx(b"hi");
+    vm.bus_mut().map(UART_PORTS, uart).unwrap();
+    vm
+}
+
+#[test]
+fn bus_reads_reach_devices_and_their_side_effects() {
+    let mut vm = vm_with_uart();
+    vm.write(DATA, 0x77);
+    assert_eq!(vm.read_mem(DATA..=DATA), b"h");
+    assert_eq!(vm.read_mem(DATA..=DATA), b"i");
+    assert_eq!(vm.read_mem(DATA..=DATA), [0]);
+    // The RAM under the device is untouched and still visible raw.
+    assert_eq!(vm.read_mem_raw(DATA - 1..=DATA), [0, 0x77]);
+}
+
+#[test]
+fn bus_writes_reach_devices_and_skip_rom() {
+    let mut vm = vm_with_uart();
+    vm.load_rom(&Rom::new(0xC000, &[0xA9, 0x01]).unwrap())
+        .unwrap();
+    vm.write_mem(DATA - 1, b"ab").unwrap();
+    vm.write_mem(0xBFFF, &[1, 2]).unwrap();
+
+    assert_eq!(vm.read(DATA - 1), b'a');
+    assert_eq!(vm.read(DATA), 0);
+    let uart = vm.bus_mut().device_mut::<Uart>(DATA).unwrap();
+    assert_eq!(uart.take_tx(), b"b");
+    assert_eq!(vm.read_mem(0xBFFF..=0xC000), [1, 0xA9]);
+
+    // Raw writes patch ROM and the RAM under devices.
+    vm.write_mem_raw(0xC000, &[0xEA]).unwrap();
+    vm.write_mem_raw(DATA, &[0x55]).unwrap();
+    assert_eq!(vm.read_mem_raw(0xC000..=0xC000), [0xEA]);
+    assert_eq!(vm.read(DATA), 0x55);
+    let uart = vm.bus_mut().device_mut::<Uart>(DATA).unwrap();
+    assert!(uart.take_tx().is_empty());
+}
+
+#[test]
+fn bus_accesses_decode_mirrors() {
+    let mut vm = vm_with_uart();
+    vm.mirror(0x1000..=0x10FF, 0x0200).unwrap();
+    // $3000-$30FF mirrors the UART's page.
+    vm.mirror(0x3000..=0x30FF, DATA & 0xFF00).unwrap();
+    vm.write_mem(0x1010, &[0x42]).unwrap();
+    assert_eq!(vm.read(0x0210), 0x42);
+    assert_eq!(vm.read(0x1010), 0);
+    assert_eq!(vm.read_mem(0x1010..=0x1010), [0x42]);
+    let mirrored = 0x3000 | DATA & 0xFF;
+    assert_eq!(vm.read_mem(mirrored..=mirrored), b"h");
+}
+
+#[test]
+fn bus_accesses_follow_the_mpu_hooks_and_unmapped_pages() {
+    let mut vm = Vm::new();
+    vm.load(0x1000, &[0x11, 0x22]).unwrap();
+    let mut mpu = Mpu::default();
+    mpu.protect(0x1000..=0x1000, READ_ONLY);
+    mpu.protect(0x1001..=0x1001, SUPERVISOR);
+    mpu.set_supervisor(false);
+    vm.bus_mut().set_mpu(mpu);
+    vm.write_mem(0x1000, &[0x33]).unwrap();
+    assert_eq!(vm.read(0x1000), 0x11);
+    assert_eq!(vm.read_mem(0x1000..=0x1001), [0x11, 0x10]);
+    // Refused, but not latched against the program.
+    let mode = *MPU_PORTS.start() + 3;
+    assert_eq!(vm.bus().mpu().unwrap().fault(), None);
+    assert_eq!(vm.read_mem(mode..=mode), [0]);
+
+    vm.on_read(0x2000..=0x2000, |_, val| val + 1);
+    vm.bus_mut().unmap_ram(0x2000..=0x20FF);
+    vm.bus_mut().set_config(BusConfig {
+        unmapped_read: UnmappedRead::Trap,
+        ..BusConfig::default()
+    });
+    vm.write_mem(0x2000, &[0x44]).unwrap();
+    assert_eq!(vm.read(0x2000), 0);
+    assert_eq!(vm.read_mem(0x2000..=0x2001), [0x21, 0x20]);
+    // LDA #$01 at the reset PC runs with no fault pending.
+    vm.load(0x0000, &[0xA9, 0x01]).unwrap();
+    assert_eq!(vm.step(), Ok(()));
+}
+
+#[test]
+fn writes_past_the_end_change_nothing() {
+    let mut vm = Vm::new();
+    let overrun = Err(VmError::OutOfBounds {
+        addr: 0xFFFF,
+        len: 2,
+    });
+    assert_eq!(vm.write_mem(0xFFFF, &[1, 2]), overrun);
+    assert_eq!(vm.write_mem_raw(0xFFFF, &[1, 2]), overrun);
+    assert_eq!(vm.read_mem(0xFFFE..=0xFFFF), [0, 0]);
+    vm.write_mem(0xFFFF, &[3]).unwrap();
+    assert_eq!(vm.read_mem_raw(0xFFFE..=0xFFFF), [0, 3]);
+    assert_eq!(vm.read_mem(0x0000..=0xFFFF).len(), 0x10000);
+}
+
+#[test]
+fn reversed_ranges_read_nothing() {
+    let mut vm = vm_with_uart();
+    vm.write_mem_raw(0x0003, &[0x11, 0x22, 0x33]).unwrap();
+    for (start, end) in [(5, 3), (DATA, DATA - 1)] {
+        assert!(vm.read_mem_raw(start..=end).is_empty());
+        assert!(vm.read_mem(start..=end).is_empty());
+    }
+    // The device was not read.
+    assert_eq!(vm.read_mem(DATA..=DATA), b"h");
+}
+
+#[test]
+fn hexdumps_list_the_labels_in_each_row() {
+    let mut vm = Vm::new();
+    vm.write_mem_raw(0x0200, b"Hi!").unwrap();
+    assert_eq!(
+        vm.hexdump(0x0200..=0x0212),
+        "0200  48 69 21 00 00 00 00 00 00 00 00 00 00 00 00 00  Hi!.............\n\
+         0210  00 00 00                                         ..."
+    );
+    vm.label(0x0203, "lives");
+    vm.label(0x0200, "greeting");
+    vm.label_region(0x0208..=0x02FF, "buffer");
+    assert_eq!(
+        vm.hexdump(0x0200..=0x0222),
+        "0200  48 69 21 00 00 00 00 00 00 00 00 00 00 00 00 00  Hi!.............  \
+         ; $0200 greeting, $0203 lives, $0208 buffer\n\
+         0210  00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00  ................  ; buffer+8\n\
+         0220  00 00 00                                         ...               ; buffer+24"
+    );
+    let (start, end) = (0x0205, 0x0203);
+    assert_eq!(vm.hexdump(start..=end), "");
+}
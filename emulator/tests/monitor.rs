@@ -0,0 +1,148 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use emulator::monitor::{Monitor, MonitorError, Reply};
+use emulator::{Rom, SymbolTable, Vm};
+
+fn text(monitor: &mut Monitor, vm: &mut Vm, line: &str) -> String {
+    match monitor.command(vm, line) {
+        Ok(Reply::Text(text)) => text,
+        other => panic!("`{line}`: {other:?}"),
+    }
+}
+
+#[test]
+fn examines_and_deposits_memory() {
+    let (mut vm, mut monitor) = (Vm::new(), Monitor::new());
+    assert_eq!(text(&mut monitor, &mut vm, ": 0200 48 69 $21"), "");
+    assert_eq!(vm.read_mem_raw(0x0200..=0x0203), [0x48, 0x69, 0x21, 0]);
+
+    let dump = text(&mut monitor, &mut vm, "m 200 21f");
+    assert_eq!(
+        dump,
+        "0200  48 69 21 00 00 00 00 00 00 00 00 00 00 00 00 00  Hi!.............\n\
+         0210  00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00  ................"
+    );
+    // An empty line carries on from the last row.
+    let next = text(&mut monitor, &mut vm, "");
+    assert_eq!(next.lines().count(), 8);
+    assert!(next.starts_with("0220  00"));
+
+    assert_eq!(
+        monitor.command(&mut vm, ": 0200 100"),
+        Err(MonitorError::BadNumber("100".into()))
+    );
+    assert_eq!(
+        monitor.command(&mut vm, ":"),
+        Err(MonitorError::Missing("address"))
+    );
+    assert_eq!(
+        monitor.command(&mut vm, "z"),
+        Err(MonitorError::UnknownCommand("z".into()))
+    );
+}
+
+#[test]
+fn assembles_disassembles_and_steps() {
+    let (mut vm, mut monitor) = (Vm::new(), Monitor::new());
+    let mut symbols = SymbolTable::new();
+    symbols.insert("start", 0xC000);
+    vm.set_symbols(symbols);
+
+    assert_eq!(
+        text(&mut monitor, &mut vm, "a start LDX #$05"),
+        "C000  A2 05     LDX #$05"
+    );
+    text(&mut monitor, &mut vm, "a C002 LDY #1");
+    text(&mut monitor, &mut vm, "a C004 .byte $02");
+    assert_eq!(
+        text(&mut monitor, &mut vm, "d start 2"),
+        "C000  A2 05     LDX #$05\nC002  A0 01     LDY #$01"
+    );
+    let err = monitor.command(&mut vm, "a C000 FOO").unwrap_err();
+    assert_eq!(err.to_string(), "unknown mnemonic `FOO`");
+
+    assert_eq!(
+        text(&mut monitor, &mut vm, "r pc=start a=7f"),
+        format!(
+            "A:7F X:00 Y:00 P:{:02X} SP:{:02X} PC:C000  CYC:{}",
+            vm.registers().flags,
+            vm.registers().sp,
+            vm.cycles()
+        )
+    );
+    let stepped = text(&mut monitor, &mut vm, "s");
+    assert!(stepped.contains("X:05"), "{stepped}");
+    assert!(stepped.ends_with("C002  A0 01     LDY #$01"), "{stepped}");
+    // An empty line steps again.
+    assert!(text(&mut monitor, &mut vm, "").contains("Y:01"));
+
+    let stopped = text(&mut monitor, &mut vm, "g");
+    assert!(
+        stopped.starts_with("illegal opcode 0x02 at PC 0xC004"),
+        "{stopped}"
+    );
+
+    assert_eq!(
+        text(&mut monitor, &mut vm, "b C002"),
+        "breakpoint set at C002"
+    );
+    assert_eq!(text(&mut monitor, &mut vm, "b"), "C002  start+2");
+    let stopped = text(&mut monitor, &mut vm, "g start");
+    assert!(stopped.starts_with("breakpoint at start+2"), "{stopped}");
+    assert_eq!(
+        monitor.command(&mut vm, "r q=1"),
+        Err(MonitorError::UnknownRegister("q".into()))
+    );
+    assert_eq!(monitor.command(&mut vm, "q"), Ok(Reply::Quit));
+}
+
+#[test]
+fn runs_over_a_stream() {
+    let mut vm = Vm::new();
+    let mut output = Vec::new();
+    let input = ": 10 aa\r\nm 10 10\r\nbogus\nq\nm 0\n";
+    vm.run_monitor(input.as_bytes(), &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(
+        output,
+        "rvm8 monitor; ? for help\n\
+         > > 0010  AA 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00  ................\n\
+         > ? unknown command `bogus`; ? for help\n\
+         > "
+    );
+}
+
+#[test]
+fn rvm8_monitor_reads_stdin() {
+    let rom = std::env::temp_dir().join(format!("rvm8-monitor-{}-rom", std::process::id()));
+    std::fs::write(&rom, Rom::new(0xC000, &[0xA9, 0x01]).unwrap().to_bytes()).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rvm8"))
+        .arg("monitor")
+        .arg(&rom)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"d C000 1\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    std::fs::remove_file(&rom).unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert!(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .contains("C000  A9 01     LDA #$01")
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_rvm8"))
+        .args(["monitor", "rom", "--trace", "-"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("`--trace` is not a monitor option"));
+}
@@ -0,0 +1,166 @@
+mod common;
+
+use common::vm_with_handler;
+use emulator::mpu::{MPU_IRQ, Mpu, MpuResponse, NO_EXECUTE, READ_ONLY, SUPERVISOR, Violation};
+use emulator::{Registers, ResetKind, Vm, VmError};
+
+/// `program` at 0x8000, an IRQ handler at 0x9000 starting `LDA #$02`, and
+/// the I flag cleared.
+fn vm_with_mpu(program: &[u8], mpu: Mpu) -> Vm {
+    let mut vm = vm_with_handler(program);
+    vm.load(0x9000, &[0xA9, 0x02]).unwrap();
+    vm.bus_mut().set_mpu(mpu);
+    vm.set_registers(Registers {
+        flags: 0,
+        ..vm.registers()
+    });
+    vm
+}
+
+/// `LSR $2743`: reads `MODE` as 1 and writes back 0, entering user mode.
+const ENTER_USER: [u8; 3] = [0x4E, 0x43, 0x27];
+
+#[test]
+fn writes_to_read_only_memory_trap() {
+    let mut mpu = Mpu::default();
+    mpu.protect(0x1200..=0x12FF, READ_ONLY);
+    // LSR $1100; LSR $1234
+    let mut vm = vm_with_mpu(&[0x4E, 0x00, 0x11, 0x4E, 0x34, 0x12], mpu);
+    vm.write(0x1100, 0x08);
+    vm.write(0x1234, 0x08);
+
+    let err = VmError::MpuFault {
+        pc: 0x8003,
+        addr: 0x1234,
+        violation: Violation::WriteProtect,
+    };
+    assert_eq!(vm.run_cycles(100), Err(err.clone()));
+    assert_eq!(vm.read(0x1100), 0x04);
+    assert_eq!(vm.read(0x1234), 0x08, "the write was blocked");
+    assert_eq!(vm.registers().pc, 0x8006, "the instruction completed");
+    assert_eq!(
+        err.to_string(),
+        "MPU fault at PC 0x8003: write to read-only memory at 0x1234"
+    );
+    assert_eq!(vm.bus().mpu().unwrap().fault(), None, "reported once");
+}
+
+#[test]
+fn no_execute_memory_is_not_executed() {
+    let mut mpu = Mpu::default();
+    mpu.protect(0x8002..=0x8FFF, NO_EXECUTE);
+    // LDA #$01; LDA #$07
+    let mut vm = vm_with_mpu(&[0xA9, 0x01, 0xA9, 0x07], mpu);
+    vm.step().unwrap();
+    assert_eq!(
+        vm.step(),
+        Err(VmError::MpuFault {
+            pc: 0x8002,
+            addr: 0x8002,
+            violation: Violation::NoExecute,
+        })
+    );
+    assert_eq!(vm.registers().a, 0x01);
+    assert_eq!(vm.registers().pc, 0x8002);
+    assert_eq!(vm.cycles(), 2);
+}
+
+#[test]
+fn supervisor_memory_is_closed_to_user_mode() {
+    let mut mpu = Mpu::default();
+    mpu.protect(0x0300..=0x03FF, SUPERVISOR);
+    // LDA $0300; LSR $2743; LDA $0301
+    let mut program = vec![0xAD, 0x00, 0x03];
+    program.extend(ENTER_USER);
+    program.extend([0xAD, 0x01, 0x03]);
+    let mut vm = vm_with_mpu(&program, mpu);
+    vm.load(0x0300, &[0x11, 0x22]).unwrap();
+
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 0x11);
+    vm.step().unwrap();
+    assert!(!vm.bus().mpu().unwrap().is_supervisor());
+    assert_eq!(
+        vm.step(),
+        Err(VmError::MpuFault {
+            pc: 0x8006,
+            addr: 0x0301,
+            violation: Violation::Supervisor,
+        })
+    );
+    assert_eq!(vm.registers().a, 0x03, "open bus");
+
+    vm.reset(ResetKind::Soft);
+    assert!(vm.bus().mpu().unwrap().is_supervisor());
+}
+
+#[test]
+fn interrupting_mpu_latches_the_violation() {
+    let mut mpu = Mpu::new(MpuResponse::Interrupt { line: MPU_IRQ });
+    mpu.protect(0x1234..=0x1234, READ_ONLY);
+    // LSR $2743; LSR $1234; LDA $2740; LDA $2742; LSR $2742
+    let mut program = ENTER_USER.to_vec();
+    program.extend([0x4E, 0x34, 0x12]);
+    program.extend([0xAD, 0x40, 0x27, 0xAD, 0x42, 0x27, 0x4E, 0x42, 0x27]);
+    let mut vm = vm_with_mpu(&program, mpu);
+    vm.write(0x1234, 0x08);
+    vm.set_registers(Registers {
+        flags: emulator::ffi::FLAG_I,
+        ..vm.registers()
+    });
+
+    vm.step().unwrap();
+    vm.step().unwrap();
+    assert_eq!(vm.read(0x1234), 0x08);
+    assert_eq!(
+        vm.bus().mpu().unwrap().fault(),
+        Some((0x1234, Violation::WriteProtect))
+    );
+    assert_eq!(vm.pending_irqs(), 1 << MPU_IRQ);
+
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 0x34);
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, READ_ONLY);
+    vm.step().unwrap();
+    assert_eq!(vm.bus().mpu().unwrap().fault(), None);
+    assert_eq!(vm.pending_irqs(), 0);
+}
+
+#[test]
+fn interrupts_enter_supervisor_mode() {
+    let mut mpu = Mpu::new(MpuResponse::Interrupt { line: MPU_IRQ });
+    mpu.protect(0x1234..=0x1234, READ_ONLY);
+    // LSR $2743; LSR $1234; LDA #$01
+    let mut program = ENTER_USER.to_vec();
+    program.extend([0x4E, 0x34, 0x12, 0xA9, 0x01]);
+    let mut vm = vm_with_mpu(&program, mpu);
+
+    vm.step().unwrap();
+    vm.step().unwrap();
+    assert!(!vm.bus().mpu().unwrap().is_supervisor());
+    vm.step().unwrap();
+    assert_eq!(vm.registers().pc, 0x9000);
+    assert!(vm.bus().mpu().unwrap().is_supervisor());
+}
+
+#[test]
+fn snapshots_keep_the_mode_and_the_latched_violation() {
+    let mut mpu = Mpu::new(MpuResponse::Interrupt { line: MPU_IRQ });
+    mpu.protect(0x1200..=0x12FF, SUPERVISOR);
+    // LSR $2743; LDA $1200; NOP
+    let mut program = ENTER_USER.to_vec();
+    program.extend_from_slice(&[0xAD, 0x00, 0x12, 0xEA]);
+    let mut vm = vm_with_mpu(&program, mpu);
+    vm.step().unwrap();
+    vm.step().unwrap();
+    let saved = vm.save_state();
+
+    let mpu = vm.bus_mut().mpu_mut().unwrap();
+    mpu.acknowledge();
+    mpu.set_supervisor(true);
+    vm.load_state(&saved).unwrap();
+    let mpu = vm.bus().mpu().unwrap();
+    assert!(!mpu.is_supervisor());
+    assert_eq!(mpu.fault(), Some((0x1200, Violation::Supervisor)));
+}
@@ -0,0 +1,178 @@
+#![cfg(feature = "netplay")]
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::net::UdpSocket;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use emulator::input::CONTROLLER;
+use emulator::netplay::{Advance, MAX_ROLLBACK, Netplay, PLAYERS, Transport};
+use emulator::{ResetKind, Vm};
+
+const FRAMES: u64 = 12;
+
+/// Memory full of `LDA #$A9` from $A9A9 on, so the CPU runs forever, with
+/// each player's input history hashed into $10 and $12 before every frame.
+fn machine() -> Vm {
+    let mut vm = Vm::new();
+    vm.bus_mut().unmap(CONTROLLER);
+    vm.load(0, &[0xA9; 0x10000]).unwrap();
+    vm.reset(ResetKind::Soft);
+    vm
+}
+
+fn apply(vm: &mut Vm, inputs: [u8; PLAYERS]) {
+    for (player, input) in inputs.into_iter().enumerate() {
+        let addr = 0x10 + 2 * player as u16;
+        vm.write(addr, vm.read(addr).wrapping_mul(31) ^ input);
+    }
+}
+
+fn input(player: usize, frame: u64) -> u8 {
+    (frame as u8).wrapping_mul(3 + 4 * player as u8) | 1
+}
+
+/// The state both peers must reach after `FRAMES` frames.
+fn expected() -> Vec<u8> {
+    let mut vm = machine();
+    for frame in 0..FRAMES {
+        apply(&mut vm, [input(0, frame), input(1, frame)]);
+        vm.run_frame().unwrap();
+    }
+    vm.memory().to_vec()
+}
+
+/// One end of an in-memory link whose packets are delivered on demand.
+struct Link {
+    inbox: Rc<RefCell<VecDeque<Vec<u8>>>>,
+    outbox: Rc<RefCell<VecDeque<Vec<u8>>>>,
+}
+
+fn link() -> (Link, Link) {
+    let (a, b) = (Rc::default(), Rc::default());
+    (
+        Link {
+            inbox: Rc::clone(&a),
+            outbox: Rc::clone(&b),
+        },
+        Link {
+            inbox: b,
+            outbox: a,
+        },
+    )
+}
+
+impl Transport for Link {
+    fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+        self.outbox.borrow_mut().push_back(packet.to_vec());
+        Ok(())
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        Ok(self.inbox.borrow_mut().pop_front().map(|packet| {
+            buf[..packet.len()].copy_from_slice(&packet);
+            packet.len()
+        }))
+    }
+}
+
+#[test]
+fn late_remote_input_rolls_back_to_the_true_state() {
+    let (a_link, b_link) = link();
+    let mut a = Netplay::new(a_link, 0, apply);
+    let mut b = Netplay::new(b_link, 1, apply);
+    let (mut a_vm, mut b_vm) = (machine(), machine());
+
+    // A runs ahead on guesses until it may not guess any further.
+    for frame in 0..MAX_ROLLBACK {
+        let advance = a.run_frame(&mut a_vm, input(0, frame)).unwrap();
+        assert_eq!(advance, Advance::Ran { resimulated: 0 });
+    }
+    assert_eq!(
+        a.run_frame(&mut a_vm, input(0, MAX_ROLLBACK)).unwrap(),
+        Advance::Waiting
+    );
+    assert_eq!(a.frame(), MAX_ROLLBACK);
+
+    // B has every input A sent, so it never guesses wrong.
+    for frame in 0..FRAMES {
+        let advance = b.run_frame(&mut b_vm, input(1, frame)).unwrap();
+        assert_eq!(advance, Advance::Ran { resimulated: 0 });
+    }
+    assert_eq!(b.confirmed_frame(), MAX_ROLLBACK);
+
+    // B's input proves every guess wrong, from the first frame on.
+    let advance = a.run_frame(&mut a_vm, input(0, MAX_ROLLBACK)).unwrap();
+    assert_eq!(
+        advance,
+        Advance::Ran {
+            resimulated: MAX_ROLLBACK
+        }
+    );
+    for frame in MAX_ROLLBACK + 1..FRAMES {
+        a.run_frame(&mut a_vm, input(0, frame)).unwrap();
+    }
+    assert_eq!(a.confirmed_frame(), FRAMES);
+    assert_eq!(a.rollbacks(), 1);
+
+    b.sync(&mut b_vm).unwrap();
+    assert_eq!(b.confirmed_frame(), FRAMES);
+    assert_eq!(a_vm.memory(), expected());
+    assert_eq!(a_vm.save_state(), b_vm.save_state());
+}
+
+#[test]
+fn lost_packets_are_sent_again() {
+    let (a_link, b_link) = link();
+    let dropped = Rc::clone(&b_link.inbox);
+    let mut a = Netplay::new(a_link, 0, apply);
+    let mut b = Netplay::new(b_link, 1, apply);
+    let (mut a_vm, mut b_vm) = (machine(), machine());
+
+    for frame in 0..FRAMES {
+        a.run_frame(&mut a_vm, input(0, frame)).unwrap();
+        if frame % 3 != 2 {
+            dropped.borrow_mut().clear();
+        }
+        b.run_frame(&mut b_vm, input(1, frame)).unwrap();
+    }
+    a.sync(&mut a_vm).unwrap();
+    b.sync(&mut b_vm).unwrap();
+    assert_eq!((a.confirmed_frame(), b.confirmed_frame()), (FRAMES, FRAMES));
+    assert_eq!(a_vm.memory(), expected());
+    assert_eq!(b_vm.memory(), expected());
+}
+
+#[test]
+fn peers_converge_over_udp() {
+    let a_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let b_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    for (socket, peer) in [(&a_socket, &b_socket), (&b_socket, &a_socket)] {
+        socket.connect(peer.local_addr().unwrap()).unwrap();
+        socket.set_nonblocking(true).unwrap();
+    }
+    let mut a = Netplay::new(a_socket, 0, apply);
+    let mut b = Netplay::new(b_socket, 1, apply);
+    let (mut a_vm, mut b_vm) = (machine(), machine());
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while a.frame() < FRAMES || b.frame() < FRAMES {
+        assert!(Instant::now() < deadline, "peers stalled");
+        if a.frame() < FRAMES {
+            a.run_frame(&mut a_vm, input(0, a.frame())).unwrap();
+        }
+        if b.frame() < FRAMES {
+            b.run_frame(&mut b_vm, input(1, b.frame())).unwrap();
+        }
+    }
+    while a.confirmed_frame() < FRAMES || b.confirmed_frame() < FRAMES {
+        assert!(Instant::now() < deadline, "peers never confirmed");
+        a.sync(&mut a_vm).unwrap();
+        b.sync(&mut b_vm).unwrap();
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(a_vm.memory(), expected());
+    assert_eq!(a_vm.save_state(), b_vm.save_state());
+}
@@ -0,0 +1,74 @@
+use emulator::pacer::{FramePacer, MAX_SKEW, Pace};
+
+/// Frames run over `refreshes` host refreshes.
+fn frames_over(pacer: &mut FramePacer, refreshes: u32) -> (u32, Vec<Pace>) {
+    let paces: Vec<Pace> = (0..refreshes).map(|_| pacer.refresh()).collect();
+    (paces.iter().map(|pace| pace.frames).sum(), paces)
+}
+
+#[test]
+fn near_multiples_lock_and_bend_the_speed() {
+    let mut pacer = FramePacer::new(59.94, 60.0);
+    assert!(pacer.is_locked());
+    assert!((pacer.speed() - 0.999).abs() < 1e-9);
+    let (frames, paces) = frames_over(&mut pacer, 600);
+    assert_eq!(frames, 600);
+    assert!(paces.iter().all(|pace| !pace.repeat && pace.dropped == 0));
+
+    // Each frame shown for two refreshes.
+    let mut pacer = FramePacer::new(120.0, 60.0);
+    let (frames, paces) = frames_over(&mut pacer, 4);
+    assert_eq!(frames, 2);
+    assert_eq!(
+        paces.iter().map(|pace| pace.repeat).collect::<Vec<_>>(),
+        [true, false, true, false]
+    );
+
+    // Two frames per refresh, one of them never seen.
+    let mut pacer = FramePacer::new(30.0, 60.0);
+    assert!(pacer.is_locked());
+    assert_eq!(
+        pacer.refresh(),
+        Pace {
+            frames: 2,
+            repeat: false,
+            dropped: 1
+        }
+    );
+}
+
+#[test]
+fn other_rates_keep_real_time() {
+    let mut pacer = FramePacer::new(75.0, 60.0);
+    assert!(!pacer.is_locked());
+    assert_eq!(pacer.speed(), 1.0);
+    let (frames, paces) = frames_over(&mut pacer, 75);
+    assert_eq!(frames, 60);
+    assert_eq!(paces.iter().filter(|pace| pace.repeat).count(), 15);
+
+    let mut pacer = FramePacer::new(50.0, 60.0);
+    let (frames, paces) = frames_over(&mut pacer, 50);
+    assert_eq!(frames, 60);
+    assert_eq!(paces.iter().map(|pace| pace.dropped).sum::<u32>(), 10);
+}
+
+#[test]
+fn the_audio_queue_steers_the_speed() {
+    let mut pacer = FramePacer::new(60.0, 60.0);
+    pacer.audio_queued(1024, 2048);
+    assert!((pacer.speed() - (1.0 - MAX_SKEW / 2.0)).abs() < 1e-9);
+    pacer.audio_queued(100_000, 2048);
+    assert!((pacer.speed() - (1.0 + MAX_SKEW)).abs() < 1e-9);
+    pacer.audio_queued(2048, 2048);
+    assert_eq!(pacer.speed(), 1.0);
+
+    pacer.audio_queued(0, 2048);
+    pacer.reset();
+    assert_eq!(pacer.speed(), 1.0);
+}
+
+#[test]
+#[should_panic(expected = "finite and positive")]
+fn rates_must_be_positive() {
+    FramePacer::new(0.0, 60.0);
+}
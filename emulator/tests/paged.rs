@@ -0,0 +1,109 @@
+use emulator::display::STATUS;
+use emulator::ffi::RVM_MEM_SIZE;
+use emulator::input::CONTROLLER;
+use emulator::paged::PAGES;
+use emulator::{BusDevice, DmaBus, PagedSnapshot, Registers, Vm};
+
+/// A machine that keeps shifting the byte at 0x00F1 with `LSR $F1`, running
+/// on through `LDA #$A9` everywhere else, as in the rewind tests.
+fn looping_vm() -> Vm {
+    let mut image = vec![0xA9; RVM_MEM_SIZE];
+    image[..2].copy_from_slice(&[0x46, 0xF1]);
+    image[0xF1] = 0xFF;
+    let mut vm = Vm::new();
+    vm.bus_mut().unmap(CONTROLLER);
+    vm.load(0, &image).unwrap();
+    vm.set_registers(Registers {
+        pc: 0,
+        ..vm.registers()
+    });
+    vm
+}
+
+fn copied_pages(newer: &PagedSnapshot, older: &PagedSnapshot) -> Vec<u8> {
+    (0..PAGES as u16)
+        .map(|page| page as u8)
+        .filter(|&page| !newer.shares_page(older, page))
+        .collect()
+}
+
+/// Stores 1 in 0x4000 over DMA after the first instruction.
+struct Poke(bool);
+
+impl BusDevice for Poke {
+    fn read8(&mut self, _offset: u16) -> u8 {
+        0
+    }
+
+    fn write8(&mut self, _offset: u16, _val: u8) {}
+
+    fn dma(&mut self, bus: &mut DmaBus<'_>) -> u32 {
+        if !std::mem::replace(&mut self.0, true) {
+            bus.write(0x4000, 1);
+        }
+        0
+    }
+}
+
+#[test]
+fn a_frame_copies_only_the_pages_it_wrote() {
+    let mut vm = looping_vm();
+    vm.write(STATUS, 0);
+    let first = vm.save_paged();
+    vm.run_frame().unwrap();
+    let second = vm.save_paged();
+    // The LSR target and the display status the vblank sets.
+    assert_eq!(copied_pages(&second, &first), [0x00, (STATUS >> 8) as u8]);
+    assert_eq!(second.to_snapshot(), vm.save_state());
+    assert_eq!(copied_pages(&vm.save_paged(), &second), Vec::<u8>::new());
+
+    // A store of the value already there keeps the page shared.
+    vm.write(0x1234, 0xA9);
+    assert_eq!(copied_pages(&vm.save_paged(), &second), Vec::<u8>::new());
+}
+
+#[test]
+fn paged_snapshots_round_trip() {
+    let mut vm = looping_vm();
+    for _ in 0..5 {
+        vm.run_frame().unwrap();
+    }
+    let paged = vm.save_paged();
+    let flat = vm.save_state();
+    for _ in 0..5 {
+        vm.run_frame().unwrap();
+    }
+    vm.memory_mut()[0x5000] = 7;
+    vm.load_paged(&paged);
+    assert_eq!(vm.save_state(), flat);
+    assert_eq!(paged.read(0x00F1), flat.memory[0xF1]);
+
+    let converted = PagedSnapshot::try_from(&flat).unwrap();
+    assert_eq!(converted, paged);
+    assert!(!converted.shares_page(&paged, 0));
+    vm.load_state(&vm.save_state()).unwrap();
+    let mut short = flat;
+    short.memory.pop();
+    assert!(PagedSnapshot::try_from(&short).is_err());
+}
+
+#[test]
+fn dma_and_host_writes_are_tracked() {
+    let mut vm = looping_vm();
+    vm.bus_mut().map(0x3000..=0x3000, Poke(false)).unwrap();
+    let before = vm.save_paged();
+    vm.step().unwrap();
+    vm.load(0x50FF, &[1, 2]).unwrap();
+    vm.write_mem(0x6000, &[3]).unwrap();
+    let after = vm.save_paged();
+    assert_eq!(
+        copied_pages(&after, &before),
+        [0x00, 0x40, 0x50, 0x51, 0x60]
+    );
+    assert_eq!(after.read(0x4000), 1);
+
+    vm.load_paged(&before);
+    assert_eq!(vm.read(0x4000), 0xA9);
+    assert_eq!(vm.read_mem_raw(0x50FF..=0x5100), [0xA9, 0xA9]);
+    assert_eq!(vm.save_state(), before.to_snapshot());
+}
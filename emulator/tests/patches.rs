@@ -0,0 +1,153 @@
+use std::sync::{Arc, Mutex};
+
+use emulator::patches::{self, Ips, Patch, PatchError};
+use emulator::{Rom, Vm};
+
+fn ips(records: &[&[u8]]) -> Vec<u8> {
+    let mut bytes = b"PATCH".to_vec();
+    for record in records {
+        bytes.extend_from_slice(record);
+    }
+    bytes.extend_from_slice(b"EOF");
+    bytes
+}
+
+#[test]
+fn lists_parse_with_comments() {
+    let list =
+        patches::parse_list("; cheats\n$0042 = $63 ; lives\n\n0xC010=234\n%1=%10\n").unwrap();
+    assert_eq!(
+        list,
+        [
+            Patch {
+                addr: 0x42,
+                value: 0x63
+            },
+            Patch {
+                addr: 0xC010,
+                value: 0xEA
+            },
+            Patch { addr: 1, value: 2 },
+        ]
+    );
+
+    assert_eq!(
+        patches::parse_list("\n$10"),
+        Err(PatchError::Syntax { line: 2 })
+    );
+    assert_eq!(
+        patches::parse_list("$10000 = 1"),
+        Err(PatchError::BadAddress {
+            line: 1,
+            text: "$10000".into()
+        })
+    );
+    assert_eq!(
+        patches::parse_list("$10 = $100").unwrap_err().to_string(),
+        "line 1: `$100` is not an 8-bit value"
+    );
+}
+
+#[test]
+fn ips_records_and_runs_apply() {
+    let patch = Ips::parse(&ips(&[
+        &[0, 0, 2, 0, 2, 0xAA, 0xBB],
+        &[0, 0, 6, 0, 0, 0, 3, 0xCC],
+    ]))
+    .unwrap();
+    let mut data = [0; 10];
+    patch.apply(&mut data).unwrap();
+    assert_eq!(data, [0, 0, 0xAA, 0xBB, 0, 0, 0xCC, 0xCC, 0xCC, 0]);
+
+    // A record past the end rejects the whole patch.
+    let mut short = [0; 8];
+    assert_eq!(
+        patch.apply(&mut short),
+        Err(PatchError::PastEnd { offset: 6 })
+    );
+    assert_eq!(short, [0; 8]);
+}
+
+#[test]
+fn malformed_ips_is_rejected() {
+    assert_eq!(Ips::parse(b"PATCX"), Err(PatchError::BadMagic));
+    assert_eq!(Ips::parse(b"PATCH"), Err(PatchError::Truncated));
+    assert_eq!(
+        Ips::parse(b"PATCH\0\0\x01\0\x04ab"),
+        Err(PatchError::Truncated)
+    );
+    // Data after EOF is ignored.
+    assert!(Ips::parse(b"PATCHEOF\0\x40\0").is_ok());
+}
+
+#[test]
+fn roms_patch_by_address_or_ips_before_loading() {
+    let mut rom = Rom::new(0xC000, &[0xA9, 0x01, 0xA2, 0x02]).unwrap();
+    rom.patch(&[Patch {
+        addr: 0xC001,
+        value: 0x3F,
+    }])
+    .unwrap();
+    rom.apply_ips(&Ips::parse(&ips(&[&[0, 0, 3, 0, 1, 0x07]])).unwrap())
+        .unwrap();
+    assert_eq!(rom.data()[..4], [0xA9, 0x3F, 0xA2, 0x07]);
+    // Serializing recomputes the checksum over the patched data.
+    assert_eq!(Rom::from_bytes(&rom.to_bytes()).unwrap(), rom);
+
+    assert_eq!(
+        rom.patch(&[
+            Patch {
+                addr: 0xC002,
+                value: 0
+            },
+            Patch {
+                addr: 0xBFFF,
+                value: 0
+            }
+        ]),
+        Err(PatchError::NotInRom(0xBFFF))
+    );
+    assert_eq!(rom.data()[2], 0xA2);
+
+    let mut vm = Vm::new();
+    vm.load_rom(&rom).unwrap();
+    vm.step().unwrap();
+    vm.step().unwrap();
+    assert_eq!((vm.registers().a, vm.registers().x), (0x3F, 0x07));
+}
+
+#[test]
+fn freezes_are_stored_again_every_frame() {
+    // LSR $10 for longer than a frame.
+    let rom = Rom::new(0xC000, &[0x46, 0x10].repeat(0x1FFE)).unwrap();
+    let mut vm = Vm::new();
+    vm.load_rom(&rom).unwrap();
+    vm.freeze(0x10, 0x80);
+    vm.freeze(0x10, 0x40);
+    assert_eq!(vm.read(0x10), 0x40);
+    assert_eq!(
+        vm.freezes(),
+        [Patch {
+            addr: 0x10,
+            value: 0x40
+        }]
+    );
+
+    let writes = Arc::new(Mutex::new(Vec::new()));
+    let log = Arc::clone(&writes);
+    vm.on_write(0x10..=0x10, move |_, val| {
+        log.lock().unwrap().push(val);
+        val
+    });
+    vm.run_frame().unwrap();
+    assert_eq!(vm.read(0x10), 0);
+    vm.run_frame().unwrap();
+    // The second frame starts over from the frozen value.
+    let writes = writes.lock().unwrap();
+    assert_eq!(writes[..3], [0x20, 0x10, 0x08]);
+    assert_eq!(writes.iter().filter(|&&val| val == 0x20).count(), 2);
+
+    assert!(vm.unfreeze(0x10));
+    assert!(!vm.unfreeze(0x10));
+    assert!(vm.freezes().is_empty());
+}
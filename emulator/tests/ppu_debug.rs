@@ -0,0 +1,90 @@
+use emulator::Vm;
+use emulator::display::{
+    BG_PALETTE, CTRL, CTRL_BACKGROUND, MAP_HEIGHT, MAP_WIDTH, PALETTE, SCROLL_X, SPRITE_PALETTE,
+    SPRITES, TILE_DATA, TILEMAP,
+};
+use emulator::ppu_debug::{Palette, SHEET_COLUMNS, Sprite};
+
+/// Identity palette: pixel value N is shade N.
+const IDENTITY: u8 = 0b11_10_01_00;
+
+/// Tile 1 has a value 3 top row and a value 2 left column, leaving the
+/// rest 0.
+fn load_tile(vm: &mut Vm) {
+    let mut tile = [0; 16];
+    tile[0] = 0xFF;
+    tile[1] = 0xFF;
+    for row in 1..8 {
+        tile[row * 2 + 1] = 0x80;
+    }
+    vm.load(TILE_DATA + 16, &tile).unwrap();
+}
+
+#[test]
+fn decodes_tiles_sprites_and_palettes() {
+    let mut vm = Vm::new();
+    load_tile(&mut vm);
+    vm.load(SPRITES + 4, &[140, 7, 33, 0b10]).unwrap();
+    vm.write(BG_PALETTE, IDENTITY);
+    vm.write(SPRITE_PALETTE, 0b00_01_10_11);
+    vm.write(CTRL, CTRL_BACKGROUND);
+
+    let ppu = vm.ppu_debug();
+    let tile = ppu.tile(1);
+    assert_eq!(tile.pixels[0], [3; 8]);
+    assert_eq!(tile.pixels[5], [2, 0, 0, 0, 0, 0, 0, 0]);
+    // Indices wrap as they do when drawn.
+    assert_eq!(ppu.tile(33), tile);
+    assert_eq!(ppu.tiles().len(), 32);
+
+    assert_eq!(
+        ppu.sprite(1),
+        Sprite {
+            index: 1,
+            y: 140,
+            x: 7,
+            tile: 33,
+            attributes: 0b10,
+        }
+    );
+    let sprite = ppu.sprite(1);
+    assert!(sprite.flip_y() && !sprite.flip_x() && sprite.on_screen());
+    assert!(!Sprite { y: 144, ..sprite }.on_screen());
+    assert_eq!(ppu.sprites().len(), 16);
+
+    assert_eq!(ppu.background_palette().shades, [0, 1, 2, 3]);
+    assert_eq!(ppu.sprite_palette().shades, [3, 2, 1, 0]);
+    assert_eq!(ppu.sprite_palette().rgba(0), PALETTE[3]);
+    assert!(ppu.background_enabled() && !ppu.sprites_enabled());
+}
+
+#[test]
+fn views_the_tilemap() {
+    let mut vm = Vm::new();
+    load_tile(&mut vm);
+    vm.write(TILEMAP + MAP_WIDTH as u16 + 2, 1);
+    vm.write(SCROLL_X, 5);
+    vm.write(BG_PALETTE, IDENTITY);
+
+    let ppu = vm.ppu_debug();
+    let map = ppu.tilemap();
+    assert_eq!(map.cells.len(), MAP_HEIGHT);
+    assert_eq!(map.cells[1][2], 1);
+    assert_eq!(map.cells.iter().flatten().filter(|&&t| t != 0).count(), 1);
+    assert_eq!((map.scroll_x, map.scroll_y), (5, 0));
+
+    // Unscrolled, and drawn though the background is off.
+    let image = ppu.tilemap_rgba();
+    let at = |x: usize, y: usize| &image[(y * 160 + x) * 4..][..4];
+    assert_eq!(at(16, 8), PALETTE[3]);
+    assert_eq!(at(16, 9), PALETTE[2]);
+    assert_eq!(at(17, 9), PALETTE[0]);
+
+    let (sheet, width, height) = ppu.tile_sheet_rgba(Palette {
+        shades: [1, 1, 1, 3],
+    });
+    assert_eq!((width, height), (SHEET_COLUMNS * 8, 32));
+    assert_eq!(sheet.len(), width * height * 4);
+    assert_eq!(&sheet[8 * 4..][..4], PALETTE[3]);
+    assert_eq!(&sheet[..4], PALETTE[1]);
+}
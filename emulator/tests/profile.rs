@@ -0,0 +1,162 @@
+use emulator::profile::{Cost, Profile};
+use emulator::{Registers, ResetKind, SymbolTable, Vm, asm};
+
+/// `LDA #$01` (2 cycles) then `LDX $10` (3 cycles), repeated from `loop`.
+const SOURCE: &str = "
+        .org $8000
+start:  LDY #$00
+loop:   LDA #$01
+        LDX $10
+        LDA #$01
+        LDX $10
+        .org $FFFC
+        .word start
+";
+
+fn boot() -> Vm {
+    let mut vm = Vm::new();
+    vm.load_assembly(&asm::assemble(SOURCE).unwrap()).unwrap();
+    vm.reset(ResetKind::Soft);
+    vm
+}
+
+#[test]
+fn cycles_are_charged_per_address() {
+    let mut vm = boot();
+    vm.enable_profiling();
+    for _ in 0..5 {
+        vm.step().unwrap();
+    }
+    let profile = vm.profile().unwrap();
+    assert_eq!(
+        profile.cost(0x8000),
+        Cost {
+            cycles: 2,
+            count: 1
+        }
+    );
+    assert_eq!(
+        profile.cost(0x8004),
+        Cost {
+            cycles: 3,
+            count: 1
+        }
+    );
+    assert_eq!(
+        profile.cost(0x800A),
+        Cost {
+            cycles: 0,
+            count: 0
+        }
+    );
+    assert_eq!(profile.total_cycles(), 12);
+    assert_eq!(profile.total_count(), 5);
+    assert_eq!(
+        profile.hot()[..2],
+        [
+            (
+                0x8004,
+                Cost {
+                    cycles: 3,
+                    count: 1
+                }
+            ),
+            (
+                0x8008,
+                Cost {
+                    cycles: 3,
+                    count: 1
+                }
+            )
+        ]
+    );
+}
+
+#[test]
+fn run_frame_is_profiled_exactly() {
+    let mut vm = boot();
+    vm.load(0x8002, &[0xA9, 0x01, 0xA6, 0x10].repeat(0x1000))
+        .unwrap();
+    vm.enable_profiling();
+    vm.run_frame().unwrap();
+    let profile = vm.take_profile().unwrap();
+    assert_eq!(profile.total_cycles(), u64::from(vm.cycles()));
+    assert!(vm.profile().is_none());
+}
+
+#[test]
+fn interrupt_entry_is_counted_apart() {
+    let mut vm = boot();
+    vm.load(0xFFFE, &[0x02, 0x80]).unwrap();
+    vm.set_registers(Registers {
+        flags: 0,
+        ..vm.registers()
+    });
+    vm.raise_irq(0);
+    vm.step().unwrap();
+    vm.enable_profiling();
+    vm.step().unwrap();
+    let profile = vm.profile().unwrap();
+    assert_eq!(profile.interrupt_cycles(), 7);
+    assert_eq!(profile.total_count(), 0);
+}
+
+#[test]
+fn report_rolls_up_by_symbol() {
+    let mut vm = boot();
+    vm.set_symbols(SymbolTable::from(&asm::assemble(SOURCE).unwrap()));
+    assert!(vm.profile_report().is_none());
+    vm.enable_profiling();
+    for _ in 0..5 {
+        vm.step().unwrap();
+    }
+    let report = vm.profile_report().unwrap();
+    let lines: Vec<_> = report.lines().collect();
+    assert_eq!(
+        lines[..3],
+        [
+            "; 12 cycles in 5 instructions, 0 cycles entering interrupts",
+            "    cycles      %    count  address",
+            "         3  25.0%        1  8004  loop+2",
+        ]
+    );
+    assert!(report.ends_with(
+        "    cycles      %    count  symbol\n        10  83.3%        4  loop\n         2  16.7%        1  start\n"
+    ));
+
+    // Without symbols only the addresses are listed.
+    let plain = vm.profile().unwrap().report(None);
+    assert!(plain.contains("         3  25.0%        1  8004\n"));
+    assert!(!plain.contains("symbol"));
+}
+
+#[test]
+fn profiles_merge() {
+    let mut vm = boot();
+    vm.enable_profiling();
+    vm.step().unwrap();
+    let mut total = Profile::new();
+    total.merge(vm.profile().unwrap());
+    total.merge(vm.profile().unwrap());
+    assert_eq!(
+        total.cost(0x8000),
+        Cost {
+            cycles: 4,
+            count: 2
+        }
+    );
+}
+
+#[test]
+fn rewound_frames_are_not_profiled_again() {
+    let mut vm = boot();
+    vm.load(0x8002, &[0xA9, 0x01, 0xA6, 0x10].repeat(0x1FF0))
+        .unwrap();
+    vm.enable_rewind(4, 1);
+    vm.enable_profiling();
+    vm.run_frame().unwrap();
+    vm.run_frame().unwrap();
+    let before = vm.profile().unwrap().total_cycles();
+    assert_eq!(vm.rewind(1).unwrap(), 1);
+    assert_eq!(vm.profile().unwrap().total_cycles(), before);
+}
@@ -0,0 +1,138 @@
+#![cfg(feature = "python")]
+
+use std::ffi::CString;
+use std::sync::Once;
+
+use emulator::python::rvm8;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+/// Defined before every script: the module and an `.rvm` image builder.
+const PRELUDE: &str = r#"
+import gc
+import struct
+import zlib
+
+import rvm8
+
+def rom(entry, data):
+    banks = -(-len(data) // 0x4000)
+    data += bytes(banks * 0x4000 - len(data))
+    return b"RVM8" + struct.pack("<BBHI4x", 1, banks, entry, zlib.crc32(data)) + data
+"#;
+
+/// Runs `script` after the prelude in fresh globals, panicking with the
+/// traceback printed if it raises.
+fn run(script: &str) {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        pyo3::append_to_inittab!(rvm8);
+        Python::initialize();
+    });
+    Python::attach(|py| {
+        let code = CString::new(format!("{PRELUDE}\n{script}")).unwrap();
+        let globals = PyDict::new(py);
+        if let Err(err) = py.run(&code, Some(&globals), None) {
+            err.display(py);
+            panic!("{err}");
+        }
+    });
+}
+
+#[test]
+fn steps_a_rom() {
+    run(r#"
+vm = rvm8.Vm()
+vm.load_rom(rom(0x8000, bytes([0xA9]) * 0x8000))
+assert vm.registers.pc == 0x8000
+vm.step()
+regs = vm.registers
+assert (regs.a, regs.pc) == (0xA9, 0x8002)
+assert vm.cycles == 2
+vm.run_cycles(4)
+assert vm.cycles == 6
+vm.run_frame()
+assert vm.frame == 1
+"#);
+}
+
+#[test]
+fn exposes_memory_in_place() {
+    run(r#"
+vm = rvm8.Vm()
+vm.load(0x0200, b"\x01\x02\x03")
+assert bytes(vm.memory[0x0200:0x0203]) == b"\x01\x02\x03"
+vm.memory[0x0300] = 9
+assert vm.read(0x0300) == 9
+vm.write(0x0301, 7)
+assert vm.memory[0x0301] == 7
+assert len(vm.memory) == rvm8.MEMORY_SIZE
+"#);
+}
+
+#[test]
+fn exposes_the_framebuffer() {
+    run(r#"
+pixels = rvm8.Vm().framebuffer
+assert pixels.shape == (rvm8.HEIGHT, rvm8.WIDTH, 4)
+assert pixels.readonly
+assert pixels.nbytes == rvm8.WIDTH * rvm8.HEIGHT * 4
+"#);
+}
+
+#[test]
+fn sets_registers() {
+    run(r#"
+vm = rvm8.Vm()
+regs = rvm8.Registers(a=1, x=2, y=3, flags=4, pc=0x1234, sp=0x01F0)
+vm.registers = regs
+assert vm.registers == regs
+assert repr(vm.registers) == "Registers(a=0x01, x=0x02, y=0x03, flags=0x04, pc=0x1234, sp=0x01F0)"
+"#);
+}
+
+#[test]
+fn raises_on_errors() {
+    run(r#"
+vm = rvm8.Vm()
+def raises(call):
+    try:
+        call()
+    except rvm8.VmError as err:
+        return str(err)
+    raise AssertionError("no VmError")
+
+assert raises(vm.step).startswith("illegal opcode")
+raises(lambda: vm.load_rom(b"RVM8"))
+raises(lambda: vm.load(0xFFFF, b"\x01\x02"))
+raises(lambda: vm.load_state(b"nope"))
+"#);
+}
+
+#[test]
+fn save_states_round_trip() {
+    run(r#"
+vm = rvm8.Vm()
+vm.load_rom(rom(0x8000, bytes([0xA9]) * 0x8000))
+vm.set_buttons(rvm8.BUTTON_A | rvm8.BUTTON_START)
+state = vm.save_state()
+vm.run_frame()
+after = bytes(vm.memory)
+vm.load_state(state)
+assert vm.frame == 0
+vm.run_frame()
+assert bytes(vm.memory) == after
+"#);
+}
+
+#[test]
+fn views_outlive_the_machine() {
+    run(r#"
+vm = rvm8.Vm()
+memory = vm.memory
+vm.write(0x0200, 5)
+del vm
+gc.collect()
+assert memory[0x0200] == 5
+"#);
+}
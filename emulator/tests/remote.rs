@@ -0,0 +1,129 @@
+#![cfg(feature = "remote")]
+
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use common::vm_with;
+use emulator::Vm;
+use emulator::ffi::RVM_MEM_SIZE;
+use emulator::input::CONTROLLER;
+use emulator::remote::RemoteControl;
+
+fn temp(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rvm8-remote-{}-{name}", std::process::id()))
+}
+
+/// How long a client waits for a reply before polling the server again.
+const WAIT: Option<Duration> = Some(Duration::from_millis(5));
+
+/// Sends `command` and polls the server until the reply line arrives.
+fn send<S: Read + Write>(
+    remote: &mut RemoteControl,
+    vm: &mut Vm,
+    client: &mut BufReader<S>,
+    command: &str,
+) -> String {
+    writeln!(client.get_mut(), "{command}").unwrap();
+    let mut reply = String::new();
+    while !reply.ends_with('\n') {
+        remote.poll(vm).unwrap();
+        if let Err(err) = client.read_line(&mut reply) {
+            assert!(matches!(
+                err.kind(),
+                std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+            ));
+        }
+    }
+    reply.trim_end().to_string()
+}
+
+fn connect(remote: &RemoteControl) -> BufReader<TcpStream> {
+    let stream = TcpStream::connect(remote.local_addr().unwrap()).unwrap();
+    stream.set_read_timeout(WAIT).unwrap();
+    BufReader::new(stream)
+}
+
+#[test]
+fn pauses_steps_and_reads_memory() {
+    let mut vm = vm_with(&[0xA9, 0x42, 0xA2, 0x01]);
+    let mut remote = RemoteControl::bind_tcp("127.0.0.1:0").unwrap();
+    let mut client = connect(&remote);
+    let mut send = |command: &str| send(&mut remote, &mut vm, &mut client, command);
+
+    assert_eq!(send("status"), "ok running frame=0 pc=$8000");
+    assert_eq!(send("pause"), "ok");
+    assert_eq!(send("status"), "ok paused frame=0 pc=$8000");
+    assert_eq!(send("step 2"), "ok pc=$8004");
+    assert_eq!(send("regs"), "ok a=$42 x=$01 y=$00 p=$04 sp=$FD pc=$8004");
+    assert_eq!(send("peek $8000 3"), "ok A9 42 A2");
+    assert_eq!(send("poke 0x1000 1 2 $FF"), "ok");
+    assert_eq!(send("peek $1000 4"), "ok 01 02 FF 00");
+    assert_eq!(send("peek $FFFF 4"), "ok 00");
+    assert_eq!(send("poke $1000 256"), "err `256` is not a byte");
+    assert_eq!(send("peek"), "err usage: peek <addr> [len]");
+    assert_eq!(send("peek nowhere"), "err `nowhere` is not a 16-bit number");
+    assert_eq!(send("rewind"), "err unknown command `rewind`");
+    assert_eq!(send("resume"), "ok");
+    assert_eq!(send("quit"), "ok");
+
+    assert!(!remote.is_paused());
+    assert_eq!(remote.clients(), 0);
+    assert_eq!(vm.read(0x1002), 0xFF);
+}
+
+#[test]
+fn saves_loads_and_screenshots() {
+    let (state, png) = (temp("state"), temp("shot.png"));
+    let mut vm = vm_with(&[0xA9, 0x42]);
+    let mut remote = RemoteControl::bind_tcp("127.0.0.1:0").unwrap();
+    let mut client = connect(&remote);
+    let mut send = |vm: &mut Vm, command: &str| send(&mut remote, vm, &mut client, command);
+
+    assert_eq!(send(&mut vm, &format!("save {}", state.display())), "ok");
+    assert_eq!(send(&mut vm, "step"), "ok pc=$8002");
+    assert_eq!(send(&mut vm, "poke $0200 7"), "ok");
+    assert_eq!(send(&mut vm, &format!("load {}", state.display())), "ok");
+    assert_eq!(vm.registers().pc, 0x8000);
+    assert_eq!(vm.read(0x0200), 0);
+    assert!(send(&mut vm, "load /nonexistent/state").starts_with("err /nonexistent/state: "));
+    assert_eq!(send(&mut vm, "save"), "err usage: save <path>");
+
+    assert_eq!(
+        send(&mut vm, &format!("screenshot {}", png.display())),
+        "ok"
+    );
+    assert!(
+        std::fs::read(&png)
+            .unwrap()
+            .starts_with(b"\x89PNG\r\n\x1a\n")
+    );
+    std::fs::remove_file(state).unwrap();
+    std::fs::remove_file(png).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn serves_a_unix_socket() {
+    use std::os::unix::net::UnixStream;
+
+    let path = temp("socket");
+    // `LDA #$A9` over the whole address space runs forever on odd addresses.
+    let mut vm = Vm::new();
+    vm.bus_mut().unmap(CONTROLLER);
+    vm.load(0, &vec![0xA9; RVM_MEM_SIZE]).unwrap();
+    let mut remote = RemoteControl::bind_unix(&path).unwrap();
+    assert_eq!(remote.local_addr(), None);
+    let stream = UnixStream::connect(&path).unwrap();
+    stream.set_read_timeout(WAIT).unwrap();
+    let mut client = BufReader::new(stream);
+    assert_eq!(
+        send(&mut remote, &mut vm, &mut client, "frame 2"),
+        "ok frame=2"
+    );
+    drop(remote);
+    assert!(!path.exists());
+}
@@ -0,0 +1,209 @@
+mod common;
+
+use std::path::PathBuf;
+
+use common::vm_with;
+use emulator::input::{CONTROLLER, Controller};
+use emulator::replay::{InputEvent, MAGIC, Recording, ReplayError, VERSION};
+use emulator::{Button, ResetKind, Snapshot, Vm};
+
+/// A program that keeps latching the controller and adding the eight button
+/// bits into A: `LSR $2500` strobes, then eight `ADC $2500` shift them in.
+fn vm() -> Vm {
+    let mut block = vec![0x4E, 0x00, 0x25];
+    for _ in 0..8 {
+        block.extend_from_slice(&[0x6D, 0x00, 0x25]);
+    }
+    vm_with(&block.repeat(1000))
+}
+
+fn path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rvm8-replay-{}-{name}", std::process::id()))
+}
+
+/// Machine state after each of `steps` instructions, with host inputs
+/// applied by `input` before each step.
+fn run(vm: &mut Vm, steps: u64, mut input: impl FnMut(&mut Vm, u64)) -> Vec<(Snapshot, u8, u8)> {
+    (0..steps)
+        .map(|i| {
+            input(vm, i);
+            vm.step().unwrap();
+            (vm.save_state(), vm.buttons(), vm.pending_irqs())
+        })
+        .collect()
+}
+
+fn inputs(vm: &mut Vm, step: u64) {
+    match step {
+        3 => vm.set_button(Button::A, true),
+        10 => vm.set_buttons(Button::Up.mask() | Button::B.mask()),
+        12 => vm.raise_irq(5),
+        20 => vm.set_irq_mask(0x0F),
+        25 => vm.ack_irq(5),
+        30 => vm.reset(ResetKind::Soft),
+        40 => vm.set_buttons(0),
+        _ => {}
+    }
+}
+
+#[test]
+fn replay_reproduces_the_recorded_run() {
+    let file = path("run");
+    let mut vm = vm();
+    for _ in 0..7 {
+        vm.step().unwrap();
+    }
+    vm.record_inputs(&file).unwrap();
+    assert!(vm.is_recording());
+    let recorded = run(&mut vm, 60, inputs);
+    vm.stop_recording().unwrap();
+    assert!(!vm.is_recording());
+
+    // A fresh machine needs nothing but the file.
+    let mut replayed = Vm::new();
+    replayed.replay_inputs(&file).unwrap();
+    assert!(replayed.is_replaying());
+    // Compared without assert_eq! to keep 64 KiB dumps out of failures.
+    let diverged = run(&mut replayed, 60, |_, _| {})
+        .iter()
+        .zip(&recorded)
+        .position(|(a, b)| a != b);
+    assert_eq!(diverged, None);
+    assert!(!replayed.is_replaying());
+    std::fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn recording_holds_only_changes() {
+    let file = path("changes");
+    let mut vm = vm();
+    vm.record_inputs(&file).unwrap();
+    vm.set_button(Button::A, true);
+    vm.set_button(Button::A, true);
+    vm.step().unwrap();
+    vm.raise_irq(1);
+    vm.raise_irq(1);
+    vm.ack_irq(2);
+    vm.step().unwrap();
+    vm.reset(ResetKind::Soft);
+    vm.reset(ResetKind::Hard);
+    vm.stop_recording().unwrap();
+
+    let recording = Recording::from_file(&file).unwrap();
+    assert_eq!(
+        recording.events,
+        [
+            (0, InputEvent::Buttons(Button::A.mask())),
+            (1, InputEvent::RaiseIrq(1)),
+            (2, InputEvent::Reset),
+            (2, InputEvent::HardReset),
+        ]
+    );
+    assert_eq!(recording.start.registers.pc, 0x8000);
+    std::fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn host_inputs_are_ignored_while_replaying() {
+    let mut vm = vm();
+    let recording = Recording {
+        start: vm.save_state(),
+        controller: Controller::default(),
+        events: vec![(0, InputEvent::Buttons(3)), (2, InputEvent::Buttons(0))],
+    };
+    vm.replay_recording(recording);
+    assert_eq!(vm.buttons(), 3);
+
+    vm.set_buttons(0xFF);
+    vm.raise_irq(0);
+    vm.reset(ResetKind::Soft);
+    vm.step().unwrap();
+    assert_eq!(vm.buttons(), 3);
+    assert_eq!(vm.pending_irqs(), 0);
+    assert_eq!(vm.registers().pc, 0x8003);
+
+    // The last event ends the replay and hands input back.
+    vm.step().unwrap();
+    vm.step().unwrap();
+    assert!(!vm.is_replaying());
+    vm.set_buttons(0xFF);
+    assert_eq!(vm.buttons(), 0xFF);
+}
+
+#[test]
+fn stop_replay_gives_input_back() {
+    let mut vm = vm();
+    vm.replay_recording(Recording {
+        start: vm.save_state(),
+        controller: Controller::default(),
+        events: vec![(100, InputEvent::Reset)],
+    });
+    vm.stop_replay();
+    vm.set_buttons(1);
+    assert_eq!(vm.buttons(), 1);
+}
+
+#[test]
+fn recordings_round_trip() {
+    let recording = Recording {
+        start: vm().save_state(),
+        controller: vm().bus().device::<Controller>(CONTROLLER).unwrap().clone(),
+        events: vec![
+            (0, InputEvent::Buttons(0x81)),
+            (7, InputEvent::RaiseIrq(7)),
+            (7, InputEvent::AckIrq(0)),
+            (1 << 40, InputEvent::IrqMask(0x55)),
+            (u64::MAX, InputEvent::Reset),
+        ],
+    };
+    assert_eq!(
+        Recording::from_bytes(&recording.to_bytes()).unwrap(),
+        recording
+    );
+}
+
+#[test]
+fn malformed_recordings_are_rejected() {
+    let bytes = Recording {
+        start: vm().save_state(),
+        controller: Controller::default(),
+        events: vec![(0, InputEvent::Buttons(1))],
+    }
+    .to_bytes();
+    let header = bytes.len() - 10;
+
+    assert!(matches!(
+        Recording::from_bytes(&bytes[..header - 1]),
+        Err(ReplayError::Truncated)
+    ));
+    assert!(matches!(
+        Recording::from_bytes(&bytes[..bytes.len() - 1]),
+        Err(ReplayError::Truncated)
+    ));
+
+    let mut bad = bytes.clone();
+    bad[0] ^= 0xFF;
+    assert!(matches!(
+        Recording::from_bytes(&bad),
+        Err(ReplayError::BadMagic)
+    ));
+
+    let mut bad = bytes.clone();
+    bad[MAGIC.len()] = VERSION + 1;
+    assert!(matches!(
+        Recording::from_bytes(&bad),
+        Err(ReplayError::UnsupportedVersion(v)) if v == VERSION + 1
+    ));
+
+    let mut bad = bytes.clone();
+    bad[header + 8..].copy_from_slice(&[1, 8]);
+    assert!(matches!(
+        Recording::from_bytes(&bad),
+        Err(ReplayError::BadEvent { offset }) if offset == header
+    ));
+
+    assert!(matches!(
+        Recording::from_file(path("missing")),
+        Err(ReplayError::Io(_))
+    ));
+}
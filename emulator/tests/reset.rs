@@ -0,0 +1,105 @@
+use std::fs;
+
+use emulator::mapper::{BankRegisters, MAPPER_PORTS, RAM_WINDOW};
+use emulator::rng::{RNG_PORTS, Rng};
+use emulator::rom::BANK_SIZE;
+use emulator::timer::{CTRL_ENABLE, TIMER_BASE, Timer, timer_ports};
+use emulator::uart::{UART_PORTS, Uart};
+use emulator::{BusDevice, Mapper, ResetKind, Rom, Vm};
+
+#[test]
+fn hard_resets_clear_ram_but_not_rom_or_save_ram() {
+    let path = std::env::temp_dir().join(format!("rvm8-reset-{}.sav", std::process::id()));
+    let _ = fs::remove_file(&path);
+    let mut vm = Vm::new();
+    vm.load_rom(&Rom::new(0xC000, &[0xA9, 0x01]).unwrap())
+        .unwrap();
+    vm.attach_sram(0x6000..=0x60FF, &path).unwrap();
+    vm.write(0x0010, 0x11);
+    vm.write(0x6000, 0x22);
+    vm.step().unwrap();
+
+    vm.reset(ResetKind::Soft);
+    assert_eq!(vm.registers().pc, 0xC000);
+    assert_eq!(vm.read(0x0010), 0x11, "a soft reset keeps RAM");
+
+    vm.step().unwrap();
+    vm.reset(ResetKind::Hard);
+    assert_eq!(vm.registers().pc, 0xC000);
+    assert_eq!(vm.read(0x0010), 0);
+    assert_eq!(vm.read(0x6000), 0x22, "save RAM is battery-backed");
+    assert_eq!(vm.read(0xC000), 0xA9);
+    assert_eq!(vm.read(0xFFFD), 0xC0, "the reset vector is ROM");
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 0x01);
+
+    vm.detach_sram().unwrap();
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn devices_return_to_their_power_on_registers() {
+    let mut vm = Vm::new();
+    vm.bus_mut().map(timer_ports(0), Timer::default()).unwrap();
+    vm.bus_mut().map(UART_PORTS, Uart::new()).unwrap();
+    vm.bus_mut().map(RNG_PORTS, Rng::new(7)).unwrap();
+    let timer = vm.bus_mut().device_mut::<Timer>(TIMER_BASE).unwrap();
+    timer.write8(2, 0x34);
+    timer.write8(5, CTRL_ENABLE);
+    let uart = vm.bus_mut().device_mut::<Uart>(0x2720).unwrap();
+    uart.write8(2, 1);
+    uart.push_rx(b"hi");
+    let rng = vm.bus_mut().device_mut::<Rng>(0x2780).unwrap();
+    let first = rng.next_byte();
+    rng.write8(1, 0x55);
+
+    vm.reset(ResetKind::Soft);
+    let timer = vm.bus_mut().device_mut::<Timer>(TIMER_BASE).unwrap();
+    assert!(!timer.running());
+    assert_eq!([timer.read8(2), timer.read8(5)], [0, 0]);
+    let uart = vm.bus_mut().device_mut::<Uart>(0x2720).unwrap();
+    assert_eq!(uart.read8(2), 0);
+    assert_eq!(uart.read8(0), b'h', "a soft reset keeps what was received");
+    let rng = vm.bus_mut().device_mut::<Rng>(0x2780).unwrap();
+    let state = rng.state();
+    rng.write8(4, (state >> 24) as u8);
+    assert_eq!(rng.state(), state, "what the program latched is gone");
+    assert_ne!(rng.next_byte(), first, "a soft reset runs on");
+
+    vm.reset(ResetKind::Hard);
+    let uart = vm.bus_mut().device_mut::<Uart>(0x2720).unwrap();
+    assert_eq!(uart.read8(1) & 1, 0, "a hard reset drops what was received");
+    let rng = vm.bus().device::<Rng>(0x2780).unwrap();
+    assert_eq!(rng.state(), state, "back to the seed last loaded");
+}
+
+#[test]
+fn resets_switch_the_first_banks_back_in() {
+    // LDA #$00, twice, in the fixed bank.
+    let mut data = vec![0; 2 * BANK_SIZE];
+    data[BANK_SIZE..][..4].copy_from_slice(&[0xA9, 0x00, 0xA9, 0x00]);
+    let rom = Rom::with_mapper(0xC000, &data, Mapper::BankedRam { ram_banks: 2 }).unwrap();
+    let mut vm = Vm::new();
+    vm.load_rom(&rom).unwrap();
+    vm.write(*RAM_WINDOW.start(), 0x11);
+    let registers = vm
+        .bus_mut()
+        .device_mut::<BankRegisters>(*MAPPER_PORTS.start());
+    registers.unwrap().write8(1, 1);
+    vm.step().unwrap();
+    vm.write(*RAM_WINDOW.start(), 0x22);
+
+    vm.reset(ResetKind::Soft);
+    let registers = vm.bus().device::<BankRegisters>(*MAPPER_PORTS.start());
+    assert_eq!(registers.unwrap().ram_bank(), 0);
+    assert_eq!(vm.read(*RAM_WINDOW.start()), 0x11, "bank 0 is back");
+
+    vm.reset(ResetKind::Hard);
+    assert_eq!(vm.read(*RAM_WINDOW.start()), 0);
+    let registers = vm
+        .bus_mut()
+        .device_mut::<BankRegisters>(*MAPPER_PORTS.start());
+    registers.unwrap().write8(1, 1);
+    vm.step().unwrap();
+    assert_eq!(vm.read(*RAM_WINDOW.start()), 0, "every RAM bank is cleared");
+}
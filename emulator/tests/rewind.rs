@@ -0,0 +1,89 @@
+use emulator::ffi::RVM_MEM_SIZE;
+use emulator::input::CONTROLLER;
+use emulator::{Registers, Snapshot, Vm};
+
+/// A machine that runs forever: memory is filled with `LDA #$A9`, wrapping
+/// around the address space, except for an `LSR $F1` at 0x0000 that keeps
+/// shifting the operand of the `LDA` at 0x00F0. The controller is unmapped
+/// so the input registers execute as RAM too.
+fn looping_vm() -> Vm {
+    let mut image = vec![0xA9; RVM_MEM_SIZE];
+    image[..2].copy_from_slice(&[0x46, 0xF1]);
+    image[0xF1] = 0xFF;
+    let mut vm = Vm::new();
+    vm.bus_mut().unmap(CONTROLLER);
+    vm.load(0, &image).unwrap();
+    vm.set_registers(Registers {
+        pc: 0,
+        ..vm.registers()
+    });
+    vm
+}
+
+fn run_frames(vm: &mut Vm, frames: u64) -> Vec<Snapshot> {
+    (0..frames)
+        .map(|_| {
+            vm.run_frame().unwrap();
+            vm.save_state()
+        })
+        .collect()
+}
+
+#[test]
+fn rewind_restores_an_earlier_frame() {
+    let mut vm = looping_vm();
+    vm.enable_rewind(60, 1);
+    let states = run_frames(&mut vm, 10);
+    assert_ne!(states[3].memory, states[9].memory);
+
+    assert_eq!(vm.rewind(6), Ok(6));
+    assert_eq!(vm.frame(), 4);
+    assert_eq!(vm.save_state(), states[3]);
+
+    // History after the new position is gone, and running forward again
+    // reproduces the same timeline.
+    assert_eq!(run_frames(&mut vm, 6), states[4..]);
+}
+
+#[test]
+fn rewind_replays_frames_between_snapshots() {
+    let mut vm = looping_vm();
+    vm.enable_rewind(16, 4);
+    let states = run_frames(&mut vm, 10);
+    assert_eq!(vm.rewind_buffer().unwrap().len(), 3);
+
+    assert_eq!(vm.rewind(3), Ok(3));
+    assert_eq!(vm.save_state(), states[6]);
+}
+
+#[test]
+fn history_is_bounded_by_capacity() {
+    let mut vm = looping_vm();
+    vm.enable_rewind(5, 1);
+    let states = run_frames(&mut vm, 20);
+    let buffer = vm.rewind_buffer().unwrap();
+    assert_eq!(buffer.len(), 5);
+    assert_eq!(buffer.oldest_frame(), Some(16));
+
+    assert_eq!(vm.rewind(100), Ok(4));
+    assert_eq!(vm.save_state(), states[15]);
+}
+
+#[test]
+fn snapshots_are_delta_compressed() {
+    let mut vm = looping_vm();
+    vm.enable_rewind(300, 1);
+    run_frames(&mut vm, 300);
+    let buffer = vm.rewind_buffer().unwrap();
+    assert_eq!(buffer.len(), 300);
+    assert!(buffer.memory_usage() < 2 * RVM_MEM_SIZE);
+}
+
+#[test]
+fn rewind_is_a_no_op_when_disabled() {
+    let mut vm = looping_vm();
+    run_frames(&mut vm, 3);
+    let before = vm.save_state();
+    assert_eq!(vm.rewind(2), Ok(0));
+    assert_eq!(vm.save_state(), before);
+}
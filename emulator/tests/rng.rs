@@ -0,0 +1,75 @@
+use emulator::config::{DeviceConfig, MachineConfig};
+use emulator::rng::{RNG_PORTS, RNG_SEED, Rng};
+use emulator::{BusDevice, ResetKind, Vm};
+
+#[test]
+fn follows_xorshift32() {
+    // The first outputs Marsaglia lists for his example seed.
+    let mut rng = Rng::default();
+    assert_eq!(rng.next_byte(), 0x2B);
+    assert_eq!(rng.state(), 723_471_715);
+    assert_eq!(rng.read8(0), 0x94);
+    assert_eq!(rng.state(), 0x94DA_CB7A);
+    assert_eq!(
+        [rng.read8(1), rng.read8(2), rng.read8(3), rng.read8(4)],
+        [0x7A, 0xCB, 0xDA, 0x94]
+    );
+    assert_eq!(rng.state(), 0x94DA_CB7A, "reading SEED does not step");
+
+    assert_eq!(Rng::new(0), Rng::new(RNG_SEED));
+    let mut again = Rng::new(rng.state());
+    assert_eq!(again.next_byte(), rng.next_byte());
+}
+
+#[test]
+fn programs_read_and_seed_it() {
+    let mut vm = Vm::new();
+    vm.bus_mut().map(RNG_PORTS, Rng::new(7)).unwrap();
+    // LDA $2780; LDX $2780; LDY $2780
+    let program = [0xAD, 0x80, 0x27, 0xAE, 0x80, 0x27, 0xAC, 0x80, 0x27, 0x02];
+    let run = |vm: &mut Vm| {
+        vm.load(0x8000, &program).unwrap();
+        vm.load(0xFFFC, &[0x00, 0x80]).unwrap();
+        vm.reset(ResetKind::Soft);
+        for _ in 0..3 {
+            vm.step().unwrap();
+        }
+        let regs = vm.registers();
+        [regs.a, regs.x, regs.y]
+    };
+
+    assert_eq!(vm.seed_rng(42), 1);
+    assert_eq!(run(&mut vm), [0x00, 0xA9, 0x1C]);
+    assert_eq!(vm.seed_rng(42), 1);
+    assert_eq!(run(&mut vm), [0x00, 0xA9, 0x1C], "the same seed repeats");
+
+    // A program seeds through the latch, the top byte last.
+    let rng = vm.bus_mut().device_mut::<Rng>(0x2780).unwrap();
+    for (offset, byte) in (1..).zip(42u32.to_le_bytes()) {
+        rng.write8(offset, byte);
+    }
+    assert_eq!(rng.state(), 42);
+    rng.write8(1, 9);
+    assert_eq!(rng.state(), 42, "only the top byte loads the seed");
+    assert_eq!(Vm::new().seed_rng(1), 0);
+}
+
+#[test]
+fn configs_map_a_seeded_rng() {
+    let config = MachineConfig::from_toml(
+        "[[ram]]\nstart = 0\nend = 0xFFFF\n[[device]]\nkind = \"rng\"\nseed = 0x1234\n",
+    )
+    .unwrap();
+    assert_eq!(
+        config.devices,
+        [DeviceConfig::Rng {
+            start: None,
+            seed: 0x1234,
+        }]
+    );
+    let vm = Vm::with_config(&config).unwrap();
+    assert_eq!(
+        vm.bus().device::<Rng>(*RNG_PORTS.end()).map(Rng::state),
+        Some(0x1234)
+    );
+}
@@ -0,0 +1,154 @@
+use emulator::rom::{BANK_SIZE, HEADER_SIZE, crc32, sha256};
+use emulator::{ReloadPolicy, Rom, RomError, Vm};
+
+#[test]
+fn crc32_matches_the_reference_value() {
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+}
+
+fn hex(digest: [u8; 32]) -> String {
+    digest.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[test]
+fn sha256_matches_the_reference_values() {
+    assert_eq!(
+        hex(sha256(b"")),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+    assert_eq!(
+        hex(sha256(b"abc")),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    // Long enough that the padding needs a block of its own.
+    assert_eq!(
+        hex(sha256(&[b'a'; 56])),
+        "b35439a4ac6f0948b6d6f9e3c6af0f5f590ce20f1bde7090ef7970686ec6738a"
+    );
+}
+
+#[test]
+fn info_identifies_the_build() {
+    let rom = Rom::new(0xC000, &[0xA9, 0x42]).unwrap();
+    let info = rom.info();
+    let bytes = rom.to_bytes();
+    assert_eq!(info.sha256, sha256(&bytes));
+    assert_eq!(info.crc32.to_le_bytes(), bytes[8..12]);
+    assert_eq!(
+        info.to_string(),
+        format!("crc32 {:08x} sha256 {}", info.crc32, hex(info.sha256))
+    );
+
+    let other = Rom::new(0xC000, &[0xA9, 0x43]).unwrap();
+    assert_ne!(other.info(), info);
+
+    let mut vm = Vm::new();
+    assert_eq!(vm.rom_info(), None);
+    vm.load_rom(&rom).unwrap();
+    assert_eq!(vm.rom_info(), Some(info));
+    vm.reload_rom(&other.to_bytes(), ReloadPolicy::default())
+        .unwrap();
+    assert_eq!(vm.rom_info(), Some(other.info()));
+}
+
+#[test]
+fn round_trips_through_bytes() {
+    let rom = Rom::new(0xC000, &[0xA9, 0x42]).unwrap();
+    assert_eq!(rom.banks(), 1);
+    let bytes = rom.to_bytes();
+    assert_eq!(bytes.len(), HEADER_SIZE + BANK_SIZE);
+    assert_eq!(&bytes[..4], b"RVM8");
+    assert_eq!(Rom::from_bytes(&bytes).unwrap(), rom);
+}
+
+#[test]
+fn rejects_malformed_images() {
+    let good = Rom::new(0xC000, &[0xA9, 0x42]).unwrap().to_bytes();
+
+    assert!(matches!(
+        Rom::from_bytes(&good[..8]),
+        Err(RomError::Truncated)
+    ));
+
+    let mut bad = good.clone();
+    bad[0] = b'X';
+    assert!(matches!(Rom::from_bytes(&bad), Err(RomError::BadMagic)));
+
+    let mut bad = good.clone();
+    bad[4] = 9;
+    assert!(matches!(
+        Rom::from_bytes(&bad),
+        Err(RomError::UnsupportedVersion(9))
+    ));
+
+    let mut bad = good.clone();
+    bad[5] = 2;
+    assert!(matches!(
+        Rom::from_bytes(&bad),
+        Err(RomError::SizeMismatch { .. })
+    ));
+
+    let mut bad = good.clone();
+    bad[HEADER_SIZE + 1] ^= 0xFF;
+    assert!(matches!(
+        Rom::from_bytes(&bad),
+        Err(RomError::ChecksumMismatch { .. })
+    ));
+
+    assert!(matches!(
+        Rom::new(0x8000, &vec![0; 3 * BANK_SIZE]),
+        Err(RomError::BadBankCount(3))
+    ));
+}
+
+#[test]
+fn load_rom_maps_protects_and_starts_at_entry() {
+    // Two banks from 0x8000; the code at 0x8010 is LSR $8000.
+    let mut data = vec![0; 2 * BANK_SIZE];
+    data[0] = 0x80;
+    data[0x10..0x13].copy_from_slice(&[0x4E, 0x00, 0x80]);
+    let rom = Rom::new(0x8010, &data).unwrap();
+
+    let mut vm = Vm::new();
+    vm.load_rom(&rom).unwrap();
+    assert_eq!(rom.base(), 0x8000);
+    assert_eq!(vm.registers().pc, 0x8010);
+    assert_eq!(vm.read(0xFFFC), 0x10);
+
+    vm.step().unwrap();
+    assert_eq!(vm.read(0x8000), 0x80);
+}
+
+#[test]
+fn reload_rom_swaps_code_under_a_running_program() {
+    // LDA #$01; LDA #$02, then LDA #$01; LDA #$07.
+    let old = Rom::new(0xC000, &[0xA9, 0x01, 0xA9, 0x02]).unwrap();
+    let new = Rom::new(0xC000, &[0xA9, 0x01, 0xA9, 0x07]).unwrap();
+    let mut vm = Vm::new();
+    vm.load_rom(&old).unwrap();
+    vm.step().unwrap();
+    vm.write(0x0200, 0x55);
+
+    vm.reload_rom(&new.to_bytes(), ReloadPolicy::default())
+        .unwrap();
+    assert_eq!(vm.registers().pc, 0xC002);
+    assert_eq!(vm.read(0x0200), 0x55);
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 0x07);
+    assert_eq!(vm.cycles(), 4, "no reset");
+
+    assert!(matches!(
+        vm.reload_rom(&[0; 4], ReloadPolicy::default()),
+        Err(RomError::Truncated)
+    ));
+    assert_eq!(vm.read(0xC003), 0x07);
+
+    let fresh = ReloadPolicy {
+        keep_ram: false,
+        keep_registers: false,
+    };
+    vm.reload_rom(&old.to_bytes(), fresh).unwrap();
+    assert_eq!(vm.registers().pc, 0xC000);
+    assert_eq!(vm.read(0x0200), 0x00);
+    assert_eq!(vm.read(0xC003), 0x02);
+}
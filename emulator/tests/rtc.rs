@@ -0,0 +1,145 @@
+mod common;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use common::vm_with;
+use emulator::config::{DeviceConfig, MachineConfig};
+use emulator::rtc::{RTC_EPOCH, RTC_PORTS, Rtc, RtcMode};
+use emulator::{BusDevice, Vm};
+
+/// 2024-02-28 23:59:58 UTC, a Wednesday.
+const LEAP_EVE: i64 = 1_709_164_798;
+
+fn registers(rtc: &mut Rtc) -> [u8; 8] {
+    let mut regs = [rtc.read8(0), 0, 0, 0, 0, 0, 0, 0];
+    for (offset, reg) in regs.iter_mut().enumerate().skip(1) {
+        *reg = rtc.read8(offset as u16);
+    }
+    regs
+}
+
+#[test]
+fn registers_show_the_date_and_time() {
+    let mut rtc = Rtc::new(RtcMode::Cycles {
+        start: LEAP_EVE,
+        clock_hz: 1_000,
+    });
+    let [year_lo, year_hi] = 2024u16.to_le_bytes();
+    assert_eq!(
+        registers(&mut rtc),
+        [58, 59, 23, 3, 28, 2, year_lo, year_hi]
+    );
+
+    rtc.tick(1_999);
+    assert_eq!(rtc.time(), LEAP_EVE + 1);
+    rtc.tick(1);
+    assert_eq!(registers(&mut rtc), [0, 0, 0, 4, 29, 2, year_lo, year_hi]);
+}
+
+#[test]
+fn reading_seconds_latches_the_rest() {
+    let mut rtc = Rtc::new(RtcMode::Cycles {
+        start: LEAP_EVE + 1,
+        clock_hz: 1_000,
+    });
+    assert_eq!(rtc.read8(0), 59);
+    rtc.tick(1_000);
+    assert_eq!(rtc.read8(2), 23, "still the latched hour");
+    assert_eq!(rtc.read8(4), 28);
+    assert_eq!(rtc.read8(0), 0);
+    assert_eq!(rtc.read8(2), 0);
+    assert_eq!(rtc.read8(4), 29);
+}
+
+#[test]
+fn writing_seconds_sets_the_clock() {
+    let mut rtc = Rtc::default();
+    assert_eq!(rtc.time(), RTC_EPOCH);
+    rtc.tick(500_000);
+    // 31 February 2023 12:00:00 carries over to 3 March.
+    let [year_lo, year_hi] = 2023u16.to_le_bytes();
+    for (offset, val) in [
+        (1, 0),
+        (2, 12),
+        (3, 6),
+        (4, 31),
+        (5, 2),
+        (6, year_lo),
+        (7, year_hi),
+    ] {
+        rtc.write8(offset, val);
+    }
+    assert_eq!(rtc.time(), RTC_EPOCH, "not set until SECONDS is written");
+    rtc.write8(0, 0);
+    assert_eq!(rtc.time(), 1_677_844_800);
+    assert_eq!(rtc.read8(3), 5, "a Friday, whatever was written");
+    assert_eq!(rtc.read8(4), 3);
+    // The second restarted.
+    rtc.tick(999_999);
+    assert_eq!(rtc.time(), 1_677_844_800);
+}
+
+#[test]
+fn host_mode_follows_the_wall_clock() {
+    let now = || {
+        let since = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        since.as_secs() as i64
+    };
+    let mut rtc = Rtc::new(RtcMode::Host);
+    assert!((rtc.time() - now()).abs() <= 1);
+    rtc.tick(u32::MAX);
+    assert!((rtc.time() - now()).abs() <= 1, "cycles do not count");
+    rtc.set_time(LEAP_EVE);
+    assert!((rtc.time() - LEAP_EVE).abs() <= 1);
+    assert_eq!(rtc.batch_cycles(), u32::MAX);
+}
+
+/// `LDA $2750; LDX $2751` repeated: reads seconds and minutes, which
+/// change every second of the RTC's 4 kHz clock.
+fn clock_vm() -> Vm {
+    let mut vm = vm_with(&[0xAD, 0x50, 0x27, 0xAE, 0x51, 0x27].repeat(0x1000));
+    let rtc = Rtc::new(RtcMode::Cycles {
+        start: LEAP_EVE,
+        clock_hz: 4_000,
+    });
+    vm.bus_mut().map(RTC_PORTS, rtc).unwrap();
+    vm
+}
+
+#[test]
+fn counting_cycles_is_deterministic_in_batches() {
+    let mut batched = clock_vm();
+    let mut stepped = clock_vm();
+    for _ in 0..20 {
+        batched.run_cycles(1_000).unwrap();
+        while stepped.cycles() < batched.cycles() {
+            stepped.step().unwrap();
+        }
+        assert_eq!(batched.registers(), stepped.registers());
+    }
+    assert_eq!(batched.registers().a, 3, "five seconds later");
+    assert_eq!(batched.registers().x, 0);
+}
+
+#[test]
+fn config_maps_an_rtc() {
+    let config = MachineConfig::from_toml("[[device]]\nkind = \"rtc\"\nhost = true").unwrap();
+    assert_eq!(
+        config.devices,
+        [DeviceConfig::Rtc {
+            start: None,
+            host: true,
+        }]
+    );
+    let config =
+        MachineConfig::from_toml("[cpu]\nclock_hz = 2_000\n[[device]]\nkind = \"rtc\"").unwrap();
+    let vm = Vm::with_config(&config).unwrap();
+    let rtc = vm.bus().device::<Rtc>(*RTC_PORTS.start()).unwrap();
+    assert_eq!(
+        rtc.mode(),
+        RtcMode::Cycles {
+            start: RTC_EPOCH,
+            clock_hz: 2_000,
+        }
+    );
+}
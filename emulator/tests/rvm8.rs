@@ -0,0 +1,318 @@
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+use emulator::Rom;
+use emulator::trace::{BinarySink, TraceReader};
+
+fn path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rvm8-run-{}-{name}", std::process::id()))
+}
+
+/// Runs `rvm8 run` on a ROM at $C000 holding `program`.
+fn run(name: &str, program: &[u8], args: &[&str]) -> Output {
+    let rom = path(name);
+    std::fs::write(&rom, Rom::new(0xC000, program).unwrap().to_bytes()).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_rvm8"))
+        .arg("run")
+        .arg(&rom)
+        .args(args)
+        .output()
+        .unwrap();
+    std::fs::remove_file(rom).unwrap();
+    output
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn exits_with_the_value_written_to_the_exit_port() {
+    // LSR $C005 writes $0A >> 1 to the port over the ROM byte at $C005.
+    let program = [0xA9, 0x01, 0x4E, 0x05, 0xC0, 0x0A];
+    let output = run("port", &program, &["--exit-port", "$C005"]);
+    assert_eq!(output.status.code(), Some(5));
+
+    // The default port reads as plain RAM, so the program exits with 0.
+    let output = run("default", &[0x4E, 0xFF, 0x27], &[]);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn halts_on_an_illegal_opcode_when_asked() {
+    let output = run("halt", &[0xA9, 0x01, 0x02], &["--exit-on-halt"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stderr(&output).contains("halted: illegal opcode 0x02 at PC 0xC002"));
+}
+
+#[test]
+fn cycle_limit_stops_the_run() {
+    let output = run("limit", &[0xA9, 0x01], &["--cycles", "1000"]);
+    assert_eq!(output.status.code(), Some(124));
+    let stderr = stderr(&output);
+    assert!(stderr.contains("cycle limit reached"));
+    // Only the first illegal opcode is reported.
+    assert_eq!(stderr.matches("illegal opcode").count(), 1);
+}
+
+#[test]
+fn traces_to_stdout_with_symbols() {
+    let map = path("map");
+    std::fs::write(&map, "value = $C005\n").unwrap();
+    let program = [0xAD, 0x05, 0xC0, 0x02];
+    let output = run(
+        "trace",
+        &program,
+        &[
+            "--exit-on-halt",
+            "--trace",
+            "-",
+            "--symbols",
+            map.to_str().unwrap(),
+        ],
+    );
+    std::fs::remove_file(map).unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("C000  AD 05 C0  LDA value      A:00"));
+}
+
+#[test]
+fn traces_in_binary_when_asked() {
+    let program = [0xAD, 0x05, 0xC0, 0x02];
+    let args = ["--exit-on-halt", "--trace", "-", "--trace-format", "binary"];
+    let output = run("binary", &program, &args);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout[..8], BinarySink::MAGIC);
+    let pcs: Vec<u16> = TraceReader::new(&output.stdout[..])
+        .unwrap()
+        .map(|record| record.unwrap().pc)
+        .collect();
+    assert_eq!(pcs, [0xC000, 0xC003]);
+}
+
+/// Runs `rvm8 trace` with `args`.
+fn trace_tool(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rvm8"))
+        .arg("trace")
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn trace_tool_dumps_and_filters_binary_traces() {
+    // LDA #$01; LDX #$02; LDA #$03, then an illegal opcode.
+    let program = [0xA9, 0x01, 0xA2, 0x02, 0xA9, 0x03, 0x02];
+    let args = ["--exit-on-halt", "--trace", "-", "--trace-format", "binary"];
+    let binary = path("tool.trace");
+    std::fs::write(&binary, run("tool", &program, &args).stdout).unwrap();
+    let binary = binary.to_str().unwrap();
+
+    let output = trace_tool(&["dump", binary]);
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().count(), 4);
+    assert!(stdout.starts_with("C000  A9 01     LDA #$01       A:00"));
+
+    let output = trace_tool(&["dump", binary, "--mnemonic", "lda", "--pc", "$C001-$C006"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().count(), 1);
+    assert!(stdout.starts_with("C004  A9 03     LDA #$03"));
+
+    let filtered = path("filtered.trace");
+    let output = trace_tool(&[
+        "filter",
+        binary,
+        filtered.to_str().unwrap(),
+        "--pc",
+        "$C002",
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    let bytes = std::fs::read(&filtered).unwrap();
+    let records: Vec<_> = TraceReader::new(&bytes[..]).unwrap().collect();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].as_ref().unwrap().instruction.mnemonic, "LDX");
+
+    std::fs::write(&filtered, b"not a trace").unwrap();
+    let output = trace_tool(&["dump", filtered.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("not an rvm8 binary trace"));
+    let output = trace_tool(&["filter", binary]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("missing output"));
+
+    std::fs::remove_file(filtered).unwrap();
+    std::fs::remove_file(binary).unwrap();
+}
+
+#[test]
+fn bad_command_lines_exit_with_2() {
+    let output = Command::new(env!("CARGO_BIN_EXE_rvm8"))
+        .args(["run"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("missing ROM"));
+
+    let output = run("bad", &[0xA9, 0x01], &["--cycles", "lots"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("bad cycle count `lots`"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_rvm8"))
+        .args(["run", "/nonexistent.rvm"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn patches_the_rom_before_running() {
+    // LSR $C003 exits with $0A >> 1, or $06 >> 1 once patched.
+    let list = path("exit.txt");
+    std::fs::write(&list, "$C003 = $06\n").unwrap();
+    let program = [0x4E, 0x03, 0xC0, 0x0A];
+    let args = ["--exit-port", "$C003", "--patch", list.to_str().unwrap()];
+    let output = run("patched", &program, &args);
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+
+    std::fs::write(&list, "$BFFF = 0\n").unwrap();
+    let output = run("unpatched", &program, &args);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("address $BFFF is not in the ROM"));
+    std::fs::remove_file(list).unwrap();
+}
+
+#[test]
+fn runs_on_a_described_machine() {
+    let machine = path("machine.toml");
+    std::fs::write(
+        &machine,
+        "[bus]\nunmapped_read = \"trap\"\n[[rom]]\nstart = 0xC000\nend = 0xFFFF\n",
+    )
+    .unwrap();
+    // LDA $9000
+    let output = run(
+        "machine",
+        &[0xAD, 0x00, 0x90],
+        &["--machine", machine.to_str().unwrap()],
+    );
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("unmapped read at 0x9000 at PC $C003"));
+
+    std::fs::write(&machine, "[[ram]]\nstart = 0\n").unwrap();
+    let output = run(
+        "bad-machine",
+        &[0xA9, 0x00],
+        &["--machine", machine.to_str().unwrap()],
+    );
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("line 1: missing key `end`"));
+    std::fs::remove_file(machine).unwrap();
+}
+
+#[test]
+fn checks_the_rom_against_a_manifest() {
+    let program = [0xA9, 0x00, 0x4E, 0xFF, 0x27];
+    let digest = Rom::new(0xC000, &program).unwrap().info().sha256;
+    let hex: String = digest.iter().map(|byte| format!("{byte:02x}")).collect();
+    let manifest = path("roms.sha256");
+    let name = path("listed");
+    let name = name.file_name().unwrap().to_str().unwrap();
+    std::fs::write(&manifest, format!("{hex}  {name}\n")).unwrap();
+    let args = ["--manifest", manifest.to_str().unwrap()];
+
+    let output = run("listed", &program, &args);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+
+    let output = run("unlisted", &program, &args);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("is not in the manifest"));
+
+    let output = run("listed", &[0xA9, 0x01, 0x4E, 0xFF, 0x27], &args);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("does not match manifest"));
+    std::fs::remove_file(manifest).unwrap();
+}
+
+#[test]
+fn traces_name_the_rom_build() {
+    let program = [0xA9, 0x00, 0x02];
+    let info = Rom::new(0xC000, &program).unwrap().info();
+    let output = run("build", &program, &["--exit-on-halt", "--trace", "-"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stderr(&output).contains(&info.to_string()));
+}
+
+#[cfg(all(unix, feature = "remote"))]
+#[test]
+fn takes_commands_over_the_control_socket() {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+
+    let (rom, socket) = (path("controlled"), path("control"));
+    std::fs::write(&rom, Rom::new(0xC000, &[0xA9, 0x01]).unwrap().to_bytes()).unwrap();
+    let child = Command::new(env!("CARGO_BIN_EXE_rvm8"))
+        .arg("run")
+        .arg(&rom)
+        .args([
+            "--exit-port",
+            "$C005",
+            "--cycles",
+            "4000000000",
+            "--control",
+        ])
+        .arg(&socket)
+        .spawn()
+        .unwrap();
+    let stream = loop {
+        match UnixStream::connect(&socket) {
+            Ok(stream) => break stream,
+            Err(_) => std::thread::sleep(std::time::Duration::from_millis(10)),
+        }
+    };
+    let mut client = BufReader::new(stream);
+    let mut send = |command: &str| {
+        writeln!(client.get_mut(), "{command}").unwrap();
+        let mut reply = String::new();
+        client.read_line(&mut reply).unwrap();
+        reply
+    };
+    assert_eq!(send("pause"), "ok\n");
+    // The exiting program from above, run from the top.
+    assert_eq!(send("poke $C000 $4E $05 $C0 0 0 $0A"), "ok\n");
+    assert_eq!(send("reset"), "ok\n");
+    assert_eq!(send("resume"), "ok\n");
+
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(5));
+    assert!(!socket.exists());
+    std::fs::remove_file(rom).unwrap();
+}
+
+#[cfg(feature = "script")]
+#[test]
+fn runs_script_callbacks() {
+    let script = path("script.rhai");
+    // Sends the program from LDA #$01 at $C000 to an exiting LSR at $C010.
+    std::fs::write(&script, "vm.on_pc(0xC002, |vm| vm.pc = 0xC010);").unwrap();
+    let mut program = [0xA9, 0x01].repeat(8);
+    program.extend([0x4E, 0x15, 0xC0, 0, 0, 0x0A]);
+    let args = ["--exit-port", "$C015", "--script", script.to_str().unwrap()];
+    let output = run("scripted", &program, &args);
+    assert_eq!(output.status.code(), Some(5));
+
+    std::fs::write(&script, "vm.on_pc(0xC002, |vm| vm.a = 0x100);").unwrap();
+    let output = run("failing", &program, &args);
+    std::fs::remove_file(script).unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("256 is not a byte"));
+}
+
+#[cfg(not(feature = "script"))]
+#[test]
+fn scripts_need_the_script_feature() {
+    let output = run("unscripted", &[0xA9, 0x01], &["--script", "script.rhai"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("--script needs the `script` feature"));
+}
@@ -0,0 +1,227 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::vm_with;
+use emulator::{ResetKind, ScheduledEvent, Vm};
+
+/// `LDA #$A9` filling 0x8000-0xBFFF: two cycles per instruction.
+fn vm() -> Vm {
+    vm_with(&[0xA9; 0x4000])
+}
+
+type Log = Arc<Mutex<Vec<(&'static str, u64)>>>;
+
+/// Logs the clock under `name` whenever an event of `kind` runs.
+fn log_kind(vm: &mut Vm, kind: u32, name: &'static str, log: &Log) {
+    let log = log.clone();
+    vm.scheduler_mut()
+        .on(kind, move |vm| log.lock().unwrap().push((name, vm.clock())));
+}
+
+/// Adds one to $0200 every 4 cycles, counting from each event's due cycle.
+fn count_every_4(vm: &mut Vm) {
+    vm.scheduler_mut().on(0, |vm| {
+        let count = vm.read(0x0200);
+        vm.write(0x0200, count.wrapping_add(1));
+        vm.scheduler_mut().after(4, 0);
+    });
+    vm.scheduler_mut().at(4, 0);
+}
+
+#[test]
+fn events_run_by_cycle_then_scheduling() {
+    let mut vm = vm();
+    let log = Arc::new(Mutex::new(Vec::new()));
+    for (kind, name) in [(0, "late"), (1, "first"), (2, "second"), (3, "never")] {
+        log_kind(&mut vm, kind, name, &log);
+    }
+    for (cycle, kind) in [(6, 0), (3, 1), (3, 2), (100, 3)] {
+        vm.scheduler_mut().at(cycle, kind);
+    }
+    assert_eq!(vm.scheduler().next_due(), Some(3));
+    vm.run_cycles(10).unwrap();
+    // Due at 3 and 6, run at the boundaries at 4 and 6.
+    assert_eq!(
+        *log.lock().unwrap(),
+        [("first", 4), ("second", 4), ("late", 6)]
+    );
+    assert_eq!(vm.scheduler().len(), 1);
+    assert_eq!(vm.scheduler().next_due(), Some(100));
+    let (id, kind) = vm.scheduler().pending().next().unwrap();
+    assert_eq!((id.cycle(), kind), (100, 3));
+}
+
+#[test]
+fn batches_stop_where_steps_would() {
+    let seen = |batched: bool| {
+        let mut vm = vm();
+        let at = Arc::new(Mutex::new(None));
+        let record = at.clone();
+        vm.scheduler_mut().on(0, move |vm| {
+            *record.lock().unwrap() = Some((vm.clock(), vm.registers().pc));
+        });
+        vm.scheduler_mut().at(1_001, 0);
+        if batched {
+            vm.run_cycles(5_000).unwrap();
+        } else {
+            while vm.clock() < 5_000 {
+                vm.step().unwrap();
+            }
+        }
+        *at.lock().unwrap()
+    };
+    assert_eq!(seen(true), Some((1_002, 0x8000 + 1_002)));
+    assert_eq!(seen(true), seen(false));
+}
+
+#[test]
+fn cancelled_and_rescheduled_events() {
+    let mut vm = vm();
+    vm.scheduler_mut().on(0, |vm| vm.write(0x0200, 0xFF));
+    vm.scheduler_mut().on(1, |vm| {
+        vm.write(0x0201, 1);
+        vm.scheduler_mut().after(0, 2);
+    });
+    vm.scheduler_mut().on(2, |vm| vm.write(0x0202, 2));
+    let cancelled = vm.scheduler_mut().at(10, 0);
+    // An event scheduled for now waits for the next boundary.
+    vm.scheduler_mut().at(20, 1);
+    assert!(vm.scheduler_mut().cancel(cancelled));
+    assert!(!vm.scheduler_mut().cancel(cancelled));
+    assert_eq!(cancelled.cycle(), 10);
+
+    vm.run_cycles(20).unwrap();
+    assert_eq!(vm.memory()[0x0200..0x0203], [0, 1, 0]);
+    vm.step().unwrap();
+    assert_eq!(vm.read(0x0202), 2);
+    assert!(vm.scheduler().is_empty());
+}
+
+#[test]
+fn handlers_can_be_replaced_and_removed() {
+    let mut vm = vm();
+    // Replaces itself the first time it runs.
+    vm.scheduler_mut().on(0, |vm| {
+        vm.write(0x0200, 1);
+        vm.scheduler_mut().on(0, |vm| vm.write(0x0201, 2));
+    });
+    vm.scheduler_mut().at(2, 0);
+    vm.scheduler_mut().at(4, 0);
+    vm.scheduler_mut().at(6, 0);
+    vm.run_cycles(4).unwrap();
+    assert_eq!(vm.memory()[0x0200..0x0202], [1, 2]);
+
+    assert!(vm.scheduler_mut().remove_handler(0));
+    assert!(!vm.scheduler_mut().remove_handler(0));
+    vm.write(0x0201, 0);
+    vm.run_cycles(4).unwrap();
+    assert_eq!(vm.read(0x0201), 0, "events without a handler do nothing");
+    assert!(vm.scheduler().is_empty());
+}
+
+#[test]
+fn periodic_events_keep_their_period() {
+    let mut vm = vm();
+    let runs = Arc::new(Mutex::new(Vec::new()));
+    let log = runs.clone();
+    vm.scheduler_mut().on(0, move |vm| {
+        log.lock().unwrap().push(vm.clock());
+        vm.scheduler_mut().after(3, 0);
+    });
+    vm.scheduler_mut().at(3, 0);
+    vm.run_cycles(30).unwrap();
+    // Due every 3 cycles however late the boundary each one ran at.
+    assert_eq!(
+        *runs.lock().unwrap(),
+        [4, 6, 10, 12, 16, 18, 22, 24, 28, 30]
+    );
+    assert_eq!(vm.scheduler().next_due(), Some(33));
+}
+
+#[test]
+fn the_clock_follows_resets_and_snapshots() {
+    let mut vm = vm();
+    vm.run_cycles(1_000).unwrap();
+    let snapshot = vm.save_state();
+    vm.run_cycles(500).unwrap();
+    assert_eq!(vm.clock(), 1_500);
+    vm.load_state(&snapshot).unwrap();
+    assert_eq!(vm.clock(), 1_000);
+
+    // Pending events stay across a reset, due on the restarted clock.
+    vm.scheduler_mut().on(0, |vm| vm.write(0x0200, 1));
+    vm.scheduler_mut().at(2_000, 0);
+    vm.reset(ResetKind::Soft);
+    assert_eq!(vm.clock(), 0);
+    vm.run_cycles(1_000).unwrap();
+    assert_eq!(vm.read(0x0200), 0);
+    vm.run_cycles(1_000).unwrap();
+    assert_eq!(vm.read(0x0200), 1);
+}
+
+#[test]
+fn snapshots_restore_the_pending_events() {
+    let mut vm = vm();
+    count_every_4(&mut vm);
+    vm.scheduler_mut().at(1_000, 1);
+    vm.run_cycles(101).unwrap();
+    let saved = vm.save_state();
+    assert_eq!(
+        saved.events,
+        [
+            ScheduledEvent { delay: 2, kind: 0 },
+            ScheduledEvent {
+                delay: 898,
+                kind: 1
+            },
+        ]
+    );
+    vm.run_cycles(500).unwrap();
+    let after = (vm.read(0x0200), vm.clock());
+
+    // Events scheduled since are dropped, and those saved come back due at
+    // the same cycles.
+    vm.scheduler_mut().at(700, 2);
+    vm.load_state(&saved).unwrap();
+    assert_eq!(vm.scheduler().next_due(), Some(104));
+    assert_eq!(vm.scheduler().len(), 2);
+    vm.run_cycles(500).unwrap();
+    assert_eq!((vm.read(0x0200), vm.clock()), after);
+
+    // Through the save-state format too.
+    let bytes = saved.to_bytes();
+    vm.load_state(&emulator::Snapshot::from_bytes(&bytes).unwrap())
+        .unwrap();
+    vm.run_cycles(500).unwrap();
+    assert_eq!((vm.read(0x0200), vm.clock()), after);
+}
+
+#[test]
+fn rewinding_replays_the_events() {
+    let run = |rewind: bool| {
+        let mut vm = vm();
+        vm.set_clock_hz(60 * 1_001);
+        count_every_4(&mut vm);
+        vm.enable_rewind(8, 1);
+        let mut counts = Vec::new();
+        for _ in 0..6 {
+            vm.run_frame().unwrap();
+            counts.push(vm.read(0x0200));
+        }
+        if rewind {
+            assert_eq!(vm.rewind(3), Ok(3));
+            for _ in 0..3 {
+                vm.run_frame().unwrap();
+                counts.push(vm.read(0x0200));
+            }
+        }
+        counts
+    };
+    let original = run(false);
+    let rewound = run(true);
+    // Back at frame 3 the count is what it was there, and the frames run
+    // again count the same.
+    assert_eq!(rewound[..6], original);
+    assert_eq!(rewound[6..], original[3..]);
+}
@@ -0,0 +1,243 @@
+mod common;
+
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use common::vm_with;
+use emulator::Vm;
+use emulator::display::{
+    BG_PALETTE, CTRL, CTRL_BACKGROUND, DisplayConfig, HEIGHT, PixelFormat, TILE_DATA, TILEMAP,
+    WIDTH,
+};
+use emulator::screenshot::FrameDump;
+
+fn path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rvm8-screenshot-{}-{name}", std::process::id()))
+}
+
+/// A machine showing one light-gray pixel in the top-left corner of a white
+/// screen, running `LSR $0300`.
+fn vm() -> Vm {
+    let mut vm = vm_with(&[0x4E, 0x00, 0x03].repeat(0x2000));
+    vm.set_clock_hz(6_000);
+    vm.write(TILE_DATA + 16, 0x80);
+    vm.write(TILEMAP, 1);
+    vm.write(BG_PALETTE, 0b11_10_01_00);
+    vm.write(CTRL, CTRL_BACKGROUND);
+    vm
+}
+
+/// A decoded PNG: its header fields, palette and unfiltered rows.
+struct Png {
+    width: u32,
+    height: u32,
+    color_type: u8,
+    palette: Option<Vec<u8>>,
+    rows: Vec<Vec<u8>>,
+}
+
+fn be32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes(bytes[..4].try_into().unwrap())
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                0xEDB8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Decodes a PNG whose image data is stored without compression, checking
+/// every CRC and the zlib checksum.
+fn decode(png: &[u8]) -> Png {
+    assert_eq!(
+        png[..8],
+        [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n']
+    );
+    let mut rest = &png[8..];
+    let (mut header, mut palette, mut zlib) = (None, None, Vec::new());
+    let mut kinds = Vec::new();
+    while !rest.is_empty() {
+        let len = be32(rest) as usize;
+        let (kind, data) = (&rest[4..8], &rest[8..8 + len]);
+        assert_eq!(be32(&rest[8 + len..]), crc32(&rest[4..8 + len]));
+        kinds.push(String::from_utf8(kind.to_vec()).unwrap());
+        match kind {
+            b"IHDR" => header = Some(data.to_vec()),
+            b"PLTE" => palette = Some(data.to_vec()),
+            b"IDAT" => zlib.extend_from_slice(data),
+            _ => {}
+        }
+        rest = &rest[12 + len..];
+    }
+    assert_eq!(kinds.last().map(String::as_str), Some("IEND"));
+    let header = header.unwrap();
+    assert_eq!(header[8..], [8, header[9], 0, 0, 0]);
+
+    let mut image = Vec::new();
+    let mut stream = &zlib[2..];
+    loop {
+        let last = stream[0] & 1 != 0;
+        assert_eq!(stream[0] >> 1, 0, "stored block");
+        let len = u16::from_le_bytes([stream[1], stream[2]]) as usize;
+        assert_eq!(!u16::from_le_bytes([stream[3], stream[4]]) as usize, len);
+        image.extend_from_slice(&stream[5..5 + len]);
+        stream = &stream[5 + len..];
+        if last {
+            break;
+        }
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in &image {
+        a = (a + u32::from(byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    assert_eq!(be32(stream), b << 16 | a);
+
+    let (width, height) = (be32(&header), be32(&header[4..]));
+    let rows: Vec<Vec<u8>> = image
+        .chunks_exact(image.len() / height as usize)
+        .map(|row| {
+            assert_eq!(row[0], 0, "no filter");
+            row[1..].to_vec()
+        })
+        .collect();
+    Png {
+        width,
+        height,
+        color_type: header[9],
+        palette,
+        rows,
+    }
+}
+
+fn encoded(vm: &Vm) -> Png {
+    let mut png = Vec::new();
+    vm.write_png(&mut png).unwrap();
+    decode(&png)
+}
+
+#[test]
+fn screenshots_hold_the_framebuffer() {
+    let mut vm = vm();
+    vm.run_frame().unwrap();
+    let file = path("shot.png");
+    vm.screenshot_png(&file).unwrap();
+    let png = decode(&std::fs::read(&file).unwrap());
+    std::fs::remove_file(&file).unwrap();
+
+    assert_eq!((png.width, png.height, png.color_type), (160, 144, 6));
+    assert!(png.palette.is_none());
+    assert_eq!(png.rows.concat(), vm.framebuffer());
+    assert_eq!(
+        png.rows[0][..8],
+        [0xAA, 0xAA, 0xAA, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]
+    );
+}
+
+#[test]
+fn screenshots_follow_the_display_config() {
+    let mut vm = vm();
+    vm.set_display_config(DisplayConfig {
+        format: PixelFormat::Rgb565,
+        scale: 2,
+    });
+    let png = encoded(&vm);
+    assert_eq!((png.width, png.height, png.color_type), (320, 288, 2));
+    assert_eq!(
+        png.rows[1][..9],
+        [0xAD, 0xAA, 0xAD, 0xAD, 0xAA, 0xAD, 0xFF, 0xFF, 0xFF]
+    );
+
+    vm.set_display_config(DisplayConfig {
+        format: PixelFormat::Indexed,
+        scale: 1,
+    });
+    let png = encoded(&vm);
+    assert_eq!((png.width, png.height, png.color_type), (160, 144, 3));
+    assert_eq!(
+        png.palette.unwrap()[..6],
+        [0xFF, 0xFF, 0xFF, 0xAA, 0xAA, 0xAA]
+    );
+    assert_eq!(png.rows.concat(), vm.framebuffer());
+}
+
+#[test]
+fn dumps_numbered_pngs_each_vblank() {
+    let mut vm = vm();
+    let dir = path("frames");
+    std::fs::create_dir_all(&dir).unwrap();
+    vm.dump_frames(FrameDump::png_files(&dir));
+    vm.run_frame().unwrap();
+    vm.write(TILEMAP, 0);
+    vm.run_frame().unwrap();
+    vm.stop_frame_dump();
+    vm.run_frame().unwrap();
+
+    let mut names: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["frame_000001.png", "frame_000002.png"]);
+    let first = decode(&std::fs::read(dir.join(&names[0])).unwrap());
+    let second = decode(&std::fs::read(dir.join(&names[1])).unwrap());
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(first.rows[0][0], 0xAA);
+    assert_eq!(second.rows[0][0], 0xFF);
+    assert!(vm.take_frame_dump_error().is_none());
+}
+
+/// A writer the test can still read after handing it to the `Vm`, failing
+/// every write once it holds `limit` bytes.
+#[derive(Clone)]
+struct Shared {
+    out: Arc<Mutex<Vec<u8>>>,
+    limit: usize,
+}
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut out = self.out.lock().unwrap();
+        if out.len() >= self.limit {
+            return Err(io::Error::other("full"));
+        }
+        out.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn raw_dumps_stream_framebuffers_until_an_error() {
+    let mut vm = vm();
+    vm.set_display_config(DisplayConfig {
+        format: PixelFormat::Indexed,
+        scale: 1,
+    });
+    let out = Shared {
+        out: Arc::default(),
+        limit: 2 * WIDTH * HEIGHT,
+    };
+    vm.dump_frames(FrameDump::raw(out.clone()));
+    for _ in 0..4 {
+        vm.run_frame().unwrap();
+    }
+    let written = out.out.lock().unwrap();
+    assert_eq!(written.len(), 2 * WIDTH * HEIGHT);
+    assert_eq!(written[..WIDTH * HEIGHT], *vm.framebuffer());
+    assert_eq!(vm.take_frame_dump_error().unwrap().to_string(), "full");
+    assert!(vm.take_frame_dump_error().is_none());
+}
@@ -0,0 +1,126 @@
+#![cfg(feature = "script")]
+
+mod common;
+
+use common::vm_with;
+use emulator::ResetKind;
+use emulator::script::ScriptError;
+
+/// LDA #$01, over and over.
+fn loads() -> Vec<u8> {
+    [0xA9, 0x01].repeat(0x100)
+}
+
+#[test]
+fn callbacks_set_registers_and_memory() {
+    let mut vm = vm_with(&loads());
+    let script = vm
+        .load_script(
+            r#"
+            vm.poke(0x0300, 0x11);
+            vm.on_pc(0x8002, |vm| {
+                vm.x = vm.a + 1;
+                vm.poke(0x0301, vm.pc & 0xFF);
+                vm.buttons = 0x05;
+            });
+            "#,
+        )
+        .unwrap();
+    assert_eq!(vm.read(0x0300), 0x11);
+    vm.step().unwrap();
+    assert_eq!(vm.registers().x, 0x00);
+    vm.step().unwrap();
+    assert_eq!(vm.registers().x, 0x02);
+    assert_eq!(vm.read(0x0301), 0x02);
+    assert_eq!(vm.buttons(), 0x05);
+    assert_eq!(script.take_errors(), []);
+}
+
+#[test]
+fn access_callbacks_see_what_the_program_moved() {
+    // LSR $0200, reading $08 and writing $04 back.
+    let mut vm = vm_with(&[0x4E, 0x00, 0x02]);
+    vm.write(0x0200, 0x08);
+    vm.load_script(
+        r#"
+        vm.on_read(0x0200, 0x0200, |vm, addr, value| vm.poke(0x0300, value));
+        vm.on_write(0x0200, 0x02FF, |vm, addr, value| vm.poke(0x0301, value));
+        vm.on_access(0x0200, 0x0200, |vm, addr, value| {
+            vm.poke(0x0302, vm.peek(0x0302) + 1);
+        });
+        "#,
+    )
+    .unwrap();
+    vm.step().unwrap();
+    assert_eq!(vm.read(0x0300), 0x08);
+    assert_eq!(vm.read(0x0301), 0x04);
+    assert_eq!(vm.read(0x0302), 2);
+}
+
+#[test]
+fn frame_callbacks_run_once_a_frame() {
+    let mut vm = vm_with(&loads());
+    vm.load_script(
+        r#"
+        vm.on_pc(0x8100, |vm| vm.pc = 0x8000);
+        vm.on_frame(|vm| vm.poke(0x0300, vm.frame));
+        "#,
+    )
+    .unwrap();
+    vm.run_frame().unwrap();
+    vm.run_frame().unwrap();
+    assert_eq!(vm.read(0x0300), 2);
+}
+
+#[test]
+fn hooks_go_with_remove_hook_and_unload() {
+    let mut vm = vm_with(&loads());
+    let count = "|vm| vm.poke(0x0300, vm.peek(0x0300) + 1)";
+    vm.load_script(&format!(
+        "let id = vm.on_pc(0x8000, {count}); vm.remove_hook(id);"
+    ))
+    .unwrap();
+    let script = vm
+        .load_script(&format!("vm.on_pc(0x8002, {count});"))
+        .unwrap();
+    vm.step().unwrap();
+    vm.step().unwrap();
+    assert_eq!(vm.read(0x0300), 1);
+
+    script.unload(&mut vm);
+    vm.reset(ResetKind::Soft);
+    vm.step().unwrap();
+    vm.step().unwrap();
+    assert_eq!(vm.read(0x0300), 1);
+}
+
+#[test]
+fn callback_errors_are_kept_and_the_machine_carries_on() {
+    let mut vm = vm_with(&loads());
+    let script = vm
+        .load_script("vm.on_pc(0x8002, |vm| vm.a = 0x100);")
+        .unwrap();
+    vm.run_cycles(8).unwrap();
+    assert_eq!(vm.registers().a, 0x01);
+    let errors = script.take_errors();
+    assert_eq!(errors.len(), 1);
+    assert!(
+        matches!(&errors[0], ScriptError::Run(err) if err.contains("256 is not a byte")),
+        "{errors:?}"
+    );
+    assert_eq!(script.take_errors(), []);
+}
+
+#[test]
+fn failed_loads_leave_no_hooks() {
+    let mut vm = vm_with(&loads());
+    let err = vm.load_script("vm.on_pc(0x8000,").unwrap_err();
+    assert!(matches!(err, ScriptError::Compile(_)), "{err}");
+
+    let err = vm
+        .load_script("vm.on_pc(0x8000, |vm| vm.poke(0x0300, 1)); vm.peek(0x10000);")
+        .unwrap_err();
+    assert!(err.to_string().contains("65536 is not an address"), "{err}");
+    vm.step().unwrap();
+    assert_eq!(vm.read(0x0300), 0);
+}
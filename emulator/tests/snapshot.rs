@@ -0,0 +1,313 @@
+mod common;
+
+use common::vm_with;
+use emulator::ffi::RVM_MEM_SIZE;
+use emulator::snapshot::{MAGIC, MemoryChange, Register, RegisterChange, VERSION};
+use emulator::timer::{CTRL_ENABLE, TIMER_BASE, Timer, timer_ports};
+use emulator::{BusDevice, DeviceState, Snapshot, SnapshotError, Vm, VmError};
+
+#[test]
+fn load_state_rewinds_registers_memory_and_cycles() {
+    // LDA #$01; LSR $10; LDA #$02
+    let mut vm = vm_with(&[0xA9, 0x01, 0x46, 0x10, 0xA9, 0x02]);
+    vm.write(0x10, 0x80);
+    vm.step().unwrap();
+    let saved = vm.save_state();
+
+    vm.step().unwrap();
+    vm.step().unwrap();
+    assert_eq!(vm.read(0x10), 0x40);
+
+    vm.load_state(&saved).unwrap();
+    assert_eq!(vm.registers().pc, 0x8002);
+    assert_eq!(vm.registers().a, 0x01);
+    assert_eq!(vm.read(0x10), 0x80);
+    assert_eq!(vm.cycles(), 2);
+    assert_eq!(vm.save_state(), saved);
+}
+
+/// A host peripheral counting the cycles it is ticked.
+struct Ticks(u32);
+
+impl BusDevice for Ticks {
+    fn read8(&mut self, offset: u16) -> u8 {
+        self.0.to_le_bytes()[usize::from(offset % 4)]
+    }
+
+    fn write8(&mut self, _: u16, _: u8) {}
+
+    fn tick(&mut self, cycles: u32) {
+        self.0 += cycles;
+    }
+
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&self.0.to_le_bytes());
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        if let Ok(bytes) = state.try_into() {
+            self.0 = u32::from_le_bytes(bytes);
+        }
+    }
+}
+
+#[test]
+fn devices_that_save_state_are_restored_with_the_machine() {
+    // LDA #$00 over and over.
+    let mut vm = vm_with(&[0xA9, 0x00].repeat(64));
+    vm.bus_mut().map(0x3000..=0x3003, Ticks(0)).unwrap();
+    vm.bus_mut().map(timer_ports(0), Timer::default()).unwrap();
+    let timer = vm.bus_mut().device_mut::<Timer>(TIMER_BASE).unwrap();
+    timer.write8(5, CTRL_ENABLE);
+    vm.run_cycles(10).unwrap();
+    let saved = vm.save_state();
+    assert_eq!(saved.devices.len(), 2);
+    assert_eq!(
+        saved.devices[0],
+        DeviceState {
+            start: 0x3000,
+            state: 10u32.to_le_bytes().to_vec(),
+        }
+    );
+
+    vm.run_cycles(20).unwrap();
+    assert_eq!(saved.diff(&vm.save_state()).devices, [0x2710, 0x3000]);
+    vm.load_state(&saved).unwrap();
+    assert_eq!(vm.bus_mut().device_mut::<Ticks>(0x3000).unwrap().0, 10);
+    assert_eq!(vm.bus().device::<Timer>(TIMER_BASE).unwrap().counter(), 10);
+    assert_eq!(vm.save_state(), saved);
+    assert_eq!(Snapshot::from_bytes(&saved.to_bytes()), Ok(saved.clone()));
+
+    // Paged snapshots, and so rewind and rollback, carry them too.
+    let paged = vm.save_paged();
+    vm.run_cycles(20).unwrap();
+    vm.load_paged(&paged);
+    assert_eq!(vm.bus_mut().device_mut::<Ticks>(0x3000).unwrap().0, 10);
+
+    let mut bare = Vm::new();
+    let before = bare.save_state();
+    assert_eq!(
+        bare.load_state(&saved),
+        Err(VmError::InvalidSnapshot("no device for a device state"))
+    );
+    assert_eq!(bare.save_state(), before);
+}
+
+#[test]
+fn snapshots_transfer_between_machines() {
+    let mut source = vm_with(&[0xA9, 0x42]);
+    source.step().unwrap();
+
+    let mut target = Vm::new();
+    target.load_state(&source.save_state()).unwrap();
+    assert_eq!(target.registers(), source.registers());
+    assert_eq!(target.memory(), source.memory());
+}
+
+#[test]
+fn rejects_truncated_memory() {
+    let mut vm = vm_with(&[0xA9, 0x42]);
+    let before = vm.save_state();
+    let bad = Snapshot {
+        memory: vec![0; 16],
+        ..before.clone()
+    };
+    assert!(matches!(
+        vm.load_state(&bad),
+        Err(VmError::InvalidSnapshot(_))
+    ));
+    assert_eq!(vm.save_state(), before);
+}
+
+#[test]
+fn rejects_frame_counts_past_the_clock() {
+    let mut vm = vm_with(&[0xA9, 0x42]);
+    let before = vm.save_state();
+    for frame in [u64::MAX, u64::MAX / u64::from(vm.cycles_per_frame())] {
+        let bad = Snapshot {
+            frame,
+            cycles: u32::MAX,
+            ..before.clone()
+        };
+        assert_eq!(
+            vm.load_state(&bad),
+            Err(VmError::InvalidSnapshot("frame count overflows the clock"))
+        );
+        assert_eq!(vm.save_state(), before);
+    }
+
+    // Paged snapshots cannot fail, so such a frame starts afresh.
+    let mut paged = vm.save_paged();
+    paged.frame = u64::MAX;
+    vm.load_paged(&paged);
+    assert_eq!(vm.frame(), u64::MAX);
+    assert_eq!(vm.registers(), before.registers);
+}
+
+#[test]
+fn bytes_round_trip() {
+    // LDA #$01; LSR $10
+    let mut vm = vm_with(&[0xA9, 0x01, 0x46, 0x10]);
+    vm.write(0x10, 0x80);
+    vm.raise_irq(2);
+    vm.step().unwrap();
+    vm.step().unwrap();
+    vm.scheduler_mut().at(100, 7);
+    let saved = vm.save_state();
+    assert_eq!(saved.events.len(), 1);
+
+    let bytes = saved.to_bytes();
+    assert_eq!(bytes[..7], MAGIC);
+    assert_eq!(bytes[7], VERSION);
+    assert_eq!(Snapshot::from_bytes(&bytes), Ok(saved));
+}
+
+/// A version 1 state written out by hand, which must keep loading however
+/// the format moves on.
+#[test]
+fn reads_version_1() {
+    let mut bytes = b"RVM8SAV\x01".to_vec();
+    bytes.extend_from_slice(&[0x11, 0x22, 0x33, 0x04, 0x02, 0x80, 0xFD, 0x00]);
+    bytes.extend_from_slice(&1234u32.to_le_bytes());
+    bytes.extend_from_slice(&56u64.to_le_bytes());
+    bytes.extend_from_slice(&[0b0100, 0xFF]);
+    let mut memory = vec![0; RVM_MEM_SIZE];
+    memory[0x10] = 0x80;
+    bytes.extend_from_slice(&memory);
+
+    let snapshot = Snapshot::from_bytes(&bytes).unwrap();
+    let regs = snapshot.registers;
+    assert_eq!(
+        (regs.a, regs.x, regs.y, regs.flags),
+        (0x11, 0x22, 0x33, 0x04)
+    );
+    assert_eq!((regs.pc, regs.sp), (0x8002, 0xFD));
+    assert_eq!((snapshot.cycles, snapshot.frame), (1234, 56));
+    assert_eq!((snapshot.irq.raised, snapshot.irq.mask), (0b0100, 0xFF));
+    assert_eq!(snapshot.memory, memory);
+
+    let mut vm = Vm::new();
+    vm.load_state(&snapshot).unwrap();
+    assert_eq!(vm.read(0x10), 0x80);
+}
+
+#[test]
+fn reads_version_2() {
+    // The same layout without the device states and events.
+    let saved = vm_with(&[0xA9, 0x42]).save_state();
+    let mut bytes = saved.to_bytes();
+    bytes.truncate(bytes.len() - 4);
+    bytes[7] = 2;
+    assert_eq!(Snapshot::from_bytes(&bytes), Ok(saved));
+}
+
+#[test]
+fn reads_version_3() {
+    // The same layout without the events.
+    let mut vm = vm_with(&[0xA9, 0x42]);
+    vm.bus_mut().map(timer_ports(0), Timer::default()).unwrap();
+    let saved = vm.save_state();
+    let mut bytes = saved.to_bytes();
+    bytes.truncate(bytes.len() - 2);
+    bytes[7] = 3;
+    assert_eq!(Snapshot::from_bytes(&bytes), Ok(saved));
+}
+
+#[test]
+fn from_bytes_rejects_foreign_data() {
+    let bytes = Vm::new().save_state().to_bytes();
+
+    let mut newer = bytes.clone();
+    newer[7] = VERSION + 1;
+    assert_eq!(
+        Snapshot::from_bytes(&newer),
+        Err(SnapshotError::UnsupportedVersion(VERSION + 1))
+    );
+    newer[7] = 0;
+    assert_eq!(
+        Snapshot::from_bytes(&newer),
+        Err(SnapshotError::UnsupportedVersion(0))
+    );
+
+    assert_eq!(
+        Snapshot::from_bytes(b"RVM8INP\x01"),
+        Err(SnapshotError::BadMagic)
+    );
+    assert_eq!(Snapshot::from_bytes(b"PNG"), Err(SnapshotError::BadMagic));
+    assert_eq!(
+        Snapshot::from_bytes(&bytes[..bytes.len() - 1]),
+        Err(SnapshotError::BadLength {
+            expected: bytes.len(),
+            actual: bytes.len() - 1,
+        })
+    );
+    assert_eq!(
+        Snapshot::from_bytes(b"RVM8"),
+        Err(SnapshotError::BadLength {
+            expected: bytes.len(),
+            actual: 4,
+        })
+    );
+    assert_eq!(
+        SnapshotError::UnsupportedVersion(9).to_string(),
+        "unsupported save state version 9"
+    );
+}
+
+#[test]
+fn diff_lists_changed_registers_and_memory_runs() {
+    // LDA #$01; LSR $10
+    let mut vm = vm_with(&[0xA9, 0x01, 0x46, 0x10]);
+    vm.write(0x10, 0x81);
+    let before = vm.save_state();
+    assert!(before.diff(&before).is_empty());
+
+    vm.step().unwrap();
+    vm.step().unwrap();
+    vm.write(0x11, 0xFF);
+    vm.write(0x13, 0x01);
+    let after = vm.save_state();
+    let diff = before.diff(&after);
+
+    assert_eq!(
+        diff.register(Register::Pc),
+        Some(RegisterChange {
+            register: Register::Pc,
+            old: 0x8000,
+            new: 0x8004
+        })
+    );
+    assert_eq!(diff.register(Register::X), None);
+    assert_eq!(diff.cycles, Some((0, after.cycles)));
+    assert_eq!(diff.frame, None);
+    assert_eq!(
+        diff.memory,
+        [
+            MemoryChange {
+                addr: 0x10,
+                old: vec![0x81, 0x00],
+                new: vec![0x40, 0xFF]
+            },
+            MemoryChange {
+                addr: 0x13,
+                old: vec![0x00],
+                new: vec![0x01]
+            },
+        ]
+    );
+    assert!(diff.changed(0x11) && !diff.changed(0x12));
+    assert_eq!(diff.changed_bytes(), 3);
+    assert_eq!(
+        diff.to_string(),
+        format!(
+            "A: $00 -> $01\nPC: $8000 -> $8004\nP: $04 -> $05\ncycles: 0 -> {}\n\
+             $0010-$0011: 81 00 -> 40 FF\n$0013: 00 -> 01\n",
+            after.cycles
+        )
+    );
+
+    // Reversed, old and new swap.
+    let back = after.diff(&before);
+    assert_eq!(back.memory[1].old, [0x01]);
+    assert_eq!(back.register(Register::A).unwrap().new, 0x00);
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use common::vm_with;
+use emulator::ffi::BusAccess;
+use emulator::snoop::BusEvent;
+use emulator::{BusDevice, TimingMode};
+
+/// Reads as $5A and ignores writes.
+struct Fixed;
+
+impl BusDevice for Fixed {
+    fn read8(&mut self, _offset: u16) -> u8 {
+        0x5A
+    }
+
+    fn write8(&mut self, _offset: u16, _val: u8) {}
+}
+
+fn event(addr: u16, value: u8, kind: BusAccess, cycle: u32) -> BusEvent {
+    BusEvent {
+        addr,
+        value,
+        kind,
+        cycle,
+    }
+}
+
+#[test]
+fn sees_every_access_in_order() {
+    // LDA $4000; LSR $0300
+    let mut vm = vm_with(&[0xAD, 0x00, 0x40, 0x4E, 0x00, 0x03]);
+    vm.bus_mut().map(0x4000..=0x4000, Fixed).unwrap();
+    vm.write(0x0300, 0x08);
+    let start = vm.cycles();
+    vm.set_bus_snooping(true);
+    vm.step().unwrap();
+    vm.step().unwrap();
+
+    let events: Vec<BusEvent> = vm.bus_events().collect();
+    let lsr = start + 4;
+    assert_eq!(
+        events[..4],
+        [
+            event(0x8000, 0xAD, BusAccess::Read, start),
+            event(0x8001, 0x00, BusAccess::Read, start),
+            event(0x8002, 0x40, BusAccess::Read, start),
+            event(0x4000, 0x5A, BusAccess::Read, start),
+        ]
+    );
+    assert_eq!(
+        events.last(),
+        Some(&event(0x0300, 0x04, BusAccess::Write, lsr))
+    );
+    assert_eq!(vm.registers().a, 0x5A, "the device still answered");
+    assert!(vm.bus_events().next().is_none(), "events are taken once");
+}
+
+#[test]
+fn cycle_accurate_events_carry_their_own_cycle() {
+    // LDA #$01; LDA $0200
+    let mut vm = vm_with(&[0xA9, 0x01, 0xAD, 0x00, 0x02]);
+    vm.bus_mut().set_timing_mode(TimingMode::CycleAccurate);
+    let start = vm.cycles();
+    vm.set_bus_snooping(true);
+    vm.run_cycles(2 + 4).unwrap();
+
+    let cycles: Vec<u32> = vm.bus_events().map(|e| e.cycle - start).collect();
+    assert_eq!(cycles, [0, 1, 2, 3, 4, 5]);
+}
+
+#[test]
+fn stopping_drops_what_was_not_taken() {
+    let mut vm = vm_with(&[0xA9, 0x01, 0xA9, 0x02]);
+    assert!(!vm.bus_snooping());
+    vm.step().unwrap();
+    assert!(vm.bus_events().next().is_none());
+
+    vm.set_bus_snooping(true);
+    assert!(vm.bus_snooping());
+    vm.step().unwrap();
+    vm.set_bus_snooping(false);
+    vm.set_bus_snooping(true);
+    assert!(vm.bus_events().next().is_none());
+}
@@ -0,0 +1,165 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::vm_with;
+use emulator::BusDevice;
+use emulator::spi::{
+    EEPROM_RDSR, EEPROM_READ, EEPROM_WRDI, EEPROM_WREN, EEPROM_WRITE, SPI_PORTS, Spi, SpiDevice,
+    SpiEeprom,
+};
+
+const SPI: u16 = *SPI_PORTS.start();
+
+/// A slave that logs what happens to it and replies with its last byte
+/// plus one.
+#[derive(Clone, Default)]
+struct Probe {
+    log: Arc<Mutex<Vec<String>>>,
+    last: u8,
+}
+
+impl SpiDevice for Probe {
+    fn select(&mut self) {
+        self.log.lock().unwrap().push("select".into());
+    }
+
+    fn transfer(&mut self, mosi: u8) -> u8 {
+        self.log.lock().unwrap().push(format!("{mosi:02X}"));
+        let reply = self.last.wrapping_add(1);
+        self.last = mosi;
+        reply
+    }
+
+    fn deselect(&mut self) {
+        self.log.lock().unwrap().push("deselect".into());
+    }
+}
+
+/// Selects `slots`, sends `bytes` and deselects, returning the replies.
+fn exchange(spi: &mut Spi, slots: u8, bytes: &[u8]) -> Vec<u8> {
+    spi.write8(1, slots);
+    let replies = bytes
+        .iter()
+        .map(|&byte| {
+            spi.write8(0, byte);
+            spi.read8(0)
+        })
+        .collect();
+    spi.write8(1, 0);
+    replies
+}
+
+#[test]
+fn frames_transfers_with_chip_select() {
+    let probe = Probe::default();
+    let log = Arc::clone(&probe.log);
+    let mut spi = Spi::new();
+    assert!(spi.attach(3, probe).is_none());
+    assert_eq!(exchange(&mut spi, 1 << 3, &[0x10, 0x20]), [0x01, 0x11]);
+    assert_eq!(*log.lock().unwrap(), ["select", "10", "20", "deselect"]);
+
+    // Nobody selected, and an empty slot.
+    assert_eq!(exchange(&mut spi, 0, &[0x55]), [0xFF]);
+    assert_eq!(exchange(&mut spi, 1 << 0, &[0x55]), [0x00]);
+    assert_eq!(log.lock().unwrap().len(), 4);
+    assert_eq!(spi.slave::<Probe>(3).unwrap().last, 0x20);
+    assert!(spi.slave::<SpiEeprom>(3).is_none());
+    assert!(spi.detach(3).is_some());
+    assert!(spi.slave::<Probe>(3).is_none());
+}
+
+#[test]
+fn replies_of_selected_slaves_are_ored() {
+    let mut spi = Spi::new();
+    spi.attach(
+        0,
+        Probe {
+            last: 0x0F,
+            ..Probe::default()
+        },
+    );
+    spi.attach(
+        1,
+        Probe {
+            last: 0x2F,
+            ..Probe::default()
+        },
+    );
+    spi.write8(1, 0b11);
+    assert_eq!(spi.read8(1), 0b11);
+    spi.write8(0, 0x01);
+    assert_eq!(spi.read8(0), 0x10 | 0x30);
+    // Only the line that changed is told.
+    spi.write8(1, 0b01);
+    spi.write8(0, 0x07);
+    assert_eq!(spi.read8(0), 0x02);
+    assert_eq!(spi.slave::<Probe>(1).unwrap().last, 0x01);
+}
+
+#[test]
+fn eeprom_needs_write_enable() {
+    let mut spi = Spi::new();
+    spi.attach(2, SpiEeprom::new(0x100));
+    let eeprom = 1 << 2;
+
+    exchange(&mut spi, eeprom, &[EEPROM_WRITE, 0x00, 0x10, 0xAA]);
+    assert_eq!(spi.slave::<SpiEeprom>(2).unwrap().contents()[0x10], 0xFF);
+
+    exchange(&mut spi, eeprom, &[EEPROM_WREN]);
+    assert_eq!(exchange(&mut spi, eeprom, &[EEPROM_RDSR, 0]), [0xFF, 0x02]);
+    // The address wraps at the end of the memory, and so does the write.
+    exchange(&mut spi, eeprom, &[EEPROM_WRITE, 0x01, 0xFF, 0x12, 0x34]);
+    assert_eq!(exchange(&mut spi, eeprom, &[EEPROM_RDSR, 0]), [0xFF, 0x00]);
+    let replies = exchange(&mut spi, eeprom, &[EEPROM_READ, 0x00, 0xFF, 0, 0, 0]);
+    assert_eq!(replies, [0xFF, 0xFF, 0xFF, 0x12, 0x34, 0xFF]);
+
+    exchange(&mut spi, eeprom, &[EEPROM_WREN]);
+    exchange(&mut spi, eeprom, &[EEPROM_WRDI]);
+    exchange(&mut spi, eeprom, &[EEPROM_WRITE, 0x00, 0x00, 0x99]);
+    let contents = spi.slave::<SpiEeprom>(2).unwrap().contents();
+    assert_eq!(&contents[..2], [0x34, 0xFF]);
+}
+
+#[test]
+fn programs_read_the_received_byte() {
+    let mut spi = Spi::new();
+    spi.attach(0, SpiEeprom::with_contents(vec![0x5A; 16]));
+    // LDA $2770
+    let mut vm = vm_with(&[0xAD, 0x70, 0x27]);
+    vm.bus_mut().map(SPI_PORTS, spi).unwrap();
+    let spi = vm.bus_mut().device_mut::<Spi>(SPI).unwrap();
+    spi.write8(1, 1);
+    for byte in [EEPROM_READ, 0x00, 0x03, 0x00] {
+        spi.write8(0, byte);
+    }
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 0x5A);
+}
+
+#[test]
+fn snapshots_keep_the_eeprom_and_the_command_in_progress() {
+    let mut spi = Spi::new();
+    spi.attach(1, SpiEeprom::new(0x100));
+    let mut vm = vm_with(&[0xEA]);
+    vm.bus_mut().map(SPI_PORTS, spi).unwrap();
+    let spi = vm.bus_mut().device_mut::<Spi>(SPI).unwrap();
+    exchange(spi, 1 << 1, &[EEPROM_WREN]);
+    exchange(spi, 1 << 1, &[EEPROM_WRITE, 0x00, 0x20, 0x42]);
+    // Halfway through reading 0x20.
+    spi.write8(1, 1 << 1);
+    for byte in [EEPROM_READ, 0x00] {
+        spi.write8(0, byte);
+    }
+    let saved = vm.save_state();
+
+    let spi = vm.bus_mut().device_mut::<Spi>(SPI).unwrap();
+    spi.write8(1, 0);
+    spi.slave_mut::<SpiEeprom>(1).unwrap().contents_mut()[0x20] = 0;
+    vm.load_state(&saved).unwrap();
+    let spi = vm.bus_mut().device_mut::<Spi>(SPI).unwrap();
+    assert_eq!(spi.read8(1), 1 << 1);
+    spi.write8(0, 0x20);
+    spi.write8(0, 0);
+    assert_eq!(spi.read8(0), 0x42);
+}
@@ -0,0 +1,104 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use emulator::{ReloadPolicy, Rom, Vm};
+
+fn temp(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rvm8-sram-{}-{name}", std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn flushes_write_only_changes() {
+    let path = temp("flush.sav");
+    let mut vm = Vm::new();
+    vm.attach_sram(0x6000..=0x60FF, &path).unwrap();
+    assert_eq!(vm.sram(), Some((0x6000..=0x60FF, path.as_path())));
+    assert!(!vm.sram_dirty());
+    assert!(!vm.flush_sram().unwrap());
+    assert!(!path.exists(), "nothing to save yet");
+
+    vm.write(0x6001, 0x42);
+    assert!(vm.sram_dirty());
+    assert!(vm.flush_sram().unwrap());
+    let saved = fs::read(&path).unwrap();
+    assert_eq!(saved.len(), 0x100);
+    assert_eq!(saved[1], 0x42);
+
+    // Unchanged since, so the file is not rewritten.
+    fs::write(&path, [0xEE; 0x100]).unwrap();
+    assert!(!vm.flush_sram().unwrap());
+    assert_eq!(fs::read(&path).unwrap(), [0xEE; 0x100]);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn save_files_are_loaded_and_saved_on_drop() {
+    let path = temp("drop.sav");
+    let mut contents = vec![0; 0x2000];
+    contents[0x10] = 0x99;
+    fs::write(&path, &contents).unwrap();
+
+    let mut vm = Vm::new();
+    vm.attach_sram(0x6000..=0x7FFF, &path).unwrap();
+    assert_eq!(vm.read(0x6010), 0x99);
+    assert!(!vm.sram_dirty());
+    vm.write(0x7FFF, 0x01);
+    drop(vm);
+
+    contents[0x1FFF] = 0x01;
+    assert_eq!(fs::read(&path).unwrap(), contents);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn save_files_of_the_wrong_size_are_refused() {
+    let path = temp("short.sav");
+    fs::write(&path, [0x11; 0x10]).unwrap();
+    let mut vm = Vm::new();
+    let err = vm.attach_sram(0x6000..=0x60FF, &path).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(
+        err.to_string(),
+        "save file is 16 bytes but the save RAM is 256"
+    );
+    assert_eq!(vm.sram(), None);
+    assert_eq!(vm.read(0x6000), 0x00);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn reattaching_saves_the_previous_range() {
+    let (first, second) = (temp("first.sav"), temp("second.sav"));
+    let mut vm = Vm::new();
+    vm.attach_sram(0x6000..=0x600F, &first).unwrap();
+    vm.write(0x6000, 0x07);
+    vm.attach_sram(0x7000..=0x700F, &second).unwrap();
+    assert_eq!(fs::read(&first).unwrap()[0], 0x07);
+    vm.detach_sram().unwrap();
+    assert_eq!(vm.sram(), None);
+    fs::remove_file(&first).unwrap();
+}
+
+#[test]
+fn reloading_the_rom_keeps_save_ram() {
+    let path = temp("reload.sav");
+    let rom = Rom::new(0xC000, &[0xA9, 0x01]).unwrap();
+    let mut vm = Vm::new();
+    vm.load_rom(&rom).unwrap();
+    vm.attach_sram(0x6000..=0x60FF, &path).unwrap();
+    vm.write(0x0200, 0x55);
+    vm.write(0x6000, 0x66);
+
+    let fresh = ReloadPolicy {
+        keep_ram: false,
+        keep_registers: false,
+    };
+    vm.reload_rom(&rom.to_bytes(), fresh).unwrap();
+    assert_eq!(vm.read(0x0200), 0x00);
+    assert_eq!(vm.read(0x6000), 0x66);
+    vm.detach_sram().unwrap();
+    fs::remove_file(&path).unwrap();
+}
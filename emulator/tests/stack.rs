@@ -0,0 +1,95 @@
+use emulator::{Registers, ResetKind, Vm, VmError};
+
+/// `LDA #$00` repeated at 0x8000 and an NMI handler at 0x9000 doing the
+/// same.
+fn vm() -> Vm {
+    let mut vm = Vm::new();
+    vm.load(0x8000, &[0xA9, 0x00].repeat(16)).unwrap();
+    vm.load(0x9000, &[0xA9, 0x00].repeat(16)).unwrap();
+    vm.load(0xFFFA, &[0x00, 0x90, 0x00, 0x80]).unwrap();
+    vm.reset(ResetKind::Soft);
+    vm
+}
+
+/// Pulses the NMI around one instruction, so the next step enters the
+/// handler.
+fn nmi(vm: &mut Vm) {
+    vm.set_nmi(true);
+    vm.step().unwrap();
+    vm.set_nmi(false);
+}
+
+#[test]
+fn pushes_below_the_bounds_overflow() {
+    let mut vm = vm();
+    vm.set_stack_bounds(Some(0xFA..=0xFD));
+    nmi(&mut vm);
+    vm.step().unwrap();
+    assert_eq!(vm.registers().sp, 0xFA, "the first entry fits");
+
+    nmi(&mut vm);
+    let err = VmError::StackOverflow {
+        pc: 0x9002,
+        sp: 0xF7,
+    };
+    assert_eq!(vm.step(), Err(err.clone()));
+    assert_eq!(vm.registers().pc, 0x9000, "the entry completed");
+    assert_eq!(err.to_string(), "stack overflow at PC 0x9002: SP is 0xF7");
+}
+
+#[test]
+fn pushes_wrapping_around_page_one_overflow() {
+    let mut vm = vm();
+    vm.set_stack_bounds(Some(0x00..=0xFF));
+    vm.set_registers(Registers {
+        sp: 0x01,
+        ..vm.registers()
+    });
+    nmi(&mut vm);
+    assert_eq!(
+        vm.step(),
+        Err(VmError::StackOverflow {
+            pc: 0x8002,
+            sp: 0xFE,
+        })
+    );
+    assert_eq!(vm.read(0x0100), 0x02, "the pushes happened");
+}
+
+#[test]
+fn pops_above_the_bounds_underflow() {
+    let mut vm = vm();
+    // 0x02: pops two bytes.
+    vm.register_opcode(0x02, |call| {
+        let mut regs = call.registers();
+        regs.sp = (regs.sp + 2) & 0xFF;
+        call.set_registers(regs);
+        Ok(2)
+    })
+    .unwrap();
+    vm.load(0x8000, &[0x02, 0x02]).unwrap();
+    vm.set_registers(Registers {
+        sp: 0xFA,
+        ..vm.registers()
+    });
+    vm.set_stack_bounds(Some(0xF0..=0xFD));
+    vm.step().unwrap();
+    assert_eq!(
+        vm.step(),
+        Err(VmError::StackUnderflow {
+            pc: 0x8001,
+            sp: 0xFE,
+        })
+    );
+}
+
+#[test]
+fn bounds_can_be_cleared() {
+    let mut vm = vm();
+    vm.set_stack_bounds(Some(0xFD..=0xFD));
+    assert_eq!(vm.stack_bounds(), Some(0xFD..=0xFD));
+    vm.set_stack_bounds(None);
+    nmi(&mut vm);
+    vm.run_cycles(20).unwrap();
+    assert_eq!(vm.registers().sp, 0xFA);
+}
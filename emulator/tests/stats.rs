@@ -0,0 +1,95 @@
+mod common;
+
+use common::vm_with_handler;
+use emulator::dma::{CTRL_START, DMA_PORTS, Dma};
+use emulator::{BusDevice, Registers, ResetKind};
+
+#[test]
+fn counts_instructions_cycles_and_accesses() {
+    // LDA #$01; LDA $3000; LSR $3000
+    let mut vm = vm_with_handler(&[0xA9, 0x01, 0xAD, 0x00, 0x30, 0x4E, 0x00, 0x30]);
+    assert_eq!(vm.stats().instructions, 0);
+    for _ in 0..3 {
+        vm.step().unwrap();
+    }
+    let stats = vm.stats();
+    assert_eq!(stats.instructions, 3);
+    assert_eq!(stats.cycles, 2 + 4 + 6);
+    assert_eq!(stats.reads, 2 + 4 + 4);
+    assert_eq!(stats.writes, 1);
+    assert_eq!(stats.irqs, 0);
+    assert_eq!(stats.hot_opcodes(), [(0x4E, 1), (0xA9, 1), (0xAD, 1)]);
+
+    vm.reset(ResetKind::Soft);
+    assert_eq!(vm.stats(), stats, "a reset keeps the counts");
+    vm.reset_stats();
+    assert_eq!(vm.stats().opcodes, [0; 256]);
+    assert_eq!(vm.stats().cycles, 0);
+}
+
+#[test]
+fn batches_count_like_steps() {
+    let program = [0xA9, 0x01, 0xAD, 0x00, 0x30].repeat(100);
+    let mut stepped = vm_with_handler(&program);
+    let mut batched = vm_with_handler(&program);
+    for _ in 0..100 {
+        stepped.step().unwrap();
+    }
+    batched.run_cycles(50 * 6 - 1).unwrap();
+    assert_eq!(batched.stats(), stepped.stats());
+    assert_eq!(batched.stats().opcodes[0xAD], 50);
+}
+
+#[test]
+fn interrupts_and_dma_are_counted() {
+    // LDA #$01, and LDA #$02 in the handler.
+    let mut vm = vm_with_handler(&[0xA9, 0x01]);
+    vm.load(0x9000, &[0xA9, 0x02]).unwrap();
+    vm.set_registers(Registers {
+        flags: 0,
+        ..vm.registers()
+    });
+    vm.bus_mut().map(DMA_PORTS, Dma::default()).unwrap();
+    vm.raise_irq(2);
+    vm.step().unwrap();
+    vm.ack_irq(2);
+    let dma = vm.bus_mut().device_mut::<Dma>(*DMA_PORTS.start()).unwrap();
+    dma.write8(4, 3);
+    dma.write8(6, CTRL_START);
+    vm.step().unwrap();
+
+    let stats = vm.stats();
+    assert_eq!(stats.irqs, 1);
+    assert_eq!(stats.instructions, 1);
+    // Interrupt entry, the handler's LDA and two cycles per byte moved.
+    assert_eq!(stats.cycles, 7 + 2 + 6);
+    assert_eq!(stats.cycles, u64::from(vm.cycles()));
+    assert_eq!(stats.writes, 3, "DMA is not the CPU");
+}
+
+#[test]
+fn rewound_frames_are_not_counted_again() {
+    let mut vm = vm_with_handler(&[0xAD, 0x00, 0x80].repeat(10_000));
+    vm.enable_rewind(8, 2);
+    for _ in 0..2 {
+        vm.run_frame().unwrap();
+    }
+    let stats = vm.stats();
+    assert_eq!(vm.rewind(1).unwrap(), 1);
+    assert_eq!(vm.stats(), stats);
+}
+
+#[test]
+fn the_summary_lists_opcodes_by_count() {
+    let mut vm = vm_with_handler(&[0xA9, 0x01, 0xA9, 0x02, 0xAD, 0x00, 0x30]);
+    for _ in 0..3 {
+        vm.step().unwrap();
+    }
+    assert_eq!(
+        vm.stats().to_string(),
+        "; 3 instructions in 8 cycles, 0 interrupts, 8 reads, 0 writes\n\
+         \x20    count      %  opcode\n\
+         \x20        2  66.7%  A9  LDA\n\
+         \x20        1  33.3%  AD  LDA\n"
+    );
+}
@@ -0,0 +1,91 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::vm_with;
+use emulator::Vm;
+use emulator::stepping::SCANLINES;
+
+/// A machine running `LDA $8000` with four cycles to a scanline.
+fn vm() -> Vm {
+    let mut vm = vm_with(&[0xAD, 0x00, 0x80].repeat(0x2000));
+    vm.set_clock_hz(SCANLINES * 4 * 60);
+    vm
+}
+
+/// Counts vblanks and frame hook runs.
+fn count_frames(vm: &mut Vm) -> (Arc<Mutex<u32>>, Arc<Mutex<u32>>) {
+    let vblanks = Arc::new(Mutex::new(0));
+    let hooks = Arc::new(Mutex::new(0));
+    let counted = vblanks.clone();
+    vm.set_vblank_callback(move |_| *counted.lock().unwrap() += 1);
+    let counted = hooks.clone();
+    vm.on_frame(move |_| *counted.lock().unwrap() += 1);
+    (vblanks, hooks)
+}
+
+#[test]
+fn scanlines_add_up_to_a_frame() {
+    let mut vm = vm();
+    let (vblanks, hooks) = count_frames(&mut vm);
+    for line in 0..SCANLINES {
+        assert_eq!(vm.scanline(), line);
+        assert_eq!(vm.frame(), 0);
+        vm.step_scanline().unwrap();
+    }
+    assert_eq!(vm.frame(), 1);
+    assert_eq!(vm.scanline(), 0);
+    assert_eq!(vm.cycles(), vm.cycles_per_frame());
+    assert_eq!((*vblanks.lock().unwrap(), *hooks.lock().unwrap()), (1, 1));
+}
+
+#[test]
+fn instructions_finish_the_frame_they_complete() {
+    let mut vm = vm();
+    let mut whole = self::vm();
+    let (vblanks, hooks) = count_frames(&mut vm);
+    whole.run_frame().unwrap();
+    let mut steps = 0;
+    while vm.frame() == 0 {
+        vm.step_instruction().unwrap();
+        steps += 1;
+    }
+    assert_eq!(steps, vm.cycles_per_frame() / 4);
+    assert_eq!(vm.cycles(), whole.cycles());
+    assert_eq!(vm.registers(), whole.registers());
+    assert_eq!((*vblanks.lock().unwrap(), *hooks.lock().unwrap()), (1, 1));
+}
+
+#[test]
+fn granularities_mix_without_repeating_events() {
+    let mut vm = vm();
+    let (vblanks, hooks) = count_frames(&mut vm);
+    vm.freeze(0x0000, 0x11);
+    vm.step_instruction().unwrap();
+    vm.step_scanline().unwrap();
+    vm.write(0x0000, 0);
+    vm.step_scanline().unwrap();
+    assert_eq!(vm.read(0x0000), 0, "freezes apply once per frame");
+    assert_eq!(vm.scanline(), 3);
+
+    vm.step_frame().unwrap();
+    assert_eq!(vm.frame(), 1);
+    assert_eq!(vm.cycles(), vm.cycles_per_frame());
+    assert_eq!((*vblanks.lock().unwrap(), *hooks.lock().unwrap()), (1, 1));
+    vm.step_frame().unwrap();
+    assert_eq!(vm.read(0x0000), 0x11, "the next frame begins afresh");
+    assert_eq!((*vblanks.lock().unwrap(), *hooks.lock().unwrap()), (2, 2));
+}
+
+#[test]
+fn plain_steps_ignore_frames() {
+    let mut vm = vm();
+    let (vblanks, _) = count_frames(&mut vm);
+    for _ in 0..vm.cycles_per_frame() / 4 + 1 {
+        vm.step().unwrap();
+    }
+    assert_eq!((vm.frame(), *vblanks.lock().unwrap()), (0, 0));
+    assert_eq!(vm.scanline(), SCANLINES - 1);
+    vm.step_instruction().unwrap();
+    assert_eq!((vm.frame(), *vblanks.lock().unwrap()), (1, 1));
+}
@@ -0,0 +1,208 @@
+mod common;
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use common::vm_with;
+use emulator::asm;
+use emulator::disasm::decode;
+use emulator::ffi::BusAccess;
+use emulator::symbols::{SymbolError, SymbolErrorKind};
+use emulator::{ResetKind, StopReason, SymbolTable, TraceConfig, Vm, WatchKind};
+
+const SOURCE: &str = "
+COUNT = 3
+        .org $8000
+start:  LDX #COUNT
+loop:   LDA table,X
+        LSR counter
+        LDA (ptr),Y
+table:  .byte 1, 2, 3
+counter = $10
+ptr = $20
+        .org $FFFC
+        .word start
+";
+
+fn symbols() -> SymbolTable {
+    SymbolTable::from(&asm::assemble(SOURCE).unwrap())
+}
+
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn map_files_parse_and_round_trip() {
+    let table = SymbolTable::parse(
+        "; generated\n\nstart = $8000\n  reset=0x8000 ; alias\nio = 9472\nbits = %1010\n",
+    )
+    .unwrap();
+    assert_eq!(table.len(), 4);
+    assert_eq!(table.address("io"), Some(0x2500));
+    assert_eq!(table.address("bits"), Some(10));
+    // The first name given to an address is the one displayed.
+    assert_eq!(table.name(0x8000), Some("start"));
+    assert_eq!(table.to_string().parse::<SymbolTable>().unwrap(), table);
+    assert_eq!(
+        table.to_string(),
+        "bits = $000A\nio = $2500\nstart = $8000\nreset = $8000\n"
+    );
+}
+
+#[test]
+fn malformed_map_files_report_the_line() {
+    let error = |text: &str| SymbolTable::parse(text).unwrap_err();
+    assert_eq!(
+        error("a = 1\nnonsense"),
+        SymbolError {
+            line: 2,
+            kind: SymbolErrorKind::Syntax
+        }
+    );
+    assert_eq!(
+        error("1st = 1").kind,
+        SymbolErrorKind::BadName("1st".into())
+    );
+    assert_eq!(
+        error("big = $10000").kind,
+        SymbolErrorKind::BadValue("$10000".into())
+    );
+    let duplicate = error("a = 1\na = 2");
+    assert_eq!(duplicate.kind, SymbolErrorKind::DuplicateSymbol("a".into()));
+    assert_eq!(
+        duplicate.to_string(),
+        "line 2: symbol `a` is already defined"
+    );
+}
+
+#[test]
+fn addresses_are_described_from_the_nearest_label() {
+    let table = symbols();
+    assert_eq!(table.address("table"), Some(0x800A));
+    assert_eq!(table.describe(0x800A), "table");
+    assert_eq!(table.describe(0x800C), "table+2");
+    assert_eq!(table.nearest(0x800A + 0xFF), Some(("table", 0xFF)));
+    assert_eq!(table.describe(0x810A), "$810A");
+    assert_eq!(table.describe(0x0002), "$0002");
+}
+
+#[test]
+fn disassembly_names_address_operands() {
+    let table = symbols();
+    let show = |bytes: &[u8]| decode(bytes).unwrap().with_symbols(&table).to_string();
+    assert_eq!(show(&[0xBD, 0x0A, 0x80]), "LDA table,X");
+    assert_eq!(show(&[0x46, 0x10]), "LSR counter");
+    assert_eq!(show(&[0x4E, 0x10, 0x00]), "LSR counter");
+    assert_eq!(show(&[0xB1, 0x20]), "LDA (ptr),Y");
+    // Immediates stay numeric even when a symbol has the same value.
+    assert_eq!(show(&[0xA2, 0x03]), "LDX #$03");
+    assert_eq!(show(&[0xAD, 0x34, 0x12]), "LDA $1234");
+}
+
+#[test]
+fn writer_traces_use_symbols() {
+    let program = asm::assemble(SOURCE).unwrap();
+    let mut vm = Vm::new();
+    vm.load_assembly(&program).unwrap();
+    vm.reset(ResetKind::Soft);
+    let out = Shared::default();
+    vm.set_trace(TraceConfig::writer(out.clone()).with_symbols(SymbolTable::from(&program)));
+    vm.step().unwrap();
+    vm.step().unwrap();
+    let text = String::from_utf8(std::mem::take(&mut *out.0.lock().unwrap())).unwrap();
+    let lines: Vec<_> = text.lines().collect();
+    assert_eq!(
+        lines[1],
+        "8002  BD 0A 80  LDA table,X    A:00 X:03 Y:00 P:04 SP:FD CYC:2"
+    );
+}
+
+#[test]
+fn stop_reasons_name_their_addresses() {
+    let table = symbols();
+    let mut vm = Vm::new();
+    vm.load_assembly(&asm::assemble(SOURCE).unwrap()).unwrap();
+    vm.reset(ResetKind::Soft);
+    vm.add_breakpoint(table.address("loop").unwrap());
+    let stop = vm.run_until_break().unwrap();
+    assert_eq!(stop.with_symbols(&table).to_string(), "breakpoint at loop");
+    assert_eq!(stop.to_string(), "breakpoint at $8002");
+
+    vm.remove_breakpoint(0x8002);
+    vm.add_watchpoint(0x10..=0x10, WatchKind::Write);
+    let stop = vm.run_until_break().unwrap();
+    assert!(matches!(
+        stop,
+        StopReason::Watchpoint {
+            access: BusAccess::Write,
+            ..
+        }
+    ));
+    assert_eq!(
+        stop.with_symbols(&table).to_string(),
+        "watchpoint: write of $00 at counter by the instruction at loop+3"
+    );
+}
+
+#[test]
+fn regions_name_every_address_inside_them() {
+    let mut table = SymbolTable::parse("oam = $0300..$03FF\nsprite0 = $0300..$0303\n").unwrap();
+    assert!(table.insert("hp", 0x0310));
+    assert!(!table.insert_region("hp", 0x0400..=0x04FF));
+    assert_eq!(table.address("oam"), Some(0x0300));
+    assert_eq!(table.region(0x0302), Some(("sprite0", 0x0300..=0x0303)));
+    assert_eq!(table.region(0x0304), Some(("oam", 0x0300..=0x03FF)));
+    assert_eq!(table.describe(0x0302), "sprite0+2");
+    assert_eq!(table.describe(0x0310), "hp");
+    // Inside the region, it wins over a label nearer below.
+    assert_eq!(table.describe(0x03F0), "oam+240");
+    assert_eq!(table.describe(0x0400), "hp+240");
+    assert_eq!(table.describe(0x0500), "$0500");
+
+    let show = |bytes: &[u8]| decode(bytes).unwrap().with_symbols(&table).to_string();
+    assert_eq!(show(&[0xBD, 0x40, 0x03]), "LDA oam+64,X");
+    assert_eq!(show(&[0xAD, 0x10, 0x03]), "LDA hp");
+
+    assert_eq!(
+        table.to_string(),
+        "oam = $0300..$03FF\nsprite0 = $0300..$0303\nhp = $0310\n"
+    );
+    assert_eq!(table.to_string().parse::<SymbolTable>().unwrap(), table);
+    assert_eq!(
+        SymbolTable::parse("bad = $0300..$02FF").unwrap_err().kind,
+        SymbolErrorKind::BadValue("$0300..$02FF".into())
+    );
+}
+
+#[test]
+fn labels_name_machine_traces_and_watchpoint_reports() {
+    // LSR $0210; LDA $0201
+    let mut vm = vm_with(&[0x4E, 0x10, 0x02, 0xAD, 0x01, 0x02]);
+    assert!(vm.label(0x0201, "player_hp"));
+    assert!(!vm.label(0x0202, "player_hp"));
+    assert!(vm.label_region(0x0210..=0x021F, "inventory"));
+
+    let out = Shared::default();
+    vm.set_trace(TraceConfig::writer(out.clone()));
+    vm.add_watchpoint(0x0201..=0x0201, WatchKind::Read);
+    let stop = vm.run_until_break().unwrap();
+    let text = String::from_utf8(std::mem::take(&mut *out.0.lock().unwrap())).unwrap();
+    let lines: Vec<_> = text.lines().collect();
+    assert!(lines[0].starts_with("8000  4E 10 02  LSR inventory  "));
+    assert!(lines[1].starts_with("8003  AD 01 02  LDA player_hp  "));
+    assert_eq!(
+        stop.with_symbols(vm.symbols().unwrap()).to_string(),
+        "watchpoint: read of $00 at player_hp by the instruction at $8003"
+    );
+}
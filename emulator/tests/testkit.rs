@@ -0,0 +1,105 @@
+use emulator::asm::assemble;
+use emulator::testkit::{self, AssertFailure, Outcome, REG_A, REG_X, TestKit};
+use emulator::{ResetKind, Rom, Vm, VmError};
+
+/// A machine at the program assembled from `source` at $C000, with the
+/// kit installed.
+fn machine(source: &str) -> (Vm, TestKit) {
+    let program = assemble(&format!(".org $C000\n{source}\n.org $FFFC\n.word $C000\n")).unwrap();
+    let mut vm = Vm::new();
+    vm.load_assembly(&program).unwrap();
+    vm.reset(ResetKind::Soft);
+    let kit = TestKit::install(&mut vm);
+    (vm, kit)
+}
+
+#[test]
+fn collects_assertions_and_output() {
+    let (mut vm, kit) = machine(
+        "
+        LDA #$80
+        LSR A
+        .byte $42, 0, $40   ; ASSERT_EQ A, $40
+        LDX #$07
+        .byte $42, 1, $08   ; ASSERT_EQ X, $08
+        .byte $52           ; PRINT done
+        .word done
+        .byte $22           ; PASS
+done:   .byte \"lsr ok\", 0
+        ",
+    );
+    let report = kit.run(&mut vm, 1000);
+    assert_eq!(report.outcome, Outcome::Passed);
+    assert_eq!(report.assertions, 1);
+    assert_eq!(
+        report.failures,
+        [AssertFailure {
+            pc: 0xC008,
+            register: REG_X,
+            expected: 0x08,
+            actual: 0x07,
+        }]
+    );
+    assert_eq!(report.output, ["lsr ok"]);
+    assert_eq!(report.cycles, 2 + 2 + 2 + 2 + 2 + 2 + 2);
+    assert!(!report.passed());
+    assert_eq!(
+        report.to_string(),
+        "passed, 1 of 2 assertions held, 14 cycles\n$C008: expected X = $08, found $07"
+    );
+}
+
+#[test]
+fn ends_on_fail_errors_and_the_cycle_limit() {
+    let (mut vm, kit) = machine("LDA #$01\n.byte $42, 0, $01\n.byte $32, 7");
+    let report = kit.run(&mut vm, 1000);
+    assert_eq!(
+        report.outcome,
+        Outcome::Failed {
+            pc: 0xC005,
+            code: 7
+        }
+    );
+    assert_eq!(report.assertions, 1);
+    assert_eq!(
+        report.to_string(),
+        "failed with code 7 at $C005, 1 of 1 assertions held, 6 cycles"
+    );
+
+    let (mut vm, kit) = machine(".byte $42, 9, 0");
+    let report = kit.run(&mut vm, 1000);
+    assert!(matches!(
+        report.outcome,
+        Outcome::Error(VmError::OpcodeFailed { .. })
+    ));
+    assert!(
+        report
+            .to_string()
+            .contains("ASSERT_EQ of unknown register 9")
+    );
+
+    let (mut vm, kit) = machine(".byte $02");
+    let report = kit.run(&mut vm, 1000);
+    assert!(matches!(
+        report.outcome,
+        Outcome::Error(VmError::IllegalOpcode { pc: 0xC000, .. })
+    ));
+
+    // LDA #$A9 over and over.
+    let (mut vm, kit) = machine(&".byte $A9\n".repeat(0x100));
+    let report = kit.run(&mut vm, 100);
+    assert_eq!(report.outcome, Outcome::TimedOut);
+    assert_eq!(report.cycles, 100);
+}
+
+#[test]
+fn runs_a_rom_file() {
+    let path = std::env::temp_dir().join(format!("rvm8-testkit-{}.rvm", std::process::id()));
+    // LDA #$2A; ASSERT_EQ A, $2A; PASS
+    let rom = Rom::new(0xC000, &[0xA9, 0x2A, 0x42, REG_A, 0x2A, 0x22]).unwrap();
+    std::fs::write(&path, rom.to_bytes()).unwrap();
+    let report = testkit::run_test_rom(&path).unwrap();
+    assert!(report.passed(), "{report}");
+    std::fs::remove_file(&path).unwrap();
+    assert!(testkit::run_test_rom(&path).is_err());
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use common::vm_with;
+use emulator::{Registers, Vm};
+
+fn assert_send<T: Send>() {}
+
+/// `ADC #seed; LSR $10seed` repeated up to the vectors, so every seed
+/// leaves different registers and RAM behind.
+fn seeded_vm(seed: u8) -> Vm {
+    let mut vm = vm_with(&[0x69, seed, 0x4E, seed, 0x10].repeat(0x1990));
+    vm.write(0x1000 | u16::from(seed), 0xFF);
+    vm
+}
+
+fn run(seed: u8) -> (Registers, u32, u8) {
+    let mut vm = seeded_vm(seed);
+    vm.run_cycles(20_000).unwrap();
+    (
+        vm.registers(),
+        vm.cycles(),
+        vm.read(0x1000 | u16::from(seed)),
+    )
+}
+
+#[test]
+fn vm_is_send() {
+    assert_send::<Vm>();
+}
+
+#[test]
+fn machines_on_different_threads_do_not_interfere() {
+    let seeds: Vec<u8> = (1..=16).collect();
+    let expected: Vec<_> = seeds.iter().map(|&seed| run(seed)).collect();
+    let results: Vec<_> = thread::scope(|scope| {
+        let handles: Vec<_> = seeds
+            .iter()
+            .map(|&seed| scope.spawn(move || run(seed)))
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    assert_eq!(results, expected);
+}
+
+#[test]
+fn machines_move_between_threads_with_their_callbacks() {
+    let mut vm = seeded_vm(0x42);
+    let writes = Arc::new(AtomicU32::new(0));
+    let count = Arc::clone(&writes);
+    vm.on_write(0x1000..=0x10FF, move |_, val| {
+        count.fetch_add(1, Ordering::SeqCst);
+        val
+    });
+    let frames = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&frames);
+    vm.on_frame(move |vm| sink.lock().unwrap().push(vm.frame()));
+
+    let mut vm = thread::spawn(move || {
+        vm.run_frame().unwrap();
+        vm
+    })
+    .join()
+    .unwrap();
+    vm.run_frame().unwrap();
+    assert_eq!(*frames.lock().unwrap(), [1, 2]);
+    assert_eq!(u64::from(writes.load(Ordering::SeqCst)), vm.stats().writes);
+    assert!(vm.stats().writes > 0);
+}
@@ -0,0 +1,138 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use common::vm_with;
+use emulator::Vm;
+use emulator::audio::{AUDIO_ENABLE, ENABLE_SQUARE1, SQUARE1_PERIOD};
+use emulator::throttle::{MAX_LAG, Throttle};
+
+/// A machine playing a square wave while it executes `LSR $0300` from
+/// 0x8000 at full volume, with a callback collecting every frame's samples.
+fn tone_vm() -> (Vm, Arc<Mutex<Vec<Vec<i16>>>>) {
+    let mut vm = vm_with(&[0x4E, 0x00, 0x03].repeat(10921));
+    vm.load(SQUARE1_PERIOD, &[0x40, 0x00, 0x2F]).unwrap();
+    vm.write(AUDIO_ENABLE, ENABLE_SQUARE1);
+    let frames = Arc::new(Mutex::new(Vec::new()));
+    let sink = frames.clone();
+    vm.set_audio_callback(move |samples| sink.lock().unwrap().push(samples.to_vec()));
+    (vm, frames)
+}
+
+fn frame_lengths(frames: &Mutex<Vec<Vec<i16>>>) -> Vec<usize> {
+    frames.lock().unwrap().iter().map(Vec::len).collect()
+}
+
+#[test]
+fn frames_are_paced_at_the_speed_set() {
+    let mut throttle = Throttle::new();
+    let interval = Duration::from_secs(1) / 60;
+    assert_eq!(throttle.frame_interval(), Some(interval));
+    throttle.set_speed(2.0);
+    let interval = throttle.frame_interval().unwrap();
+    assert_eq!(interval.as_micros(), 8333);
+
+    let start = Instant::now();
+    assert_eq!(throttle.frame_finished(start), Some(start + interval));
+    // A late frame is followed by an early one.
+    let late = start + interval + interval / 2;
+    assert_eq!(throttle.frame_finished(late), Some(start + interval * 2));
+    // Falling too far behind restarts the schedule.
+    let stalled = start + interval * (MAX_LAG + 3);
+    assert_eq!(throttle.frame_finished(stalled), Some(stalled + interval));
+}
+
+#[test]
+fn late_frames_start_at_once() {
+    let mut throttle = Throttle::new();
+    let interval = throttle.frame_interval().unwrap();
+    let start = Instant::now();
+    throttle.frame_finished(start);
+    assert_eq!(throttle.frame_finished(start + interval * 2), None);
+    assert_eq!(
+        throttle.frame_finished(start + interval * 2),
+        Some(start + interval * 3)
+    );
+}
+
+#[test]
+fn turbo_is_uncapped_and_pause_waits() {
+    let mut throttle = Throttle::new();
+    throttle.set_turbo(true);
+    assert_eq!(throttle.frame_interval(), None);
+    let now = Instant::now();
+    assert_eq!(throttle.frame_finished(now), None);
+
+    throttle.set_turbo(false);
+    throttle.pause();
+    assert!(throttle.is_paused());
+    let frame = Duration::from_secs(1) / 60;
+    assert_eq!(throttle.frame_finished(now), Some(now + frame));
+
+    // Resuming does not catch up on the pause.
+    throttle.resume();
+    let later = now + frame * 100;
+    assert_eq!(throttle.frame_finished(later), Some(later + frame));
+}
+
+#[test]
+#[should_panic(expected = "speed must be finite and positive")]
+fn zero_speed_is_rejected() {
+    Throttle::new().set_speed(0.0);
+}
+
+#[test]
+fn audio_is_resampled_to_the_speed() {
+    let (mut vm, frames) = tone_vm();
+    let mut throttle = Throttle::new();
+    throttle.apply(&mut vm);
+    vm.run_frame().unwrap();
+    throttle.set_speed(2.0);
+    throttle.apply(&mut vm);
+    vm.run_frame().unwrap();
+    throttle.set_speed(0.5);
+    throttle.apply(&mut vm);
+    vm.run_frame().unwrap();
+
+    let lengths = frame_lengths(&frames);
+    assert_eq!(lengths[0], 735);
+    assert!((367..=368).contains(&lengths[1]), "{lengths:?}");
+    // Give or take the position carried over from the faster frame.
+    assert!((1466..=1470).contains(&lengths[2]), "{lengths:?}");
+    let frames = frames.lock().unwrap();
+    let level = frames[0][0].abs();
+    assert!(level > 0);
+    assert!(frames.iter().flatten().all(|s| s.abs() <= level));
+}
+
+#[test]
+fn turbo_runs_uncapped_with_the_callback_muted() {
+    let (mut vm, frames) = tone_vm();
+    let mut throttle = Throttle::new();
+    throttle.set_turbo(true);
+    assert!(throttle.run_frame(&mut vm).unwrap());
+    assert!(throttle.run_frame(&mut vm).unwrap());
+    assert_eq!(vm.frame(), 2);
+    assert!(frames.lock().unwrap().is_empty());
+
+    throttle.set_turbo(false);
+    assert!(throttle.run_frame(&mut vm).unwrap());
+    assert_eq!(frame_lengths(&frames), [735]);
+}
+
+#[test]
+fn paused_throttles_run_no_frames() {
+    let (mut vm, frames) = tone_vm();
+    let mut throttle = Throttle::new();
+    throttle.pause();
+    let start = Instant::now();
+    assert!(!throttle.run_frame(&mut vm).unwrap());
+    assert!(start.elapsed() >= Duration::from_secs(1) / 60);
+    assert_eq!(vm.frame(), 0);
+    assert!(frames.lock().unwrap().is_empty());
+
+    throttle.resume();
+    assert!(throttle.run_frame(&mut vm).unwrap());
+    assert_eq!(vm.frame(), 1);
+}
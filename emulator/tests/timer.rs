@@ -0,0 +1,167 @@
+mod common;
+
+use common::vm_with_handler;
+use emulator::timer::{
+    CTRL_ENABLE, CTRL_IRQ, CTRL_ONE_SHOT, TIMER_IRQ, TIMER_SIZE, Timer, timer_ports,
+};
+use emulator::{BusDevice, Registers, Vm};
+
+const TIMER: u16 = *timer_ports(0).start();
+
+/// `LDA #$A9` filling 0x8000-0x8FFF: two cycles per instruction.
+fn vm() -> Vm {
+    vm_with_handler(&[0xA9; 0x1000])
+}
+
+fn configure(vm: &mut Vm, counter: u16, reload: u16, prescale: u8, ctrl: u8) {
+    let timer = vm.bus_mut().device_mut::<Timer>(TIMER).unwrap();
+    let bytes = [counter.to_le_bytes(), reload.to_le_bytes()].concat();
+    for (i, byte) in bytes.into_iter().enumerate() {
+        timer.write8(i as u16, byte);
+    }
+    timer.write8(4, prescale);
+    timer.write8(5, ctrl);
+}
+
+fn timer(vm: &Vm) -> &Timer {
+    vm.bus().device::<Timer>(TIMER).unwrap()
+}
+
+#[test]
+fn slots_do_not_overlap() {
+    assert_eq!(timer_ports(0), 0x2710..=0x2717);
+    assert_eq!(*timer_ports(1).start(), 0x2710 + TIMER_SIZE);
+}
+
+#[test]
+fn prescaler_divides_the_clock() {
+    let mut vm = vm();
+    vm.bus_mut().map(timer_ports(0), Timer::default()).unwrap();
+    configure(&mut vm, 0, 0, 2, CTRL_ENABLE);
+    for _ in 0..10 {
+        vm.step().unwrap();
+    }
+    // 20 cycles at one tick per 4 cycles.
+    assert_eq!(timer(&vm).counter(), 5);
+}
+
+#[test]
+fn overflow_reloads_and_raises_the_line() {
+    let mut vm = vm();
+    vm.bus_mut().map(timer_ports(0), Timer::default()).unwrap();
+    configure(&mut vm, 0xFFFE, 0xFFF0, 0, CTRL_ENABLE | CTRL_IRQ);
+    vm.step().unwrap();
+    assert!(timer(&vm).overflowed());
+    assert_eq!(timer(&vm).counter(), 0xFFF0);
+    assert_eq!(vm.active_irq(), Some(TIMER_IRQ));
+
+    // Acknowledging clears the line; the counter keeps running.
+    vm.bus_mut()
+        .device_mut::<Timer>(TIMER)
+        .unwrap()
+        .write8(6, 0);
+    assert_eq!(vm.active_irq(), None);
+    vm.step().unwrap();
+    assert_eq!(timer(&vm).counter(), 0xFFF2);
+}
+
+#[test]
+fn long_gaps_skip_whole_periods() {
+    let mut timer = Timer::default();
+    timer.write8(2, 0xF0);
+    timer.write8(3, 0xFF);
+    timer.write8(5, CTRL_ENABLE);
+    // From 0 to overflow is 0x10000 ticks, then 0x10 per period.
+    timer.tick(0x10000 + 0x10 * 1000 + 3);
+    assert_eq!(timer.counter(), 0xFFF3);
+}
+
+#[test]
+fn one_shot_stops_after_overflow() {
+    let mut vm = vm();
+    vm.bus_mut().map(timer_ports(0), Timer::default()).unwrap();
+    configure(&mut vm, 0xFFFF, 0x1234, 0, CTRL_ENABLE | CTRL_ONE_SHOT);
+    vm.step().unwrap();
+    vm.step().unwrap();
+    assert_eq!(timer(&vm).counter(), 0x1234);
+    assert!(!timer(&vm).running());
+}
+
+#[test]
+fn overflow_interrupts_the_cpu() {
+    let mut vm = vm();
+    vm.load(0x9000, &[0xA9, 0x55]).unwrap();
+    vm.set_registers(Registers {
+        flags: 0,
+        ..vm.registers()
+    });
+    vm.bus_mut().map(timer_ports(0), Timer::default()).unwrap();
+    configure(&mut vm, 0xFFFC, 0, 0, CTRL_ENABLE | CTRL_IRQ);
+    vm.step().unwrap();
+    vm.step().unwrap();
+    // Overflowed during the second instruction; taken before the third.
+    vm.step().unwrap();
+    assert_eq!(vm.registers().pc, 0x9000);
+}
+
+#[test]
+fn batches_read_and_interrupt_like_steps() {
+    // LDA $2710, reading the counter's low byte, in both the main program
+    // and the handler.
+    let program = [0xAD, 0x10, 0x27].repeat(0x300);
+    let setup = || {
+        let mut vm = vm();
+        vm.load(0x8000, &program).unwrap();
+        vm.load(0x9000, &program).unwrap();
+        vm.set_registers(Registers {
+            flags: 0,
+            ..vm.registers()
+        });
+        vm.bus_mut().map(timer_ports(0), Timer::default()).unwrap();
+        configure(&mut vm, 0xFF00, 0, 3, CTRL_ENABLE | CTRL_IRQ);
+        vm
+    };
+    let mut stepped = setup();
+    let mut batched = setup();
+    for _ in 0..4 {
+        batched.run_cycles(700).unwrap();
+        while stepped.cycles() < batched.cycles() {
+            stepped.step().unwrap();
+        }
+        assert_eq!(batched.cycles(), stepped.cycles());
+        assert_eq!(batched.registers(), stepped.registers());
+        assert_eq!(timer(&batched).counter(), timer(&stepped).counter());
+    }
+    assert!(batched.registers().pc > 0x9000, "the overflow was taken");
+}
+
+#[test]
+fn batches_take_overflows_where_steps_do() {
+    // The main program leaves the timer alone, so only the overflow ends a
+    // batch. The handler acknowledges and lets the next one in, pushing
+    // three bytes for each on the stack.
+    let setup = || {
+        let mut vm = vm();
+        // LSR $2716; CLI; then LDA #$A9 as in the main program.
+        let mut handler = vec![0x4E, 0x16, 0x27, 0x58];
+        handler.extend_from_slice(&[0xA9; 0x200]);
+        vm.load(0x9000, &handler).unwrap();
+        vm.set_registers(Registers {
+            flags: 0,
+            ..vm.registers()
+        });
+        vm.bus_mut().map(timer_ports(0), Timer::default()).unwrap();
+        configure(&mut vm, 0xFF00, 0xFF00, 0, CTRL_ENABLE | CTRL_IRQ);
+        vm
+    };
+    let mut stepped = setup();
+    let mut batched = setup();
+    let sp = batched.registers().sp;
+    batched.run_cycles(5_001).unwrap();
+    while stepped.cycles() < batched.cycles() {
+        stepped.step().unwrap();
+    }
+    assert_eq!(batched.cycles(), stepped.cycles());
+    assert_eq!(batched.registers(), stepped.registers());
+    assert_eq!(sp.wrapping_sub(batched.registers().sp) / 3, 19);
+}
@@ -0,0 +1,239 @@
+mod common;
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use common::vm_with;
+use emulator::disasm;
+use emulator::trace::{BinarySink, TraceBuffer, TraceReadError, TraceReader};
+use emulator::{Registers, TraceConfig, TraceRecord, TraceSink};
+
+/// A writer whose output the test can still read after handing it to the
+/// `Vm`, failing every write once `limit` lines have been written.
+#[derive(Clone, Default)]
+struct Shared {
+    out: Arc<Mutex<Vec<u8>>>,
+    limit: Option<usize>,
+}
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut out = self.out.lock().unwrap();
+        let lines = out.iter().filter(|&&b| b == b'\n').count();
+        if self.limit.is_some_and(|limit| lines >= limit) {
+            return Err(io::Error::other("full"));
+        }
+        out.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn callback_sees_each_instruction_before_it_runs() {
+    // LDA #$42; LDX $1234
+    let mut vm = vm_with(&[0xA9, 0x42, 0xAE, 0x34, 0x12]);
+    let records = Arc::new(Mutex::new(Vec::<TraceRecord>::new()));
+    let sink = Arc::clone(&records);
+    vm.set_trace(TraceConfig::callback(move |r| {
+        sink.lock().unwrap().push(*r)
+    }));
+    vm.step().unwrap();
+    vm.step().unwrap();
+
+    let records = records.lock().unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!((records[0].pc, records[0].opcode()), (0x8000, 0xA9));
+    assert_eq!(records[0].registers.a, 0);
+    assert_eq!(records[1].pc, 0x8002);
+    assert_eq!(records[1].registers.a, 0x42);
+    assert_eq!(records[1].cycles, 2);
+    assert_eq!(records[1].instruction.to_string(), "LDX $1234");
+}
+
+#[test]
+fn writer_gets_one_line_per_instruction() {
+    // LDA #$42; LDX $1234; LDA #$00
+    let mut vm = vm_with(&[0xA9, 0x42, 0xAE, 0x34, 0x12, 0xA9, 0x00]);
+    let out = Shared::default();
+    vm.set_trace(TraceConfig::writer(out.clone()));
+    vm.step().unwrap();
+    vm.step().unwrap();
+    vm.clear_trace();
+    vm.step().unwrap();
+
+    let text = String::from_utf8(out.out.lock().unwrap().clone()).unwrap();
+    let lines: Vec<_> = text.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "8000  A9 42     LDA #$42       A:00 X:00 Y:00 P:04 SP:FD CYC:0",
+            "8002  AE 34 12  LDX $1234      A:42 X:00 Y:00 P:04 SP:FD CYC:2",
+        ]
+    );
+}
+
+#[test]
+fn write_errors_stop_the_trace() {
+    let mut vm = vm_with(&[0xA9, 0x01, 0xA9, 0x02, 0xA9, 0x03]);
+    let out = Shared {
+        limit: Some(1),
+        ..Shared::default()
+    };
+    vm.set_trace(TraceConfig::writer(out.clone()));
+    for _ in 0..3 {
+        vm.step().unwrap();
+    }
+    assert_eq!(
+        out.out
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|&&b| b == b'\n')
+            .count(),
+        1
+    );
+    assert!(vm.take_trace_error().is_some());
+    assert!(vm.take_trace_error().is_none());
+}
+
+#[test]
+fn illegal_opcodes_are_traced() {
+    let mut vm = vm_with(&[0x02]);
+    let records = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&records);
+    vm.set_trace(TraceConfig::callback(move |r| {
+        sink.lock().unwrap().push(r.pc)
+    }));
+    assert!(vm.step().is_err());
+    assert_eq!(*records.lock().unwrap(), [0x8000]);
+}
+
+#[test]
+fn sinks_combine_and_stop_together() {
+    // LDA #$42; LDX $1234; LDY #$01
+    let mut vm = vm_with(&[0xA9, 0x42, 0xAE, 0x34, 0x12, 0xA0, 0x01]);
+    let buffer = TraceBuffer::new(2);
+    let (text, binary) = (Shared::default(), Shared::default());
+    let failing = Shared {
+        limit: Some(2),
+        ..Shared::default()
+    };
+    vm.set_trace(
+        TraceConfig::writer(text.clone())
+            .and(TraceConfig::sink(buffer.clone()))
+            .and(TraceConfig::sink(BinarySink::new(binary.clone())))
+            .and(TraceConfig::writer(failing)),
+    );
+    vm.step().unwrap();
+    vm.step().unwrap();
+    vm.step().unwrap();
+
+    let pcs: Vec<u16> = buffer.records().iter().map(|r| r.pc).collect();
+    assert_eq!(pcs, [0x8002, 0x8005], "the buffer keeps the last two");
+    assert_eq!(buffer.take().len(), 2);
+    assert!(buffer.records().is_empty());
+    let text = String::from_utf8(text.out.lock().unwrap().clone()).unwrap();
+    assert_eq!(text.lines().count(), 3);
+
+    let binary = binary.out.lock().unwrap().clone();
+    let read: Vec<String> = TraceReader::new(&binary[..])
+        .unwrap()
+        .map(|record| record.unwrap().to_string())
+        .collect();
+    assert_eq!(read, text.lines().collect::<Vec<_>>());
+
+    assert_eq!(vm.take_trace_error().unwrap().to_string(), "full");
+    vm.step().unwrap_err();
+    assert!(
+        buffer.records().is_empty(),
+        "one failing sink stops them all"
+    );
+}
+
+fn record(pc: u16, bytes: [u8; 3], a: u8, sp: u16, cycles: u32) -> TraceRecord {
+    let instruction = disasm::decode(&bytes).unwrap();
+    let mut bytes = bytes;
+    bytes[usize::from(instruction.size)..].fill(0);
+    TraceRecord {
+        pc,
+        bytes,
+        instruction,
+        registers: Registers {
+            a,
+            pc,
+            sp,
+            ..Registers::default()
+        },
+        cycles,
+    }
+}
+
+#[test]
+fn binary_traces_read_back_what_was_written() {
+    let records = [
+        record(0x8000, [0xA9, 0x42, 0], 0x00, 0x01FD, 7),
+        // Sequential, with A changed.
+        record(0x8002, [0xAE, 0x34, 0x12], 0x42, 0x01FD, 9),
+        // Near and far jumps, and the stack moving.
+        record(0x7FF0, [0x58, 0, 0], 0x42, 0x01FA, 13),
+        record(0x1234, [0x4A, 0, 0], 0x42, 0x01FA, 15),
+        // Seen before, then rewritten.
+        record(0x8000, [0xA9, 0x42, 0], 0x42, 0x01FA, 17),
+        record(0x8000, [0xA0, 0x07, 0], 0x42, 0x01FA, 19),
+        // The cycle counter wraps.
+        record(0x8002, [0xAE, 0x34, 0x12], 0x42, 0x01FA, 1),
+    ];
+    let out = Shared::default();
+    let mut sink = BinarySink::new(out.clone());
+    for record in &records {
+        sink.record(record).unwrap();
+    }
+    let bytes = out.out.lock().unwrap().clone();
+    assert_eq!(bytes[..8], BinarySink::MAGIC);
+
+    let read: Vec<TraceRecord> = TraceReader::new(&bytes[..])
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(read, records);
+
+    // A repeated instruction costs its header and cycles.
+    let before = bytes.len();
+    sink.record(&records[6]).unwrap();
+    let after = out.out.lock().unwrap().len();
+    assert!(after - before <= 4, "{} bytes", after - before);
+}
+
+#[test]
+fn broken_binary_traces_are_reported() {
+    assert!(TraceReader::new(&b""[..]).unwrap().next().is_none());
+    assert!(matches!(
+        TraceReader::new(&b"RVM8SAV\x01"[..]),
+        Err(TraceReadError::BadMagic)
+    ));
+
+    let out = Shared::default();
+    let mut sink = BinarySink::new(out.clone());
+    sink.record(&record(0x8000, [0xAE, 0x34, 0x12], 0, 0, 0))
+        .unwrap();
+    let bytes = out.out.lock().unwrap().clone();
+    let mut reader = TraceReader::new(&bytes[..bytes.len() - 1]).unwrap();
+    assert!(matches!(
+        reader.next(),
+        Some(Err(TraceReadError::Truncated))
+    ));
+    assert!(reader.next().is_none());
+
+    // Code that was never seen cannot repeat.
+    let mut corrupt = BinarySink::MAGIC.to_vec();
+    corrupt.extend_from_slice(&[0x00, 0x02]);
+    let mut reader = TraceReader::new(&corrupt[..]).unwrap();
+    assert!(matches!(
+        reader.next(),
+        Some(Err(TraceReadError::Corrupt { records: 0 }))
+    ));
+}
@@ -0,0 +1,175 @@
+mod common;
+
+use std::io::{self, Cursor, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use common::vm_with;
+use emulator::uart::{CTRL_RX_IRQ, STATUS_RX_READY, STATUS_TX_READY, UART_IRQ, UART_PORTS, Uart};
+use emulator::{BusDevice, Vm};
+
+const UART: u16 = *UART_PORTS.start();
+
+/// A writer the test can inspect after handing it to the UART.
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct Broken;
+
+impl Write for Broken {
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs `program` from 0x8000 with the UART mapped.
+fn vm(program: &[u8], uart: Uart) -> Vm {
+    let mut vm = vm_with(program);
+    vm.bus_mut().map(UART_PORTS, uart).unwrap();
+    vm
+}
+
+fn uart(vm: &mut Vm) -> &mut Uart {
+    vm.bus_mut().device_mut::<Uart>(UART).unwrap()
+}
+
+/// Polls `STATUS` until a received byte is waiting.
+fn wait_rx(uart: &mut Uart) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while uart.read8(1) & STATUS_RX_READY == 0 {
+        assert!(Instant::now() < deadline, "no byte received");
+        std::thread::yield_now();
+    }
+}
+
+#[test]
+fn program_reads_received_bytes() {
+    let mut device = Uart::new();
+    device.push_rx(b"hi");
+    // LDA $2721; LDA $2720; LDA $2720; LDA $2721
+    let mut vm = vm(
+        &[
+            0xAD, 0x21, 0x27, 0xAD, 0x20, 0x27, 0xAD, 0x20, 0x27, 0xAD, 0x21, 0x27,
+        ],
+        device,
+    );
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, STATUS_RX_READY | STATUS_TX_READY);
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, b'h');
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, b'i');
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, STATUS_TX_READY);
+}
+
+#[test]
+fn program_writes_are_sent() {
+    let out = Shared::default();
+    // LSR $2720 reads nothing (0) and sends the shifted byte.
+    let mut vm = vm(&[0x4E, 0x20, 0x27], Uart::connect(io::empty(), out.clone()));
+    vm.step().unwrap();
+    assert_eq!(*out.0.lock().unwrap(), [0]);
+
+    let device = uart(&mut vm);
+    device.write8(0, b'o');
+    device.write8(0, b'k');
+    assert_eq!(*out.0.lock().unwrap(), b"\0ok");
+}
+
+#[test]
+fn unconnected_uart_buffers_output() {
+    let mut device = Uart::new();
+    device.write8(0, b'x');
+    assert_eq!(device.take_tx(), b"x");
+    assert!(device.take_tx().is_empty());
+}
+
+#[test]
+fn reader_is_pumped_in_the_background() {
+    let mut device = Uart::connect(Cursor::new(b"abc".to_vec()), io::sink());
+    let mut received = Vec::new();
+    while received.len() < 3 {
+        wait_rx(&mut device);
+        received.push(device.read8(0));
+    }
+    assert_eq!(received, b"abc");
+}
+
+#[test]
+fn receive_interrupt_follows_the_queue() {
+    let mut vm = vm(&[0xA9, 0x00, 0xA9, 0x00], Uart::new());
+    uart(&mut vm).push_rx(b"z");
+    assert_eq!(vm.active_irq(), None);
+
+    uart(&mut vm).write8(2, CTRL_RX_IRQ);
+    assert_eq!(vm.active_irq(), Some(UART_IRQ));
+    assert_eq!(uart(&mut vm).read8(0), b'z');
+    assert_eq!(vm.active_irq(), None);
+}
+
+#[test]
+fn write_error_disconnects_the_writer() {
+    let mut device = Uart::connect(io::empty(), Broken);
+    device.write8(0, 1);
+    assert_eq!(
+        device.take_error().unwrap().kind(),
+        io::ErrorKind::BrokenPipe
+    );
+    device.write8(0, 2);
+    assert_eq!(device.take_tx(), [2]);
+}
+
+#[test]
+fn tcp_carries_both_directions() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    let mut device = Uart::tcp(server).unwrap();
+
+    client.write_all(b"?").unwrap();
+    wait_rx(&mut device);
+    assert_eq!(device.read8(0), b'?');
+
+    device.write8(0, b'!');
+    let mut reply = [0];
+    client.read_exact(&mut reply).unwrap();
+    assert_eq!(reply, *b"!");
+}
+
+#[test]
+fn snapshots_keep_the_bytes_received() {
+    let mut vm = vm_with(&[0xEA]);
+    let mut uart = Uart::new();
+    uart.push_rx(b"ok");
+    uart.write8(2, CTRL_RX_IRQ);
+    vm.bus_mut().map(UART_PORTS, uart).unwrap();
+    let saved = vm.save_state();
+
+    let uart = vm.bus_mut().device_mut::<Uart>(UART).unwrap();
+    uart.read8(0);
+    uart.write8(2, 0);
+    vm.load_state(&saved).unwrap();
+    assert_eq!(vm.pending_irqs(), 1 << UART_IRQ);
+    let uart = vm.bus_mut().device_mut::<Uart>(UART).unwrap();
+    assert_eq!(
+        [uart.read8(0), uart.read8(0), uart.read8(0)],
+        [b'o', b'k', 0]
+    );
+}
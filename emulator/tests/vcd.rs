@@ -0,0 +1,141 @@
+mod common;
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use common::vm_with;
+use emulator::{TraceConfig, Vm};
+
+/// A writer whose output the test can still read after handing it to the
+/// `Vm`, failing every write once it holds `limit` bytes.
+#[derive(Clone)]
+struct Shared {
+    out: Arc<Mutex<Vec<u8>>>,
+    limit: usize,
+}
+
+impl Shared {
+    fn new(limit: usize) -> Self {
+        Self {
+            out: Arc::default(),
+            limit,
+        }
+    }
+
+    fn text(&self) -> String {
+        String::from_utf8(self.out.lock().unwrap().clone()).unwrap()
+    }
+}
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut out = self.out.lock().unwrap();
+        if out.len() + buf.len() > self.limit {
+            return Err(io::Error::other("full"));
+        }
+        out.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+const HEADER: &str = "\
+$version rvm-8 $end
+$timescale 1ns $end
+$scope module rvm8 $end
+$var wire 16 a addr $end
+$var wire 8 d data $end
+$var wire 1 r rd $end
+$var wire 1 w wr $end
+$var wire 8 i irq $end
+$upscope $end
+$enddefinitions $end
+#0
+$dumpvars
+b0 a
+b0 d
+0r
+0w
+b0 i
+$end
+";
+
+/// `LDA $1234; LSR $1234` with 0x42 at 0x1234.
+fn vm() -> Vm {
+    let mut vm = vm_with(&[0xAD, 0x34, 0x12, 0x4E, 0x34, 0x12]);
+    vm.write(0x1234, 0x42);
+    vm
+}
+
+#[test]
+fn dumps_every_access_cycle_by_cycle() {
+    let mut vm = vm();
+    let out = Shared::new(usize::MAX);
+    vm.set_trace(TraceConfig::vcd(out.clone()));
+    vm.raise_irq(2);
+    vm.run_cycles(10).unwrap();
+    vm.clear_trace();
+
+    let body = out.text();
+    let body = body.strip_prefix(HEADER).unwrap();
+    assert_eq!(
+        body,
+        "\
+b1000000000000000 a
+b10101101 d
+1r
+b100 i
+#1000
+b1000000000000001 a
+b110100 d
+#2000
+b1000000000000010 a
+b10010 d
+#3000
+b1001000110100 a
+b1000010 d
+#4000
+b1000000000000011 a
+b1001110 d
+#5000
+b1000000000000100 a
+b110100 d
+#6000
+b1000000000000101 a
+b10010 d
+#7000
+b1001000110100 a
+b1000010 d
+#8000
+b100001 d
+0r
+1w
+#9000
+0w
+"
+    );
+    assert_eq!(vm.read(0x1234), 0x21);
+}
+
+#[test]
+fn an_empty_dump_is_still_a_valid_file() {
+    let mut vm = vm();
+    let out = Shared::new(usize::MAX);
+    vm.set_trace(TraceConfig::vcd(out.clone()));
+    vm.clear_trace();
+    assert_eq!(out.text(), HEADER);
+}
+
+#[test]
+fn write_errors_stop_the_dump() {
+    let mut vm = vm();
+    let out = Shared::new(HEADER.len() + 40);
+    vm.set_trace(TraceConfig::vcd(out.clone()));
+    vm.run_cycles(10).unwrap();
+    assert_eq!(vm.take_trace_error().unwrap().to_string(), "full");
+    assert!(out.text().starts_with(HEADER));
+    assert_eq!(vm.read(0x1234), 0x21, "the program still ran");
+}
@@ -0,0 +1,192 @@
+mod common;
+
+use common::vm_with;
+use emulator::vm::CLOCK_HZ;
+use emulator::{BusDevice, CpuConfig, IllegalOpcodes, Registers, ResetKind, Vm, VmError};
+
+#[test]
+fn reset_starts_at_reset_vector() {
+    let vm = vm_with(&[]);
+    assert_eq!(vm.registers().pc, 0x8000);
+    assert_eq!(vm.cycles(), 0);
+}
+
+#[test]
+fn step_executes_program() {
+    // LDA #$05; ADC $10
+    let mut vm = vm_with(&[0xA9, 0x05, 0x65, 0x10]);
+    vm.write(0x10, 0x0A);
+    vm.step().unwrap();
+    vm.step().unwrap();
+    assert_eq!(vm.registers().a, 15);
+    assert_eq!(vm.registers().pc, 0x8004);
+}
+
+#[test]
+fn illegal_opcode_is_an_error() {
+    let mut vm = vm_with(&[0x02]);
+    assert_eq!(
+        vm.step(),
+        Err(VmError::IllegalOpcode {
+            pc: 0x8000,
+            opcode: 0x02
+        })
+    );
+}
+
+/// Reads 0x02, an illegal opcode, at every offset.
+struct Jam;
+
+impl BusDevice for Jam {
+    fn read8(&mut self, _: u16) -> u8 {
+        0x02
+    }
+
+    fn write8(&mut self, _: u16, _: u8) {}
+}
+
+#[test]
+fn illegal_opcodes_report_the_byte_fetched() {
+    // LDA #$05, with $8000-$80FF mirroring $1000-$10FF, which jams.
+    let mut vm = vm_with(&[0xA9, 0x05]);
+    vm.write(0x1000, 0x03);
+    vm.mirror(0x8000..=0x80FF, 0x1000).unwrap();
+    let jam = |pc, opcode| Err(VmError::IllegalOpcode { pc, opcode });
+    assert_eq!(vm.step(), jam(0x8000, 0x03));
+
+    vm.bus_mut().map(0x9000..=0x90FF, Jam).unwrap();
+    for run in [Vm::step, |vm: &mut Vm| vm.run_cycles(100)] {
+        vm.set_registers(Registers {
+            pc: 0x9000,
+            ..vm.registers()
+        });
+        assert_eq!(run(&mut vm), jam(0x9000, 0x02));
+    }
+}
+
+#[test]
+fn illegal_opcodes_follow_the_cpu_config() {
+    // NOP $1234 on a 6502; a jam; then LDA #$05.
+    let mut vm = vm_with(&[0x0C, 0x34, 0x12, 0x02, 0xA9, 0x05]);
+    let undocumented = CpuConfig {
+        clock_hz: Some(CLOCK_HZ),
+        illegal_opcodes: IllegalOpcodes::Undocumented,
+    };
+    assert_eq!(vm.cpu_config().illegal_opcodes, IllegalOpcodes::Trap);
+    vm.set_cpu_config(undocumented);
+    vm.reset(ResetKind::Soft);
+    assert_eq!(vm.cpu_config(), undocumented, "resets keep it");
+    assert_eq!(
+        vm.run_cycles(100),
+        Err(VmError::IllegalOpcode {
+            pc: 0x8003,
+            opcode: 0x02
+        })
+    );
+
+    vm.set_cpu_config(CpuConfig {
+        illegal_opcodes: IllegalOpcodes::Nop,
+        ..CpuConfig::default()
+    });
+    vm.reset(ResetKind::Soft);
+    vm.run_cycles(2 + 2 + 2 + 2 + 2).unwrap();
+    assert_eq!(vm.registers().pc, 0x8006);
+    assert_eq!(vm.registers().a, 0x05);
+    assert_eq!(vm.stats().instructions, 1 + 5, "four one-byte NOPs and LDA");
+}
+
+#[test]
+fn load_rejects_overrun() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        vm.load(0xFFFF, &[1, 2]),
+        Err(VmError::OutOfBounds {
+            addr: 0xFFFF,
+            len: 2
+        })
+    );
+    vm.load(0xFFFE, &[1, 2]).unwrap();
+    assert_eq!(vm.read(0xFFFF), 2);
+}
+
+#[test]
+fn set_registers_round_trips() {
+    let mut vm = Vm::new();
+    let regs = Registers {
+        a: 1,
+        x: 2,
+        y: 3,
+        pc: 0x1234,
+        sp: 0xF0,
+        flags: 0x81,
+    };
+    vm.set_registers(regs);
+    assert_eq!(vm.registers(), regs);
+}
+
+/// The same machine twice: one to run in batches, one to step.
+fn twins(program: &[u8]) -> (Vm, Vm) {
+    (vm_with(program), vm_with(program))
+}
+
+/// Steps until at least `cycles` more cycles have elapsed, as
+/// `run_cycles` promises to.
+fn step_cycles(vm: &mut Vm, cycles: u32) -> Result<(), VmError> {
+    let end = vm.cycles() + cycles;
+    while vm.cycles() < end {
+        vm.step()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn run_cycles_matches_stepping() {
+    // LDA #$01; LSR $2500 (strobes the controller); ADC $2500; LDX $10
+    let block = [0xA9, 0x01, 0x4E, 0x00, 0x25, 0x6D, 0x00, 0x25, 0xA6, 0x10];
+    let (mut batched, mut stepped) = twins(&block.repeat(200));
+    for vm in [&mut batched, &mut stepped] {
+        vm.set_buttons(0b1011);
+        vm.write(0x10, 0x77);
+    }
+    batched.run_cycles(1000).unwrap();
+    step_cycles(&mut stepped, 1000).unwrap();
+    assert_eq!(batched.cycles(), stepped.cycles());
+    assert_eq!(batched.registers(), stepped.registers());
+    assert!(batched.save_state() == stepped.save_state());
+}
+
+#[test]
+fn run_cycles_overshoots_by_at_most_one_instruction() {
+    // LDA #$05 takes 2 cycles.
+    let mut vm = vm_with(&[0xA9, 0x05].repeat(10));
+    vm.run_cycles(3).unwrap();
+    assert_eq!(vm.cycles(), 4);
+    assert_eq!(vm.registers().pc, 0x8004);
+    vm.run_cycles(0).unwrap();
+    assert_eq!(vm.cycles(), 4);
+}
+
+#[test]
+fn run_cycles_reports_illegal_opcodes() {
+    let mut vm = vm_with(&[0xA9, 0x05, 0x02]);
+    assert_eq!(
+        vm.run_cycles(100),
+        Err(VmError::IllegalOpcode {
+            pc: 0x8002,
+            opcode: 0x02
+        })
+    );
+    assert_eq!(vm.registers().pc, 0x8003);
+}
+
+#[test]
+fn run_frame_matches_stepping() {
+    let (mut batched, mut stepped) = twins(&[0xA5, 0x10, 0x65, 0x11].repeat(0x1000));
+    batched.run_frame().unwrap();
+    // A trace needs every instruction, so this frame is stepped.
+    stepped.set_trace(emulator::TraceConfig::callback(|_| {}));
+    stepped.run_frame().unwrap();
+    assert_eq!(batched.frame(), 1);
+    assert_eq!(batched.cycles(), stepped.cycles());
+    assert_eq!(batched.registers(), stepped.registers());
+}
@@ -0,0 +1,38 @@
+#![cfg(feature = "wasm")]
+
+use emulator::Rom;
+use emulator::wasm::WebVm;
+
+// Only success paths run natively: building a `JsError` needs a JS host.
+
+#[test]
+fn exposes_the_framebuffer_in_place() {
+    let vm = WebVm::new();
+    assert_eq!(
+        vm.framebuffer_len(),
+        WebVm::width() * WebVm::height() * WebVm::bytes_per_pixel()
+    );
+    assert!(!vm.framebuffer_ptr().is_null());
+}
+
+#[test]
+fn switches_pixel_format_and_scale() {
+    let mut vm = WebVm::new();
+    vm.set_display("rgb565", 3).unwrap();
+    assert_eq!(
+        vm.framebuffer_len(),
+        WebVm::width() * 3 * WebVm::height() * 3 * 2
+    );
+}
+
+#[test]
+fn runs_a_rom_frame_by_frame() {
+    // LSR $0300 over the whole bank; one frame is about 2800 of them.
+    let rom = Rom::new(0xC000, &[0x4E, 0x00, 0x03].repeat(5000)).unwrap();
+    let mut vm = WebVm::new();
+    vm.load_rom(&rom.to_bytes()).unwrap();
+    vm.set_button(0, true);
+    vm.set_buttons(0b1000_0001);
+    vm.step_frame().unwrap();
+    assert_eq!(vm.frame(), 1);
+}
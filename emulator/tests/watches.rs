@@ -0,0 +1,59 @@
+mod common;
+
+use common::vm_with;
+use emulator::{Condition, SymbolTable, WatchChange};
+
+/// `LDX #$01`, `LSR $0300`, `LDA #$07` and `LDY #$00`.
+const PROGRAM: [u8; 9] = [0xA2, 0x01, 0x4E, 0x00, 0x03, 0xA9, 0x07, 0xA0, 0x00];
+
+#[test]
+fn stops_report_the_watches_that_changed() {
+    let mut vm = vm_with(&PROGRAM);
+    vm.write(0x0300, 0x08);
+    let mut symbols = SymbolTable::new();
+    symbols.insert("counter", 0x0300);
+    let x = vm.add_watch(Condition::parse("X").unwrap());
+    let counter = vm.add_watch(Condition::parse_with_symbols("[counter]", &symbols).unwrap());
+    let a = vm.add_watch(Condition::parse("A == 7").unwrap());
+    assert_eq!(vm.watches()[1].value, 8, "evaluated when added");
+
+    vm.add_breakpoint(0x8005);
+    vm.run_until_break().unwrap();
+    assert_eq!(
+        vm.watch_changes(),
+        [
+            WatchChange {
+                id: x,
+                old: 0,
+                new: 1
+            },
+            WatchChange {
+                id: counter,
+                old: 8,
+                new: 4
+            },
+        ]
+    );
+
+    vm.step().unwrap();
+    assert_eq!(
+        vm.watch_changes().len(),
+        2,
+        "stepping alone updates nothing"
+    );
+    assert_eq!(
+        vm.update_watches(),
+        [WatchChange {
+            id: a,
+            old: 0,
+            new: 1
+        }]
+    );
+    assert!(vm.update_watches().is_empty());
+
+    assert!(vm.remove_watch(counter));
+    assert!(!vm.remove_watch(counter));
+    let ids: Vec<_> = vm.watches().iter().map(|watch| watch.id).collect();
+    assert_eq!(ids, [x, a]);
+    assert_eq!(vm.watches()[1].expression.to_string(), "A == 7");
+}
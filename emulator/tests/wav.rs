@@ -0,0 +1,125 @@
+mod common;
+
+use std::io::{self, Cursor, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+
+use common::vm_with;
+use emulator::Vm;
+use emulator::audio::{AUDIO_ENABLE, ENABLE_SQUARE1, SQUARE1_CTRL, SQUARE1_PERIOD};
+
+/// A machine playing a square wave while it runs `LSR $0300`.
+fn vm() -> Vm {
+    let mut vm = vm_with(&[0x4E, 0x00, 0x03].repeat(0x2000));
+    vm.set_clock_hz(6_000);
+    vm.set_sample_rate(3_000);
+    vm.load(SQUARE1_PERIOD, &[0x01, 0x00]).unwrap();
+    vm.write(SQUARE1_CTRL, 0x2F);
+    vm.write(AUDIO_ENABLE, ENABLE_SQUARE1);
+    vm
+}
+
+/// A seekable writer the test can still read after handing it to the `Vm`,
+/// failing every write once `fail` is set.
+#[derive(Clone, Default)]
+struct Shared {
+    out: Arc<Mutex<Cursor<Vec<u8>>>>,
+    fail: Arc<Mutex<bool>>,
+}
+
+impl Shared {
+    fn bytes(&self) -> Vec<u8> {
+        self.out.lock().unwrap().get_ref().clone()
+    }
+}
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if *self.fail.lock().unwrap() {
+            return Err(io::Error::other("full"));
+        }
+        self.out.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for Shared {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.out.lock().unwrap().seek(pos)
+    }
+}
+
+fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes[..4].try_into().unwrap())
+}
+
+/// Checks the WAV header and returns the rate and the samples.
+fn parse(wav: &[u8]) -> (u32, Vec<i16>) {
+    assert_eq!(&wav[..4], b"RIFF");
+    assert_eq!(le32(&wav[4..]) as usize, wav.len() - 8);
+    assert_eq!(&wav[8..16], b"WAVEfmt ");
+    assert_eq!(le32(&wav[16..]), 16);
+    assert_eq!(wav[20..24], [1, 0, 1, 0], "mono PCM");
+    let rate = le32(&wav[24..]);
+    assert_eq!(le32(&wav[28..]), rate * 2);
+    assert_eq!(wav[32..36], [2, 0, 16, 0]);
+    assert_eq!(&wav[36..40], b"data");
+    assert_eq!(le32(&wav[40..]) as usize, wav.len() - 44);
+    let samples = wav[44..]
+        .chunks_exact(2)
+        .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+    (rate, samples)
+}
+
+#[test]
+fn captures_what_the_callback_hears() {
+    let mut vm = vm();
+    let heard = Arc::new(Mutex::new(Vec::new()));
+    let sink = heard.clone();
+    vm.set_audio_callback(move |samples| sink.lock().unwrap().extend_from_slice(samples));
+    let out = Shared::default();
+    vm.start_audio_capture(out.clone()).unwrap();
+    for _ in 0..3 {
+        vm.run_frame().unwrap();
+    }
+    vm.stop_audio_capture().unwrap();
+    vm.run_frame().unwrap();
+
+    let (rate, samples) = parse(&out.bytes());
+    assert_eq!(rate, 3_000);
+    assert_eq!(samples.len(), 150);
+    assert_eq!(samples, heard.lock().unwrap()[..150]);
+    assert_eq!(samples[..2], [15 * 682, 15 * 682]);
+}
+
+#[test]
+fn captures_without_a_callback_and_finishes_when_dropped() {
+    let mut vm = vm();
+    let out = Shared::default();
+    vm.start_audio_capture(out.clone()).unwrap();
+    vm.run_frame().unwrap();
+    drop(vm);
+
+    let (_, samples) = parse(&out.bytes());
+    assert_eq!(samples.len(), 50);
+    assert!(samples.iter().any(|&s| s != 0));
+}
+
+#[test]
+fn write_errors_stop_the_capture() {
+    let mut vm = vm();
+    let out = Shared::default();
+    vm.start_audio_capture(out.clone()).unwrap();
+    vm.run_frame().unwrap();
+    *out.fail.lock().unwrap() = true;
+    vm.run_frame().unwrap();
+    *out.fail.lock().unwrap() = false;
+    vm.run_frame().unwrap();
+
+    assert_eq!(vm.stop_audio_capture().unwrap_err().to_string(), "full");
+    assert_eq!(out.bytes().len(), 44 + 100, "only the first frame");
+    assert!(vm.stop_audio_capture().is_ok());
+}
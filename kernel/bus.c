@@ -1,10 +1,80 @@
 /*
  * rvm-8/kernel/bus.c
  *
- * Memory bus implementation for rvm-8 (currently a stub).
+ * Memory bus implementation for rvm-8.
  *
  * Copyright (c) 2025 foxomax
  * SPDX-License-Identifier: MIT
+ *
+ * Notes:
+ * - All CPU memory traffic goes through mem_read/mem_write. Hosts can
+ *   observe it, or map their own devices over it, by installing a
+ *   bus_hook; the per-page hook_pages filter keeps plain RAM accesses
+ *   down to a single table lookup.
+ * - Slow memory is modelled per page: wait_pages adds its cycles to
+ *   every access, so instructions touching it take longer.
+ * - Stores to RAM mark their page in dirty_pages, so a host taking
+ *   snapshots only copies the pages that changed since the last one.
+ */
+
+#include "cpu.h"
+#include <stddef.h>
+
+/**
+ * @brief Decodes an address through the page map.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @param addr The 16-bit address the CPU put on the bus.
+ * @return The address with its page replaced by page_map's entry for it.
+ */
+uint16_t mem_decode(const CPU *cpu, uint16_t addr) {
+  return (uint16_t)(cpu->page_map[addr >> 8] << 8 | (addr & 0xFF));
+}
+
+/**
+ * @brief Reads a byte from memory.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @param addr The 16-bit address to read from.
+ * @return The byte value at the specified address, or the one supplied by
+ *         a device claiming it.
  */
+uint8_t mem_read(CPU *cpu, uint16_t addr) {
+  cpu->stats.reads++;
+  addr = mem_decode(cpu, addr);
+  cpu->cycles += cpu->wait_pages[addr >> 8];
+  uint8_t val = cpu->memory[addr];
+
+  if (cpu->bus_hook != NULL && cpu->hook_pages[addr >> 8] &&
+      cpu->bus_hook(cpu->bus_ctx, BUS_READ, addr, &val))
+    cpu->bus_claimed = 1;
+
+  return val;
+}
+
+/**
+ * @brief Writes a byte to a specified memory address.
+ *
+ * This function writes the given value to the memory at the specified address,
+ * unless a device mapped through bus_hook claims the write or the page is ROM.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @param addr The 16-bit memory address to write to.
+ * @param val The 8-bit value to write.
+ */
+void mem_write(CPU *cpu, uint16_t addr, uint8_t val) {
+  cpu->stats.writes++;
+  addr = mem_decode(cpu, addr);
+  cpu->cycles += cpu->wait_pages[addr >> 8];
+  if (cpu->bus_hook != NULL && cpu->hook_pages[addr >> 8] &&
+      cpu->bus_hook(cpu->bus_ctx, BUS_WRITE, addr, &val)) {
+    cpu->bus_claimed = 1;
+    return;
+  }
+
+  if (cpu->rom_pages[addr >> 8])
+    return;
 
-// ...file intentionally left as a stub for now...
+  cpu->memory[addr] = val;
+  cpu->dirty_pages[addr >> 8] = 1;
+}
@@ -13,35 +13,12 @@
  */
 
 #include "cpu.h"
-#include <stdio.h>
 #include <string.h>
 
-/**
- * @brief Reads a byte from memory.
- *
- * @param cpu Pointer to the CPU instance.
- * @param addr The 16-bit address to read from.
- * @return The byte value at the specified address.
- */
-uint8_t mem_read(CPU *cpu, uint16_t addr) { return cpu->memory[addr]; }
-
-/**
- * @brief Writes a byte to a specified memory address.
- *
- * This function writes the given value to the memory at the specified address.
- *
- * @param cpu Pointer to the CPU instance.
- * @param addr The 16-bit memory address to write to.
- * @param val The 8-bit value to write.
- */
-void mem_write(CPU *cpu, uint16_t addr, uint8_t val) {
-  cpu->memory[addr] = val;
-}
-
 /**
  * @brief Initializes the CPU state.
  *
- * Sets up the CPU registers, memory pointer, and instruction table.
+ * Sets up the CPU registers and memory pointer.
  *
  * @param cpu Pointer to the CPU structure to initialize.
  * @param memory Pointer to the memory buffer.
@@ -49,18 +26,18 @@ void mem_write(CPU *cpu, uint16_t addr, uint8_t val) {
 void cpu_init(CPU *cpu, uint8_t *memory) {
   uint8_t *mem = memory;
 
-  init_instruction_table();
-
   memset(cpu, 0, sizeof(CPU));
   cpu->memory = mem;
+  for (int page = 0; page < 256; page++)
+    cpu->page_map[page] = (uint8_t)page;
   cpu->a = 0;
   cpu->x = 0;
   cpu->y = 0;
   cpu->sp = 0xFD;
   cpu->flags = FLAG_I;
 
-  uint16_t lo = cpu->memory[0xFFFC];
-  uint16_t hi = cpu->memory[0xFFFD];
+  uint16_t lo = cpu->memory[mem_decode(cpu, 0xFFFC)];
+  uint16_t hi = cpu->memory[mem_decode(cpu, 0xFFFD)];
 
   cpu->pc = (hi << 8) | lo;
   cpu->cycles = 0;
@@ -75,8 +52,8 @@ void cpu_init(CPU *cpu, uint8_t *memory) {
  * @param cpu Pointer to the CPU instance.
  */
 void cpu_reset(CPU *cpu) {
-  uint16_t lo = cpu->memory[0xFFFC];
-  uint16_t hi = cpu->memory[0xFFFD];
+  uint16_t lo = cpu->memory[mem_decode(cpu, 0xFFFC)];
+  uint16_t hi = cpu->memory[mem_decode(cpu, 0xFFFD)];
 
   cpu->pc = (hi << 8) | lo;
   cpu->flags = FLAG_I;
@@ -85,25 +62,136 @@ void cpu_reset(CPU *cpu) {
   cpu->x = 0;
   cpu->y = 0;
   cpu->cycles = 0;
+  // Latched interrupts are lost, and an NMI line held through the reset
+  // is no edge.
+  cpu->int_state = cpu->nmi ? INT_NMI_LEVEL : 0;
+}
+
+/**
+ * @brief Pushes a byte onto the page-one stack.
+ */
+static void push(CPU *cpu, uint8_t val) {
+  mem_write(cpu, 0x0100 | (cpu->sp & 0xFF), val);
+  cpu->sp = (cpu->sp - 1) & 0xFF;
+}
+
+/**
+ * @brief Enters the interrupt handler at `vector`, saving the PC and flags.
+ */
+static void enter_interrupt(CPU *cpu, uint16_t vector) {
+  push(cpu, cpu->pc >> 8);
+  push(cpu, lo8(cpu->pc));
+  push(cpu, cpu->flags & ~FLAG_B);
+  cpu->flags |= FLAG_I;
+
+  uint16_t lo = mem_read(cpu, vector);
+  uint16_t hi = mem_read(cpu, vector + 1);
+
+  cpu->pc = (hi << 8) | lo;
+  cpu->cycles += IRQ_CYCLES;
+}
+
+/**
+ * @brief Samples the interrupt lines at the end of a step, with `flags` as
+ * the I flag is seen by the poll.
+ *
+ * A latched NMI stays latched until it is taken; the IRQ is re-sampled
+ * every time because its line is level-sensitive.
+ */
+static void poll_interrupts(CPU *cpu, uint8_t flags) {
+  uint8_t state = cpu->int_state & INT_NMI;
+
+  if (cpu->nmi) {
+    if (!(cpu->int_state & INT_NMI_LEVEL))
+      state |= INT_NMI;
+    state |= INT_NMI_LEVEL;
+  }
+  if (flags & FLAG_I)
+    state |= INT_IRQ_MASKED;
+  else if (cpu->irq)
+    state |= INT_IRQ;
+  cpu->int_state = state;
+}
+
+void cpu_poll_irq(CPU *cpu) {
+  cpu->int_state &= ~INT_IRQ;
+  if (cpu->irq && !(cpu->int_state & INT_IRQ_MASKED))
+    cpu->int_state |= INT_IRQ;
+}
+
+/**
+ * @brief Picks what an opcode without a handler executes under the CPU's
+ * illegal_mode; the returned handler is NULL where it traps.
+ */
+static Instruction illegal_instruction(const CPU *cpu, uint8_t opcode) {
+  switch (cpu->illegal_mode) {
+  case ILLEGAL_NOP:
+    return illegal_nop;
+  case ILLEGAL_UNDOCUMENTED:
+    return undocumented_table[opcode];
+  default:
+    return instruction_table[opcode];
+  }
 }
 
 /**
  * @brief Executes a single CPU instruction.
  *
  * This function fetches an opcode from memory, looks up the corresponding
- * instruction, and executes its handler. It also handles illegal opcodes.
+ * instruction, and executes its handler, or the host's ext_handler for an
+ * extension opcode. Illegal opcodes are reported to the caller instead of
+ * being executed, unless illegal_mode says otherwise. An interrupt latched
+ * by the previous step is entered in place of the instruction.
  *
  * @param cpu Pointer to the CPU instance.
+ * @return RVM_OK, or RVM_ILLEGAL_OPCODE if the opcode has no handler.
  */
-void cpu_step(CPU *cpu) {
-  uint8_t opcode = mem_read(cpu, cpu->pc++);
-  Instruction instr = instruction_table[opcode];
+int cpu_step(CPU *cpu) {
+  uint32_t start = cpu->cycles;
+  uint8_t flags = cpu->flags;
+  int status = RVM_OK;
 
-  if (instr.handler == NULL) {
-    printf("Illegal opcode 0x%02X at PC 0x%04X\n", opcode, cpu->pc - 1);
-    return;
+  if (cpu->int_state & (INT_NMI | INT_IRQ)) {
+    uint16_t vector = cpu->int_state & INT_NMI ? NMI_VECTOR : IRQ_VECTOR;
+    cpu->int_state &= INT_NMI_LEVEL;
+    enter_interrupt(cpu, vector);
+    cpu->stats.irqs++;
+    // The poll at the end of the entry sees the I flag it just set.
+    flags = cpu->flags;
+  } else {
+    uint8_t opcode = mem_read(cpu, cpu->pc++);
+    Instruction instr = instruction_table[opcode];
+    int extension = cpu->ext_handler != NULL && cpu->ext_opcodes[opcode];
+
+    if (instr.handler == NULL && !extension)
+      instr = illegal_instruction(cpu, opcode);
+    if (instr.handler != NULL) {
+      cpu->cycles += instr.handler(cpu, instr.mode);
+      cpu->stats.opcodes[opcode]++;
+    } else if (extension) {
+      cpu->cycles += cpu->ext_handler(cpu->ext_ctx, cpu, opcode);
+      cpu->stats.opcodes[opcode]++;
+    } else {
+      status = RVM_ILLEGAL_OPCODE;
+    }
   }
+  poll_interrupts(cpu, flags);
+  cpu->stats.cycles += (uint32_t)(cpu->cycles - start);
+  return status;
+}
+
+int cpu_run(CPU *cpu, uint32_t budget) {
+  uint32_t start = cpu->cycles;
 
-  uint8_t cycles = instr.handler(cpu, instr.mode);
-  cpu->cycles += cycles;
-}
\ No newline at end of file
+  cpu->bus_claimed = 0;
+  while ((uint32_t)(cpu->cycles - start) < budget) {
+    int status = cpu_step(cpu);
+    if (status != RVM_OK)
+      return status;
+    if (cpu->bus_claimed) {
+      cpu->bus_claimed = 0;
+      break;
+    }
+  }
+  return RVM_OK;
+}
@@ -10,8 +10,6 @@
 #include <stdio.h>
 #include <string.h>
 
-Instruction instruction_table[256];
-
 /**
  * @brief Reads the address for the immediate addressing mode.
  *
@@ -138,6 +136,7 @@ uint8_t handler_lda(CPU *cpu, AddressingMode mode) {
     }
 
     value = mem_read(cpu, addr);
+    cycles_used += 5;
     break;
   }
   default:
@@ -250,16 +249,90 @@ uint8_t handler_ldy(CPU *cpu, AddressingMode mode) {
       return 0;
   }
 
-  cpu->x = lo8(value);
+  cpu->y = lo8(value);
   return cycles_used;
 }
 
+static void set_flag(CPU *cpu, uint8_t flag, int on) {
+  if (on)
+    cpu->flags |= flag;
+  else
+    cpu->flags &= ~flag;
+}
+
+/**
+ * @brief Adds a value and the carry flag to the accumulator.
+ *
+ * In binary mode Z, N, V and C describe the sum. While FLAG_D is set the
+ * operands are packed BCD and the result and C are those of the decimal
+ * sum. The other flags follow the NMOS 6502: Z still reflects the binary
+ * sum, and N and V the intermediate result once the low digit has been
+ * adjusted, so they are only meaningful for valid BCD operands.
+ */
+static void adc_op(CPU *cpu, uint8_t value) {
+  uint8_t carry_in = (cpu->flags & FLAG_C) ? 1 : 0;
+  uint16_t result_16 = (uint16_t)cpu->a + (uint16_t)value + (uint16_t)carry_in;
+  uint8_t final_result = lo8(result_16);
+
+  set_flag(cpu, FLAG_Z, final_result == 0);
+  if (!(cpu->flags & FLAG_D)) {
+    set_flag(cpu, FLAG_N, final_result & 0x80);
+    set_flag(cpu, FLAG_V, ~(cpu->a ^ value) & (cpu->a ^ final_result) & 0x80);
+    set_flag(cpu, FLAG_C, result_16 & 0x100);
+    cpu->a = final_result;
+    return;
+  }
+
+  int low = (cpu->a & 0x0F) + (value & 0x0F) + carry_in;
+  if (low >= 0x0A)
+    low = ((low + 0x06) & 0x0F) + 0x10;
+  int sum = (cpu->a & 0xF0) + (value & 0xF0) + low;
+  int signed_sum = (int8_t)(cpu->a & 0xF0) + (int8_t)(value & 0xF0) + low;
+
+  set_flag(cpu, FLAG_N, sum & 0x80);
+  set_flag(cpu, FLAG_V, signed_sum < -128 || signed_sum > 127);
+  if (sum >= 0xA0)
+    sum += 0x60;
+  set_flag(cpu, FLAG_C, sum >= 0x100);
+  cpu->a = lo8(sum);
+}
+
+/**
+ * @brief Subtracts a value and the borrow (inverted carry) from the
+ * accumulator.
+ *
+ * Z, N, V and C always describe the binary difference, as on the NMOS
+ * 6502; while FLAG_D is set the result is the packed BCD difference.
+ */
+static void sbc_op(CPU *cpu, uint8_t value) {
+  uint8_t carry_in = (cpu->flags & FLAG_C) ? 1 : 0;
+  uint16_t result_16 =
+      (uint16_t)cpu->a + (uint16_t)(value ^ 0xFF) + (uint16_t)carry_in;
+  uint8_t final_result = lo8(result_16);
+
+  set_flag(cpu, FLAG_Z, final_result == 0);
+  set_flag(cpu, FLAG_N, final_result & 0x80);
+  set_flag(cpu, FLAG_V, (cpu->a ^ value) & (cpu->a ^ final_result) & 0x80);
+  set_flag(cpu, FLAG_C, result_16 & 0x100);
+
+  if (cpu->flags & FLAG_D) {
+    int low = (cpu->a & 0x0F) - (value & 0x0F) + carry_in - 1;
+    if (low < 0)
+      low = ((low - 0x06) & 0x0F) - 0x10;
+    int difference = (cpu->a & 0xF0) - (value & 0xF0) + low;
+    if (difference < 0)
+      difference -= 0x60;
+    final_result = lo8(difference);
+  }
+  cpu->a = final_result;
+}
+
 /**
  * @brief Handles the ADC (Add with Carry) instruction.
  *
  * This function adds a value from memory and the carry flag to the
- * accumulator. It updates the Zero (Z), Negative (N), Overflow (V), and
- * Carry (C) flags based on the result of the addition.
+ * accumulator, in binary or, while FLAG_D is set, decimal. It updates the
+ * Zero (Z), Negative (N), Overflow (V), and Carry (C) flags; see adc_op.
  *
  * @param cpu Pointer to the CPU instance.
  * @param mode The addressing mode used by the instruction.
@@ -283,29 +356,102 @@ uint8_t handler_adc(CPU *cpu, AddressingMode mode) {
     return 0;
   }
 
-  uint8_t value = mem_read(cpu, addr);
-  uint8_t carry_in = (cpu->flags & FLAG_C) ? 1 : 0;
-  uint16_t result_16 = (uint16_t)cpu->a + (uint16_t)value + (uint16_t)carry_in;
-  uint8_t final_result = lo8(result_16);
+  adc_op(cpu, mem_read(cpu, addr));
+  return 2;
+}
 
-  if (final_result == 0)
-    cpu->flags |= FLAG_Z;
-  else
-    cpu->flags &= ~FLAG_Z;
-  if (final_result & 0x80)
-    cpu->flags |= FLAG_N;
-  else
-    cpu->flags &= ~FLAG_N;
-  if (~(cpu->a ^ value) & (cpu->a ^ final_result) & 0x80)
-    cpu->flags |= FLAG_V;
-  else
-    cpu->flags &= ~FLAG_V;
-  if (result_16 & 0x100)
-    cpu->flags |= FLAG_C;
-  else
-    cpu->flags &= ~FLAG_C;
+/**
+ * @brief Handles the SBC (Subtract with Carry) instruction.
+ *
+ * This function subtracts a value from memory and the inverted carry flag
+ * from the accumulator, in binary or, while FLAG_D is set, decimal. It
+ * updates the Zero (Z), Negative (N), Overflow (V), and Carry (C) flags;
+ * see sbc_op.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @param mode The addressing mode used by the instruction.
+ * @return The number of cycles consumed by the instruction.
+ */
+uint8_t handler_sbc(CPU *cpu, AddressingMode mode) {
+  uint16_t addr;
+  uint8_t cycles_used;
 
-  cpu->a = final_result;
+  switch (mode) {
+  case MODE_IMMEDIATE:
+    addr = addr_immediate(cpu);
+    cycles_used = 2;
+    break;
+  case MODE_ZEROPAGE:
+    addr = addr_zeropage(cpu);
+    cycles_used = 3;
+    break;
+  case MODE_ABSOLUTE:
+    addr = addr_absolute(cpu);
+    cycles_used = 4;
+    break;
+  default:
+    printf("Unimplemented addressing mode\n");
+    return 0;
+  }
+
+  sbc_op(cpu, mem_read(cpu, addr));
+  return cycles_used;
+}
+
+/**
+ * @brief Handles the SED (Set Decimal) instruction, which turns on decimal
+ * arithmetic for ADC and SBC.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @param mode The addressing mode used by the instruction (implied).
+ * @return The number of cycles consumed by the instruction.
+ */
+uint8_t handler_sed(CPU *cpu, AddressingMode mode) {
+  (void)mode;
+  cpu->flags |= FLAG_D;
+  return 2;
+}
+
+/**
+ * @brief Handles the CLD (Clear Decimal) instruction, which returns ADC
+ * and SBC to binary arithmetic.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @param mode The addressing mode used by the instruction (implied).
+ * @return The number of cycles consumed by the instruction.
+ */
+uint8_t handler_cld(CPU *cpu, AddressingMode mode) {
+  (void)mode;
+  cpu->flags &= ~FLAG_D;
+  return 2;
+}
+
+/**
+ * @brief Handles the CLI (Clear Interrupt Disable) instruction, which
+ * unmasks IRQs. The poll at its own end still sees them masked, so an IRQ
+ * is taken after the next instruction at the earliest.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @param mode The addressing mode used by the instruction (implied).
+ * @return The number of cycles consumed by the instruction.
+ */
+uint8_t handler_cli(CPU *cpu, AddressingMode mode) {
+  (void)mode;
+  cpu->flags &= ~FLAG_I;
+  return 2;
+}
+
+/**
+ * @brief Handles the SEI (Set Interrupt Disable) instruction, which masks
+ * IRQs; one the poll at its end still saw unmasked is taken right after it.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @param mode The addressing mode used by the instruction (implied).
+ * @return The number of cycles consumed by the instruction.
+ */
+uint8_t handler_sei(CPU *cpu, AddressingMode mode) {
+  (void)mode;
+  cpu->flags |= FLAG_I;
   return 2;
 }
 
@@ -324,19 +470,22 @@ uint8_t handler_lsr(CPU *cpu, AddressingMode mode) {
       value = lsr_op(cpu, value);
       mem_write(cpu, addr, value);
       cycles_used = 5;
+      break;
     case MODE_ZEROPAGE_X:
       addr = (addr_zeropage(cpu) + cpu->x) & 0xFF;
       value = mem_read(cpu, addr);
       value = lsr_op(cpu, value);
       mem_write(cpu, addr, value);
       cycles_used = 6;
+      break;
     case MODE_ABSOLUTE:
       addr = addr_absolute(cpu);
       value = mem_read(cpu, addr);
       value = lsr_op(cpu, value);
       mem_write(cpu, addr, value);
-     cycles_used = 6;
-    case MODE_ABSOLUTE_X:
+      cycles_used = 6;
+      break;
+    case MODE_ABSOLUTE_X: {
       uint16_t base_addr = addr_absolute(cpu);
       addr = base_addr + cpu->x;
       if ((base_addr & 0xFF00) != (addr & 0xFF00)) {
@@ -346,6 +495,8 @@ uint8_t handler_lsr(CPU *cpu, AddressingMode mode) {
       value = lsr_op(cpu, value);
       mem_write(cpu, addr, value);
       cycles_used += 7;
+      break;
+    }
     default:
       printf("Unimplemented addressing mode\n");
       return 0;
@@ -354,47 +505,89 @@ uint8_t handler_lsr(CPU *cpu, AddressingMode mode) {
 }
 
 /**
- * @brief Initializes the instruction table.
+ * @brief Handles the undocumented NOPs, and illegal opcodes under
+ * ILLEGAL_NOP.
+ *
+ * Operands are fetched and, outside immediate mode, the address they form
+ * is read and the value dropped, as on the 6502.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @param mode The addressing mode used by the instruction.
+ * @return The number of cycles consumed by the instruction.
+ */
+uint8_t handler_nop(CPU *cpu, AddressingMode mode) {
+  switch (mode) {
+  case MODE_IMMEDIATE:
+    mem_read(cpu, addr_immediate(cpu));
+    return 2;
+  case MODE_ZEROPAGE:
+    mem_read(cpu, addr_zeropage(cpu));
+    return 3;
+  case MODE_ZEROPAGE_X:
+    mem_read(cpu, (addr_zeropage(cpu) + cpu->x) & 0xFF);
+    return 4;
+  case MODE_ABSOLUTE:
+    mem_read(cpu, addr_absolute(cpu));
+    return 4;
+  case MODE_ABSOLUTE_X: {
+    uint16_t base_addr = addr_absolute(cpu);
+    uint16_t addr = base_addr + cpu->x;
+    mem_read(cpu, addr);
+    return (base_addr & 0xFF00) != (addr & 0xFF00) ? 5 : 4;
+  }
+  default:
+    return 2;
+  }
+}
+
+/**
+ * @brief Handles the undocumented LAX instruction.
  *
- * This function populates the instruction table with all the implemented
- * opcodes, their handlers, addressing modes, and cycle counts.
+ * Loads a byte into both A and X, updating Z and N. It is LDA and LDX at
+ * once and takes the cycles of the load that has its addressing mode.
+ *
+ * @param cpu Pointer to the CPU instance.
+ * @param mode The addressing mode used by the instruction.
+ * @return The number of cycles consumed by the instruction.
  */
-void init_instruction_table() {
-  for (int i = 0; i < 256; i++)
-    instruction_table[i] = (Instruction){"???", NULL, MODE_IMPLIED, 0};
-
-  instruction_table[0xA9] =
-      (Instruction){"LDA", handler_lda, MODE_IMMEDIATE, 2};
-  instruction_table[0xA5] = (Instruction){"LDA", handler_lda, MODE_ZEROPAGE, 3};
-  instruction_table[0xAD] = (Instruction){"LDA", handler_lda, MODE_ABSOLUTE, 4};
-  instruction_table[0xB5] =
-      (Instruction){"LDA", handler_lda, MODE_ZEROPAGE_X, 4};
-  instruction_table[0xBD] =
-      (Instruction){"LDA", handler_lda, MODE_ABSOLUTE_X, 4};
-  instruction_table[0xB9] =
-      (Instruction){"LDA", handler_lda, MODE_ABSOLUTE_Y, 4};
-  instruction_table[0xA1] =
-      (Instruction){"LDA", handler_lda, MODE_INDIRECT_X, 2};
-  instruction_table[0xB1] =
-      (Instruction){"LDA", handler_lda, MODE_INDIRECT_Y, 2};
-  instruction_table[0xA2] =
-      (Instruction){"LDX", handler_ldx, MODE_IMMEDIATE, 2};
-  instruction_table[0xA6] = (Instruction){"LDX", handler_ldx, MODE_ZEROPAGE, 3};
-  instruction_table[0xAE] = (Instruction){"LDX", handler_ldx, MODE_ABSOLUTE, 4};
-  instruction_table[0xB6] = (Instruction){"LDX", handler_ldx, MODE_ZEROPAGE_Y, 4};
-  instruction_table[0xBE] = (Instruction){"LDX", handler_ldx, MODE_ABSOLUTE_Y, 4};
-  instruction_table[0xA0] = (Instruction){"LDY", handler_ldy, MODE_IMMEDIATE, 2};
-  instruction_table[0xA4] = (Instruction){"LDY", handler_ldy, MODE_ZEROPAGE, 3};
-  instruction_table[0xB4] = (Instruction){"LDY", handler_ldy, MODE_ZEROPAGE_X, 4};
-  instruction_table[0xAC] = (Instruction){"LDY", handler_ldy, MODE_ABSOLUTE, 4};
-  instruction_table[0xBC] = (Instruction){"LDY", handler_ldy, MODE_ABSOLUTE_X, 4};
-  instruction_table[0x4A] = (Instruction){"LSR", handler_lsr, MODE_ACCUMULATOR, 2};
-  instruction_table[0x46] = (Instruction){"LSR", handler_lsr, MODE_ZEROPAGE, 5};
-  instruction_table[0x56] = (Instruction){"LSR", handler_lsr, MODE_ZEROPAGE_X, 6};
-  instruction_table[0x4E] = (Instruction){"LSR", handler_lsr, MODE_ABSOLUTE, 6};
-  instruction_table[0x5E] = (Instruction){"LSR", handler_lsr, MODE_ABSOLUTE_X, 7};
-  instruction_table[0x69] =
-      (Instruction){"ADC", handler_adc, MODE_IMMEDIATE, 2};
-  instruction_table[0x65] = (Instruction){"ADC", handler_adc, MODE_ZEROPAGE, 3};
-  instruction_table[0x6D] = (Instruction){"ADC", handler_adc, MODE_ABSOLUTE, 4};
+uint8_t handler_lax(CPU *cpu, AddressingMode mode) {
+  uint8_t cycles_used;
+
+  if (mode == MODE_ZEROPAGE_Y || mode == MODE_ABSOLUTE_Y) {
+    cycles_used = handler_ldx(cpu, mode);
+    cpu->a = cpu->x;
+  } else {
+    cycles_used = handler_lda(cpu, mode);
+    cpu->x = cpu->a;
+  }
+
+  cpu->flags &= ~(FLAG_Z | FLAG_N);
+  if (cpu->a == 0)
+    cpu->flags |= FLAG_Z;
+  if (cpu->a & 0x80)
+    cpu->flags |= FLAG_N;
+
+  return cycles_used;
 }
+
+const Instruction illegal_nop = {"NOP", handler_nop, MODE_IMPLIED, 2};
+
+/**
+ * The instruction tables, built at compile time from opcodes.def and
+ * undocumented.def. Opcodes the lists leave out have no handler. Being
+ * constant, the tables are safe to share between CPUs on any number of
+ * threads.
+ */
+const Instruction instruction_table[256] = {
+#define OPCODE(code, name, handler, mode, cycles)                              \
+  [code] = {#name, handler, mode, cycles},
+#include "opcodes.def"
+#undef OPCODE
+};
+
+const Instruction undocumented_table[256] = {
+#define OPCODE(code, name, handler, mode, cycles)                              \
+  [code] = {#name, handler, mode, cycles},
+#include "undocumented.def"
+#undef OPCODE
+};
@@ -0,0 +1,134 @@
+/*
+ * rvm-8/kernel/state.c
+ *
+ * Portable export and import of the CPU state.
+ *
+ * Copyright (c) 2025 foxomax
+ * SPDX-License-Identifier: MIT
+ *
+ * Notes:
+ * - The layout is fixed and little-endian, independent of how the
+ *   compiler lays out the CPU struct, so a state exported by one kernel
+ *   can be imported by another implementation of the same API: the Rust
+ *   core reads and writes the very same bytes.
+ * - Only what the kernel itself keeps goes in. The callbacks, their
+ *   contexts, hook_pages and ext_opcodes belong to the host that installed
+ *   them and stay as they are on import.
+ */
+
+#include "cpu.h"
+#include <string.h>
+
+static const uint8_t state_magic[4] = {'R', '8', 'K', 'S'};
+
+static uint8_t *put16(uint8_t *p, uint16_t v) {
+  p[0] = lo8(v);
+  p[1] = lo8(v >> 8);
+  return p + 2;
+}
+
+static uint8_t *put32(uint8_t *p, uint32_t v) {
+  for (int i = 0; i < 4; i++)
+    p[i] = lo8(v >> (8 * i));
+  return p + 4;
+}
+
+static uint8_t *put64(uint8_t *p, uint64_t v) {
+  for (int i = 0; i < 8; i++)
+    p[i] = (uint8_t)(v >> (8 * i));
+  return p + 8;
+}
+
+static uint16_t get16(const uint8_t *p) { return (uint16_t)(p[0] | p[1] << 8); }
+
+static uint32_t get32(const uint8_t *p) {
+  uint32_t v = 0;
+  for (int i = 0; i < 4; i++)
+    v |= (uint32_t)p[i] << (8 * i);
+  return v;
+}
+
+static uint64_t get64(const uint8_t *p) {
+  uint64_t v = 0;
+  for (int i = 0; i < 8; i++)
+    v |= (uint64_t)p[i] << (8 * i);
+  return v;
+}
+
+size_t rvm8_export_state(const CPU *cpu, uint8_t *buf, size_t len) {
+  if (buf == NULL || len < RVM_STATE_SIZE)
+    return RVM_STATE_SIZE;
+
+  uint8_t *p = buf;
+  memcpy(p, state_magic, sizeof state_magic);
+  p += sizeof state_magic;
+  *p++ = RVM_STATE_VERSION;
+  *p++ = cpu->a;
+  *p++ = cpu->x;
+  *p++ = cpu->y;
+  p = put16(p, cpu->pc);
+  p = put16(p, cpu->sp);
+  *p++ = cpu->flags;
+  p = put32(p, cpu->cycles);
+  *p++ = cpu->irq;
+  *p++ = cpu->nmi;
+  *p++ = cpu->int_state;
+  *p++ = cpu->illegal_mode;
+  memcpy(p, cpu->rom_pages, 256);
+  p += 256;
+  memcpy(p, cpu->page_map, 256);
+  p += 256;
+  memcpy(p, cpu->wait_pages, 256);
+  p += 256;
+  memcpy(p, cpu->dirty_pages, 256);
+  p += 256;
+  for (int op = 0; op < 256; op++)
+    p = put64(p, cpu->stats.opcodes[op]);
+  p = put64(p, cpu->stats.cycles);
+  p = put64(p, cpu->stats.irqs);
+  p = put64(p, cpu->stats.reads);
+  p = put64(p, cpu->stats.writes);
+  memcpy(p, cpu->memory, RVM_MEM_SIZE);
+  return RVM_STATE_SIZE;
+}
+
+int rvm8_import_state(CPU *cpu, const uint8_t *buf, size_t len) {
+  if (buf == NULL || len != RVM_STATE_SIZE ||
+      memcmp(buf, state_magic, sizeof state_magic) != 0 ||
+      buf[4] != RVM_STATE_VERSION || buf[20] > ILLEGAL_UNDOCUMENTED)
+    return RVM_BAD_STATE;
+
+  const uint8_t *p = buf + sizeof state_magic + 1;
+  cpu->a = *p++;
+  cpu->x = *p++;
+  cpu->y = *p++;
+  cpu->pc = get16(p);
+  p += 2;
+  cpu->sp = get16(p);
+  p += 2;
+  cpu->flags = *p++;
+  cpu->cycles = get32(p);
+  p += 4;
+  cpu->irq = *p++;
+  cpu->nmi = *p++;
+  cpu->int_state = *p++;
+  cpu->illegal_mode = *p++;
+  memcpy(cpu->rom_pages, p, 256);
+  p += 256;
+  memcpy(cpu->page_map, p, 256);
+  p += 256;
+  memcpy(cpu->wait_pages, p, 256);
+  p += 256;
+  memcpy(cpu->dirty_pages, p, 256);
+  p += 256;
+  for (int op = 0; op < 256; op++, p += 8)
+    cpu->stats.opcodes[op] = get64(p);
+  cpu->stats.cycles = get64(p);
+  cpu->stats.irqs = get64(p + 8);
+  cpu->stats.reads = get64(p + 16);
+  cpu->stats.writes = get64(p + 24);
+  p += 32;
+  memcpy(cpu->memory, p, RVM_MEM_SIZE);
+  cpu->bus_claimed = 0;
+  return RVM_OK;
+}
@@ -187,8 +187,693 @@ void test_lda_modes() {
   memory[cpu.pc] = 0xB1; // LDA ($40),Y
   memory[cpu.pc + 1] = 0x40;
 
+  uint32_t cycles = cpu.cycles;
   cpu_step(&cpu);
   assert(cpu.a == 0x88);
+  assert(cpu.cycles - cycles == 5);
+
+  printf("PASS!\n");
+}
+
+void test_ldy_lsr() {
+  printf("TEST: LDY and LSR...\n");
+  setup_test();
+
+  memory[0xFFFC] = 0x00;
+  memory[0xFFFD] = 0x80;
+
+  memory[0x8000] = 0xA0; // LDY #$22
+  memory[0x8001] = 0x22;
+  memory[0x8002] = 0x46; // LSR $10
+  memory[0x8003] = 0x10;
+  memory[0x0010] = 0x03;
+
+  cpu_init(&cpu, memory);
+
+  cpu_step(&cpu);
+  assert(cpu.y == 0x22);
+  assert(cpu.x == 0x00);
+
+  cpu_step(&cpu);
+  assert(memory[0x0010] == 0x01);
+  assert(cpu.flags & FLAG_C);
+  assert(cpu.pc == 0x8004);
+  assert(cpu.cycles == 7);
+
+  printf("PASS!\n");
+}
+
+static int hook_calls;
+static BusAccess hook_kind;
+static uint16_t hook_addr;
+static uint8_t hook_val;
+
+static int record_access(void *ctx, BusAccess kind, uint16_t addr,
+                         uint8_t *val) {
+  (void)ctx;
+  hook_calls++;
+  hook_kind = kind;
+  hook_addr = addr;
+  hook_val = *val;
+  return 0;
+}
+
+// Claims page 0x40: reads return 0x99, writes are latched in hook_val.
+static int fake_device(void *ctx, BusAccess kind, uint16_t addr,
+                       uint8_t *val) {
+  (void)ctx;
+  (void)addr;
+  if (kind == BUS_READ)
+    *val = 0x99;
+  else
+    hook_val = *val;
+  return 1;
+}
+
+void test_bus_hook() {
+  printf("TEST: Bus hook page filter...\n");
+  setup_test();
+
+  memory[0xFFFC] = 0x00;
+  memory[0xFFFD] = 0x80;
+
+  memory[0x8000] = 0xA5; // LDA $10
+  memory[0x8001] = 0x10;
+  memory[0x8002] = 0x4E; // LSR $3000
+  memory[0x8003] = 0x00;
+  memory[0x8004] = 0x30;
+  memory[0x0010] = 0x42;
+  memory[0x3000] = 0x08;
+
+  cpu_init(&cpu, memory);
+  cpu.bus_hook = record_access;
+  cpu.hook_pages[0x30] = 1;
+
+  // Page 0x00 and the code page are not selected.
+  cpu_step(&cpu);
+  assert(cpu.a == 0x42);
+  assert(hook_calls == 0);
+
+  // LSR reads then writes $3000.
+  cpu_step(&cpu);
+  assert(hook_calls == 2);
+  assert(hook_kind == BUS_WRITE);
+  assert(hook_addr == 0x3000);
+  assert(hook_val == 0x04);
+
+  printf("PASS!\n");
+}
+
+void test_bus_device() {
+  printf("TEST: Bus hook claiming accesses...\n");
+  setup_test();
+
+  memory[0xFFFC] = 0x00;
+  memory[0xFFFD] = 0x80;
+
+  memory[0x8000] = 0xAD; // LDA $4000
+  memory[0x8001] = 0x00;
+  memory[0x8002] = 0x40;
+  memory[0x8003] = 0x4E; // LSR $4001
+  memory[0x8004] = 0x01;
+  memory[0x8005] = 0x40;
+  memory[0x4000] = 0x11;
+  memory[0x4001] = 0x22;
+
+  cpu_init(&cpu, memory);
+  cpu.bus_hook = fake_device;
+  cpu.hook_pages[0x40] = 1;
+
+  cpu_step(&cpu);
+  assert(cpu.a == 0x99);
+
+  // The device value is shifted and the write never reaches RAM.
+  cpu_step(&cpu);
+  assert(hook_val == 0x4C);
+  assert(memory[0x4001] == 0x22);
+
+  printf("PASS!\n");
+}
+
+void test_rom_pages() {
+  printf("TEST: ROM pages drop writes...\n");
+  setup_test();
+
+  memory[0xFFFC] = 0x00;
+  memory[0xFFFD] = 0x80;
+
+  memory[0x8000] = 0x4E; // LSR $9000
+  memory[0x8001] = 0x00;
+  memory[0x8002] = 0x90;
+  memory[0x9000] = 0x80;
+
+  cpu_init(&cpu, memory);
+  cpu.rom_pages[0x90] = 1;
+
+  cpu_step(&cpu);
+  assert(memory[0x9000] == 0x80);
+
+  printf("PASS!\n");
+}
+
+void test_page_map() {
+  printf("TEST: page map mirrors...\n");
+  setup_test();
+
+  memory[0xFFFC] = 0x00;
+  memory[0xFFFD] = 0x80;
+
+  memory[0x8000] = 0xAD; // LDA $0810
+  memory[0x8001] = 0x10;
+  memory[0x8002] = 0x08;
+  memory[0x8003] = 0x4E; // LSR $0811
+  memory[0x8004] = 0x11;
+  memory[0x8005] = 0x08;
+  memory[0x0010] = 0x42;
+  memory[0x0011] = 0x80;
+
+  cpu_init(&cpu, memory);
+  assert(mem_decode(&cpu, 0x0810) == 0x0810);
+  cpu.page_map[0x08] = 0x00;
+  assert(mem_decode(&cpu, 0x0810) == 0x0010);
+
+  cpu_step(&cpu);
+  assert(cpu.a == 0x42);
+  cpu_step(&cpu);
+  assert(memory[0x0011] == 0x40);
+  assert(memory[0x0811] == 0x00);
+
+  printf("PASS!\n");
+}
+
+void test_wait_pages() {
+  printf("TEST: wait states...\n");
+  setup_test();
+
+  memory[0xFFFC] = 0x00;
+  memory[0xFFFD] = 0x80;
+
+  memory[0x8000] = 0xAD; // LDA $9000
+  memory[0x8001] = 0x00;
+  memory[0x8002] = 0x90;
+  memory[0x8003] = 0x4E; // LSR $9000
+  memory[0x8004] = 0x00;
+  memory[0x8005] = 0x90;
+
+  cpu_init(&cpu, memory);
+  cpu.wait_pages[0x80] = 1;
+  cpu.wait_pages[0x90] = 2;
+
+  // Three fetches from page $80 and one read of page $90.
+  cpu_step(&cpu);
+  assert(cpu.cycles == 4 + 3 + 2);
+  // LSR reads and writes $9000.
+  cpu_step(&cpu);
+  assert(cpu.cycles == 9 + 6 + 3 + 4);
+
+  printf("PASS!\n");
+}
+
+void test_dirty_pages() {
+  printf("TEST: dirty pages...\n");
+  setup_test();
+
+  memory[0xFFFC] = 0x00;
+  memory[0xFFFD] = 0x80;
+
+  memory[0x8000] = 0x4E; // LSR $9000
+  memory[0x8001] = 0x00;
+  memory[0x8002] = 0x90;
+  memory[0x8003] = 0x4E; // LSR $A000
+  memory[0x8004] = 0x00;
+  memory[0x8005] = 0xA0;
+  memory[0x8006] = 0xAD; // LDA $B000
+  memory[0x8007] = 0x00;
+  memory[0x8008] = 0xB0;
+
+  cpu_init(&cpu, memory);
+  cpu.rom_pages[0xA0] = 1;
+  cpu_step(&cpu);
+  cpu_step(&cpu);
+  cpu_step(&cpu);
+  // Only the store that reached RAM counts.
+  for (int page = 0; page < 256; page++)
+    assert(cpu.dirty_pages[page] == (page == 0x90));
+
+  printf("PASS!\n");
+}
+
+void test_irq() {
+  printf("TEST: IRQ entry...\n");
+  setup_test();
+
+  memory[0xFFFC] = 0x00;
+  memory[0xFFFD] = 0x80;
+  memory[0xFFFE] = 0x00;
+  memory[0xFFFF] = 0x90;
+
+  memory[0x8000] = 0xA9; // LDA #$01
+  memory[0x8001] = 0x01;
+  memory[0x9000] = 0xA9; // LDA #$02
+  memory[0x9001] = 0x02;
+
+  memory[0x8002] = 0xA9; // LDA #$03
+  memory[0x8003] = 0x03;
+
+  cpu_init(&cpu, memory);
+  cpu.irq = 1;
+
+  // Masked by the I flag set at reset.
+  cpu_step(&cpu);
+  assert(cpu.pc == 0x8002);
+  assert(!(cpu.int_state & INT_IRQ));
+
+  // The instruction after the line is unmasked still runs; its poll
+  // latches the IRQ, which is taken next.
+  cpu.flags &= ~FLAG_I;
+  cpu.flags |= FLAG_C;
+  cpu_step(&cpu);
+  assert(cpu.pc == 0x8004);
+  assert(cpu.int_state & INT_IRQ);
+  uint32_t cycles = cpu.cycles;
+  cpu_step(&cpu);
+  assert(cpu.pc == 0x9000);
+  assert(cpu.cycles == cycles + IRQ_CYCLES);
+  assert(cpu.flags & FLAG_I);
+  assert(cpu.sp == 0xFA);
+  assert(memory[0x01FD] == 0x80);
+  assert(memory[0x01FC] == 0x04);
+  assert(memory[0x01FB] == FLAG_C);
+
+  // The handler runs with further IRQs masked.
+  cpu_step(&cpu);
+  assert(cpu.a == 0x02);
+  assert(!(cpu.int_state & INT_IRQ));
+
+  printf("PASS!\n");
+}
+
+void test_interrupt_polling() {
+  printf("TEST: Interrupt polling...\n");
+  setup_test();
+
+  memory[0xFFFA] = 0x00; // NMI handler at 0xA000
+  memory[0xFFFB] = 0xA0;
+  memory[0xFFFC] = 0x00;
+  memory[0xFFFD] = 0x80;
+  memory[0xFFFE] = 0x00; // IRQ handler at 0x9000
+  memory[0xFFFF] = 0x90;
+
+  memory[0x8000] = 0x58; // CLI
+  memory[0x8001] = 0xA9; // LDA #$01
+  memory[0x8002] = 0x01;
+  memory[0x8003] = 0x78; // SEI
+  memory[0x8004] = 0xA9; // LDA #$02
+  memory[0x8005] = 0x02;
+
+  // CLI takes effect one instruction late.
+  cpu_init(&cpu, memory);
+  cpu.irq = 1;
+  cpu_step(&cpu);
+  assert(!(cpu.flags & FLAG_I));
+  assert(!(cpu.int_state & INT_IRQ));
+  cpu_step(&cpu);
+  assert(cpu.pc == 0x8003);
+  cpu_step(&cpu);
+  assert(cpu.pc == 0x9000);
+
+  // So does SEI: an IRQ is still taken right after it.
+  cpu_init(&cpu, memory);
+  cpu.pc = 0x8003;
+  cpu.flags = 0;
+  cpu.irq = 1;
+  cpu_step(&cpu);
+  assert(cpu.flags & FLAG_I);
+  assert(cpu.int_state & INT_IRQ);
+
+  // A latched IRQ is taken even if its line dropped since.
+  cpu.irq = 0;
+  cpu_step(&cpu);
+  assert(cpu.pc == 0x9000);
+  assert(cpu.stats.irqs == 1);
+
+  // NMIs ignore the I flag and trigger on the rising edge only.
+  cpu_init(&cpu, memory);
+  cpu.pc = 0x8001;
+  cpu.nmi = 1;
+  cpu_step(&cpu);
+  assert(cpu.int_state == (INT_NMI | INT_NMI_LEVEL | INT_IRQ_MASKED));
+  cpu_step(&cpu);
+  assert(cpu.pc == 0xA000);
+  assert(cpu.int_state == (INT_NMI_LEVEL | INT_IRQ_MASKED));
+  // A held line does not retrigger.
+  cpu.pc = 0x8001;
+  cpu_step(&cpu);
+  cpu_step(&cpu);
+  assert(cpu.pc == 0x8004);
+
+  // An NMI wins over an IRQ latched by the same poll.
+  cpu.nmi = 0;
+  cpu.pc = 0x8001;
+  cpu_step(&cpu);
+  assert(cpu.int_state == INT_IRQ_MASKED);
+  cpu.nmi = 1;
+  cpu.irq = 1;
+  cpu.flags = 0;
+  cpu_step(&cpu);
+  assert(cpu.int_state == (INT_NMI | INT_IRQ | INT_NMI_LEVEL));
+  cpu_step(&cpu);
+  assert(cpu.pc == 0xA000);
+
+  // A repeated poll sees the line as it is now, masked as it was then.
+  cpu_init(&cpu, memory);
+  cpu.pc = 0x8001;
+  cpu.flags = 0;
+  cpu_step(&cpu);
+  cpu.irq = 1;
+  cpu_poll_irq(&cpu);
+  assert(cpu.int_state == INT_IRQ);
+  cpu.irq = 0;
+  cpu_poll_irq(&cpu);
+  assert(cpu.int_state == 0);
+  cpu.flags = FLAG_I;
+  cpu_step(&cpu);
+  cpu.irq = 1;
+  cpu_poll_irq(&cpu);
+  assert(cpu.int_state == INT_IRQ_MASKED);
+
+  // A reset drops latched interrupts and sees no edge on a held line.
+  cpu.nmi = 1;
+  cpu.int_state = INT_IRQ | INT_NMI;
+  cpu_reset(&cpu);
+  assert(cpu.int_state == INT_NMI_LEVEL);
+
+  printf("PASS!\n");
+}
+
+void test_cpu_run() {
+  printf("TEST: Batched run...\n");
+  setup_test();
+
+  memory[0xFFFC] = 0x00;
+  memory[0xFFFD] = 0x80;
+
+  memory[0x8000] = 0xA9; // LDA #$01
+  memory[0x8001] = 0x01;
+  memory[0x8002] = 0xA2; // LDX #$02
+  memory[0x8003] = 0x02;
+  memory[0x8004] = 0xAD; // LDA $4000
+  memory[0x8005] = 0x00;
+  memory[0x8006] = 0x40;
+  memory[0x8007] = 0xA0; // LDY #$03
+  memory[0x8008] = 0x03;
+  // 0x8009 holds an illegal opcode.
+
+  cpu_init(&cpu, memory);
+  cpu.bus_hook = fake_device;
+  cpu.hook_pages[0x40] = 1;
+
+  // The budget is met on the instruction that reaches it.
+  assert(cpu_run(&cpu, 3) == RVM_OK);
+  assert(cpu.pc == 0x8004);
+  assert(cpu.cycles == 4);
+
+  // The device read ends the batch early.
+  assert(cpu_run(&cpu, 100) == RVM_OK);
+  assert(cpu.pc == 0x8007);
+  assert(cpu.a == 0x99);
+  assert(!cpu.bus_claimed);
+
+  assert(cpu_run(&cpu, 0) == RVM_OK);
+  assert(cpu.pc == 0x8007);
+
+  // Execution stops at the illegal opcode, PC just past it.
+  assert(cpu_run(&cpu, 100) == RVM_ILLEGAL_OPCODE);
+  assert(cpu.y == 0x03);
+  assert(cpu.pc == 0x800A);
+
+  printf("PASS!\n");
+}
+
+void test_run_terminates() {
+  printf("TEST: Batched run over any memory contents...\n");
+  setup_test();
+
+  // Every instruction takes cycles, so a run through memory filled with
+  // LDA ($B1),Y, which crosses a page each time, still meets its budget.
+  memset(memory, 0xB1, RVM_MEM_SIZE);
+  cpu_init(&cpu, memory);
+  cpu.y = 0xFF;
+  assert(cpu_run(&cpu, 100000) == RVM_OK);
+  assert(cpu.cycles >= 100000);
+  assert(cpu.cycles < 100006);
+
+  printf("PASS!\n");
+}
+
+void test_stats() {
+  printf("TEST: Execution counters...\n");
+  setup_test();
+
+  memory[0xFFFC] = 0x00;
+  memory[0xFFFD] = 0x80;
+  memory[0xFFFE] = 0x00;
+  memory[0xFFFF] = 0x90;
+
+  memory[0x8000] = 0xA9; // LDA #$01
+  memory[0x8001] = 0x01;
+  memory[0x8002] = 0x4E; // LSR $3000
+  memory[0x8003] = 0x00;
+  memory[0x8004] = 0x30;
+  memory[0x9000] = 0x02; // illegal
+
+  cpu_init(&cpu, memory);
+  cpu_step(&cpu);
+  cpu_step(&cpu);
+  assert(cpu.stats.opcodes[0xA9] == 1);
+  assert(cpu.stats.opcodes[0x4E] == 1);
+  assert(cpu.stats.cycles == 2 + 6);
+  assert(cpu.stats.reads == 2 + 4);
+  assert(cpu.stats.writes == 1);
+
+  // An IRQ latched by the last poll.
+  cpu.int_state = INT_IRQ;
+  cpu_step(&cpu);
+  assert(cpu.stats.irqs == 1);
+  assert(cpu.stats.cycles == 8 + IRQ_CYCLES);
+  assert(cpu.stats.reads == 6 + 2);
+  assert(cpu.stats.writes == 1 + 3);
+
+  // The illegal opcode is fetched but not counted as executed.
+  assert(cpu_step(&cpu) == RVM_ILLEGAL_OPCODE);
+  assert(cpu.stats.opcodes[0x02] == 0);
+  assert(cpu.stats.reads == 9);
+
+  // A reset keeps the counts.
+  cpu_reset(&cpu);
+  assert(cpu.stats.opcodes[0xA9] == 1);
+  assert(cpu.stats.cycles == 15);
+
+  printf("PASS!\n");
+}
+
+static int ext_calls;
+
+/* Extension "ADD #imm, X": adds its operand to X; takes 3 cycles. */
+static uint8_t add_x(void *ctx, CPU *cpu, uint8_t opcode) {
+  (void)ctx;
+  ext_calls++;
+  assert(opcode == 0x02);
+  cpu->x += mem_read(cpu, cpu->pc++);
+  return 3;
+}
+
+void test_ext_opcodes() {
+  printf("TEST: Extension opcodes...\n");
+  setup_test();
+
+  memory[0xFFFC] = 0x00;
+  memory[0xFFFD] = 0x80;
+
+  memory[0x8000] = 0x02; // extension, operand 5
+  memory[0x8001] = 0x05;
+  memory[0x8002] = 0x03; // still illegal
+  memory[0x8003] = 0xA9; // LDA #$07; the table handler wins
+  memory[0x8004] = 0x07;
+
+  cpu_init(&cpu, memory);
+  ext_calls = 0;
+  cpu.ext_handler = add_x;
+  cpu.ext_opcodes[0x02] = 1;
+  cpu.ext_opcodes[0xA9] = 1;
+
+  assert(cpu_step(&cpu) == RVM_OK);
+  assert(cpu.x == 5);
+  assert(cpu.pc == 0x8002);
+  assert(cpu.cycles == 3);
+  assert(cpu.stats.opcodes[0x02] == 1);
+
+  assert(cpu_step(&cpu) == RVM_ILLEGAL_OPCODE);
+  assert(cpu_step(&cpu) == RVM_OK);
+  assert(cpu.a == 7);
+  assert(ext_calls == 1);
+
+  // Without a handler the enabled opcode is illegal again.
+  cpu.ext_handler = NULL;
+  cpu.pc = 0x8000;
+  assert(cpu_step(&cpu) == RVM_ILLEGAL_OPCODE);
+  assert(ext_calls == 1);
+
+  printf("PASS!\n");
+}
+
+void test_illegal_modes() {
+  printf("TEST: Illegal opcode modes...\n");
+  setup_test();
+
+  memory[0xFFFC] = 0x00;
+  memory[0xFFFD] = 0x80;
+
+  memory[0x8000] = 0xAF; // LAX $1234 (undocumented)
+  memory[0x8001] = 0x34;
+  memory[0x8002] = 0x12;
+  memory[0x8003] = 0x1C; // NOP $12F0,X (undocumented), page cross
+  memory[0x8004] = 0xF0;
+  memory[0x8005] = 0x12;
+  memory[0x8006] = 0x02; // jams a 6502
+  memory[0x1234] = 0x90;
+
+  cpu_init(&cpu, memory);
+  assert(cpu.illegal_mode == ILLEGAL_TRAP);
+  assert(cpu_step(&cpu) == RVM_ILLEGAL_OPCODE);
+  assert(cpu.pc == 0x8001);
+
+  cpu.pc = 0x8000;
+  cpu.illegal_mode = ILLEGAL_UNDOCUMENTED;
+  assert(cpu_step(&cpu) == RVM_OK);
+  assert(cpu.a == 0x90 && cpu.x == 0x90);
+  assert(cpu.flags & FLAG_N);
+  assert(cpu.cycles == 4);
+  assert(cpu_step(&cpu) == RVM_OK);
+  assert(cpu.pc == 0x8006);
+  assert(cpu.cycles == 4 + 5);
+  assert(cpu.stats.reads == 1 + 4 + 4);
+  assert(cpu_step(&cpu) == RVM_ILLEGAL_OPCODE);
+
+  // Every illegal opcode is a one-byte NOP.
+  cpu.pc = 0x8000;
+  cpu.illegal_mode = ILLEGAL_NOP;
+  cpu.a = 0;
+  assert(cpu_step(&cpu) == RVM_OK);
+  assert(cpu.pc == 0x8001);
+  assert(cpu.a == 0);
+  assert(cpu.cycles == 9 + 2);
+  cpu.pc = 0x8006;
+  assert(cpu_step(&cpu) == RVM_OK);
+  assert(cpu.stats.opcodes[0x02] == 1);
+
+  printf("PASS!\n");
+}
+
+/* Runs `op #b` on A = a with the given carry in decimal mode. */
+static uint8_t decimal_op(uint8_t op, uint8_t a, uint8_t b, int carry) {
+  memory[0x9000] = op;
+  memory[0x9001] = b;
+  cpu.pc = 0x9000;
+  cpu.a = a;
+  cpu.flags = FLAG_D | (carry ? FLAG_C : 0);
+  assert(cpu_step(&cpu) == RVM_OK);
+  return cpu.a;
+}
+
+void test_decimal_mode() {
+  printf("TEST: Decimal mode...\n");
+  setup_test();
+
+  memory[0xFFFC] = 0x00;
+  memory[0xFFFD] = 0x80;
+  memory[0x8000] = 0xF8; // SED
+  memory[0x8001] = 0xD8; // CLD
+
+  cpu_init(&cpu, memory);
+  cpu_step(&cpu);
+  assert(cpu.flags & FLAG_D);
+  cpu_step(&cpu);
+  assert(!(cpu.flags & FLAG_D));
+  assert(cpu.cycles == 4);
+
+  assert(decimal_op(0x69, 0x12, 0x34, 0) == 0x46);
+  assert(!(cpu.flags & FLAG_C));
+  assert(decimal_op(0x69, 0x58, 0x46, 1) == 0x05);
+  assert(cpu.flags & FLAG_C);
+  assert(decimal_op(0x69, 0x81, 0x92, 0) == 0x73);
+  assert((cpu.flags & (FLAG_C | FLAG_V | FLAG_N | FLAG_Z)) == (FLAG_C | FLAG_V));
+  // Z comes from the binary sum 0x9A and N from the adjusted 0xA0.
+  assert(decimal_op(0x69, 0x99, 0x01, 0) == 0x00);
+  assert((cpu.flags & (FLAG_C | FLAG_V | FLAG_N | FLAG_Z)) == (FLAG_C | FLAG_N));
+
+  assert(decimal_op(0xE9, 0x46, 0x12, 1) == 0x34);
+  assert(cpu.flags & FLAG_C);
+  assert(decimal_op(0xE9, 0x32, 0x02, 0) == 0x29);
+  assert(cpu.flags & FLAG_C);
+  assert(decimal_op(0xE9, 0x12, 0x21, 1) == 0x91);
+  assert((cpu.flags & (FLAG_C | FLAG_N)) == FLAG_N);
+  assert(decimal_op(0xE9, 0x21, 0x34, 1) == 0x87);
+  assert(!(cpu.flags & FLAG_C));
+
+  // Binary SBC: 0x50 - 0xB0 overflows.
+  cpu.flags = FLAG_C;
+  cpu.a = 0x50;
+  memory[0x9000] = 0xE9;
+  memory[0x9001] = 0xB0;
+  cpu.pc = 0x9000;
+  cpu_step(&cpu);
+  assert(cpu.a == 0xA0);
+  assert((cpu.flags & (FLAG_C | FLAG_V | FLAG_N)) == (FLAG_V | FLAG_N));
+
+  printf("PASS!\n");
+}
+
+void test_state_export() {
+  printf("TEST: state export and import...\n");
+  setup_test();
+
+  memory[0xFFFC] = 0x00;
+  memory[0xFFFD] = 0x80;
+  memory[0x8000] = 0xA9; // LDA #$42
+  memory[0x8001] = 0x42;
+  memory[0x8002] = 0xA2; // LDX #$07
+  memory[0x8003] = 0x07;
+
+  cpu_init(&cpu, memory);
+  cpu.wait_pages[0x80] = 1;
+  cpu.illegal_mode = ILLEGAL_NOP;
+  cpu_step(&cpu);
+
+  static uint8_t state[RVM_STATE_SIZE];
+  assert(rvm8_export_state(&cpu, NULL, 0) == RVM_STATE_SIZE);
+  assert(rvm8_export_state(&cpu, state, sizeof state) == RVM_STATE_SIZE);
+
+  // A second CPU on its own memory picks up where the first left off.
+  static uint8_t other_memory[65536];
+  CPU other;
+  cpu_init(&other, other_memory);
+  other.hook_pages[0x27] = 1;
+  assert(rvm8_import_state(&other, state, sizeof state) == RVM_OK);
+  assert(other.a == 0x42 && other.pc == 0x8002 && other.cycles == cpu.cycles);
+  assert(other.wait_pages[0x80] == 1 && other.illegal_mode == ILLEGAL_NOP);
+  assert(other.stats.opcodes[0xA9] == 1);
+  assert(other.hook_pages[0x27] == 1);
+  assert(other.memory == other_memory && other_memory[0x8002] == 0xA2);
+  cpu_step(&cpu);
+  cpu_step(&other);
+  assert(other.x == 0x07 && other.cycles == cpu.cycles);
+
+  // Bad states leave the CPU alone.
+  assert(rvm8_import_state(&other, state, sizeof state - 1) == RVM_BAD_STATE);
+  state[0] = 'X';
+  assert(rvm8_import_state(&other, state, sizeof state) == RVM_BAD_STATE);
+  assert(other.x == 0x07);
 
   printf("PASS!\n");
 }
@@ -197,6 +882,22 @@ int main() {
   test_simple_addition();
   test_overflow_carry();
   test_lda_modes();
+  test_ldy_lsr();
+  test_bus_hook();
+  test_bus_device();
+  test_rom_pages();
+  test_page_map();
+  test_wait_pages();
+  test_dirty_pages();
+  test_irq();
+  test_interrupt_polling();
+  test_cpu_run();
+  test_run_terminates();
+  test_stats();
+  test_ext_opcodes();
+  test_illegal_modes();
+  test_decimal_mode();
+  test_state_export();
 
   printf("\nALL TESTS WERE PASSED.\n");
   return 0;